@@ -0,0 +1,1000 @@
+//! Offline analysis of recorded replies
+//!
+//! The oracles that only need to decode replies can run long after a
+//! campaign, so a new oracle can be applied to old traffic. The input is
+//! a directory of JSON-lines transcripts (the `convert` format: one
+//! call/reply pair per line); every pair is checked for protocol
+//! compliance and leaked bytes, v3 attributes are checked for
+//! consistency per filehandle, and the campaign-level auth and restart
+//! oracles are fed in file order. READ and READLINK replies are checked
+//! for bytes of other replies and for data running past their record.
+//! COMPOUND results are walked as far as they can be decoded; vendor
+//! attributes and ops along the way go to the registered
+//! [`crate::vendor`] decoders and show up as annotations.
+
+use crate::nfsv3;
+use crate::nfsv4;
+use crate::oracle::{
+    AuthTracker, AuthVerdict, RestartTracker, Splice, SpliceTracker, VerifierSource,
+};
+use crate::rpc::{msg_type, program, reject_stat, reply_stat};
+use crate::transcript::{self, TranscriptEntry, TranscriptError};
+use crate::vendor::{self, Registry};
+use crate::xdr::XdrDecoder;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AnalyzeError {
+    #[error("reading {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path}: {source}")]
+    Transcript {
+        path: PathBuf,
+        source: TranscriptError,
+    },
+}
+
+/// Highest accept_stat (SYSTEM_ERR) and nfsstat3 value defined
+const MAX_ACCEPT_STAT: u32 = 5;
+const NFS3_STATUSES: &[u32] = &[
+    0, 1, 2, 5, 6, 13, 17, 18, 19, 20, 21, 22, 27, 28, 30, 31, 63, 66, 69, 70, 71, 10001, 10002,
+    10003, 10004, 10005, 10006, 10007, 10008,
+];
+
+/// Largest opaque_auth body (RFC 5531 §8.2)
+const MAX_AUTH_BYTES: usize = 400;
+
+/// fattr3 is fixed-size
+const FATTR3_LEN: usize = 84;
+
+/// Which check produced a finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Check {
+    /// Reply malformed or inconsistent with its call
+    Compliance,
+    /// Non-zero XDR padding or trailing bytes, possibly uninitialized memory
+    InfoLeak,
+    /// Attributes of one handle that contradict each other
+    AttrConsistency,
+    /// A credential's AUTH_ERROR verdict changed
+    AuthFlip,
+    /// A boot verifier changed
+    Restart,
+    /// Reported by a vendor decoder
+    Vendor,
+    /// Bytes of another reply inside this one, or data crossing the end
+    /// of its record: a reused or mis-split reply buffer
+    Splitting,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub check: Check,
+    pub file: PathBuf,
+    /// 1-based transcript line of the call/reply pair
+    pub entry: usize,
+    pub detail: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {:?}: {}",
+            self.file.display(),
+            self.entry,
+            self.check,
+            self.detail
+        )
+    }
+}
+
+/// A vendor attribute or op result a registered decoder named
+#[derive(Debug, Clone, PartialEq)]
+pub struct Annotation {
+    pub file: PathBuf,
+    pub entry: usize,
+    pub decoder: String,
+    /// `attr <bit>` or `op <opcode>`
+    pub what: String,
+    pub value: serde_json::Value,
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {} {}: {}",
+            self.file.display(),
+            self.entry,
+            self.decoder,
+            self.what,
+            self.value
+        )
+    }
+}
+
+struct CallHeader<'a> {
+    xid: u32,
+    program: u32,
+    version: u32,
+    procedure: u32,
+    cred: &'a [u8],
+    args: &'a [u8],
+}
+
+fn parse_call(buf: &[u8]) -> Option<CallHeader<'_>> {
+    let mut r = XdrDecoder::new(buf);
+    let xid = r.get_u32().ok()?;
+    if r.get_u32().ok()? != msg_type::CALL {
+        return None;
+    }
+    r.get_u32().ok()?;
+    let (program, version, procedure) = (r.get_u32().ok()?, r.get_u32().ok()?, r.get_u32().ok()?);
+    let cred_start = r.position();
+    r.get_u32().ok()?;
+    r.get_opaque().ok()?;
+    let cred = &buf[cred_start..r.position()];
+    r.get_u32().ok()?;
+    r.get_opaque().ok()?;
+    Some(CallHeader {
+        xid,
+        program,
+        version,
+        procedure,
+        cred,
+        args: r.rest(),
+    })
+}
+
+/// One fattr3, the fields compared across replies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fattr3 {
+    ftype: u32,
+    fsid: u64,
+    fileid: u64,
+    nsecs: [u32; 3],
+}
+
+fn fattr3(r: &mut XdrDecoder) -> Option<Fattr3> {
+    let start = r.position();
+    let ftype = r.get_u32().ok()?;
+    r.get_raw(4 * 4 + 8 * 2 + 8).ok()?;
+    let fsid = r.get_u64().ok()?;
+    let fileid = r.get_u64().ok()?;
+    let mut nsecs = [0; 3];
+    for n in &mut nsecs {
+        r.get_u32().ok()?;
+        *n = r.get_u32().ok()?;
+    }
+    debug_assert_eq!(r.position() - start, FATTR3_LEN);
+    Some(Fattr3 {
+        ftype,
+        fsid,
+        fileid,
+        nsecs,
+    })
+}
+
+/// wcc_data: optional pre-op (24 bytes), optional post-op fattr3
+fn skip_wcc(r: &mut XdrDecoder) -> Option<()> {
+    if r.get_u32().ok()? != 0 {
+        r.get_raw(24).ok()?;
+    }
+    if r.get_u32().ok()? != 0 {
+        r.get_raw(FATTR3_LEN).ok()?;
+    }
+    Some(())
+}
+
+/// Runs every offline check over transcripts
+#[derive(Debug, Default)]
+pub struct Analyzer {
+    auth: AuthTracker,
+    restarts: RestartTracker,
+    splices: SpliceTracker,
+    attrs: HashMap<Vec<u8>, Fattr3>,
+    request: u64,
+    vendors: Registry,
+    pub findings: Vec<Finding>,
+    pub annotations: Vec<Annotation>,
+}
+
+impl Analyzer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An analyzer that decodes vendor extensions with `vendors`
+    pub fn with_vendors(vendors: Registry) -> Self {
+        Self {
+            vendors,
+            ..Self::default()
+        }
+    }
+
+    /// Check one call/reply pair; `entry` locates it for the report
+    pub fn entry(&mut self, file: &Path, entry: usize, pair: &TranscriptEntry) {
+        self.request += 1;
+        let mut found = Vec::new();
+        let mut notes = Vec::new();
+        let call = parse_call(&pair.call);
+        if let Some(call) = &call {
+            self.splices.call(call.xid);
+        }
+        if let (Some(call), Some(reply)) = (call, pair.reply.as_deref()) {
+            self.check_reply(&call, reply, &mut found, &mut notes);
+        }
+        self.annotations
+            .extend(notes.into_iter().map(|(decoder, what, value)| Annotation {
+                file: file.to_path_buf(),
+                entry,
+                decoder,
+                what,
+                value,
+            }));
+        self.findings
+            .extend(found.into_iter().map(|(check, detail)| Finding {
+                check,
+                file: file.to_path_buf(),
+                entry,
+                detail,
+            }));
+    }
+
+    fn check_reply(
+        &mut self,
+        call: &CallHeader,
+        reply: &[u8],
+        found: &mut Vec<(Check, String)>,
+        notes: &mut Vec<Note>,
+    ) {
+        let mut r = XdrDecoder::new(reply);
+        let header = (|| Some((r.get_u32().ok()?, r.get_u32().ok()?, r.get_u32().ok()?)))();
+        let Some((xid, mtype, stat)) = header else {
+            found.push((Check::Compliance, "reply shorter than its header".into()));
+            return;
+        };
+        if xid != call.xid {
+            found.push((
+                Check::Compliance,
+                format!("xid {:#x} answers call {:#x}", xid, call.xid),
+            ));
+        }
+        if mtype != msg_type::REPLY {
+            found.push((Check::Compliance, format!("msg_type {}", mtype)));
+            return;
+        }
+        match stat {
+            reply_stat::MSG_ACCEPTED => {}
+            reply_stat::MSG_DENIED => {
+                if r.get_u32().ok() == Some(reject_stat::AUTH_ERROR) {
+                    if let Ok(auth_stat) = r.get_u32() {
+                        let verdict = AuthVerdict::Denied(auth_stat);
+                        self.auth_verdict(call.cred, verdict, found);
+                    }
+                }
+                return;
+            }
+            other => {
+                found.push((Check::Compliance, format!("reply_stat {}", other)));
+                return;
+            }
+        }
+        self.auth_verdict(call.cred, AuthVerdict::Accepted, found);
+
+        let parsed = (|| {
+            r.get_u32().ok()?;
+            let verf_len = r.get_opaque().ok()?.len();
+            Some((verf_len, r.get_u32().ok()?))
+        })();
+        let Some((verf_len, accept)) = parsed else {
+            found.push((Check::Compliance, "truncated accepted reply".into()));
+            return;
+        };
+        if verf_len > MAX_AUTH_BYTES {
+            found.push((Check::Compliance, format!("{}-byte verifier", verf_len)));
+        }
+        if accept > MAX_ACCEPT_STAT {
+            found.push((Check::Compliance, format!("accept_stat {}", accept)));
+        }
+        if accept == 0 && call.program == program::NFS {
+            match call.version {
+                3 => self.check_v3(call, &mut r, found),
+                4 => {
+                    let mut payloads = Vec::new();
+                    check_v4(call, &mut r, &self.vendors, found, notes, &mut payloads);
+                    for (what, bytes) in payloads {
+                        self.check_payload(call.xid, what, bytes, found);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if let Some(at) = r.dirty_pad() {
+            found.push((
+                Check::InfoLeak,
+                format!("non-zero XDR padding at offset {}", at),
+            ));
+        }
+    }
+
+    fn auth_verdict(
+        &mut self,
+        cred: &[u8],
+        verdict: AuthVerdict,
+        found: &mut Vec<(Check, String)>,
+    ) {
+        if let Some(flip) = self.auth.record(cred, verdict) {
+            found.push((
+                Check::AuthFlip,
+                format!("{:?} became {:?}", flip.before, flip.after),
+            ));
+        }
+    }
+
+    fn check_v3(
+        &mut self,
+        call: &CallHeader,
+        r: &mut XdrDecoder,
+        found: &mut Vec<(Check, String)>,
+    ) {
+        if call.procedure == 0 {
+            return;
+        }
+        let Ok(status) = r.get_u32() else {
+            found.push((Check::Compliance, "missing nfsstat3".into()));
+            return;
+        };
+        if !NFS3_STATUSES.contains(&status) {
+            found.push((Check::Compliance, format!("undefined nfsstat3 {}", status)));
+        }
+        if status != 0 {
+            return;
+        }
+        let writeverf = match call.procedure {
+            nfsv3::proc::GETATTR => {
+                let fh = XdrDecoder::new(call.args)
+                    .get_opaque()
+                    .ok()
+                    .map(<[u8]>::to_vec);
+                match (fattr3(r), fh) {
+                    (Some(attrs), Some(fh)) => self.check_attrs(fh, attrs, found),
+                    (None, _) => found.push((Check::Compliance, "truncated fattr3".into())),
+                    _ => {}
+                }
+                None
+            }
+            nfsv3::proc::WRITE => (|| {
+                skip_wcc(r)?;
+                r.get_raw(8).ok()?;
+                r.get_raw(8).ok()
+            })(),
+            nfsv3::proc::COMMIT => (|| {
+                skip_wcc(r)?;
+                r.get_raw(8).ok()
+            })(),
+            nfsv3::proc::READ | nfsv3::proc::READLINK => {
+                self.check_read3(call, r, found);
+                return;
+            }
+            _ => return,
+        };
+        if let Some(verf) = writeverf {
+            if let Some(restart) =
+                self.restarts
+                    .observe(VerifierSource::V3WriteVerf, verf, self.request)
+            {
+                found.push((
+                    Check::Restart,
+                    format!(
+                        "write verifier changed after request {}",
+                        restart.last_unchanged
+                    ),
+                ));
+            }
+        }
+        if r.remaining() > 0 {
+            found.push((
+                Check::InfoLeak,
+                format!("{} bytes after the result", r.remaining()),
+            ));
+        }
+    }
+
+    /// READ3resok or READLINK3resok: the data is all in the record, READ
+    /// counts what it returns, and neither the data nor what follows it
+    /// holds another reply
+    fn check_read3(
+        &mut self,
+        call: &CallHeader,
+        r: &mut XdrDecoder,
+        found: &mut Vec<(Check, String)>,
+    ) {
+        let read = call.procedure == nfsv3::proc::READ;
+        let what = if read { "READ data" } else { "READLINK target" };
+        let Some(()) = skip_post_op_attr(r) else {
+            found.push((Check::Compliance, "truncated post_op_attr".into()));
+            return;
+        };
+        let count = if read {
+            match (|| Some((r.get_u32().ok()?, r.get_u32().ok()?)))() {
+                Some((count, _eof)) => Some(count),
+                None => {
+                    found.push((Check::Compliance, "truncated READ3resok".into()));
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let len = XdrDecoder::new(r.rest()).get_u32().ok().unwrap_or(0) as usize;
+        let Some(data) = r.get_opaque().ok() else {
+            found.push((
+                Check::Splitting,
+                format!(
+                    "{} of {} bytes runs past the end of the record ({} left)",
+                    what,
+                    len,
+                    r.remaining()
+                ),
+            ));
+            return;
+        };
+        if let Some(count) = count.filter(|&c| c as usize != data.len()) {
+            found.push((
+                Check::Splitting,
+                format!("READ count {} for {} bytes of data", count, data.len()),
+            ));
+        }
+        self.check_payload(call.xid, what, data, found);
+        if !read {
+            let fh = XdrDecoder::new(call.args).get_opaque().ok();
+            if let Some(Splice::Target { before, other }) =
+                fh.and_then(|fh| self.splices.readlink(fh, data))
+            {
+                let other = match other {
+                    Some(fh) => format!(", the target of handle {}", hex::encode(&fh)),
+                    None => String::new(),
+                };
+                found.push((
+                    Check::Splitting,
+                    format!(
+                        "symlink target changed from {:?} to {:?}{}",
+                        String::from_utf8_lossy(&before),
+                        String::from_utf8_lossy(data),
+                        other
+                    ),
+                ));
+            }
+        }
+        let rest = r.rest();
+        if rest.is_empty() {
+            return;
+        }
+        if rest.get(4..8) == Some(&msg_type::REPLY.to_be_bytes()[..]) {
+            let xid = u32::from_be_bytes(rest[..4].try_into().unwrap());
+            found.push((
+                Check::Splitting,
+                format!(
+                    "{} bytes after the result begin a reply to xid {:#x}",
+                    rest.len(),
+                    xid
+                ),
+            ));
+        } else {
+            found.push((
+                Check::InfoLeak,
+                format!("{} bytes after the result", rest.len()),
+            ));
+        }
+    }
+
+    /// Another call's reply header inside `bytes` of the reply to `xid`
+    fn check_payload(&self, xid: u32, what: &str, bytes: &[u8], found: &mut Vec<(Check, String)>) {
+        if let Some(Splice::Header { offset, xid: other }) =
+            self.splices.embedded_header(bytes, xid)
+        {
+            found.push((
+                Check::Splitting,
+                format!(
+                    "{} holds a reply header for xid {:#x} at offset {}",
+                    what, other, offset
+                ),
+            ));
+        }
+    }
+
+    fn check_attrs(&mut self, fh: Vec<u8>, attrs: Fattr3, found: &mut Vec<(Check, String)>) {
+        if !(1..=7).contains(&attrs.ftype) {
+            found.push((Check::AttrConsistency, format!("ftype3 {}", attrs.ftype)));
+        }
+        if attrs.nsecs.iter().any(|&n| n >= 1_000_000_000) {
+            found.push((
+                Check::AttrConsistency,
+                format!("nanoseconds out of range: {:?}", attrs.nsecs),
+            ));
+        }
+        if let Some(before) = self.attrs.insert(fh, attrs) {
+            let mut changed = Vec::new();
+            if before.ftype != attrs.ftype {
+                changed.push("type");
+            }
+            if before.fsid != attrs.fsid {
+                changed.push("fsid");
+            }
+            if before.fileid != attrs.fileid {
+                changed.push("fileid");
+            }
+            if !changed.is_empty() {
+                found.push((
+                    Check::AttrConsistency,
+                    format!("same handle, different {}", changed.join(", ")),
+                ));
+            }
+        }
+    }
+}
+
+/// Decoder name, what it decoded, and the value, before the annotation
+/// is located
+type Note = (String, String, serde_json::Value);
+
+/// What a result holds that other replies could have leaked into, and
+/// the bytes
+type Payload<'a> = (&'static str, &'a [u8]);
+
+/// Post-op attributes: present flag and fattr3
+fn skip_post_op_attr(r: &mut XdrDecoder) -> Option<()> {
+    if r.get_u32().ok()? != 0 {
+        r.get_raw(FATTR3_LEN).ok()?;
+    }
+    Some(())
+}
+
+/// COMPOUND4res against COMPOUND4args: tag echoed, no more results than
+/// ops, all of them when the COMPOUND succeeded, first result for the
+/// first op; then the successful results are walked
+fn check_v4<'a>(
+    call: &CallHeader,
+    r: &mut XdrDecoder<'a>,
+    vendors: &Registry,
+    found: &mut Vec<(Check, String)>,
+    notes: &mut Vec<Note>,
+    payloads: &mut Vec<Payload<'a>>,
+) {
+    if call.procedure != nfsv4::PROC_COMPOUND {
+        return;
+    }
+    let mut a = XdrDecoder::new(call.args);
+    let Some((tag, _minor, ops, first_op)) = (|| {
+        Some((
+            a.get_opaque().ok()?,
+            a.get_u32().ok()?,
+            a.get_u32().ok()?,
+            a.get_u32().ok(),
+        ))
+    })() else {
+        return;
+    };
+    let Some((status, reply_tag, results)) =
+        (|| Some((r.get_u32().ok()?, r.get_opaque().ok()?, r.get_u32().ok()?)))()
+    else {
+        found.push((Check::Compliance, "truncated COMPOUND4res".into()));
+        return;
+    };
+    if reply_tag != tag {
+        found.push((Check::Compliance, "COMPOUND tag not echoed".into()));
+    }
+    if results > ops {
+        found.push((
+            Check::Compliance,
+            format!("{} results for {} ops", results, ops),
+        ));
+    } else if status == 0 && results != ops {
+        found.push((
+            Check::Compliance,
+            format!("NFS4_OK with {} results for {} ops", results, ops),
+        ));
+    }
+    for i in 0..results.min(ops) {
+        let Ok(got) = r.get_u32() else { return };
+        if let (0, Some(first_op)) = (i, first_op) {
+            if got != first_op && got != nfsv4::op::ILLEGAL {
+                found.push((
+                    Check::Compliance,
+                    format!("first result for op {}, call sent op {}", got, first_op),
+                ));
+            }
+        }
+        if r.get_u32().ok() != Some(0)
+            || walk_result(got, r, vendors, found, notes, payloads).is_none()
+        {
+            return;
+        }
+    }
+}
+
+/// Step over one successful result body; `None` when it cannot be
+/// decoded, which ends the walk
+fn walk_result<'a>(
+    opcode: u32,
+    r: &mut XdrDecoder<'a>,
+    vendors: &Registry,
+    found: &mut Vec<(Check, String)>,
+    notes: &mut Vec<Note>,
+    payloads: &mut Vec<Payload<'a>>,
+) -> Option<()> {
+    use nfsv4::op;
+    match opcode {
+        op::PUTFH
+        | op::PUTPUBFH
+        | op::PUTROOTFH
+        | op::SAVEFH
+        | op::RESTOREFH
+        | op::LOOKUP
+        | op::LOOKUPP => {}
+        op::SEQUENCE => {
+            r.get_raw(16 + 5 * 4).ok()?;
+        }
+        op::GETFH => {
+            r.get_opaque().ok()?;
+        }
+        op::READ => {
+            r.get_u32().ok()?;
+            payloads.push(("READ data", r.get_opaque().ok()?));
+        }
+        op::READLINK => {
+            payloads.push(("READLINK target", r.get_opaque().ok()?));
+        }
+        op::GETATTR => {
+            let words = r.get_u32().ok()? as usize;
+            let mask = (0..words)
+                .map(|_| r.get_u32().ok())
+                .collect::<Option<Vec<_>>>()?;
+            let attrs = r.get_opaque().ok()?;
+            let walk = vendor::walk_fattr4(vendors, &mask, attrs);
+            for v in walk.vendor {
+                found.extend(v.findings.iter().map(|f| {
+                    (
+                        Check::Vendor,
+                        format!("{}: attr {}: {}", v.decoder, v.bit, f),
+                    )
+                }));
+                notes.push((v.decoder, format!("attr {}", v.bit), v.value));
+            }
+            if walk.opaque_from.is_none() && walk.trailing > 0 {
+                found.push((
+                    Check::InfoLeak,
+                    format!("{} bytes after the last attribute", walk.trailing),
+                ));
+            }
+        }
+        _ => {
+            let (decoder, d) = vendors.op(opcode, r.rest())?;
+            r.get_raw(d.len).ok()?;
+            found.extend(
+                d.findings
+                    .iter()
+                    .map(|f| (Check::Vendor, format!("{}: op {}: {}", decoder, opcode, f))),
+            );
+            notes.push((decoder.to_string(), format!("op {}", opcode), d.value));
+        }
+    }
+    Some(())
+}
+
+/// Analyze every `.jsonl` transcript under `dir`, in path order
+pub fn analyze_dir(dir: &Path) -> Result<Analyzer, AnalyzeError> {
+    analyze_dir_with(dir, Registry::new())
+}
+
+/// [`analyze_dir`], decoding vendor extensions with `vendors`
+pub fn analyze_dir_with(dir: &Path, vendors: Registry) -> Result<Analyzer, AnalyzeError> {
+    let mut files = Vec::new();
+    collect(dir, &mut files)?;
+    files.sort();
+    let mut analyzer = Analyzer::with_vendors(vendors);
+    for path in files {
+        let text = std::fs::read_to_string(&path).map_err(|source| AnalyzeError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        let entries =
+            transcript::parse_jsonl(&text).map_err(|source| AnalyzeError::Transcript {
+                path: path.clone(),
+                source,
+            })?;
+        // parse_jsonl skips blank lines; map entries back to line numbers
+        let lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| !l.trim().is_empty());
+        for ((i, _), pair) in lines.zip(&entries) {
+            analyzer.entry(&path, i + 1, pair);
+        }
+    }
+    Ok(analyzer)
+}
+
+fn collect(dir: &Path, out: &mut Vec<PathBuf>) -> Result<(), AnalyzeError> {
+    let io = |source| AnalyzeError::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+    for entry in std::fs::read_dir(dir).map_err(io)? {
+        let path = entry.map_err(io)?.path();
+        if path.is_dir() {
+            collect(&path, out)?;
+        } else if path.extension().is_some_and(|e| e == "jsonl") {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{auth_stat, RpcCall};
+    use crate::xdr::XdrEncoder;
+
+    fn call(xid: u32, version: u32, procedure: u32, args: &[u8]) -> Vec<u8> {
+        RpcCall::new(xid, program::NFS, version, procedure, false)
+            .with_auth_sys("m", 0, 0)
+            .with_args(args)
+            .build()
+            .to_vec()
+    }
+
+    fn accepted(xid: u32, body: impl FnOnce(&mut XdrEncoder)) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        for v in [xid, msg_type::REPLY, reply_stat::MSG_ACCEPTED, 0, 0, 0] {
+            enc.put_u32(v);
+        }
+        body(&mut enc);
+        enc.into_bytes().to_vec()
+    }
+
+    fn getattr_reply(xid: u32, fileid: u64, nsec: u32) -> Vec<u8> {
+        accepted(xid, |enc| {
+            enc.put_u32(0);
+            enc.put_u32(1); // NF3REG
+            enc.put_raw(&[0; 16 + 16 + 8]);
+            enc.put_u64(7); // fsid
+            enc.put_u64(fileid);
+            for _ in 0..3 {
+                enc.put_u32(1);
+                enc.put_u32(nsec);
+            }
+        })
+    }
+
+    fn checks(a: &Analyzer) -> Vec<Check> {
+        a.findings.iter().map(|f| f.check).collect()
+    }
+
+    #[test]
+    fn test_v3_attrs_and_leaks() {
+        let mut fh = XdrEncoder::new();
+        fh.put_opaque(b"handle");
+        let path = Path::new("t.jsonl");
+        let mut a = Analyzer::new();
+        let pair = |xid, reply| TranscriptEntry {
+            call: call(xid, 3, 1, fh.as_bytes()),
+            reply: Some(reply),
+        };
+        a.entry(path, 1, &pair(1, getattr_reply(1, 42, 5)));
+        assert!(a.findings.is_empty(), "{:?}", a.findings);
+        a.entry(path, 2, &pair(2, getattr_reply(2, 43, 5)));
+        a.entry(path, 3, &pair(3, getattr_reply(3, 43, 1_000_000_000)));
+        let mut trailing = getattr_reply(4, 43, 5);
+        trailing.extend_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        a.entry(path, 4, &pair(4, trailing));
+        assert_eq!(
+            checks(&a),
+            [
+                Check::AttrConsistency,
+                Check::AttrConsistency,
+                Check::InfoLeak
+            ]
+        );
+        assert!(a.findings[0].detail.contains("fileid"));
+        assert_eq!(
+            a.findings[2].to_string(),
+            "t.jsonl:4: InfoLeak: 4 bytes after the result"
+        );
+    }
+
+    #[test]
+    fn test_read_and_readlink_splitting() {
+        let mut fh = XdrEncoder::new();
+        fh.put_opaque(b"link");
+        let path = Path::new("t.jsonl");
+        let mut a = Analyzer::new();
+        let read = |xid, count: u32, data: &[u8]| {
+            let data = data.to_vec();
+            let reply = accepted(xid, move |enc| {
+                enc.put_u32(0);
+                enc.put_u32(0); // no attributes
+                enc.put_u32(count);
+                enc.put_u32(1);
+                enc.put_opaque(&data);
+            });
+            TranscriptEntry {
+                call: call(xid, 3, nfsv3::proc::READ, fh.as_bytes()),
+                reply: Some(reply),
+            }
+        };
+        let readlink = |xid, target: &'static [u8]| TranscriptEntry {
+            call: call(xid, 3, nfsv3::proc::READLINK, fh.as_bytes()),
+            reply: Some(accepted(xid, |enc| {
+                enc.put_u32(0);
+                enc.put_u32(0);
+                enc.put_opaque(target);
+            })),
+        };
+        a.entry(path, 1, &read(0x10, 4, b"data"));
+        a.entry(path, 2, &readlink(0x11, b"/srv/a"));
+        a.entry(path, 3, &readlink(0x12, b"/srv/a"));
+        assert!(a.findings.is_empty(), "{:?}", a.findings);
+
+        // The reply to 0x10 left in the buffer READ 0x13 goes out in
+        let stale = [&[0, 0, 0, 0x10, 0, 0, 0, 1][..], b"data"].concat();
+        a.entry(path, 4, &read(0x13, 12, &stale));
+        a.entry(path, 5, &read(0x14, 99, b"data"));
+        let mut split = read(0x15, 4, b"data");
+        let next = accepted(0x16, |enc| enc.put_u32(0));
+        split.reply.as_mut().unwrap().extend_from_slice(&next);
+        a.entry(path, 6, &split);
+        let mut cut = read(0x17, 4, b"data");
+        cut.reply.as_mut().unwrap().truncate(46);
+        a.entry(path, 7, &cut);
+        a.entry(path, 8, &readlink(0x18, b"/etc"));
+        assert_eq!(checks(&a), [Check::Splitting; 5]);
+        let details: Vec<_> = a.findings.iter().map(|f| f.detail.as_str()).collect();
+        assert_eq!(
+            details,
+            [
+                "READ data holds a reply header for xid 0x10 at offset 0",
+                "READ count 99 for 4 bytes of data",
+                "28 bytes after the result begin a reply to xid 0x16",
+                "READ data of 4 bytes runs past the end of the record (2 left)",
+                r#"symlink target changed from "/srv/a" to "/etc""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compound_and_auth_checks() {
+        let mut args = XdrEncoder::new();
+        args.put_opaque(b"t");
+        args.put_u32(1);
+        args.put_u32(2);
+        args.put_u32(crate::nfsv4::op::SEQUENCE);
+        let path = Path::new("v4.jsonl");
+        let mut a = Analyzer::new();
+        let reply = accepted(9, |enc| {
+            enc.put_u32(0);
+            // Tag with dirty padding, and too few results for NFS4_OK
+            enc.put_raw(&[0, 0, 0, 1, b't', 0xaa, 0, 0]);
+            enc.put_u32(1);
+            enc.put_u32(crate::nfsv4::op::SEQUENCE);
+        });
+        let v4 = call(9, 4, 1, args.as_bytes());
+        a.entry(
+            path,
+            1,
+            &TranscriptEntry {
+                call: v4.clone(),
+                reply: Some(reply),
+            },
+        );
+        assert_eq!(checks(&a), [Check::Compliance, Check::InfoLeak]);
+
+        let mut denied = XdrEncoder::new();
+        for v in [
+            10,
+            msg_type::REPLY,
+            reply_stat::MSG_DENIED,
+            reject_stat::AUTH_ERROR,
+            auth_stat::AUTH_TOOWEAK,
+        ] {
+            denied.put_u32(v);
+        }
+        let mut v4_10 = v4.clone();
+        v4_10[..4].copy_from_slice(&10u32.to_be_bytes());
+        a.entry(
+            path,
+            2,
+            &TranscriptEntry {
+                call: v4_10,
+                reply: Some(denied.into_bytes().to_vec()),
+            },
+        );
+        assert_eq!(a.findings.last().unwrap().check, Check::AuthFlip);
+    }
+
+    #[test]
+    fn test_vendor_results_annotated() {
+        use crate::nfsv4::{attr, bitmap_from_bits, op, put_bitmap};
+        use crate::vendor::SpecDecoder;
+
+        let mut args = XdrEncoder::new();
+        args.put_opaque(b"");
+        args.put_u32(1);
+        args.put_u32(3);
+        args.put_u32(op::PUTROOTFH);
+        let mut vendors = Registry::new();
+        vendors.register(
+            SpecDecoder::from_json(
+                r#"{"name": "acme", "attrs": [{"bit": 100, "name": "tier",
+                    "fields": [{"name": "id", "type": "u32"}]}],
+                   "ops": [{"opcode": 9000, "name": "acme_ping",
+                    "result": [{"name": "ok", "type": "bool"}]}]}"#,
+            )
+            .unwrap(),
+        );
+        let mut a = Analyzer::with_vendors(vendors);
+        let reply = accepted(5, |enc| {
+            enc.put_u32(0);
+            enc.put_opaque(b"");
+            enc.put_u32(3);
+            enc.put_u32(op::PUTROOTFH);
+            enc.put_u32(0);
+            enc.put_u32(op::GETATTR);
+            enc.put_u32(0);
+            put_bitmap(enc, &bitmap_from_bits(&[attr::SIZE, 100]));
+            enc.put_opaque(&[0, 0, 0, 0, 0, 0, 16, 0, 0, 0, 0, 3, 0xee, 0xee, 0xee, 0xee]);
+            enc.put_u32(9000);
+            enc.put_u32(0);
+            enc.put_u32(2);
+        });
+        a.entry(
+            Path::new("x.jsonl"),
+            1,
+            &TranscriptEntry {
+                call: call(5, 4, 1, args.as_bytes()),
+                reply: Some(reply),
+            },
+        );
+        assert_eq!(checks(&a), [Check::InfoLeak, Check::Vendor]);
+        assert_eq!(a.findings[0].detail, "4 bytes after the last attribute");
+        assert_eq!(a.findings[1].detail, "acme: op 9000: ok is bool 2");
+        let notes: Vec<String> = a.annotations.iter().map(|n| n.to_string()).collect();
+        assert_eq!(
+            notes,
+            [
+                r#"x.jsonl:1: acme attr 100: {"tier":{"id":3}}"#,
+                r#"x.jsonl:1: acme op 9000: {"acme_ping":{"ok":true}}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn test_analyze_dir_walks_transcripts() {
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-analyze-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("run1")).unwrap();
+        let c = call(1, 3, 0, &[]);
+        let r = accepted(2, |_| {});
+        let line = format!(
+            "{{\"call\": \"{}\", \"reply\": \"{}\"}}\n\n",
+            hex::encode(&c),
+            hex::encode(&r)
+        );
+        std::fs::write(dir.join("run1/a.jsonl"), format!("\n{}", line)).unwrap();
+        std::fs::write(dir.join("ignored.bin"), b"x").unwrap();
+        let a = analyze_dir(&dir).unwrap();
+        assert_eq!(a.findings.len(), 1);
+        assert_eq!(a.findings[0].entry, 2);
+        assert!(a.findings[0].detail.contains("xid 0x2 answers call 0x1"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(analyze_dir(&dir).is_err());
+    }
+}
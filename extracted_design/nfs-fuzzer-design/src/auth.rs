@@ -0,0 +1,296 @@
+//! AUTH_SYS identities (RFC 5531 Appendix A)
+//!
+//! Servers key idmapping, access and credential caches on the identity in
+//! AUTH_SYS. Rotating through a pool of identities, per connection or per
+//! request, exercises those caches and shows whether state created by one
+//! identity leaks to another.
+
+use crate::rpc::{auth_flavor, auth_none, auth_sys};
+use crate::xdr::XdrEncoder;
+use clap::ValueEnum;
+use std::fmt;
+use std::str::FromStr;
+
+/// One AUTH_SYS identity
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub machine: String,
+    pub uid: u32,
+    pub gid: u32,
+    pub gids: Vec<u32>,
+}
+
+impl Identity {
+    pub fn new(uid: u32, gid: u32) -> Self {
+        Self {
+            machine: "fuzzer".to_string(),
+            uid,
+            gid,
+            gids: Vec::new(),
+        }
+    }
+
+    /// Encoded opaque_auth credential
+    pub fn credential(&self) -> Vec<u8> {
+        auth_sys(&self.machine, self.uid, self.gid, &self.gids)
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.uid, self.gid)?;
+        if !self.gids.is_empty() {
+            let gids: Vec<String> = self.gids.iter().map(|g| g.to_string()).collect();
+            write!(f, ":{}", gids.join(","))?;
+        }
+        write!(f, "@{}", self.machine)
+    }
+}
+
+/// Parses `uid:gid[:gid,gid,...][@machine]`
+impl FromStr for Identity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ids, machine) = match s.split_once('@') {
+            Some((ids, machine)) => (ids, machine),
+            None => (s, "fuzzer"),
+        };
+        let num = |v: &str| {
+            v.parse::<u32>()
+                .map_err(|e| format!("bad id {:?} in {:?}: {}", v, s, e))
+        };
+        let mut parts = ids.splitn(3, ':');
+        let uid = num(parts.next().unwrap_or_default())?;
+        let gid = num(parts
+            .next()
+            .ok_or_else(|| format!("missing gid in {:?}", s))?)?;
+        let gids = match parts.next() {
+            Some(list) if !list.is_empty() => list.split(',').map(num).collect::<Result<_, _>>()?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            machine: machine.to_string(),
+            uid,
+            gid,
+            gids,
+        })
+    }
+}
+
+/// Identities that commonly get special-cased: root, nobody, squashed
+/// and unsigned/signed edge ids
+pub fn default_identities() -> Vec<Identity> {
+    let mut with_groups = Identity::new(1000, 1000);
+    with_groups.gids = vec![0, 4, 27, 1000];
+    let mut long_machine = Identity::new(1001, 1001);
+    long_machine.machine = "m".repeat(255);
+    vec![
+        Identity::new(0, 0),
+        Identity::new(1000, 1000),
+        Identity::new(65534, 65534),
+        Identity::new(65535, 65535),
+        Identity::new(u32::MAX - 1, u32::MAX - 1),
+        Identity::new(u32::MAX, u32::MAX),
+        Identity::new(0x8000_0000, 0),
+        with_groups,
+        long_machine,
+    ]
+}
+
+/// Spec limit on AUTH_SYS supplementary gids (NGRPS)
+pub const NGRPS: usize = 16;
+
+/// Spec limit on an opaque_auth body
+pub const MAX_AUTH_BYTES: usize = 400;
+
+/// gids counts around NGRPS and the 8- and 16-bit limits
+pub const GIDS_BOUNDARY: &[usize] = &[NGRPS, NGRPS + 1, 255, 256, 65535];
+
+/// `base` with exactly each boundary number of distinct gids
+///
+/// The count always matches the data, so a server that sizes a buffer
+/// from the count and then copies the elements is tested honestly. From
+/// 256 gids the body also exceeds MAX_AUTH_BYTES.
+pub fn gids_boundary_cases(base: &Identity) -> Vec<(String, Identity)> {
+    GIDS_BOUNDARY
+        .iter()
+        .map(|&n| {
+            let mut id = base.clone();
+            id.gids = (1..=n as u32).collect();
+            (format!("auth_sys_gids_{}", n), id)
+        })
+        .collect()
+}
+
+/// Flavors no server should take: obsolete, unassigned or out of range
+pub const UNKNOWN_FLAVORS: &[u32] = &[
+    auth_flavor::AUTH_SHORT,
+    auth_flavor::AUTH_DES,
+    7,
+    0x7fff_ffff,
+    u32::MAX,
+];
+
+/// An opaque_auth of `flavor` whose length field is `len`, whatever
+/// `body` holds
+fn opaque_auth(flavor: u32, len: u32, body: &[u8]) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    enc.put_u32(flavor);
+    enc.put_u32(len);
+    enc.put_raw(body);
+    enc.into_bytes().to_vec()
+}
+
+/// Malformed call headers around `base`'s credential, each a name, a
+/// credential and a verifier: unknown flavors, a body past
+/// MAX_AUTH_BYTES, length fields that claim more or less than follows,
+/// and oversized or unknown verifiers
+pub fn header_cases(base: &Identity) -> Vec<(String, Vec<u8>, Vec<u8>)> {
+    let cred = base.credential();
+    let body = &cred[8..];
+    let len = body.len() as u32;
+    let sys = |len| opaque_auth(auth_flavor::AUTH_SYS, len, body);
+    let oversized = vec![0; MAX_AUTH_BYTES + 4];
+    let mut cases: Vec<_> = UNKNOWN_FLAVORS
+        .iter()
+        .map(|&flavor| {
+            let name = format!("auth_flavor_{}", flavor);
+            (name, opaque_auth(flavor, 0, &[]), auth_none())
+        })
+        .collect();
+    let oversized_cred = opaque_auth(auth_flavor::AUTH_SYS, oversized.len() as u32, &oversized);
+    let oversized_verf = opaque_auth(auth_flavor::AUTH_NONE, oversized.len() as u32, &oversized);
+    for (name, cred, verf) in [
+        ("auth_sys_body_oversized", oversized_cred, auth_none()),
+        ("auth_sys_length_long", sys(len + 64), auth_none()),
+        ("auth_sys_length_short", sys(len - 8), auth_none()),
+        ("auth_sys_length_unaligned", sys(len - 1), auth_none()),
+        ("auth_sys_length_max", sys(u32::MAX), auth_none()),
+        ("verifier_oversized", cred.clone(), oversized_verf),
+        (
+            "verifier_flavor_unknown",
+            cred.clone(),
+            opaque_auth(7, 0, &[]),
+        ),
+    ] {
+        cases.push((name.to_string(), cred, verf));
+    }
+    cases
+}
+
+/// When the pool advances to the next identity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Rotation {
+    PerConnection,
+    PerRequest,
+}
+
+/// Round-robin over a pool of identities
+#[derive(Debug, Clone)]
+pub struct IdentityPool {
+    identities: Vec<Identity>,
+    rotation: Rotation,
+    next: usize,
+    current: usize,
+}
+
+impl IdentityPool {
+    /// Falls back to `default_identities` if `identities` is empty
+    pub fn new(identities: Vec<Identity>, rotation: Rotation) -> Self {
+        let identities = if identities.is_empty() {
+            default_identities()
+        } else {
+            identities
+        };
+        Self {
+            identities,
+            rotation,
+            next: 0,
+            current: 0,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.current = self.next;
+        self.next = (self.next + 1) % self.identities.len();
+    }
+
+    /// Call on each new connection; returns the identity it starts with
+    pub fn on_connect(&mut self) -> &Identity {
+        if self.rotation == Rotation::PerConnection {
+            self.advance();
+        }
+        &self.identities[self.current]
+    }
+
+    /// Identity to put on the next request
+    pub fn for_request(&mut self) -> &Identity {
+        if self.rotation == Rotation::PerRequest {
+            self.advance();
+        }
+        &self.identities[self.current]
+    }
+
+    pub fn identities(&self) -> &[Identity] {
+        &self.identities
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identity_parse_roundtrip() {
+        let id: Identity = "0:0:4,27@host".parse().unwrap();
+        assert_eq!(id.gids, vec![4, 27]);
+        assert_eq!(id.to_string(), "0:0:4,27@host");
+        assert_eq!("1:2".parse::<Identity>().unwrap().machine, "fuzzer");
+        assert!("1".parse::<Identity>().is_err());
+        assert!("x:2".parse::<Identity>().is_err());
+    }
+
+    #[test]
+    fn test_gids_boundary_counts_match_data() {
+        for (name, id) in gids_boundary_cases(&Identity::new(0, 0)) {
+            let cred = id.credential();
+            // flavor, length, stamp, machine (4 + 8), uid, gid, then the count
+            let count = u32::from_be_bytes(cred[32..36].try_into().unwrap()) as usize;
+            assert_eq!(count, id.gids.len(), "{}", name);
+            assert_eq!(cred.len(), 36 + 4 * count);
+        }
+    }
+
+    #[test]
+    fn test_header_cases_lie_about_what_follows() {
+        let base = Identity::new(0, 0);
+        let body = base.credential().len() as u32 - 8;
+        let cases = header_cases(&base);
+        assert_eq!(cases.len(), UNKNOWN_FLAVORS.len() + 7);
+        let length = |name: &str| {
+            let (_, cred, _) = cases.iter().find(|(n, _, _)| n == name).unwrap();
+            u32::from_be_bytes(cred[4..8].try_into().unwrap())
+        };
+        assert_eq!(length("auth_sys_length_long"), body + 64);
+        assert_eq!(length("auth_sys_length_max"), u32::MAX);
+        assert!(length("auth_sys_body_oversized") as usize > MAX_AUTH_BYTES);
+        let (_, _, verf) = cases.last().unwrap();
+        assert_eq!(verf, &[0, 0, 0, 7, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_rotation_modes() {
+        let ids = vec![Identity::new(1, 1), Identity::new(2, 2)];
+        let mut per_conn = IdentityPool::new(ids.clone(), Rotation::PerConnection);
+        assert_eq!(per_conn.on_connect().uid, 1);
+        assert_eq!(per_conn.for_request().uid, 1);
+        assert_eq!(per_conn.for_request().uid, 1);
+        assert_eq!(per_conn.on_connect().uid, 2);
+
+        let mut per_req = IdentityPool::new(ids, Rotation::PerRequest);
+        per_req.on_connect();
+        let uids: Vec<u32> = (0..3).map(|_| per_req.for_request().uid).collect();
+        assert_eq!(uids, vec![1, 2, 1]);
+    }
+}
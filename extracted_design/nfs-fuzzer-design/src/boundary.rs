@@ -0,0 +1,508 @@
+//! Boundary cases at the server's advertised limits
+//!
+//! Servers say how much they accept: FSINFO's rtmax, wtmax and
+//! maxfilesize and PATHCONF's name_max in v3, the maxread, maxwrite,
+//! maxfilesize and maxname attributes in v4. Off-by-one checks cluster
+//! right at those numbers, so each limit learned becomes four cases: one
+//! below, exactly at, one above and twice the limit. Reads ask for that
+//! many bytes, writes carry that many, a SETATTR sets that size and a
+//! LOOKUP uses a name that long.
+//!
+//! The limits and cases for a target are kept together in a suite
+//! directory: `limits.json` plus the cases as corpus entries, so later
+//! campaigns against the same server start from the same set, and a
+//! server whose limits changed after an upgrade shows up in a diff.
+
+use crate::auth::Identity;
+use crate::corpus::{Corpus, CorpusError};
+use crate::generate::Input;
+use crate::lineage::Lineage;
+use crate::nfsv3::{self, stable_how, Sattr3};
+use crate::nfsv4::{
+    self, attr, bitmap_from_bits, getattr, getfh, lookup, minor_version, op, putrootfh, status,
+    CompoundBuilder, Fattr, Op, Stateid,
+};
+use crate::pattern::Pattern;
+use crate::rpc::program;
+use crate::scenario::component;
+use crate::xdr::XdrDecoder;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Size of a fattr3 on the wire
+const FATTR3_LEN: usize = 84;
+
+/// Most data a WRITE case carries; the count field still says the full
+/// boundary when it is larger
+pub const MAX_PAYLOAD: usize = 16 << 20;
+
+/// Longest name a LOOKUP case sends
+pub const MAX_NAME: usize = 64 << 10;
+
+/// What a server said it accepts; `None` where it did not say
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerLimits {
+    pub maxread: Option<u64>,
+    pub maxwrite: Option<u64>,
+    pub maxfilesize: Option<u64>,
+    pub maxname: Option<u64>,
+}
+
+impl ServerLimits {
+    /// Limits `other` knows and this does not
+    pub fn merge(&mut self, other: &ServerLimits) {
+        self.maxread = self.maxread.or(other.maxread);
+        self.maxwrite = self.maxwrite.or(other.maxwrite);
+        self.maxfilesize = self.maxfilesize.or(other.maxfilesize);
+        self.maxname = self.maxname.or(other.maxname);
+    }
+
+    pub fn get(&self, limit: Limit) -> Option<u64> {
+        match limit {
+            Limit::Read => self.maxread,
+            Limit::Write => self.maxwrite,
+            Limit::FileSize => self.maxfilesize,
+            Limit::Name => self.maxname,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        Limit::ALL.iter().all(|&l| self.get(l).is_none())
+    }
+}
+
+/// Skip a post_op_attr
+fn skip_post_op_attr(dec: &mut XdrDecoder<'_>) -> Option<()> {
+    if dec.get_bool().ok()? {
+        dec.get_raw(FATTR3_LEN).ok()?;
+    }
+    Some(())
+}
+
+/// rtmax, wtmax and maxfilesize from FSINFO3res
+pub fn decode_fsinfo3(results: &[u8]) -> Option<ServerLimits> {
+    let mut dec = XdrDecoder::new(results);
+    if dec.get_u32().ok()? != 0 {
+        return None;
+    }
+    skip_post_op_attr(&mut dec)?;
+    let rtmax = dec.get_u32().ok()?;
+    // rtpref, rtmult
+    dec.get_raw(8).ok()?;
+    let wtmax = dec.get_u32().ok()?;
+    // wtpref, wtmult, dtpref
+    dec.get_raw(12).ok()?;
+    let maxfilesize = dec.get_u64().ok()?;
+    Some(ServerLimits {
+        maxread: Some(rtmax.into()),
+        maxwrite: Some(wtmax.into()),
+        maxfilesize: Some(maxfilesize),
+        maxname: None,
+    })
+}
+
+/// name_max from PATHCONF3res
+pub fn decode_pathconf3(results: &[u8]) -> Option<ServerLimits> {
+    let mut dec = XdrDecoder::new(results);
+    if dec.get_u32().ok()? != 0 {
+        return None;
+    }
+    skip_post_op_attr(&mut dec)?;
+    let _linkmax = dec.get_u32().ok()?;
+    Some(ServerLimits {
+        maxname: Some(dec.get_u32().ok()?.into()),
+        ..ServerLimits::default()
+    })
+}
+
+/// linkmax from PATHCONF3res
+pub fn decode_linkmax3(results: &[u8]) -> Option<u32> {
+    let mut dec = XdrDecoder::new(results);
+    if dec.get_u32().ok()? != 0 {
+        return None;
+    }
+    skip_post_op_attr(&mut dec)?;
+    dec.get_u32().ok()
+}
+
+/// The GETATTR mask of the v4 limits
+pub fn limits_mask() -> Vec<u32> {
+    bitmap_from_bits(&[
+        attr::MAXFILESIZE,
+        attr::MAXNAME,
+        attr::MAXREAD,
+        attr::MAXWRITE,
+    ])
+}
+
+/// v4.0 PUTROOTFH, GETFH and a GETATTR of [`limits_mask`], which
+/// [`decode_root_limits`] reads
+pub fn root_limits(identity: &Identity) -> bytes::BytesMut {
+    CompoundBuilder::new(minor_version::V4_0)
+        .with_tag(b"limits")
+        .ops([putrootfh(), getfh(), getattr(&limits_mask())])
+        .message(identity)
+}
+
+/// The root handle and limits from the reply to [`root_limits`]
+pub fn decode_root_limits(results: &[u8]) -> Option<(Vec<u8>, ServerLimits)> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    let count = dec.get_u32().ok()?;
+    let mut fh = None;
+    for _ in 0..count {
+        let opcode = dec.get_u32().ok()?;
+        if dec.get_u32().ok()? != status::NFS4_OK {
+            return None;
+        }
+        match opcode {
+            op::GETFH => fh = Some(dec.get_opaque().ok()?.to_vec()),
+            op::GETATTR => {
+                let words = dec.get_u32().ok()?;
+                let mut mask = Vec::new();
+                for _ in 0..words {
+                    mask.push(dec.get_u32().ok()?);
+                }
+                let vals = dec.get_opaque().ok()?;
+                return Some((fh?, decode_fattr4(&mask, vals)?));
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The limits in a fattr4 holding at most the attributes of
+/// [`limits_mask`], in attribute order
+pub fn decode_fattr4(mask: &[u32], vals: &[u8]) -> Option<ServerLimits> {
+    let wanted = limits_mask();
+    let first = mask.first().copied().unwrap_or(0);
+    if first & !wanted[0] != 0 || mask.iter().skip(1).any(|&w| w != 0) {
+        return None;
+    }
+    let has = |bit: u32| first & (1 << bit) != 0;
+    let mut dec = XdrDecoder::new(vals);
+    let mut limits = ServerLimits::default();
+    if has(attr::MAXFILESIZE) {
+        limits.maxfilesize = Some(dec.get_u64().ok()?);
+    }
+    if has(attr::MAXNAME) {
+        limits.maxname = Some(dec.get_u32().ok()?.into());
+    }
+    if has(attr::MAXREAD) {
+        limits.maxread = Some(dec.get_u64().ok()?);
+    }
+    if has(attr::MAXWRITE) {
+        limits.maxwrite = Some(dec.get_u64().ok()?);
+    }
+    Some(limits)
+}
+
+/// One of the limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    Read,
+    Write,
+    FileSize,
+    Name,
+}
+
+impl Limit {
+    pub const ALL: [Limit; 4] = [Limit::Read, Limit::Write, Limit::FileSize, Limit::Name];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Limit::Read => "maxread",
+            Limit::Write => "maxwrite",
+            Limit::FileSize => "maxfilesize",
+            Limit::Name => "maxname",
+        }
+    }
+}
+
+/// Where around the limit a case sits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Side {
+    Below,
+    At,
+    Above,
+    Double,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::Below, Side::At, Side::Above, Side::Double];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::Below => "below",
+            Side::At => "at",
+            Side::Above => "above",
+            Side::Double => "double",
+        }
+    }
+
+    /// The value at this side of `limit`, saturating
+    pub fn of(self, limit: u64) -> u64 {
+        match self {
+            Side::Below => limit.saturating_sub(1),
+            Side::At => limit,
+            Side::Above => limit.saturating_add(1),
+            Side::Double => limit.saturating_mul(2),
+        }
+    }
+}
+
+/// One boundary case
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Boundary {
+    pub limit: Limit,
+    pub side: Side,
+    pub value: u64,
+}
+
+impl Boundary {
+    /// `maxwrite_above`
+    pub fn name(&self) -> String {
+        format!("{}_{}", self.limit.name(), self.side.name())
+    }
+
+    /// The data a WRITE case carries
+    fn data(&self) -> Vec<u8> {
+        let len = self.value.min(MAX_PAYLOAD as u64) as usize;
+        Pattern::Stamp { tag: 0xb0d7 }.fill(0, len)
+    }
+
+    fn name_bytes(&self) -> Vec<u8> {
+        component(0, (self.value as usize).clamp(1, MAX_NAME), b'n')
+    }
+
+    /// The v3 call, on `file` or, for names, in `dir`
+    pub fn v3_call(&self, file: &[u8], dir: &[u8]) -> nfsv3::Call {
+        let count = self.value.min(u32::MAX.into()) as u32;
+        match self.limit {
+            Limit::Read => nfsv3::read(file, 0, count),
+            Limit::Write => nfsv3::write(file, 0, count, stable_how::FILE_SYNC, &self.data()),
+            Limit::FileSize => {
+                let attrs = Sattr3 {
+                    size: Some(self.value),
+                    ..Sattr3::default()
+                };
+                nfsv3::setattr(file, &attrs, None)
+            }
+            Limit::Name => nfsv3::lookup(dir, &self.name_bytes()),
+        }
+    }
+
+    /// The v4 op, on the current filehandle
+    pub fn v4_op(&self) -> Op {
+        let anon = Stateid::ANONYMOUS;
+        let count = self.value.min(u32::MAX.into()) as u32;
+        match self.limit {
+            Limit::Read => nfsv4::read(&anon, 0, count),
+            Limit::Write => nfsv4::write(&anon, 0, nfsv4::stable_how::FILE_SYNC, &self.data()),
+            Limit::FileSize => nfsv4::setattr(
+                &anon,
+                &Fattr {
+                    mask: bitmap_from_bits(&[attr::SIZE]),
+                    vals: self.value.to_be_bytes().to_vec(),
+                },
+            ),
+            Limit::Name => lookup(&self.name_bytes()),
+        }
+    }
+}
+
+/// Every boundary of every limit known, duplicates of a saturated value
+/// dropped
+pub fn boundaries(limits: &ServerLimits) -> Vec<Boundary> {
+    let mut out = Vec::new();
+    for limit in Limit::ALL {
+        let Some(max) = limits.get(limit) else {
+            continue;
+        };
+        let mut seen = Vec::new();
+        for side in Side::ALL {
+            let value = side.of(max);
+            if !seen.contains(&value) {
+                seen.push(value);
+                out.push(Boundary { limit, side, value });
+            }
+        }
+    }
+    out
+}
+
+fn input(boundary: &Boundary, version: u32, procedure: u32, args: Vec<u8>) -> Input {
+    let name = boundary.name();
+    Input {
+        lineage: Lineage::new(format!("boundary:{}", name)),
+        name,
+        program: program::NFS,
+        version,
+        procedure,
+        args,
+        auth: None,
+    }
+}
+
+/// The v3 cases of `limits` against `file` and `dir`
+pub fn v3_inputs(limits: &ServerLimits, file: &[u8], dir: &[u8]) -> Vec<Input> {
+    boundaries(limits)
+        .iter()
+        .map(|b| {
+            let call = b.v3_call(file, dir);
+            input(b, 3, call.procedure, call.args)
+        })
+        .collect()
+}
+
+/// The v4.0 cases of `limits`, each a COMPOUND of PUTFH `fh` and the op
+pub fn v4_inputs(limits: &ServerLimits, fh: &[u8]) -> Vec<Input> {
+    boundaries(limits)
+        .iter()
+        .map(|b| {
+            let args = CompoundBuilder::new(minor_version::V4_0)
+                .putfh(fh)
+                .op(b.v4_op())
+                .build();
+            input(b, 4, nfsv4::PROC_COMPOUND, args)
+        })
+        .collect()
+}
+
+/// A target's limits and the cases made from them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suite {
+    pub limits: ServerLimits,
+    pub inputs: Vec<Input>,
+}
+
+impl Suite {
+    pub const LIMITS: &'static str = "limits.json";
+
+    /// `<output>/boundaries/<address>_<port>_v<version>`
+    pub fn dir(output: &Path, target: SocketAddr, version: u32) -> PathBuf {
+        output
+            .join("boundaries")
+            .join(format!("{}_{}_v{}", target.ip(), target.port(), version))
+    }
+
+    /// Write the limits and, as corpus entries, the cases into `dir`
+    pub fn save(&self, dir: &Path, identity: &Identity) -> Result<(), CorpusError> {
+        let corpus = Corpus::open(dir);
+        for (i, input) in self.inputs.iter().enumerate() {
+            let stem = format!("{:03}_{}", i, input.name);
+            corpus.write(&stem, input, &input.message(identity))?;
+        }
+        let path = dir.join(Self::LIMITS);
+        let json = serde_json::to_vec_pretty(&self.limits).map_err(|source| CorpusError::Json {
+            path: path.clone(),
+            source,
+        })?;
+        std::fs::write(&path, json).map_err(|source| CorpusError::Io { path, source })
+    }
+
+    /// The limits saved in `dir`
+    pub fn load_limits(dir: &Path) -> Result<ServerLimits, CorpusError> {
+        let path = dir.join(Self::LIMITS);
+        let text = std::fs::read_to_string(&path).map_err(|source| CorpusError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        serde_json::from_str(&text).map_err(|source| CorpusError::Json { path, source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdr::XdrEncoder;
+
+    #[test]
+    fn test_decode_limits() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(0);
+        enc.put_bool(true);
+        enc.put_raw(&[0; FATTR3_LEN]);
+        for v in [1 << 20, 4096, 4096, 1 << 19, 4096, 4096, 4096] {
+            enc.put_u32(v);
+        }
+        enc.put_u64(u64::MAX);
+        let limits = decode_fsinfo3(enc.as_bytes()).unwrap();
+        assert_eq!(
+            (limits.maxread, limits.maxwrite, limits.maxfilesize),
+            (Some(1 << 20), Some(1 << 19), Some(u64::MAX))
+        );
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(0);
+        enc.put_bool(false);
+        enc.put_u32(32_000);
+        enc.put_u32(255);
+        assert_eq!(decode_pathconf3(enc.as_bytes()).unwrap().maxname, Some(255));
+
+        // PUTROOTFH, GETFH, GETATTR of maxname and maxwrite
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(status::NFS4_OK);
+        enc.put_opaque(b"limits");
+        enc.put_u32(3);
+        enc.put_u32(op::PUTROOTFH);
+        enc.put_u32(status::NFS4_OK);
+        enc.put_u32(op::GETFH);
+        enc.put_u32(status::NFS4_OK);
+        enc.put_opaque(&[7; 8]);
+        enc.put_u32(op::GETATTR);
+        enc.put_u32(status::NFS4_OK);
+        enc.put_u32(1);
+        enc.put_u32(1 << attr::MAXNAME | 1 << attr::MAXWRITE);
+        let mut vals = XdrEncoder::new();
+        vals.put_u32(255);
+        vals.put_u64(1 << 20);
+        enc.put_opaque(vals.as_bytes());
+        let (fh, limits) = decode_root_limits(enc.as_bytes()).unwrap();
+        assert_eq!(fh, [7; 8]);
+        assert_eq!(
+            (limits.maxname, limits.maxwrite),
+            (Some(255), Some(1 << 20))
+        );
+        assert_eq!(limits.maxread, None);
+        // An attribute with no known width cannot be skipped
+        assert_eq!(decode_fattr4(&[1 << attr::SIZE], &[0; 8]), None);
+    }
+
+    #[test]
+    fn test_boundary_suite() {
+        let limits = ServerLimits {
+            maxwrite: Some(8192),
+            maxfilesize: Some(u64::MAX),
+            maxname: Some(255),
+            ..ServerLimits::default()
+        };
+        let cases = boundaries(&limits);
+        // maxfilesize saturates above and at double
+        assert_eq!(cases.len(), 4 + 2 + 4);
+        let values: Vec<u64> = cases[..4].iter().map(|b| b.value).collect();
+        assert_eq!(values, [8191, 8192, 8193, 16384]);
+        assert_eq!(cases[3].name(), "maxwrite_double");
+
+        let v3 = v3_inputs(&limits, &[1; 32], &[2; 32]);
+        assert_eq!(v3[1].procedure, nfsv3::proc::WRITE);
+        // fh, offset, count, stable, then the data
+        assert_eq!(v3[1].args.len(), 4 + 32 + 8 + 4 + 4 + 4 + 8192);
+        let name = v3.iter().find(|i| i.name == "maxname_above").unwrap();
+        assert_eq!(name.args.len(), 4 + 32 + 4 + 256);
+        let v4 = v4_inputs(&limits, &[3; 16]);
+        assert_eq!(v4.len(), v3.len());
+        assert_eq!(v4[0].lineage.seed, "boundary:maxwrite_below");
+
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-boundary-{}", std::process::id()));
+        let suite = Suite { limits, inputs: v4 };
+        let at = Suite::dir(&dir, "10.0.0.2:2049".parse().unwrap(), 4);
+        assert!(at.ends_with("boundaries/10.0.0.2_2049_v4"));
+        suite.save(&at, &Identity::new(0, 0)).unwrap();
+        assert_eq!(Suite::load_limits(&at).unwrap(), limits);
+        assert!(at.join("000_maxwrite_below.bin").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
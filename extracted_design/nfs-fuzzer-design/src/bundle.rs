@@ -0,0 +1,546 @@
+//! Bug-report bundles
+//!
+//! A finding is only worth as much as the report a vendor can act on.
+//! [`Bundle::build`] gathers one behavior bucket, the findings sharing a
+//! [`Signature::id`], into what such a report needs: the reproducer,
+//! minimized when it was, with its connection so `nfs-fuzzer replay`
+//! runs it as is; a pcapng of its calls for Wireshark; a decoded
+//! transcript; what is known of the server; everything saved with the
+//! finding, such as target logs and kernel lines; and a `README.md`
+//! describing it. [`Bundle::write`] packs it as a `.tar.gz`.
+
+use crate::corpus::Meta;
+use crate::fields::Message;
+use crate::minimize;
+use crate::pcap;
+use crate::replay::{self, Endpoint};
+use crate::repro::Reproduction;
+use crate::rpc::{auth_flavor, msg_type};
+use crate::signature::Signature;
+use crate::stats::CampaignStats;
+use crate::xdr::XdrDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Directories of `<output>` whose findings are bucketed
+const FINDINGS: &[&str] = &["crashes", "hangs"];
+
+/// Client address the pcap's calls come from, in TEST-NET-1
+const CLIENT: &str = "192.0.2.1:800";
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("{path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("no finding under {output} has behavior {bucket}")]
+    NoBucket { output: PathBuf, bucket: String },
+    #[error("{bucket} matches several behaviors: {}", .ids.join(", "))]
+    Ambiguous { bucket: String, ids: Vec<String> },
+}
+
+fn io_at(path: &Path) -> impl FnOnce(io::Error) -> BundleError + '_ {
+    move |source| BundleError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// A saved finding and its signature
+#[derive(Debug, Clone)]
+pub struct Saved {
+    /// Crash directory, or a hang's input
+    pub path: PathBuf,
+    pub signature: Signature,
+}
+
+/// Every finding under `output` that saved a signature, oldest first
+pub fn findings(output: &Path) -> Result<Vec<Saved>, BundleError> {
+    let mut found = Vec::new();
+    for kind in FINDINGS {
+        let dir = output.join(kind);
+        if !dir.is_dir() {
+            continue;
+        }
+        let mut paths = Vec::new();
+        for dirent in std::fs::read_dir(&dir).map_err(io_at(&dir))? {
+            let path = dirent.map_err(io_at(&dir))?.path();
+            if path.is_dir() || path.extension().is_some_and(|e| e == "bin") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            let file = Signature::path_for(&path);
+            let Ok(text) = std::fs::read(&file) else {
+                continue;
+            };
+            if let Ok(signature) = serde_json::from_slice(&text) {
+                found.push(Saved { path, signature });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The findings of bucket `bucket`: the start of a signature id, with or
+/// without its `v<VERSION>:`
+pub fn bucket(output: &Path, bucket: &str) -> Result<Vec<Saved>, BundleError> {
+    let matches = |id: &str| match bucket.contains(':') {
+        true => id.starts_with(bucket),
+        false => id
+            .split_once(':')
+            .is_some_and(|(_, hash)| hash.starts_with(bucket)),
+    };
+    let found: Vec<Saved> = findings(output)?
+        .into_iter()
+        .filter(|s| matches(&s.signature.id()))
+        .collect();
+    let ids: BTreeSet<String> = found.iter().map(|s| s.signature.id()).collect();
+    match ids.len() {
+        0 => Err(BundleError::NoBucket {
+            output: output.to_path_buf(),
+            bucket: bucket.to_string(),
+        }),
+        1 => Ok(found),
+        _ => Err(BundleError::Ambiguous {
+            bucket: bucket.to_string(),
+            ids: ids.into_iter().collect(),
+        }),
+    }
+}
+
+/// What is known of the server a bucket was found on
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerInfo {
+    pub endpoint: Option<Endpoint>,
+    /// Conditions the campaign ran under, such as middlebox interference
+    pub tags: BTreeSet<String>,
+    /// Replies per status over the whole campaign
+    pub statuses: std::collections::BTreeMap<String, u64>,
+}
+
+/// A bug report's files, by path in the archive
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// Id of the behavior bundled
+    pub id: String,
+    pub members: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    /// Bundle bucket `bucket` of the campaign in `output`, from its
+    /// finding with a minimized reproducer if any, else its first
+    pub fn build(output: &Path, bucket: &str) -> Result<Self, BundleError> {
+        let found = self::bucket(output, bucket)?;
+        let chosen = found
+            .iter()
+            .find(|s| minimize::path_for(&s.path).is_dir())
+            .unwrap_or(&found[0]);
+        let id = chosen.signature.id();
+        let minimized = minimize::path_for(&chosen.path);
+        let minimized = minimized.is_dir().then_some(minimized);
+        let source = minimized.as_deref().unwrap_or(&chosen.path);
+        let calls = replay::calls(source).map_err(io_at(source))?;
+        let endpoint = Endpoint::load(&chosen.path).ok();
+        let stats = CampaignStats::load(output).ok();
+
+        let mut bundle = Self {
+            id: id.clone(),
+            members: Vec::new(),
+        };
+        for (stem, call) in &calls {
+            bundle.add(format!("reproducer/{}.bin", stem), call.clone());
+            let note = source
+                .is_dir()
+                .then(|| source.join(format!("{}.json", stem)));
+            if let Some(text) = note.and_then(|n| std::fs::read(n).ok()) {
+                bundle.add(format!("reproducer/{}.json", stem), text);
+            }
+        }
+        if let Some(endpoint) = &endpoint {
+            bundle.add(
+                format!("reproducer/{}", Endpoint::FILE),
+                serde_json::to_vec_pretty(endpoint).unwrap_or_default(),
+            );
+            bundle.add(
+                "reproducer.pcapng",
+                capture(endpoint, &calls).map_err(io_at(source))?,
+            );
+        }
+        bundle.add("transcript.txt", transcript(source, &calls).into_bytes());
+        let server = ServerInfo {
+            endpoint,
+            tags: stats.as_ref().map(|s| s.tags.clone()).unwrap_or_default(),
+            statuses: stats
+                .as_ref()
+                .map(|s| s.statuses.clone())
+                .unwrap_or_default(),
+        };
+        bundle.add(
+            "server.json",
+            serde_json::to_vec_pretty(&server).unwrap_or_default(),
+        );
+        for file in saved_files(&chosen.path).map_err(io_at(&chosen.path))? {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            let data = std::fs::read(&file).map_err(io_at(&file))?;
+            bundle.add(format!("finding/{}", name), data);
+        }
+        let readme = Readme {
+            chosen,
+            findings: found.len(),
+            minimized: minimized.is_some(),
+            calls: &calls,
+            server: &server,
+            members: &bundle.members,
+        };
+        bundle.add("README.md", readme.to_string().into_bytes());
+        Ok(bundle)
+    }
+
+    fn add(&mut self, path: impl Into<String>, data: Vec<u8>) {
+        self.members.push((path.into(), data));
+    }
+
+    /// Name of the bundle's top directory and file: its id, `:` dropped
+    pub fn name(&self) -> String {
+        format!("nfs-fuzzer-{}", self.id.replace(':', "-"))
+    }
+
+    /// Write as a gzipped tar, every member under [`Bundle::name`]
+    pub fn write(&self, out: impl Write) -> io::Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+        for (path, data) in &self.members {
+            let mut header = tar::Header::new_ustar();
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(0o644);
+            header.set_mtime(mtime);
+            header.set_size(data.len() as u64);
+            let path = format!("{}/{}", self.name(), path);
+            tar.append_data(&mut header, path, data.as_slice())?;
+        }
+        tar.into_inner()?.finish()?.flush()
+    }
+
+    /// Write to `<dir>/<name>.tar.gz`
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, BundleError> {
+        let path = dir.join(format!("{}.tar.gz", self.name()));
+        let file = std::fs::File::create(&path).map_err(io_at(&path))?;
+        self.write(io::BufWriter::new(file)).map_err(io_at(&path))?;
+        Ok(path)
+    }
+}
+
+/// The files saved with the finding at `path`: a crash directory's, or
+/// those beside a hang's input with its stem
+fn saved_files(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let (dir, stem) = if path.is_dir() {
+        (path.to_path_buf(), None)
+    } else {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        (dir.to_path_buf(), Some(format!("{}.", stem)))
+    };
+    let mut files = Vec::new();
+    for dirent in std::fs::read_dir(&dir)? {
+        let file = dirent?.path();
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        if file.is_file() && stem.as_ref().is_none_or(|s| name.starts_with(s.as_str())) {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The calls as a pcapng, sent a millisecond apart from the client
+/// [`CLIENT`]; replies were not kept, so there are none
+fn capture(endpoint: &Endpoint, calls: &[(String, Vec<u8>)]) -> io::Result<Vec<u8>> {
+    let client: SocketAddr = CLIENT.parse().expect("client address");
+    let start = SystemTime::now();
+    let mut writer = pcap::Writer::new(Vec::new())?;
+    for (i, (_, call)) in calls.iter().enumerate() {
+        let at = start + Duration::from_millis(i as u64);
+        writer.call(at, endpoint.proto, client, endpoint.target, call)?;
+    }
+    Ok(writer.into_inner())
+}
+
+/// One record-marked call decoded: its RPC header, then the fields of
+/// its arguments as far as their layout can be inferred
+fn decode(out: &mut String, call: &[u8]) {
+    let body = call.get(4..).unwrap_or_default();
+    let mut d = XdrDecoder::new(body);
+    let header = (|| {
+        let xid = d.get_u32().ok()?;
+        if d.get_u32().ok()? != msg_type::CALL {
+            return None;
+        }
+        let rpcvers = d.get_u32().ok()?;
+        let (program, version, procedure) =
+            (d.get_u32().ok()?, d.get_u32().ok()?, d.get_u32().ok()?);
+        let flavor = d.get_u32().ok()?;
+        d.get_opaque().ok()?;
+        d.get_u32().ok()?;
+        d.get_opaque().ok()?;
+        Some((xid, rpcvers, program, version, procedure, flavor))
+    })();
+    let Some((xid, rpcvers, program, version, procedure, flavor)) = header else {
+        let _ = writeln!(out, "  not an RPC call: {}", hex::encode(body));
+        return;
+    };
+    let flavor = match flavor {
+        auth_flavor::AUTH_NONE => "AUTH_NONE".to_string(),
+        auth_flavor::AUTH_SYS => "AUTH_SYS".to_string(),
+        n => format!("flavor {}", n),
+    };
+    let _ = writeln!(
+        out,
+        "  xid {:#010x} rpc v{} program {} v{} proc {} {}",
+        xid, rpcvers, program, version, procedure, flavor
+    );
+    let args = Message::infer(d.rest());
+    let _ = writeln!(out, "  {} argument bytes", args.bytes.len());
+    for field in &args.fields {
+        let _ = writeln!(
+            out,
+            "    {:>6} {:<12} {}",
+            field.offset,
+            format!("{:?}", field.kind),
+            hex::encode(&args.bytes[field.range()])
+        );
+    }
+}
+
+/// The decoded calls of a reproducer, named from their notes when the
+/// reproducer has them
+fn transcript(source: &Path, calls: &[(String, Vec<u8>)]) -> String {
+    let mut out = String::new();
+    for (i, (stem, call)) in calls.iter().enumerate() {
+        let note = source
+            .is_dir()
+            .then(|| source.join(format!("{}.json", stem)));
+        let meta: Option<Meta> = note
+            .and_then(|n| std::fs::read(n).ok())
+            .and_then(|t| serde_json::from_slice(&t).ok());
+        let name = meta.map_or(stem.clone(), |m| m.name);
+        let _ = writeln!(out, "#{} {} ({} bytes)", i, name, call.len());
+        decode(&mut out, call);
+    }
+    out
+}
+
+/// The bundle's `README.md`
+struct Readme<'a> {
+    chosen: &'a Saved,
+    findings: usize,
+    minimized: bool,
+    calls: &'a [(String, Vec<u8>)],
+    server: &'a ServerInfo,
+    members: &'a [(String, Vec<u8>)],
+}
+
+impl std::fmt::Display for Readme<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sig = &self.chosen.signature;
+        let kind = match self.chosen.path.is_dir() {
+            true => "Server stopped answering",
+            false => "Server hung",
+        };
+        writeln!(
+            f,
+            "# {} after program {} v{} procedure {}\n",
+            kind, sig.program, sig.version, sig.procedure
+        )?;
+        if let Some(endpoint) = &self.server.endpoint {
+            writeln!(
+                f,
+                "Found by nfs-fuzzer against {} over {:?}, NFS v{}.\n",
+                endpoint.target, endpoint.proto, endpoint.nfs_version
+            )?;
+        }
+        writeln!(f, "| | |\n|---|---|")?;
+        writeln!(f, "| Behavior | `{}` |", sig)?;
+        writeln!(f, "| Signature | `{}` |", sig.id())?;
+        writeln!(f, "| Findings with it | {} |", self.findings)?;
+        let reproduction = match Reproduction::load(&self.chosen.path) {
+            Ok(r) => format!("{} ({} of {} trials)", r.verdict(), r.reproduced, r.trials),
+            Err(_) => "not verified".to_string(),
+        };
+        writeln!(f, "| Reproduces | {} |", reproduction)?;
+        let bytes: usize = self.calls.iter().map(|(_, c)| c.len()).sum();
+        writeln!(
+            f,
+            "| Reproducer | {} calls, {} bytes{} |",
+            self.calls.len(),
+            bytes,
+            if self.minimized { ", minimized" } else { "" }
+        )?;
+        writeln!(f, "\n## Reproducing\n")?;
+        writeln!(
+            f,
+            "The calls in `reproducer/` are record-marked ONC RPC messages, sent \
+             in name order on one connection. To send them again:\n"
+        )?;
+        writeln!(
+            f,
+            "```\nnfs-fuzzer replay reproducer --to <server>:2049\n```\n"
+        )?;
+        let pcap = self.members.iter().any(|(p, _)| p == "reproducer.pcapng");
+        if pcap {
+            writeln!(f, "`reproducer.pcapng` holds the same calls for Wireshark.")?;
+        }
+        writeln!(f, "`transcript.txt` decodes them.")?;
+        if !self.server.tags.is_empty() {
+            writeln!(f, "\n## Campaign conditions\n")?;
+            for tag in &self.server.tags {
+                writeln!(f, "- `{}`", tag)?;
+            }
+        }
+        writeln!(f, "\n## Contents\n")?;
+        for (path, data) in self.members {
+            writeln!(f, "- `{}` ({} bytes)", path, data.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Proto;
+    use crate::corpus::Corpus;
+    use crate::generate::Input;
+    use crate::lineage::Lineage;
+    use crate::rpc::program;
+    use std::io::Read;
+
+    /// The members of a tar.gz written by [`Bundle::write`], read back
+    fn unpack(gz: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(gz));
+        let mut members = Vec::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data).unwrap();
+            members.push((path, data));
+        }
+        members
+    }
+
+    #[test]
+    fn test_archive_format() {
+        let long = format!("top/{}/file.bin", "d".repeat(120));
+        let bundle = Bundle {
+            id: "v1:00ff".to_string(),
+            members: vec![
+                ("README.md".to_string(), b"# hi\n".to_vec()),
+                (long.clone(), vec![7; 70_000]),
+            ],
+        };
+        let mut gz = Vec::new();
+        bundle.write(&mut gz).unwrap();
+        let members = unpack(&gz);
+        assert_eq!(
+            members[0],
+            (
+                "nfs-fuzzer-v1-00ff/README.md".to_string(),
+                b"# hi\n".to_vec()
+            )
+        );
+        assert_eq!(members[1].0, format!("nfs-fuzzer-v1-00ff/{}", long));
+        assert_eq!(members[1].1, vec![7; 70_000]);
+        assert!(gz.len() < 1024);
+    }
+
+    #[test]
+    fn test_bundle_of_a_minimized_crash() {
+        let output = std::env::temp_dir().join(format!("nfs-fuzzer-bundle-{}", std::process::id()));
+        let input = |name: &str, args: Vec<u8>| Input {
+            name: name.to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure: 1,
+            args,
+            lineage: Lineage::new(name),
+            auth: None,
+        };
+        let identity = crate::auth::Identity::new(0, 0);
+        let endpoint = Endpoint {
+            target: "10.0.0.5:2049".parse().unwrap(),
+            proto: Proto::Tcp,
+            program: crate::rpc::program::NFS,
+            nfs_version: 3,
+            connect_ms: 1000,
+            request_ms: 1000,
+        };
+        let crash = output.join("crashes").join("00000042");
+        let corpus = Corpus::open(&crash);
+        for (i, case) in [
+            input("v3:NULL", vec![]),
+            input("v3:GETATTR", vec![0, 0, 0, 4, 1, 2, 3, 4]),
+        ]
+        .iter()
+        .enumerate()
+        {
+            corpus
+                .write(
+                    &format!("{:03}_{}", i, case.name),
+                    case,
+                    &case.message(&identity),
+                )
+                .unwrap();
+        }
+        let last = input("v3:GETATTR", vec![0, 0, 0, 4, 1, 2, 3, 4]);
+        Corpus::open(minimize::path_for(&crash))
+            .write("000_v3:GETATTR", &last, &last.message(&identity))
+            .unwrap();
+        endpoint.save(&crash).unwrap();
+        std::fs::write(crash.join("target.log"), "nfsd: panic\n").unwrap();
+        let signature = Signature::of_outcome(&last, crate::signature::Connection::Closed);
+        signature.save(&crash).unwrap();
+
+        let id = signature.id();
+        let bundle = Bundle::build(&output, &id[3..10]).unwrap();
+        assert_eq!(bundle.id, id);
+        let names: Vec<&str> = bundle.members.iter().map(|(p, _)| p.as_str()).collect();
+        assert!(names.contains(&"reproducer/000_v3:GETATTR.bin"));
+        assert!(!names.contains(&"reproducer/001_v3:GETATTR.bin"));
+        assert!(names.contains(&"reproducer.pcapng"));
+        assert!(names.contains(&"finding/target.log"));
+        let member = |n: &str| {
+            let data = &bundle.members.iter().find(|(p, _)| p == n).unwrap().1;
+            String::from_utf8_lossy(data).to_string()
+        };
+        assert!(member("transcript.txt").contains("program 100003 v3 proc 1 AUTH_SYS"));
+        assert!(member("transcript.txt").contains("01020304"));
+        let readme = member("README.md");
+        assert!(
+            readme.starts_with("# Server stopped answering after program 100003 v3 procedure 1")
+        );
+        assert!(readme.contains("1 calls, ") && readme.contains("minimized"));
+
+        let path = bundle.save(&output).unwrap();
+        assert_eq!(
+            unpack(&std::fs::read(path).unwrap()).len(),
+            bundle.members.len()
+        );
+        assert!(matches!(
+            Bundle::build(&output, "ffffffff"),
+            Err(BundleError::NoBucket { .. })
+        ));
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
@@ -0,0 +1,490 @@
+//! Stale data from caching tiers
+//!
+//! A proxy or re-exporting server in front of the origin, such as knfsd
+//! re-exporting a mount backed by cachefilesd, answers from attributes and
+//! pages it cached. Close-to-open semantics let it serve them for the
+//! attribute cache window (the client's actimeo), and no longer. The
+//! scenarios here write through the origin and read attributes and data
+//! of the same file back through the cache. Every committed write or
+//! truncation is a new [`Versions`] entry, and anything the cache serves
+//! must match a version committed no earlier than the window allows.
+
+use crate::nfsv3::{self, stable_how, Call, Sattr3};
+use crate::pattern::Pattern;
+use crate::xdr::XdrDecoder;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Which server a step goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Path {
+    /// The server that owns the file
+    Origin,
+    /// The proxy or re-export in front of it
+    Cache,
+}
+
+/// One step of a scenario
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// FILE_SYNC write through the origin
+    Write { offset: u64, data: Vec<u8> },
+    /// SETATTR of size through the origin
+    Truncate { size: u64 },
+    /// GETATTR through the cache
+    Getattr,
+    /// READ through the cache
+    Read { offset: u64, count: u32 },
+    /// Wait without sending anything
+    Settle(Duration),
+}
+
+impl Action {
+    /// Where the step goes; `None` for a pause
+    pub fn path(&self) -> Option<Path> {
+        match self {
+            Action::Write { .. } | Action::Truncate { .. } => Some(Path::Origin),
+            Action::Getattr | Action::Read { .. } => Some(Path::Cache),
+            Action::Settle(_) => None,
+        }
+    }
+}
+
+/// v3 handles of the same file on each path, filled in at run time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Paths {
+    pub origin: Vec<u8>,
+    pub cache: Vec<u8>,
+}
+
+impl Paths {
+    /// The v3 call of a step
+    pub fn call(&self, action: &Action) -> Option<Call> {
+        Some(match action {
+            Action::Write { offset, data } => nfsv3::write(
+                &self.origin,
+                *offset,
+                data.len() as u32,
+                stable_how::FILE_SYNC,
+                data,
+            ),
+            Action::Truncate { size } => {
+                let attrs = Sattr3 {
+                    size: Some(*size),
+                    ..Sattr3::default()
+                };
+                nfsv3::setattr(&self.origin, &attrs, None)
+            }
+            Action::Getattr => nfsv3::getattr(&self.cache),
+            Action::Read { offset, count } => nfsv3::read(&self.cache, *offset, *count),
+            Action::Settle(_) => return None,
+        })
+    }
+}
+
+/// A named sequence of steps on one file, which starts out empty
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Poisoning {
+    pub name: &'static str,
+    pub steps: Vec<Action>,
+}
+
+/// Ways to get the cache to hold on to an old version; `settle` should
+/// be the allowed window plus whatever slack the cache is given
+pub fn poisonings(settle: Duration) -> Vec<Poisoning> {
+    use Action::*;
+    // Stamped so stale data shows which write it came from
+    let write = |tag: u32, offset| Write {
+        offset,
+        data: Pattern::Stamp { tag }.fill(offset, 512),
+    };
+    let read = |count| Read { offset: 0, count };
+    vec![
+        Poisoning {
+            name: "size_after_append",
+            steps: vec![Getattr, write(1, 0), Getattr, Settle(settle), Getattr],
+        },
+        Poisoning {
+            name: "same_size_overwrite",
+            steps: vec![
+                write(1, 0),
+                Settle(settle),
+                read(512),
+                write(2, 0),
+                read(512),
+                Settle(settle),
+                read(512),
+            ],
+        },
+        Poisoning {
+            name: "truncate_under_cached_pages",
+            steps: vec![
+                write(1, 0),
+                write(1, 512),
+                Settle(settle),
+                read(1024),
+                Truncate { size: 0 },
+                Settle(settle),
+                Getattr,
+                read(1024),
+            ],
+        },
+        Poisoning {
+            name: "rapid_appends",
+            steps: vec![
+                Getattr,
+                write(1, 0),
+                Getattr,
+                write(2, 512),
+                Getattr,
+                write(3, 1024),
+                write(4, 1536),
+                Settle(settle),
+                Getattr,
+                read(2048),
+            ],
+        },
+        Poisoning {
+            name: "extend_by_truncate",
+            steps: vec![
+                write(1, 0),
+                Settle(settle),
+                read(512),
+                Getattr,
+                Truncate { size: 8192 },
+                Settle(settle),
+                Getattr,
+                Read {
+                    offset: 4096,
+                    count: 512,
+                },
+            ],
+        },
+    ]
+}
+
+/// What the cache served
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Observation {
+    Size(u64),
+    Data {
+        offset: u64,
+        count: u32,
+        data: Vec<u8>,
+    },
+}
+
+/// The observation in the results of a successful cache step
+pub fn observe(action: &Action, results: &[u8]) -> Option<Observation> {
+    let mut dec = XdrDecoder::new(results);
+    if dec.get_u32().ok()? != 0 {
+        return None;
+    }
+    match action {
+        Action::Getattr => {
+            // fattr3: type, mode, nlink, uid and gid come before the size
+            dec.get_raw(5 * 4).ok()?;
+            Some(Observation::Size(dec.get_u64().ok()?))
+        }
+        Action::Read { offset, count } => {
+            if dec.get_bool().ok()? {
+                dec.get_raw(FATTR3_LEN).ok()?;
+            }
+            dec.get_u32().ok()?;
+            dec.get_bool().ok()?;
+            Some(Observation::Data {
+                offset: *offset,
+                count: *count,
+                data: dec.get_opaque().ok()?.to_vec(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// fattr3 is fixed-size
+const FATTR3_LEN: usize = 84;
+
+/// What the cache served against what it had to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stale {
+    /// An old version, `overdue` past the end of its window
+    Old {
+        served: usize,
+        required: usize,
+        overdue: Duration,
+    },
+    /// Something no version ever held
+    Unknown { required: usize },
+}
+
+/// Every version of the file and when it was committed, as time since
+/// the scenario started
+#[derive(Debug, Clone)]
+pub struct Versions {
+    window: Duration,
+    versions: Vec<(Duration, Vec<u8>)>,
+}
+
+impl Versions {
+    /// An empty file; served data may lag commits by up to `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            versions: vec![(Duration::ZERO, Vec::new())],
+        }
+    }
+
+    /// Record a modification the origin committed at `at`
+    pub fn commit(&mut self, at: Duration, action: &Action) {
+        let mut bytes = self.versions.last().unwrap().1.clone();
+        match action {
+            Action::Write { offset, data } => {
+                let (start, end) = (*offset as usize, *offset as usize + data.len());
+                if bytes.len() < end {
+                    bytes.resize(end, 0);
+                }
+                bytes[start..end].copy_from_slice(data);
+            }
+            Action::Truncate { size } => bytes.resize(*size as usize, 0),
+            _ => return,
+        }
+        self.versions.push((at, bytes));
+    }
+
+    fn matches(bytes: &[u8], observation: &Observation) -> bool {
+        match observation {
+            Observation::Size(size) => bytes.len() as u64 == *size,
+            Observation::Data {
+                offset,
+                count,
+                data,
+            } => {
+                let start = (*offset as usize).min(bytes.len());
+                let end = start.saturating_add(*count as usize).min(bytes.len());
+                bytes[start..end] == data[..]
+            }
+        }
+    }
+
+    /// Check what the cache served to a request sent at `at`. Versions
+    /// committed within the window before it may or may not show yet;
+    /// the newest one older than that must.
+    pub fn check(&self, at: Duration, observation: &Observation) -> Option<Stale> {
+        let required = self
+            .versions
+            .iter()
+            .rposition(|(t, _)| *t + self.window <= at)
+            .unwrap_or(0);
+        let matches = |i: usize| Self::matches(&self.versions[i].1, observation);
+        if (required..self.versions.len()).any(matches) {
+            return None;
+        }
+        Some(match (0..required).rev().find(|&i| matches(i)) {
+            Some(served) => Stale::Old {
+                served,
+                required,
+                overdue: at.saturating_sub(self.versions[served + 1].0 + self.window),
+            },
+            None => Stale::Unknown { required },
+        })
+    }
+}
+
+/// Run `steps` through `send`, which returns the NFS results of an
+/// accepted reply, and check what the cache served; returns the index of
+/// each stale step. Commits are timed when the origin's reply arrives and
+/// observations when the request leaves, so timing only ever excuses the
+/// cache.
+pub async fn run<F, Fut>(
+    steps: &[Action],
+    paths: &Paths,
+    window: Duration,
+    mut send: F,
+) -> Vec<(usize, Stale)>
+where
+    F: FnMut(Path, Call) -> Fut,
+    Fut: Future<Output = Option<Vec<u8>>>,
+{
+    let start = Instant::now();
+    let mut versions = Versions::new(window);
+    let mut stale = Vec::new();
+    for (i, action) in steps.iter().enumerate() {
+        let (Some(path), Some(call)) = (action.path(), paths.call(action)) else {
+            if let Action::Settle(pause) = action {
+                tokio::time::sleep(*pause).await;
+            }
+            continue;
+        };
+        let sent = start.elapsed();
+        let Some(results) = send(path, call).await else {
+            continue;
+        };
+        match path {
+            Path::Origin if results.starts_with(&[0; 4]) => {
+                versions.commit(start.elapsed(), action)
+            }
+            Path::Origin => {}
+            Path::Cache => {
+                let found = observe(action, &results).and_then(|o| versions.check(sent, &o));
+                stale.extend(found.map(|s| (i, s)));
+            }
+        }
+    }
+    stale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdr::XdrEncoder;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn test_versions_allow_the_window_only() {
+        let mut v = Versions::new(10 * MS);
+        v.commit(5 * MS, &Action::Truncate { size: 100 });
+        v.commit(
+            20 * MS,
+            &Action::Write {
+                offset: 98,
+                data: b"abcd".to_vec(),
+            },
+        );
+        assert_eq!(v.check(8 * MS, &Observation::Size(0)), None);
+        assert_eq!(v.check(21 * MS, &Observation::Size(100)), None);
+        assert_eq!(
+            v.check(40 * MS, &Observation::Size(100)),
+            Some(Stale::Old {
+                served: 1,
+                required: 2,
+                overdue: 10 * MS
+            })
+        );
+        assert_eq!(
+            v.check(40 * MS, &Observation::Size(7)),
+            Some(Stale::Unknown { required: 2 })
+        );
+        let data = |offset, data: &[u8]| Observation::Data {
+            offset,
+            count: 8,
+            data: data.to_vec(),
+        };
+        assert_eq!(v.check(40 * MS, &data(96, b"\0\0abcd")), None);
+        assert!(matches!(
+            v.check(40 * MS, &data(96, b"\0\0\0\0")),
+            Some(Stale::Old { served: 1, .. })
+        ));
+        // A read that came back short of the data is stale too
+        assert!(v.check(40 * MS, &data(98, b"ab")).is_some());
+    }
+
+    #[test]
+    fn test_observe_replies() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(0);
+        enc.put_raw(&[0; 20]);
+        enc.put_u64(4096);
+        enc.put_raw(&[0; FATTR3_LEN - 28]);
+        assert_eq!(
+            observe(&Action::Getattr, enc.as_bytes()),
+            Some(Observation::Size(4096))
+        );
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(0);
+        enc.put_bool(true);
+        enc.put_raw(&[0; FATTR3_LEN]);
+        enc.put_u32(3);
+        enc.put_bool(true);
+        enc.put_opaque(b"xyz");
+        let read = Action::Read {
+            offset: 7,
+            count: 16,
+        };
+        assert_eq!(
+            observe(&read, enc.as_bytes()),
+            Some(Observation::Data {
+                offset: 7,
+                count: 16,
+                data: b"xyz".to_vec()
+            })
+        );
+        // NFS3ERR_STALE
+        assert_eq!(observe(&Action::Getattr, &[0, 0, 0, 70]), None);
+    }
+
+    /// Results a server holding `file` gives `call`
+    fn answer(file: &mut Vec<u8>, call: &Call) -> Vec<u8> {
+        let mut args = XdrDecoder::new(&call.args);
+        args.get_opaque().unwrap();
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(0);
+        match call.procedure {
+            1 => {
+                enc.put_raw(&[0; 20]);
+                enc.put_u64(file.len() as u64);
+                enc.put_raw(&[0; FATTR3_LEN - 28]);
+            }
+            6 => {
+                let offset = (args.get_u64().unwrap() as usize).min(file.len());
+                let end = (offset + args.get_u32().unwrap() as usize).min(file.len());
+                enc.put_bool(false);
+                enc.put_u32((end - offset) as u32);
+                enc.put_bool(end == file.len());
+                enc.put_opaque(&file[offset..end]);
+            }
+            7 => {
+                let offset = args.get_u64().unwrap() as usize;
+                args.get_raw(8).unwrap();
+                let data = args.get_opaque().unwrap();
+                if file.len() < offset + data.len() {
+                    file.resize(offset + data.len(), 0);
+                }
+                file[offset..offset + data.len()].copy_from_slice(data);
+            }
+            _ => {}
+        }
+        enc.as_bytes().to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_run_flags_a_cache_that_never_revalidates() {
+        let paths = Paths {
+            origin: vec![1; 8],
+            cache: vec![2; 8],
+        };
+        let steps = &poisonings(40 * MS)[0].steps;
+        // An honest pass-through is never stale
+        let mut file = Vec::new();
+        let found = run(steps, &paths, 20 * MS, |_, call| {
+            let results = answer(&mut file, &call);
+            async move { Some(results) }
+        })
+        .await;
+        assert_eq!(found, []);
+
+        // This one keeps the attributes of its first GETATTR forever
+        let (mut origin, mut cached) = (Vec::new(), None);
+        let found = run(steps, &paths, 20 * MS, |path, call| {
+            let results = match path {
+                Path::Origin => answer(&mut origin, &call),
+                Path::Cache => cached
+                    .get_or_insert_with(|| answer(&mut origin, &call))
+                    .clone(),
+            };
+            async move { Some(results) }
+        })
+        .await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, 4);
+        assert!(matches!(
+            found[0].1,
+            Stale::Old {
+                served: 0,
+                required: 1,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,307 @@
+//! Campaign templates
+//!
+//! A lab drives many servers with the same campaign, differing only in
+//! the target, its export and where the results go. A campaign template
+//! is a file of command-line options, as many per line as wanted, `#`
+//! starting a comment, in which `${NAME}` is replaced before the options
+//! are parsed, and `${NAME:-default}` falls back to `default`:
+//!
+//! ```text
+//! # state machine campaign for the lab
+//! --target ${TARGET} --export ${EXPORT:-/export}
+//! --preset state-machine --iterations 100000
+//! --output runs/${RUN_ID}
+//! ```
+//!
+//! A variable is taken from `--var NAME=VALUE` on the command line, then
+//! from the environment, then from the built-ins the fuzzer provides,
+//! such as `RUN_ID`, the campaign id. The template's options go before
+//! the command line's, so an option given on both takes the command
+//! line's value. Comments go before substitution, so a commented-out
+//! line may name variables that are not set. Words are split as a shell
+//! would, with single and double quotes, after substitution, so a value
+//! with spaces must be quoted in the template.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Variable holding the campaign id
+pub const RUN_ID: &str = "RUN_ID";
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("reading {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("line {line}: ${{{name}}} is not set (--var {name}=VALUE or the environment)")]
+    Undefined { line: usize, name: String },
+    #[error("line {line}: unterminated ${{")]
+    Unterminated { line: usize },
+    #[error("line {line}: unterminated quote")]
+    Quote { line: usize },
+    #[error("--var takes NAME=VALUE, got {0:?}")]
+    Var(String),
+    #[error("{0} needs a value")]
+    MissingValue(String),
+}
+
+/// Replace the variables of `text`, line `line` of a template, with what
+/// `lookup` gives for them
+pub fn substitute(
+    text: &str,
+    line: usize,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, TemplateError> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(at) = rest.find("${") {
+        out.push_str(&rest[..at]);
+        let end = rest[at..]
+            .find('}')
+            .ok_or(TemplateError::Unterminated { line })?;
+        let body = &rest[at + 2..at + end];
+        let (name, default) = match body.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (body, None),
+        };
+        let value = lookup(name)
+            .or_else(|| default.map(str::to_string))
+            .ok_or_else(|| TemplateError::Undefined {
+                line,
+                name: name.to_string(),
+            })?;
+        out.push_str(&value);
+        rest = &rest[at + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// `text` up to its comment: a `#` starting a word outside quotes
+pub fn uncomment(text: &str) -> &str {
+    let mut quote = None;
+    let mut in_word = false;
+    for (at, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '#') if !in_word => return &text[..at],
+            (None, c) => in_word = !c.is_whitespace(),
+        }
+    }
+    text
+}
+
+/// The words of `text`, line `line` of a template, split as a shell
+/// would: at whitespace outside quotes, a `#` outside quotes starting a
+/// comment
+pub fn split(text: &str, line: usize) -> Result<Vec<String>, TemplateError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, '#') if word.is_none() => break,
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(TemplateError::Quote { line });
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// The options of the template `text`, its variables resolved through
+/// `lookup`
+pub fn expand(
+    text: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<String>, TemplateError> {
+    let mut args = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = uncomment(line);
+        args.extend(split(&substitute(line, i + 1, lookup)?, i + 1)?);
+    }
+    Ok(args)
+}
+
+/// The value of an option in `args`, given as `--name VALUE` or
+/// `--name=VALUE`, at every occurrence
+fn values<'a>(args: &'a [String], option: &str) -> Result<Vec<&'a str>, TemplateError> {
+    let mut out = Vec::new();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == option {
+            let value = it
+                .next()
+                .ok_or_else(|| TemplateError::MissingValue(option.to_string()))?;
+            out.push(value.as_str());
+        } else if let Some(value) = arg.strip_prefix(option).and_then(|v| v.strip_prefix('=')) {
+            out.push(value);
+        }
+    }
+    Ok(out)
+}
+
+/// `argv` with the options of its `--campaign` template, if it names one,
+/// inserted after the program name; variables come from its `--var`s,
+/// then the environment, then `builtins`
+pub fn splice(
+    argv: Vec<String>,
+    builtins: &[(&str, String)],
+) -> Result<Vec<String>, TemplateError> {
+    let Some(path) = values(&argv, "--campaign")?.last().map(PathBuf::from) else {
+        return Ok(argv);
+    };
+    let mut vars = BTreeMap::new();
+    for var in values(&argv, "--var")? {
+        let (name, value) = var
+            .split_once('=')
+            .ok_or_else(|| TemplateError::Var(var.to_string()))?;
+        vars.insert(name.to_string(), value.to_string());
+    }
+    let lookup = |name: &str| {
+        vars.get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .or_else(|| {
+                builtins
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, v)| v.clone())
+            })
+    };
+    let args = expand(&read(&path)?, &lookup)?;
+    let mut out = argv;
+    let at = out.len().min(1);
+    out.splice(at..at, args);
+    Ok(out)
+}
+
+fn read(path: &Path) -> Result<String, TemplateError> {
+    std::fs::read_to_string(path).map_err(|source| TemplateError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn test_substitute_and_split() {
+        let vars = |name: &str| (name == "TARGET").then(|| "10.0.0.5".to_string());
+        assert_eq!(
+            substitute("-t ${TARGET} --export ${EXPORT:-/srv}", 1, &vars).unwrap(),
+            "-t 10.0.0.5 --export /srv"
+        );
+        assert!(matches!(
+            substitute("${EXPORT}", 3, &vars),
+            Err(TemplateError::Undefined { line: 3, name }) if name == "EXPORT"
+        ));
+        assert!(matches!(
+            substitute("${TARGET", 2, &vars),
+            Err(TemplateError::Unterminated { line: 2 })
+        ));
+
+        assert_eq!(
+            split(r#"--monitor "host=nfs1,command=dmesg -W" -v # trailing"#, 1).unwrap(),
+            ["--monitor", "host=nfs1,command=dmesg -W", "-v"]
+        );
+        assert_eq!(split("--tag ''", 1).unwrap(), ["--tag", ""]);
+        assert!(split("# only a comment", 1).unwrap().is_empty());
+        assert!(matches!(
+            split("'open", 4),
+            Err(TemplateError::Quote { line: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_comments_are_not_substituted() {
+        let vars = |name: &str| (name == "TARGET").then(|| "10.0.0.5".to_string());
+        assert_eq!(uncomment("-t x # was ${OLD}"), "-t x ");
+        assert_eq!(uncomment("--tag 'a # b' x#y"), "--tag 'a # b' x#y");
+        let template = "# --export ${UNSET}\n-t ${TARGET} # --output ${ALSO_UNSET}\n";
+        assert_eq!(
+            expand(template, &vars).unwrap(),
+            strings(&["-t", "10.0.0.5"])
+        );
+        assert!(matches!(
+            expand("-t ${UNSET}", &vars),
+            Err(TemplateError::Undefined { line: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_splice_puts_template_first() {
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-campaign-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lab.args");
+        std::fs::write(
+            &path,
+            "# lab\n--target ${LAB_TARGET}\n--output runs/${RUN_ID} --iterations 10\n",
+        )
+        .unwrap();
+        let file = path.to_string_lossy().to_string();
+        let argv = strings(&[
+            "nfs-fuzzer",
+            "--campaign",
+            &file,
+            "--var=LAB_TARGET=nfs3",
+            "--iterations",
+            "20",
+        ]);
+        let out = splice(argv.clone(), &[(RUN_ID, "abc".to_string())]).unwrap();
+        assert_eq!(
+            out[..7],
+            strings(&[
+                "nfs-fuzzer",
+                "--target",
+                "nfs3",
+                "--output",
+                "runs/abc",
+                "--iterations",
+                "10"
+            ])[..]
+        );
+        assert_eq!(out[7..], argv[1..]);
+
+        let plain = strings(&["nfs-fuzzer", "-t", "nfs1"]);
+        assert_eq!(splice(plain.clone(), &[]).unwrap(), plain);
+        assert!(matches!(
+            splice(strings(&["nfs-fuzzer", "--campaign", &file]), &[]),
+            Err(TemplateError::Undefined { line: 2, .. })
+        ));
+        assert!(matches!(
+            splice(
+                strings(&["nfs-fuzzer", "--campaign", &file, "--var", "X"]),
+                &[]
+            ),
+            Err(TemplateError::Var(_))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,452 @@
+//! Middlebox canary
+//!
+//! A NAT, firewall or application-level gateway between the fuzzer and
+//! the target sees every record too, and one that parses ONC RPC may
+//! drop, reset or rewrite what it does not like. Its refusals then look
+//! like server crashes and hangs. Before a campaign the canary sends
+//! NULL calls that a server must treat the same way: in one fragment,
+//! in many small fragments, one byte per segment, two pipelined in one
+//! write, and to a program nobody registers. Each goes on a connection of
+//! its own, and the replies are compared with the plain call's reply.
+//! A reset, a silence or a reply framed or worded differently from the
+//! plain one is [`Interference`], and it is tagged on the campaign.
+
+use crate::connection::Timeouts;
+use crate::rpc::{self, program, RpcCall};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+/// An unassigned program in the user-defined range
+pub const UNREGISTERED: u32 = 0x3fff_fff1;
+
+/// Fragment size of the fragmented probe
+pub const FRAGMENT: usize = 8;
+
+/// Prefix of the campaign tags
+pub const TAG: &str = "middlebox";
+
+/// One way of sending the NULL call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Probe {
+    /// One record of one fragment, in one write
+    Plain,
+    /// Fragments of [`FRAGMENT`] bytes
+    Fragmented,
+    /// One byte per write
+    Segmented,
+    /// Two calls in one write
+    Pipelined,
+    /// To [`UNREGISTERED`], which the server answers PROG_UNAVAIL
+    Unregistered,
+}
+
+impl Probe {
+    pub const ALL: [Probe; 5] = [
+        Probe::Plain,
+        Probe::Fragmented,
+        Probe::Segmented,
+        Probe::Pipelined,
+        Probe::Unregistered,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Probe::Plain => "plain",
+            Probe::Fragmented => "fragmented",
+            Probe::Segmented => "segmented",
+            Probe::Pipelined => "pipelined",
+            Probe::Unregistered => "unregistered",
+        }
+    }
+
+    /// How many replies the probe asks for
+    pub fn calls(self) -> usize {
+        match self {
+            Probe::Pipelined => 2,
+            _ => 1,
+        }
+    }
+
+    /// The writes of the probe with XIDs from `xid` on
+    pub fn writes(self, xid: u32, version: u32) -> Vec<Vec<u8>> {
+        let null = |xid| {
+            RpcCall::new(xid, program::NFS, version, 0, true)
+                .with_auth_none()
+                .build()
+                .to_vec()
+        };
+        match self {
+            Probe::Plain => vec![null(xid)],
+            Probe::Fragmented => {
+                let body = &null(xid)[4..];
+                let mut out = Vec::new();
+                let mut chunks = body.chunks(FRAGMENT).peekable();
+                while let Some(chunk) = chunks.next() {
+                    let last = if chunks.peek().is_none() {
+                        0x8000_0000
+                    } else {
+                        0
+                    };
+                    out.extend_from_slice(&(last | chunk.len() as u32).to_be_bytes());
+                    out.extend_from_slice(chunk);
+                }
+                vec![out]
+            }
+            Probe::Segmented => null(xid).into_iter().map(|b| vec![b]).collect(),
+            Probe::Pipelined => vec![[null(xid), null(xid.wrapping_add(1))].concat()],
+            Probe::Unregistered => vec![RpcCall::new(xid, UNREGISTERED, 1, 0, true)
+                .with_auth_none()
+                .build()
+                .to_vec()],
+        }
+    }
+}
+
+/// One reply record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// Length of each fragment
+    pub fragments: Vec<usize>,
+    /// The message, fragments joined
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn xid(&self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.body.get(..4)?.try_into().ok()?))
+    }
+}
+
+/// What came back for a probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every reply the probe asked for
+    Answered(Vec<Reply>),
+    /// The connection was reset
+    Reset,
+    /// The other end closed it, or refused it
+    Closed,
+    /// Nothing within the read timeout
+    Silent,
+}
+
+impl Outcome {
+    fn of(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe => Outcome::Reset,
+            _ => Outcome::Closed,
+        }
+    }
+}
+
+/// One reply record, fragment lengths kept
+pub async fn read_reply<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Reply> {
+    let mut reply = Reply {
+        fragments: Vec::new(),
+        body: Vec::new(),
+    };
+    loop {
+        let mark = stream.read_u32().await?;
+        let len = (mark & 0x7fff_ffff) as usize;
+        if reply.body.len() + len > crate::connection::MAX_RECORD {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let start = reply.body.len();
+        reply.body.resize(start + len, 0);
+        stream.read_exact(&mut reply.body[start..]).await?;
+        reply.fragments.push(len);
+        if mark & 0x8000_0000 != 0 {
+            return Ok(reply);
+        }
+    }
+}
+
+/// Send `probe` on `stream` and read its replies
+pub async fn run_probe<S>(
+    stream: &mut S,
+    probe: Probe,
+    xid: u32,
+    version: u32,
+    wait: Duration,
+) -> Outcome
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let exchange = async {
+        for write in probe.writes(xid, version) {
+            stream.write_all(&write).await?;
+            stream.flush().await?;
+        }
+        let mut replies = Vec::new();
+        for _ in 0..probe.calls() {
+            replies.push(read_reply(stream).await?);
+        }
+        Ok::<_, io::Error>(replies)
+    };
+    match timeout(wait, exchange).await {
+        Ok(Ok(replies)) => Outcome::Answered(replies),
+        Ok(Err(e)) => Outcome::of(&e),
+        Err(_) => Outcome::Silent,
+    }
+}
+
+/// Something on the path treating a probe differently from the plain call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Interference {
+    /// Its connection was reset
+    Reset(Probe),
+    /// Its connection was closed, or nothing came back
+    Dropped(Probe),
+    /// Replies to other XIDs than the probe's
+    Mismatched(Probe),
+    /// Replies framed or worded differently from the plain call's
+    Rewritten(Probe),
+}
+
+impl fmt::Display for Interference {
+    /// The campaign tag, e.g. `middlebox:reset:fragmented`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, probe) = match self {
+            Interference::Reset(p) => ("reset", p),
+            Interference::Dropped(p) => ("dropped", p),
+            Interference::Mismatched(p) => ("mismatched", p),
+            Interference::Rewritten(p) => ("rewritten", p),
+        };
+        write!(f, "{}:{}:{}", TAG, kind, probe.name())
+    }
+}
+
+/// All the probes' outcomes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fingerprint {
+    /// Each probe with the first XID it sent and what came back
+    pub outcomes: Vec<(Probe, u32, Outcome)>,
+}
+
+impl Fingerprint {
+    /// The reply to the plain call, if it was answered as sent
+    pub fn baseline(&self) -> Option<&Reply> {
+        self.outcomes
+            .iter()
+            .find_map(|(probe, xid, outcome)| match outcome {
+                Outcome::Answered(replies) if *probe == Probe::Plain => {
+                    replies.first().filter(|r| r.xid() == Some(*xid))
+                }
+                _ => None,
+            })
+    }
+
+    /// How the other probes fared against the plain call; nothing if the
+    /// plain call itself went unanswered, since then there is nothing to
+    /// tell the path from the server by
+    pub fn interference(&self) -> Vec<Interference> {
+        let Some(base) = self.baseline() else {
+            return Vec::new();
+        };
+        let mut found = Vec::new();
+        for (probe, xid, outcome) in &self.outcomes {
+            let probe = *probe;
+            let replies = match outcome {
+                Outcome::Answered(replies) => replies,
+                Outcome::Reset => {
+                    found.push(Interference::Reset(probe));
+                    continue;
+                }
+                Outcome::Closed | Outcome::Silent => {
+                    found.push(Interference::Dropped(probe));
+                    continue;
+                }
+            };
+            let mut want: Vec<u32> = (0..probe.calls() as u32)
+                .map(|i| xid.wrapping_add(i))
+                .collect();
+            let mut got: Vec<u32> = replies.iter().filter_map(Reply::xid).collect();
+            want.sort_unstable();
+            got.sort_unstable();
+            if want != got {
+                found.push(Interference::Mismatched(probe));
+            } else if probe != Probe::Unregistered
+                && replies
+                    .iter()
+                    .any(|r| r.fragments != base.fragments || r.body[4..] != base.body[4..])
+            {
+                found.push(Interference::Rewritten(probe));
+            }
+        }
+        found
+    }
+}
+
+/// Run every probe on a connection of its own from `connect`
+pub async fn fingerprint_with<S, F, Fut>(
+    mut connect: F,
+    version: u32,
+    wait: Duration,
+) -> Fingerprint
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<S>>,
+{
+    let mut print = Fingerprint::default();
+    for probe in Probe::ALL {
+        let xid = rpc::next_xid();
+        // Pipelined takes the next XID too
+        rpc::next_xid();
+        let outcome = match timeout(wait, connect()).await {
+            Ok(Ok(mut stream)) => run_probe(&mut stream, probe, xid, version, wait).await,
+            Ok(Err(e)) => Outcome::of(&e),
+            Err(_) => Outcome::Silent,
+        };
+        print.outcomes.push((probe, xid, outcome));
+    }
+    print
+}
+
+/// Run every probe against `target` over TCP
+pub async fn fingerprint(target: SocketAddr, version: u32, timeouts: Timeouts) -> Fingerprint {
+    let connect = || async {
+        let stream = timeout(timeouts.connect, TcpStream::connect(target))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    };
+    fingerprint_with(connect, version, timeouts.read).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hang::read_record;
+    use tokio::io::DuplexStream;
+
+    fn reply(xid: u32, fragments: &[usize]) -> Reply {
+        let mut body = xid.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0; 20]);
+        Reply {
+            fragments: fragments.to_vec(),
+            body,
+        }
+    }
+
+    #[test]
+    fn test_interference() {
+        let fp = |rest: Vec<(Probe, u32, Outcome)>| {
+            let mut outcomes = vec![(Probe::Plain, 10, Outcome::Answered(vec![reply(10, &[24])]))];
+            outcomes.extend(rest);
+            Fingerprint { outcomes }
+        };
+        let clean = fp(vec![
+            (
+                Probe::Pipelined,
+                20,
+                Outcome::Answered(vec![reply(21, &[24]), reply(20, &[24])]),
+            ),
+            (
+                Probe::Unregistered,
+                30,
+                Outcome::Answered(vec![reply(30, &[12])]),
+            ),
+        ]);
+        assert!(clean.interference().is_empty());
+
+        let dirty = fp(vec![
+            (Probe::Fragmented, 20, Outcome::Reset),
+            (
+                Probe::Segmented,
+                30,
+                Outcome::Answered(vec![reply(30, &[8, 16])]),
+            ),
+            (
+                Probe::Pipelined,
+                40,
+                Outcome::Answered(vec![reply(40, &[24]), reply(40, &[24])]),
+            ),
+            (Probe::Unregistered, 50, Outcome::Silent),
+        ]);
+        assert_eq!(
+            dirty.interference(),
+            [
+                Interference::Reset(Probe::Fragmented),
+                Interference::Rewritten(Probe::Segmented),
+                Interference::Mismatched(Probe::Pipelined),
+                Interference::Dropped(Probe::Unregistered),
+            ]
+        );
+        assert_eq!(
+            dirty.interference()[0].to_string(),
+            "middlebox:reset:fragmented"
+        );
+
+        // Nothing to compare with
+        let down = Fingerprint {
+            outcomes: vec![
+                (Probe::Plain, 1, Outcome::Closed),
+                (Probe::Segmented, 2, Outcome::Reset),
+            ],
+        };
+        assert!(down.interference().is_empty());
+    }
+
+    /// Answers every call like a server, except that it hangs up on
+    /// records of more than one fragment, as an RPC gateway might
+    async fn gateway(mut stream: DuplexStream) {
+        loop {
+            let Ok(mark) = stream.read_u32().await else {
+                return;
+            };
+            if mark & 0x8000_0000 == 0 {
+                return;
+            }
+            let mut call = vec![0; (mark & 0x7fff_ffff) as usize];
+            if stream.read_exact(&mut call).await.is_err() {
+                return;
+            }
+            let mut record = 0x8000_0018u32.to_be_bytes().to_vec();
+            record.extend_from_slice(&call[..4]);
+            record.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+            let stat: u32 = if call[12..16] == UNREGISTERED.to_be_bytes() {
+                1
+            } else {
+                0
+            };
+            record.extend_from_slice(&[0, 0, 0, 0]);
+            record.extend_from_slice(&stat.to_be_bytes());
+            if stream.write_all(&record).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint_against_a_gateway() {
+        let connect = || async {
+            let (client, server) = tokio::io::duplex(4096);
+            tokio::spawn(gateway(server));
+            Ok(client)
+        };
+        let fp = fingerprint_with(connect, 3, Duration::from_secs(5)).await;
+        assert_eq!(fp.outcomes.len(), Probe::ALL.len());
+        assert_eq!(
+            fp.interference(),
+            [Interference::Dropped(Probe::Fragmented)]
+        );
+
+        // The fragmented probe is still one well-formed call
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        for write in Probe::Fragmented.writes(7, 3) {
+            client.write_all(&write).await.unwrap();
+        }
+        let call = read_record(&mut server).await.unwrap();
+        assert_eq!(call[..], Probe::Plain.writes(7, 3)[0][4..]);
+    }
+}
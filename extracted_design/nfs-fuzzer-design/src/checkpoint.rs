@@ -0,0 +1,209 @@
+//! Campaign checkpoints
+//!
+//! A campaign meant to run for days outlives the fuzzer process: the host
+//! reboots, the job is preempted, the fuzzer is upgraded. Every so many
+//! cases, and when the campaign ends, the fuzzer writes a [`Checkpoint`]
+//! to `checkpoint.json` in its output directory: the seed cases are
+//! generated from, how many were sent, the coverage map and the findings
+//! so far. The statistics and the cases that found new coverage are kept
+//! in the output directory anyway, in `stats.json` and `queue/`. A run
+//! started with `--resume` takes all of it back up: the same seed, the
+//! cases already sent skipped, the queue back in the pool and the
+//! findings' cases read back from where they were saved. Without
+//! coverage feedback the resumed run sends exactly the cases the first
+//! would have gone on to; with it, the variants drawn from the queue may
+//! differ, since the queue was not yet full when they were first drawn.
+
+use crate::corpus::Corpus;
+use crate::fuzz::{Finding, FindingKind};
+use crate::generate::{self, Input};
+use crate::kcov::CoverageMap;
+use crate::lineage::Lineage;
+use crate::replay;
+use crate::repro::Reproduction;
+use crate::signature::Signature;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Version of the checkpoint format
+pub const VERSION: u32 = 1;
+
+/// Cases between checkpoints, by default
+pub const DEFAULT_EVERY: u64 = 1000;
+
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("checkpoint I/O: {0}")]
+    Io(#[from] io::Error),
+    #[error("bad checkpoint: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("checkpoint version {0}, expected {VERSION}")]
+    Version(u32),
+}
+
+/// A finding as a checkpoint keeps it: its cases are read back from the
+/// output directory
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedFinding {
+    pub kind: FindingKind,
+    pub name: String,
+    pub path: PathBuf,
+    pub reproduction: Option<Reproduction>,
+    pub minimized: Option<PathBuf>,
+    pub signature: Option<Signature>,
+}
+
+impl SavedFinding {
+    pub fn of(finding: &Finding) -> Self {
+        Self {
+            kind: finding.kind,
+            name: finding.name.clone(),
+            path: finding.path.clone(),
+            reproduction: finding.reproduction.clone(),
+            minimized: finding.minimized.clone(),
+            signature: finding.signature.clone(),
+        }
+    }
+
+    /// The finding with its cases as saved, those no longer readable
+    /// left out
+    pub fn restore(self) -> Finding {
+        Finding {
+            inputs: saved_inputs(&self.path),
+            kind: self.kind,
+            name: self.name,
+            path: self.path,
+            reproduction: self.reproduction,
+            minimized: self.minimized,
+            signature: self.signature,
+        }
+    }
+}
+
+/// The cases saved at `path`, a finding or a corpus directory such as
+/// the queue, named and traced by their notes where they have them
+pub fn saved_inputs(path: &Path) -> Vec<Input> {
+    let Ok(calls) = replay::calls(path) else {
+        return Vec::new();
+    };
+    calls
+        .into_iter()
+        .filter_map(|(stem, call)| {
+            let meta = path
+                .is_dir()
+                .then(|| Corpus::open(path).load(&stem).ok())
+                .flatten();
+            let (name, lineage) = match meta {
+                Some(meta) => (meta.name, meta.lineage),
+                None => (stem.clone(), Lineage::new(&stem)),
+            };
+            generate::call_input(name, lineage, &call)
+        })
+        .collect()
+}
+
+/// Where a campaign stood
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    /// Seed the campaign's cases are generated from
+    pub seed: u64,
+    /// Cases sent
+    pub sent: u64,
+    /// Edges covered, with coverage feedback
+    pub coverage: Option<CoverageMap>,
+    pub findings: Vec<SavedFinding>,
+}
+
+impl Checkpoint {
+    pub const FILE: &'static str = "checkpoint.json";
+
+    /// Write to `<dir>/checkpoint.json`, replacing the last one only
+    /// once this one is complete
+    pub fn save(&self, dir: &Path) -> Result<(), CheckpointError> {
+        std::fs::create_dir_all(dir)?;
+        let partial = dir.join(format!("{}.partial", Self::FILE));
+        std::fs::write(&partial, serde_json::to_vec(self)?)?;
+        std::fs::rename(partial, dir.join(Self::FILE))?;
+        Ok(())
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, CheckpointError> {
+        let checkpoint: Self = serde_json::from_slice(&std::fs::read(dir.join(Self::FILE))?)?;
+        if checkpoint.version != VERSION {
+            return Err(CheckpointError::Version(checkpoint.version));
+        }
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+    use crate::rpc::program;
+
+    #[test]
+    fn test_round_trip_restores_finding_cases() {
+        let dir =
+            std::env::temp_dir().join(format!("nfs-fuzzer-checkpoint-{}", std::process::id()));
+        let crash = dir.join("crashes").join("00000007");
+        let input = Input {
+            name: "v3:GETATTR".to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure: 1,
+            args: vec![0, 0, 0, 4, 1, 2, 3, 4],
+            lineage: Lineage::new("v3:GETATTR"),
+            auth: None,
+        };
+        Corpus::open(&crash)
+            .write(
+                "000_v3:GETATTR",
+                &input,
+                &input.message(&Identity::new(0, 0)),
+            )
+            .unwrap();
+        let finding = Finding {
+            kind: FindingKind::Crash,
+            name: input.name.clone(),
+            path: crash.clone(),
+            inputs: vec![input.clone()],
+            reproduction: None,
+            minimized: None,
+            signature: None,
+        };
+        let mut coverage = CoverageMap::default();
+        coverage.add(&[0x1000, 0x1004, 0x1008]);
+        let checkpoint = Checkpoint {
+            version: VERSION,
+            seed: 42,
+            sent: 1234,
+            coverage: Some(coverage),
+            findings: vec![SavedFinding::of(&finding)],
+        };
+        checkpoint.save(&dir).unwrap();
+        let loaded = Checkpoint::load(&dir).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.coverage.as_ref().map(CoverageMap::edges), Some(3));
+        assert_eq!(loaded.findings[0].clone().restore(), finding);
+
+        std::fs::write(dir.join(Checkpoint::FILE), r#"{"version":9}"#).unwrap();
+        assert!(matches!(
+            Checkpoint::load(&dir),
+            Err(CheckpointError::Json(_))
+        ));
+        let future = Checkpoint {
+            version: VERSION + 1,
+            ..checkpoint
+        };
+        future.save(&dir).unwrap();
+        assert!(matches!(
+            Checkpoint::load(&dir),
+            Err(CheckpointError::Version(2))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
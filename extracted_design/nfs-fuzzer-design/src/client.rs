@@ -0,0 +1,437 @@
+//! Driving the client under test
+//!
+//! `serve` damages what a client reads; something still has to make the
+//! client read. A [`ClientDriver`] runs the [`WORKLOAD`] script on the
+//! client host, locally or over `ssh`: mount the export through the
+//! malicious server, list it, read from it, write and remove a file, and
+//! unmount. After each round it reads the kernel log for the marks a
+//! kernel leaves when it trips over a reply (oopses, BUGs, KASAN and
+//! UBSAN reports, warnings with a call trace), and a client that stops
+//! answering `ssh` altogether counts as crashed too.
+//!
+//! Every step runs under `timeout`, so a mount stuck waiting on a reply
+//! shows up as a hang instead of stalling the loop, and the unmount is
+//! forced and lazy so the next round starts from a clean mount point.
+//! NFSv3 mounts send their MOUNT calls straight to the upstream server,
+//! which `serve` does not relay.
+
+use crate::controller::{Ssh, SSH_DISCONNECTED};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use tracing::{error, info};
+
+/// Steps of one round, by name; `{mount}`, `{options}`, `{server}` and
+/// `{export}` are filled in from the [`ClientSpec`]
+pub const WORKLOAD: &[(&str, &str)] = &[
+    (
+        "mount",
+        "mkdir -p {mount} && mount -t nfs -o {options} {server}:{export} {mount}",
+    ),
+    ("ls", "ls -laR {mount} | head -n 1000"),
+    (
+        "read",
+        "find {mount} -maxdepth 2 -type f | head -n 32 | while read -r f; do head -c 65536 \"$f\" >/dev/null; done",
+    ),
+    (
+        "write",
+        "dd if=/dev/urandom of={mount}/.nfsfuzz bs=4k count=16 conv=fsync",
+    ),
+    ("remove", "rm -f {mount}/.nfsfuzz"),
+    ("umount", "umount -f -l {mount}"),
+];
+
+/// Kernel log lines that mean the client tripped over something
+pub const CRASH_MARKS: &[&str] = &[
+    "BUG:",
+    "kernel BUG",
+    "Oops",
+    "general protection fault",
+    "KASAN:",
+    "UBSAN:",
+    "WARNING:",
+    "Call Trace:",
+    "Kernel panic",
+];
+
+/// Exit status of `timeout` when the command ran out of time
+const TIMED_OUT: i32 = 124;
+
+/// Where the client's commands run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientHost {
+    Local,
+    Ssh(Box<Ssh>),
+}
+
+/// The client under test and how it mounts the malicious server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientSpec {
+    pub host: ClientHost,
+    /// Address the client reaches the malicious server at
+    pub server: String,
+    pub export: String,
+    pub mount: PathBuf,
+    /// NFS version to mount with, as `mount -o vers=` takes it
+    pub vers: String,
+    /// Seconds each step may run
+    pub step_timeout: u64,
+}
+
+impl ClientSpec {
+    pub fn new(host: ClientHost) -> Self {
+        Self {
+            host,
+            server: "127.0.0.1".to_string(),
+            export: "/".to_string(),
+            mount: PathBuf::from("/mnt/nfsfuzz"),
+            vers: "4.1".to_string(),
+            step_timeout: 60,
+        }
+    }
+
+    /// Mount options for a server on `port` relaying to `upstream`
+    pub fn options(&self, port: u16, upstream: SocketAddr) -> String {
+        let mut options = format!(
+            "vers={},proto=tcp,port={},soft,timeo=50,retrans=1",
+            self.vers, port
+        );
+        if self.vers == "3" {
+            options.push_str(&format!(",mountaddr={},mountproto=tcp", upstream.ip()));
+        }
+        options
+    }
+}
+
+/// `local[,OPTS]` or `ssh,host=H[,port=N][,key=PATH][,OPTS]`, where OPTS
+/// are `server=ADDR`, `export=PATH`, `mount=PATH`, `vers=V` and
+/// `timeout=SECS`
+impl FromStr for ClientSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').filter(|p| !p.is_empty());
+        let kind = parts.next().unwrap_or("local");
+        let mut options = Vec::new();
+        for part in parts {
+            options.push(
+                part.split_once('=')
+                    .ok_or_else(|| format!("expected key=value, got {:?}", part))?,
+            );
+        }
+        let mut ssh = match kind {
+            "local" => None,
+            "ssh" => {
+                let host = options
+                    .iter()
+                    .find(|(k, _)| *k == "host")
+                    .ok_or("ssh client needs host=")?;
+                Some(Box::new(Ssh::new(host.1)))
+            }
+            _ => return Err(format!("unknown client {:?}", kind)),
+        };
+        let mut spec = Self::new(ClientHost::Local);
+        for &(key, value) in &options {
+            match (key, ssh.as_mut()) {
+                ("server", _) => spec.server = value.to_string(),
+                ("export", _) => spec.export = value.to_string(),
+                ("mount", _) => spec.mount = PathBuf::from(value),
+                ("vers", _) => spec.vers = value.to_string(),
+                ("timeout", _) => {
+                    spec.step_timeout = value
+                        .parse()
+                        .map_err(|_| format!("bad step timeout {:?}", value))?
+                }
+                ("host", Some(_)) => {}
+                ("port", Some(ssh)) => {
+                    ssh.port = Some(value.parse().map_err(|_| format!("bad port {:?}", value))?)
+                }
+                ("key", Some(ssh)) => ssh.key = Some(PathBuf::from(value)),
+                _ => return Err(format!("unknown {} client option {:?}", kind, key)),
+            }
+        }
+        if let Some(ssh) = ssh {
+            spec.host = ClientHost::Ssh(ssh);
+        }
+        Ok(spec)
+    }
+}
+
+/// How one step of a round went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    Ok,
+    /// Exit status and standard error
+    Failed(i32, String),
+    /// Ran out of its timeout
+    Hung,
+    /// The command could not be run, or ssh lost the host
+    Unreachable(String),
+}
+
+/// One round of the workload
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Round {
+    pub steps: Vec<(&'static str, StepStatus)>,
+    /// New kernel log lines carrying a [`CRASH_MARKS`] entry
+    pub marks: Vec<String>,
+}
+
+impl Round {
+    /// The kernel complained or the host went away
+    pub fn crashed(&self) -> bool {
+        !self.marks.is_empty()
+            || self
+                .steps
+                .iter()
+                .any(|(_, s)| matches!(s, StepStatus::Unreachable(_)))
+    }
+}
+
+impl fmt::Display for Round {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, status) in &self.steps {
+            match status {
+                StepStatus::Ok => writeln!(f, "{}: ok", name)?,
+                StepStatus::Failed(code, stderr) => {
+                    writeln!(f, "{}: exit {}: {}", name, code, stderr)?
+                }
+                StepStatus::Hung => writeln!(f, "{}: timed out", name)?,
+                StepStatus::Unreachable(why) => writeln!(f, "{}: unreachable: {}", name, why)?,
+            }
+        }
+        for line in &self.marks {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// `s` as one single-quoted shell word
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Lines of `log` after the last occurrence of `last`, or all of it when
+/// there was none or it is gone, as after a reboot or once the ring
+/// buffer wrapped
+pub fn since<'a>(log: &'a [String], last: Option<&str>) -> &'a [String] {
+    match last.and_then(|l| log.iter().rposition(|x| x == l)) {
+        Some(at) => &log[at + 1..],
+        None => log,
+    }
+}
+
+/// The lines of `log` carrying a [`CRASH_MARKS`] entry
+pub fn crash_lines(log: &[String]) -> Vec<String> {
+    log.iter()
+        .filter(|l| CRASH_MARKS.iter().any(|m| l.contains(m)))
+        .cloned()
+        .collect()
+}
+
+/// Runs the workload on the client and watches its kernel log
+#[derive(Debug)]
+pub struct ClientDriver {
+    spec: ClientSpec,
+    options: String,
+    /// Last kernel log line seen, once read
+    last: Option<String>,
+    started: bool,
+}
+
+impl ClientDriver {
+    /// A driver mounting from `serve` on `port`, relaying to `upstream`
+    pub fn new(spec: ClientSpec, port: u16, upstream: SocketAddr) -> Self {
+        let options = spec.options(port, upstream);
+        Self {
+            spec,
+            options,
+            last: None,
+            started: false,
+        }
+    }
+
+    /// `step` with the placeholders filled in, under `timeout`
+    pub fn script(&self, step: &str) -> String {
+        let script = step
+            .replace("{mount}", &quote(&self.spec.mount.to_string_lossy()))
+            .replace("{options}", &quote(&self.options))
+            .replace("{server}", &self.spec.server)
+            .replace("{export}", &quote(&self.spec.export));
+        format!(
+            "timeout {} sh -c {}",
+            self.spec.step_timeout,
+            quote(&script)
+        )
+    }
+
+    fn command(&self, script: &str) -> Command {
+        match &self.spec.host {
+            ClientHost::Local => {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c").arg(script);
+                cmd
+            }
+            ClientHost::Ssh(ssh) => ssh.command(script),
+        }
+    }
+
+    fn run(&self, script: &str) -> (StepStatus, Vec<u8>) {
+        let out = match self.command(script).output() {
+            Ok(out) => out,
+            Err(e) => return (StepStatus::Unreachable(e.to_string()), Vec::new()),
+        };
+        let stderr = || String::from_utf8_lossy(&out.stderr).trim().to_string();
+        let status = match out.status.code() {
+            Some(0) => StepStatus::Ok,
+            Some(TIMED_OUT) => StepStatus::Hung,
+            Some(SSH_DISCONNECTED) if matches!(self.spec.host, ClientHost::Ssh(_)) => {
+                StepStatus::Unreachable(stderr())
+            }
+            Some(code) => StepStatus::Failed(code, stderr()),
+            None => StepStatus::Failed(-1, stderr()),
+        };
+        (status, out.stdout)
+    }
+
+    /// New kernel log lines since the last read; the first read only
+    /// marks where the log stood
+    fn kernel_log(&mut self) -> Result<Vec<String>, StepStatus> {
+        match self.run("dmesg") {
+            (StepStatus::Ok, out) => {
+                let log: Vec<String> = String::from_utf8_lossy(&out)
+                    .lines()
+                    .map(str::to_string)
+                    .collect();
+                let new = match self.started {
+                    true => since(&log, self.last.as_deref()).to_vec(),
+                    false => Vec::new(),
+                };
+                self.started = true;
+                if let Some(l) = log.last() {
+                    self.last = Some(l.clone());
+                }
+                Ok(new)
+            }
+            (status, _) => Err(status),
+        }
+    }
+
+    /// Run the workload once; blocks for as long as the steps take
+    pub fn round(&mut self) -> Round {
+        let mut round = Round::default();
+        if !self.started {
+            if let Err(status) = self.kernel_log() {
+                round.steps.push(("dmesg", status));
+                return round;
+            }
+        }
+        for &(name, step) in WORKLOAD {
+            let (status, _) = self.run(&self.script(step));
+            let lost = matches!(status, StepStatus::Unreachable(_));
+            round.steps.push((name, status));
+            if lost {
+                return round;
+            }
+        }
+        match self.kernel_log() {
+            Ok(log) => round.marks = crash_lines(&log),
+            Err(status) => round.steps.push(("dmesg", status)),
+        }
+        round
+    }
+}
+
+/// Run up to `rounds` rounds, stopping at the first crash; its report
+/// goes to `<report_dir>/client_crash_<round>.txt` when a directory is
+/// given. Returns the round that crashed.
+pub fn drive(
+    driver: &mut ClientDriver,
+    rounds: u64,
+    report_dir: Option<&Path>,
+) -> io::Result<Option<u64>> {
+    for n in 0..rounds {
+        let round = driver.round();
+        if !round.crashed() {
+            info!("Client round {}:\n{}", n, round);
+            continue;
+        }
+        error!("Client crashed in round {}:\n{}", n, round);
+        if let Some(dir) = report_dir {
+            std::fs::create_dir_all(dir)?;
+            std::fs::write(
+                dir.join(format!("client_crash_{:06}.txt", n)),
+                round.to_string(),
+            )?;
+        }
+        return Ok(Some(n));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_and_script() {
+        let spec: ClientSpec = "ssh,host=root@client,port=2222,server=10.0.0.2,vers=3"
+            .parse()
+            .unwrap();
+        let ClientHost::Ssh(ssh) = &spec.host else {
+            panic!("not ssh")
+        };
+        assert_eq!((ssh.host.as_str(), ssh.port), ("root@client", Some(2222)));
+        assert_eq!(
+            (spec.server.as_str(), spec.vers.as_str()),
+            ("10.0.0.2", "3")
+        );
+        let upstream: SocketAddr = "10.0.0.5:2049".parse().unwrap();
+        assert_eq!(
+            spec.options(20490, upstream),
+            "vers=3,proto=tcp,port=20490,soft,timeo=50,retrans=1,mountaddr=10.0.0.5,mountproto=tcp"
+        );
+        assert_eq!("local".parse(), Ok(ClientSpec::new(ClientHost::Local)));
+        assert!("local,port=22".parse::<ClientSpec>().is_err());
+        assert!("ssh,vers=3".parse::<ClientSpec>().is_err());
+        assert!("docker".parse::<ClientSpec>().is_err());
+
+        let spec = ClientSpec {
+            mount: PathBuf::from("/mnt/it's"),
+            ..ClientSpec::new(ClientHost::Local)
+        };
+        let driver = ClientDriver::new(spec, 2049, upstream);
+        assert_eq!(
+            driver.script("rm -f {mount}/x"),
+            r#"timeout 60 sh -c 'rm -f '\''/mnt/it'\''\'\'''\''s'\''/x'"#
+        );
+    }
+
+    #[test]
+    fn test_new_crash_lines() {
+        let log: Vec<String> = [
+            "[1.0] nfs: server up",
+            "[2.0] BUG: unable to handle page fault",
+            "[3.0] Call Trace:",
+            "[4.0] eth0: link up",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        assert_eq!(since(&log, None), &log[..]);
+        assert_eq!(since(&log, Some("[1.0] nfs: server up")), &log[1..]);
+        assert_eq!(since(&log, Some("[4.0] eth0: link up")), &[] as &[String]);
+        assert_eq!(since(&log, Some("gone")), &log[..]);
+        assert_eq!(crash_lines(&log), &log[1..3]);
+        let round = Round {
+            steps: vec![("mount", StepStatus::Hung)],
+            marks: Vec::new(),
+        };
+        assert!(!round.crashed());
+        assert_eq!(round.to_string(), "mount: timed out\n");
+    }
+}
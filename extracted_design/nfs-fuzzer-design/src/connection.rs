@@ -0,0 +1,883 @@
+//! Record-marked RPC connections (RFC 5531 §11)
+//!
+//! Over TCP each message is a record of one or more fragments, each
+//! behind a four-byte mark whose top bit flags the last fragment. A
+//! connection writes whole call messages as built by `RpcCall`, joins
+//! reply fragments back into records, and hands each caller the reply
+//! with its XID. Replies that arrive for other outstanding calls are
+//! kept until asked for, so calls can be pipelined, though only the
+//! [`MAX_PARKED`] latest and only as far as the memory budget they are
+//! charged to allows. A caller that only needs the start of a reply, to
+//! classify it, can have the rest dropped as it arrives instead of
+//! buffered.
+//!
+//! Over UDP each message is one datagram with no mark. Nothing tells the
+//! client a datagram was lost, so a call is sent again, with the same
+//! XID, whenever its reply is overdue, each wait longer than the last.
+
+use crate::hang::LatencyBudget;
+use crate::limits::{BoundedStore, MemoryBudget};
+use crate::netfault::{FaultConfig, FaultStats, FaultySocket};
+use crate::rpc::{self, pmap, rpcb, RpcError, RpcReply};
+use crate::xdr::XdrStream;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+
+/// Largest reply record accepted: a mark claiming more is a finding,
+/// not an allocation
+pub const MAX_RECORD: usize = 64 << 20;
+
+/// Largest UDP payload over IPv4
+pub const MAX_DATAGRAM: usize = 65507;
+
+/// Most bytes read off the stream at once while streaming a record
+const CHUNK: usize = 64 << 10;
+
+/// Most replies kept for XIDs not yet asked for. Late replies to calls
+/// that timed out, UDP duplicates and replies to XIDs never sent are
+/// never asked for, so past this the oldest are dropped.
+pub const MAX_PARKED: usize = 64;
+
+/// Replies read while waiting for a different XID, oldest first
+#[derive(Debug)]
+struct Parked(BoundedStore<(u32, Vec<u8>)>);
+
+impl Parked {
+    fn new(budget: MemoryBudget) -> Self {
+        Self(BoundedStore::new(budget))
+    }
+
+    /// Keep `reply` to `xid` in place of any kept before, making room
+    fn park(&mut self, xid: u32, reply: Vec<u8>) {
+        self.take(xid);
+        if self.0.len() >= MAX_PARKED {
+            self.0.pop_front();
+            self.0.dropped += 1;
+        }
+        let bytes = reply.len();
+        let _ = self.0.push((xid, reply), bytes);
+    }
+
+    fn take(&mut self, xid: u32) -> Option<Vec<u8>> {
+        self.0.remove_first(|(x, _)| *x == xid).map(|(_, reply)| reply)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl Default for Parked {
+    fn default() -> Self {
+        Self::new(MemoryBudget::new(usize::MAX))
+    }
+}
+
+/// The start of a reply record and the length of all of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplyHead {
+    /// Up to the first `keep` bytes of the reply, XID first
+    pub head: Vec<u8>,
+    pub len: usize,
+}
+
+impl ReplyHead {
+    /// Whether `head` holds the whole reply
+    pub fn is_complete(&self) -> bool {
+        self.head.len() == self.len
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConnectionError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{op} timed out after {after:?}")]
+    Timeout { op: &'static str, after: Duration },
+    #[error("server closed the connection")]
+    Closed,
+    #[error("reply record of {len} bytes exceeds {max}")]
+    RecordTooLarge { len: usize, max: usize },
+    #[error("call is too short to carry an XID")]
+    NoXid,
+    #[error("call of {len} bytes does not fit in a {max}-byte datagram")]
+    DatagramTooLarge { len: usize, max: usize },
+    #[error("malformed record mark in call")]
+    BadRecord,
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+}
+
+/// Transport protocol
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Proto {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl Proto {
+    /// The protocol number portmap registers it under
+    pub fn ipproto(self) -> u32 {
+        match self {
+            Proto::Tcp => pmap::IPPROTO_TCP,
+            Proto::Udp => pmap::IPPROTO_UDP,
+        }
+    }
+}
+
+/// Timeouts for one connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    /// Waiting for each reply
+    pub read: Duration,
+    /// Writing each call
+    pub write: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self::from(&LatencyBudget::default())
+    }
+}
+
+impl From<&LatencyBudget> for Timeouts {
+    fn from(budget: &LatencyBudget) -> Self {
+        Self {
+            connect: budget.connect,
+            read: budget.request,
+            write: budget.request,
+        }
+    }
+}
+
+/// An RPC connection over a record-marked stream
+#[derive(Debug)]
+pub struct NfsConnection<S = TcpStream> {
+    stream: S,
+    pub timeouts: Timeouts,
+    pub max_record: usize,
+    /// Replies read while waiting for a different XID
+    pending: Parked,
+}
+
+impl NfsConnection {
+    /// Connect to `addr` within the connect timeout
+    pub async fn connect(addr: SocketAddr, timeouts: Timeouts) -> Result<Self, ConnectionError> {
+        let stream = timeout(timeouts.connect, TcpStream::connect(addr))
+            .await
+            .map_err(|_| ConnectionError::Timeout {
+                op: "connect",
+                after: timeouts.connect,
+            })??;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream, timeouts))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NfsConnection<S> {
+    pub fn new(stream: S, timeouts: Timeouts) -> Self {
+        Self {
+            stream,
+            timeouts,
+            max_record: MAX_RECORD,
+            pending: Parked::default(),
+        }
+    }
+
+    /// Charge replies kept for later calls to `budget`
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.pending = Parked::new(budget);
+        self
+    }
+
+    /// The underlying stream, for sending raw bytes that are not one
+    /// well-formed call
+    pub fn stream(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Replies waiting to be collected
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Replies dropped unasked for to stay within [`MAX_PARKED`] or the
+    /// memory budget
+    pub fn dropped(&self) -> u64 {
+        self.pending.0.dropped
+    }
+
+    /// Write `msg`, a record-marked call, and return its XID
+    pub async fn send(&mut self, msg: &[u8]) -> Result<u32, ConnectionError> {
+        let xid = msg
+            .get(4..8)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+            .ok_or(ConnectionError::NoXid)?;
+        let after = self.timeouts.write;
+        timeout(after, self.stream.write_all(msg))
+            .await
+            .map_err(|_| ConnectionError::Timeout { op: "write", after })??;
+        Ok(xid)
+    }
+
+    /// Read one complete record, joining its fragments
+    async fn read_record(&mut self) -> Result<Vec<u8>, ConnectionError> {
+        let mut record = Vec::new();
+        loop {
+            let mark = match self.stream.read_u32().await {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(ConnectionError::Closed)
+                }
+                r => r?,
+            };
+            let start = record.len();
+            let len = start + (mark & 0x7fff_ffff) as usize;
+            if len > self.max_record {
+                return Err(ConnectionError::RecordTooLarge {
+                    len,
+                    max: self.max_record,
+                });
+            }
+            record.resize(len, 0);
+            self.stream.read_exact(&mut record[start..]).await?;
+            if mark & 0x8000_0000 != 0 {
+                return Ok(record);
+            }
+        }
+    }
+
+    /// Read no more than `s` is short, so nothing past the current record
+    /// is taken off the stream, and feed it to `s`
+    async fn fill(&mut self, s: &mut XdrStream) -> Result<(), ConnectionError> {
+        let want = match s.skipping() {
+            0 => s.needed(),
+            n => n,
+        };
+        let mut chunk = vec![0; want.clamp(1, CHUNK)];
+        match self.stream.read(&mut chunk).await? {
+            0 => Err(ConnectionError::Closed),
+            n => {
+                s.feed(&chunk[..n]);
+                Ok(())
+            }
+        }
+    }
+
+    /// Read one record, keeping its first `keep` bytes and dropping the
+    /// rest as it arrives
+    async fn read_head(&mut self, keep: usize) -> Result<ReplyHead, ConnectionError> {
+        let mut s = XdrStream::new();
+        let mut head = Vec::new();
+        let mut len = 0;
+        loop {
+            let mark = loop {
+                match s.try_u32().map_err(RpcError::from)? {
+                    Some(mark) => break mark,
+                    None => self.fill(&mut s).await?,
+                }
+            };
+            let frag = (mark & 0x7fff_ffff) as usize;
+            len += frag;
+            if len > self.max_record {
+                return Err(ConnectionError::RecordTooLarge {
+                    len,
+                    max: self.max_record,
+                });
+            }
+            let take = frag.min(keep - head.len());
+            let data = loop {
+                match s.try_raw(take) {
+                    Some(data) => break data,
+                    None => self.fill(&mut s).await?,
+                }
+            };
+            head.extend_from_slice(&data);
+            s.skip(frag - take);
+            while s.skipping() > 0 {
+                self.fill(&mut s).await?;
+            }
+            if mark & 0x8000_0000 != 0 {
+                return Ok(ReplyHead { head, len });
+            }
+        }
+    }
+
+    /// The first `keep` bytes of the reply to `xid`, without its record
+    /// mark; the rest is read and dropped, so a multi-megabyte reply is
+    /// never held in memory. Replies to other XIDs read meanwhile are
+    /// kept for later calls if they fit in `keep`, and dropped otherwise.
+    pub async fn recv_head(&mut self, xid: u32, keep: usize) -> Result<ReplyHead, ConnectionError> {
+        if let Some(mut reply) = self.pending.take(xid) {
+            let len = reply.len();
+            reply.truncate(keep);
+            return Ok(ReplyHead { head: reply, len });
+        }
+        let after = self.timeouts.read;
+        let wait = async {
+            loop {
+                let record = self.read_head(keep.max(4)).await?;
+                match record.head.get(..4) {
+                    Some(b) if u32::from_be_bytes(b.try_into().unwrap()) == xid => {
+                        let len = record.len;
+                        let mut head = record.head;
+                        head.truncate(keep);
+                        return Ok(ReplyHead { head, len });
+                    }
+                    Some(b) if record.is_complete() => {
+                        self.pending
+                            .park(u32::from_be_bytes(b.try_into().unwrap()), record.head);
+                    }
+                    _ => {}
+                }
+            }
+        };
+        timeout(after, wait)
+            .await
+            .map_err(|_| ConnectionError::Timeout { op: "read", after })?
+    }
+
+    /// The reply to `xid`, without its record mark. Replies to other
+    /// XIDs read meanwhile are kept for later `recv` calls. A timeout
+    /// can leave the stream part way through a record, so a connection
+    /// that timed out should be replaced.
+    pub async fn recv(&mut self, xid: u32) -> Result<Vec<u8>, ConnectionError> {
+        if let Some(reply) = self.pending.take(xid) {
+            return Ok(reply);
+        }
+        let after = self.timeouts.read;
+        let wait = async {
+            loop {
+                let record = self.read_record().await?;
+                match record.get(..4) {
+                    Some(b) if u32::from_be_bytes(b.try_into().unwrap()) == xid => {
+                        return Ok(record)
+                    }
+                    Some(b) => {
+                        self.pending
+                            .park(u32::from_be_bytes(b.try_into().unwrap()), record);
+                    }
+                    // Too short to be a reply; nobody will ask for it
+                    None => {}
+                }
+            }
+        };
+        timeout(after, wait)
+            .await
+            .map_err(|_| ConnectionError::Timeout { op: "read", after })?
+    }
+
+    /// Send `msg` and wait for its reply
+    pub async fn call(&mut self, msg: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        let xid = self.send(msg).await?;
+        self.recv(xid).await
+    }
+
+    /// The next record, whatever it holds, however long it takes to come;
+    /// for a backchannel, where the server speaks first
+    pub async fn next_record(&mut self) -> Result<Vec<u8>, ConnectionError> {
+        self.read_record().await
+    }
+}
+
+/// The message inside record-marked `msg`, fragments joined
+pub(crate) fn unmark(msg: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+    let mut out = Vec::with_capacity(msg.len());
+    let mut rest = msg;
+    loop {
+        let mark = rest
+            .get(..4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+            .ok_or(ConnectionError::BadRecord)?;
+        let len = (mark & 0x7fff_ffff) as usize;
+        let frag = rest.get(4..4 + len).ok_or(ConnectionError::BadRecord)?;
+        out.extend_from_slice(frag);
+        rest = &rest[4 + len..];
+        if mark & 0x8000_0000 != 0 {
+            return match rest.is_empty() {
+                true => Ok(out),
+                false => Err(ConnectionError::BadRecord),
+            };
+        }
+    }
+}
+
+/// When an unanswered UDP call is sent again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Retransmit {
+    /// Wait before the first resend
+    pub initial: Duration,
+    /// Each wait is this many times the last
+    pub backoff: f64,
+    /// Longest single wait
+    pub max_wait: Duration,
+    /// Sends in all, the first included
+    pub tries: u32,
+}
+
+impl Default for Retransmit {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(500),
+            backoff: 2.0,
+            max_wait: Duration::from_secs(4),
+            tries: 5,
+        }
+    }
+}
+
+impl Retransmit {
+    /// How long to wait after each send
+    pub fn waits(&self) -> impl Iterator<Item = Duration> + '_ {
+        let mut wait = self.initial;
+        (0..self.tries).map(move |_| {
+            let this = wait;
+            wait = wait.mul_f64(self.backoff).min(self.max_wait);
+            this
+        })
+    }
+}
+
+/// An RPC connection over a connected UDP socket
+#[derive(Debug)]
+pub struct UdpConnection {
+    socket: FaultySocket,
+    pub timeouts: Timeouts,
+    pub retransmit: Retransmit,
+    /// Datagrams read while waiting for a different XID
+    pending: Parked,
+    /// Datagrams sent again for want of a reply
+    pub resent: u64,
+}
+
+impl UdpConnection {
+    /// Bind an ephemeral port and connect it to `addr`
+    pub async fn connect(addr: SocketAddr, timeouts: Timeouts) -> Result<Self, ConnectionError> {
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        Ok(Self::new(socket, timeouts))
+    }
+
+    pub fn new(socket: UdpSocket, timeouts: Timeouts) -> Self {
+        Self {
+            socket: FaultySocket::new(socket, FaultConfig::default(), 0),
+            timeouts,
+            retransmit: Retransmit::default(),
+            pending: Parked::default(),
+            resent: 0,
+        }
+    }
+
+    /// Charge datagrams kept for later calls to `budget`
+    pub fn with_budget(mut self, budget: MemoryBudget) -> Self {
+        self.pending = Parked::new(budget);
+        self
+    }
+
+    /// Drop, duplicate, reorder and delay outgoing datagrams
+    pub fn with_faults(mut self, faults: FaultConfig, seed: u64) -> Self {
+        self.socket = self.socket.with_faults(faults, seed);
+        self
+    }
+
+    pub fn fault_stats(&self) -> FaultStats {
+        self.socket.stats()
+    }
+
+    /// Send record-marked `msg` as one datagram and return its XID
+    pub async fn send(&mut self, msg: &[u8]) -> Result<u32, ConnectionError> {
+        let datagram = unmark(msg)?;
+        self.send_datagram(&datagram).await
+    }
+
+    async fn send_datagram(&mut self, datagram: &[u8]) -> Result<u32, ConnectionError> {
+        if datagram.len() > MAX_DATAGRAM {
+            return Err(ConnectionError::DatagramTooLarge {
+                len: datagram.len(),
+                max: MAX_DATAGRAM,
+            });
+        }
+        let xid = datagram
+            .get(..4)
+            .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+            .ok_or(ConnectionError::NoXid)?;
+        let after = self.timeouts.write;
+        timeout(after, self.socket.send(datagram))
+            .await
+            .map_err(|_| ConnectionError::Timeout { op: "write", after })??;
+        Ok(xid)
+    }
+
+    /// Wait until `deadline` for the reply to `xid`
+    async fn recv_until(&mut self, xid: u32, deadline: Instant) -> Option<io::Result<Vec<u8>>> {
+        if let Some(reply) = self.pending.take(xid) {
+            return Some(Ok(reply));
+        }
+        let mut buf = vec![0; MAX_DATAGRAM];
+        loop {
+            let wait = deadline.checked_duration_since(Instant::now())?;
+            let n = match timeout(wait, self.socket.socket().recv(&mut buf)).await {
+                Err(_) => return None,
+                Ok(Err(e)) => return Some(Err(e)),
+                Ok(Ok(n)) => n,
+            };
+            let datagram = buf[..n].to_vec();
+            match datagram.get(..4) {
+                Some(b) if u32::from_be_bytes(b.try_into().unwrap()) == xid => {
+                    return Some(Ok(datagram))
+                }
+                Some(b) => {
+                    self.pending
+                        .park(u32::from_be_bytes(b.try_into().unwrap()), datagram);
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// The reply to `xid`, within the read timeout; nothing is resent
+    pub async fn recv(&mut self, xid: u32) -> Result<Vec<u8>, ConnectionError> {
+        let after = self.timeouts.read;
+        match self.recv_until(xid, Instant::now() + after).await {
+            Some(reply) => Ok(reply?),
+            None => Err(ConnectionError::Timeout { op: "read", after }),
+        }
+    }
+
+    /// Send `msg` and wait for its reply, sending it again as the
+    /// retransmit policy says. A reply to any of the sends counts.
+    pub async fn call(&mut self, msg: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        let datagram = unmark(msg)?;
+        let mut waited = Duration::ZERO;
+        let waits: Vec<Duration> = self.retransmit.waits().collect();
+        for (n, wait) in waits.into_iter().enumerate() {
+            if n > 0 {
+                self.resent += 1;
+            }
+            let xid = self.send_datagram(&datagram).await?;
+            waited += wait;
+            if let Some(reply) = self.recv_until(xid, Instant::now() + wait).await {
+                return Ok(reply?);
+            }
+        }
+        let _ = self.socket.flush().await;
+        Err(ConnectionError::Timeout {
+            op: "read",
+            after: waited,
+        })
+    }
+}
+
+/// A connection over either transport
+#[derive(Debug)]
+pub enum Transport {
+    Tcp(NfsConnection),
+    Udp(UdpConnection),
+}
+
+impl Transport {
+    pub async fn connect(
+        proto: Proto,
+        addr: SocketAddr,
+        timeouts: Timeouts,
+    ) -> Result<Self, ConnectionError> {
+        Ok(match proto {
+            Proto::Tcp => Transport::Tcp(NfsConnection::connect(addr, timeouts).await?),
+            Proto::Udp => Transport::Udp(UdpConnection::connect(addr, timeouts).await?),
+        })
+    }
+
+    pub fn proto(&self) -> Proto {
+        match self {
+            Transport::Tcp(_) => Proto::Tcp,
+            Transport::Udp(_) => Proto::Udp,
+        }
+    }
+
+    /// Ask portmap v2 on the other end which port `program`/`version`
+    /// listens on over this transport's protocol
+    pub async fn getport(
+        &mut self,
+        program: u32,
+        version: u32,
+    ) -> Result<Option<u16>, ConnectionError> {
+        let reply = self
+            .call(&rpc::getport_call(program, version, self.proto().ipproto()))
+            .await?;
+        Ok(rpc::getport_result(
+            RpcReply::parse(&reply)?.into_results()?,
+        )?)
+    }
+
+    /// Ask portmap v2 on the other end for everything registered with it
+    pub async fn dump(&mut self) -> Result<Vec<rpc::Mapping>, ConnectionError> {
+        let reply = self.call(&rpc::dump_call()).await?;
+        Ok(rpc::dump_result(RpcReply::parse(&reply)?.into_results()?)?)
+    }
+
+    /// Ask rpcbind v4 on the other end where `program`/`version` listens
+    /// over this transport's protocol on IPv4; the address may name
+    /// another host
+    pub async fn getaddr(
+        &mut self,
+        program: u32,
+        version: u32,
+    ) -> Result<Option<SocketAddr>, ConnectionError> {
+        let netid = match self.proto() {
+            Proto::Tcp => "tcp",
+            Proto::Udp => "udp",
+        };
+        let reply = self
+            .call(&rpc::getaddr_call(rpcb::V4, program, version, netid))
+            .await?;
+        let uaddr = rpc::getaddr_result(RpcReply::parse(&reply)?.into_results()?)?;
+        Ok(uaddr.as_deref().and_then(rpc::parse_uaddr))
+    }
+
+    /// The local end of the connection
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Transport::Tcp(conn) => conn.stream.local_addr(),
+            Transport::Udp(conn) => conn.socket.socket().local_addr(),
+        }
+    }
+
+    /// Charge replies kept for later calls to `budget`
+    pub fn with_budget(self, budget: MemoryBudget) -> Self {
+        match self {
+            Transport::Tcp(conn) => Transport::Tcp(conn.with_budget(budget)),
+            Transport::Udp(conn) => Transport::Udp(conn.with_budget(budget)),
+        }
+    }
+
+    /// Inject faults into UDP sends; TCP is left as it is
+    pub fn with_faults(self, faults: FaultConfig, seed: u64) -> Self {
+        match self {
+            Transport::Udp(conn) => Transport::Udp(conn.with_faults(faults, seed)),
+            tcp => tcp,
+        }
+    }
+
+    /// Send record-marked `msg` and wait for its reply
+    pub async fn call(&mut self, msg: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        match self {
+            Transport::Tcp(conn) => conn.call(msg).await,
+            Transport::Udp(conn) => conn.call(msg).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{program, RpcCall, RpcReply};
+
+    fn call(xid: u32) -> Vec<u8> {
+        RpcCall::new(xid, program::NFS, 3, 0, true)
+            .with_auth_none()
+            .build()
+            .to_vec()
+    }
+
+    /// A successful reply to `xid`, split into one-word fragments
+    fn reply(xid: u32) -> Vec<u8> {
+        let words = [xid, 1, 0, 0, 0, 0];
+        let mut out = Vec::new();
+        for (i, w) in words.iter().enumerate() {
+            let last = if i + 1 == words.len() { 0x8000_0000 } else { 0 };
+            out.extend_from_slice(&(last | 4u32).to_be_bytes());
+            out.extend_from_slice(&w.to_be_bytes());
+        }
+        out
+    }
+
+    fn timeouts() -> Timeouts {
+        Timeouts {
+            connect: Duration::from_millis(50),
+            read: Duration::from_millis(50),
+            write: Duration::from_millis(50),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_matched_by_xid() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut conn = NfsConnection::new(client, timeouts());
+        assert_eq!(conn.send(&call(1)).await.unwrap(), 1);
+        assert_eq!(conn.send(&call(2)).await.unwrap(), 2);
+        // Answered out of order, and 3 never asked for
+        server.write_all(&reply(3)).await.unwrap();
+        server.write_all(&reply(2)).await.unwrap();
+        server.write_all(&reply(1)).await.unwrap();
+
+        let one = conn.recv(1).await.unwrap();
+        assert!(RpcReply::parse(&one).unwrap().is_success());
+        assert_eq!(one.len(), 24);
+        assert_eq!(conn.pending(), 2);
+        assert_eq!(conn.recv(2).await.unwrap()[..4], [0, 0, 0, 2]);
+        assert!(matches!(
+            conn.recv(9).await,
+            Err(ConnectionError::Timeout { op: "read", .. })
+        ));
+        drop(server);
+        assert!(matches!(conn.recv(9).await, Err(ConnectionError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_parked_replies_are_bounded() {
+        let (client, mut server) = tokio::io::duplex(1 << 16);
+        let budget = MemoryBudget::new(24 * MAX_PARKED);
+        let mut conn = NfsConnection::new(client, timeouts()).with_budget(budget.clone());
+        // A server spraying replies nobody asked for, 3 twice
+        for xid in (100..100 + 2 * MAX_PARKED as u32).chain([3, 3, 1]) {
+            server.write_all(&reply(xid)).await.unwrap();
+        }
+        assert_eq!(conn.recv(1).await.unwrap()[..4], [0, 0, 0, 1]);
+        assert_eq!(conn.pending(), MAX_PARKED);
+        assert_eq!(conn.dropped(), MAX_PARKED as u64 + 1);
+        assert_eq!(budget.used(), 24 * MAX_PARKED);
+        assert!(conn.recv(3).await.is_ok());
+        assert_eq!(budget.used(), 24 * (MAX_PARKED - 1));
+        drop(conn);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_recv_head_drops_the_rest() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut conn = NfsConnection::new(client, timeouts());
+        // A 1 MiB reply to 1 in two fragments, then a short reply to 2
+        let body = [&reply(1)[4..8], &[0xaa; (1 << 20) - 4][..]].concat();
+        tokio::spawn(async move {
+            server.write_all(&(4u32 << 10).to_be_bytes()).await.unwrap();
+            server.write_all(&body[..4 << 10]).await.unwrap();
+            let rest = (0x8000_0000 | (body.len() - (4 << 10)) as u32).to_be_bytes();
+            server.write_all(&rest).await.unwrap();
+            server.write_all(&body[4 << 10..]).await.unwrap();
+            server.write_all(&reply(2)).await.unwrap();
+        });
+        let head = conn.recv_head(1, 6).await.unwrap();
+        assert_eq!(head.head, [0, 0, 0, 1, 0xaa, 0xaa]);
+        assert_eq!(head.len, 1 << 20);
+        assert!(!head.is_complete());
+        // The stream is left at the next record
+        let next = conn.recv_head(2, 64).await.unwrap();
+        assert!(next.is_complete());
+        assert!(RpcReply::parse(&next.head).unwrap().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_oversized_record_and_tcp() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut conn = NfsConnection::new(client, timeouts());
+        conn.max_record = 16;
+        server
+            .write_all(&0x8000_0011u32.to_be_bytes())
+            .await
+            .unwrap();
+        assert!(matches!(
+            conn.recv(1).await,
+            Err(ConnectionError::RecordTooLarge { len: 17, max: 16 })
+        ));
+        assert!(matches!(
+            conn.send(&[0; 4]).await,
+            Err(ConnectionError::NoXid)
+        ));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut s, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; call(7).len()];
+            s.read_exact(&mut buf).await.unwrap();
+            s.write_all(&reply(7)).await.unwrap();
+        });
+        let mut conn = NfsConnection::connect(addr, timeouts()).await.unwrap();
+        assert_eq!(conn.call(&call(7)).await.unwrap()[..4], [0, 0, 0, 7]);
+    }
+
+    #[tokio::test]
+    async fn test_udp_retransmits() {
+        assert!(matches!(
+            unmark(&[0x80, 0, 0, 8, 0, 0, 0, 1]),
+            Err(ConnectionError::BadRecord)
+        ));
+        assert_eq!(
+            unmark(&[0, 0, 0, 2, 1, 2, 0x80, 0, 0, 2, 3, 4]).unwrap(),
+            [1, 2, 3, 4]
+        );
+        let policy = Retransmit {
+            initial: Duration::from_millis(20),
+            backoff: 3.0,
+            max_wait: Duration::from_millis(100),
+            tries: 4,
+        };
+        let waits: Vec<u64> = policy.waits().map(|w| w.as_millis() as u64).collect();
+        assert_eq!(waits, [20, 60, 100, 100]);
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut conn = UdpConnection::connect(addr, timeouts()).await.unwrap();
+        conn.retransmit = policy;
+        // Ignore the first send, answer the second
+        let answer = async {
+            let mut buf = [0; 128];
+            let (_, peer) = server.recv_from(&mut buf).await.unwrap();
+            let (n, _) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..4], [0, 0, 0, 5]);
+            let reply = unmark(&reply(5)).unwrap();
+            server.send_to(&reply, peer).await.unwrap();
+            n
+        };
+        let msg = call(5);
+        let (n, got) = tokio::join!(answer, conn.call(&msg));
+        assert_eq!(n, call(5).len() - 4);
+        assert!(RpcReply::parse(&got.unwrap()).unwrap().is_success());
+        assert_eq!(conn.resent, 1);
+
+        let big = RpcCall::new(6, program::NFS, 3, 7, true)
+            .with_args(&vec![0; MAX_DATAGRAM])
+            .build();
+        assert!(matches!(
+            conn.send(&big).await,
+            Err(ConnectionError::DatagramTooLarge { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_getport_over_udp() {
+        let portmap = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = portmap.local_addr().unwrap();
+        let udp = UdpConnection::connect(addr, timeouts()).await.unwrap();
+        let mut conn = Transport::Udp(udp).with_faults(FaultConfig::default(), 1);
+        assert_eq!(conn.proto(), Proto::Udp);
+        let answer = async {
+            let mut buf = [0; 128];
+            let (n, peer) = portmap.recv_from(&mut buf).await.unwrap();
+            // GETPORT(MOUNT, 3, UDP)
+            assert_eq!(
+                buf[n - 16..n - 4],
+                [0, 1, 0x86, 0xa5, 0, 0, 0, 3, 0, 0, 0, 17]
+            );
+            let mut reply = buf[..4].to_vec();
+            reply.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            reply.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0x4e, 0x25]);
+            portmap.send_to(&reply, peer).await.unwrap();
+        };
+        let (_, port) = tokio::join!(answer, conn.getport(program::MOUNT, 3));
+        assert_eq!(port.unwrap(), Some(20005));
+    }
+}
@@ -0,0 +1,197 @@
+//! Server containers the fuzzer runs itself
+//!
+//! Userspace servers such as NFS-Ganesha or unfs3 ship as container
+//! images, and a CI job fuzzing one should not need a lab around it. A
+//! [`Container`] starts the image with `docker run`, finds the address
+//! docker gave it, and [`wait_ready`] holds the campaign until the server
+//! answers NULL. The campaign then drives it through the [`Docker`]
+//! controller: a crash restarts the container and collects its output
+//! into the finding like any other target's logs. Dropping the
+//! [`Container`] removes it, so a job that fails halfway leaves nothing
+//! running.
+//!
+//! The container's own address is used rather than a published port, so
+//! the fuzzer must run where that address is routable, as on a Linux
+//! docker host.
+
+use crate::connection::{Proto, Timeouts, Transport};
+use crate::controller::{run, ControllerError, Docker};
+use crate::rpc::{self, program};
+use std::net::{IpAddr, SocketAddr};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::warn;
+
+/// How long a new container gets to start answering, by default
+pub const READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Error, Debug)]
+pub enum ContainerError {
+    #[error(transparent)]
+    Docker(#[from] ControllerError),
+    #[error("container {container} has no address (inspect gave {output:?})")]
+    NoAddress { container: String, output: String },
+}
+
+/// What container to start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerSpec {
+    pub image: String,
+    pub name: String,
+    /// Options passed to `docker run` before the image, such as
+    /// `--privileged` or `-v /srv/export:/export`
+    pub options: Vec<String>,
+    /// Daemon to talk to (`docker -H`); the CLI's default when absent
+    pub host: Option<String>,
+}
+
+impl ContainerSpec {
+    pub fn new(image: &str, name: &str) -> Self {
+        Self {
+            image: image.to_string(),
+            name: name.to_string(),
+            options: Vec::new(),
+            host: None,
+        }
+    }
+
+    pub fn with_options(mut self, options: impl IntoIterator<Item = String>) -> Self {
+        self.options.extend(options);
+        self
+    }
+
+    /// The `docker run` invocation starting it, detached
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("docker");
+        if let Some(host) = &self.host {
+            cmd.arg("-H").arg(host);
+        }
+        cmd.args(["run", "--detach", "--name", &self.name])
+            .args(&self.options)
+            .arg(&self.image);
+        cmd
+    }
+}
+
+/// A running server container, removed on drop
+#[derive(Debug)]
+pub struct Container {
+    docker: Docker,
+}
+
+impl Container {
+    pub fn start(spec: &ContainerSpec) -> Result<Self, ContainerError> {
+        run(spec.command())?;
+        Ok(Self {
+            docker: Docker {
+                host: spec.host.clone(),
+                ..Docker::new(&spec.name)
+            },
+        })
+    }
+
+    /// The controller that restarts it and collects its logs
+    pub fn docker(&self) -> &Docker {
+        &self.docker
+    }
+
+    /// Its address on the first docker network it is attached to
+    pub fn address(&self) -> Result<IpAddr, ContainerError> {
+        let format = "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{end}}";
+        let out = run(self.docker.command("inspect", &["--format", format]))?;
+        let text = String::from_utf8_lossy(&out);
+        text.split_whitespace()
+            .find_map(|a| a.parse().ok())
+            .ok_or_else(|| ContainerError::NoAddress {
+                container: self.docker.container.clone(),
+                output: text.trim().to_string(),
+            })
+    }
+}
+
+impl Drop for Container {
+    fn drop(&mut self) {
+        if let Err(e) = run(self.docker.command("rm", &["--force"])) {
+            warn!(
+                "Could not remove container {}: {}",
+                self.docker.container, e
+            );
+        }
+    }
+}
+
+/// Call NULL on `addr` until it answers, for up to `timeout`; how long
+/// that took, or `None` if it never did
+pub async fn wait_ready(
+    proto: Proto,
+    addr: SocketAddr,
+    nfs_version: u32,
+    timeouts: Timeouts,
+    timeout: Duration,
+) -> Option<Duration> {
+    let start = Instant::now();
+    let null = rpc::simple_rpc_call(program::NFS, nfs_version, 0);
+    loop {
+        if let Ok(mut conn) = Transport::connect(proto, addr, timeouts).await {
+            if conn.call(&null).await.is_ok() {
+                return Some(start.elapsed());
+            }
+        }
+        if start.elapsed() >= timeout {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(500)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::describe;
+    use crate::hang::read_record;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_run_command() {
+        let spec = ContainerSpec {
+            host: Some("unix:///run/docker.sock".to_string()),
+            ..ContainerSpec::new("ganesha:latest", "nfs-fuzzer-1")
+        }
+        .with_options(["--privileged".to_string()]);
+        assert_eq!(
+            describe(&spec.command()),
+            "docker -H unix:///run/docker.sock run --detach --name nfs-fuzzer-1 --privileged ganesha:latest"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait_ready() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeouts = Timeouts {
+            connect: Duration::from_millis(200),
+            read: Duration::from_millis(200),
+            write: Duration::from_millis(200),
+        };
+        // Starts answering on its second connection
+        tokio::spawn(async move {
+            drop(listener.accept().await.unwrap());
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let call = read_record(&mut conn).await.unwrap();
+            let mut reply = vec![0x80, 0, 0, 24];
+            reply.extend_from_slice(&call[..4]);
+            reply.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+            conn.write_all(&reply).await.unwrap();
+        });
+        let took = wait_ready(Proto::Tcp, addr, 3, timeouts, Duration::from_secs(5)).await;
+        assert!(took.is_some_and(|t| t >= Duration::from_millis(500)));
+
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = closed.local_addr().unwrap();
+        drop(closed);
+        let took = wait_ready(Proto::Tcp, addr, 3, timeouts, Duration::from_millis(600)).await;
+        assert_eq!(took, None);
+    }
+}
@@ -0,0 +1,214 @@
+//! Pausing and resuming a campaign
+//!
+//! Target maintenance should not cost the campaign its sessions, opens
+//! and locks. A [`Gate`] held by every sender stops new requests while
+//! paused; connections, leases and everything else stay up, and nothing
+//! in flight is cancelled. SIGUSR1 toggles the gate, and a line-based
+//! control socket accepts `pause`, `resume`, `toggle` and `status`.
+
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Shared pause flag; clones control the same campaign
+#[derive(Debug, Clone)]
+pub struct Gate {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gate {
+    pub fn new() -> Self {
+        Self {
+            paused: Arc::new(watch::channel(false).0),
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    pub fn pause(&self) {
+        self.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.set(false);
+    }
+
+    /// Flip the state; returns whether the campaign is now paused
+    pub fn toggle(&self) -> bool {
+        let mut now = false;
+        self.paused.send_modify(|p| {
+            *p = !*p;
+            now = *p;
+        });
+        now
+    }
+
+    fn set(&self, paused: bool) {
+        self.paused
+            .send_if_modified(|p| std::mem::replace(p, paused) != paused);
+    }
+
+    /// Wait until the campaign is running; returns at once if it is.
+    /// Senders call this before every request.
+    pub async fn wait_running(&self) {
+        let mut rx = self.paused.subscribe();
+        // The sender lives as long as `self`, so this cannot fail
+        let _ = rx.wait_for(|paused| !paused).await;
+    }
+}
+
+/// Toggle `gate` on every SIGUSR1
+#[cfg(unix)]
+pub fn listen_sigusr1(gate: Gate) -> std::io::Result<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut usr1 = signal(SignalKind::user_defined1())?;
+    Ok(tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            let paused = gate.toggle();
+            info!("SIGUSR1: campaign {}", state_name(paused));
+        }
+    }))
+}
+
+/// A control socket command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Toggle,
+    Status,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "pause" => Ok(Command::Pause),
+            "resume" => Ok(Command::Resume),
+            "toggle" => Ok(Command::Toggle),
+            "status" => Ok(Command::Status),
+            other => Err(format!("unknown command {:?}", other)),
+        }
+    }
+}
+
+fn state_name(paused: bool) -> &'static str {
+    if paused {
+        "paused"
+    } else {
+        "running"
+    }
+}
+
+/// Apply one command line; the reply is the resulting state or an error
+pub fn handle(gate: &Gate, line: &str) -> String {
+    match line.parse::<Command>() {
+        Ok(Command::Pause) => gate.pause(),
+        Ok(Command::Resume) => gate.resume(),
+        Ok(Command::Toggle) => {
+            gate.toggle();
+        }
+        Ok(Command::Status) => {}
+        Err(e) => return format!("error: {}", e),
+    }
+    state_name(gate.is_paused()).to_string()
+}
+
+/// Serve the control socket: one command per line, one reply line each
+pub async fn serve(listener: TcpListener, gate: Gate) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("control socket: {}", e);
+                continue;
+            }
+        };
+        let gate = gate.clone();
+        tokio::spawn(async move {
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let reply = handle(&gate, &line);
+                info!("control {}: {} -> {}", peer, line.trim(), reply);
+                if write
+                    .write_all(format!("{}\n", reply).as_bytes())
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::net::TcpStream;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn test_gate_blocks_while_paused() {
+        let gate = Gate::new();
+        timeout(Duration::from_millis(100), gate.wait_running())
+            .await
+            .unwrap();
+        gate.pause();
+        assert!(timeout(Duration::from_millis(50), gate.wait_running())
+            .await
+            .is_err());
+        let waiter = tokio::spawn({
+            let gate = gate.clone();
+            async move { gate.wait_running().await }
+        });
+        assert!(!gate.toggle());
+        timeout(Duration::from_millis(100), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_control_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let gate = Gate::new();
+        tokio::spawn(serve(listener, gate.clone()));
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        for (cmd, reply) in [
+            ("status", "running"),
+            ("PAUSE", "paused"),
+            ("pause", "paused"),
+            ("toggle", "running"),
+            ("halt", "error: unknown command \"halt\""),
+        ] {
+            write
+                .write_all(format!("{}\n", cmd).as_bytes())
+                .await
+                .unwrap();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), reply);
+        }
+        assert!(!gate.is_paused());
+    }
+}
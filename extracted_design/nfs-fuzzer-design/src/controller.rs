@@ -0,0 +1,631 @@
+//! Controlling the machine under test
+//!
+//! Everything that acts on the target rather than talking NFS to it goes
+//! through a [`TargetController`]: restarting the NFS service after a
+//! crash, rebooting a host that will not come back, pulling its logs
+//! into a finding, and snapshotting it. `none` does none of these and
+//! leaves restarts to the target's own supervisor; `ssh` runs commands on
+//! the host, `libvirt` drives the virtual machine it runs in through
+//! `virsh`, `docker` the container it runs in, and `shell` runs whatever
+//! local commands it is given, so any other setup can be hooked in. A
+//! [`ControllerConfig`] picks one from a spec such as
+//! `ssh,host=root@nfs1,service=nfs-kernel-server`,
+//! `libvirt,domain=nfs1,uri=qemu:///system` or
+//! `shell,restart=/opt/bin/restart-nfs`. Specs are split at commas, so
+//! commands given in them cannot contain one; point at a script instead.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ControllerError {
+    #[error("{controller} controller cannot {action}")]
+    Unsupported {
+        controller: &'static str,
+        action: &'static str,
+    },
+    #[error("could not run {command}: {source}")]
+    Spawn {
+        command: String,
+        source: std::io::Error,
+    },
+    #[error("{command} failed ({status}): {stderr}")]
+    Failed {
+        command: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+}
+
+/// Actions on the target host
+///
+/// Implementations block, so callers on the runtime go through
+/// [`blocking`].
+pub trait TargetController: Send + Sync + fmt::Debug {
+    /// Name the controller is selected by
+    fn name(&self) -> &'static str;
+    /// Restart the NFS service, keeping the host up
+    fn restart_service(&self) -> Result<(), ControllerError>;
+    /// Reset the whole host
+    fn reboot(&self) -> Result<(), ControllerError>;
+    /// Recent server logs, as text
+    fn collect_logs(&self) -> Result<Vec<u8>, ControllerError>;
+    /// Save the target's state under `name`
+    fn snapshot(&self, name: &str) -> Result<(), ControllerError>;
+}
+
+/// Run a controller action off the runtime
+pub async fn blocking<T: Send + 'static>(
+    controller: &Arc<dyn TargetController>,
+    action: impl FnOnce(&dyn TargetController) -> Result<T, ControllerError> + Send + 'static,
+) -> Result<T, ControllerError> {
+    let controller = Arc::clone(controller);
+    tokio::task::spawn_blocking(move || action(controller.as_ref()))
+        .await
+        .unwrap_or_else(|e| {
+            Err(ControllerError::Spawn {
+                command: "controller task".to_string(),
+                source: std::io::Error::other(e),
+            })
+        })
+}
+
+pub(crate) fn describe(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Run `cmd` to completion; its stdout on success
+pub(crate) fn run(mut cmd: Command) -> Result<Vec<u8>, ControllerError> {
+    let command = describe(&cmd);
+    let out = cmd.output().map_err(|source| ControllerError::Spawn {
+        command: command.clone(),
+        source,
+    })?;
+    if out.status.success() {
+        Ok(out.stdout)
+    } else {
+        Err(ControllerError::Failed {
+            command,
+            status: out.status,
+            stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// Does nothing; the target restarts itself or not at all
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NoController;
+
+impl NoController {
+    fn unsupported<T>(action: &'static str) -> Result<T, ControllerError> {
+        Err(ControllerError::Unsupported {
+            controller: "none",
+            action,
+        })
+    }
+}
+
+impl TargetController for NoController {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn restart_service(&self) -> Result<(), ControllerError> {
+        Self::unsupported("restart the service")
+    }
+
+    fn reboot(&self) -> Result<(), ControllerError> {
+        Self::unsupported("reboot")
+    }
+
+    fn collect_logs(&self) -> Result<Vec<u8>, ControllerError> {
+        Self::unsupported("collect logs")
+    }
+
+    fn snapshot(&self, _name: &str) -> Result<(), ControllerError> {
+        Self::unsupported("snapshot")
+    }
+}
+
+/// Commands run on the target over `ssh` in batch mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ssh {
+    /// `host` or `user@host`
+    pub host: String,
+    pub port: Option<u16>,
+    /// Private key to log in with
+    pub key: Option<PathBuf>,
+    /// systemd unit of the NFS server
+    pub service: String,
+    /// Seconds of journal collected into findings
+    pub log_window: u64,
+    /// Command run to snapshot, with `{name}` replaced; none can't
+    pub snapshot: Option<String>,
+    /// Command run to restart the server instead of restarting `service`
+    pub restart: Option<String>,
+}
+
+impl Ssh {
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port: None,
+            key: None,
+            service: "nfs-server".to_string(),
+            log_window: 300,
+            snapshot: None,
+            restart: None,
+        }
+    }
+
+    /// The `ssh` invocation running `remote` on the host
+    pub fn command(&self, remote: &str) -> Command {
+        let mut cmd = Command::new("ssh");
+        cmd.args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10"]);
+        if let Some(port) = self.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        if let Some(key) = &self.key {
+            cmd.arg("-i").arg(key);
+        }
+        cmd.arg(&self.host).arg("--").arg(remote);
+        cmd
+    }
+}
+
+/// Exit status ssh reports when the connection went away
+pub(crate) const SSH_DISCONNECTED: i32 = 255;
+
+impl TargetController for Ssh {
+    fn name(&self) -> &'static str {
+        "ssh"
+    }
+
+    fn restart_service(&self) -> Result<(), ControllerError> {
+        let restart = match &self.restart {
+            Some(command) => command.clone(),
+            None => format!("systemctl restart {}", self.service),
+        };
+        run(self.command(&restart)).map(drop)
+    }
+
+    fn reboot(&self) -> Result<(), ControllerError> {
+        // The host drops the session as it goes down
+        match run(self.command("systemctl reboot")) {
+            Err(ControllerError::Failed { status, .. })
+                if status.code() == Some(SSH_DISCONNECTED) =>
+            {
+                Ok(())
+            }
+            result => result.map(drop),
+        }
+    }
+
+    fn collect_logs(&self) -> Result<Vec<u8>, ControllerError> {
+        // The whole journal: in-kernel servers log to the kernel's
+        run(self.command(&format!(
+            "journalctl --no-pager --since '-{}s'",
+            self.log_window
+        )))
+    }
+
+    fn snapshot(&self, name: &str) -> Result<(), ControllerError> {
+        match &self.snapshot {
+            Some(template) => run(self.command(&template.replace("{name}", name))).map(drop),
+            None => Err(ControllerError::Unsupported {
+                controller: "ssh",
+                action: "snapshot without a snapshot command",
+            }),
+        }
+    }
+}
+
+/// A virtual machine target driven through `virsh`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Libvirt {
+    pub domain: String,
+    /// Connection URI; virsh's default when absent
+    pub uri: Option<String>,
+    /// File the guest's serial console is logged to, on this host
+    pub console_log: Option<PathBuf>,
+}
+
+impl Libvirt {
+    pub fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.to_string(),
+            uri: None,
+            console_log: None,
+        }
+    }
+
+    /// The `virsh` invocation of `subcommand` on the domain
+    pub fn command(&self, subcommand: &str, extra: &[&str]) -> Command {
+        let mut cmd = Command::new("virsh");
+        if let Some(uri) = &self.uri {
+            cmd.arg("-c").arg(uri);
+        }
+        cmd.arg(subcommand).arg(&self.domain).args(extra);
+        cmd
+    }
+}
+
+impl TargetController for Libvirt {
+    fn name(&self) -> &'static str {
+        "libvirt"
+    }
+
+    /// The guest is opaque to virsh, so restarting the service resets
+    /// the machine
+    fn restart_service(&self) -> Result<(), ControllerError> {
+        self.reboot()
+    }
+
+    /// A hard reset; a wedged guest would ignore `virsh reboot`
+    fn reboot(&self) -> Result<(), ControllerError> {
+        run(self.command("reset", &[])).map(drop)
+    }
+
+    /// What the guest wrote to its serial console
+    fn collect_logs(&self) -> Result<Vec<u8>, ControllerError> {
+        let path = self
+            .console_log
+            .as_ref()
+            .ok_or(ControllerError::Unsupported {
+                controller: "libvirt",
+                action: "collect logs without a console log",
+            })?;
+        std::fs::read(path).map_err(|source| ControllerError::Spawn {
+            command: format!("read {}", path.display()),
+            source,
+        })
+    }
+
+    fn snapshot(&self, name: &str) -> Result<(), ControllerError> {
+        run(self.command("snapshot-create-as", &["--name", name, "--atomic"])).map(drop)
+    }
+}
+
+/// A target in a container, driven through the `docker` CLI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Docker {
+    pub container: String,
+    /// Daemon to talk to (`docker -H`); the CLI's default when absent
+    pub host: Option<String>,
+    /// Seconds of container output collected into findings
+    pub log_window: u64,
+}
+
+impl Docker {
+    pub fn new(container: &str) -> Self {
+        Self {
+            container: container.to_string(),
+            host: None,
+            log_window: 300,
+        }
+    }
+
+    /// The `docker` invocation of `subcommand` on the container
+    pub fn command(&self, subcommand: &str, extra: &[&str]) -> Command {
+        let mut cmd = Command::new("docker");
+        if let Some(host) = &self.host {
+            cmd.arg("-H").arg(host);
+        }
+        cmd.arg(subcommand).args(extra).arg(&self.container);
+        cmd
+    }
+}
+
+impl TargetController for Docker {
+    fn name(&self) -> &'static str {
+        "docker"
+    }
+
+    fn restart_service(&self) -> Result<(), ControllerError> {
+        run(self.command("restart", &[])).map(drop)
+    }
+
+    /// Kill rather than stop, which waits on a wedged server, then start
+    fn reboot(&self) -> Result<(), ControllerError> {
+        match run(self.command("kill", &[])) {
+            // Already down
+            Err(ControllerError::Failed { .. }) | Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        run(self.command("start", &[])).map(drop)
+    }
+
+    /// The container's standard output; a server logging to the kernel
+    /// logs to the host's
+    fn collect_logs(&self) -> Result<Vec<u8>, ControllerError> {
+        let since = format!("{}s", self.log_window);
+        run(self.command("logs", &["--since", &since]))
+    }
+
+    fn snapshot(&self, _name: &str) -> Result<(), ControllerError> {
+        Err(ControllerError::Unsupported {
+            controller: "docker",
+            action: "snapshot",
+        })
+    }
+}
+
+/// Local commands, run with `sh -c`, for each action; an action without
+/// one is unsupported
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Shell {
+    pub restart: Option<String>,
+    pub reboot: Option<String>,
+    /// Prints the logs to collect
+    pub logs: Option<String>,
+    /// With `{name}` replaced
+    pub snapshot: Option<String>,
+}
+
+impl Shell {
+    fn run(command: &Option<String>, action: &'static str) -> Result<Vec<u8>, ControllerError> {
+        let command = command.as_ref().ok_or(ControllerError::Unsupported {
+            controller: "shell",
+            action,
+        })?;
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        run(cmd)
+    }
+}
+
+impl TargetController for Shell {
+    fn name(&self) -> &'static str {
+        "shell"
+    }
+
+    fn restart_service(&self) -> Result<(), ControllerError> {
+        Self::run(&self.restart, "restart the service without restart=").map(drop)
+    }
+
+    fn reboot(&self) -> Result<(), ControllerError> {
+        Self::run(&self.reboot, "reboot without reboot=").map(drop)
+    }
+
+    fn collect_logs(&self) -> Result<Vec<u8>, ControllerError> {
+        Self::run(&self.logs, "collect logs without logs=")
+    }
+
+    fn snapshot(&self, name: &str) -> Result<(), ControllerError> {
+        let command = self.snapshot.as_ref().map(|c| c.replace("{name}", name));
+        Self::run(&command, "snapshot without snapshot=").map(drop)
+    }
+}
+
+/// Which controller a campaign uses, and how it is set up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ControllerConfig {
+    #[default]
+    None,
+    Ssh(Ssh),
+    Libvirt(Libvirt),
+    Docker(Docker),
+    Shell(Shell),
+}
+
+impl ControllerConfig {
+    pub fn controller(&self) -> Arc<dyn TargetController> {
+        match self {
+            ControllerConfig::None => Arc::new(NoController),
+            ControllerConfig::Ssh(ssh) => Arc::new(ssh.clone()),
+            ControllerConfig::Libvirt(libvirt) => Arc::new(libvirt.clone()),
+            ControllerConfig::Docker(docker) => Arc::new(docker.clone()),
+            ControllerConfig::Shell(shell) => Arc::new(shell.clone()),
+        }
+    }
+}
+
+impl FromStr for ControllerConfig {
+    type Err = String;
+
+    /// `none`,
+    /// `ssh,host=H[,port=N][,key=PATH][,service=UNIT][,restart=CMD][,logs=SECS][,snapshot=CMD]`,
+    /// `libvirt,domain=D[,uri=URI][,console=PATH]`,
+    /// `docker,container=C[,host=URL][,logs=SECS]` or
+    /// `shell[,restart=CMD][,reboot=CMD][,logs=CMD][,snapshot=CMD]`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',').filter(|p| !p.is_empty());
+        let kind = parts.next().unwrap_or("none");
+        let mut options = Vec::new();
+        for part in parts {
+            options.push(
+                part.split_once('=')
+                    .ok_or_else(|| format!("expected key=value, got {:?}", part))?,
+            );
+        }
+        let required = |key: &str| {
+            options
+                .iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| *v)
+                .ok_or_else(|| format!("{} controller needs {}=", kind, key))
+        };
+        match kind {
+            "none" => match options.first() {
+                Some((key, _)) => Err(format!("none controller takes no {:?}", key)),
+                None => Ok(ControllerConfig::None),
+            },
+            "ssh" => {
+                let mut ssh = Ssh::new(required("host")?);
+                for &(key, value) in &options {
+                    match key {
+                        "host" => {}
+                        "port" => {
+                            ssh.port =
+                                Some(value.parse().map_err(|_| format!("bad port {:?}", value))?)
+                        }
+                        "key" => ssh.key = Some(PathBuf::from(value)),
+                        "service" => ssh.service = value.to_string(),
+                        "logs" => {
+                            ssh.log_window = value
+                                .parse()
+                                .map_err(|_| format!("bad log window {:?}", value))?
+                        }
+                        "snapshot" => ssh.snapshot = Some(value.to_string()),
+                        "restart" => ssh.restart = Some(value.to_string()),
+                        _ => return Err(format!("unknown ssh option {:?}", key)),
+                    }
+                }
+                Ok(ControllerConfig::Ssh(ssh))
+            }
+            "libvirt" => {
+                let mut libvirt = Libvirt::new(required("domain")?);
+                for &(key, value) in &options {
+                    match key {
+                        "domain" => {}
+                        "uri" => libvirt.uri = Some(value.to_string()),
+                        "console" => libvirt.console_log = Some(PathBuf::from(value)),
+                        _ => return Err(format!("unknown libvirt option {:?}", key)),
+                    }
+                }
+                Ok(ControllerConfig::Libvirt(libvirt))
+            }
+            "docker" => {
+                let mut docker = Docker::new(required("container")?);
+                for &(key, value) in &options {
+                    match key {
+                        "container" => {}
+                        "host" => docker.host = Some(value.to_string()),
+                        "logs" => {
+                            docker.log_window = value
+                                .parse()
+                                .map_err(|_| format!("bad log window {:?}", value))?
+                        }
+                        _ => return Err(format!("unknown docker option {:?}", key)),
+                    }
+                }
+                Ok(ControllerConfig::Docker(docker))
+            }
+            "shell" => {
+                let mut shell = Shell::default();
+                for &(key, value) in &options {
+                    let slot = match key {
+                        "restart" => &mut shell.restart,
+                        "reboot" => &mut shell.reboot,
+                        "logs" => &mut shell.logs,
+                        "snapshot" => &mut shell.snapshot,
+                        _ => return Err(format!("unknown shell option {:?}", key)),
+                    };
+                    *slot = Some(value.to_string());
+                }
+                if shell == Shell::default() {
+                    return Err("shell controller needs at least one command".to_string());
+                }
+                Ok(ControllerConfig::Shell(shell))
+            }
+            _ => Err(format!("unknown controller {:?}", kind)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_specs() {
+        assert_eq!("none".parse(), Ok(ControllerConfig::None));
+        let ssh: ControllerConfig = "ssh,host=root@nfs1,port=2222,service=nfs-kernel-server"
+            .parse()
+            .unwrap();
+        let ControllerConfig::Ssh(ssh) = ssh else {
+            panic!("not ssh")
+        };
+        assert_eq!(
+            (ssh.host.as_str(), ssh.port, ssh.service.as_str()),
+            ("root@nfs1", Some(2222), "nfs-kernel-server")
+        );
+        assert_eq!(
+            "libvirt,domain=nfs1,uri=qemu:///system".parse(),
+            Ok(ControllerConfig::Libvirt(Libvirt {
+                domain: "nfs1".to_string(),
+                uri: Some("qemu:///system".to_string()),
+                console_log: None,
+            }))
+        );
+        assert!("ssh".parse::<ControllerConfig>().is_err());
+        assert!("ssh,host=h,colour=red".parse::<ControllerConfig>().is_err());
+        assert!("vagrant".parse::<ControllerConfig>().is_err());
+        assert!("docker".parse::<ControllerConfig>().is_err());
+        assert!("shell".parse::<ControllerConfig>().is_err());
+        assert!("shell,restart=true,halt=true"
+            .parse::<ControllerConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut ssh = Ssh::new("root@nfs1");
+        ssh.port = Some(2222);
+        assert_eq!(
+            describe(&ssh.command("systemctl restart nfs-server")),
+            "ssh -o BatchMode=yes -o ConnectTimeout=10 -p 2222 root@nfs1 -- systemctl restart nfs-server"
+        );
+        let libvirt = Libvirt {
+            uri: Some("qemu:///system".to_string()),
+            ..Libvirt::new("nfs1")
+        };
+        assert_eq!(
+            describe(&libvirt.command("snapshot-create-as", &["--name", "s"])),
+            "virsh -c qemu:///system snapshot-create-as nfs1 --name s"
+        );
+        let docker = Docker {
+            host: Some("ssh://root@nfs1".to_string()),
+            ..Docker::new("nfsd")
+        };
+        assert_eq!(
+            describe(&docker.command("logs", &["--since", "300s"])),
+            "docker -H ssh://root@nfs1 logs --since 300s nfsd"
+        );
+        let none = ControllerConfig::None.controller();
+        assert_eq!(none.name(), "none");
+        assert!(matches!(
+            none.reboot(),
+            Err(ControllerError::Unsupported { .. })
+        ));
+    }
+
+    #[test]
+    fn test_shell_and_docker_hooks() {
+        let ControllerConfig::Docker(docker) = "docker,container=nfsd,logs=60".parse().unwrap()
+        else {
+            panic!("not docker")
+        };
+        assert_eq!((docker.container.as_str(), docker.log_window), ("nfsd", 60));
+        let ControllerConfig::Ssh(ssh) = "ssh,host=nfs1,restart=/opt/restart-nfs".parse().unwrap()
+        else {
+            panic!("not ssh")
+        };
+        assert_eq!(ssh.restart.as_deref(), Some("/opt/restart-nfs"));
+
+        let config: ControllerConfig = "shell,restart=echo restarted,logs=printf 'oops'"
+            .parse()
+            .unwrap();
+        let shell = config.controller();
+        assert_eq!(shell.name(), "shell");
+        assert!(shell.restart_service().is_ok());
+        assert_eq!(shell.collect_logs().unwrap(), b"oops");
+        assert!(matches!(
+            shell.reboot(),
+            Err(ControllerError::Unsupported { .. })
+        ));
+        let failing: ControllerConfig = "shell,restart=exit 3".parse().unwrap();
+        assert!(matches!(
+            failing.controller().restart_service(),
+            Err(ControllerError::Failed { status, .. }) if status.code() == Some(3)
+        ));
+    }
+}
@@ -0,0 +1,348 @@
+//! Corpus entries and queries over them
+//!
+//! An entry is a record-marked call `<stem>.bin` with a `<stem>.json`
+//! note beside it, the layout `--generate-only` writes. The note carries
+//! the entry's lineage and its tags: the procedure it exercises, the
+//! strategies that made it, and the behaviors it was seen to trigger.
+//! Behaviors accumulate as campaigns replay the entry, so a corpus of
+//! hundreds of thousands of entries can still be searched for the ones
+//! that hang a server or draw a particular error.
+
+use crate::generate::Input;
+use crate::lineage::Lineage;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CorpusError {
+    #[error("{path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{path}: {source}")]
+    Json {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("no corpus entry {0}")]
+    NoEntry(String),
+}
+
+fn io(path: &Path) -> impl FnOnce(std::io::Error) -> CorpusError + '_ {
+    move |source| CorpusError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+/// What an entry exercises, how it was made, and what it did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tags {
+    /// `v3:GETATTR`, `v4:COMPOUND`, ...
+    pub procedure: String,
+    /// The seed's origin (`seed` for built-in seeds), then every mutation
+    /// strategy in order of first use
+    pub strategies: Vec<String>,
+    /// Reply statuses and oracle verdicts observed (`NFS3ERR_INVAL`,
+    /// `timeout`, `crash`, ...)
+    #[serde(default)]
+    pub behaviors: BTreeSet<String>,
+}
+
+impl Tags {
+    pub fn of(input: &Input) -> Self {
+        let procedure = match input.version {
+            4 => "v4:COMPOUND".to_string(),
+            v => format!("v{}:{}", v, input.name),
+        };
+        let origin = match input.lineage.seed.split_once(':') {
+            Some((o, _)) if !matches!(o, "v3" | "v4") => o,
+            _ => "seed",
+        };
+        let mut strategies = vec![origin.to_string()];
+        strategies.extend(input.lineage.strategies().into_iter().map(String::from));
+        Self {
+            procedure,
+            strategies,
+            behaviors: BTreeSet::new(),
+        }
+    }
+}
+
+/// The `.json` note of an entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Meta {
+    pub name: String,
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    pub lineage: Lineage,
+    /// Notes written before tagging existed have none
+    #[serde(default)]
+    pub tags: Tags,
+}
+
+impl Meta {
+    pub fn of(input: &Input) -> Self {
+        Self {
+            name: input.name.clone(),
+            program: input.program,
+            version: input.version,
+            procedure: input.procedure,
+            lineage: input.lineage.clone(),
+            tags: Tags::of(input),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub stem: String,
+    pub meta: Meta,
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let behaviors: Vec<&str> = self
+            .meta
+            .tags
+            .behaviors
+            .iter()
+            .map(String::as_str)
+            .collect();
+        write!(
+            f,
+            "{} {} [{}] {}",
+            self.stem,
+            self.meta.tags.procedure,
+            self.meta.tags.strategies.join(","),
+            self.meta.lineage
+        )?;
+        if !behaviors.is_empty() {
+            write!(f, " => {}", behaviors.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Entry filters; unset fields match everything, names match without
+/// regard to case
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub version: Option<u32>,
+    /// Procedure tag (`v3:READ`) or its name alone (`READ`), or the
+    /// entry name
+    pub procedure: Option<String>,
+    pub strategy: Option<String>,
+    pub behavior: Option<String>,
+}
+
+impl Query {
+    pub fn matches(&self, meta: &Meta) -> bool {
+        let eq = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+        let tags = &meta.tags;
+        self.version.is_none_or(|v| v == meta.version)
+            && self.procedure.as_deref().is_none_or(|p| {
+                eq(p, &tags.procedure)
+                    || tags
+                        .procedure
+                        .split_once(':')
+                        .is_some_and(|(_, n)| eq(p, n))
+                    || eq(p, &meta.name)
+            })
+            && self
+                .strategy
+                .as_deref()
+                .is_none_or(|s| tags.strategies.iter().any(|t| eq(s, t)))
+            && self
+                .behavior
+                .as_deref()
+                .is_none_or(|b| tags.behaviors.iter().any(|t| eq(b, t)))
+    }
+}
+
+/// A corpus directory
+#[derive(Debug, Clone)]
+pub struct Corpus {
+    dir: PathBuf,
+}
+
+impl Corpus {
+    pub fn open(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn meta_path(&self, stem: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", stem))
+    }
+
+    fn call_path(&self, stem: &str) -> PathBuf {
+        self.dir.join(format!("{}.bin", stem))
+    }
+
+    /// Write `input` as entry `stem`
+    pub fn write(&self, stem: &str, input: &Input, call: &[u8]) -> Result<(), CorpusError> {
+        std::fs::create_dir_all(&self.dir).map_err(io(&self.dir))?;
+        let path = self.call_path(stem);
+        std::fs::write(&path, call).map_err(io(&path))?;
+        self.save(stem, &Meta::of(input))
+    }
+
+    fn save(&self, stem: &str, meta: &Meta) -> Result<(), CorpusError> {
+        let path = self.meta_path(stem);
+        let json = serde_json::to_vec_pretty(meta).map_err(|source| CorpusError::Json {
+            path: path.clone(),
+            source,
+        })?;
+        std::fs::write(&path, json).map_err(io(&path))
+    }
+
+    pub fn load(&self, stem: &str) -> Result<Meta, CorpusError> {
+        let path = self.meta_path(stem);
+        let text = match std::fs::read_to_string(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(CorpusError::NoEntry(stem.to_string()))
+            }
+            r => r.map_err(io(&path))?,
+        };
+        serde_json::from_str(&text).map_err(|source| CorpusError::Json { path, source })
+    }
+
+    /// Every entry, notes without a call beside them skipped, by stem
+    pub fn entries(&self) -> Result<Vec<Entry>, CorpusError> {
+        let mut stems = Vec::new();
+        for dirent in std::fs::read_dir(&self.dir).map_err(io(&self.dir))? {
+            let path = dirent.map_err(io(&self.dir))?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    if self.call_path(stem).is_file() {
+                        stems.push(stem.to_string());
+                    }
+                }
+            }
+        }
+        stems.sort();
+        stems
+            .into_iter()
+            .map(|stem| {
+                let meta = self.load(&stem)?;
+                Ok(Entry { stem, meta })
+            })
+            .collect()
+    }
+
+    /// Entries matching `query`
+    pub fn query(&self, query: &Query) -> Result<Vec<Entry>, CorpusError> {
+        let mut entries = self.entries()?;
+        entries.retain(|e| query.matches(&e.meta));
+        Ok(entries)
+    }
+
+    /// Record that entry `stem` showed `behavior`; false if it already had
+    pub fn add_behavior(&self, stem: &str, behavior: &str) -> Result<bool, CorpusError> {
+        let mut meta = self.load(stem)?;
+        if !meta.tags.behaviors.insert(behavior.to_string()) {
+            return Ok(false);
+        }
+        self.save(stem, &meta)?;
+        Ok(true)
+    }
+
+    /// Copy `entries` into the corpus directory `to`, e.g. as the seeds
+    /// of another campaign
+    pub fn extract(&self, entries: &[Entry], to: &Path) -> Result<(), CorpusError> {
+        std::fs::create_dir_all(to).map_err(io(to))?;
+        for entry in entries {
+            for from in [self.call_path(&entry.stem), self.meta_path(&entry.stem)] {
+                let dest = to.join(from.file_name().unwrap());
+                std::fs::copy(&from, &dest).map_err(io(&from))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+    use crate::generate;
+    use crate::preset::Strategy;
+
+    #[test]
+    fn test_tags_and_query() {
+        let inputs = generate::generate(&[Strategy::Referrals], 200, 3).unwrap();
+        let getattr = Meta::of(&inputs[1]);
+        assert_eq!(getattr.tags.procedure, "v3:GETATTR");
+        assert_eq!(getattr.tags.strategies, ["seed"]);
+        let referral = inputs
+            .iter()
+            .map(Meta::of)
+            .find(|m| m.lineage.seed.starts_with("Referrals:"))
+            .unwrap();
+        assert_eq!(referral.tags.procedure, "v4:COMPOUND");
+        assert_eq!(referral.tags.strategies, ["Referrals"]);
+        let mutated = Meta::of(inputs.last().unwrap());
+        assert!(mutated.tags.strategies.len() > 1);
+
+        let q = |procedure: &str| Query {
+            procedure: Some(procedure.into()),
+            ..Query::default()
+        };
+        assert!(q("getattr").matches(&getattr));
+        assert!(q("V3:GETATTR").matches(&getattr));
+        assert!(!q("read").matches(&getattr));
+        assert!(q("compound").matches(&referral));
+        let by_strategy = Query {
+            version: Some(4),
+            strategy: Some("referrals".into()),
+            ..Query::default()
+        };
+        assert!(by_strategy.matches(&referral) && !by_strategy.matches(&getattr));
+        assert!(!Query {
+            behavior: Some("timeout".into()),
+            ..Query::default()
+        }
+        .matches(&getattr));
+    }
+
+    #[test]
+    fn test_corpus_dir() {
+        let base = std::env::temp_dir().join(format!("nfs-fuzzer-corpus-{}", std::process::id()));
+        let inputs = generate::generate(&[], 3, 1).unwrap();
+        let dir = generate::write_cases(&base, &inputs, &Identity::new(0, 0)).unwrap();
+        std::fs::write(dir.join("stray.json"), "{}").unwrap();
+        let corpus = Corpus::open(&dir);
+        let entries = corpus.entries().unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].stem, "000001_GETATTR");
+
+        assert!(corpus.add_behavior("000001_GETATTR", "timeout").unwrap());
+        assert!(!corpus.add_behavior("000001_GETATTR", "timeout").unwrap());
+        assert!(matches!(
+            corpus.add_behavior("nope", "timeout"),
+            Err(CorpusError::NoEntry(_))
+        ));
+        let hung = corpus
+            .query(&Query {
+                behavior: Some("TIMEOUT".into()),
+                ..Query::default()
+            })
+            .unwrap();
+        assert_eq!(hung.len(), 1);
+        assert_eq!(
+            hung[0].to_string(),
+            "000001_GETATTR v3:GETATTR [seed] v3:GETATTR => timeout"
+        );
+
+        let out = base.join("hangs");
+        corpus.extract(&hung, &out).unwrap();
+        let extracted = Corpus::open(&out).entries().unwrap();
+        assert_eq!(extracted, hung);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,148 @@
+//! Filehandle reuse across protocol versions
+//!
+//! Many servers mint v3 and v4 handles from the same export and inode
+//! data, sometimes in the same format, and decide which version a handle
+//! belongs to from its length or a header byte. Handles harvested over
+//! MOUNT/NFSv3 are replayed in v4 PUTFH and v4 handles in v3 procedures,
+//! as they are and reshaped to the other version's size limits, to find
+//! servers that share a handle namespace across versions or fail to
+//! validate handles from the other one.
+
+use crate::auth::Identity;
+use crate::nfsv3;
+use crate::nfsv4::{attr, bitmap_from_bits, getattr, getfh, lookup, lookupp, putfh, FuzzCase};
+use bytes::BytesMut;
+
+/// NFS3_FHSIZE
+pub const NFS3_FHSIZE: usize = nfsv3::FHSIZE;
+/// NFS4_FHSIZE
+pub const NFS4_FHSIZE: usize = 128;
+
+/// `fh` as harvested, then resized to the limits of the version it is
+/// replayed into (`limit`) and with its first byte (often a version or
+/// type tag) changed
+pub fn handle_variants(fh: &[u8], limit: usize) -> Vec<(&'static str, Vec<u8>)> {
+    let mut out = vec![("as_is", fh.to_vec())];
+    let mut push = |label, v: Vec<u8>| {
+        if !out.iter().any(|(_, o)| *o == v) {
+            out.push((label, v));
+        }
+    };
+    let mut padded = fh.to_vec();
+    padded.resize(limit, 0);
+    push("zero_padded", padded);
+    let mut over = fh.to_vec();
+    over.resize(limit + 1, 0);
+    push("over_limit", over);
+    if fh.len() > 1 {
+        push("truncated", fh[..fh.len() / 2].to_vec());
+    }
+    if let Some(&first) = fh.first() {
+        for (label, tag) in [
+            ("tag_plus_1", first.wrapping_add(1)),
+            ("tag_flipped", !first),
+        ] {
+            let mut v = fh.to_vec();
+            v[0] = tag;
+            push(label, v);
+        }
+    }
+    out
+}
+
+/// v4 cases on each v3 handle: PUTFH followed by ops that read the
+/// object, its name space and its handle back
+pub fn v3_handles_in_v4(handles: &[Vec<u8>]) -> Vec<FuzzCase> {
+    let mask = bitmap_from_bits(&[attr::TYPE, attr::SIZE, attr::FSID, attr::FILEHANDLE]);
+    let mut cases = Vec::new();
+    for (i, fh) in handles.iter().enumerate() {
+        for (label, v) in handle_variants(fh, NFS4_FHSIZE) {
+            let name = |op: &str| format!("v3fh{}_{}_{}", i, label, op);
+            cases.push(FuzzCase::new(
+                name("getattr"),
+                vec![putfh(&v), getattr(&mask)],
+            ));
+            cases.push(FuzzCase::new(name("getfh"), vec![putfh(&v), getfh()]));
+            cases.push(FuzzCase::new(
+                name("lookupp"),
+                vec![putfh(&v), lookupp(), getfh()],
+            ));
+            cases.push(FuzzCase::new(
+                name("lookup"),
+                vec![putfh(&v), lookup(b"."), lookup(b".."), getfh()],
+            ));
+        }
+    }
+    cases
+}
+
+/// v3 calls on each v4 handle (GETFH results), including handles past
+/// NFS3_FHSIZE that a v3 decoder must reject
+pub fn v4_handles_in_v3(identity: &Identity, handles: &[Vec<u8>]) -> Vec<(String, BytesMut)> {
+    let mut calls = Vec::new();
+    for (i, fh) in handles.iter().enumerate() {
+        for (label, v) in handle_variants(fh, NFS3_FHSIZE) {
+            let name = |p: &str| format!("v4fh{}_{}_{}", i, label, p);
+            let verf = [0; nfsv3::VERFSIZE];
+            for (p, call) in [
+                ("getattr", nfsv3::getattr(&v)),
+                ("access", nfsv3::access(&v, nfsv3::access::ALL)),
+                ("lookup_dotdot", nfsv3::lookup(&v, b"..")),
+                ("readdir", nfsv3::readdir(&v, 0, &verf, 4096)),
+                ("fsstat", nfsv3::fsstat(&v)),
+            ] {
+                calls.push((name(p), call.message(identity)));
+            }
+        }
+    }
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::op;
+
+    #[test]
+    fn test_handle_variants() {
+        let fh: Vec<u8> = (1..=28).collect();
+        let v = handle_variants(&fh, NFS3_FHSIZE);
+        let labels: Vec<&str> = v.iter().map(|(l, _)| *l).collect();
+        assert_eq!(
+            labels,
+            [
+                "as_is",
+                "zero_padded",
+                "over_limit",
+                "truncated",
+                "tag_plus_1",
+                "tag_flipped"
+            ]
+        );
+        assert_eq!(v[1].1.len(), 64);
+        assert_eq!(v[2].1.len(), 65);
+        assert_eq!(v[5].1[0], 0xfe);
+        // A handle already at the limit is not padded again
+        assert_eq!(handle_variants(&[7; 64], NFS3_FHSIZE).len(), 5);
+        assert_eq!(handle_variants(&[], NFS4_FHSIZE).len(), 3);
+    }
+
+    #[test]
+    fn test_cross_version_cases() {
+        let handles = vec![vec![1u8; 32]];
+        let v4 = v3_handles_in_v4(&handles);
+        assert!(v4.iter().all(|c| c.ops[0].opcode == op::PUTFH));
+        assert_eq!(
+            v4.len(),
+            4 * handle_variants(&handles[0], NFS4_FHSIZE).len()
+        );
+        assert_eq!(v4[0].name, "v3fh0_as_is_getattr");
+
+        let v3 = v4_handles_in_v3(&Identity::new(0, 0), &[vec![2u8; 100]]);
+        let (name, msg) = v3.iter().find(|(n, _)| n == "v4fh0_as_is_getattr").unwrap();
+        assert!(name.ends_with("getattr"));
+        // Procedure, then the 100-byte handle as the whole argument
+        assert_eq!(&msg[24..28], &[0, 0, 0, 1]);
+        assert_eq!(&msg[msg.len() - 104..msg.len() - 100], &[0, 0, 0, 100]);
+    }
+}
@@ -0,0 +1,327 @@
+//! SQLite results database
+//!
+//! A campaign of millions of cases leaves a results log too long to grep
+//! and findings spread over thousands of directories. With `--db`, every
+//! case (as [`crate::results`] records it) and every finding also goes
+//! into an SQLite database, indexed by procedure, status and campaign,
+//! with a `procedure_stats` view of counts and latencies per procedure
+//! and status. Several campaigns can share one database; each row
+//! carries its campaign id. `stats db` prints the summary or runs any
+//! query against it.
+//!
+//! The database is written through SQLite linked into the fuzzer, with
+//! prepared statements committed every [`BATCH`] rows. A write that
+//! fails, say because another process holds the database locked past
+//! the busy timeout, ends the database's part in the campaign with one
+//! warning; the fuzz loop drops it rather than failing every case.
+
+use crate::fuzz::{Finding, FindingKind};
+use crate::results::Record;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Batch, Connection, OpenFlags};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tracing::warn;
+
+/// Statements per transaction
+pub const BATCH: usize = 1000;
+
+/// Tables, indexes and views, created if missing
+pub const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS iterations (
+    id INTEGER PRIMARY KEY,
+    campaign TEXT NOT NULL,
+    timestamp REAL NOT NULL,
+    xid INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    program INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    procedure INTEGER NOT NULL,
+    strategy TEXT,
+    request BLOB NOT NULL,
+    status TEXT NOT NULL,
+    latency_ms REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS iterations_procedure ON iterations (program, version, procedure);
+CREATE INDEX IF NOT EXISTS iterations_status ON iterations (status);
+CREATE INDEX IF NOT EXISTS iterations_campaign ON iterations (campaign, timestamp);
+CREATE TABLE IF NOT EXISTS findings (
+    id INTEGER PRIMARY KEY,
+    campaign TEXT NOT NULL,
+    timestamp REAL NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    path TEXT NOT NULL,
+    signature TEXT,
+    cases INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS findings_kind ON findings (kind);
+CREATE INDEX IF NOT EXISTS findings_signature ON findings (signature);
+CREATE VIEW IF NOT EXISTS procedure_stats AS
+    SELECT campaign, name, status, COUNT(*) AS count,
+        ROUND(AVG(latency_ms), 3) AS mean_ms, ROUND(MAX(latency_ms), 3) AS max_ms
+    FROM iterations GROUP BY campaign, name, status;
+";
+
+/// What `stats db` prints without a query of its own
+pub const SUMMARY: &str = "\
+SELECT name, status, SUM(count) AS count, ROUND(SUM(mean_ms * count) / SUM(count), 3) AS mean_ms,
+    MAX(max_ms) AS max_ms
+FROM procedure_stats GROUP BY name, status ORDER BY name, count DESC;
+SELECT kind, COUNT(*) AS findings, COUNT(DISTINCT signature) AS behaviors
+FROM findings GROUP BY kind ORDER BY kind;
+";
+
+/// How long a write waits for another process's lock on the database
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const INSERT_ITERATION: &str = "\
+INSERT INTO iterations (campaign, timestamp, xid, name, program, version, procedure, strategy,
+    request, status, latency_ms)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)";
+
+const INSERT_FINDING: &str = "\
+INSERT INTO findings (campaign, timestamp, kind, name, path, signature, cases)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)";
+
+#[derive(Error, Debug)]
+pub enum DbError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+}
+
+/// The name findings of `kind` are stored under: `crash`, `kernel`,
+/// `hang:request`, `hang:server` or `oracle:` and the oracle's name
+pub fn kind_name(kind: FindingKind) -> String {
+    match kind {
+        FindingKind::Crash => "crash".to_string(),
+        FindingKind::Kernel => "kernel".to_string(),
+        FindingKind::Hang(hang) => match serde_json::to_value(hang) {
+            Ok(serde_json::Value::String(s)) => format!("hang:{}", s),
+            _ => "hang".to_string(),
+        },
+        FindingKind::Oracle(oracle) => match serde_json::to_value(oracle) {
+            Ok(serde_json::Value::String(s)) => format!("oracle:{}", s),
+            _ => "oracle".to_string(),
+        },
+    }
+}
+
+/// A database open for writing
+#[derive(Debug)]
+pub struct ResultsDb {
+    campaign: String,
+    conn: Connection,
+    /// Rows since the last commit
+    pending: usize,
+}
+
+impl ResultsDb {
+    /// Open `path`, creating the database and its tables if need be, for
+    /// the rows of `campaign`
+    pub fn open(path: &Path, campaign: &str) -> Result<Self, DbError> {
+        let conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN;")?;
+        Ok(Self {
+            campaign: campaign.to_string(),
+            conn,
+            pending: 0,
+        })
+    }
+
+    fn inserted(&mut self) -> Result<(), DbError> {
+        self.pending += 1;
+        if self.pending >= BATCH {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Commit the rows so far and start a new transaction
+    pub fn commit(&mut self) -> Result<(), DbError> {
+        self.conn.execute_batch("COMMIT; BEGIN;")?;
+        self.pending = 0;
+        Ok(())
+    }
+
+    pub fn iteration(&mut self, record: &Record) -> Result<(), DbError> {
+        self.conn
+            .prepare_cached(INSERT_ITERATION)?
+            .execute(params![
+                self.campaign,
+                record.timestamp,
+                record.xid,
+                record.name,
+                record.program,
+                record.version,
+                record.procedure,
+                record.strategy,
+                record.request,
+                record.status,
+                record.latency_ms,
+            ])?;
+        self.inserted()
+    }
+
+    /// Record `finding`, committing at once so it survives the fuzzer
+    pub fn finding(&mut self, finding: &Finding) -> Result<(), DbError> {
+        self.conn.prepare_cached(INSERT_FINDING)?.execute(params![
+            self.campaign,
+            now(),
+            kind_name(finding.kind),
+            finding.name,
+            finding.path.to_string_lossy(),
+            finding.signature.as_ref().map(|s| s.id()),
+            finding.inputs.len(),
+        ])?;
+        self.inserted()?;
+        self.commit()
+    }
+
+    /// Commit what is pending
+    pub fn close(&mut self) -> Result<(), DbError> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT;")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ResultsDb {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() {
+            warn!("Could not finish the results database: {}", e);
+        }
+    }
+}
+
+fn cell(value: ValueRef<'_>) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(n) => n.to_string(),
+        ValueRef::Real(x) => x.to_string(),
+        ValueRef::Text(text) => String::from_utf8_lossy(text).into_owned(),
+        ValueRef::Blob(blob) => hex::encode(blob),
+    }
+}
+
+/// `rows` under `names`, in columns padded to their widest cell, the
+/// header underlined
+fn columns(names: &[String], rows: &[Vec<String>]) -> String {
+    let widths: Vec<usize> = (0..names.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([names[i].chars().count()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let line = |cells: &[String]| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, &w)| format!("{:<w$}", c, w = w))
+            .collect();
+        format!("{}\n", padded.join("  ").trim_end())
+    };
+    let rule: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+    let mut out = line(names);
+    out.push_str(&line(&rule));
+    rows.iter().for_each(|row| out.push_str(&line(row)));
+    out
+}
+
+/// The output of `sql`, one or more statements, run against the
+/// database at `path` opened read-only: each statement's rows in
+/// columns under their headers
+pub fn query(path: &Path, sql: &str) -> Result<String, DbError> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut out = String::new();
+    let mut batch = Batch::new(&conn, sql);
+    while let Some(mut statement) = batch.next()? {
+        let names: Vec<String> = statement
+            .column_names()
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let mut rows = Vec::new();
+        let mut results = statement.query([])?;
+        while let Some(row) = results.next()? {
+            rows.push(
+                (0..names.len())
+                    .map(|i| row.get_ref(i).map(cell))
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if !names.is_empty() {
+            out.push_str(&columns(&names, &rows));
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hang::HangKind;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_rows_and_summary() {
+        let path =
+            std::env::temp_dir().join(format!("nfs-fuzzer-db-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let record = Record {
+            timestamp: 1760000000.25,
+            xid: 0x1234,
+            name: "v3:GETATTR".to_string(),
+            program: 100003,
+            version: 3,
+            procedure: 1,
+            strategy: None,
+            request: vec![0, 0, 0x12, 0x34],
+            status: "NFS3ERR_70".to_string(),
+            latency_ms: 1.5,
+        };
+        let finding = Finding {
+            kind: FindingKind::Hang(HangKind::Server),
+            name: "it's".to_string(),
+            path: PathBuf::from("out/hangs/00000009_v3:READ.bin"),
+            inputs: Vec::new(),
+            reproduction: None,
+            minimized: None,
+            signature: None,
+        };
+        assert_eq!(kind_name(finding.kind), "hang:server");
+        let mut db = ResultsDb::open(&path, "c'1").unwrap();
+        db.iteration(&record).unwrap();
+        db.iteration(&record).unwrap();
+        db.finding(&finding).unwrap();
+        drop(db);
+
+        let summary = query(&path, SUMMARY).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "name        status      count  mean_ms  max_ms");
+        assert_eq!(lines[2], "v3:GETATTR  NFS3ERR_70  2      1.5      1.5");
+        assert_eq!(lines[5], "hang:server  1         0");
+        let rows = query(
+            &path,
+            "SELECT campaign, name, request FROM iterations LIMIT 1",
+        )
+        .unwrap();
+        assert!(rows.ends_with("c'1       v3:GETATTR  00001234\n"));
+        assert!(query(&path, "DELETE FROM findings").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Export paths with special characters (RFC 1813 §5.2.5, §5.2.1)
+//!
+//! MOUNT's EXPORT reply carries directory paths as opaque strings, and
+//! nothing stops them from holding spaces, UTF-8 or newlines. Recon keeps
+//! them as raw bytes and only escapes them for display, so a path lists
+//! the same way it goes back out in MNT. The same paths are then sent
+//! back re-encoded the ways naive parsers (exports files, shell scripts,
+//! lossy string conversion) would mangle them.
+
+use crate::auth::Identity;
+use crate::mount;
+use crate::xdr::{XdrDecoder, XdrError};
+use bytes::BytesMut;
+use thiserror::Error;
+
+/// MNTPATHLEN
+pub const MNTPATHLEN: usize = 1024;
+
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum ExportsError {
+    #[error("export list truncated at offset {0}")]
+    Truncated(usize),
+}
+
+/// One exportnode: path and the groups allowed to mount it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export {
+    pub dir: Vec<u8>,
+    pub groups: Vec<Vec<u8>>,
+}
+
+impl Export {
+    /// The path as it appears in logs and reports, see [`display_path`]
+    pub fn display(&self) -> String {
+        display_path(&self.dir)
+    }
+}
+
+impl From<XdrError> for ExportsError {
+    fn from(e: XdrError) -> Self {
+        ExportsError::Truncated(e.offset())
+    }
+}
+
+/// Decode the result of MOUNTPROC3_EXPORT (a linked list of exportnodes)
+pub fn decode_export_list(buf: &[u8]) -> Result<Vec<Export>, ExportsError> {
+    let mut r = XdrDecoder::new(buf);
+    let mut exports = Vec::new();
+    while r.get_u32()? != 0 {
+        let dir = r.get_opaque()?.to_vec();
+        let mut groups = Vec::new();
+        while r.get_u32()? != 0 {
+            groups.push(r.get_opaque()?.to_vec());
+        }
+        exports.push(Export { dir, groups });
+    }
+    Ok(exports)
+}
+
+/// Printable form of a path: valid UTF-8 is kept (spaces included),
+/// control characters, backslashes and invalid bytes are escaped, and
+/// the result is quoted when it has leading or trailing spaces
+pub fn display_path(path: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in path.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c.is_control() || c == '\\' || c == '"' {
+                out.extend(c.escape_default());
+            } else {
+                out.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", b));
+        }
+    }
+    if out.starts_with(' ') || out.ends_with(' ') || out.is_empty() {
+        format!("\"{}\"", out)
+    } else {
+        out
+    }
+}
+
+/// Paths with characters naive server-side parsers trip over, probed
+/// even when the export list holds none
+pub fn special_paths() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("space", b"/export/with space".to_vec()),
+        ("trailing_space", b"/export ".to_vec()),
+        ("tab", b"/export\tdir".to_vec()),
+        ("newline", b"/export\n/etc".to_vec()),
+        ("crlf", b"/export\r\n".to_vec()),
+        ("utf8", "/export/caf\u{e9}".as_bytes().to_vec()),
+        ("utf8_decomposed", "/export/cafe\u{301}".as_bytes().to_vec()),
+        ("utf8_bom", "\u{feff}/export".as_bytes().to_vec()),
+        ("invalid_utf8", b"/export/\xff\xfe".to_vec()),
+        ("overlong_slash", b"/export\xc0\xafetc".to_vec()),
+        ("nul", b"/export\0/etc".to_vec()),
+        ("mntpathlen", vec![b'/'; MNTPATHLEN]),
+        ("mntpathlen_plus_1", vec![b'/'; MNTPATHLEN + 1]),
+    ]
+}
+
+/// `dir` as given, then mangled the ways naive code does: cut at the
+/// first whitespace, split at newlines, escaped as in /etc/exports or a
+/// shell, lossily converted, or double-encoded as Latin-1
+pub fn mnt_path_variants(dir: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let mut out = vec![("as_given".to_string(), dir.to_vec())];
+    let mut push = |label: &str, path: Vec<u8>| {
+        if !out.iter().any(|(_, p)| *p == path) {
+            out.push((label.to_string(), path));
+        }
+    };
+    if let Some(i) = dir.iter().position(u8::is_ascii_whitespace) {
+        push("cut_at_space", dir[..i].to_vec());
+    }
+    for (i, line) in dir.split(|&b| b == b'\n').enumerate().skip(1) {
+        push(&format!("line{}", i), line.to_vec());
+    }
+    // exports(5) writes whitespace as \040-style octal escapes
+    let octal = dir.iter().fold(Vec::new(), |mut v, &b| {
+        if b.is_ascii_whitespace() || b == b'\\' {
+            v.extend_from_slice(format!("\\{:03o}", b).as_bytes());
+        } else {
+            v.push(b);
+        }
+        v
+    });
+    push("octal_escaped", octal);
+    let shell = dir.iter().fold(Vec::new(), |mut v, &b| {
+        if b == b' ' {
+            v.push(b'\\');
+        }
+        v.push(b);
+        v
+    });
+    push("backslash_escaped", shell);
+    let mut quoted = b"\"".to_vec();
+    quoted.extend_from_slice(dir);
+    quoted.push(b'"');
+    push("quoted", quoted);
+    push(
+        "lossy_utf8",
+        String::from_utf8_lossy(dir).into_owned().into_bytes(),
+    );
+    let latin1: String = dir.iter().map(|&b| b as char).collect();
+    push("latin1_double_encoded", latin1.into_bytes());
+    let percent = dir.iter().fold(String::new(), |mut s, &b| {
+        if b.is_ascii_graphic() && b != b'%' {
+            s.push(b as char);
+        } else {
+            s.push_str(&format!("%{:02X}", b));
+        }
+        s
+    });
+    push("percent_encoded", percent.into_bytes());
+    let mut trailing = dir.to_vec();
+    trailing.push(b'\n');
+    push("trailing_newline", trailing);
+    out
+}
+
+/// MNT calls for every variant of every export path and of
+/// [`special_paths`]
+pub fn mnt_calls(identity: &Identity, exports: &[Export]) -> Vec<(String, BytesMut)> {
+    let mut calls = Vec::new();
+    let known = exports
+        .iter()
+        .enumerate()
+        .map(|(i, e)| (format!("export{}", i), e.dir.clone()));
+    let special = special_paths()
+        .into_iter()
+        .map(|(label, p)| (label.to_string(), p));
+    for (base, dir) in known.chain(special) {
+        for (label, path) in mnt_path_variants(&dir) {
+            calls.push((
+                format!("mnt_{}_{}", base, label),
+                mount::mnt(&path).message(identity),
+            ));
+        }
+    }
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdr::XdrEncoder;
+
+    #[test]
+    fn test_export_list_keeps_raw_bytes() {
+        let mut enc = XdrEncoder::new();
+        enc.put_bool(true);
+        enc.put_opaque(b"/srv/with space\n");
+        enc.put_bool(true);
+        enc.put_opaque(b"*");
+        enc.put_bool(false);
+        enc.put_bool(true);
+        enc.put_opaque(b"/srv/\xff");
+        enc.put_bool(false);
+        enc.put_bool(false);
+        let exports = decode_export_list(enc.as_bytes()).unwrap();
+        assert_eq!(exports.len(), 2);
+        assert_eq!(exports[0].dir, b"/srv/with space\n");
+        assert_eq!(exports[0].groups, [b"*".to_vec()]);
+        assert_eq!(exports[0].display(), "/srv/with space\\n");
+        assert_eq!(exports[1].display(), "/srv/\\xff");
+        assert_eq!(display_path(b" a"), "\" a\"");
+        assert_eq!(display_path("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        let bytes = enc.as_bytes();
+        assert_eq!(
+            decode_export_list(&bytes[..bytes.len() - 4]),
+            Err(ExportsError::Truncated(bytes.len() - 4))
+        );
+    }
+
+    #[test]
+    fn test_mnt_path_variants() {
+        let variants = mnt_path_variants(b"/a b\nc");
+        let get = |label: &str| {
+            variants
+                .iter()
+                .find(|(l, _)| l == label)
+                .map(|(_, p)| p.as_slice())
+        };
+        assert_eq!(get("as_given"), Some(&b"/a b\nc"[..]));
+        assert_eq!(get("cut_at_space"), Some(&b"/a"[..]));
+        assert_eq!(get("line1"), Some(&b"c"[..]));
+        assert_eq!(get("octal_escaped"), Some(&b"/a\\040b\\012c"[..]));
+        assert_eq!(get("percent_encoded"), Some(&b"/a%20b%0Ac"[..]));
+        // Plain paths only yield the variants that change something
+        let plain = mnt_path_variants(b"/srv");
+        assert!(plain.iter().all(|(l, _)| l != "cut_at_space"));
+        let calls = mnt_calls(&Identity::new(0, 0), &[]);
+        let per: usize = special_paths()
+            .iter()
+            .map(|(_, p)| mnt_path_variants(p).len())
+            .sum();
+        assert_eq!(calls.len(), per);
+    }
+}
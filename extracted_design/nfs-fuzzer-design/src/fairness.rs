@@ -0,0 +1,394 @@
+//! Fair sharing of one campaign between simulated clients
+//!
+//! A multi-client simulation runs several independent case streams
+//! against the same server, each standing for one client with its own
+//! mutation strategy. Left to interleave freely, a client whose strategy
+//! produces cases fastest, or forever, takes over the campaign, and the
+//! other clients' sequences, whose findings often depend on the state an
+//! earlier case of the same client left behind, are spread too thin to
+//! get anywhere. The [`Scheduler`] gives every client a token bucket: a
+//! case costs one token, and every case sent refills each bucket by that
+//! client's share of the total rate, up to a burst. Time is counted in
+//! cases rather than seconds so runs replay the same way whatever the
+//! target's latency. The [`FairnessReport`] afterwards compares what each
+//! client got with what it was due, and which findings were its own.
+
+use crate::fuzz::Finding;
+use crate::generate::Input;
+use crate::mutations::Weights;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Tokens a bucket holds at most, and starts with
+pub const DEFAULT_BURST: f64 = 8.0;
+
+/// Separates a client's name from the case name in the names of the
+/// cases it sent
+pub const SEPARATOR: char = '@';
+
+/// Tokens a client has to spend
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TokenBucket {
+    burst: f64,
+    tokens: f64,
+}
+
+impl TokenBucket {
+    pub fn new(burst: f64) -> Self {
+        Self {
+            burst,
+            tokens: burst,
+        }
+    }
+
+    pub fn refill(&mut self, tokens: f64) {
+        self.tokens = (self.tokens + tokens).min(self.burst);
+    }
+
+    /// Spend a token if there is one
+    pub fn try_take(&mut self) -> bool {
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// One simulated client: `NAME:RATE[:MUTATORS]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimClient {
+    pub name: String,
+    /// Relative to the other clients' rates
+    pub rate: f64,
+    /// Its strategy; the campaign's mutators when unset
+    pub mutators: Option<Weights>,
+}
+
+impl FromStr for SimClient {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let name = parts.next().unwrap_or_default();
+        if name.is_empty() || name.contains(SEPARATOR) || name.contains('/') {
+            return Err(format!("bad client name {:?}", name));
+        }
+        let rate = match parts.next() {
+            None => 1.0,
+            Some(r) => r
+                .parse::<f64>()
+                .ok()
+                .filter(|r| r.is_finite() && *r > 0.0)
+                .ok_or_else(|| format!("rate of {} must be positive, got {:?}", name, r))?,
+        };
+        let mutators = parts.next().map(str::parse).transpose()?;
+        Ok(Self {
+            name: name.to_string(),
+            rate,
+            mutators,
+        })
+    }
+}
+
+/// The client that sent a case named `name`, as the [`Scheduler`] named it
+pub fn client_of(name: &str) -> Option<&str> {
+    name.split_once(SEPARATOR).map(|(client, _)| client)
+}
+
+/// What one client got
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientShare {
+    pub name: String,
+    pub rate: f64,
+    /// Cases it sent
+    pub cases: u64,
+    /// Turns it had a case ready but no token
+    pub throttled: u64,
+    /// Findings whose cases were all its own
+    pub findings: u64,
+}
+
+/// Client shares as the campaign goes, shared between the scheduler and
+/// whoever reports on them
+#[derive(Debug, Clone, Default)]
+pub struct Shares(Arc<Mutex<Vec<ClientShare>>>);
+
+impl Shares {
+    pub fn snapshot(&self) -> Vec<ClientShare> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// A client's case stream, `None` once it ran dry
+type Stream<'a> = Option<Box<dyn Iterator<Item = Input> + 'a>>;
+
+/// Interleaves the case streams of several clients through their token
+/// buckets; cases come out named `<client>@<case>`
+pub struct Scheduler<'a> {
+    clients: Vec<(TokenBucket, Stream<'a>)>,
+    shares: Shares,
+    /// Client whose turn is next
+    next: usize,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new() -> Self {
+        Self {
+            clients: Vec::new(),
+            shares: Shares::default(),
+            next: 0,
+        }
+    }
+
+    pub fn with_client(
+        mut self,
+        name: &str,
+        rate: f64,
+        burst: f64,
+        inputs: impl Iterator<Item = Input> + 'a,
+    ) -> Self {
+        self.clients
+            .push((TokenBucket::new(burst), Some(Box::new(inputs))));
+        self.shares.0.lock().unwrap().push(ClientShare {
+            name: name.to_string(),
+            rate,
+            cases: 0,
+            throttled: 0,
+            findings: 0,
+        });
+        self
+    }
+
+    pub fn shares(&self) -> Shares {
+        self.shares.clone()
+    }
+}
+
+impl Default for Scheduler<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for Scheduler<'_> {
+    type Item = Input;
+
+    fn next(&mut self) -> Option<Input> {
+        let mut shares = self.shares.0.lock().unwrap();
+        let n = self.clients.len();
+        loop {
+            let live: Vec<usize> = (0..n).filter(|&i| self.clients[i].1.is_some()).collect();
+            let total: f64 = live.iter().map(|&i| shares[i].rate).sum();
+            if live.is_empty() {
+                return None;
+            }
+            for &i in &live {
+                self.clients[i].0.refill(shares[i].rate / total);
+            }
+            for k in 0..n {
+                let i = (self.next + k) % n;
+                let (bucket, inputs) = &mut self.clients[i];
+                let Some(stream) = inputs else {
+                    continue;
+                };
+                if !bucket.try_take() {
+                    shares[i].throttled += 1;
+                    continue;
+                }
+                let Some(mut input) = stream.next() else {
+                    *inputs = None;
+                    continue;
+                };
+                self.next = (i + 1) % n;
+                shares[i].cases += 1;
+                input.name = format!("{}{}{}", shares[i].name, SEPARATOR, input.name);
+                return Some(input);
+            }
+        }
+    }
+}
+
+/// Each client's share of a campaign against its due
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FairnessReport {
+    pub clients: Vec<ClientShare>,
+    /// Jain's index of the clients' cases relative to their rates: 1
+    /// when every client got exactly its due, 1/n when one got it all
+    pub index: f64,
+    /// Findings with cases of more than one client
+    pub shared_findings: u64,
+}
+
+impl FairnessReport {
+    pub const FILE: &'static str = "fairness.json";
+
+    /// Report on `shares`, attributing `findings` to the clients whose
+    /// cases led to them
+    pub fn new(shares: &Shares, findings: &[Finding]) -> Self {
+        let mut clients = shares.snapshot();
+        let mut shared_findings = 0;
+        for finding in findings {
+            let mut owners: Vec<&str> = finding
+                .inputs
+                .iter()
+                .filter_map(|i| client_of(&i.name))
+                .collect();
+            owners.sort_unstable();
+            owners.dedup();
+            match owners[..] {
+                [owner] => {
+                    if let Some(c) = clients.iter_mut().find(|c| c.name == owner) {
+                        c.findings += 1;
+                    }
+                }
+                [] => {}
+                _ => shared_findings += 1,
+            }
+        }
+        let x: Vec<f64> = clients.iter().map(|c| c.cases as f64 / c.rate).collect();
+        let (sum, squares) = (x.iter().sum::<f64>(), x.iter().map(|v| v * v).sum::<f64>());
+        let index = if squares == 0.0 {
+            1.0
+        } else {
+            sum * sum / (x.len() as f64 * squares)
+        };
+        Self {
+            clients,
+            index,
+            shared_findings,
+        }
+    }
+
+    pub fn save(&self, dir: &std::path::Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(Self::FILE), serde_json::to_vec_pretty(self)?)
+    }
+}
+
+impl fmt::Display for FairnessReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cases: u64 = self.clients.iter().map(|c| c.cases).sum();
+        let rates: f64 = self.clients.iter().map(|c| c.rate).sum();
+        writeln!(
+            f,
+            "  {:<16} {:>8} {:>8} {:>10} {:>10} {:>8}",
+            "client", "due", "got", "cases", "throttled", "findings"
+        )?;
+        for c in &self.clients {
+            writeln!(
+                f,
+                "  {:<16} {:>7.1}% {:>7.1}% {:>10} {:>10} {:>8}",
+                c.name,
+                100.0 * c.rate / rates,
+                100.0 * c.cases as f64 / cases.max(1) as f64,
+                c.cases,
+                c.throttled,
+                c.findings
+            )?;
+        }
+        if self.shared_findings > 0 {
+            writeln!(
+                f,
+                "  {} findings shared between clients",
+                self.shared_findings
+            )?;
+        }
+        write!(f, "fairness index {:.3}", self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fuzz::FindingKind;
+    use crate::lineage::Lineage;
+    use crate::rpc::program;
+
+    fn stream(name: &'static str) -> impl Iterator<Item = Input> {
+        std::iter::repeat_with(move || Input {
+            name: name.to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure: 0,
+            args: Vec::new(),
+            lineage: Lineage::new(name),
+            auth: None,
+        })
+    }
+
+    #[test]
+    fn test_spec_and_bucket() {
+        let spec: SimClient = "alice:3:havoc=2".parse().unwrap();
+        assert_eq!((spec.name.as_str(), spec.rate), ("alice", 3.0));
+        assert_eq!(spec.mutators, Some(Weights(vec![("havoc", 2)])));
+        assert_eq!("bob".parse::<SimClient>().unwrap().rate, 1.0);
+        assert!("bob:0".parse::<SimClient>().is_err());
+        assert!("a@b:1".parse::<SimClient>().is_err());
+        assert!(":1".parse::<SimClient>().is_err());
+
+        let mut bucket = TokenBucket::new(2.0);
+        assert!(bucket.try_take() && bucket.try_take());
+        assert!(!bucket.try_take());
+        bucket.refill(0.5);
+        assert!(!bucket.try_take());
+        bucket.refill(10.0);
+        assert!(bucket.try_take() && bucket.try_take() && !bucket.try_take());
+        assert_eq!(client_of("alice@v3:NULL"), Some("alice"));
+        assert_eq!(client_of("v3:NULL"), None);
+    }
+
+    #[test]
+    fn test_scheduler_holds_clients_to_their_rates() {
+        let scheduler = Scheduler::new()
+            .with_client("greedy", 3.0, DEFAULT_BURST, stream("flood"))
+            .with_client("slow", 1.0, DEFAULT_BURST, stream("open"))
+            .with_client("short", 1.0, DEFAULT_BURST, stream("lock").take(10));
+        let shares = scheduler.shares();
+        let cases: Vec<Input> = scheduler.take(1000).collect();
+        let count = |c: &str| {
+            cases
+                .iter()
+                .filter(|i| client_of(&i.name) == Some(c))
+                .count()
+        };
+        assert_eq!(count("short"), 10);
+        // The rest split 3:1 once the bursts are spent
+        assert!(
+            (735..=765).contains(&count("greedy")),
+            "{}",
+            count("greedy")
+        );
+        assert_eq!(count("greedy") + count("slow"), 990);
+        // Left without tokens while the greedy client had some
+        assert!(shares.snapshot()[1].throttled > 0);
+
+        let finding = |names: &[&str]| Finding {
+            kind: FindingKind::Crash,
+            name: names[names.len() - 1].to_string(),
+            path: Default::default(),
+            inputs: names
+                .iter()
+                .map(|n| Input {
+                    name: n.to_string(),
+                    ..cases[0].clone()
+                })
+                .collect(),
+            reproduction: None,
+            minimized: None,
+            signature: None,
+        };
+        let findings = [
+            finding(&["slow@open", "slow@open"]),
+            finding(&["slow@open", "greedy@flood"]),
+        ];
+        let report = FairnessReport::new(&shares, &findings);
+        assert_eq!(report.clients[1].findings, 1);
+        assert_eq!(report.shared_findings, 1);
+        // "short" ran dry, so the index is below 1 but far from 1/3
+        assert!(report.index > 0.6 && report.index < 1.0, "{}", report.index);
+        assert!(report.to_string().contains("1 findings shared"));
+    }
+}
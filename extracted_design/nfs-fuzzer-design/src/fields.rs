@@ -0,0 +1,285 @@
+//! Annotated XDR messages
+//!
+//! Byte mutators see a payload as a flat buffer, and most of what they
+//! make dies in the server's decoder at the first length that no longer
+//! matches its body. A [`Message`] keeps the bytes together with the
+//! fields they encode: where every integer, length header, array count,
+//! union discriminant and opaque body sits, so a mutation can change one
+//! field and say which, such as a length header set to 0xFFFFFFFF over a
+//! body that stays four bytes long.
+//!
+//! [`Annotated`] wraps [`XdrEncoder`] and records each field as it is
+//! written. Payloads built without it, which is most of them, get a
+//! layout from [`Message::infer`].
+
+use crate::xdr::{xdr_pad_len, XdrEncoder};
+use std::ops::Range;
+
+/// What a field encodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// A 32-bit integer, enum or bool
+    Word,
+    /// A 64-bit integer
+    Hyper,
+    /// The byte length in front of a variable-length opaque or string
+    Length,
+    /// The element count in front of a variable-length array
+    Count,
+    /// The discriminant of a union
+    Discriminant,
+    /// Opaque or string bytes, without their padding
+    Bytes,
+}
+
+/// One field of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    /// Dotted path of the field, e.g. `op1.args`
+    pub name: String,
+    pub kind: Kind,
+    pub offset: usize,
+    pub len: usize,
+}
+
+impl Field {
+    pub fn range(&self) -> Range<usize> {
+        self.offset..self.offset + self.len
+    }
+}
+
+/// Encoded bytes and the fields they hold, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub bytes: Vec<u8>,
+    pub fields: Vec<Field>,
+}
+
+impl Message {
+    /// A layout for bytes encoded without annotations. A word is taken
+    /// as a length header when that many bytes and zero padding follow
+    /// it, and as a plain word otherwise; hypers, counts and
+    /// discriminants read as words. Trailing bytes short of a word are
+    /// one opaque body.
+    pub fn infer(bytes: &[u8]) -> Self {
+        Self {
+            bytes: bytes.to_vec(),
+            fields: infer_fields(bytes, 0, ""),
+        }
+    }
+
+    /// The value of a four-byte field
+    pub fn u32_at(&self, index: usize) -> u32 {
+        let at = self.fields[index].offset;
+        u32::from_be_bytes(self.bytes[at..at + 4].try_into().unwrap())
+    }
+
+    /// Overwrite a four-byte field
+    pub fn set_u32(&mut self, index: usize, value: u32) {
+        let at = self.fields[index].offset;
+        self.bytes[at..at + 4].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Overwrite an eight-byte field
+    pub fn set_u64(&mut self, index: usize, value: u64) {
+        let at = self.fields[index].offset;
+        self.bytes[at..at + 8].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// The length header in front of the opaque body at `index`, if it
+    /// has one
+    pub fn header_of(&self, index: usize) -> Option<usize> {
+        let prev = index.checked_sub(1)?;
+        let field = &self.fields[prev];
+        (field.kind == Kind::Length && field.offset + 4 == self.fields[index].offset)
+            .then_some(prev)
+    }
+
+    /// Replace the opaque body at `index` and its padding with `data`,
+    /// padded, and move the fields after it. The length header is left
+    /// as it was; see [`Message::header_of`].
+    pub fn splice(&mut self, index: usize, data: &[u8]) {
+        let field = &self.fields[index];
+        // An inferred trailing body has no padding to replace
+        let old_end = (field.offset + field.len + xdr_pad_len(field.len)).min(self.bytes.len());
+        let old = field.offset..old_end;
+        let mut new = data.to_vec();
+        new.resize(data.len() + xdr_pad_len(data.len()), 0);
+        let new_end = old.start + new.len();
+        self.bytes.splice(old, new);
+        self.fields[index].len = data.len();
+        for field in &mut self.fields[index + 1..] {
+            field.offset = field.offset + new_end - old_end;
+        }
+    }
+}
+
+fn infer_fields(bytes: &[u8], base: usize, prefix: &str) -> Vec<Field> {
+    let field = |kind, offset: usize, len| Field {
+        name: format!("{}@{}", prefix, base + offset),
+        kind,
+        offset: base + offset,
+        len,
+    };
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while at + 4 <= bytes.len() {
+        let body = u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        let end = (at + 4).saturating_add(body);
+        let padded = end.saturating_add(xdr_pad_len(body));
+        if body > 0 && padded <= bytes.len() && bytes[end..padded].iter().all(|&b| b == 0) {
+            fields.push(field(Kind::Length, at, 4));
+            fields.push(field(Kind::Bytes, at + 4, body));
+            at = padded;
+        } else {
+            fields.push(field(Kind::Word, at, 4));
+            at += 4;
+        }
+    }
+    if at < bytes.len() {
+        fields.push(field(Kind::Bytes, at, bytes.len() - at));
+    }
+    fields
+}
+
+/// An [`XdrEncoder`] that records the fields it writes
+#[derive(Default)]
+pub struct Annotated {
+    enc: XdrEncoder,
+    fields: Vec<Field>,
+    scope: Vec<String>,
+}
+
+impl Annotated {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.scope
+            .iter()
+            .map(String::as_str)
+            .chain([name])
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    fn record(&mut self, name: &str, kind: Kind, len: usize, put: impl FnOnce(&mut XdrEncoder)) {
+        let offset = self.enc.len();
+        put(&mut self.enc);
+        self.fields.push(Field {
+            name: self.path(name),
+            kind,
+            offset,
+            len,
+        });
+    }
+
+    pub fn put_u32(&mut self, name: &str, value: u32) {
+        self.record(name, Kind::Word, 4, |enc| enc.put_u32(value));
+    }
+
+    pub fn put_u64(&mut self, name: &str, value: u64) {
+        self.record(name, Kind::Hyper, 8, |enc| enc.put_u64(value));
+    }
+
+    pub fn put_bool(&mut self, name: &str, value: bool) {
+        self.record(name, Kind::Word, 4, |enc| enc.put_bool(value));
+    }
+
+    /// The discriminant of a union; the arm follows as more fields
+    pub fn put_discriminant(&mut self, name: &str, value: u32) {
+        self.record(name, Kind::Discriminant, 4, |enc| enc.put_u32(value));
+    }
+
+    /// The count of a variable-length array; the elements follow
+    pub fn put_count(&mut self, name: &str, count: u32) {
+        self.record(name, Kind::Count, 4, |enc| enc.put_u32(count));
+    }
+
+    pub fn put_opaque(&mut self, name: &str, data: &[u8]) {
+        self.record(name, Kind::Length, 4, |enc| enc.put_u32(data.len() as u32));
+        self.put_opaque_fixed(name, data);
+    }
+
+    pub fn put_string(&mut self, name: &str, s: &str) {
+        self.put_opaque(name, s.as_bytes());
+    }
+
+    pub fn put_opaque_fixed(&mut self, name: &str, data: &[u8]) {
+        self.record(name, Kind::Bytes, data.len(), |enc| {
+            enc.put_opaque_fixed(data)
+        });
+    }
+
+    /// Bytes encoded elsewhere, laid out by [`Message::infer`] under
+    /// `name`
+    pub fn put_inferred(&mut self, name: &str, data: &[u8]) {
+        let fields = infer_fields(data, self.enc.len(), &self.path(name));
+        self.enc.put_raw(data);
+        self.fields.extend(fields);
+    }
+
+    /// Write fields with `name` in front of theirs
+    pub fn scope(&mut self, name: &str, f: impl FnOnce(&mut Self)) {
+        self.scope.push(name.to_string());
+        f(self);
+        self.scope.pop();
+    }
+
+    pub fn finish(self) -> Message {
+        Message {
+            bytes: self.enc.into_bytes().to_vec(),
+            fields: self.fields,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotated_layout_and_splice() {
+        let mut enc = Annotated::new();
+        enc.put_string("name", "abcde");
+        enc.scope("attr", |enc| {
+            enc.put_discriminant("how", 1);
+            enc.put_u64("size", 4096);
+        });
+        let mut msg = enc.finish();
+        let mut plain = XdrEncoder::new();
+        plain.put_string("abcde");
+        plain.put_u32(1);
+        plain.put_u64(4096);
+        assert_eq!(msg.bytes, plain.as_bytes());
+        let names: Vec<&str> = msg.fields.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["name", "name", "attr.how", "attr.size"]);
+        assert_eq!(msg.fields[3].range(), 16..24);
+
+        // A longer body moves the fields after it; the header stays
+        assert_eq!(msg.header_of(1), Some(0));
+        msg.splice(1, &[b'x'; 9]);
+        assert_eq!(msg.bytes.len(), 28);
+        assert_eq!(msg.u32_at(0), 5);
+        assert_eq!(msg.u32_at(2), 1);
+        assert_eq!(msg.fields[3].offset, 20);
+    }
+
+    #[test]
+    fn test_infer() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(0);
+        enc.put_opaque(b"fh-bytes");
+        enc.put_u32(0xffff_ffff);
+        let msg = Message::infer(enc.as_bytes());
+        let kinds: Vec<Kind> = msg.fields.iter().map(|f| f.kind).collect();
+        assert_eq!(kinds, [Kind::Word, Kind::Length, Kind::Bytes, Kind::Word]);
+        assert_eq!(msg.fields[2].name, "@8");
+        assert_eq!(msg.fields[2].len, 8);
+        // A word followed by too little, or by nonzero padding, is a word
+        let msg = Message::infer(&[0, 0, 0, 3, b'a', b'b', b'c', 1, 9]);
+        let kinds: Vec<Kind> = msg.fields.iter().map(|f| f.kind).collect();
+        assert_eq!(kinds, [Kind::Word, Kind::Word, Kind::Bytes]);
+    }
+}
@@ -0,0 +1,361 @@
+//! Post-mutation constraint fixup
+//!
+//! A mutated message usually breaks several length and count fields at
+//! once, and the server rejects it at the first one it checks, so the
+//! code behind the other fields never runs. The fixup pass rewrites the
+//! declared fields to match the mutated bytes again, optionally leaving
+//! one field wrong so that each test case carries a single, deliberate
+//! inconsistency.
+
+use crate::fields::{self, Message};
+use crate::rpc::auth_flavor;
+use crate::xdr::xdr_pad_len;
+use std::str::FromStr;
+
+/// What a u32 field must agree with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// TCP record mark: last-fragment bit plus the bytes up to `end`
+    RecordMark,
+    /// Byte length of the data up to `end` (opaque_auth body, opaque)
+    Length,
+    /// Number of `elem`-byte elements up to `end`
+    Count { elem: usize },
+}
+
+/// A u32 field at `at` describing the bytes from `at + 4` to `end`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub at: usize,
+    pub end: usize,
+}
+
+impl Constraint {
+    fn expected(&self, current: u32) -> u32 {
+        let len = self.end - self.at - 4;
+        match self.kind {
+            Kind::RecordMark => (current & 0x8000_0000) | len as u32,
+            Kind::Length => len as u32,
+            Kind::Count { elem } => (len / elem) as u32,
+        }
+    }
+
+    fn fits(&self, buf: &[u8]) -> bool {
+        self.at + 4 <= self.end && self.end <= buf.len()
+    }
+}
+
+/// Which declared constraints the fixup pass re-satisfies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fixup {
+    /// Leave the mutated message alone
+    #[default]
+    Off,
+    /// Repair every field
+    All,
+    /// Repair every field but the named one
+    Preserve(&'static str),
+}
+
+/// Every constraint name a layout declares
+pub const NAMES: &[&str] = &[
+    "record_mark",
+    "cred_length",
+    "machine_length",
+    "gids_count",
+    "verf_length",
+    "opaque_length",
+];
+
+/// `off`, `all`, or the name of the one constraint to leave broken
+impl FromStr for Fixup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "all" => Ok(Self::All),
+            name => NAMES
+                .iter()
+                .find(|&&n| n == name)
+                .map(|&n| Self::Preserve(n))
+                .ok_or_else(|| format!("expected off, all or one of {}", NAMES.join(", "))),
+        }
+    }
+}
+
+/// The constraints declared for one message
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    pub constraints: Vec<Constraint>,
+}
+
+fn shift_pos(p: usize, pos: usize, delta: isize) -> usize {
+    if delta >= 0 {
+        if p >= pos {
+            p + delta as usize
+        } else {
+            p
+        }
+    } else {
+        let removed = delta.unsigned_abs();
+        if p >= pos + removed {
+            p - removed
+        } else {
+            p.min(pos)
+        }
+    }
+}
+
+impl Layout {
+    pub fn push(&mut self, name: &'static str, kind: Kind, at: usize, end: usize) {
+        self.constraints.push(Constraint {
+            name,
+            kind,
+            at,
+            end,
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Constraint> {
+        self.constraints.iter().find(|c| c.name == name)
+    }
+
+    /// Follow a mutation that inserted (`delta > 0`) or removed bytes at `pos`
+    ///
+    /// Bytes inserted exactly at a field's end count as part of it. A
+    /// constraint whose own u32 was removed is dropped.
+    pub fn shift(&mut self, pos: usize, delta: isize) {
+        self.constraints.retain_mut(|c| {
+            if delta < 0 && c.at + 4 > pos && c.at < pos + delta.unsigned_abs() {
+                return false;
+            }
+            c.at = shift_pos(c.at, pos, delta);
+            c.end = shift_pos(c.end, pos, delta);
+            true
+        });
+    }
+
+    /// Follow a mutation from `before` to `after`, the bytes differing
+    /// between their common prefix and suffix. A constraint whose own
+    /// u32 the mutation changed is dropped, so the value the mutator
+    /// chose stays in place.
+    pub fn follow(&mut self, before: &[u8], after: &[u8]) {
+        let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let room = before.len().min(after.len()) - prefix;
+        let suffix = before
+            .iter()
+            .rev()
+            .zip(after.iter().rev())
+            .take(room)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (old, new) = (before.len() - suffix, after.len() - suffix);
+        self.constraints
+            .retain(|c| !(c.at < old.max(prefix) && prefix < c.at + 4));
+        let delta = new as isize - old as isize;
+        if delta != 0 {
+            self.shift(prefix + (old - prefix).min(new - prefix), delta);
+        }
+    }
+
+    /// Names of the constraints `buf` currently violates
+    pub fn violations(&self, buf: &[u8]) -> Vec<&'static str> {
+        self.constraints
+            .iter()
+            .filter(|c| c.fits(buf))
+            .filter(|c| {
+                let current = read_u32(buf, c.at);
+                c.expected(current) != current
+            })
+            .map(|c| c.name)
+            .collect()
+    }
+
+    /// Rewrite violated fields as `mode` asks; returns the names repaired
+    pub fn apply(&self, buf: &mut [u8], mode: Fixup) -> Vec<&'static str> {
+        if mode == Fixup::Off {
+            return Vec::new();
+        }
+        let mut fixed = Vec::new();
+        for c in &self.constraints {
+            if mode == Fixup::Preserve(c.name) || !c.fits(buf) {
+                continue;
+            }
+            let current = read_u32(buf, c.at);
+            let expected = c.expected(current);
+            if expected != current {
+                buf[c.at..c.at + 4].copy_from_slice(&expected.to_be_bytes());
+                fixed.push(c.name);
+            }
+        }
+        fixed
+    }
+}
+
+fn read_u32(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+/// Declare the constraints of an unmutated record-marked RPC call
+///
+/// Covers the record mark, credential and verifier lengths and, for
+/// AUTH_SYS, the machine name length and gids count. Returns `None` if
+/// the message is too short to hold what its headers claim.
+pub fn rpc_call_layout(msg: &[u8]) -> Option<Layout> {
+    let u32_at = |at: usize| msg.get(at..at + 4).map(|b| read_u32(b, 0) as usize);
+    let mut layout = Layout::default();
+    layout.push("record_mark", Kind::RecordMark, 0, msg.len());
+
+    // Record mark and the six header words
+    let cred = 4 + 24;
+    let cred_len = u32_at(cred + 4)?;
+    let cred_end = cred + 8 + cred_len;
+    layout.push("cred_length", Kind::Length, cred + 4, cred_end);
+    if u32_at(cred)? == auth_flavor::AUTH_SYS as usize {
+        let machine = cred + 12;
+        let machine_len = u32_at(machine)?;
+        layout.push(
+            "machine_length",
+            Kind::Length,
+            machine,
+            machine + 4 + machine_len,
+        );
+        let count = machine + 4 + machine_len + xdr_pad_len(machine_len) + 8;
+        let gids = u32_at(count)?;
+        layout.push(
+            "gids_count",
+            Kind::Count { elem: 4 },
+            count,
+            count + 4 + 4 * gids,
+        );
+    }
+
+    let verf_len = u32_at(cred_end + 4)?;
+    layout.push(
+        "verf_length",
+        Kind::Length,
+        cred_end + 4,
+        cred_end + 8 + verf_len,
+    );
+    layout
+        .constraints
+        .iter()
+        .all(|c| c.end <= msg.len())
+        .then_some(layout)
+}
+
+/// Declare the length headers [`Message::infer`] finds in call
+/// arguments, all named `opaque_length`
+pub fn args_layout(args: &[u8]) -> Layout {
+    let mut layout = Layout::default();
+    let message = Message::infer(args);
+    for (i, field) in message.fields.iter().enumerate() {
+        if field.kind == fields::Kind::Length {
+            let end = field.offset + 4 + message.u32_at(i) as usize;
+            layout.push("opaque_length", Kind::Length, field.offset, end);
+        }
+    }
+    layout
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{auth_none, auth_sys, RpcCall};
+
+    fn call() -> Vec<u8> {
+        RpcCall::new(1, 100003, 3, 1, true)
+            .with_auth(&auth_sys("fuzzer", 0, 0, &[1, 2]), &auth_none())
+            .with_args(&[0; 8])
+            .build()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_layout_of_clean_call_is_satisfied() {
+        let msg = call();
+        let layout = rpc_call_layout(&msg).unwrap();
+        let names: Vec<_> = layout.constraints.iter().map(|c| c.name).collect();
+        assert_eq!(
+            names,
+            [
+                "record_mark",
+                "cred_length",
+                "machine_length",
+                "gids_count",
+                "verf_length"
+            ]
+        );
+        assert!(layout.violations(&msg).is_empty());
+        assert!(rpc_call_layout(&msg[..40]).is_none());
+    }
+
+    #[test]
+    fn test_fixup_after_gid_insertion_preserves_one() {
+        let mut msg = call();
+        let mut layout = rpc_call_layout(&msg).unwrap();
+        // Append a third gid after the existing two
+        let gids_end = layout.get("gids_count").unwrap().end;
+        msg.splice(gids_end..gids_end, 3u32.to_be_bytes());
+        layout.shift(gids_end, 4);
+        assert_eq!(
+            layout.violations(&msg),
+            ["record_mark", "cred_length", "gids_count"]
+        );
+
+        let mut fixed = msg.clone();
+        let repaired = layout.apply(&mut fixed, Fixup::Preserve("cred_length"));
+        assert_eq!(repaired, ["record_mark", "gids_count"]);
+        assert_eq!(layout.violations(&fixed), ["cred_length"]);
+        assert_eq!(read_u32(&fixed, 0), 0x8000_0000 | (msg.len() - 4) as u32);
+
+        layout.apply(&mut fixed, Fixup::All);
+        assert!(layout.violations(&fixed).is_empty());
+        assert_eq!(rpc_call_layout(&fixed), Some(layout));
+    }
+
+    #[test]
+    fn test_shift_drops_removed_fields() {
+        let msg = call();
+        let mut layout = rpc_call_layout(&msg).unwrap();
+        let machine = layout.get("machine_length").unwrap().at;
+        layout.shift(machine, -4);
+        assert!(layout.get("machine_length").is_none());
+        assert_eq!(layout.get("record_mark").unwrap().end, msg.len() - 4);
+        assert_eq!(layout.get("cred_length").unwrap().at, 32);
+    }
+
+    #[test]
+    fn test_follow_keeps_what_the_mutator_wrote() {
+        // One word, then a five-byte opaque
+        let args = [
+            0, 0, 0, 7, 0, 0, 0, 5, b'h', b'e', b'l', b'l', b'o', 0, 0, 0,
+        ];
+        let layout = args_layout(&args);
+        assert_eq!(layout.constraints.len(), 1);
+
+        // Two bytes inserted into the body are repaired
+        let mut grown = args.to_vec();
+        grown.splice(10..10, *b"xx");
+        let mut followed = layout.clone();
+        followed.follow(&args, &grown);
+        assert_eq!(followed.apply(&mut grown, Fixup::All), ["opaque_length"]);
+        assert_eq!(read_u32(&grown, 4), 7);
+        let mut kept = args.to_vec();
+        kept.splice(10..10, *b"xx");
+        assert!(followed
+            .apply(&mut kept, "opaque_length".parse().unwrap())
+            .is_empty());
+
+        // A header the mutation set itself is left alone
+        let mut header = args.to_vec();
+        header[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut followed = layout.clone();
+        followed.follow(&args, &header);
+        assert!(followed.constraints.is_empty());
+        assert!("nonsense".parse::<Fixup>().is_err());
+    }
+}
@@ -0,0 +1,1950 @@
+//! The fuzzing loop
+//!
+//! Cases are sent one at a time and every reply is counted by status and
+//! by behavior signature (see [`crate::signature`]); the signature of the
+//! last case is saved with any finding it ends.
+//!
+//! Connections and requests in flight take permits from the campaign's
+//! [`crate::limits::Governor`], and the cases kept for findings are
+//! charged to its memory budget, the oldest dropped when it runs out.
+//!
+//! A NULL health probe runs every so many cases and whenever the server
+//! drops the connection. When the probe goes unanswered on a fresh
+//! connection the server is taken to have died, and every case since the
+//! last good probe is written to `<output>/crashes/<n>/` as corpus
+//! entries, the highest-numbered entry being the last case sent. A case
+//! that outlives the request budget is a hang instead and goes to
+//! `<output>/hangs/`. Findings are saved with the connection details
+//! [`crate::replay`] needs to re-send them.
+//!
+//! After a finding the loop waits for the server to come back, asking the
+//! configured [`crate::controller`] to restart it and to reboot it if it
+//! is still down halfway through the wait, and saves the target's logs
+//! with the finding. It then re-sends the finding a few times to measure
+//! how reliably it reproduces (see [`crate::repro`]), shrinks a crash
+//! that reproduces every time to a minimal reproducer (see
+//! [`crate::minimize`]), and carries on.
+//!
+//! With a v4.1 session attached, every v4.1 compound is sent on it, its
+//! SEQUENCE resolved against the slot table (see
+//! [`crate::nfsv4::session`]). Client ids, stateids and handles in v4
+//! replies are kept, and the placeholders for them in generated compounds
+//! resolved before sending (see [`crate::nfsv4::state`]).
+//!
+//! On a session, the StateidSweep case is replaced by a TEST_STATEID of
+//! guesses around the stateids harvested so far. A fabricated stateid
+//! the server calls valid is an oracle finding (see
+//! [`crate::nfsv4::stateids`]).
+//!
+//! On a session, the ReplyCache case runs the reply cache plans on the
+//! session's last slot instead: expensive compounds cached, replayed,
+//! falsely retried and pushed out. A replay answered other than the
+//! plan expects is an oracle finding (see [`crate::nfsv4::replycache`]).
+//!
+//! With a GSS context attached, the GssWindow case sends calls with
+//! broken checksums and channel bindings on it and runs its sequence
+//! window plans (see [`crate::gss`]). A call the server carries out, or
+//! a step of the window it gets wrong, is an oracle finding saved to
+//! `<output>/oracle/`.
+//!
+//! A context cases are authenticated with carries every case of its
+//! program and version that has no credential of its own, and the
+//! replies are checked and unwrapped before anything else reads them.
+//!
+//! With the flavors SECINFO lists for the export attached, an
+//! AuthDowngrade probe that succeeds under AUTH_SYS or AUTH_NONE on a
+//! krb5-only export is an oracle finding (see
+//! [`crate::nfsv4::secinfo`]).
+//!
+//! A v4 reply that contradicts the compound it answers is an oracle
+//! finding too: READ_PLUS segments that miss the range asked for, or a
+//! time read back corrupted (see [`crate::nfsv4::replies`]). So is an
+//! attribute sweep combination its single-attribute replies do not
+//! explain (see [`crate::nfsv4::attrsweep`]).
+//!
+//! So is a credential the server changes its mind about, rejected
+//! before and accepted now or the other way round (see
+//! [`crate::oracle`]).
+//!
+//! A boot verifier that changes shows the server restarted. The findings
+//! made since it was last seen unchanged are tagged as probable crashes
+//! with a `restart.txt`.
+//!
+//! A panic in generation or in the fuzzer's handling of a case is an
+//! internal error: the input goes to `<output>/internal/` and the
+//! campaign carries on (see [`crate::isolate`]).
+//!
+//! With coverage feedback attached, the kernel coverage of every case is
+//! collected, and a case reaching new edges is saved to `<output>/queue/`
+//! and handed to the generator to mutate (see [`crate::kcov`]). With a
+//! kernel log monitor attached, oops, BUG and WARN lines the target logs
+//! without going down are findings of the cases sent just before them,
+//! saved to `<output>/kernel/` (see [`crate::monitor`]).
+//!
+//! With checkpoints on, where the campaign stands is written to
+//! `<output>/checkpoint.json` every so many cases and at the end, for a
+//! later run to resume from (see [`crate::checkpoint`]).
+//!
+//! Traffic can be recorded three ways: a capture takes every call and
+//! reply into a pcapng file, a results log takes every case and its
+//! outcome as a JSON line (see [`crate::results`]), and a results
+//! database takes every case and finding (see [`crate::db`]).
+
+use crate::auth::{Identity, IdentityPool};
+use crate::checkpoint::{Checkpoint, CheckpointError, SavedFinding};
+use crate::connection::{ConnectionError, Proto, Timeouts, Transport};
+use crate::control::Gate;
+use crate::controller::{self, ControllerConfig, ControllerError, TargetController};
+use crate::corpus::{Corpus, CorpusError};
+use crate::db::ResultsDb;
+use crate::generate::{compound_args, Input};
+use crate::gss::{self, DynContext};
+use crate::hang::{self, HangKind, LatencyBudget};
+use crate::isolate;
+use crate::kcov::Feedback;
+use crate::limits::{BoundedStore, Governor};
+use crate::minimize::{self, Minimizer};
+use crate::monitor::{self, KernelEvent, Monitor};
+use crate::netfault::FaultConfig;
+use crate::nfsv4::attrsweep::{self, SweepRecorder};
+use crate::nfsv4::referral::Pathname;
+use crate::nfsv4::replycache::{self, replay_plans};
+use crate::nfsv4::secinfo::{self, Secinfo};
+use crate::nfsv4::session::SlotTable;
+use crate::nfsv4::state::{self, SessionState};
+use crate::nfsv4::stateids::{self, Hit, StateidSweep};
+use crate::nfsv4::{self, minor_version, status, CompoundBuilder, Stateid};
+use crate::oracle::{self, AuthTracker, AuthVerdict, Restart, RestartTracker};
+use crate::pcap;
+use crate::preset::Oracle;
+use crate::replay::Endpoint;
+use crate::repro::{Reproduction, Verdict};
+use crate::results::{Record, ResultLog};
+use crate::rpc::{self, program, RpcReply};
+use crate::signature::{status_name, Connection, Signature};
+use crate::stats::{CampaignStats, StatsError};
+use bytes::BytesMut;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+use tokio::sync::OwnedSemaphorePermit;
+use tracing::{error, info, warn};
+
+#[derive(Error, Debug)]
+pub enum FuzzError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Corpus(#[from] CorpusError),
+    #[error(transparent)]
+    Stats(#[from] StatsError),
+    #[error(transparent)]
+    Checkpoint(#[from] CheckpointError),
+    #[error("target did not come back within {0:?}")]
+    Down(Duration),
+    #[error("fuzzer panicked on {MAX_INTERNAL_ERRORS} cases in a row, last: {0}")]
+    Internal(String),
+}
+
+/// Consecutive internal errors after which the campaign gives up, the
+/// fault being in the fuzzer rather than in any one input
+pub const MAX_INTERNAL_ERRORS: u32 = 100;
+
+/// Stateids in each TEST_STATEID of the stateid sweep
+pub const SWEEP_BATCH: usize = 16;
+
+/// How a campaign reaches and watches its target
+#[derive(Debug, Clone)]
+pub struct FuzzConfig {
+    pub target: SocketAddr,
+    pub proto: Proto,
+    /// Faults to inject into UDP traffic, and the seed they are drawn
+    /// with; each new connection draws from the next seed
+    pub udp_faults: Option<(FaultConfig, u64)>,
+    pub budget: LatencyBudget,
+    pub output: PathBuf,
+    pub identity: Identity,
+    /// Identities to rotate cases through instead of `identity`
+    pub identities: Option<IdentityPool>,
+    /// Program the health probe's NULL is sent to: NFS, unless the
+    /// campaign fuzzes another RPC program
+    pub probe_program: u32,
+    /// Version of it the NULL is sent to
+    pub nfs_version: u32,
+    /// Cases between health probes
+    pub probe_every: u64,
+    /// How long a dead or stalled server gets to come back
+    pub restart_wait: Duration,
+    /// Times each finding is re-sent to measure its reproduction rate;
+    /// zero skips verification
+    pub verify_trials: u32,
+    /// Tests spent shrinking each crash; zero skips minimization
+    pub minimize_tests: u32,
+    /// What restarts the target and collects its logs
+    pub controller: ControllerConfig,
+    /// Ceilings on connections, requests in flight and memory
+    pub governor: Governor,
+    /// Oracles whose violations are recorded; crashes and hangs always
+    /// are
+    pub oracles: Vec<Oracle>,
+}
+
+impl FuzzConfig {
+    pub fn new(target: SocketAddr, output: impl Into<PathBuf>) -> Self {
+        Self {
+            target,
+            proto: Proto::Tcp,
+            udp_faults: None,
+            budget: LatencyBudget::default(),
+            output: output.into(),
+            identity: Identity::new(0, 0),
+            identities: None,
+            probe_program: program::NFS,
+            nfs_version: 3,
+            probe_every: 100,
+            restart_wait: Duration::from_secs(60),
+            verify_trials: 5,
+            minimize_tests: 100,
+            controller: ControllerConfig::None,
+            governor: Governor::new(Default::default()),
+            oracles: Oracle::ALL.to_vec(),
+        }
+    }
+}
+
+/// What a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    /// The server stopped answering NULL on new connections
+    Crash,
+    Hang(HangKind),
+    /// The target's kernel logged an oops, BUG or WARN and carried on
+    Kernel,
+    /// An oracle saw the server break a rule
+    Oracle(Oracle),
+}
+
+/// A case or group of cases that took the server down or hung it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub kind: FindingKind,
+    /// Name of the last case sent before it
+    pub name: String,
+    /// Where its input was written
+    pub path: PathBuf,
+    /// The cases that led to it, oldest first
+    pub inputs: Vec<Input>,
+    /// How reliably it reproduced, once verified
+    pub reproduction: Option<Reproduction>,
+    /// Where its minimized reproducer was written
+    pub minimized: Option<PathBuf>,
+    /// How the server handled the last case; findings with the same
+    /// [`Signature::id`] are likely the same bug
+    pub signature: Option<Signature>,
+}
+
+/// Whether `input` is the placeholder case for the GSS window checks,
+/// which has nothing of its own to send
+fn is_gss_window(input: &Input) -> bool {
+    input.lineage.seed.starts_with("GssWindow:") && input.lineage.steps.is_empty()
+}
+
+/// Whether `input` is an attribute sweep GETATTR as generated, whose
+/// reply the sweep judges
+fn is_sweep(input: &Input) -> bool {
+    input.lineage.seed.starts_with("AttrSweep:") && input.lineage.steps.is_empty()
+}
+
+/// Whether `input` is the stand-in for the stateid sweep, which the
+/// fuzzer fills in from the stateids it has harvested
+fn is_stateid_sweep(input: &Input) -> bool {
+    input.lineage.seed == "StateidSweep:stateid_sweep" && input.lineage.steps.is_empty()
+}
+
+/// Whether `input` is the stand-in for the reply cache plans, which the
+/// fuzzer runs on its session
+fn is_reply_cache(input: &Input) -> bool {
+    input.lineage.seed == "ReplyCache:reply_cache" && input.lineage.steps.is_empty()
+}
+
+/// The flavor of a downgrade probe as generated, which goes out under a
+/// credential of its own
+fn downgraded(input: &Input) -> Option<u32> {
+    if !input.lineage.seed.starts_with("AuthDowngrade:") || !input.lineage.steps.is_empty() {
+        return None;
+    }
+    let cred = input.auth.as_ref()?.cred.get(..4)?;
+    Some(u32::from_be_bytes(cred.try_into().unwrap()))
+}
+
+/// Write the kernel lines of a finding to `dmesg.txt` in `dir`
+fn save_kernel_lines(dir: &std::path::Path, events: &[KernelEvent]) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let text: String = events.iter().map(|e| format!("{}\n", e.line)).collect();
+    std::fs::write(dir.join("dmesg.txt"), text)
+}
+
+/// One campaign against one target
+#[derive(Debug)]
+pub struct Fuzzer {
+    config: FuzzConfig,
+    controller: Arc<dyn TargetController>,
+    capture: Option<pcap::Writer<BufWriter<File>>>,
+    results: Option<ResultLog>,
+    db: Option<ResultsDb>,
+    session: Option<SlotTable>,
+    /// GSS context the window and checksum checks are run on
+    gss: Option<DynContext>,
+    /// Whether cases go out under `gss` rather than AUTH_SYS
+    gss_auth: bool,
+    /// How the server has answered each credential
+    auth: AuthTracker,
+    /// Boot verifiers seen, and the findings since, by path
+    restarts: RestartTracker,
+    /// Attribute sweep replies so far
+    sweep: SweepRecorder,
+    state: SessionState,
+    /// Stateids guessed so far, and those the server took
+    stateids: StateidSweep,
+    /// The export the downgrade probes walk to, and the flavors its
+    /// SECINFO lists
+    secinfo: Option<(Pathname, Vec<Secinfo>)>,
+    feedback: Option<Feedback>,
+    /// The connection, with its slot from the governor
+    conn: Option<(Transport, OwnedSemaphorePermit)>,
+    /// Connections opened so far
+    connects: u64,
+    /// Signature of the last case sent
+    signature: Option<Signature>,
+    monitor: Option<Monitor>,
+    /// Cases sent recently enough to have caused a kernel line yet to
+    /// arrive, with when they were sent
+    recent: BoundedStore<(Instant, Input)>,
+    /// Cases sent since the last good health probe, oldest first
+    window: BoundedStore<Input>,
+    sent: u64,
+    /// Seed recorded in checkpoints, and cases between them
+    checkpoints: Option<(u64, u64)>,
+    pub stats: CampaignStats,
+    pub findings: Vec<Finding>,
+}
+
+impl Fuzzer {
+    pub fn new(config: FuzzConfig) -> Self {
+        let memory = config.governor.memory.clone();
+        Self {
+            controller: config.controller.controller(),
+            config,
+            capture: None,
+            results: None,
+            db: None,
+            session: None,
+            gss: None,
+            gss_auth: false,
+            auth: AuthTracker::new(),
+            restarts: RestartTracker::new(),
+            sweep: SweepRecorder::new(),
+            state: SessionState::default(),
+            stateids: StateidSweep::new(Vec::new(), SWEEP_BATCH),
+            secinfo: None,
+            feedback: None,
+            signature: None,
+            monitor: None,
+            recent: BoundedStore::new(memory.clone()),
+            conn: None,
+            connects: 0,
+            window: BoundedStore::new(memory),
+            sent: 0,
+            checkpoints: None,
+            stats: CampaignStats::default(),
+            findings: Vec::new(),
+        }
+    }
+
+    /// Use `controller` instead of the configured one
+    pub fn with_controller(mut self, controller: Arc<dyn TargetController>) -> Self {
+        self.controller = controller;
+        self
+    }
+
+    /// Write all traffic to `capture`
+    pub fn with_capture(mut self, capture: pcap::Writer<BufWriter<File>>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Append every case and its outcome to `log`
+    pub fn with_results(mut self, log: ResultLog) -> Self {
+        self.results = Some(log);
+        self
+    }
+
+    /// Store every case and finding in `db`
+    pub fn with_db(mut self, db: ResultsDb) -> Self {
+        self.db = Some(db);
+        self
+    }
+
+    /// Send v4.1 compounds on the session of `table`, under its client id
+    pub fn with_session(mut self, table: SlotTable) -> Self {
+        self.state.clientid = Some(table.clientid);
+        self.session = Some(table);
+        self
+    }
+
+    /// Run the GSS window and checksum checks on `ctx`
+    pub fn with_gss(mut self, ctx: DynContext) -> Self {
+        self.gss = Some(ctx);
+        self
+    }
+
+    /// Send every case `ctx` can carry under it, instead of AUTH_SYS,
+    /// and run its window and checksum checks
+    pub fn with_gss_auth(mut self, ctx: DynContext) -> Self {
+        self.gss_auth = true;
+        self.with_gss(ctx)
+    }
+
+    /// Judge the downgrade probes against the flavors SECINFO lists for
+    /// `export`
+    pub fn with_secinfo(mut self, export: Pathname, advertised: Vec<Secinfo>) -> Self {
+        self.secinfo = Some((export, advertised));
+        self
+    }
+
+    /// Collect coverage after every case through `feedback`
+    pub fn with_feedback(mut self, feedback: Feedback) -> Self {
+        self.feedback = Some(feedback);
+        self
+    }
+
+    /// Watch the target's kernel log through `monitor`
+    pub fn with_monitor(mut self, monitor: Monitor) -> Self {
+        self.monitor = Some(monitor);
+        self
+    }
+
+    /// Write a checkpoint of the campaign, generated from `seed`, every
+    /// `every` cases and when it ends
+    pub fn with_checkpoints(mut self, seed: u64, every: u64) -> Self {
+        self.checkpoints = Some((seed, every));
+        self
+    }
+
+    /// Carry on from `checkpoint` with the statistics `stats` were saved
+    /// with it; feedback, if any, must be attached first
+    pub fn resume(mut self, checkpoint: Checkpoint, stats: CampaignStats) -> Self {
+        self.sent = checkpoint.sent;
+        self.stats = stats;
+        self.findings = checkpoint
+            .findings
+            .into_iter()
+            .map(SavedFinding::restore)
+            .collect();
+        if let (Some(feedback), Some(map)) = (&mut self.feedback, checkpoint.coverage) {
+            feedback.restore(map);
+        }
+        self
+    }
+
+    /// Where the campaign stands, as generated from `seed`
+    pub fn checkpoint(&self, seed: u64) -> Checkpoint {
+        Checkpoint {
+            version: crate::checkpoint::VERSION,
+            seed,
+            sent: self.sent,
+            coverage: self.feedback.as_ref().map(|f| f.map().clone()),
+            findings: self.findings.iter().map(SavedFinding::of).collect(),
+        }
+    }
+
+    /// Save the statistics and a checkpoint if checkpoints are on and
+    /// `due` says one is due
+    fn save_checkpoint(&self, due: impl FnOnce(u64) -> bool) -> Result<(), FuzzError> {
+        let Some((seed, every)) = self.checkpoints else {
+            return Ok(());
+        };
+        if !due(every) {
+            return Ok(());
+        }
+        self.stats.save(&self.config.output)?;
+        self.checkpoint(seed).save(&self.config.output)?;
+        Ok(())
+    }
+
+    /// The session v4.1 compounds are sent on, as it stands
+    pub fn session(&self) -> Option<&SlotTable> {
+        self.session.as_ref()
+    }
+
+    /// State harvested from the replies so far
+    pub fn state(&self) -> &SessionState {
+        &self.state
+    }
+
+    /// `input` as it goes out: a v4 compound's state placeholders
+    /// resolved, and its SEQUENCE against the session if there is one
+    fn on_session(&self, input: &Input) -> Input {
+        let mut input = input.clone();
+        if (input.program, input.version, input.procedure)
+            != (program::NFS, 4, nfsv4::PROC_COMPOUND)
+        {
+            return input;
+        }
+        input.args = self.state.resolve(&input.args);
+        if let Some(args) = self.session.as_ref().and_then(|t| t.stamp(&input.args)) {
+            input.args = args;
+        }
+        input
+    }
+
+    /// The stateid sweep stand-in as it goes out, a TEST_STATEID of
+    /// guesses around the stateids harvested so far, with the guesses;
+    /// other inputs, and the stand-in without a session, as they are
+    fn sweep_stateids(&mut self, input: &Input) -> (Input, Option<Vec<Stateid>>) {
+        if self.session.is_none() || !is_stateid_sweep(input) {
+            return (input.clone(), None);
+        }
+        let state = &self.state;
+        let harvested = state.opens.iter().chain(&state.locks);
+        for &s in harvested.chain(&state.delegations).chain(&state.layouts) {
+            self.stateids.observe(s);
+        }
+        let mut rng = StdRng::seed_from_u64(self.sent);
+        let (case, batch) = self.stateids.test_case(&mut rng);
+        let swept = Input {
+            args: compound_args(&case.ops),
+            ..input.clone()
+        };
+        (swept, Some(batch))
+    }
+
+    /// Cases sent so far
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        Timeouts::from(&self.config.budget)
+    }
+
+    async fn connection(&mut self) -> Result<&mut Transport, ConnectionError> {
+        if self.conn.is_none() {
+            let permit = self.config.governor.connection().await;
+            let mut conn =
+                Transport::connect(self.config.proto, self.config.target, self.timeouts())
+                    .await?
+                    .with_budget(self.config.governor.memory.clone());
+            if let Some((faults, seed)) = self.config.udp_faults {
+                conn = conn.with_faults(faults, seed.wrapping_add(self.connects));
+            }
+            self.connects += 1;
+            self.conn = Some((conn, permit));
+            if let Some(pool) = &mut self.config.identities {
+                pool.on_connect();
+            }
+        }
+        Ok(&mut self.conn.as_mut().unwrap().0)
+    }
+
+    /// Who the next case goes out as: `identity`, or with a pool the
+    /// identity it rotates to. The connection is opened first, so a pool
+    /// rotating per connection has moved on when it is new.
+    async fn identity(&mut self) -> Identity {
+        if self.config.identities.is_none() {
+            return self.config.identity.clone();
+        }
+        // A failed connect is retried, and reported, by the call
+        let _ = self.connection().await;
+        match &mut self.config.identities {
+            Some(pool) => pool.for_request().clone(),
+            None => self.config.identity.clone(),
+        }
+    }
+
+    /// Send `msg` on the current connection, or a new one if there is
+    /// none, and add both directions to the capture
+    async fn call(&mut self, msg: &[u8]) -> Result<Vec<u8>, ConnectionError> {
+        let (proto, server) = (self.config.proto, self.config.target);
+        let _pending = self.config.governor.pending().await;
+        let conn = self.connection().await?;
+        let client = conn.local_addr();
+        let sent = SystemTime::now();
+        let result = conn.call(msg).await;
+        if let (Some(capture), Ok(client)) = (&mut self.capture, client) {
+            let written = capture
+                .call(sent, proto, client, server, msg)
+                .and_then(|()| match &result {
+                    Ok(reply) => capture.reply(SystemTime::now(), proto, client, server, reply),
+                    Err(_) => Ok(()),
+                })
+                .and_then(|()| capture.flush());
+            if let Err(e) = written {
+                warn!("Could not write the capture: {}", e);
+            }
+        }
+        result
+    }
+
+    /// Send a NULL on the current connection, or a new one if there is
+    /// none or it fails; whether the server answered
+    pub async fn alive(&mut self) -> bool {
+        let null = rpc::simple_rpc_call(self.config.probe_program, self.config.nfs_version, 0);
+        for _ in 0..2 {
+            if self.call(&null).await.is_ok() {
+                return true;
+            }
+            self.conn = None;
+        }
+        false
+    }
+
+    /// Probe until the server answers or the restart wait runs out; a
+    /// server still down halfway through has its host rebooted
+    async fn wait_for_restart(&mut self) -> Result<(), FuzzError> {
+        let start = Instant::now();
+        let deadline = start + self.config.restart_wait;
+        let mut escalate = Some(start + self.config.restart_wait / 2);
+        self.restart_target().await;
+        loop {
+            if self.alive().await {
+                info!("Target is back");
+                if let Some(Err(e)) = self.monitor.as_mut().map(Monitor::reopen) {
+                    warn!("Kernel log monitor lost, continuing without: {}", e);
+                    self.monitor = None;
+                }
+                return Ok(());
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(FuzzError::Down(self.config.restart_wait));
+            }
+            if escalate.is_some_and(|at| now >= at) {
+                escalate = None;
+                self.reboot_target().await;
+            }
+            tokio::time::sleep((deadline - now).min(Duration::from_secs(1))).await;
+        }
+    }
+
+    /// Have the controller reboot the host, if it can
+    async fn reboot_target(&self) {
+        match controller::blocking(&self.controller, |c| c.reboot()).await {
+            Ok(()) => warn!(
+                "Target still down, rebooted it through {}",
+                self.controller.name()
+            ),
+            Err(ControllerError::Unsupported { .. }) => {}
+            Err(e) => warn!("Could not reboot the target: {}", e),
+        }
+    }
+
+    /// Have the controller restart the service, or reboot the host where
+    /// it cannot restart the service alone
+    async fn restart_target(&self) {
+        let result = match controller::blocking(&self.controller, |c| c.restart_service()).await {
+            Err(ControllerError::Unsupported { .. }) => {
+                controller::blocking(&self.controller, |c| c.reboot()).await
+            }
+            result => result,
+        };
+        match result {
+            Ok(()) => info!("Restarted the target through {}", self.controller.name()),
+            Err(ControllerError::Unsupported { .. }) => {}
+            Err(e) => warn!("Could not restart the target: {}", e),
+        }
+    }
+
+    /// Save the target's recent logs with the newest finding: inside a
+    /// crash directory, or beside a hang's input
+    async fn collect_logs(&self) -> Result<(), FuzzError> {
+        let Some(finding) = self.findings.last() else {
+            return Ok(());
+        };
+        match controller::blocking(&self.controller, |c| c.collect_logs()).await {
+            Ok(logs) => {
+                let path = if finding.path.is_dir() {
+                    finding.path.join("target.log")
+                } else {
+                    finding.path.with_extension("log")
+                };
+                std::fs::write(path, logs)?;
+            }
+            Err(ControllerError::Unsupported { .. }) => {}
+            Err(e) => warn!("Could not collect target logs: {}", e),
+        }
+        Ok(())
+    }
+
+    /// Write the cases since the last good probe as a crash, with the
+    /// kernel lines the monitor passed on since the last case
+    fn record_crash(&mut self) -> Result<(), FuzzError> {
+        let lines = self.kernel_events();
+        let dir = self
+            .config
+            .output
+            .join("crashes")
+            .join(format!("{:08}", self.sent));
+        let corpus = Corpus::open(&dir);
+        for (i, input) in self.window.iter().enumerate() {
+            let stem = format!("{:03}_{}", i, input.name);
+            corpus.write(&stem, input, &input.message(&self.config.identity))?;
+        }
+        if let Some(last) = self.window.last() {
+            warn!(
+                "Target died after {} ({} cases since the last probe), saved to {}",
+                last.name,
+                self.window.len(),
+                dir.display()
+            );
+            self.stats.record_crash(&last.lineage);
+            Endpoint::of(&self.config).save(&dir)?;
+            if let Some(signature) = &self.signature {
+                signature.save(&dir)?;
+            }
+            if !lines.is_empty() {
+                save_kernel_lines(&dir, &lines)?;
+            }
+            let finding = Finding {
+                kind: FindingKind::Crash,
+                name: last.name.clone(),
+                path: dir,
+                inputs: self.window.take(),
+                reproduction: None,
+                minimized: None,
+                signature: self.signature.clone(),
+            };
+            self.add_finding(finding);
+        }
+        self.stats.save(&self.config.output)?;
+        Ok(())
+    }
+
+    /// Collect the coverage of `input`, just sent, saving it to
+    /// `<output>/queue/<n>_<name>/` if it reached new edges; when the agent
+    /// fails the campaign carries on without feedback
+    async fn cover(&mut self, input: &Input) -> Result<(), FuzzError> {
+        let Some(feedback) = &mut self.feedback else {
+            return Ok(());
+        };
+        match feedback.observe(input).await {
+            Ok(0) => {}
+            Ok(new) => {
+                self.stats.edges = feedback.edges() as u64;
+                self.stats.queued += 1;
+                let stem = format!("{:08}_{}", self.sent, input.name);
+                Corpus::open(self.config.output.join("queue")).write(
+                    &stem,
+                    input,
+                    &input.message(&self.config.identity),
+                )?;
+                info!(
+                    "{} reached {} new edges, {} in all",
+                    input.name, new, self.stats.edges
+                );
+            }
+            Err(e) => {
+                warn!("Coverage feedback lost, continuing without: {}", e);
+                self.feedback = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Kernel lines the monitor passed on since the last call
+    fn kernel_events(&mut self) -> Vec<KernelEvent> {
+        let Some(monitor) = &mut self.monitor else {
+            return Vec::new();
+        };
+        match monitor.drain() {
+            Ok(events) => events,
+            Err(e) => {
+                warn!("Kernel log monitor lost, continuing without: {}", e);
+                self.monitor = None;
+                Vec::new()
+            }
+        }
+    }
+
+    /// Turn the kernel lines that arrived since the last case into a
+    /// finding of the cases sent shortly before them, and verify it
+    async fn watch_kernel(&mut self) -> Result<(), FuzzError> {
+        let events = self.kernel_events();
+        if let (Some(first), Some(last)) = (events.first(), events.last()) {
+            let sent: Vec<Instant> = self.recent.iter().map(|(at, _)| *at).collect();
+            let inputs: Vec<Input> = monitor::correlate(&sent, first.at, last.at)
+                .into_iter()
+                .filter_map(|i| self.recent.get(i).map(|(_, input)| input.clone()))
+                .collect();
+            if inputs.is_empty() {
+                warn!("Kernel logged before any case was sent: {}", first.line);
+            } else {
+                self.record_kernel(inputs, &events)?;
+                self.verify_last().await?;
+            }
+        }
+        // Later lines only look back from now; keep the last case for
+        // the ones that look back to nothing
+        let now = Instant::now();
+        while self.recent.len() > 1
+            && self
+                .recent
+                .front()
+                .is_some_and(|(at, _)| *at + monitor::LOOKBACK < now)
+        {
+            self.recent.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Write `inputs` and the kernel lines they are taken to have caused
+    /// to `<output>/kernel/<n>/`
+    fn record_kernel(
+        &mut self,
+        inputs: Vec<Input>,
+        events: &[KernelEvent],
+    ) -> Result<(), FuzzError> {
+        let dir = self
+            .config
+            .output
+            .join("kernel")
+            .join(format!("{:08}", self.sent));
+        let corpus = Corpus::open(&dir);
+        for (i, input) in inputs.iter().enumerate() {
+            let stem = format!("{:03}_{}", i, input.name);
+            corpus.write(&stem, input, &input.message(&self.config.identity))?;
+        }
+        save_kernel_lines(&dir, events)?;
+        Endpoint::of(&self.config).save(&dir)?;
+        let last = &inputs[inputs.len() - 1];
+        warn!(
+            "Kernel logged {:?} after {} ({} cases), saved to {}",
+            events[0].line,
+            last.name,
+            inputs.len(),
+            dir.display()
+        );
+        self.stats.record_crash(&last.lineage);
+        self.add_finding(Finding {
+            kind: FindingKind::Kernel,
+            name: last.name.clone(),
+            path: dir,
+            inputs,
+            reproduction: None,
+            minimized: None,
+            signature: None,
+        });
+        self.stats.save(&self.config.output)?;
+        Ok(())
+    }
+
+    /// Save an input that made the fuzzer itself panic to
+    /// `<output>/internal/<n>_<name>/`, the panic message next to it
+    fn record_internal(&mut self, input: &Input, message: &str) -> Result<(), FuzzError> {
+        let dir = self
+            .config
+            .output
+            .join("internal")
+            .join(format!("{:08}_{}", self.sent, input.name));
+        Corpus::open(&dir).write(&input.name, input, &input.message(&self.config.identity))?;
+        std::fs::write(dir.join("panic.txt"), format!("{}\n", message))?;
+        error!(
+            "Internal error on {} ({}), saved to {}",
+            input.name,
+            message,
+            dir.display()
+        );
+        self.stats.internal_errors += 1;
+        Ok(())
+    }
+
+    fn record_hang(&mut self, input: &Input, msg: &[u8], kind: HangKind) -> Result<(), FuzzError> {
+        let name = format!("{:08}_{}", self.sent, input.name);
+        let path = hang::capture_hang(
+            &self.config.output,
+            &name,
+            msg,
+            kind,
+            &self.config.budget,
+            &input.lineage,
+        )?;
+        Endpoint::of(&self.config).save(&path)?;
+        if let Some(signature) = &self.signature {
+            signature.save(&path)?;
+        }
+        warn!(
+            "{} hung ({:?}), saved to {}",
+            input.name,
+            kind,
+            path.display()
+        );
+        self.stats.record_crash(&input.lineage);
+        self.add_finding(Finding {
+            kind: FindingKind::Hang(kind),
+            name: input.name.clone(),
+            path,
+            inputs: vec![input.clone()],
+            reproduction: None,
+            minimized: None,
+            signature: self.signature.clone(),
+        });
+        self.stats.save(&self.config.output)?;
+        Ok(())
+    }
+
+    /// Save a case an oracle caught the server out on to
+    /// `<output>/oracle/<n>_<name>/`, what it saw next to it
+    fn record_violation(
+        &mut self,
+        oracle: Oracle,
+        input: &Input,
+        name: &str,
+        msg: &[u8],
+        detail: &str,
+    ) -> Result<(), FuzzError> {
+        if !self.config.oracles.contains(&oracle) {
+            return Ok(());
+        }
+        let dir = self
+            .config
+            .output
+            .join("oracle")
+            .join(format!("{:08}_{}", self.sent, name));
+        Corpus::open(&dir).write(name, input, msg)?;
+        std::fs::write(dir.join("violation.txt"), format!("{}\n", detail))?;
+        warn!("{:?}: {}, saved to {}", oracle, detail, dir.display());
+        self.add_finding(Finding {
+            kind: FindingKind::Oracle(oracle),
+            name: input.name.clone(),
+            path: dir,
+            inputs: vec![input.clone()],
+            reproduction: None,
+            minimized: None,
+            signature: self.signature.clone(),
+        });
+        Ok(())
+    }
+
+    /// Mark the findings made before `restart` as probable crashes, in a
+    /// `restart.txt` beside each
+    fn tag_probable_crashes(&self, restart: &Restart) {
+        let text = format!(
+            "Probable crash: the server restarted, its {:?} changing between calls {} and {}\n",
+            restart.source, restart.last_unchanged, restart.detected_at
+        );
+        for path in restart.probable_crashes.iter().map(PathBuf::from) {
+            let tag = match path.is_dir() {
+                true => path.join("restart.txt"),
+                false => path.with_extension("restart.txt"),
+            };
+            warn!("{} is a probable crash", path.display());
+            if let Err(e) = std::fs::write(&tag, &text) {
+                warn!("Could not write {}: {}", tag.display(), e);
+            }
+        }
+    }
+
+    fn add_finding(&mut self, finding: Finding) {
+        // A restart is not a probable crash of the next one
+        if finding.kind != FindingKind::Oracle(Oracle::Restart) {
+            let path = finding.path.display().to_string();
+            self.restarts.finding(path, self.sent);
+        }
+        if let Some(Err(e)) = self.db.as_mut().map(|db| db.finding(&finding)) {
+            warn!("Could not write the results database, so nothing more goes into it: {}", e);
+            self.db = None;
+        }
+        self.findings.push(finding);
+    }
+
+    /// Check on the server; a dead one is a crash of the window
+    async fn probe(&mut self) -> Result<(), FuzzError> {
+        if self.alive().await {
+            self.window.clear();
+            return Ok(());
+        }
+        self.record_crash()?;
+        self.collect_logs().await?;
+        self.wait_for_restart().await?;
+        self.verify_last().await?;
+        self.minimize_last().await
+    }
+
+    /// Send `inputs` to a live target and say whether a finding of
+    /// `kind` showed again; the target is left alive
+    async fn trial(&mut self, kind: FindingKind, inputs: &[Input]) -> Result<bool, FuzzError> {
+        if !self.alive().await {
+            self.wait_for_restart().await?;
+        }
+        self.kernel_events();
+        let mut hung = false;
+        for input in inputs {
+            let msg = input.message(&self.identity().await);
+            let result = self.call(&msg).await;
+            match result {
+                Ok(_) => {}
+                Err(ConnectionError::Timeout { .. }) => {
+                    hung = true;
+                    self.conn = None;
+                }
+                Err(_) => self.conn = None,
+            }
+        }
+        let alive = self.alive().await;
+        if kind == FindingKind::Kernel {
+            tokio::time::sleep(monitor::SETTLE).await;
+        }
+        // Taken either way, so what a trial logs is not put down to the
+        // cases after it
+        let logged = !self.kernel_events().is_empty();
+        let reproduced = match kind {
+            FindingKind::Crash => !alive,
+            FindingKind::Hang(HangKind::Request) => hung,
+            FindingKind::Hang(HangKind::Server) => hung && !alive,
+            FindingKind::Kernel => logged,
+            // Judged on the replies, which a trial does not look at
+            FindingKind::Oracle(_) => false,
+        };
+        if !alive {
+            self.wait_for_restart().await?;
+        }
+        Ok(reproduced)
+    }
+
+    /// Re-send the newest finding `verify_trials` times and save how
+    /// often it reproduced beside it. A crash window is cut to its last
+    /// case first if that alone brings the server down; that trial
+    /// counts as the first.
+    async fn verify_last(&mut self) -> Result<(), FuzzError> {
+        let trials = self.config.verify_trials;
+        let finding = match self.findings.last() {
+            Some(finding) if trials > 0 => finding.clone(),
+            _ => return Ok(()),
+        };
+        let mut cases = finding.inputs.clone();
+        let (mut done, mut reproduced) = (0, 0);
+        if finding.kind == FindingKind::Crash && cases.len() > 1 {
+            let last = cases.split_off(cases.len() - 1);
+            if self.trial(finding.kind, &last).await? {
+                cases = last;
+                (done, reproduced) = (1, 1);
+            } else {
+                cases.extend(last);
+            }
+        }
+        while done < trials {
+            if self.trial(finding.kind, &cases).await? {
+                reproduced += 1;
+            }
+            done += 1;
+        }
+        let repro = Reproduction {
+            reproducer: cases.iter().map(|c| c.name.clone()).collect(),
+            trials,
+            reproduced,
+        };
+        let path = repro.save(&finding.path)?;
+        info!(
+            "{} reproduced {}/{} ({}), saved to {}",
+            finding.name,
+            reproduced,
+            trials,
+            repro.verdict(),
+            path.display()
+        );
+        if let Some(f) = self.findings.last_mut() {
+            f.reproduction = Some(repro);
+        }
+        Ok(())
+    }
+
+    /// Shrink the newest finding if it is a crash that reproduced every
+    /// time, or was not verified, and write what is left beside it
+    async fn minimize_last(&mut self) -> Result<(), FuzzError> {
+        let budget = self.config.minimize_tests;
+        let finding = match self.findings.last() {
+            Some(f)
+                if budget > 0
+                    && f.kind == FindingKind::Crash
+                    && f.reproduction
+                        .as_ref()
+                        .is_none_or(|r| r.verdict() == Verdict::Deterministic) =>
+            {
+                f.clone()
+            }
+            _ => return Ok(()),
+        };
+        // Verification may have cut the window down to its last cases
+        let keep = finding
+            .reproduction
+            .as_ref()
+            .map_or(finding.inputs.len(), |r| r.reproducer.len());
+        let cases = finding.inputs[finding.inputs.len() - keep..].to_vec();
+        let before: usize = cases.iter().map(|c| c.args.len()).sum();
+        let mut minimizer = Minimizer::new(cases, budget);
+        while let Some(candidate) = minimizer.propose() {
+            let reproduced = self.trial(finding.kind, &candidate).await?;
+            minimizer.report(reproduced);
+        }
+        let tests = minimizer.tests();
+        let cases = minimizer.finish();
+        let dir = minimize::path_for(&finding.path);
+        let corpus = Corpus::open(&dir);
+        for (i, input) in cases.iter().enumerate() {
+            let stem = format!("{:03}_{}", i, input.name);
+            corpus.write(&stem, input, &input.message(&self.config.identity))?;
+        }
+        Endpoint::of(&self.config).save(&dir)?;
+        info!(
+            "{} minimized in {} tests: {} cases, {} of {} argument bytes, saved to {}",
+            finding.name,
+            tests,
+            cases.len(),
+            cases.iter().map(|c| c.args.len()).sum::<usize>(),
+            before,
+            dir.display()
+        );
+        if let Some(f) = self.findings.last_mut() {
+            f.minimized = Some(dir);
+        }
+        Ok(())
+    }
+
+    /// Send one case and judge what came of it
+    pub async fn run_case(&mut self, input: &Input) -> Result<(), FuzzError> {
+        if self.gss.is_some() && is_gss_window(input) {
+            return self.run_gss_case(input).await;
+        }
+        if self.session.is_some() && is_reply_cache(input) {
+            return self.run_reply_cache(input).await;
+        }
+        let (input, guesses) = self.sweep_stateids(input);
+        let input = &self.on_session(&input);
+        let (msg, seq) = self.message(input).await;
+        // Over the memory ceiling the oldest cases of the window go first
+        let _ = self.window.push(input.clone(), input.footprint());
+        self.sent += 1;
+        self.stats.record_input(&input.lineage);
+        let (at, start) = (SystemTime::now(), Instant::now());
+        let result = match seq {
+            Some(seq) => self
+                .call(&msg)
+                .await
+                .map(|reply| self.unwrap_reply(seq, reply)),
+            None => self.call(&msg).await,
+        };
+        if self.results.is_some() || self.db.is_some() {
+            let status = match &result {
+                Ok(reply) => status_name(input, reply),
+                Err(ConnectionError::Timeout { .. }) => "timeout".to_string(),
+                Err(_) => "closed".to_string(),
+            };
+            let record = Record::new(input, &msg, at, &status, start.elapsed());
+            if let Some(log) = &mut self.results {
+                if let Err(e) = log.append(&record) {
+                    warn!("Could not write the results log: {}", e);
+                }
+            }
+            if let Some(Err(e)) = self.db.as_mut().map(|db| db.iteration(&record)) {
+                warn!("Could not write the results database, so nothing more goes into it: {}", e);
+                self.db = None;
+            }
+        }
+        self.cover(input).await?;
+        let signature = match &result {
+            Ok(reply) => Signature::of_reply(input, reply),
+            Err(ConnectionError::Timeout { .. }) => {
+                Signature::of_outcome(input, Connection::Timeout)
+            }
+            Err(_) => Signature::of_outcome(input, Connection::Closed),
+        };
+        self.stats.record_signature(&signature, &input.lineage);
+        self.signature = Some(signature);
+        if self.monitor.is_some() {
+            let _ = self.recent.push((start, input.clone()), input.footprint());
+            self.watch_kernel().await?;
+        }
+        match result {
+            Ok(reply) => {
+                self.stats
+                    .record(&status_name(input, &reply), start.elapsed());
+                let mut violations = Vec::new();
+                if let Ok(reply) = RpcReply::parse(&reply) {
+                    let verdict = AuthVerdict::of(&reply);
+                    if let (Some(verdict), Some(cred)) = (verdict, oracle::call_credential(&msg)) {
+                        // Exports may take a credential for some calls
+                        // and not others, so it is judged per procedure
+                        let mut key = cred.to_vec();
+                        for word in [input.program, input.version, input.procedure] {
+                            key.extend(word.to_be_bytes());
+                        }
+                        if let Some(flip) = self.auth.record(&key, verdict).cloned() {
+                            let flips = self.auth.flips.iter();
+                            // Only the first change of each, not every
+                            // swing back and forth after it
+                            if flips.filter(|f| f.credential == flip.credential).count() == 1 {
+                                let detail = format!(
+                                    "{:?} became {:?}, {} calls after it was first seen",
+                                    flip.before,
+                                    flip.after,
+                                    flip.changed_at - flip.first_seen
+                                );
+                                violations.push((Oracle::AuthFlip, detail));
+                            }
+                        }
+                    }
+                    if let Some(table) = &mut self.session {
+                        table.record(reply.results);
+                    }
+                    if (input.program, input.version) == (program::NFS, 4) {
+                        self.state.observe(reply.results);
+                    }
+                    let (prog, vers, proc) = (input.program, input.version, input.procedure);
+                    if let Some((source, verifier)) =
+                        oracle::boot_verifier(prog, vers, proc, reply.results)
+                    {
+                        if let Some(restart) = self.restarts.observe(source, &verifier, self.sent) {
+                            let restart = restart.clone();
+                            self.tag_probable_crashes(&restart);
+                            let detail = format!(
+                                "{:?} changed after call {}, {} findings since",
+                                source,
+                                restart.last_unchanged,
+                                restart.probable_crashes.len()
+                            );
+                            violations.push((Oracle::Restart, detail));
+                        }
+                    }
+                    if (prog, vers, proc) == (program::NFS, 4, nfsv4::PROC_COMPOUND) {
+                        violations.extend(nfsv4::replies::check(&input.args, reply.results));
+                    }
+                    if let (Some((export, advertised)), Some(flavor)) =
+                        (&self.secinfo, downgraded(input))
+                    {
+                        // A compound's status is that of the last op it ran
+                        let last = reply
+                            .results
+                            .get(..4)
+                            .map(|w| u32::from_be_bytes(w.try_into().unwrap()));
+                        let bypass = last.and_then(|last| {
+                            secinfo::check_probe(advertised, export, &input.name, flavor, last)
+                        });
+                        if let Some(bypass) = bypass {
+                            violations.push((Oracle::SecPolicy, bypass.to_string()));
+                        }
+                    }
+                    let latency = start.elapsed();
+                    if let Some((mask, got)) = is_sweep(input)
+                        .then(|| attrsweep::decode_getattr(&input.args, reply.results, latency))
+                        .flatten()
+                    {
+                        for anomaly in self.sweep.judge(&mask, &got, status::NFS4_OK) {
+                            violations.push((Oracle::AttrSweep, format!("{:?}", anomaly)));
+                        }
+                        self.sweep.record(&mask, got);
+                    }
+                    if let Some(guesses) = &guesses {
+                        let statuses = stateids::decode_test_stateid(reply.results);
+                        let hits = self
+                            .stateids
+                            .record_results(guesses, &statuses.unwrap_or_default());
+                        for hit in hits {
+                            let detail = match hit {
+                                Hit::Fabricated(s) => {
+                                    format!("fabricated stateid {:?} reported valid", s)
+                                }
+                                Hit::Seqid(s) => {
+                                    format!(
+                                        "stateid {:?} reported valid under a seqid it never had",
+                                        s
+                                    )
+                                }
+                            };
+                            violations.push((Oracle::StateidLeak, detail));
+                        }
+                    }
+                }
+                for (oracle, detail) in violations {
+                    self.record_violation(oracle, input, &input.name, &msg, &detail)?;
+                }
+            }
+            Err(ConnectionError::Timeout { .. }) => {
+                self.stats.record("timeout", start.elapsed());
+                // A late reply could be taken for the probe's, so probe
+                // on a new connection
+                self.conn = None;
+                let kind = if self.alive().await {
+                    HangKind::Request
+                } else {
+                    HangKind::Server
+                };
+                self.record_hang(input, &msg, kind)?;
+                self.collect_logs().await?;
+                if kind == HangKind::Server {
+                    self.wait_for_restart().await?;
+                }
+                self.verify_last().await?;
+                self.minimize_last().await?;
+            }
+            Err(e) => {
+                // Servers close connections on garbage all the time;
+                // only a failed probe makes it a crash
+                self.stats.record("closed", start.elapsed());
+                info!("{}: {}", input.name, e);
+                self.conn = None;
+                return self.probe().await;
+            }
+        }
+        if self.sent.is_multiple_of(self.config.probe_every.max(1)) {
+            self.probe().await?;
+        }
+        Ok(())
+    }
+
+    /// The call of `input`: under the GSS context when cases go out under
+    /// it and the input carries no credential of its own, with the
+    /// sequence number its reply is checked against
+    async fn message(&mut self, input: &Input) -> (BytesMut, Option<u32>) {
+        if self.gss_auth && input.auth.is_none() {
+            let target = (input.program, input.version);
+            if let Some(ctx) = self.gss.as_mut().filter(|ctx| ctx.target() == target) {
+                match ctx.call(input.procedure, &input.args) {
+                    Ok((seq, msg)) => return (msg, Some(seq)),
+                    Err(e) => warn!("{}: {}", input.name, e),
+                }
+            }
+        }
+        (input.message(&self.identity().await), None)
+    }
+
+    /// A reply to the GSS call with sequence number `seq` with its
+    /// results unwrapped, so it reads like any other; one whose verifier
+    /// or checksum does not check out is kept as it came
+    fn unwrap_reply(&self, seq: u32, reply: Vec<u8>) -> Vec<u8> {
+        let Some(ctx) = &self.gss else {
+            return reply;
+        };
+        let Ok(parsed) = RpcReply::parse(&reply) else {
+            return reply;
+        };
+        match ctx.results(seq, &parsed) {
+            Ok(results) => {
+                let mut plain = reply[..reply.len() - parsed.results.len()].to_vec();
+                plain.extend(results);
+                plain
+            }
+            Err(e) => {
+                info!("GSS reply to sequence number {}: {}", seq, e);
+                reply
+            }
+        }
+    }
+
+    /// Send the checksum and channel binding calls of the GSS context,
+    /// then run its window plans on a connection of their own; whatever
+    /// the server carried out or answered wrongly is a finding
+    async fn run_gss_case(&mut self, input: &Input) -> Result<(), FuzzError> {
+        // NULL, the call with the least side effects
+        let (procedure, args) = (0, Vec::new());
+        let calls = match &self.gss {
+            Some(ctx) => ctx.mic_calls(procedure, &args).and_then(|mut calls| {
+                calls.extend(ctx.bind_channel_calls(&[])?);
+                Ok(calls)
+            }),
+            None => return Ok(()),
+        };
+        let calls = match calls {
+            Ok(calls) => calls,
+            Err(e) => {
+                warn!("GSS checksum calls for {}: {}", input.name, e);
+                return Ok(());
+            }
+        };
+        self.sent += 1;
+        self.stats.record_input(&input.lineage);
+        let start = Instant::now();
+        for (name, msg) in calls {
+            match self.call(&msg).await {
+                Ok(reply) => {
+                    if RpcReply::parse(&reply).is_ok_and(|r| r.is_success()) {
+                        let detail = format!("{} carried out", name);
+                        self.record_violation(Oracle::GssSequence, input, &name, &msg, &detail)?;
+                    }
+                }
+                Err(e) => {
+                    info!("{}: {}", name, e);
+                    self.conn = None;
+                }
+            }
+        }
+        // The window plans skip the context's sequence numbers ahead, so
+        // they go on a fresh connection and leave it to be dropped
+        self.conn = None;
+        if let Err(e) = self.connection().await {
+            info!("{}: {}", input.name, e);
+            return self.probe().await;
+        }
+        let violations = match (&mut self.conn, &mut self.gss) {
+            (Some((Transport::Tcp(conn), _)), Some(ctx)) => {
+                gss::probe_window(conn, ctx, procedure, &args).await
+            }
+            _ => Ok(Vec::new()),
+        };
+        self.conn = None;
+        match violations {
+            Ok(violations) => {
+                self.stats.record("gss_window", start.elapsed());
+                let msg = input.message(&self.config.identity);
+                for (i, violation) in violations.iter().enumerate() {
+                    let name = format!("{}_{}", input.name, i);
+                    self.record_violation(Oracle::GssSequence, input, &name, &msg, violation)?;
+                }
+            }
+            Err(e) => warn!("GSS window probe failed: {}", e),
+        }
+        self.probe().await
+    }
+
+    /// Run every replay plan of every expensive compound on the last slot
+    /// of the session, each plan from where the slot then stands; a step
+    /// the server answers with the wrong SEQUENCE status, or a replay
+    /// that is not the reply it cached, is a finding
+    async fn run_reply_cache(&mut self, input: &Input) -> Result<(), FuzzError> {
+        self.sent += 1;
+        self.stats.record_input(&input.lineage);
+        let start = Instant::now();
+        let identity = self.identity().await;
+        let (dir, file) = (state::filehandle(1), state::filehandle(0));
+        let decoy = [nfsv4::putrootfh()];
+        for (name, ops) in replycache::high_cost_compounds(&dir, &file) {
+            let mut n = 0;
+            loop {
+                let Some(slot) = self
+                    .session
+                    .as_ref()
+                    .and_then(|t| t.slot(t.highest_slotid()))
+                else {
+                    return Ok(());
+                };
+                let Some(plan) = replay_plans(&slot, name, &ops, &decoy).into_iter().nth(n) else {
+                    break;
+                };
+                n += 1;
+                let mut replies = Vec::new();
+                for (i, step) in plan.steps.iter().enumerate() {
+                    let args = CompoundBuilder::new(minor_version::V4_1)
+                        .ops(step.all_ops())
+                        .build();
+                    let step_input = Input {
+                        name: format!("{}_{}", plan.name, i),
+                        args: self.state.resolve(&args),
+                        ..input.clone()
+                    };
+                    let msg = step_input.message(&identity);
+                    let reply = match self.call(&msg).await {
+                        Ok(reply) => reply,
+                        Err(e) => {
+                            info!("{}: {}", step_input.name, e);
+                            self.conn = None;
+                            break;
+                        }
+                    };
+                    let Ok(reply) = RpcReply::parse(&reply) else {
+                        break;
+                    };
+                    let Some(table) = &mut self.session else {
+                        break;
+                    };
+                    let sequence = table.record(reply.results).unwrap_or(u32::MAX);
+                    if let Err(e) =
+                        replycache::check_replay(step, sequence, reply.results, &replies)
+                    {
+                        let detail = format!("{}: {}", step_input.name, e);
+                        let name = step_input.name.clone();
+                        self.record_violation(
+                            Oracle::ReplyCache,
+                            &step_input,
+                            &name,
+                            &msg,
+                            &detail,
+                        )?;
+                    }
+                    replies.push(reply.results.to_vec());
+                }
+            }
+        }
+        self.stats.record("reply_cache", start.elapsed());
+        self.probe().await
+    }
+
+    /// Run `inputs` through [`Fuzzer::run_case`], holding each one while
+    /// `gate` is paused, then save the statistics
+    pub async fn run(
+        &mut self,
+        inputs: impl IntoIterator<Item = Input>,
+        gate: &Gate,
+    ) -> Result<(), FuzzError> {
+        let mut inputs = inputs.into_iter();
+        let mut failures = 0;
+        loop {
+            let input = match isolate::catch(|| inputs.next()) {
+                Ok(Some(input)) => input,
+                Ok(None) => break,
+                Err(message) => {
+                    error!("Input generation panicked: {}", message);
+                    self.stats.internal_errors += 1;
+                    failures += 1;
+                    if failures == MAX_INTERNAL_ERRORS {
+                        return Err(FuzzError::Internal(message));
+                    }
+                    continue;
+                }
+            };
+            gate.wait_running().await;
+            let message = match isolate::panicked(&input.lineage) {
+                Some(message) => message,
+                None => match isolate::catch_future(self.run_case(&input)).await {
+                    Ok(result) => {
+                        result?;
+                        failures = 0;
+                        let sent = self.sent;
+                        self.save_checkpoint(|every| every > 0 && sent.is_multiple_of(every))?;
+                        continue;
+                    }
+                    Err(message) => {
+                        // The case may have been cut off half-way through
+                        // its exchange
+                        self.conn = None;
+                        message
+                    }
+                },
+            };
+            self.record_internal(&input, &message)?;
+            failures += 1;
+            if failures == MAX_INTERNAL_ERRORS {
+                return Err(FuzzError::Internal(message));
+            }
+        }
+        self.stats.save(&self.config.output)?;
+        self.save_checkpoint(|_| true)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hang::read_record;
+    use crate::lineage::Lineage;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    /// NULL procedure of NFS v3, or `procedure` with empty arguments
+    fn input(name: &str, procedure: u32) -> Input {
+        Input {
+            name: name.to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure,
+            args: Vec::new(),
+            lineage: Lineage::new(name),
+            auth: None,
+        }
+    }
+
+    /// An NFS server that answers every call with status `procedure`,
+    /// dies for a while on procedure 99, never answers procedure 98,
+    /// turns the first call of procedure 97 away as AUTH_TOOWEAK and
+    /// answers WRITE, procedure 7, with a new verifier after each death
+    async fn server(listener: TcpListener) {
+        let addr = listener.local_addr().unwrap();
+        let mut listener = Some(listener);
+        let mut turned_away = false;
+        let mut boots = 0u64;
+        loop {
+            let (mut s, _) = listener.as_ref().unwrap().accept().await.unwrap();
+            while let Ok(call) = read_record(&mut s).await {
+                let procedure = u32::from_be_bytes(call[20..24].try_into().unwrap());
+                match procedure {
+                    97 if !turned_away => {
+                        turned_away = true;
+                        let mut reply = vec![0x80, 0, 0, 20];
+                        reply.extend_from_slice(&call[..4]);
+                        for word in [1, 1, 1, 5] {
+                            reply.extend_from_slice(&u32::to_be_bytes(word));
+                        }
+                        if s.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    7 => {
+                        // WRITE3resok, the verifier the times restarted
+                        let mut reply = vec![0x80, 0, 0, 52];
+                        reply.extend_from_slice(&call[..4]);
+                        for word in [1, 0, 0, 0, 0, 0, 0, 0, 0, 0] {
+                            reply.extend_from_slice(&u32::to_be_bytes(word));
+                        }
+                        reply.extend_from_slice(&boots.to_be_bytes());
+                        if s.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    98 => continue,
+                    99 => {
+                        boots += 1;
+                        drop(s);
+                        drop(listener.take());
+                        tokio::time::sleep(Duration::from_millis(300)).await;
+                        listener = Some(TcpListener::bind(addr).await.unwrap());
+                        break;
+                    }
+                    _ => {}
+                }
+                let mut reply = vec![0x80, 0, 0, 28];
+                reply.extend_from_slice(&call[..4]);
+                for word in [1, 0, 0, 0, 0, procedure] {
+                    reply.extend_from_slice(&u32::to_be_bytes(word));
+                }
+                if s.write_all(&reply).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn config(addr: SocketAddr, name: &str) -> FuzzConfig {
+        let output =
+            std::env::temp_dir().join(format!("nfs-fuzzer-{}-{}", name, std::process::id()));
+        FuzzConfig {
+            budget: LatencyBudget {
+                connect: Duration::from_millis(100),
+                request: Duration::from_millis(100),
+                probe: Duration::from_millis(100),
+            },
+            probe_every: 2,
+            restart_wait: Duration::from_secs(5),
+            verify_trials: 0,
+            minimize_tests: 0,
+            ..FuzzConfig::new(addr, output)
+        }
+    }
+
+    /// Counts restarts and hands out fixed logs
+    #[derive(Debug, Default)]
+    struct Recorder {
+        restarts: std::sync::atomic::AtomicU32,
+    }
+
+    impl TargetController for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        fn restart_service(&self) -> Result<(), ControllerError> {
+            self.restarts
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn reboot(&self) -> Result<(), ControllerError> {
+            unreachable!("the service restarts")
+        }
+
+        fn collect_logs(&self) -> Result<Vec<u8>, ControllerError> {
+            Ok(b"nfsd: oops".to_vec())
+        }
+
+        fn snapshot(&self, _name: &str) -> Result<(), ControllerError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_crash_is_recorded_and_campaign_continues() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = config(addr, "crash");
+        let output = config.output.clone();
+        let recorder = Arc::new(Recorder::default());
+        let mut fuzzer = Fuzzer::new(config).with_controller(recorder.clone());
+        let inputs = vec![
+            input("ok", 0),
+            input("err", 22),
+            input("before", 1),
+            input("killer", 99),
+            input("after", 0),
+        ];
+        fuzzer.run(inputs, &Gate::new()).await.unwrap();
+        assert_eq!(fuzzer.sent(), 5);
+        assert_eq!(fuzzer.findings.len(), 1);
+        let finding = &fuzzer.findings[0];
+        assert_eq!(
+            (finding.kind, finding.name.as_str()),
+            (FindingKind::Crash, "killer")
+        );
+        // The window since the probe after "err": "before" and "killer"
+        assert!(finding.path.join("001_killer.bin").exists());
+        assert!(finding.path.join("000_before.json").exists());
+        assert_eq!(Endpoint::load(&finding.path).unwrap().target, addr);
+        let logs = std::fs::read(finding.path.join("target.log")).unwrap();
+        assert_eq!(logs, b"nfsd: oops");
+        assert_eq!(
+            recorder.restarts.load(std::sync::atomic::Ordering::SeqCst),
+            1
+        );
+        let stats = CampaignStats::load(&output).unwrap();
+        assert_eq!(stats.statuses["NFS3_OK"], 2);
+        assert_eq!(stats.statuses["NFS3ERR_22"], 1);
+        assert_eq!(stats.strategies["unmutated"].crashes, 1);
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_crash_is_verified_and_minimized() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = FuzzConfig {
+            verify_trials: 2,
+            minimize_tests: 10,
+            ..config(addr, "verify")
+        };
+        let output = config.output.clone();
+        let mut fuzzer = Fuzzer::new(config);
+        let killer = Input {
+            args: vec![7; 12],
+            ..input("killer", 99)
+        };
+        let inputs = vec![input("before", 1), killer];
+        fuzzer.run(inputs, &Gate::new()).await.unwrap();
+        let finding = &fuzzer.findings[0];
+        assert_eq!(finding.inputs.len(), 2);
+        let repro = finding.reproduction.as_ref().unwrap();
+        assert_eq!(repro.reproducer, ["killer"]);
+        assert_eq!(repro.verdict(), crate::repro::Verdict::Deterministic);
+        assert_eq!(&Reproduction::load(&finding.path).unwrap(), repro);
+        // Procedure 99 kills the server whatever its arguments
+        let minimized = finding.minimized.as_ref().unwrap();
+        let calls = crate::replay::calls(minimized).unwrap();
+        let bare = input("killer", 99).message(&Identity::new(0, 0));
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].1.len(), bare.len());
+        assert_eq!(Endpoint::load(minimized).unwrap().target, addr);
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_governor_bounds_connections_and_window() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let governor = Governor::new(crate::limits::Limits {
+            max_memory: 2 * input("ok", 0).footprint(),
+            max_connections: 1,
+            max_pending: 1,
+        });
+        let config = FuzzConfig {
+            probe_every: 100,
+            governor: governor.clone(),
+            ..config(addr, "governor")
+        };
+        let output = config.output.clone();
+        let mut fuzzer = Fuzzer::new(config);
+        for _ in 0..5 {
+            fuzzer.run_case(&input("ok", 0)).await.unwrap();
+        }
+        assert_eq!(governor.free_connections(), 0);
+        // Only the two newest cases fit the memory ceiling
+        assert_eq!(fuzzer.window.len(), 2);
+        assert_eq!(fuzzer.window.dropped, 3);
+        drop(fuzzer);
+        assert_eq!(governor.free_connections(), 1);
+        assert_eq!(governor.memory.used(), 0);
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    #[tokio::test]
+    async fn test_identities_rotate_per_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let pool = IdentityPool::new(
+            vec![Identity::new(1, 1), Identity::new(2, 2)],
+            crate::auth::Rotation::PerConnection,
+        );
+        let config = FuzzConfig {
+            identities: Some(pool),
+            ..config(addr, "identities")
+        };
+        let mut fuzzer = Fuzzer::new(config);
+        assert_eq!(fuzzer.identity().await.uid, 1);
+        assert_eq!(fuzzer.identity().await.uid, 1);
+        fuzzer.conn = None;
+        assert_eq!(fuzzer.identity().await.uid, 2);
+        fuzzer.conn = None;
+        assert_eq!(fuzzer.identity().await.uid, 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_hang() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = config(addr, "hang");
+        let output = config.output.clone();
+        std::fs::create_dir_all(&output).unwrap();
+        let capture = pcap::Writer::create(output.join("traffic.pcapng")).unwrap();
+        let mut fuzzer = Fuzzer::new(config).with_capture(capture);
+        fuzzer.run_case(&input("stuck", 98)).await.unwrap();
+        fuzzer.run_case(&input("ok", 0)).await.unwrap();
+        assert_eq!(fuzzer.findings.len(), 1);
+        assert_eq!(
+            fuzzer.findings[0].kind,
+            FindingKind::Hang(HangKind::Request)
+        );
+        assert!(fuzzer.findings[0].path.exists());
+        assert_eq!(fuzzer.stats.statuses["timeout"], 1);
+        assert_eq!(fuzzer.stats.statuses["NFS3_OK"], 1);
+        // Headers, then the handshake and calls of each connection
+        let capture = std::fs::metadata(output.join("traffic.pcapng")).unwrap();
+        assert!(capture.len() > 28 + 20 + 3 * 72);
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_internal_errors_are_saved_and_campaign_continues() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = config(addr, "internal");
+        let output = config.output.clone();
+        let mut fuzzer = Fuzzer::new(config).with_controller(Arc::new(Recorder::default()));
+        let inputs = (0..4).map(|i| match i {
+            0 => input("ok", 0),
+            1 => panic!("generator bug"),
+            2 => {
+                let mut broken = input("broken", 0);
+                broken.lineage = broken.lineage.then(isolate::panic_step("mutator bug"));
+                broken
+            }
+            _ => input("after", 0),
+        });
+        fuzzer.run(inputs, &Gate::new()).await.unwrap();
+        assert_eq!(fuzzer.sent(), 2);
+        assert_eq!(fuzzer.stats.internal_errors, 2);
+        let dir = output.join("internal").join("00000001_broken");
+        assert!(dir.join("broken.bin").exists());
+        let message = std::fs::read_to_string(dir.join("panic.txt")).unwrap();
+        assert_eq!(message, "mutator bug\n");
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gss_window_case_runs_on_the_context() {
+        use crate::gss::{Context, Service};
+        use crate::nfsv4::ssv::{SsvKey, SsvMech};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = config(addr, "gss");
+        let output = config.output.clone();
+        let mech = SsvMech {
+            key: SsvKey::initial(32),
+        };
+        let ctx = Context::new(mech, vec![7; 8], Service::Integrity, 8, program::NFS, 4);
+        let mut fuzzer = Fuzzer::new(config)
+            .with_controller(Arc::new(Recorder::default()))
+            .with_gss(ctx.boxed());
+        let mut window = input("gss_window", 0);
+        window.lineage = Lineage::new("GssWindow:gss_window");
+        fuzzer.run(vec![window], &Gate::new()).await.unwrap();
+        // The server carries out everything, so every call is a finding
+        assert_eq!(fuzzer.sent(), 1);
+        assert!(fuzzer.findings.len() > 1);
+        for finding in &fuzzer.findings {
+            assert_eq!(finding.kind, FindingKind::Oracle(Oracle::GssSequence));
+            assert!(finding.path.join("violation.txt").exists());
+        }
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_credential_accepted_after_denial_is_a_finding() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = config(addr, "authflip");
+        let output = config.output.clone();
+        let mut fuzzer = Fuzzer::new(config.clone()).with_controller(Arc::new(Recorder::default()));
+        let inputs = vec![
+            input("denied", 97),
+            input("other", 0),
+            input("accepted", 97),
+        ];
+        fuzzer.run(inputs, &Gate::new()).await.unwrap();
+        assert_eq!(fuzzer.findings.len(), 1);
+        let finding = &fuzzer.findings[0];
+        assert_eq!(finding.kind, FindingKind::Oracle(Oracle::AuthFlip));
+        assert_eq!(finding.name, "accepted");
+        let detail = std::fs::read_to_string(finding.path.join("violation.txt")).unwrap();
+        assert!(
+            detail.starts_with("Denied(5) became Accepted"),
+            "{}",
+            detail
+        );
+        std::fs::remove_dir_all(&output).unwrap();
+
+        // A campaign that does not check AuthFlip records nothing
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let unchecked = FuzzConfig {
+            target: addr,
+            oracles: vec![Oracle::Liveness, Oracle::Hang],
+            ..config
+        };
+        let output = unchecked.output.clone();
+        let mut fuzzer = Fuzzer::new(unchecked).with_controller(Arc::new(Recorder::default()));
+        let inputs = vec![input("denied", 97), input("accepted", 97)];
+        fuzzer.run(inputs, &Gate::new()).await.unwrap();
+        assert!(fuzzer.findings.is_empty());
+        let _ = std::fs::remove_dir_all(&output);
+    }
+
+    #[tokio::test]
+    async fn test_changed_write_verifier_tags_the_crash_before_it() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(server(listener));
+        let config = config(addr, "restart");
+        let output = config.output.clone();
+        let mut fuzzer = Fuzzer::new(config).with_controller(Arc::new(Recorder::default()));
+        let inputs = vec![input("first", 7), input("killer", 99), input("second", 7)];
+        fuzzer.run(inputs, &Gate::new()).await.unwrap();
+        assert_eq!(fuzzer.findings.len(), 2);
+        let (crash, restart) = (&fuzzer.findings[0], &fuzzer.findings[1]);
+        assert_eq!(crash.kind, FindingKind::Crash);
+        assert_eq!(restart.kind, FindingKind::Oracle(Oracle::Restart));
+        assert_eq!(restart.name, "second");
+        assert!(crash.path.join("restart.txt").exists());
+        let detail = std::fs::read_to_string(restart.path.join("violation.txt")).unwrap();
+        assert!(
+            detail.starts_with("V3WriteVerf changed after call 1"),
+            "{}",
+            detail
+        );
+        std::fs::remove_dir_all(&output).unwrap();
+    }
+}
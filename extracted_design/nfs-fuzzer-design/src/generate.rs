@@ -0,0 +1,878 @@
+//! Offline test-case generation (`--generate-only`)
+//!
+//! Runs the generation and mutation pipeline without a target: the
+//! built-in seeds and the cases of every strategy that needs no server
+//! state come first, then variants made by the mutation engine, each
+//! with its lineage. Cases are written to `<output>/generated/` as corpus
+//! entries, a record-marked call (`.bin`) plus a `.json` note, for
+//! inspecting what a strategy produces or for feeding other tools.
+//!
+//! v4 strategy cases are framed as a COMPOUND starting at PUTROOTFH, and
+//! single-operation seeds as a sandwich reaching a file under the root.
+//! The SEQUENCE a live v4.1 campaign puts first depends on the session it
+//! creates, so it is absent here.
+//!
+//! Cases that are about the RPC credential itself, such as the
+//! RPCSEC_GSS control calls of [`gss::control_cases`], carry it raw in
+//! [`Input::auth`] rather than taking the campaign's identity.
+//!
+//! Calls lifted from a packet capture (see [`capture_inputs`]) can join
+//! the base inputs, so mutation also starts from what a real client
+//! sends.
+
+use crate::auth::{self, Identity};
+use crate::corpus::{Corpus, CorpusError};
+use crate::crossfh;
+use crate::gss;
+use crate::isolate;
+use crate::kcov::Pool;
+use crate::lineage::Lineage;
+use crate::mutations::Engine;
+use crate::nfsv4::pnfs::{self, layout_iomode, layout_type, GrantedLayout, LayoutCommit};
+use crate::nfsv4::referral::Pathname;
+use crate::nfsv4::sandwich::Target;
+use crate::nfsv4::state::{self, Kind};
+use crate::nfsv4::{
+    self, delegation, flexfiles, lockowner, namedattr, reexport, referral, replycache, savedfh,
+    secinfo, sparse, stateids, times, CompoundBuilder, FuzzCase, Op, Stateid,
+};
+use crate::preset::Strategy;
+use crate::rpc::{auth_flavor, auth_none, next_xid, program, RpcCall};
+use crate::seeds;
+use crate::webnfs;
+use crate::xdr::XdrDecoder;
+use bytes::BytesMut;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum GenerateError {
+    #[error("{0:?} produces no cases")]
+    NoCases(Strategy),
+}
+
+/// Credential and verifier of a call, each an encoded `opaque_auth`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawAuth {
+    pub cred: Vec<u8>,
+    pub verf: Vec<u8>,
+}
+
+/// One generated call, before credentials and framing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input {
+    pub name: String,
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    pub args: Vec<u8>,
+    pub lineage: Lineage,
+    /// Credential and verifier sent instead of the identity's
+    pub auth: Option<RawAuth>,
+}
+
+impl Input {
+    /// The record-marked call carrying this input
+    pub fn message(&self, identity: &Identity) -> BytesMut {
+        let call = RpcCall::new(next_xid(), self.program, self.version, self.procedure, true);
+        match &self.auth {
+            Some(auth) => call.with_auth(&auth.cred, &auth.verf),
+            None => call.with_auth(&identity.credential(), &auth_none()),
+        }
+        .with_args(&self.args)
+        .build()
+    }
+
+    /// Bytes this input holds, as charged to a [`crate::limits::MemoryBudget`]
+    pub fn footprint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.name.len()
+            + self.args.len()
+            + self.lineage.seed.len()
+            + self.lineage.steps.len() * 32
+            + self
+                .auth
+                .as_ref()
+                .map_or(0, |a| a.cred.len() + a.verf.len())
+    }
+}
+
+/// COMPOUND4args for v4.1: empty tag, PUTROOTFH, then `ops`
+pub fn compound_args(ops: &[Op]) -> Vec<u8> {
+    CompoundBuilder::new(nfsv4::minor_version::V4_1)
+        .putrootfh()
+        .ops(ops.iter().cloned())
+        .build()
+}
+
+/// File the framing of single-operation seeds reaches
+pub const SEED_FILE: &[u8] = b"fuzz.seed";
+
+fn v4_input(seed: String, name: &str, args: Vec<u8>) -> Input {
+    Input {
+        name: name.to_string(),
+        program: program::NFS,
+        version: 4,
+        procedure: nfsv4::PROC_COMPOUND,
+        args,
+        lineage: Lineage::new(seed),
+        auth: None,
+    }
+}
+
+/// Strategies with cases that need no server state
+pub const STATELESS: &[Strategy] = &[
+    Strategy::AttrSweep,
+    Strategy::AuthSys,
+    Strategy::Delegations,
+    Strategy::NamedAttrs,
+    Strategy::LockOwners,
+    Strategy::PublicFh,
+    Strategy::Referrals,
+    Strategy::SavedFh,
+    Strategy::StateProtection,
+    Strategy::GssControl,
+];
+
+/// Strategies whose inputs walk to the export or run against files made
+/// on it: [`export_inputs`], [`cross_version_inputs`] and the scenario,
+/// mixed, race, reclaim and NSM runners give them, not [`base_inputs`]
+pub const NEED_EXPORT: &[Strategy] = &[
+    Strategy::DeepTree,
+    Strategy::LongNames,
+    Strategy::HardLinks,
+    Strategy::CrossVersionFh,
+    Strategy::AuthDowngrade,
+    Strategy::MixedVersion,
+    Strategy::LockReclaim,
+    Strategy::NsmSpoof,
+    Strategy::OpRaces,
+    Strategy::ReExportLoops,
+];
+
+/// `cases` on the latest harvested filehandle
+fn on_file(cases: Vec<FuzzCase>) -> Vec<FuzzCase> {
+    let fh = state::filehandle(0);
+    cases
+        .into_iter()
+        .map(|mut case| {
+            case.ops.insert(0, nfsv4::putfh(&fh));
+            case
+        })
+        .collect()
+}
+
+/// Extents the sparse-file cases aim at: data in the first page and at
+/// 1 MiB, a hole between
+const EXTENTS: &[(u64, u64)] = &[(0, 4096), (1 << 20, 4096)];
+
+/// Sparse-file cases on the latest harvested open: SEEK, ALLOCATE and
+/// DEALLOCATE at extent edges, a hole punched and probed back, and
+/// READ_PLUS at the edges, whose replies the fuzzer checks
+fn sparse_cases() -> Vec<FuzzCase> {
+    let open = state::stateid(Kind::Open, 0);
+    let mut cases = sparse::seek_cases(&open, EXTENTS);
+    cases.extend(sparse::allocate_cases(&open, EXTENTS));
+    cases.extend(sparse::hole_punch_cases(&open, 0, 1 << 20));
+    cases.extend(sparse::read_plus_cases(&open, EXTENTS));
+    on_file(cases)
+}
+
+/// pNFS cases on harvested state: LAYOUTGETs to harvest from, device
+/// list and info requests, layout returns and commits the server never
+/// granted or that outlive their delegation, flex-files return bodies
+/// naming the harvested devices, and flex-files layouts as LAYOUTCOMMIT
+/// updates, which RFC 8435 leaves empty
+fn layout_cases() -> Vec<FuzzCase> {
+    let open = state::stateid(Kind::Open, 0);
+    let devices = [state::deviceid(0), state::deviceid(1)];
+    let granted = |layout_type| GrantedLayout {
+        stateid: state::stateid(Kind::Layout, 0),
+        layout_type,
+        iomode: layout_iomode::RW,
+        offset: 0,
+        length: u64::MAX,
+    };
+    let files = granted(layout_type::NFSV4_1_FILES);
+    let get = |name: &str, layout| {
+        let op = pnfs::layoutget(layout, layout_iomode::RW, 0, u64::MAX, 0, &open, 4096);
+        FuzzCase::new(name, vec![op])
+    };
+    let mut cases = vec![
+        get("layoutget_files", layout_type::NFSV4_1_FILES),
+        get("layoutget_flex_files", layout_type::FLEX_FILES),
+    ];
+    cases.extend(pnfs::getdevicelist_cases());
+    cases.extend(pnfs::getdeviceinfo_cases(&devices[..1]));
+    cases.extend(pnfs::return_ungranted_cases(&open));
+    cases.extend(pnfs::commit_beyond_grant_cases(&files));
+    let delegation = state::stateid(Kind::Delegation, 0);
+    cases.extend(pnfs::after_delegreturn_cases(&delegation, &files));
+    let flex = granted(layout_type::FLEX_FILES);
+    cases.extend(flexfiles::layoutreturn_cases(&flex, &devices));
+    for (name, body) in flexfiles::layout_bodies(&devices[0], &state::filehandle(0)) {
+        let mut commit = LayoutCommit::covering(&flex);
+        commit.body = body;
+        cases.push(FuzzCase::new(
+            format!("ff_commit_{}", name),
+            vec![commit.op()],
+        ));
+    }
+    on_file(cases)
+}
+
+/// Cases a strategy can produce without server state, or with
+/// placeholders for the state the fuzzer harvests (see
+/// [`nfsv4::state`]); strategies with calls of their own or that need
+/// the export yield none here
+pub fn strategy_cases(strategy: Strategy, seed: u64) -> Vec<FuzzCase> {
+    let owner = lockowner::LockOwner::new(0, b"generate".to_vec());
+    match strategy {
+        Strategy::AttrSweep => {
+            let masks = nfsv4::attrsweep::sweep_masks(seed, 16, 6);
+            nfsv4::attrsweep::getattr_cases(&masks)
+        }
+        Strategy::Delegations => {
+            let mut cases = delegation::want_delegation_cases();
+            cases.extend(delegation::get_dir_delegation_cases());
+            cases
+        }
+        Strategy::NamedAttrs => namedattr::openattr_cases(),
+        Strategy::LockOwners => lockowner::release_cases(&Stateid::ANONYMOUS, &owner),
+        Strategy::PublicFh => webnfs::putpubfh_cases(&[]),
+        Strategy::Referrals => referral::absent_fs_cases(b"referral"),
+        Strategy::SavedFh => savedfh::unsaved_cases(),
+        Strategy::Stateful => state::cases(SEED_FILE),
+        Strategy::Layouts => layout_cases(),
+        Strategy::Sparse => sparse_cases(),
+        Strategy::Timestamps => on_file(times::timestamp_cases(&state::stateid(Kind::Open, 0))),
+        Strategy::StateProtection => nfsv4::ssv::cases(),
+        Strategy::StateidSweep => {
+            let harvested = [Kind::Open, Kind::Lock, Kind::Delegation, Kind::Layout]
+                .map(|kind| state::stateid(kind, 0));
+            vec![
+                // A stand-in: the fuzzer sends guesses around the
+                // stateids it has harvested in its place
+                FuzzCase::new("stateid_sweep", Vec::new()),
+                FuzzCase::new(
+                    "test_stateid_harvested",
+                    vec![stateids::test_stateid(&harvested)],
+                ),
+                FuzzCase::new(
+                    "free_stateid_lock",
+                    vec![stateids::free_stateid(&harvested[1])],
+                ),
+            ]
+        }
+        Strategy::ReplyCache => {
+            let (dir, file) = (state::filehandle(1), state::filehandle(0));
+            // A stand-in: the fuzzer replays these on its session
+            let mut cases = vec![FuzzCase::new("reply_cache", Vec::new())];
+            cases.extend(
+                replycache::high_cost_compounds(&dir, &file)
+                    .into_iter()
+                    .map(|(name, ops)| FuzzCase::new(name, ops)),
+            );
+            cases
+        }
+        // A stand-in: the fuzzer runs the window and checksum checks of
+        // its GSS context in its place
+        Strategy::GssWindow => vec![FuzzCase::new("gss_window", Vec::new())],
+        // Calls with credentials of their own, from strategy_calls
+        Strategy::RpcHeader | Strategy::AuthSys | Strategy::GssControl => Vec::new(),
+        Strategy::DeepTree
+        | Strategy::LongNames
+        | Strategy::HardLinks
+        | Strategy::CrossVersionFh
+        | Strategy::AuthDowngrade
+        | Strategy::MixedVersion
+        | Strategy::LockReclaim
+        | Strategy::NsmSpoof
+        | Strategy::OpRaces
+        | Strategy::ReExportLoops => Vec::new(),
+    }
+}
+
+/// The input of a built-in seed, framed as [`base_inputs`] frames it
+fn seed_input(s: &seeds::Seed) -> Input {
+    let id = format!("v{}:{}", s.version, s.name);
+    match s.op() {
+        Some(op) => {
+            let target = Target::new(nfsv4::minor_version::V4_1, SEED_FILE);
+            v4_input(id, s.name, target.wrap(&[op]).build())
+        }
+        None => Input {
+            name: s.name.to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure: s.number,
+            args: s.args.to_vec(),
+            lineage: Lineage::new(id),
+            auth: None,
+        },
+    }
+}
+
+/// Cases of `strategy` that carry their own credential or header, as
+/// calls to both NFS versions a campaign may fuzz: NULL with malformed
+/// credentials and verifiers or a procedure past the last, GETATTR
+/// seeds under AUTH_SYS credentials with boundary gids counts, and the
+/// RPCSEC_GSS control cases
+pub fn strategy_calls(strategy: Strategy) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    for version in [3, 4] {
+        let id = |name: &str| Lineage::new(format!("{:?}:v{}:{}", strategy, version, name));
+        match strategy {
+            Strategy::AuthSys => {
+                let getattr = seeds::seeds(Some(version))
+                    .find(|s| s.name == "GETATTR")
+                    .map(seed_input)
+                    .expect("GETATTR seeds for both versions");
+                for (name, identity) in auth::gids_boundary_cases(&Identity::new(0, 0)) {
+                    inputs.push(Input {
+                        lineage: id(&name),
+                        auth: Some(RawAuth {
+                            cred: identity.credential(),
+                            verf: auth_none(),
+                        }),
+                        name,
+                        ..getattr.clone()
+                    });
+                }
+            }
+            Strategy::RpcHeader => {
+                let null = Input {
+                    name: "NULL".to_string(),
+                    program: program::NFS,
+                    version,
+                    procedure: 0,
+                    args: Vec::new(),
+                    lineage: id("NULL"),
+                    auth: None,
+                };
+                for (name, cred, verf) in auth::header_cases(&Identity::new(0, 0)) {
+                    inputs.push(Input {
+                        lineage: id(&name),
+                        auth: Some(RawAuth { cred, verf }),
+                        name,
+                        ..null.clone()
+                    });
+                }
+                // Past the last procedure of each version
+                for procedure in [if version == 3 { 22 } else { 2 }, u32::MAX] {
+                    let name = format!("procedure_{}", procedure);
+                    inputs.push(Input {
+                        lineage: id(&name),
+                        name,
+                        procedure,
+                        ..null.clone()
+                    });
+                }
+            }
+            Strategy::GssControl => {
+                for (name, msg) in gss::control_cases(program::NFS, version) {
+                    let lineage = id(&name);
+                    inputs.extend(call_input(name, lineage, &msg));
+                }
+            }
+            _ => {}
+        }
+    }
+    inputs
+}
+
+/// Inputs of `strategy` that walk to `export`, the export a campaign
+/// mounts: for AuthDowngrade the SECINFO queries, then every downgrade
+/// probe under AUTH_SYS as `identity` and under AUTH_NONE; for
+/// ReExportLoops the repeated walks, the crossings of the export's mount
+/// point and the symlinks planted in it
+pub fn export_inputs(strategy: Strategy, export: &Pathname, identity: &Identity) -> Vec<Input> {
+    let id = |name: &str| format!("{:?}:{}", strategy, name);
+    let mut inputs = Vec::new();
+    if strategy == Strategy::ReExportLoops {
+        let mut cases = reexport::repeat_cases(export, reexport::DEPTHS);
+        cases.extend(reexport::crossing_cases(export, reexport::DEPTHS));
+        cases.extend(reexport::symlink_loop_cases(export));
+        for case in cases {
+            inputs.push(v4_input(
+                id(&case.name),
+                &case.name,
+                compound_args(&case.ops),
+            ));
+        }
+    }
+    if strategy == Strategy::AuthDowngrade {
+        for case in secinfo::query_cases(export) {
+            inputs.push(v4_input(
+                id(&case.name),
+                &case.name,
+                compound_args(&case.ops),
+            ));
+        }
+        for case in secinfo::probe_cases(export) {
+            for (flavor, cred) in secinfo::downgrade_credentials(identity) {
+                let name = format!("{}_{}", case.name, flavor);
+                inputs.push(Input {
+                    auth: Some(RawAuth {
+                        cred,
+                        verf: auth_none(),
+                    }),
+                    ..v4_input(id(&name), &name, compound_args(&case.ops))
+                });
+            }
+        }
+    }
+    inputs
+}
+
+/// CrossVersionFh inputs: v4 compounds on each of the `v3` handles and
+/// v3 calls as `identity` on each of the `v4` handles, every handle as
+/// harvested and reshaped to the other version's limits
+pub fn cross_version_inputs(v3: &[Vec<u8>], v4: &[Vec<u8>], identity: &Identity) -> Vec<Input> {
+    let id = |name: &str| format!("{:?}:{}", Strategy::CrossVersionFh, name);
+    let mut inputs: Vec<Input> = crossfh::v3_handles_in_v4(v3)
+        .into_iter()
+        .map(|case| v4_input(id(&case.name), &case.name, compound_args(&case.ops)))
+        .collect();
+    for (name, msg) in crossfh::v4_handles_in_v3(identity, v4) {
+        let lineage = Lineage::new(id(&name));
+        inputs.extend(call_input(name, lineage, &msg));
+    }
+    inputs
+}
+
+/// Unmutated inputs: every seed, then the cases and calls of each
+/// strategy, skipping those in [`NEED_EXPORT`]; an error if any other
+/// strategy has none
+pub fn base_inputs(strategies: &[Strategy], seed: u64) -> Result<Vec<Input>, GenerateError> {
+    let mut inputs: Vec<Input> = seeds::seeds(None).map(seed_input).collect();
+    for &strategy in strategies {
+        if NEED_EXPORT.contains(&strategy) {
+            continue;
+        }
+        let before = inputs.len();
+        for case in strategy_cases(strategy, seed) {
+            let id = format!("{:?}:{}", strategy, case.name);
+            inputs.push(v4_input(id, &case.name, compound_args(&case.ops)));
+        }
+        inputs.extend(strategy_calls(strategy));
+        if inputs.len() == before {
+            return Err(GenerateError::NoCases(strategy));
+        }
+    }
+    Ok(inputs)
+}
+
+/// Program, version, procedure, RPCSEC_GSS credentials and arguments
+type Call<'a> = (u32, u32, u32, Option<RawAuth>, &'a [u8]);
+
+/// NFS program, version, procedure and arguments of an RPC call, with
+/// the credential and verifier if they are RPCSEC_GSS
+fn parse_call(msg: &[u8]) -> Option<Call<'_>> {
+    let mut dec = XdrDecoder::new(msg);
+    let _xid = dec.get_u32().ok()?;
+    // CALL, RPC version 2
+    if dec.get_u32().ok()? != 0 || dec.get_u32().ok()? != 2 {
+        return None;
+    }
+    let (prog, vers, proc) = (
+        dec.get_u32().ok()?,
+        dec.get_u32().ok()?,
+        dec.get_u32().ok()?,
+    );
+    let at = |dec: &XdrDecoder| msg.len() - dec.rest().len();
+    let cred = at(&dec);
+    let gss = dec.get_u32().ok()? == auth_flavor::RPCSEC_GSS;
+    dec.get_opaque().ok()?;
+    let verf = at(&dec);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    let auth = gss.then(|| RawAuth {
+        cred: msg[cred..verf].to_vec(),
+        verf: msg[verf..at(&dec)].to_vec(),
+    });
+    (prog == program::NFS).then_some((prog, vers, proc, auth, dec.rest()))
+}
+
+/// The input of the record-marked NFS call `call`, as a corpus entry
+/// or finding saved it; an RPCSEC_GSS credential stays with it
+pub fn call_input(name: String, lineage: Lineage, call: &[u8]) -> Option<Input> {
+    let (program, version, procedure, auth, args) = parse_call(call.get(4..)?)?;
+    Some(Input {
+        name,
+        program,
+        version,
+        procedure,
+        args: args.to_vec(),
+        lineage,
+        auth,
+    })
+}
+
+/// Inputs from the NFS calls among `messages`, as [`crate::pcap::messages`]
+/// returns them: credentials dropped, names from the built-in seeds for
+/// v3 and `COMPOUND` for v4, each distinct call once. Lineage seeds are
+/// `pcap:<origin>:<index>`.
+pub fn capture_inputs(messages: &[Vec<u8>], origin: &str) -> Vec<Input> {
+    let mut inputs: Vec<Input> = Vec::new();
+    for msg in messages {
+        // Under RPCSEC_GSS the arguments may be wrapped for integrity or
+        // sealed, and are no use as seeds
+        let Some((program, version, procedure, None, args)) = parse_call(msg) else {
+            continue;
+        };
+        if inputs
+            .iter()
+            .any(|i| (i.version, i.procedure, &i.args[..]) == (version, procedure, args))
+        {
+            continue;
+        }
+        let name = match version {
+            4 => "COMPOUND".to_string(),
+            v => seeds::seeds(Some(v))
+                .find(|s| s.number == procedure)
+                .map_or_else(|| format!("PROC{}", procedure), |s| s.name.to_string()),
+        };
+        let id = format!("pcap:{}:{}", origin, inputs.len());
+        inputs.push(Input {
+            name,
+            program,
+            version,
+            procedure,
+            args: args.to_vec(),
+            lineage: Lineage::new(id),
+            auth: None,
+        });
+    }
+    inputs
+}
+
+/// Apply one mutation from `engine` to the arguments, recording it in
+/// the lineage; the input is returned as is if none applies. A mutator
+/// that panics leaves the arguments as they were and a
+/// [`isolate::PANIC`] step in the lineage
+pub fn mutate<R: Rng>(rng: &mut R, engine: &Engine, input: &Input) -> Input {
+    let mut args = input.args.clone();
+    match isolate::catch(|| engine.mutate(rng, &mut args)) {
+        Ok(Some(step)) => Input {
+            args,
+            lineage: input.lineage.then(step),
+            ..input.clone()
+        },
+        Ok(None) => input.clone(),
+        Err(message) => Input {
+            lineage: input.lineage.then(isolate::panic_step(&message)),
+            ..input.clone()
+        },
+    }
+}
+
+/// `n` inputs: the base inputs first, then variants with one to three
+/// stacked mutations of bases picked at random; deterministic in `seed`
+pub fn generate(strategies: &[Strategy], n: usize, seed: u64) -> Result<Vec<Input>, GenerateError> {
+    generate_with(strategies, n, seed, &Engine::default())
+}
+
+/// [`generate`] with the mutators and weights of `engine`
+pub fn generate_with(
+    strategies: &[Strategy],
+    n: usize,
+    seed: u64,
+    engine: &Engine,
+) -> Result<Vec<Input>, GenerateError> {
+    Ok(stream(strategies, seed, engine)?.take(n).collect())
+}
+
+/// The endless form of [`generate_with`]: the base inputs, then mutated
+/// variants for as long as the caller keeps taking them
+pub fn stream<'a>(
+    strategies: &[Strategy],
+    seed: u64,
+    engine: &'a Engine,
+) -> Result<impl Iterator<Item = Input> + 'a, GenerateError> {
+    Ok(stream_from(base_inputs(strategies, seed)?, seed, engine))
+}
+
+/// [`stream`] from bases of the caller's, such as [`base_inputs`] with
+/// [`capture_inputs`] added
+pub fn stream_from(
+    bases: Vec<Input>,
+    seed: u64,
+    engine: &Engine,
+) -> impl Iterator<Item = Input> + '_ {
+    guided(bases, seed, engine, Pool::default())
+}
+
+/// [`stream_from`] with coverage feedback: once `pool` holds cases that
+/// found new coverage, three variants in four are mutated from one of
+/// them rather than from a base. With an empty pool the stream is the
+/// same as [`stream_from`]'s.
+pub fn guided(
+    bases: Vec<Input>,
+    seed: u64,
+    engine: &Engine,
+    pool: Pool,
+) -> impl Iterator<Item = Input> + '_ {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let variants = std::iter::repeat_with({
+        let bases = bases.clone();
+        move || {
+            let pooled = if !pool.is_empty() && rng.gen_bool(0.75) {
+                pool.pick(&mut rng)
+            } else {
+                None
+            };
+            let mut input = pooled.unwrap_or_else(|| bases[rng.gen_range(0..bases.len())].clone());
+            for _ in 0..rng.gen_range(1..=3) {
+                input = mutate(&mut rng, engine, &input);
+                if isolate::panicked(&input.lineage).is_some() {
+                    break;
+                }
+            }
+            input
+        }
+    });
+    bases.into_iter().chain(variants)
+}
+
+/// Write `inputs` as corpus entries `<output>/generated/<index>_<name>`;
+/// returns the directory
+pub fn write_cases(
+    output: &Path,
+    inputs: &[Input],
+    identity: &Identity,
+) -> Result<PathBuf, CorpusError> {
+    let dir = output.join("generated");
+    let corpus = Corpus::open(&dir);
+    for (i, input) in inputs.iter().enumerate() {
+        let stem = format!("{:06}_{}", i, input.name);
+        corpus.write(&stem, input, &input.message(identity))?;
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_deterministic_and_sized() {
+        let strategies = [
+            Strategy::AttrSweep,
+            Strategy::Referrals,
+            Strategy::RpcHeader,
+        ];
+        let bases = base_inputs(&strategies, 1).unwrap();
+        assert_eq!(
+            bases.len(),
+            seeds::seeds(None).count()
+                + strategy_cases(Strategy::AttrSweep, 1).len()
+                + strategy_cases(Strategy::Referrals, 1).len()
+                + strategy_calls(Strategy::RpcHeader).len()
+        );
+        let a = generate(&strategies, bases.len() + 50, 1).unwrap();
+        assert_eq!(a.len(), bases.len() + 50);
+        assert_eq!(a, generate(&strategies, bases.len() + 50, 1).unwrap());
+        assert_eq!(a[..bases.len()], bases[..]);
+        assert!(a[bases.len()..].iter().all(|i| !i.lineage.steps.is_empty()));
+        assert_eq!(generate(&strategies, 3, 1).unwrap().len(), 3);
+        // COMPOUND: tag, minor version 1, op count, PUTROOTFH, GETFH,
+        // then the verifying GETATTR
+        let v4 = bases.iter().find(|i| i.lineage.seed == "v4:GETFH").unwrap();
+        assert_eq!(
+            &v4.args[..20],
+            &[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 24, 0, 0, 0, 10]
+        );
+        assert_eq!(v4.args[20..24], nfsv4::op::GETATTR.to_be_bytes());
+    }
+
+    #[test]
+    fn test_every_preset_strategy_has_inputs() {
+        use crate::preset::Preset;
+        use clap::ValueEnum;
+        for preset in Preset::value_variants() {
+            let strategies = preset.campaign().strategies;
+            assert!(base_inputs(&strategies, 1).is_ok(), "{:?}", preset);
+        }
+        let export = referral::components("/srv/export");
+        for strategy in [Strategy::AuthDowngrade, Strategy::ReExportLoops] {
+            assert!(!export_inputs(strategy, &export, &Identity::new(0, 0)).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_guided_mutates_the_pool() {
+        let engine = Engine::default();
+        let bases = base_inputs(&[], 1).unwrap();
+        let pool = Pool::default();
+        let plain: Vec<_> = stream_from(bases.clone(), 1, &engine).take(100).collect();
+        let unguided: Vec<_> = guided(bases.clone(), 1, &engine, pool.clone())
+            .take(100)
+            .collect();
+        assert_eq!(plain, unguided);
+
+        let mut found = bases[0].clone();
+        found.lineage = Lineage::new("found");
+        pool.add(found);
+        let variants: Vec<_> = guided(bases.clone(), 1, &engine, pool)
+            .skip(bases.len())
+            .take(400)
+            .collect();
+        let pooled = variants
+            .iter()
+            .filter(|i| i.lineage.seed == "found")
+            .count();
+        assert!((250..350).contains(&pooled), "{}", pooled);
+    }
+
+    #[test]
+    fn test_gss_control_cases_keep_their_credentials() {
+        let cases = gss::control_cases(program::NFS, 4);
+        let calls = strategy_calls(Strategy::GssControl);
+        assert_eq!(calls.len(), 2 * cases.len());
+        assert!(base_inputs(&[Strategy::GssControl], 1)
+            .unwrap()
+            .ends_with(&calls));
+        let call = calls.iter().find(|c| c.version == 4).unwrap();
+        assert_eq!(call.lineage.seed, format!("GssControl:v4:{}", cases[0].0));
+        // Only the xid differs from the case as built
+        let msg = call.message(&Identity::new(0, 0));
+        assert_eq!(msg[8..], cases[0].1[8..]);
+        assert!(call.auth.is_some());
+
+        let calls = strategy_calls(Strategy::AuthSys);
+        let gids: Vec<_> = calls.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(gids.len(), 2 * auth::GIDS_BOUNDARY.len());
+        assert_eq!(gids[1], "auth_sys_gids_17");
+        let getattr = &calls[1];
+        assert_eq!((getattr.version, getattr.procedure), (3, 1));
+        // Flavor, body length, stamp, machine name "fuzzer", uid, gid, count
+        let cred = &getattr.auth.as_ref().unwrap().cred;
+        assert_eq!(cred[32..36], 17u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_layout_cases_use_placeholders() {
+        let cases = strategy_cases(Strategy::Layouts, 1);
+        let fh = nfsv4::putfh(&state::filehandle(0));
+        assert!(cases.iter().all(|c| c.ops[0] == fh));
+        let contains = |name: &str, needle: &[u8]| {
+            let case = cases.iter().find(|c| c.name == name).unwrap();
+            compound_args(&case.ops)
+                .windows(needle.len())
+                .any(|w| w == needle)
+        };
+        assert!(contains("ff_return_io_error", &state::deviceid(0)));
+        let layout = state::stateid(Kind::Layout, 0);
+        assert!(contains("commit_past_end", &layout.other));
+    }
+
+    #[test]
+    fn test_delegation_cases_need_no_state() {
+        let cases = strategy_cases(Strategy::Delegations, 1);
+        let names: Vec<_> = cases.iter().map(|c| c.name.as_str()).collect();
+        assert!(names.contains(&"want_delegation_ask_cancel_ask"));
+        assert!(names.contains(&"get_dir_delegation_baseline"));
+        assert!(STATELESS.contains(&Strategy::Delegations));
+    }
+
+    #[test]
+    fn test_sparse_and_time_cases_are_on_the_file() {
+        for strategy in [Strategy::Sparse, Strategy::Timestamps] {
+            let cases = strategy_cases(strategy, 1);
+            assert!(!cases.is_empty(), "{:?}", strategy);
+            let putfh = nfsv4::putfh(&state::filehandle(0));
+            assert!(cases.iter().all(|c| c.ops[0] == putfh), "{:?}", strategy);
+        }
+    }
+
+    #[test]
+    fn test_downgrade_probes_carry_both_flavors() {
+        let export = referral::components("/srv/secure");
+        let identity = Identity::new(0, 0);
+        let inputs = export_inputs(Strategy::AuthDowngrade, &export, &identity);
+        let probes: Vec<_> = inputs.iter().filter(|i| i.auth.is_some()).collect();
+        assert_eq!(probes.len(), 2 * secinfo::probe_cases(&export).len());
+        assert_eq!(
+            inputs.len() - probes.len(),
+            secinfo::query_cases(&export).len()
+        );
+        let cred = &probes[1].auth.as_ref().unwrap().cred;
+        assert_eq!(cred[..4], auth_flavor::AUTH_NONE.to_be_bytes());
+        assert!(export_inputs(Strategy::AttrSweep, &export, &identity).is_empty());
+    }
+
+    #[test]
+    fn test_cross_version_inputs_go_to_the_other_version() {
+        let (v3, v4) = (vec![vec![1; 28]], vec![vec![2; 64]]);
+        let inputs = cross_version_inputs(&v3, &v4, &Identity::new(0, 0));
+        let in_v4 = inputs.iter().filter(|i| i.version == 4).count();
+        assert_eq!(in_v4, crossfh::v3_handles_in_v4(&v3).len());
+        assert_eq!(
+            inputs.len() - in_v4,
+            crossfh::v4_handles_in_v3(&Identity::new(0, 0), &v4).len()
+        );
+        assert!(inputs
+            .iter()
+            .all(|i| i.lineage.seed.starts_with("CrossVersionFh:")));
+        let export = referral::components("/srv/loop");
+        let loops = export_inputs(Strategy::ReExportLoops, &export, &Identity::new(0, 0));
+        assert!(loops.iter().any(|i| i.name == "reexport_symlinks_remove"));
+    }
+
+    #[test]
+    fn test_write_cases() {
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-gen-{}", std::process::id()));
+        let inputs = generate(&[], 2, 7).unwrap();
+        let out = write_cases(&dir, &inputs, &Identity::new(0, 0)).unwrap();
+        let bin = std::fs::read(out.join("000000_NULL.bin")).unwrap();
+        assert_eq!(
+            u32::from_be_bytes(bin[..4].try_into().unwrap()) & 0x7fff_ffff,
+            bin.len() as u32 - 4
+        );
+        let note: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out.join("000001_GETATTR.json")).unwrap())
+                .unwrap();
+        assert_eq!(note["lineage"]["seed"], "v3:GETATTR");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_capture_seeds_join_the_stream() {
+        use crate::connection::Proto;
+        use crate::pcap;
+        let client = "192.0.2.1:700".parse().unwrap();
+        let server = "192.0.2.2:2049".parse().unwrap();
+        let at = std::time::UNIX_EPOCH;
+        let getattr = seeds::seeds(Some(3)).find(|s| s.name == "GETATTR").unwrap();
+        let mount = RpcCall::new(1, program::MOUNT, 3, 1, true)
+            .with_auth_none()
+            .build();
+        let mut w = pcap::Writer::new(Vec::new()).unwrap();
+        for msg in [getattr.call().unwrap(), getattr.call().unwrap(), mount] {
+            w.call(at, Proto::Tcp, client, server, &msg).unwrap();
+        }
+        w.reply(at, Proto::Tcp, client, server, &[0, 0, 0, 1, 0, 0, 0, 1])
+            .unwrap();
+        let messages = pcap::messages(&w.into_inner()).unwrap();
+        assert_eq!(messages.len(), 4);
+        // One GETATTR: the repeat, the MOUNT call and the reply are dropped
+        let captured = capture_inputs(&messages, "client.pcap");
+        assert_eq!(captured.len(), 1);
+        assert_eq!(
+            (captured[0].name.as_str(), captured[0].procedure),
+            ("GETATTR", 1)
+        );
+        assert_eq!(captured[0].args, getattr.args);
+        assert_eq!(captured[0].lineage.seed, "pcap:client.pcap:0");
+
+        let mut bases = base_inputs(&[], 1).unwrap();
+        bases.extend(captured.clone());
+        let n = bases.len();
+        let inputs: Vec<Input> = stream_from(bases, 1, &Engine::default())
+            .take(n + 1)
+            .collect();
+        assert_eq!(inputs[n - 1], captured[0]);
+    }
+}
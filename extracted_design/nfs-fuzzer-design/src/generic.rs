@@ -0,0 +1,197 @@
+//! Generic Sun RPC program fuzzing
+//!
+//! NFS appliances often register vendor RPC services next to NFS and
+//! MOUNT. Their argument types are unknown, so calls carry generated XDR
+//! blobs: sequences of well-formed-looking items (integers at their
+//! edges, counted opaques, strings, floats), with length prefixes that
+//! sometimes lie about what follows. The services to fuzz are named on
+//! the command line or taken from a portmap DUMP of the host.
+
+use crate::generate::Input;
+use crate::lineage::Lineage;
+use crate::rpc::{program, Mapping};
+use crate::xdr::{hostile_float, XdrEncoder};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// A registered program/version, as listed by rpcbind DUMP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpcService {
+    pub program: u32,
+    pub version: u32,
+    /// Highest procedure number to sweep; unknown programs rarely go past 32
+    pub max_proc: u32,
+}
+
+impl RpcService {
+    pub fn new(program: u32, version: u32) -> Self {
+        Self {
+            program,
+            version,
+            max_proc: 32,
+        }
+    }
+}
+
+/// The services of a portmap DUMP reachable over `protocol`, each with
+/// its port; portmap itself, and any registration listed twice, left out
+pub fn services(mappings: &[Mapping], protocol: u32, max_proc: u32) -> Vec<(RpcService, u16)> {
+    let mut out: Vec<(RpcService, u16)> = Vec::new();
+    for m in mappings {
+        let Ok(port) = u16::try_from(m.port) else {
+            continue;
+        };
+        let service = RpcService {
+            max_proc,
+            ..RpcService::new(m.program, m.version)
+        };
+        if m.protocol == protocol
+            && m.program != program::PORTMAP
+            && port != 0
+            && !out.iter().any(|(s, _)| *s == service)
+        {
+            out.push((service, port));
+        }
+    }
+    out
+}
+
+const EDGE_U32: &[u32] = &[0, 1, 0x7fff_ffff, 0x8000_0000, 0xffff_fffe, 0xffff_ffff];
+
+fn put_item<R: Rng + ?Sized>(rng: &mut R, enc: &mut XdrEncoder) {
+    match rng.gen_range(0..10) {
+        0 | 1 => enc.put_u32(rng.gen_range(0..16)),
+        2 => enc.put_u32(EDGE_U32[rng.gen_range(0..EDGE_U32.len())]),
+        3 => enc.put_u64(rng.gen()),
+        4 => enc.put_bool(rng.gen()),
+        5 => {
+            let data: Vec<u8> = (0..rng.gen_range(0..64)).map(|_| rng.gen()).collect();
+            enc.put_opaque(&data);
+        }
+        6 => {
+            let len = rng.gen_range(0..32);
+            let s: String = (0..len)
+                .map(|_| rng.gen_range(b'!'..=b'~') as char)
+                .collect();
+            enc.put_string(&s);
+        }
+        7 => {
+            // Length prefix that disagrees with the bytes that follow
+            let actual = rng.gen_range(0..16);
+            enc.put_u32(EDGE_U32[rng.gen_range(0..EDGE_U32.len())].wrapping_add(actual));
+            enc.put_opaque_fixed(&vec![0x41; actual as usize]);
+        }
+        8 => match rng.gen_range(0..3) {
+            0 => enc.put_u32(hostile_float::F32[rng.gen_range(0..hostile_float::F32.len())]),
+            1 => enc.put_u64(hostile_float::F64[rng.gen_range(0..hostile_float::F64.len())]),
+            _ => enc.put_quad(hostile_float::QUAD[rng.gen_range(0..hostile_float::QUAD.len())]),
+        },
+        _ => {
+            // Array count followed by fewer elements than claimed
+            let count = rng.gen_range(1..8);
+            enc.put_u32(count + rng.gen_range(0..=1) * 0x1000_0000);
+            for _ in 0..count {
+                enc.put_u32(rng.gen());
+            }
+        }
+    }
+}
+
+/// A random argument blob of up to `max_items` XDR items
+pub fn random_xdr_blob<R: Rng + ?Sized>(rng: &mut R, max_items: usize) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    for _ in 0..rng.gen_range(0..=max_items) {
+        put_item(rng, &mut enc);
+    }
+    enc.into_bytes().to_vec()
+}
+
+/// Procedure numbers to call: every one up to `max_proc`, then a few past it
+pub fn procedures(service: &RpcService) -> Vec<u32> {
+    let mut procs: Vec<u32> = (0..=service.max_proc).collect();
+    procs.extend([service.max_proc.saturating_add(1), 0x7fff_ffff, u32::MAX]);
+    procs.dedup();
+    procs
+}
+
+/// One call per procedure with a random argument blob
+pub fn generic_calls<R: Rng + ?Sized>(
+    rng: &mut R,
+    service: &RpcService,
+    max_items: usize,
+) -> Vec<Input> {
+    procedures(service)
+        .into_iter()
+        .map(|procedure| {
+            let name = format!(
+                "rpc{}_v{}_proc{}",
+                service.program, service.version, procedure
+            );
+            Input {
+                lineage: Lineage::new(format!("generic:{}", name)),
+                name,
+                program: service.program,
+                version: service.version,
+                procedure,
+                args: random_xdr_blob(rng, max_items),
+                auth: None,
+            }
+        })
+        .collect()
+}
+
+/// Sweeps of [`generic_calls`] from `seed`, one after another, forever
+pub fn stream(service: RpcService, seed: u64, max_items: usize) -> impl Iterator<Item = Input> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    std::iter::repeat_with(move || generic_calls(&mut rng, &service, max_items)).flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::pmap;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_blobs_are_word_aligned() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        for _ in 0..200 {
+            assert_eq!(random_xdr_blob(&mut rng, 16).len() % 4, 0);
+        }
+    }
+
+    #[test]
+    fn test_generic_calls_cover_procedures() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut service = RpcService::new(300_019, 1);
+        service.max_proc = 3;
+        let calls = generic_calls(&mut rng, &service, 4);
+        let procs: Vec<u32> = calls.iter().map(|c| c.procedure).collect();
+        assert_eq!(procs, vec![0, 1, 2, 3, 4, 0x7fff_ffff, u32::MAX]);
+        // Program number sits after the record mark, xid, type and rpcvers
+        let msg = calls[0].message(&crate::auth::Identity::new(0, 0));
+        assert_eq!(&msg[16..20], &300_019u32.to_be_bytes());
+        assert_eq!(stream(service, 7, 4).take(20).count(), 20);
+    }
+
+    #[test]
+    fn test_services_from_dump() {
+        let mapping = |program, version, protocol, port| Mapping {
+            program,
+            version,
+            protocol,
+            port,
+        };
+        let dump = [
+            mapping(program::PORTMAP, 2, pmap::IPPROTO_TCP, 111),
+            mapping(300_019, 1, pmap::IPPROTO_TCP, 700),
+            mapping(300_019, 1, pmap::IPPROTO_UDP, 701),
+            mapping(300_019, 1, pmap::IPPROTO_TCP, 702),
+            mapping(300_020, 2, pmap::IPPROTO_TCP, 70_000),
+        ];
+        let found = services(&dump, pmap::IPPROTO_TCP, 8);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0.max_proc, 8);
+        assert_eq!((found[0].0.program, found[0].1), (300_019, 700));
+    }
+}
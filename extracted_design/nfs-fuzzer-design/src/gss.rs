@@ -0,0 +1,1395 @@
+//! RPCSEC_GSS contexts (RFC 2203)
+//!
+//! Many enterprise exports refuse AUTH_SYS outright, which leaves most
+//! of the server out of reach. An RPCSEC_GSS context is set up with
+//! INIT and CONTINUE_INIT calls to the NULL procedure carrying the
+//! mechanism's tokens. After that every call has a sequence number and
+//! a verifier over its header. Under integrity (krb5i) the arguments and
+//! results travel with a checksum, and under privacy (krb5p) they are
+//! sealed. Replies are checked the same way: the verifier is a checksum
+//! of the sequence number, and the results must carry that number back.
+//!
+//! The Kerberos V5 mechanism itself is the platform's GSS-API library,
+//! reached through a [`Mechanism`]; with the `krb5` feature that is
+//! `krb5::Krb5`, which links libgssapi_krb5. Everything on the RPC side
+//! is here: credentials, sequence numbers, checksums and wrapping, and
+//! the out-of-window and replayed sequence numbers a fuzzer wants to
+//! send on purpose.
+//!
+//! The GSS layer is a target in its own right: kernel servers parse
+//! context tokens and credentials before anyone is authenticated, and
+//! that parsing has a history of bugs. [`control_cases`] needs no
+//! mechanism. It sends malformed INIT tokens, unknown handles and
+//! broken credentials. [`Context::fuzz_calls`] breaks the rules of an
+//! established context: replayed and out-of-window sequence numbers,
+//! truncated and corrupted checksums, and bodies that do not match the
+//! service. [`Context::mic_calls`] takes the header checksum apart
+//! further, [`Context::window_plans`] walk the sequence window with
+//! numbers reused, just below it and far ahead of it ([`probe_window`]
+//! runs them and checks what was dropped), and
+//! [`Context::bind_channel_calls`] send RPCSEC_GSSv2 channel-binding
+//! requests the server has to refuse.
+
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::nfsv4::secinfo::KRB5_OID;
+use crate::rpc::{
+    self, auth_flavor, auth_none, gss_proc, gss_service, next_xid, RpcCall, RpcError, RpcReply,
+    GSS_MAXSEQ,
+};
+use crate::xdr::{XdrDecoder, XdrEncoder, XdrError};
+use bytes::BytesMut;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// GSS major status of a complete context
+pub const GSS_S_COMPLETE: u32 = 0;
+/// GSS major status when more tokens are needed
+pub const GSS_S_CONTINUE_NEEDED: u32 = 1;
+
+/// Most INIT round trips before giving up
+pub const MAX_ROUNDS: usize = 8;
+
+/// Size of the oversized INIT token
+pub const HUGE_TOKEN: usize = 1 << 20;
+
+/// Handle no server hands out
+const BOGUS_HANDLE: &[u8] = b"nfs-fuzzer-bogus-handle";
+
+#[derive(Debug, Error)]
+pub enum GssError {
+    #[error("mechanism: {0}")]
+    Mechanism(String),
+    #[error("context establishment failed: major {major:#x}, minor {minor:#x}")]
+    Major { major: u32, minor: u32 },
+    #[error("context not complete after {0} rounds")]
+    Rounds(usize),
+    #[error("reply verifier does not verify")]
+    BadVerifier,
+    #[error("context sequence numbers exhausted")]
+    SequenceExhausted,
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error("reply: {0}")]
+    Xdr(#[from] XdrError),
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+}
+
+/// The GSS-API calls an RPCSEC_GSS context needs from its mechanism
+pub trait Mechanism: fmt::Debug {
+    /// The next context token given the server's last, `None` at the
+    /// start; `Ok(None)` once the mechanism has nothing more to send
+    fn step(&mut self, input: Option<&[u8]>) -> Result<Option<Vec<u8>>, GssError>;
+    /// A checksum over `msg`; a failure fails the call being built
+    fn get_mic(&self, msg: &[u8]) -> Result<Vec<u8>, GssError>;
+    fn verify_mic(&self, msg: &[u8], mic: &[u8]) -> bool;
+    /// `msg` sealed; a failure fails the call being built
+    fn wrap(&self, msg: &[u8]) -> Result<Vec<u8>, GssError>;
+    fn unwrap(&self, token: &[u8]) -> Option<Vec<u8>>;
+
+    /// Whether [`wrap`](Self::wrap) really seals; calls under the
+    /// privacy service are not fuzzed on a mechanism that cannot
+    fn seals(&self) -> bool {
+        true
+    }
+}
+
+impl<M: Mechanism + ?Sized> Mechanism for Box<M> {
+    fn step(&mut self, input: Option<&[u8]>) -> Result<Option<Vec<u8>>, GssError> {
+        (**self).step(input)
+    }
+
+    fn get_mic(&self, msg: &[u8]) -> Result<Vec<u8>, GssError> {
+        (**self).get_mic(msg)
+    }
+
+    fn verify_mic(&self, msg: &[u8], mic: &[u8]) -> bool {
+        (**self).verify_mic(msg, mic)
+    }
+
+    fn wrap(&self, msg: &[u8]) -> Result<Vec<u8>, GssError> {
+        (**self).wrap(msg)
+    }
+
+    fn unwrap(&self, token: &[u8]) -> Option<Vec<u8>> {
+        (**self).unwrap(token)
+    }
+
+    fn seals(&self) -> bool {
+        (**self).seals()
+    }
+}
+
+/// A context over whichever mechanism set it up, as a campaign holds it
+pub type DynContext = Context<Box<dyn Mechanism + Send>>;
+
+/// Protection level, named by its Kerberos pseudo-flavor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Service {
+    /// `krb5`: authentication only
+    Authentication,
+    /// `krb5i`: a checksum over arguments and results
+    Integrity,
+    /// `krb5p`: arguments and results sealed
+    Privacy,
+}
+
+impl Service {
+    pub const ALL: [Service; 3] = [
+        Service::Authentication,
+        Service::Integrity,
+        Service::Privacy,
+    ];
+
+    /// rpc_gss_service_t
+    pub fn code(self) -> u32 {
+        match self {
+            Service::Authentication => gss_service::NONE,
+            Service::Integrity => gss_service::INTEGRITY,
+            Service::Privacy => gss_service::PRIVACY,
+        }
+    }
+}
+
+impl FromStr for Service {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "krb5" => Ok(Service::Authentication),
+            "krb5i" => Ok(Service::Integrity),
+            "krb5p" => Ok(Service::Privacy),
+            other => Err(format!(
+                "unknown service `{}` (krb5, krb5i or krb5p)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Service::Authentication => "krb5",
+            Service::Integrity => "krb5i",
+            Service::Privacy => "krb5p",
+        })
+    }
+}
+
+/// rpc_gss_init_res
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InitRes {
+    pub handle: Vec<u8>,
+    pub major: u32,
+    pub minor: u32,
+    pub window: u32,
+    pub token: Vec<u8>,
+}
+
+impl InitRes {
+    pub fn decode(results: &[u8]) -> Result<Self, XdrError> {
+        let mut dec = XdrDecoder::new(results);
+        Ok(Self {
+            handle: dec.get_opaque()?.to_vec(),
+            major: dec.get_u32()?,
+            minor: dec.get_u32()?,
+            window: dec.get_u32()?,
+            token: dec.get_opaque()?.to_vec(),
+        })
+    }
+}
+
+/// An INIT (no `handle` yet) or CONTINUE_INIT call carrying `token` to
+/// the NULL procedure of `program`/`version`
+pub fn init_call(
+    program: u32,
+    version: u32,
+    service: Service,
+    handle: Option<&[u8]>,
+    token: &[u8],
+) -> BytesMut {
+    let proc = match handle {
+        None => gss_proc::INIT,
+        Some(_) => gss_proc::CONTINUE_INIT,
+    };
+    let cred = rpc::rpcsec_gss_cred(proc, 0, service.code(), handle.unwrap_or_default());
+    let mut args = XdrEncoder::new();
+    args.put_opaque(token);
+    RpcCall::new(next_xid(), program, version, 0, true)
+        .with_auth(&cred, &auth_none())
+        .with_args(args.as_bytes())
+        .build()
+}
+
+/// An established context
+#[derive(Debug)]
+pub struct Context<M> {
+    pub mech: M,
+    pub handle: Vec<u8>,
+    pub service: Service,
+    /// Sequence window the server granted
+    pub window: u32,
+    program: u32,
+    version: u32,
+    seq: u32,
+}
+
+/// Set up a context for `program`/`version` on `conn`; the reply to the
+/// final round must carry a verifier over the window
+pub async fn establish<S, M>(
+    conn: &mut NfsConnection<S>,
+    mut mech: M,
+    service: Service,
+    program: u32,
+    version: u32,
+) -> Result<Context<M>, GssError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    M: Mechanism,
+{
+    let mut handle: Option<Vec<u8>> = None;
+    let mut input: Option<Vec<u8>> = None;
+    for _ in 0..MAX_ROUNDS {
+        let Some(token) = mech.step(input.as_deref())? else {
+            return Err(GssError::Mechanism("no initial token".to_string()));
+        };
+        let msg = init_call(program, version, service, handle.as_deref(), &token);
+        let reply = conn.call(&msg).await?;
+        let reply = RpcReply::parse(&reply)?;
+        let verifier = reply.verifier().map(|v| (v.flavor, v.body.to_vec()));
+        let res = InitRes::decode(reply.into_results()?)?;
+        match res.major {
+            GSS_S_COMPLETE => {
+                if !res.token.is_empty() {
+                    mech.step(Some(&res.token))?;
+                }
+                let window = res.window.to_be_bytes();
+                match verifier {
+                    Some((auth_flavor::RPCSEC_GSS, mic)) if mech.verify_mic(&window, &mic) => {}
+                    _ => return Err(GssError::BadVerifier),
+                }
+                return Ok(Context {
+                    mech,
+                    handle: res.handle,
+                    service,
+                    window: res.window,
+                    program,
+                    version,
+                    seq: 0,
+                });
+            }
+            GSS_S_CONTINUE_NEEDED => {
+                handle = Some(res.handle);
+                input = Some(res.token);
+            }
+            major => {
+                return Err(GssError::Major {
+                    major,
+                    minor: res.minor,
+                })
+            }
+        }
+    }
+    Err(GssError::Rounds(MAX_ROUNDS))
+}
+
+/// One way of making a header checksum, among the fuzz calls' variants
+type Checksum<'a> = &'a dyn Fn(&[u8]) -> Result<Vec<u8>, GssError>;
+
+impl<M: Mechanism> Context<M> {
+    /// A context the server set up some other way than INIT, such as the
+    /// SSV contexts EXCHANGE_ID hands out (see [`crate::nfsv4::ssv`])
+    pub fn new(
+        mech: M,
+        handle: Vec<u8>,
+        service: Service,
+        window: u32,
+        program: u32,
+        version: u32,
+    ) -> Self {
+        Self {
+            mech,
+            handle,
+            service,
+            window,
+            program,
+            version,
+            seq: 0,
+        }
+    }
+
+    /// The context over its mechanism boxed, see [`DynContext`]
+    pub fn boxed(self) -> DynContext
+    where
+        M: Send + 'static,
+    {
+        Context {
+            mech: Box::new(self.mech),
+            handle: self.handle,
+            service: self.service,
+            window: self.window,
+            program: self.program,
+            version: self.version,
+            seq: self.seq,
+        }
+    }
+
+    /// Program and version the context was set up for
+    pub fn target(&self) -> (u32, u32) {
+        (self.program, self.version)
+    }
+
+    /// The sequence number the next [`Context::call`] uses
+    pub fn next_seq(&self) -> u32 {
+        self.seq + 1
+    }
+
+    /// A DATA call of `procedure` with `args` under the context's next
+    /// sequence number; returns that number for [`Context::results`]
+    pub fn call(&mut self, procedure: u32, args: &[u8]) -> Result<(u32, BytesMut), GssError> {
+        let seq = self.next_seq();
+        if seq >= GSS_MAXSEQ {
+            return Err(GssError::SequenceExhausted);
+        }
+        self.seq = seq;
+        Ok((seq, self.call_at(seq, self.service, procedure, args)?))
+    }
+
+    /// A DATA call under any sequence number and service, without
+    /// advancing the context: replays, numbers behind the window or
+    /// past MAXSEQ, a service other than the one negotiated
+    pub fn call_at(
+        &self,
+        seq: u32,
+        service: Service,
+        procedure: u32,
+        args: &[u8],
+    ) -> Result<BytesMut, GssError> {
+        let body = self.protect(seq, service, args)?;
+        self.data_call(seq, service.code(), procedure, &body, |h| {
+            self.mech.get_mic(h)
+        })
+    }
+
+    /// `args` as `service` sends them under `seq`
+    fn protect(&self, seq: u32, service: Service, args: &[u8]) -> Result<Vec<u8>, GssError> {
+        match service {
+            Service::Authentication => Ok(args.to_vec()),
+            Service::Integrity => rpc::gss_integ_body(seq, args, |m| self.mech.get_mic(m)),
+            Service::Privacy => rpc::gss_priv_body(seq, args, |m| self.mech.wrap(m)),
+        }
+    }
+
+    /// A DATA call naming service `code` with `body` as it stands and
+    /// `mic` as the header checksum
+    fn data_call(
+        &self,
+        seq: u32,
+        code: u32,
+        procedure: u32,
+        body: &[u8],
+        mic: impl FnOnce(&[u8]) -> Result<Vec<u8>, GssError>,
+    ) -> Result<BytesMut, GssError> {
+        let cred = rpc::rpcsec_gss_cred(gss_proc::DATA, seq, code, &self.handle);
+        Ok(
+            RpcCall::new(next_xid(), self.program, self.version, procedure, true)
+                .with_gss(&cred, mic)?
+                .with_args(body)
+                .build(),
+        )
+    }
+
+    /// Calls of `procedure` with `args` that break the rules of the
+    /// context, none of which advance it. A server has to drop the stale
+    /// and replayed ones silently and refuse the rest with
+    /// RPCSEC_GSS_CREDPROBLEM, GARBAGE_ARGS or a dropped call; the ones
+    /// past MAXSEQ end the context. Privacy-service calls are left out
+    /// when the mechanism cannot seal.
+    pub fn fuzz_calls(
+        &self,
+        procedure: u32,
+        args: &[u8],
+    ) -> Result<Vec<(String, BytesMut)>, GssError> {
+        let seq = self.next_seq();
+        let code = self.service.code();
+        let mic = |m: &[u8]| self.mech.get_mic(m);
+        let truncated = |m: &[u8]| {
+            let mut c = mic(m)?;
+            c.truncate(c.len() / 2);
+            Ok(c)
+        };
+        let flipped = |m: &[u8]| {
+            let mut c = mic(m)?;
+            if let Some(b) = c.last_mut() {
+                *b ^= 1;
+            }
+            Ok(c)
+        };
+        let overflowed = |body: Vec<u8>| [&[0xff; 4][..], &body[4..]].concat();
+        let half = |mut body: Vec<u8>| {
+            body.truncate(body.len() / 2);
+            body
+        };
+        let named = |name: &str, msg: BytesMut| (format!("gss_{}", name), msg);
+
+        let mut cases = Vec::new();
+        // Sequence numbers
+        let ahead = seq.saturating_add(self.window.saturating_mul(4));
+        for (name, s) in [
+            ("replay", self.seq.max(1)),
+            ("stale", self.stale_seq()),
+            ("seq_zero", 0),
+            ("seq_ahead", ahead),
+            ("seq_maxseq", GSS_MAXSEQ),
+            ("seq_max", u32::MAX),
+        ] {
+            cases.push(named(name, self.call_at(s, self.service, procedure, args)?));
+        }
+        // Header checksums
+        let body = self.protect(seq, self.service, args)?;
+        let header = |name, mic: Checksum<'_>| {
+            Ok::<_, GssError>(named(
+                name,
+                self.data_call(seq, code, procedure, &body, mic)?,
+            ))
+        };
+        cases.extend([
+            header("mic_truncated", &truncated)?,
+            header("mic_flipped", &flipped)?,
+            header("mic_empty", &|_| Ok(Vec::new()))?,
+            header("mic_oversized", &|m| {
+                let mut c = mic(m)?;
+                c.resize(4096, 0);
+                Ok(c)
+            })?,
+        ]);
+        // Bodies that do not hold what the credential says
+        let integ = gss_service::INTEGRITY;
+        let privacy = gss_service::PRIVACY;
+        let mut bodies = vec![
+            ("integ_unwrapped", integ, args.to_vec()),
+            (
+                "integ_seq_mismatch",
+                integ,
+                rpc::gss_integ_body(seq + 1, args, mic)?,
+            ),
+            (
+                "integ_mic_truncated",
+                integ,
+                rpc::gss_integ_body(seq, args, truncated)?,
+            ),
+            (
+                "integ_mic_flipped",
+                integ,
+                rpc::gss_integ_body(seq, args, flipped)?,
+            ),
+            (
+                "integ_length_overflow",
+                integ,
+                overflowed(rpc::gss_integ_body(seq, args, mic)?),
+            ),
+        ];
+        let seals = self.mech.seals();
+        if seals {
+            let wrap = |m: &[u8]| self.mech.wrap(m);
+            bodies.extend([
+                ("priv_unwrapped", privacy, args.to_vec()),
+                (
+                    "priv_seq_mismatch",
+                    privacy,
+                    rpc::gss_priv_body(seq + 1, args, wrap)?,
+                ),
+                (
+                    "priv_truncated",
+                    privacy,
+                    half(rpc::gss_priv_body(seq, args, wrap)?),
+                ),
+                (
+                    "priv_length_overflow",
+                    privacy,
+                    overflowed(rpc::gss_priv_body(seq, args, wrap)?),
+                ),
+            ]);
+        }
+        bodies.push(("service_unknown", 4, args.to_vec()));
+        for (name, code, body) in bodies {
+            cases.push(named(
+                name,
+                self.data_call(seq, code, procedure, &body, mic)?,
+            ));
+        }
+        // Well-formed calls under a service the context was not set up with
+        let services = Service::ALL
+            .into_iter()
+            .filter(|&s| seals || s != Service::Privacy);
+        for service in services.filter(|&s| s != self.service) {
+            let name = format!("service_{}", service);
+            cases.push(named(&name, self.call_at(seq, service, procedure, args)?));
+        }
+        // DESTROY that does not verify
+        let cred = rpc::rpcsec_gss_cred(gss_proc::DESTROY, seq, code, &self.handle);
+        cases.push(named(
+            "destroy_mic_flipped",
+            RpcCall::new(next_xid(), self.program, self.version, 0, true)
+                .with_gss(&cred, flipped)?
+                .build(),
+        ));
+        Ok(cases)
+    }
+
+    /// Calls of `procedure` with `args` under the next sequence number
+    /// whose header checksum is wrong in ways [`Context::fuzz_calls`]
+    /// leaves out: each byte of it flipped in turn, a checksum that is
+    /// good but over another xid, another sequence number or the
+    /// arguments, one with bytes after it, and a good checksum under a
+    /// verifier flavor other than RPCSEC_GSS. None of them may be
+    /// carried out.
+    pub fn mic_calls(
+        &self,
+        procedure: u32,
+        args: &[u8],
+    ) -> Result<Vec<(String, BytesMut)>, GssError> {
+        let seq = self.next_seq();
+        let code = self.service.code();
+        let body = self.protect(seq, self.service, args)?;
+        let mic = |m: &[u8]| self.mech.get_mic(m);
+        let named = |name: String, msg: BytesMut| (format!("gss_{}", name), msg);
+        let header = |name: String, mic: Checksum<'_>| {
+            Ok::<_, GssError>(named(
+                name,
+                self.data_call(seq, code, procedure, &body, mic)?,
+            ))
+        };
+
+        let mut cases = Vec::new();
+        for i in 0..self.mech.get_mic(&[])?.len().min(MAX_MIC_FLIPS) {
+            cases.push(header(format!("mic_byte_{}", i), &|m| {
+                let mut c = mic(m)?;
+                if let Some(b) = c.get_mut(i) {
+                    *b ^= 0x80;
+                }
+                Ok(c)
+            })?);
+        }
+        // Header offsets of the xid and the credential's sequence number
+        let edited = |at: usize, value: u32| {
+            move |m: &[u8]| {
+                let mut h = m.to_vec();
+                h[at..at + 4].copy_from_slice(&value.to_be_bytes());
+                mic(&h)
+            }
+        };
+        cases.extend([
+            header("mic_other_xid".to_string(), &|m| {
+                let xid = u32::from_be_bytes(m[..4].try_into().unwrap());
+                edited(0, !xid)(m)
+            })?,
+            header("mic_last_seq".to_string(), &edited(40, self.seq))?,
+            header("mic_next_seq".to_string(), &edited(40, seq + 1))?,
+            header("mic_of_args".to_string(), &|_| mic(args))?,
+            header("mic_padded".to_string(), &|m| {
+                Ok([mic(m)?, vec![0; 4]].concat())
+            })?,
+            header("mic_doubled".to_string(), &|m| Ok(mic(m)?.repeat(2)))?,
+        ]);
+        // A good checksum, then a verifier flavor that does not match it
+        let good = self.data_call(seq, code, procedure, &body, mic)?;
+        let cred = rpc::rpcsec_gss_cred(gss_proc::DATA, seq, code, &self.handle);
+        let at = 4 + 24 + cred.len();
+        for (name, flavor) in [
+            ("verifier_auth_none", auth_flavor::AUTH_NONE),
+            ("verifier_auth_sys", auth_flavor::AUTH_SYS),
+            ("verifier_flavor_unknown", 0xffff),
+        ] {
+            let mut msg = good.clone();
+            msg[at..at + 4].copy_from_slice(&flavor.to_be_bytes());
+            cases.push(named(name.to_string(), msg));
+        }
+        Ok(cases)
+    }
+
+    /// Plans that walk the server's sequence window, each starting past
+    /// the highest number of the one before it, the first past the
+    /// context's last. Numbers above the highest the server has seen
+    /// move the window up; unseen numbers in the window are carried out;
+    /// numbers already seen or below the window are dropped without a
+    /// reply (RFC 2203 §5.3.3.1). Run a plan's steps in order with
+    /// [`Context::call_at`] and [`check_window`], then
+    /// [`Context::skip_to`] its [`WindowPlan::highest`].
+    pub fn window_plans(&self) -> Vec<WindowPlan> {
+        let w = self.window.max(1);
+        let step = |seq: u32, expect| WindowStep { seq, expect };
+        // Under a window of one, the number below a new highest is
+        // already out of it
+        let below = if w > 1 { Window::Accept } else { Window::Drop };
+        let mut plans = Vec::new();
+
+        // The window filled from the top down, then the ends and middle
+        // of it sent again
+        let base = self.seq;
+        let n = w.min(MAX_WINDOW_FILL);
+        let mut steps: Vec<WindowStep> = (1..=n)
+            .rev()
+            .map(|i| step(base + i, Window::Accept))
+            .collect();
+        let mut again = vec![n, n.div_ceil(2), 1];
+        again.dedup();
+        for i in again {
+            steps.push(step(base + i, Window::Drop));
+        }
+        plans.push(WindowPlan::new("window_reverse_fill", steps));
+
+        // A jump of two windows, then each side of the bottom edge
+        let h = plans[0].highest().saturating_add(2 * w);
+        plans.push(WindowPlan::new(
+            "window_edges",
+            vec![
+                step(h, Window::Accept),
+                step(h - w + 1, below),
+                step(h - w, Window::Drop),
+                step(h, Window::Drop),
+                step(h + 1, Window::Accept),
+            ],
+        ));
+
+        // The same new number twice, then the one before it again
+        let n = plans[1].highest() + 1;
+        plans.push(WindowPlan::new(
+            "window_duplicate",
+            vec![
+                step(n, Window::Accept),
+                step(n, Window::Drop),
+                step(n - 1, Window::Drop),
+            ],
+        ));
+
+        // Far ahead, which leaves everything before it behind the window
+        // except the numbers just under it
+        let last = plans[2].highest();
+        let f = last.saturating_add(w.saturating_mul(64));
+        plans.push(WindowPlan::new(
+            "window_far_ahead",
+            vec![
+                step(f, Window::Accept),
+                step(last + 1, Window::Drop),
+                step(f - 1, below),
+            ],
+        ));
+        plans
+    }
+
+    /// Carry on numbering after `seq`, the highest a plan sent
+    pub fn skip_to(&mut self, seq: u32) {
+        self.seq = self.seq.max(seq);
+    }
+
+    /// RPCSEC_GSSv2 BIND_CHANNEL calls (RFC 5403) claiming the channel
+    /// bindings with hash `hash`: a well-formed one, then ones with the
+    /// MIC broken, the hash empty or huge, the arguments missing or
+    /// overrunning, a version 1 credential, a replayed sequence number
+    /// and a handle the server never issued. Over plain TCP there are no
+    /// channel bindings, so a server must refuse even the well-formed
+    /// one.
+    pub fn bind_channel_calls(&self, hash: &[u8]) -> Result<Vec<(String, BytesMut)>, GssError> {
+        let seq = self.next_seq();
+        let code = self.service.code();
+        let cred = |version: u32, seq: u32, handle: &[u8]| {
+            let mut body = XdrEncoder::new();
+            for w in [version, gss_proc::BIND_CHANNEL, seq, code] {
+                body.put_u32(w);
+            }
+            body.put_opaque(handle);
+            let mut enc = XdrEncoder::new();
+            enc.put_u32(auth_flavor::RPCSEC_GSS);
+            enc.put_opaque(body.as_bytes());
+            enc.as_bytes().to_vec()
+        };
+        // rgss2_bind_chan_verf_args: the MIC's input, holding the hash,
+        // then the MIC
+        let args = |hash: &[u8], edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut input = XdrEncoder::new();
+            input.put_opaque(hash);
+            let mut mic = self.mech.get_mic(input.as_bytes())?;
+            edit(&mut mic);
+            let mut enc = XdrEncoder::new();
+            enc.put_opaque(input.as_bytes());
+            enc.put_opaque(&mic);
+            Ok::<_, GssError>(enc.as_bytes().to_vec())
+        };
+        let call = |name: &str, cred: Vec<u8>, args: &[u8]| {
+            let msg = RpcCall::new(next_xid(), self.program, self.version, 0, true)
+                .with_gss(&cred, |h| self.mech.get_mic(h))?
+                .with_args(args)
+                .build();
+            Ok::<_, GssError>((format!("gss_bind_channel{}", name), msg))
+        };
+        let v2 = cred(2, seq, &self.handle);
+        let good = args(hash, &|_| {})?;
+        [
+            call("", v2.clone(), &good),
+            call(
+                "_mic_flipped",
+                v2.clone(),
+                &args(hash, &|m| {
+                    if let Some(b) = m.last_mut() {
+                        *b ^= 1;
+                    }
+                })?,
+            ),
+            call("_mic_empty", v2.clone(), &args(hash, &|m| m.clear())?),
+            call("_hash_empty", v2.clone(), &args(&[], &|_| {})?),
+            call(
+                "_hash_64k",
+                v2.clone(),
+                &args(&vec![0xa5; 64 << 10], &|_| {})?,
+            ),
+            call("_no_args", v2.clone(), &[]),
+            call("_args_overflow", v2, &[0xff; 4]),
+            call("_version_1", cred(1, seq, &self.handle), &good),
+            call("_replay", cred(2, self.seq.max(1), &self.handle), &good),
+            call("_unknown_handle", cred(2, seq, BOGUS_HANDLE), &good),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    /// A sequence number just behind the window of the last call, which
+    /// the server has to drop silently
+    pub fn stale_seq(&self) -> u32 {
+        self.seq.saturating_sub(self.window)
+    }
+
+    /// The results of the reply to the call with sequence number `seq`,
+    /// with its verifier checked and its body unwrapped
+    pub fn results(&self, seq: u32, reply: &RpcReply<'_>) -> Result<Vec<u8>, GssError> {
+        match reply.verifier() {
+            Some(v)
+                if v.flavor == auth_flavor::RPCSEC_GSS
+                    && self.mech.verify_mic(&seq.to_be_bytes(), v.body) => {}
+            _ => return Err(GssError::BadVerifier),
+        }
+        let results = reply.clone().into_results()?;
+        Ok(match self.service {
+            Service::Authentication => results.to_vec(),
+            Service::Integrity => {
+                rpc::gss_integ_unwrap(results, seq, |m, c| self.mech.verify_mic(m, c))?.to_vec()
+            }
+            Service::Privacy => rpc::gss_priv_unwrap(results, seq, |t| self.mech.unwrap(t))?,
+        })
+    }
+
+    /// RPCSEC_GSS_DESTROY of the context
+    pub fn destroy(&mut self) -> Result<BytesMut, GssError> {
+        self.seq += 1;
+        let cred = rpc::rpcsec_gss_cred(
+            gss_proc::DESTROY,
+            self.seq,
+            self.service.code(),
+            &self.handle,
+        );
+        Ok(
+            RpcCall::new(next_xid(), self.program, self.version, 0, true)
+                .with_gss(&cred, |header| self.mech.get_mic(header))?
+                .build(),
+        )
+    }
+}
+
+/// Most bytes of a checksum [`Context::mic_calls`] flips one at a time
+pub const MAX_MIC_FLIPS: usize = 64;
+
+/// Most numbers the first [`Context::window_plans`] plan fills the
+/// window with
+pub const MAX_WINDOW_FILL: u32 = 32;
+
+/// What a correct server does with one call of a [`WindowPlan`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// Carries it out and replies
+    Accept,
+    /// Drops it without a reply
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowStep {
+    pub seq: u32,
+    pub expect: Window,
+}
+
+/// Sequence numbers to send in order on a context
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowPlan {
+    pub name: String,
+    pub steps: Vec<WindowStep>,
+}
+
+impl WindowPlan {
+    fn new(name: &str, steps: Vec<WindowStep>) -> Self {
+        Self {
+            name: name.to_string(),
+            steps,
+        }
+    }
+
+    /// The highest sequence number the plan sends
+    pub fn highest(&self) -> u32 {
+        self.steps.iter().map(|s| s.seq).max().unwrap_or(0)
+    }
+}
+
+/// Check the outcome of `step`: the reply to it, `None` if there was
+/// none within the budget. An Accept step needs a reply that is not a
+/// denial; a Drop step must go unanswered.
+pub fn check_window(step: &WindowStep, reply: Option<&RpcReply<'_>>) -> Result<(), String> {
+    match (step.expect, reply) {
+        (Window::Accept, None) => Err(format!("sequence number {} went unanswered", step.seq)),
+        (Window::Accept, Some(r)) if r.verifier().is_none() => Err(format!(
+            "sequence number {} denied: {:?}",
+            step.seq, r.status
+        )),
+        (Window::Drop, Some(r)) => Err(format!(
+            "sequence number {} answered ({:?}) instead of dropped",
+            step.seq, r.status
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Run the [`Context::window_plans`] of `ctx` on `conn`, each step a
+/// call of `procedure` with `args`, NULL and nothing being the calls
+/// with the least side effects; the steps the server got wrong, each
+/// named by its plan
+pub async fn probe_window<S, M>(
+    conn: &mut NfsConnection<S>,
+    ctx: &mut Context<M>,
+    procedure: u32,
+    args: &[u8],
+) -> Result<Vec<String>, GssError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    M: Mechanism,
+{
+    let mut violations = Vec::new();
+    for plan in ctx.window_plans() {
+        for step in &plan.steps {
+            let msg = ctx.call_at(step.seq, ctx.service, procedure, args)?;
+            let checked = match conn.call(&msg).await {
+                Ok(reply) => check_window(step, Some(&RpcReply::parse(&reply)?)),
+                Err(ConnectionError::Timeout { .. }) => check_window(step, None),
+                Err(e) => return Err(e.into()),
+            };
+            if let Err(e) = checked {
+                violations.push(format!("{}: {}", plan.name, e));
+            }
+        }
+        ctx.skip_to(plan.highest());
+    }
+    Ok(violations)
+}
+
+/// DER length octets (X.690 §8.1.3)
+fn der_length(len: usize) -> Vec<u8> {
+    if len < 0x80 {
+        return vec![len as u8];
+    }
+    let bytes = (len as u32).to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count();
+    let mut out = vec![0x80 | (4 - skip) as u8];
+    out.extend_from_slice(&bytes[skip..]);
+    out
+}
+
+/// An InitialContextToken (RFC 2743 §3.1) for the Kerberos V5 mechanism
+/// around `inner`, which starts with the two-byte token id of RFC 1964
+fn krb5_token(inner: &[u8]) -> Vec<u8> {
+    let mut body = KRB5_OID.to_vec();
+    body.extend_from_slice(inner);
+    let mut token = vec![0x60];
+    token.extend(der_length(body.len()));
+    token.extend(body);
+    token
+}
+
+/// Malformed initial context tokens: broken DER framing around the
+/// mechanism OID, another mechanism, and Kerberos tokens that are empty,
+/// of the wrong kind or whose AP-REQ claims more than is there
+pub fn malformed_tokens() -> Vec<(&'static str, Vec<u8>)> {
+    // SPNEGO, 1.3.6.1.5.5.2
+    let spnego = [0x60, 0x08, 0x06, 0x06, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x02];
+    vec![
+        ("empty", Vec::new()),
+        ("not_der", b"NTLMSSP\0".to_vec()),
+        ("tag_only", vec![0x60]),
+        (
+            "length_overflow",
+            vec![0x60, 0x84, 0xff, 0xff, 0xff, 0xff, 0x06],
+        ),
+        ("length_octets", vec![0x60, 0x89, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+        (
+            "length_indefinite",
+            [&[0x60, 0x80][..], KRB5_OID, &[0, 0]].concat(),
+        ),
+        (
+            "length_past_end",
+            [&[0x60, 0x82, 0x40, 0x00][..], KRB5_OID].concat(),
+        ),
+        (
+            "oid_length_overflow",
+            vec![0x60, 0x05, 0x06, 0x84, 0xff, 0xff, 0xff],
+        ),
+        ("oid_past_end", vec![0x60, 0x03, 0x06, 0x7f, 0x2a]),
+        ("other_mech", spnego.to_vec()),
+        ("krb5_no_token", krb5_token(&[])),
+        ("krb5_tok_id_only", krb5_token(&[0x01])),
+        ("krb5_bad_tok_id", krb5_token(&[0xff, 0xff, 0x6e, 0x00])),
+        ("krb5_ap_rep", krb5_token(&[0x02, 0x00, 0x6f, 0x00])),
+        ("krb5_ap_req_empty", krb5_token(&[0x01, 0x00, 0x6e, 0x00])),
+        (
+            "krb5_ap_req_overflow",
+            krb5_token(&[0x01, 0x00, 0x6e, 0x84, 0xff, 0xff, 0xff, 0xff]),
+        ),
+        (
+            "krb5_ap_req_truncated",
+            krb5_token(&[0x01, 0x00, 0x6e, 0x82, 0x01, 0x00, 0x30, 0x81]),
+        ),
+        ("huge", krb5_token(&vec![0x41; HUGE_TOKEN])),
+    ]
+}
+
+/// RPCSEC_GSS credentials of raw `words`, for bodies no version 1
+/// credential has
+fn raw_cred(words: &[u32]) -> Vec<u8> {
+    let mut body = XdrEncoder::new();
+    for &w in words {
+        body.put_u32(w);
+    }
+    let mut enc = XdrEncoder::new();
+    enc.put_u32(auth_flavor::RPCSEC_GSS);
+    enc.put_opaque(body.as_bytes());
+    enc.as_bytes().to_vec()
+}
+
+/// GSS control cases against `program`/`version` that need no context:
+/// INIT with each of the [`malformed_tokens`], then control procedures
+/// with handles the server never issued, unknown procedures, versions
+/// and services, and credential bodies that are cut short or claim more
+/// than they hold
+pub fn control_cases(program: u32, version: u32) -> Vec<(String, BytesMut)> {
+    let mut cases: Vec<(String, BytesMut)> = malformed_tokens()
+        .into_iter()
+        .map(|(name, token)| {
+            let msg = init_call(program, version, Service::Integrity, None, &token);
+            (format!("gss_init_{}", name), msg)
+        })
+        .collect();
+    let mut token = XdrEncoder::new();
+    token.put_opaque(&krb5_token(&[0x01, 0x00]));
+    let token = token.as_bytes();
+    let call = |name: &str, cred: Vec<u8>, procedure: u32, args: &[u8]| {
+        let msg = RpcCall::new(next_xid(), program, version, procedure, true)
+            .with_auth(&cred, &auth_none())
+            .with_args(args)
+            .build();
+        (format!("gss_{}", name), msg)
+    };
+    let cred = rpc::rpcsec_gss_cred;
+    let (none, integ) = (gss_service::NONE, gss_service::INTEGRITY);
+    let (init, data) = (gss_proc::INIT, gss_proc::DATA);
+    cases.extend([
+        call(
+            "init_with_handle",
+            cred(init, 0, integ, BOGUS_HANDLE),
+            0,
+            token,
+        ),
+        call("init_not_nullproc", cred(init, 0, integ, b""), 1, token),
+        call("init_no_args", cred(init, 0, integ, b""), 0, &[]),
+        call(
+            "init_args_overflow",
+            cred(init, 0, integ, b""),
+            0,
+            &[0xff; 4],
+        ),
+        call(
+            "continue_unknown_handle",
+            cred(gss_proc::CONTINUE_INIT, 0, integ, BOGUS_HANDLE),
+            0,
+            token,
+        ),
+        call(
+            "continue_no_handle",
+            cred(gss_proc::CONTINUE_INIT, 0, integ, b""),
+            0,
+            token,
+        ),
+        call(
+            "data_unknown_handle",
+            cred(data, 1, none, BOGUS_HANDLE),
+            0,
+            &[],
+        ),
+        call("data_no_handle", cred(data, 1, none, b""), 0, &[]),
+        call(
+            "destroy_unknown_handle",
+            cred(gss_proc::DESTROY, 1, none, BOGUS_HANDLE),
+            0,
+            &[],
+        ),
+        call("proc_unknown", cred(4, 0, integ, b""), 0, token),
+        call("proc_max", cred(u32::MAX, 0, integ, b""), 0, token),
+        call("service_zero", cred(init, 0, 0, b""), 0, token),
+        call("service_unknown", cred(init, 0, 4, b""), 0, token),
+        call(
+            "seq_maxseq",
+            cred(data, GSS_MAXSEQ, none, BOGUS_HANDLE),
+            0,
+            &[],
+        ),
+        call("seq_max", cred(data, u32::MAX, none, BOGUS_HANDLE), 0, &[]),
+        call("version_zero", raw_cred(&[0, init, 0, integ, 0]), 0, token),
+        call("version_two", raw_cred(&[2, init, 0, integ, 0]), 0, token),
+        call("cred_truncated", raw_cred(&[1, init]), 0, token),
+        call("cred_empty", raw_cred(&[]), 0, token),
+        call(
+            "handle_length_overflow",
+            raw_cred(&[1, data, 1, none, u32::MAX]),
+            0,
+            &[],
+        ),
+        call(
+            "handle_past_cred",
+            raw_cred(&[1, data, 1, none, 64]),
+            0,
+            &[],
+        ),
+        call(
+            "handle_oversized",
+            cred(data, 1, none, &[0x41; 1024]),
+            0,
+            &[],
+        ),
+    ]);
+    // INIT takes an AUTH_NONE verifier
+    let Ok(msg) = RpcCall::new(next_xid(), program, version, 0, true)
+        .with_gss(&cred(init, 0, integ, b""), |_| {
+            Ok::<_, Infallible>(vec![0; 28])
+        })
+        .map(|call| call.with_args(token).build());
+    cases.push(("gss_init_gss_verifier".to_string(), msg));
+    cases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Timeouts;
+    use crate::hang::read_record;
+    use crate::rpc::program;
+    use tokio::io::AsyncWriteExt;
+
+    /// Two rounds to set up; the "checksum" is a byte sum and "sealing"
+    /// flips every bit
+    #[derive(Debug, Default)]
+    struct Toy {
+        rounds: usize,
+    }
+
+    fn sum(msg: &[u8]) -> Vec<u8> {
+        let s = msg.iter().fold(0u32, |a, &b| a.wrapping_add(b.into()));
+        s.to_be_bytes().to_vec()
+    }
+
+    impl Mechanism for Toy {
+        fn step(&mut self, _input: Option<&[u8]>) -> Result<Option<Vec<u8>>, GssError> {
+            self.rounds += 1;
+            Ok(Some(vec![self.rounds as u8; 3]))
+        }
+        fn get_mic(&self, msg: &[u8]) -> Result<Vec<u8>, GssError> {
+            Ok(sum(msg))
+        }
+        fn verify_mic(&self, msg: &[u8], mic: &[u8]) -> bool {
+            sum(msg) == mic
+        }
+        fn wrap(&self, msg: &[u8]) -> Result<Vec<u8>, GssError> {
+            Ok(msg.iter().map(|b| !b).collect())
+        }
+        fn unwrap(&self, token: &[u8]) -> Option<Vec<u8>> {
+            Some(token.iter().map(|b| !b).collect())
+        }
+    }
+
+    /// An accepted reply with a GSS verifier `verf` and `results`
+    fn reply(xid: &[u8], verf: &[u8], results: &[u8]) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        enc.put_raw(xid);
+        enc.put_u32(1);
+        enc.put_u32(0);
+        enc.put_u32(auth_flavor::RPCSEC_GSS);
+        enc.put_opaque(verf);
+        enc.put_u32(0);
+        enc.put_raw(results);
+        let mut record = (0x8000_0000 | enc.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(enc.as_bytes());
+        record
+    }
+
+    #[test]
+    fn test_services() {
+        assert_eq!("krb5i".parse::<Service>(), Ok(Service::Integrity));
+        assert!("krb4".parse::<Service>().is_err());
+        assert_eq!(Service::Privacy.to_string(), "krb5p");
+
+        let mut ctx = Context {
+            mech: Toy::default(),
+            handle: b"ctx".to_vec(),
+            service: Service::Integrity,
+            window: 128,
+            program: program::NFS,
+            version: 4,
+            seq: 0,
+        };
+        let (seq, msg) = ctx.call(1, &[0, 0, 0, 9]).unwrap();
+        assert_eq!((seq, ctx.next_seq()), (1, 2));
+        // Credential: flavor, length, version, DATA, seq, service, handle
+        let cred = &msg[28..28 + 8 + 20 + 4];
+        assert_eq!(cred[12..16], gss_proc::DATA.to_be_bytes());
+        assert_eq!(cred[16..20], 1u32.to_be_bytes());
+        assert_eq!(cred[20..24], gss_service::INTEGRITY.to_be_bytes());
+        // Verifier over xid through credentials
+        let verf = &msg[60..72];
+        assert_eq!(verf[8..], sum(&msg[4..60])[..]);
+        // Arguments: opaque of seq and args, then its checksum
+        assert_eq!(msg[72..84], [0, 0, 0, 8, 0, 0, 0, 1, 0, 0, 0, 9]);
+        assert_eq!(msg[88..], sum(&[0, 0, 0, 1, 0, 0, 0, 9])[..]);
+
+        // Results come back under the same sequence number
+        let results = rpc::gss_integ_body(1, b"okay", |m| ctx.mech.get_mic(m)).unwrap();
+        let good = reply(&[0; 4], &sum(&1u32.to_be_bytes()), &results);
+        let parsed = RpcReply::parse(&good[4..]).unwrap();
+        assert_eq!(ctx.results(1, &parsed).unwrap(), b"okay");
+        assert!(matches!(
+            ctx.results(2, &parsed),
+            Err(GssError::BadVerifier)
+        ));
+        let replayed = reply(&[0; 4], &sum(&2u32.to_be_bytes()), &results);
+        let parsed = RpcReply::parse(&replayed[4..]).unwrap();
+        assert!(matches!(
+            ctx.results(2, &parsed),
+            Err(GssError::Rpc(RpcError::GssSeq { sent: 2, got: 1 }))
+        ));
+
+        ctx.service = Service::Privacy;
+        let sealed = rpc::gss_priv_body(5, b"data", |m| ctx.mech.wrap(m)).unwrap();
+        let msg = reply(&[0; 4], &sum(&5u32.to_be_bytes()), &sealed);
+        let parsed = RpcReply::parse(&msg[4..]).unwrap();
+        assert_eq!(ctx.results(5, &parsed).unwrap(), b"data");
+        ctx.seq = GSS_MAXSEQ - 1;
+        assert!(matches!(ctx.call(1, &[]), Err(GssError::SequenceExhausted)));
+        assert_eq!(ctx.stale_seq(), GSS_MAXSEQ - 1 - 128);
+    }
+
+    #[tokio::test]
+    async fn test_establish_in_two_rounds() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut conn = NfsConnection::new(client, Timeouts::default());
+        let serve = async {
+            for (major, handle) in [(GSS_S_CONTINUE_NEEDED, b"h1"), (GSS_S_COMPLETE, b"h2")] {
+                let call = read_record(&mut server).await.unwrap();
+                // NULL procedure; CONTINUE_INIT names the handle so far
+                assert_eq!(call[20..24], [0; 4]);
+                let proc = u32::from_be_bytes(call[36..40].try_into().unwrap());
+                let expect = if major == GSS_S_CONTINUE_NEEDED {
+                    gss_proc::INIT
+                } else {
+                    gss_proc::CONTINUE_INIT
+                };
+                assert_eq!(proc, expect);
+                let mut res = XdrEncoder::new();
+                res.put_opaque(handle);
+                res.put_u32(major);
+                res.put_u32(0);
+                res.put_u32(64);
+                res.put_opaque(b"");
+                let verf = sum(&64u32.to_be_bytes());
+                server
+                    .write_all(&reply(&call[..4], &verf, res.as_bytes()))
+                    .await
+                    .unwrap();
+            }
+        };
+        let (_, ctx) = tokio::join!(
+            serve,
+            establish(&mut conn, Toy::default(), Service::Privacy, program::NFS, 4)
+        );
+        let mut ctx = ctx.unwrap();
+        assert_eq!((ctx.handle.as_slice(), ctx.window), (&b"h2"[..], 64));
+        assert_eq!(ctx.mech.rounds, 2);
+        let destroy = ctx.destroy().unwrap();
+        // After the record mark
+        assert_eq!(destroy[40..44], gss_proc::DESTROY.to_be_bytes());
+    }
+
+    /// Credential body, verifier body and arguments of record-marked `msg`
+    fn parts(msg: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let mut dec = XdrDecoder::new(&msg[4..]);
+        for _ in 0..7 {
+            dec.get_u32().unwrap();
+        }
+        let cred = dec.get_opaque().unwrap().to_vec();
+        dec.get_u32().unwrap();
+        let verf = dec.get_opaque().unwrap().to_vec();
+        (cred, verf, dec.rest().to_vec())
+    }
+
+    #[test]
+    fn test_control_cases() {
+        assert_eq!(der_length(5), [5]);
+        assert_eq!(der_length(0x1234), [0x82, 0x12, 0x34]);
+        let tokens = malformed_tokens();
+        let huge = &tokens.last().unwrap().1;
+        assert_eq!(huge[..5], [0x60, 0x83, 0x10, 0x00, 0x0b]);
+        assert_eq!(huge.len(), 5 + KRB5_OID.len() + HUGE_TOKEN);
+
+        let cases = control_cases(program::NFS, 4);
+        let names: std::collections::HashSet<&str> =
+            cases.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names.len(), cases.len());
+        assert!(cases.len() > tokens.len() + 20);
+        let find = |name: &str| &cases.iter().find(|(n, _)| n == name).unwrap().1;
+        // The token travels as the opaque argument of INIT
+        let (cred, verf, args) = parts(find("gss_init_tag_only"));
+        assert_eq!(cred[4..8], gss_proc::INIT.to_be_bytes());
+        assert!(verf.is_empty());
+        assert_eq!(args, [0, 0, 0, 1, 0x60, 0, 0, 0]);
+        let (cred, _, _) = parts(find("gss_handle_length_overflow"));
+        assert_eq!(cred[16..], [0xff; 4]);
+        let (_, verf, _) = parts(find("gss_init_gss_verifier"));
+        assert_eq!(verf.len(), 28);
+    }
+
+    #[test]
+    fn test_window_plans_match_the_window() {
+        for window in [1, 16, 128] {
+            let mut ctx = Context::new(
+                Toy::default(),
+                b"ctx".to_vec(),
+                Service::Integrity,
+                window,
+                program::NFS,
+                4,
+            );
+            ctx.seq = 7;
+            // RFC 2203 §5.3.3.1 as the server runs it, the context's
+            // numbers up to 7 already seen
+            let mut seen: std::collections::HashSet<u32> = (1..=7).collect();
+            let mut highest = 7;
+            for plan in ctx.window_plans() {
+                assert!(plan.steps.iter().all(|s| s.seq > 0 && s.seq < GSS_MAXSEQ));
+                for step in &plan.steps {
+                    let inside = step.seq + window > highest;
+                    let expect = match inside && seen.insert(step.seq) {
+                        true => Window::Accept,
+                        false => Window::Drop,
+                    };
+                    assert_eq!(step.expect, expect, "{} {:?}", plan.name, step);
+                    highest = highest.max(step.seq);
+                }
+                ctx.skip_to(plan.highest());
+            }
+            assert_eq!(ctx.seq, highest);
+        }
+        let step = WindowStep {
+            seq: 9,
+            expect: Window::Drop,
+        };
+        assert!(check_window(&step, None).is_ok());
+        let accepted = reply(&[0; 4], &[], &[]);
+        let parsed = RpcReply::parse(&accepted[4..]).unwrap();
+        assert!(check_window(&step, Some(&parsed)).is_err());
+    }
+
+    #[test]
+    fn test_mic_and_bind_channel_calls() {
+        let ctx = Context::new(
+            Toy::default(),
+            b"ctx".to_vec(),
+            Service::Integrity,
+            16,
+            program::NFS,
+            4,
+        );
+        let cases = ctx.mic_calls(1, &[0, 0, 0, 9]).unwrap();
+        let names: std::collections::HashSet<&str> =
+            cases.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names.len(), cases.len());
+        // The toy checksum is four bytes
+        assert!(names.contains("gss_mic_byte_3") && !names.contains("gss_mic_byte_4"));
+        let find = |name: &str| &cases.iter().find(|(n, _)| n == name).unwrap().1;
+        let header = |msg: &[u8]| msg[4..60].to_vec();
+        for name in ["gss_mic_byte_0", "gss_mic_other_xid", "gss_mic_next_seq"] {
+            let msg = find(name);
+            assert_ne!(parts(msg).1, sum(&header(msg)), "{}", name);
+        }
+        let (_, verf, _) = parts(find("gss_mic_padded"));
+        assert_eq!(verf.len(), 8);
+        // Verifier flavor after the header, its body still good
+        let msg = find("gss_verifier_auth_sys");
+        assert_eq!(msg[60..64], auth_flavor::AUTH_SYS.to_be_bytes());
+        assert_eq!(parts(msg).1, sum(&header(msg)));
+
+        let cases = ctx.bind_channel_calls(&[0x11; 32]).unwrap();
+        let find = |name: &str| parts(&cases.iter().find(|(n, _)| n == name).unwrap().1);
+        let (cred, _, args) = find("gss_bind_channel");
+        assert_eq!(cred[..8], [0, 0, 0, 2, 0, 0, 0, 4]);
+        // The MIC's input holds the hash, and the MIC is over that input
+        assert_eq!(args[..8], [0, 0, 0, 36, 0, 0, 0, 32]);
+        assert_eq!(args[40..], [&[0, 0, 0, 4][..], &sum(&args[4..40])].concat());
+        let (cred, _, _) = find("gss_bind_channel_version_1");
+        assert_eq!(cred[..4], 1u32.to_be_bytes());
+        assert!(find("gss_bind_channel_no_args").2.is_empty());
+    }
+
+    #[test]
+    fn test_context_fuzz_calls() {
+        let ctx = Context {
+            mech: Toy::default(),
+            handle: b"ctx".to_vec(),
+            service: Service::Integrity,
+            window: 16,
+            program: program::NFS,
+            version: 4,
+            seq: 40,
+        };
+        let cases = ctx.fuzz_calls(1, &[0, 0, 0, 9]).unwrap();
+        let find = |name: &str| parts(&cases.iter().find(|(n, _)| n == name).unwrap().1);
+        let seq_of = |cred: &[u8]| u32::from_be_bytes(cred[8..12].try_into().unwrap());
+        assert_eq!(seq_of(&find("gss_replay").0), 40);
+        assert_eq!(seq_of(&find("gss_stale").0), 24);
+        assert_eq!(seq_of(&find("gss_seq_maxseq").0), GSS_MAXSEQ);
+        // The context itself does not move
+        assert_eq!(ctx.next_seq(), 41);
+
+        let (_, verf, args) = find("gss_mic_truncated");
+        assert_eq!(verf.len(), 2);
+        let (_, good, _) = find("gss_service_krb5p");
+        assert_eq!(good.len(), 4);
+        let (_, _, args_again) = find("gss_integ_seq_mismatch");
+        assert!(matches!(
+            rpc::gss_integ_unwrap(&args_again, 41, |m, c| sum(m) == c),
+            Err(RpcError::GssSeq { sent: 41, got: 42 })
+        ));
+        assert!(rpc::gss_integ_unwrap(&args, 41, |m, c| sum(m) == c).is_ok());
+        let (_, _, args) = find("gss_integ_mic_flipped");
+        assert!(matches!(
+            rpc::gss_integ_unwrap(&args, 41, |m, c| sum(m) == c),
+            Err(RpcError::GssChecksum)
+        ));
+        let (cred, _, _) = find("gss_destroy_mic_flipped");
+        assert_eq!(cred[4..8], gss_proc::DESTROY.to_be_bytes());
+    }
+}
@@ -0,0 +1,240 @@
+//! Per-request latency budget and hang capture
+//!
+//! A request that never gets a reply while the connection stays open is
+//! a different bug from a dropped connection: some handler is stuck. The
+//! request budget is separate from the connect timeout, and a follow-up
+//! NULL tells a stuck request (NULL still answered) from a stalled
+//! server (NULL unanswered too).
+
+use crate::lineage::Lineage;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::timeout;
+
+/// Timeouts for one connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyBudget {
+    /// Establishing the connection
+    pub connect: Duration,
+    /// Waiting for the reply to one request
+    pub request: Duration,
+    /// Waiting for the follow-up NULL after a hang
+    pub probe: Duration,
+}
+
+impl Default for LatencyBudget {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(10),
+            request: Duration::from_secs(5),
+            probe: Duration::from_secs(5),
+        }
+    }
+}
+
+/// How far a hang reaches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HangKind {
+    /// Only this request is stuck; the follow-up NULL was answered
+    Request,
+    /// The follow-up NULL went unanswered too
+    Server,
+}
+
+/// Outcome of a request under the budget
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Timing {
+    Replied { elapsed: Duration, reply: Vec<u8> },
+    Hang(HangKind),
+}
+
+/// Read one complete record-marked reply, joining fragments
+pub async fn read_record<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<Vec<u8>> {
+    let mut record = Vec::new();
+    loop {
+        let mark = stream.read_u32().await?;
+        let start = record.len();
+        record.resize(start + (mark & 0x7fff_ffff) as usize, 0);
+        stream.read_exact(&mut record[start..]).await?;
+        if mark & 0x8000_0000 != 0 {
+            return Ok(record);
+        }
+    }
+}
+
+fn xid(record: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(record.get(..4)?.try_into().ok()?))
+}
+
+/// Send `msg` and wait up to the request budget for its reply
+///
+/// On a hang, `null` (a record-marked NULL call) is sent and replies are
+/// read until the one with its XID arrives or the probe budget runs out.
+/// A late reply to `msg` seen meanwhile is discarded; the request still
+/// counts as hung.
+pub async fn timed_call<S>(
+    stream: &mut S,
+    msg: &[u8],
+    null: &[u8],
+    budget: &LatencyBudget,
+) -> io::Result<Timing>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let start = Instant::now();
+    stream.write_all(msg).await?;
+    if let Ok(reply) = timeout(budget.request, read_record(stream)).await {
+        return Ok(Timing::Replied {
+            elapsed: start.elapsed(),
+            reply: reply?,
+        });
+    }
+
+    stream.write_all(null).await?;
+    let null_xid = null.get(4..).and_then(xid);
+    let probe = async {
+        loop {
+            let reply = read_record(stream).await?;
+            if xid(&reply) == null_xid {
+                return io::Result::Ok(());
+            }
+        }
+    };
+    Ok(match timeout(budget.probe, probe).await {
+        Ok(Ok(())) => Timing::Hang(HangKind::Request),
+        Ok(Err(e)) if e.kind() != io::ErrorKind::UnexpectedEof => return Err(e),
+        _ => Timing::Hang(HangKind::Server),
+    })
+}
+
+#[derive(Serialize)]
+struct HangRecord<'a> {
+    name: &'a str,
+    kind: HangKind,
+    budget_ms: u128,
+    lineage: &'a Lineage,
+}
+
+/// Save a hanging input as `<output>/hangs/<name>.bin` with a `.json`
+/// note of the hang kind, budget and how the input was derived; returns
+/// the input's path
+pub fn capture_hang(
+    output: &Path,
+    name: &str,
+    input: &[u8],
+    kind: HangKind,
+    budget: &LatencyBudget,
+    lineage: &Lineage,
+) -> io::Result<PathBuf> {
+    let dir = output.join("hangs");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.bin", name));
+    std::fs::write(&path, input)?;
+    let record = HangRecord {
+        name,
+        kind,
+        budget_ms: budget.request.as_millis(),
+        lineage,
+    };
+    std::fs::write(
+        dir.join(format!("{}.json", name)),
+        serde_json::to_vec_pretty(&record)?,
+    )?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineage::Step;
+    use crate::rpc::{program, RpcCall};
+
+    fn call(xid: u32, procedure: u32) -> Vec<u8> {
+        RpcCall::new(xid, program::NFS, 3, procedure, true)
+            .with_auth_none()
+            .build()
+            .to_vec()
+    }
+
+    fn budget() -> LatencyBudget {
+        LatencyBudget {
+            connect: Duration::from_millis(50),
+            request: Duration::from_millis(50),
+            probe: Duration::from_millis(50),
+        }
+    }
+
+    /// Server that answers calls whose procedure is not `stuck` by
+    /// echoing their XID in a two-fragment reply
+    async fn server(mut s: tokio::io::DuplexStream, stuck: Option<u32>) {
+        while let Ok(call) = read_record(&mut s).await {
+            if Some(u32::from_be_bytes(call[20..24].try_into().unwrap())) == stuck {
+                continue;
+            }
+            let mut reply = vec![0, 0, 0, 2];
+            reply.extend_from_slice(&call[..2]);
+            reply.extend_from_slice(&[0x80, 0, 0, 2]);
+            reply.extend_from_slice(&call[2..4]);
+            if s.write_all(&reply).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_hang_vs_reply() {
+        let (mut client, srv) = tokio::io::duplex(4096);
+        tokio::spawn(server(srv, Some(6)));
+        let null = call(100, 0);
+
+        let timing = timed_call(&mut client, &call(1, 1), &null, &budget())
+            .await
+            .unwrap();
+        assert!(matches!(timing, Timing::Replied { reply, .. } if reply == [0, 0, 0, 1]));
+
+        let timing = timed_call(&mut client, &call(2, 6), &null, &budget())
+            .await
+            .unwrap();
+        assert_eq!(timing, Timing::Hang(HangKind::Request));
+    }
+
+    #[tokio::test]
+    async fn test_server_stall_and_capture() {
+        let (mut client, srv) = tokio::io::duplex(4096);
+        // Reads calls but never answers, keeping the connection open
+        tokio::spawn(async move {
+            let mut srv = srv;
+            while read_record(&mut srv).await.is_ok() {}
+        });
+        let msg = call(3, 1);
+        let timing = timed_call(&mut client, &msg, &call(101, 0), &budget())
+            .await
+            .unwrap();
+        assert_eq!(timing, Timing::Hang(HangKind::Server));
+
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-hang-{}", std::process::id()));
+        let lineage = Lineage::new("v3:GETATTR").then(Step::new("truncate").with("len", 40));
+        let path = capture_hang(
+            &dir,
+            "getattr_1",
+            &msg,
+            HangKind::Server,
+            &budget(),
+            &lineage,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), msg);
+        let note = std::fs::read_to_string(dir.join("hangs/getattr_1.json")).unwrap();
+        assert!(note.contains("\"server\""));
+        let note: serde_json::Value = serde_json::from_str(&note).unwrap();
+        assert_eq!(
+            serde_json::from_value::<Lineage>(note["lineage"].clone()).unwrap(),
+            lineage
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Finding NFS servers on the local network
+//!
+//! Portmappers answer UDP calls sent to a broadcast address, so one
+//! datagram per question reaches every server on the subnet. A scan
+//! broadcasts a portmap NULL and a GETPORT for each NFS version over
+//! TCP, gathers replies until the wait is over, and groups them by the
+//! host that sent them. A host that answers the NULL but registers no
+//! NFS version still shows up, as a portmapper without NFS.
+
+use crate::rpc::{self, next_xid, pmap, program, RpcCall, RpcReply};
+use bytes::BytesMut;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// NFS versions asked about
+pub const VERSIONS: [u32; 3] = [2, 3, 4];
+
+/// One host that answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Server {
+    pub addr: IpAddr,
+    /// TCP port of each NFS version registered
+    pub nfs: BTreeMap<u32, u16>,
+}
+
+impl Server {
+    /// Where to fuzz it: the port of its highest NFS version
+    pub fn target(&self) -> Option<SocketAddr> {
+        let (_, &port) = self.nfs.iter().next_back()?;
+        Some(SocketAddr::new(self.addr, port))
+    }
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+        if self.nfs.is_empty() {
+            return write!(f, ": portmap, no NFS registered");
+        }
+        for (i, (version, port)) in self.nfs.iter().enumerate() {
+            let sep = if i == 0 { ": NFS" } else { "," };
+            write!(f, "{} v{} (port {})", sep, version, port)?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls of one scan and the replies to them
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// NFS version each GETPORT asked about, by XID; `None` for the NULL
+    calls: HashMap<u32, Option<u32>>,
+    servers: BTreeMap<IpAddr, Server>,
+}
+
+impl Scan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn call(&mut self, msg: BytesMut, version: Option<u32>) -> Vec<u8> {
+        // Single-fragment calls: the datagram is the call without its mark
+        let datagram = msg[4..].to_vec();
+        let xid = u32::from_be_bytes(datagram[..4].try_into().unwrap());
+        self.calls.insert(xid, version);
+        datagram
+    }
+
+    /// The datagrams to broadcast: NULL, then a GETPORT per version
+    pub fn calls(&mut self) -> Vec<Vec<u8>> {
+        let null = RpcCall::new(
+            next_xid(),
+            program::PORTMAP,
+            pmap::VERSION,
+            pmap::NULL,
+            true,
+        )
+        .with_auth_none()
+        .build();
+        let mut datagrams = vec![self.call(null, None)];
+        for version in VERSIONS {
+            let getport = rpc::getport_call(program::NFS, version, pmap::IPPROTO_TCP);
+            datagrams.push(self.call(getport, Some(version)));
+        }
+        datagrams
+    }
+
+    /// Account for `datagram` from `peer`; anything that is not a
+    /// successful reply to one of this scan's calls is ignored
+    pub fn reply(&mut self, peer: IpAddr, datagram: &[u8]) {
+        let Ok(reply) = RpcReply::parse(datagram) else {
+            return;
+        };
+        let Some(&version) = self.calls.get(&reply.xid) else {
+            return;
+        };
+        let Ok(results) = reply.into_results() else {
+            return;
+        };
+        let server = self.servers.entry(peer).or_insert_with(|| Server {
+            addr: peer,
+            nfs: BTreeMap::new(),
+        });
+        if let Some(version) = version {
+            if let Ok(Some(port)) = rpc::getport_result(results) {
+                server.nfs.insert(version, port);
+            }
+        }
+    }
+
+    /// Hosts that answered, in address order
+    pub fn servers(self) -> Vec<Server> {
+        self.servers.into_values().collect()
+    }
+}
+
+/// Broadcast a scan to `to`, usually the subnet's broadcast address on
+/// port 111, and collect replies for `wait`
+pub async fn broadcast(to: SocketAddr, wait: Duration) -> std::io::Result<Vec<Server>> {
+    let socket =
+        UdpSocket::bind(SocketAddr::new(std::net::Ipv4Addr::UNSPECIFIED.into(), 0)).await?;
+    socket.set_broadcast(true)?;
+    let mut scan = Scan::new();
+    for datagram in scan.calls() {
+        socket.send_to(&datagram, to).await?;
+    }
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut buf = vec![0; 65536];
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (n, peer) = received?;
+        scan.reply(peer.ip(), &buf[..n]);
+    }
+    Ok(scan.servers())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::success_reply;
+
+    /// An accepted reply to `call` carrying `results`
+    fn reply(call: &[u8], results: &[u8]) -> Vec<u8> {
+        success_reply(u32::from_be_bytes(call[..4].try_into().unwrap()), results)
+    }
+
+    #[test]
+    fn test_scan_groups_replies_by_host() {
+        let mut scan = Scan::new();
+        let calls = scan.calls();
+        assert_eq!(calls.len(), 1 + VERSIONS.len());
+        let a: IpAddr = "10.0.0.2".parse().unwrap();
+        let b: IpAddr = "10.0.0.1".parse().unwrap();
+        scan.reply(a, &reply(&calls[0], &[]));
+        scan.reply(a, &reply(&calls[2], &[0, 0, 0x08, 0x01]));
+        scan.reply(a, &reply(&calls[3], &[0, 0, 0x08, 0x01]));
+        scan.reply(a, &reply(&calls[1], &[0, 0, 0, 0]));
+        scan.reply(b, &reply(&calls[0], &[]));
+        // Not ours
+        scan.reply(b, &reply(&[0xde, 0xad, 0xbe, 0xef], &[0, 0, 0x08, 0x01]));
+        let servers = scan.servers();
+        assert_eq!(servers.len(), 2);
+        assert_eq!(
+            servers[0].to_string(),
+            "10.0.0.1: portmap, no NFS registered"
+        );
+        assert_eq!(
+            servers[1].to_string(),
+            "10.0.0.2: NFS v3 (port 2049), v4 (port 2049)"
+        );
+        assert_eq!(servers[1].target(), Some(SocketAddr::new(a, 2049)));
+        assert_eq!(servers[0].target(), None);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_collects_until_wait() {
+        let portmap = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = portmap.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 256];
+            loop {
+                let (n, peer) = portmap.recv_from(&mut buf).await.unwrap();
+                // Answers the NULL and GETPORT v3 only
+                let results: &[u8] = match buf[n - 12..n - 8] {
+                    [0, 0, 0, 3] => &[0, 0, 0x08, 0x01],
+                    _ if n < 48 => &[],
+                    _ => continue,
+                };
+                portmap.send_to(&reply(&buf, results), peer).await.unwrap();
+            }
+        });
+        let servers = broadcast(addr, Duration::from_millis(300)).await.unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].nfs, BTreeMap::from([(3, 2049)]));
+    }
+}
@@ -0,0 +1,115 @@
+//! Panic isolation for fuzzer-side code
+//!
+//! A mutator that indexes past the end of a short argument buffer, or a
+//! reply handler that trips over a reply nobody anticipated, is a bug in
+//! the fuzzer, not a finding, and should not end a campaign that has been
+//! running for hours. Generation and each case's send-and-judge step run
+//! under [`catch`] and [`catch_future`], which turn a panic into its
+//! message; the loop logs it as an internal error, saves the input that
+//! caused it under `<output>/internal/` and carries on.
+//!
+//! A mutation that panics is recorded in the input's lineage as a
+//! [`PANIC`] step carrying the message, on the arguments it was given, so
+//! the input can travel to the loop like any other and be saved there.
+
+use crate::lineage::{Lineage, Step};
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Strategy name of the lineage step marking a mutation that panicked
+pub const PANIC: &str = "panic";
+
+/// The message a panic was raised with, as far as it can be recovered
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panic with a non-string payload".to_string()
+    }
+}
+
+/// Run `f`, turning a panic into its message
+pub fn catch<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|p| panic_message(&*p))
+}
+
+/// The lineage step recording a panic with `message`
+pub fn panic_step(message: &str) -> Step {
+    Step::new(PANIC).with("message", message)
+}
+
+/// The message of the panic recorded in `lineage`, if any
+pub fn panicked(lineage: &Lineage) -> Option<String> {
+    lineage
+        .steps
+        .iter()
+        .find(|s| s.strategy == PANIC)
+        .map(|s| match s.params.get("message") {
+            Some(serde_json::Value::String(m)) => m.clone(),
+            _ => String::new(),
+        })
+}
+
+/// Future returned by [`catch_future`]
+pub struct CatchUnwind<F> {
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = self.inner.as_mut();
+        match catch(|| inner.poll(cx)) {
+            Ok(Poll::Ready(v)) => Poll::Ready(Ok(v)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(message) => Poll::Ready(Err(message)),
+        }
+    }
+}
+
+/// Await `fut`, turning a panic in any of its polls into its message;
+/// the future is not polled again after it panicked
+pub fn catch_future<F: Future>(fut: F) -> CatchUnwind<F> {
+    CatchUnwind {
+        inner: Box::pin(fut),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch_and_panic_step() {
+        assert_eq!(catch(|| 7), Ok(7));
+        let args: Vec<u8> = Vec::new();
+        let err = catch(|| args[3]).unwrap_err();
+        assert!(err.contains("index out of bounds"), "{}", err);
+        assert_eq!(
+            catch(|| -> u8 { panic!("bad {}", 1) }),
+            Err("bad 1".to_string())
+        );
+
+        let seed = Lineage::new("v3:NULL");
+        assert_eq!(panicked(&seed), None);
+        let broken = seed.then(Step::new("bitflip")).then(panic_step("boom"));
+        assert_eq!(panicked(&broken).as_deref(), Some("boom"));
+    }
+
+    #[tokio::test]
+    async fn test_catch_future() {
+        assert_eq!(catch_future(async { 1 }).await, Ok(1));
+        let err = catch_future(async {
+            tokio::task::yield_now().await;
+            panic!("after a yield");
+        })
+        .await;
+        assert_eq!(err, Err::<(), _>("after a yield".to_string()));
+    }
+}
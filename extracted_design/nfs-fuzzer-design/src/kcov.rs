@@ -0,0 +1,344 @@
+//! Coverage feedback from the target's kernel
+//!
+//! Blind mutation draws every variant from the same bases; with coverage
+//! the campaign can keep the cases that reached code nothing before them
+//! did and mutate those instead. Linux's KCOV records the kernel PCs a
+//! task runs, but only for the task that asked. nfsd's work happens on
+//! kernel threads, so it can only come in through KCOV's remote mode,
+//! which collects from kernel threads that bracket their work with
+//! `kcov_remote_start_common()`. Upstream nfsd does not do that, so the
+//! target's kernel needs it added around request processing in
+//! `svc_process()`. An agent on the target then holds
+//! `/sys/kernel/debug/kcov` open with KCOV_REMOTE_ENABLE for the common
+//! handle.
+//!
+//! The fuzzer starts the agent over `ssh` and keeps its standard input
+//! and output for the campaign. After each case it writes `collect`, and
+//! the agent answers with one line: the PCs recorded since the last
+//! collection, in hex, space-separated, in the order they ran.
+//! Consecutive PCs hash to edges in a [`CoverageMap`], AFL style. A case
+//! that sets an edge never seen before goes into the [`Pool`] that
+//! [`crate::generate::guided`] picks the bases of its variants from.
+
+use crate::controller::Ssh;
+use crate::generate::Input;
+use crate::limits::{BoundedStore, MemoryBudget};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time::timeout;
+
+/// Edges the map tells apart
+pub const MAP_SIZE: usize = 1 << 16;
+
+/// Agent command run on the target when none is given
+pub const DEFAULT_AGENT: &str = "kcov-nfsd";
+
+/// How long the agent may take to answer a collection
+pub const COLLECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum KcovError {
+    #[error("could not start the coverage agent: {0}")]
+    Spawn(io::Error),
+    #[error("coverage agent: {0}")]
+    Io(#[from] io::Error),
+    #[error("coverage agent exited")]
+    Closed,
+    #[error("coverage agent did not answer within {0:?}")]
+    Timeout(Duration),
+    #[error("coverage agent sent {0:?}, not a PC")]
+    Parse(String),
+}
+
+/// Where the agent runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KcovConfig {
+    pub ssh: Ssh,
+    /// Command line of the agent on the target
+    pub agent: String,
+}
+
+/// `host=H[,port=N][,key=PATH][,agent=CMD]`
+impl FromStr for KcovConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ssh = None;
+        let (mut port, mut key, mut agent) = (None, None, DEFAULT_AGENT.to_string());
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let (k, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", part))?;
+            match k {
+                "host" => ssh = Some(Ssh::new(value)),
+                "port" => port = Some(value.parse().map_err(|_| format!("bad port {:?}", value))?),
+                "key" => key = Some(PathBuf::from(value)),
+                "agent" => agent = value.to_string(),
+                _ => return Err(format!("unknown kcov option {:?}", k)),
+            }
+        }
+        let mut ssh = ssh.ok_or("kcov needs host=")?;
+        ssh.port = port;
+        ssh.key = key;
+        Ok(Self { ssh, agent })
+    }
+}
+
+/// The PCs of one agent line
+pub fn parse_pcs(line: &str) -> Result<Vec<u64>, KcovError> {
+    line.split_whitespace()
+        .map(|pc| {
+            let digits = pc.strip_prefix("0x").unwrap_or(pc);
+            u64::from_str_radix(digits, 16).map_err(|_| KcovError::Parse(pc.to_string()))
+        })
+        .collect()
+}
+
+/// Edges seen so far, hashed into [`MAP_SIZE`] slots
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CoverageMap {
+    seen: Vec<u64>,
+    edges: usize,
+}
+
+impl Default for CoverageMap {
+    fn default() -> Self {
+        Self {
+            seen: vec![0; MAP_SIZE / 64],
+            edges: 0,
+        }
+    }
+}
+
+impl CoverageMap {
+    /// Slot of the edge from `prev` to `pc`; hashing the two ends
+    /// differently keeps A->B and B->A apart
+    fn slot(prev: u64, pc: u64) -> usize {
+        let h = pc.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ prev.wrapping_mul(0xc2b2_ae3d_27d4_eb4f);
+        (h >> 48) as usize % MAP_SIZE
+    }
+
+    /// Add the edges of a PC trace; returns how many were new
+    pub fn add(&mut self, pcs: &[u64]) -> usize {
+        let mut new = 0;
+        let mut prev = 0;
+        for &pc in pcs {
+            let slot = Self::slot(prev, pc);
+            let (word, bit) = (slot / 64, 1 << (slot % 64));
+            if self.seen[word] & bit == 0 {
+                self.seen[word] |= bit;
+                new += 1;
+            }
+            prev = pc;
+        }
+        self.edges += new;
+        new
+    }
+
+    /// Distinct edges seen
+    pub fn edges(&self) -> usize {
+        self.edges
+    }
+}
+
+/// Cases that found new coverage, shared between the loop that finds
+/// them and the generator that mutates them; over its memory budget the
+/// oldest cases make way for new ones
+#[derive(Debug, Clone)]
+pub struct Pool(Arc<Mutex<BoundedStore<Input>>>);
+
+impl Default for Pool {
+    /// A pool with a budget of its own and no ceiling
+    fn default() -> Self {
+        Self::new(MemoryBudget::new(usize::MAX))
+    }
+}
+
+impl Pool {
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self(Arc::new(Mutex::new(BoundedStore::new(budget))))
+    }
+
+    pub fn add(&self, input: Input) {
+        let bytes = input.footprint();
+        let _ = self.0.lock().unwrap().push(input, bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A case from the pool, newer ones as likely as older
+    pub fn pick<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Input> {
+        let inputs = self.0.lock().unwrap();
+        match inputs.len() {
+            0 => None,
+            n => inputs.get(rng.gen_range(0..n)).cloned(),
+        }
+    }
+}
+
+/// A running agent
+#[derive(Debug)]
+pub struct KcovSession {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl KcovSession {
+    /// Start the agent of `config` on the target
+    pub fn start(config: &KcovConfig) -> Result<Self, KcovError> {
+        Self::spawn(Command::from(config.ssh.command(&config.agent)))
+    }
+
+    fn spawn(mut cmd: Command) -> Result<Self, KcovError> {
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(KcovError::Spawn)?;
+        let stdin = child.stdin.take().ok_or(KcovError::Closed)?;
+        let stdout = child.stdout.take().ok_or(KcovError::Closed)?;
+        Ok(Self {
+            _child: child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    /// The PCs recorded since the last collection
+    pub async fn collect(&mut self) -> Result<Vec<u64>, KcovError> {
+        self.stdin.write_all(b"collect\n").await?;
+        self.stdin.flush().await?;
+        let mut line = String::new();
+        match timeout(COLLECT_TIMEOUT, self.stdout.read_line(&mut line)).await {
+            Err(_) => Err(KcovError::Timeout(COLLECT_TIMEOUT)),
+            Ok(Ok(0)) => Err(KcovError::Closed),
+            Ok(r) => {
+                r?;
+                parse_pcs(&line)
+            }
+        }
+    }
+}
+
+/// Coverage collection for a campaign: the agent, the edges seen and the
+/// pool new coverage goes to
+#[derive(Debug)]
+pub struct Feedback {
+    session: KcovSession,
+    map: CoverageMap,
+    pool: Pool,
+}
+
+impl Feedback {
+    pub fn new(session: KcovSession, pool: Pool) -> Self {
+        Self {
+            session,
+            map: CoverageMap::default(),
+            pool,
+        }
+    }
+
+    /// Collect the coverage of `input`, just sent, adding it to the pool
+    /// if it reached new edges; returns how many
+    pub async fn observe(&mut self, input: &Input) -> Result<usize, KcovError> {
+        let pcs = self.session.collect().await?;
+        let new = self.map.add(&pcs);
+        if new > 0 {
+            self.pool.add(input.clone());
+        }
+        Ok(new)
+    }
+
+    pub fn edges(&self) -> usize {
+        self.map.edges()
+    }
+
+    pub fn map(&self) -> &CoverageMap {
+        &self.map
+    }
+
+    /// Carry on from the edges of `map`, as a resumed campaign does
+    pub fn restore(&mut self, map: CoverageMap) {
+        self.map = map;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineage::Lineage;
+    use crate::rpc::program;
+
+    #[test]
+    fn test_config_pcs_and_map() {
+        let config: KcovConfig = "host=root@nfs1,port=2222,agent=/opt/kcov-nfsd -v"
+            .parse()
+            .unwrap();
+        assert_eq!(config.ssh.host, "root@nfs1");
+        assert_eq!(config.ssh.port, Some(2222));
+        assert_eq!(config.agent, "/opt/kcov-nfsd -v");
+        assert!("port=22".parse::<KcovConfig>().is_err());
+        assert!("host=h,pcs=1".parse::<KcovConfig>().is_err());
+
+        assert_eq!(
+            parse_pcs("0xffffffff81000000 ffffffff81000010\n").unwrap(),
+            [0xffff_ffff_8100_0000, 0xffff_ffff_8100_0010]
+        );
+        assert!(parse_pcs("").unwrap().is_empty());
+        assert!(matches!(parse_pcs("81 zz"), Err(KcovError::Parse(p)) if p == "zz"));
+
+        let pc = |n: u64| 0xffff_ffff_8100_0000 + 0x10 * n;
+        let mut map = CoverageMap::default();
+        assert_eq!(map.add(&[pc(1), pc(2), pc(3)]), 3);
+        assert_eq!(map.add(&[pc(1), pc(2), pc(3)]), 0);
+        // Same PCs, new order: new edges
+        assert_eq!(map.add(&[pc(3), pc(2)]), 2);
+        assert_eq!(map.edges(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_feedback_pools_new_coverage() {
+        // Reports the same two PCs every time
+        let mut agent = Command::new("sh");
+        agent.args(["-c", "while read c; do echo 10 20; done"]);
+        let session = KcovSession::spawn(agent).unwrap();
+        let pool = Pool::default();
+        let mut feedback = Feedback::new(session, pool.clone());
+        let input = Input {
+            name: "null".to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure: 0,
+            args: Vec::new(),
+            lineage: Lineage::new("null"),
+            auth: None,
+        };
+        assert_eq!(feedback.observe(&input).await.unwrap(), 2);
+        assert_eq!(feedback.observe(&input).await.unwrap(), 0);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(
+            pool.pick(&mut rand::thread_rng()).map(|i| i.name),
+            Some("null".to_string())
+        );
+
+        let mut session = KcovSession::spawn(Command::new("true")).unwrap();
+        assert!(session.collect().await.is_err());
+    }
+}
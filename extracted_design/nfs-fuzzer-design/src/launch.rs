@@ -0,0 +1,176 @@
+//! Releasing pre-built calls at precise times
+//!
+//! Timer wheels wake a task a millisecond or more late, which is wider
+//! than most of the windows race strategies aim at. A launch sleeps until
+//! shortly before its deadline, then busy-waits the rest of the way and
+//! writes each call at its own offset from the deadline, back to back
+//! from one task, so calls on different connections leave within
+//! microseconds of each other or of a chosen stagger. The spin holds a
+//! runtime worker for its duration; keep it short and run launches on
+//! the multi-threaded runtime.
+
+use crate::connection::{ConnectionError, NfsConnection};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// How long before a deadline sleeping gives way to spinning
+pub const DEFAULT_SPIN: Duration = Duration::from_millis(2);
+
+/// Busy-wait until `at`; returns how late the wait ended
+pub fn spin_until(at: Instant) -> Duration {
+    loop {
+        let now = Instant::now();
+        if now >= at {
+            return now - at;
+        }
+        std::hint::spin_loop();
+    }
+}
+
+/// Sleep until `spin` before `at`, then busy-wait up to it
+pub async fn wait_until(at: Instant, spin: Duration) -> Duration {
+    if let Some(wake) = at.checked_sub(spin) {
+        if wake > Instant::now() {
+            tokio::time::sleep_until(wake.into()).await;
+        }
+    }
+    spin_until(at)
+}
+
+/// One call of a launch
+#[derive(Debug)]
+pub struct Slot<'a, S> {
+    pub conn: &'a mut NfsConnection<S>,
+    /// Record-marked call
+    pub msg: &'a [u8],
+    /// When to write it, after the launch deadline
+    pub offset: Duration,
+}
+
+/// A call as it left
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sent {
+    pub xid: u32,
+    /// When the write started, from the launch deadline
+    pub at: Duration,
+    /// How far after its own offset the write started
+    pub late: Duration,
+}
+
+/// Calls on any number of connections released together
+#[derive(Debug)]
+pub struct Launch<'a, S> {
+    slots: Vec<Slot<'a, S>>,
+    spin: Duration,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> Launch<'a, S> {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            spin: DEFAULT_SPIN,
+        }
+    }
+
+    /// Time spent busy-waiting before the first call
+    pub fn with_spin(mut self, spin: Duration) -> Self {
+        self.spin = spin;
+        self
+    }
+
+    /// Write `msg` on `conn` at `offset` after the deadline
+    pub fn slot(mut self, conn: &'a mut NfsConnection<S>, msg: &'a [u8], offset: Duration) -> Self {
+        self.slots.push(Slot { conn, msg, offset });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Write every call at `at` plus its offset, earliest offset first;
+    /// results are in slot order. A write that blocks delays the ones
+    /// after it, which shows in their `late`.
+    pub async fn fire(self, at: Instant) -> Vec<Result<Sent, ConnectionError>> {
+        let mut slots: Vec<(usize, Slot<'a, S>)> = self.slots.into_iter().enumerate().collect();
+        slots.sort_by_key(|(_, s)| s.offset);
+        let mut results = Vec::with_capacity(slots.len());
+        for (n, (i, slot)) in slots.into_iter().enumerate() {
+            let target = at + slot.offset;
+            let late = if n == 0 {
+                wait_until(target, self.spin).await
+            } else {
+                spin_until(target)
+            };
+            let started = Instant::now();
+            let sent = slot.conn.send(slot.msg).await.map(|xid| Sent {
+                xid,
+                at: started - at,
+                late,
+            });
+            results.push((i, sent));
+        }
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, r)| r).collect()
+    }
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin> Default for Launch<'a, S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Timeouts;
+    use crate::rpc::{program, RpcCall};
+
+    fn call(xid: u32) -> Vec<u8> {
+        RpcCall::new(xid, program::NFS, 3, 0, true)
+            .with_auth_none()
+            .build()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_spin_until() {
+        let at = Instant::now() + Duration::from_micros(200);
+        spin_until(at);
+        assert!(Instant::now() >= at);
+        // A deadline already past returns at once
+        let now = Instant::now();
+        spin_until(at);
+        assert!(now.elapsed() < Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn test_fire_in_offset_order() {
+        let (ca, _sa) = tokio::io::duplex(4096);
+        let (cb, _sb) = tokio::io::duplex(4096);
+        let mut a = NfsConnection::new(ca, Timeouts::default());
+        let mut b = NfsConnection::new(cb, Timeouts::default());
+        let (m1, m2) = (call(1), call(2));
+        let launch = Launch::new()
+            .with_spin(Duration::from_millis(1))
+            .slot(&mut a, &m1, Duration::from_micros(300))
+            .slot(&mut b, &m2, Duration::ZERO);
+        assert_eq!(launch.len(), 2);
+        let at = Instant::now() + Duration::from_millis(5);
+        let sent: Vec<Sent> = launch
+            .fire(at)
+            .await
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert!(Instant::now() >= at + Duration::from_micros(300));
+        assert_eq!((sent[0].xid, sent[1].xid), (1, 2));
+        assert!(sent[0].at >= Duration::from_micros(300));
+        assert!(sent[1].at < sent[0].at);
+    }
+}
@@ -3,9 +3,69 @@
 //! A security testing tool for NFS v3/v4 implementations.
 
 pub mod xdr;
+pub mod fields;
 pub mod rpc;
-// pub mod nfsv3;  // TODO: implement
-// pub mod nfsv4;  // TODO: implement
-// pub mod mount;  // TODO: implement
-// pub mod mutations;  // TODO: implement
-// pub mod connection;  // TODO: implement
+pub mod auth;
+pub mod nfsv3;
+pub mod nfsv4;
+pub mod mount;
+pub mod mutations;
+pub mod connection;
+pub mod scenario;
+pub mod transcript;
+pub mod preset;
+pub mod generic;
+pub mod oracle;
+pub mod fixup;
+pub mod hang;
+pub mod stats;
+pub mod seeds;
+pub mod webnfs;
+pub mod exports;
+pub mod limits;
+pub mod telemetry;
+pub mod lineage;
+pub mod generate;
+pub mod analyze;
+pub mod crossfh;
+pub mod mixed;
+pub mod cachetier;
+pub mod nsm;
+pub mod control;
+pub mod vendor;
+pub mod corpus;
+pub mod pattern;
+pub mod netfault;
+pub mod proxy;
+pub mod race;
+pub mod launch;
+pub mod controller;
+pub mod inventory;
+pub mod fuzz;
+pub mod repro;
+pub mod minimize;
+pub mod replay;
+pub mod pcap;
+pub mod toctou;
+pub mod boundary;
+pub mod gss;
+#[cfg(feature = "krb5")]
+pub mod krb5;
+pub mod canary;
+pub mod isolate;
+pub mod nlm;
+pub mod serve;
+pub mod templates;
+pub mod client;
+pub mod kcov;
+pub mod signature;
+pub mod monitor;
+pub mod fairness;
+pub mod campaign;
+pub mod container;
+pub mod bundle;
+pub mod checkpoint;
+pub mod view;
+pub mod results;
+pub mod populate;
+pub mod db;
@@ -0,0 +1,297 @@
+//! Resource ceilings for the fuzzer itself
+//!
+//! A week-long unattended campaign must not be ended by the fuzzer
+//! running out of memory or file descriptors. Corpus entries, in-memory
+//! transcripts and replies parked on a connection for an XID not yet
+//! asked for are charged against one memory budget, open connections
+//! and in-flight requests against semaphores. Hitting a ceiling degrades
+//! instead of failing: connection attempts wait, extra concurrent
+//! requests are declined so the caller sends them inline, and bounded
+//! stores evict their oldest entries to make room.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Ceilings for one campaign
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes held by corpus entries, transcripts and parked replies
+    pub max_memory: usize,
+    pub max_connections: usize,
+    /// Requests sent but not yet answered, across all connections
+    pub max_pending: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_memory: 512 << 20,
+            max_connections: 16,
+            max_pending: 256,
+        }
+    }
+}
+
+/// How close the memory budget is to its ceiling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Pressure {
+    Normal,
+    /// Above `HIGH_WATER` percent: stop keeping optional data in memory
+    High,
+    /// Nothing more fits without evicting
+    Full,
+}
+
+/// Percentage of the memory ceiling at which pressure becomes high
+pub const HIGH_WATER: usize = 80;
+
+/// Shared byte budget
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    used: Arc<AtomicUsize>,
+    max: usize,
+}
+
+/// Bytes reserved from a [`MemoryBudget`], returned on drop
+#[derive(Debug)]
+pub struct MemoryGrant {
+    used: Arc<AtomicUsize>,
+    bytes: usize,
+}
+
+impl Drop for MemoryGrant {
+    fn drop(&mut self) {
+        self.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+impl MemoryGrant {
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl MemoryBudget {
+    pub fn new(max: usize) -> Self {
+        Self {
+            used: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Reserve `bytes`, or `None` if that would pass the ceiling
+    pub fn try_reserve(&self, bytes: usize) -> Option<MemoryGrant> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(bytes).filter(|&n| n <= self.max)
+            })
+            .ok()?;
+        Some(MemoryGrant {
+            used: self.used.clone(),
+            bytes,
+        })
+    }
+
+    pub fn pressure(&self) -> Pressure {
+        let used = self.used();
+        if used >= self.max {
+            Pressure::Full
+        } else if used.saturating_mul(100) >= self.max.saturating_mul(HIGH_WATER) {
+            Pressure::High
+        } else {
+            Pressure::Normal
+        }
+    }
+}
+
+/// FIFO of items charged to a memory budget; the oldest are evicted when
+/// a new one does not fit
+#[derive(Debug)]
+pub struct BoundedStore<T> {
+    budget: MemoryBudget,
+    items: VecDeque<(T, MemoryGrant)>,
+    /// Items evicted or refused so far
+    pub dropped: u64,
+}
+
+impl<T> BoundedStore<T> {
+    pub fn new(budget: MemoryBudget) -> Self {
+        Self {
+            budget,
+            items: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Store `item` of `bytes`, evicting old items as needed; an item
+    /// that does not fit the budget even with this store empty (other
+    /// holders share it) is handed back, without evicting anything if it
+    /// is larger than the whole budget
+    pub fn push(&mut self, item: T, bytes: usize) -> Result<(), T> {
+        if bytes > self.budget.max {
+            self.dropped += 1;
+            return Err(item);
+        }
+        loop {
+            if let Some(grant) = self.budget.try_reserve(bytes) {
+                self.items.push_back((item, grant));
+                return Ok(());
+            }
+            if self.items.pop_front().is_none() {
+                self.dropped += 1;
+                return Err(item);
+            }
+            self.dropped += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter().map(|(item, _)| item)
+    }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        self.items.get(i).map(|(item, _)| item)
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.items.back().map(|(item, _)| item)
+    }
+
+    /// Remove the oldest item, returning its bytes to the budget
+    pub fn pop_front(&mut self) -> Option<T> {
+        self.items.pop_front().map(|(item, _)| item)
+    }
+
+    /// Remove the oldest item `f` picks out, returning its bytes to the
+    /// budget
+    pub fn remove_first(&mut self, mut f: impl FnMut(&T) -> bool) -> Option<T> {
+        let i = self.items.iter().position(|(item, _)| f(item))?;
+        self.items.remove(i).map(|(item, _)| item)
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Remove every item, oldest first
+    pub fn take(&mut self) -> Vec<T> {
+        self.items.drain(..).map(|(item, _)| item).collect()
+    }
+}
+
+/// All ceilings of a campaign, shared by its tasks
+#[derive(Debug, Clone)]
+pub struct Governor {
+    pub limits: Limits,
+    pub memory: MemoryBudget,
+    connections: Arc<Semaphore>,
+    pending: Arc<Semaphore>,
+}
+
+impl Governor {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            memory: MemoryBudget::new(limits.max_memory),
+            connections: Arc::new(Semaphore::new(limits.max_connections.max(1))),
+            pending: Arc::new(Semaphore::new(limits.max_pending.max(1))),
+        }
+    }
+
+    /// Wait for a connection slot; hold the permit for the connection's
+    /// lifetime
+    pub async fn connection(&self) -> OwnedSemaphorePermit {
+        self.connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("governor semaphores are never closed")
+    }
+
+    /// Wait for an in-flight request slot
+    pub async fn pending(&self) -> OwnedSemaphorePermit {
+        self.pending
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("governor semaphores are never closed")
+    }
+
+    /// An in-flight slot if one is free right now; on `None` the caller
+    /// should send the request inline rather than spawn it
+    pub fn try_pending(&self) -> Option<OwnedSemaphorePermit> {
+        self.pending.clone().try_acquire_owned().ok()
+    }
+
+    pub fn free_connections(&self) -> usize {
+        self.connections.available_permits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_and_store_degrade() {
+        let budget = MemoryBudget::new(100);
+        let grant = budget.try_reserve(80).unwrap();
+        assert_eq!(budget.pressure(), Pressure::High);
+        assert!(budget.try_reserve(21).is_none());
+        drop(grant);
+        assert_eq!(budget.used(), 0);
+        assert_eq!(budget.pressure(), Pressure::Normal);
+
+        let mut store = BoundedStore::new(budget.clone());
+        for i in 0..4 {
+            store.push(i, 30).unwrap();
+        }
+        // The fourth push evicted the first
+        assert_eq!(store.iter().copied().collect::<Vec<_>>(), [1, 2, 3]);
+        assert_eq!(store.dropped, 1);
+        assert_eq!(store.push(9, 101), Err(9));
+        assert_eq!(store.len(), 3);
+        assert_eq!(budget.used(), 90);
+        assert_eq!(store.pop_front(), Some(1));
+        assert_eq!(store.last(), Some(&3));
+        assert_eq!(budget.used(), 60);
+        assert_eq!(store.take(), [2, 3]);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_governor_permits() {
+        let gov = Governor::new(Limits {
+            max_memory: 1024,
+            max_connections: 1,
+            max_pending: 2,
+        });
+        let a = gov.try_pending().unwrap();
+        let _b = gov.pending().await;
+        assert!(gov.try_pending().is_none());
+        drop(a);
+        assert!(gov.try_pending().is_some());
+        let conn = gov.connection().await;
+        assert_eq!(gov.free_connections(), 0);
+        drop(conn);
+        assert_eq!(gov.free_connections(), 1);
+    }
+}
@@ -0,0 +1,142 @@
+//! Mutation lineage of test cases
+//!
+//! A finding is only half useful without the way it was made. Each case
+//! carries the seed it started from and the mutations applied to it, in
+//! order and with their parameters; the chain goes into finding metadata
+//! and the case's tracing span, so a researcher can see which strategy
+//! found what and re-derive variants from any point of the chain.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// One applied mutation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    pub strategy: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, Value>,
+}
+
+impl Step {
+    pub fn new(strategy: impl Into<String>) -> Self {
+        Self {
+            strategy: strategy.into(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.params.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.strategy)?;
+        for (i, (k, v)) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}={}", k, v)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Seed id and the mutations applied to it, first to last
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lineage {
+    pub seed: String,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+impl Lineage {
+    pub fn new(seed: impl Into<String>) -> Self {
+        Self {
+            seed: seed.into(),
+            steps: Vec::new(),
+        }
+    }
+
+    /// A child lineage with `step` appended; the parent is left as is so
+    /// sibling variants can branch from it
+    pub fn then(&self, step: Step) -> Self {
+        let mut child = self.clone();
+        child.steps.push(step);
+        child
+    }
+
+    /// The ancestor after the first `n` steps
+    pub fn prefix(&self, n: usize) -> Self {
+        Self {
+            seed: self.seed.clone(),
+            steps: self.steps.iter().take(n).cloned().collect(),
+        }
+    }
+
+    /// Strategies that contributed, in order of first use
+    pub fn strategies(&self) -> Vec<&str> {
+        let mut out: Vec<&str> = Vec::new();
+        for step in &self.steps {
+            if !out.contains(&step.strategy.as_str()) {
+                out.push(&step.strategy);
+            }
+        }
+        out
+    }
+
+    /// The mutation chain without the seed, `a(k=v)>b()`, empty for an
+    /// unmutated seed
+    pub fn chain(&self) -> String {
+        let steps: Vec<String> = self.steps.iter().map(Step::to_string).collect();
+        steps.join(">")
+    }
+}
+
+impl fmt::Display for Lineage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.seed)?;
+        for step in &self.steps {
+            write!(f, ">{}", step)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Lineage {
+        Lineage::new("v4:OPEN")
+            .then(Step::new("bitflip").with("offset", 12).with("bit", 3))
+            .then(Step::new("truncate").with("len", 40))
+            .then(Step::new("bitflip").with("offset", 2).with("bit", 0))
+    }
+
+    #[test]
+    fn test_display_and_strategies() {
+        let l = sample();
+        assert_eq!(
+            l.to_string(),
+            "v4:OPEN>bitflip(bit=3,offset=12)>truncate(len=40)>bitflip(bit=0,offset=2)"
+        );
+        assert_eq!(l.strategies(), ["bitflip", "truncate"]);
+        assert_eq!(l.prefix(1).chain(), "bitflip(bit=3,offset=12)");
+        assert_eq!(Lineage::new("s").chain(), "");
+    }
+
+    #[test]
+    fn test_roundtrips_through_json() {
+        let l = sample();
+        let text = serde_json::to_string(&l).unwrap();
+        assert_eq!(serde_json::from_str::<Lineage>(&text).unwrap(), l);
+        let bare: Lineage =
+            serde_json::from_str(r#"{"seed":"x","steps":[{"strategy":"s"}]}"#).unwrap();
+        assert_eq!(bare, Lineage::new("x").then(Step::new("s")));
+    }
+}
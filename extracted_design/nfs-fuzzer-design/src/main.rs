@@ -1,37 +1,687 @@
 //! NFS Fuzzer - Main entry point
 
-use clap::Parser;
+use anyhow::Context;
+use clap::{Parser, Subcommand, ValueEnum};
+use nfs_fuzzer::analyze;
+use nfs_fuzzer::auth::{Identity, IdentityPool, Rotation};
+use nfs_fuzzer::boundary::{self, Suite};
+use nfs_fuzzer::bundle::Bundle;
+use nfs_fuzzer::campaign;
+use nfs_fuzzer::cachetier;
+use nfs_fuzzer::canary;
+use nfs_fuzzer::checkpoint::{self, Checkpoint};
+use nfs_fuzzer::client::{self, ClientDriver, ClientSpec};
+use nfs_fuzzer::connection::{NfsConnection, Proto, Timeouts, Transport};
+use nfs_fuzzer::container::{self, Container, ContainerSpec};
+use nfs_fuzzer::control;
+use nfs_fuzzer::controller::ControllerConfig;
+use nfs_fuzzer::corpus;
+use nfs_fuzzer::db::{self, ResultsDb};
+use nfs_fuzzer::vendor;
+use nfs_fuzzer::fairness::{self, FairnessReport, Scheduler, SimClient};
+use nfs_fuzzer::fuzz::{Finding, FuzzConfig, Fuzzer};
+use nfs_fuzzer::generate;
+use nfs_fuzzer::generic::{self, RpcService};
+use nfs_fuzzer::gss::{DynContext, Service};
+#[cfg(feature = "krb5")]
+use nfs_fuzzer::{gss, krb5::Krb5};
+use nfs_fuzzer::hang::LatencyBudget;
+use nfs_fuzzer::inventory;
+use nfs_fuzzer::kcov::{Feedback, KcovConfig, KcovSession, Pool};
+use nfs_fuzzer::limits::{Governor, Limits};
+use nfs_fuzzer::mixed::{self, SharedFile};
+use nfs_fuzzer::monitor::{Monitor, MonitorConfig};
+use nfs_fuzzer::mount;
+use nfs_fuzzer::fixup::Fixup;
+use nfs_fuzzer::mutations::{Engine, Weights};
+use nfs_fuzzer::netfault::FaultConfig;
+use nfs_fuzzer::nfsv3;
+use nfs_fuzzer::nlm;
+use nfs_fuzzer::nsm;
+use nfs_fuzzer::nfsv4::{self, backchannel, FuzzCase};
+use nfs_fuzzer::nfsv4::lockowner::LockOwner;
+use nfs_fuzzer::nfsv4::reclaim::{self, ClientOwner};
+use nfs_fuzzer::nfsv4::referral::{self, Pathname};
+use nfs_fuzzer::nfsv4::secinfo::{self, Secinfo};
+use nfs_fuzzer::nfsv4::session::{self, SlotTable};
+use nfs_fuzzer::nfsv4::state::SessionState;
+use nfs_fuzzer::nfsv4::ssv::{self, SsvParams};
+use nfs_fuzzer::pcap;
+use nfs_fuzzer::populate::{self, Shape};
+use nfs_fuzzer::preset::{Campaign, Oracle, Preset, Strategy};
+use nfs_fuzzer::proxy::{self, Corruption};
+use nfs_fuzzer::race;
+use nfs_fuzzer::replay::{self, Endpoint};
+use nfs_fuzzer::results::ResultLog;
+use nfs_fuzzer::rpc;
+use nfs_fuzzer::scenario::{self, dsl::ScenarioFile};
+use nfs_fuzzer::seeds;
+use nfs_fuzzer::serve;
+use nfs_fuzzer::templates::Template;
+use nfs_fuzzer::stats::{self, CampaignStats};
+use nfs_fuzzer::telemetry::{CampaignId, OtlpFileLayer};
+use nfs_fuzzer::toctou;
+use nfs_fuzzer::transcript::{self, TranscriptEntry};
+use nfs_fuzzer::view;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use std::net::SocketAddr;
-use tracing::{info, Level};
-use tracing_subscriber::FmtSubscriber;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::{info, warn, Level};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{fmt, Layer};
 
-mod xdr;
-mod rpc;
+/// What to fuzz
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// NFS and its side protocols
+    Nfs,
+    /// Any Sun RPC program, with generated XDR arguments
+    RpcGeneric,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert a recorded transcript into a scenario file
+    Convert {
+        /// JSON-lines transcript (.jsonl), or a raw record-marked client stream
+        input: PathBuf,
+
+        /// Raw record-marked server stream, paired with the calls by XID
+        #[arg(long)]
+        replies: Option<PathBuf>,
+
+        /// Scenario file to write (.ron for the hand-editable format, else JSON)
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Scenarios: namespace operations with their expected outcomes
+    Scenario {
+        #[command(subcommand)]
+        command: ScenarioCommand,
+    },
+    /// Step through a recorded transcript, call beside reply
+    View {
+        /// JSON-lines transcript (.jsonl), or a raw record-marked client stream
+        input: PathBuf,
+
+        /// Raw record-marked server stream, paired with the calls by XID
+        #[arg(long)]
+        replies: Option<PathBuf>,
+    },
+    /// Run the reply-decoding oracles over recorded transcripts
+    Analyze {
+        /// Directory searched recursively for .jsonl transcripts
+        dir: PathBuf,
+
+        /// Vendor decoder description (JSON) for private attributes and ops
+        #[arg(long = "vendor")]
+        vendors: Vec<PathBuf>,
+    },
+    /// Pack the findings of one behavior into a .tar.gz for a bug report
+    Bundle {
+        /// Signature id of the behavior (`v1:<hash>`), or the start of its hash
+        bucket: String,
+
+        /// Campaign output directory the findings were saved under
+        #[arg(long, default_value = "./fuzz-results")]
+        dir: PathBuf,
+
+        /// Directory to write the bundle to
+        #[arg(short, long, default_value = ".")]
+        output: PathBuf,
+    },
+    /// Built-in argument seeds
+    Seeds {
+        #[command(subcommand)]
+        command: SeedsCommand,
+    },
+    /// Campaign statistics
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommand,
+    },
+    /// Search and maintain a corpus directory
+    Corpus {
+        #[command(subcommand)]
+        command: CorpusCommand,
+    },
+    /// Relay a real client's TCP traffic to the server, optionally
+    /// damaging it on the way
+    Proxy {
+        /// Address to accept clients on
+        listen: SocketAddr,
+
+        /// Server to relay to
+        upstream: SocketAddr,
+
+        /// Corrupt bytes inside records, e.g. `rate=0.01,bytes=4,dir=both`;
+        /// add `marks` to damage record marks too
+        #[arg(long, value_name = "SPEC")]
+        corrupt: Option<Corruption>,
+
+        /// RNG seed for the damage (random if not given)
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Act as the server for a client under test, passing its calls to a
+    /// real server and mutating the replies
+    Serve {
+        /// Address to accept clients on
+        listen: SocketAddr,
+
+        /// Server that answers the calls
+        upstream: SocketAddr,
+
+        /// Probability a reply is mutated
+        #[arg(long, default_value_t = 0.1)]
+        rate: f64,
+
+        /// Mutate the RPC reply header after the XID, not only the results
+        #[arg(long)]
+        header: bool,
+
+        /// Mutator weights, e.g. `bitflip=4,havoc=1`
+        #[arg(long, value_name = "SPEC")]
+        mutators: Option<Weights>,
+
+        /// RNG seed for the mutations (random if not given)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Directory for one JSON-lines log of damaged replies per connection
+        #[arg(long, value_name = "DIR")]
+        log: Option<PathBuf>,
+
+        /// Answer the calls a known-bug template targets with its forged
+        /// reply (repeatable; `--rate 0` for templates only)
+        #[arg(long = "template", value_enum, value_name = "NAME")]
+        templates: Vec<Template>,
+
+        /// Drive a client through mount/ls/read/write rounds against this
+        /// server and watch its kernel log, e.g. `ssh,host=root@client,server=10.0.0.2`
+        #[arg(long, value_name = "SPEC")]
+        client: Option<ClientSpec>,
+
+        /// Client rounds to run before stopping, if none crashed it
+        #[arg(long, default_value_t = 100)]
+        rounds: u64,
+    },
+    /// List NFS servers on the local network by broadcasting portmap
+    /// calls
+    Discover {
+        /// Broadcast address and portmap port to send to
+        #[arg(long, default_value = "255.255.255.255:111")]
+        broadcast: SocketAddr,
+
+        /// Milliseconds to wait for replies
+        #[arg(long, default_value_t = 2000)]
+        wait: u64,
+
+        /// Write a target list: one `address:port` per NFS server found
+        #[arg(long, value_name = "FILE")]
+        targets: Option<PathBuf>,
+    },
+    /// Create a reproducible tree of directories, files and symlinks on
+    /// the export for stateful strategies to work on
+    Populate {
+        /// NFS server
+        target: SocketAddr,
+
+        /// Export to MNT for the root handle
+        #[arg(long)]
+        export: String,
+
+        #[arg(long, value_enum, default_value_t = Proto::Tcp)]
+        proto: Proto,
+
+        /// Directory of the export to create the tree in
+        #[arg(long, default_value = populate::ROOT)]
+        root: String,
+
+        /// Levels of directories
+        #[arg(long, default_value_t = Shape::default().depth)]
+        depth: u32,
+
+        /// Directories in each directory
+        #[arg(long, default_value_t = Shape::default().fanout)]
+        fanout: u32,
+
+        /// Files in each directory
+        #[arg(long, default_value_t = Shape::default().files)]
+        files: u32,
+
+        /// Largest file, in bytes
+        #[arg(long, default_value_t = Shape::default().max_size)]
+        max_size: u64,
+
+        /// Probability a name is a weird one (long, non-UTF-8, control
+        /// characters, ...)
+        #[arg(long, default_value_t = Shape::default().weird)]
+        weird: f64,
+
+        /// Seed the tree and the file contents follow from
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Re-send a saved finding: a crash directory, or one `.bin` call
+    Replay {
+        path: PathBuf,
+
+        /// Rounds to send it
+        #[arg(long, default_value_t = 1)]
+        count: u32,
+
+        /// Milliseconds to wait between rounds
+        #[arg(long, default_value_t = 0)]
+        delay: u64,
+
+        /// Send to this address instead of the one saved with the finding
+        #[arg(long)]
+        to: Option<SocketAddr>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CorpusCommand {
+    /// List the entries matching every filter given
+    Query {
+        /// Corpus directory
+        dir: PathBuf,
+
+        /// NFS version
+        #[arg(long)]
+        nfs_version: Option<u32>,
+
+        /// Procedure tag (v3:READ) or name (READ)
+        #[arg(long)]
+        procedure: Option<String>,
+
+        /// Seed origin or mutation strategy that made the entry
+        #[arg(long)]
+        strategy: Option<String>,
+
+        /// Behavior the entry was seen to trigger
+        #[arg(long)]
+        behavior: Option<String>,
+
+        /// Copy the matching entries into this corpus directory
+        #[arg(long, value_name = "DIR")]
+        extract: Option<PathBuf>,
+    },
+    /// Record behaviors an entry triggered
+    Tag {
+        /// Corpus directory
+        dir: PathBuf,
+
+        /// Entry name (file name without extension)
+        entry: String,
+
+        #[arg(required = true)]
+        behaviors: Vec<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ScenarioCommand {
+    /// Run a scenario file over v3, checking each step's outcome, then
+    /// its cleanup
+    Run {
+        /// Scenario file (.ron for the hand-editable format, else JSON)
+        file: PathBuf,
+
+        /// NFS server
+        target: SocketAddr,
+
+        /// Export to MNT for the root handle
+        #[arg(long)]
+        export: String,
+
+        /// Write the status of every step to this JSON file
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SeedsCommand {
+    /// List the seeds embedded in the binary
+    List {
+        /// Only seeds for this NFS version (3 or 4)
+        #[arg(long)]
+        nfs_version: Option<u32>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StatsCommand {
+    /// Test whether two campaign result directories differ significantly
+    Compare {
+        /// Baseline result directory
+        baseline: PathBuf,
+
+        /// Candidate result directory
+        candidate: PathBuf,
+
+        /// Significance level
+        #[arg(long, default_value_t = 0.05)]
+        alpha: f64,
+    },
+    /// Print the campaign report of a result directory
+    Report {
+        /// Result directory
+        dir: PathBuf,
+    },
+    /// Query a results database: cases by procedure and status, and
+    /// findings by kind
+    Db {
+        /// Results database written with `--db`
+        db: PathBuf,
+
+        /// Run this SQL instead, against the `iterations` and `findings`
+        /// tables or the `procedure_stats` view
+        #[arg(long)]
+        sql: Option<String>,
+    },
+}
 
 /// NFS Protocol Fuzzer
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    disable_version_flag = true,
+    subcommand_negates_reqs = true,
+    args_override_self = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Print version
+    #[arg(long, action = clap::ArgAction::Version)]
+    version: Option<bool>,
+
+    /// Read options from a campaign template, with `${NAME}` replaced
+    /// from `--var`, the environment or `RUN_ID` (the campaign id);
+    /// options given here override the template's
+    #[arg(long, value_name = "FILE")]
+    campaign: Option<PathBuf>,
+
+    /// Set a campaign template variable, `NAME=VALUE`
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    vars: Vec<String>,
+
     /// Target NFS server IP address
-    #[arg(short, long)]
-    target: String,
+    #[arg(short, long, required_unless_present_any = ["generate_only", "docker_target"])]
+    target: Option<String>,
+
+    /// Start this NFS server image in a container and fuzz that, restarting
+    /// it after crashes and collecting its output into findings; the
+    /// container is removed when the campaign ends
+    #[arg(long, value_name = "IMAGE", conflicts_with_all = ["target", "controller"])]
+    docker_target: Option<String>,
+
+    /// Option for `docker run` with --docker-target, such as `--privileged`
+    #[arg(long = "docker-arg", value_name = "ARG", allow_hyphen_values = true)]
+    docker_args: Vec<String>,
+
+    /// Seconds the --docker-target container gets to answer NULL
+    #[arg(long, default_value_t = container::READY_TIMEOUT.as_secs())]
+    docker_ready: u64,
 
     /// Target port (default: 2049 for NFS)
     #[arg(short, long, default_value_t = 2049)]
     port: u16,
 
+    /// Transport protocol; --races, --mixed, --cache-tier and the
+    /// strategies run outside the fuzz loop need TCP
+    #[arg(long, value_enum, default_value_t = Proto::Tcp)]
+    proto: Proto,
+
+    /// Ask portmap/rpcbind on port 111 for the NFS, MOUNT and NLM ports
+    /// (the NFS port replaces --port)
+    #[arg(long)]
+    autodiscover: bool,
+
     /// NFS version to fuzz (3 or 4)
     #[arg(short = 'V', long, default_value_t = 3)]
     nfs_version: u32,
 
+    /// Run the mixed interactions on a shared file in the export, v3 and
+    /// v4 steps concurrently on two connections, checking what v4 reads
+    /// back (needs --export; overrides --nfs-version). --iterations sets
+    /// the rounds of each interaction
+    #[arg(long)]
+    mixed: bool,
+
+    /// Sweep pairs of operations on one file in the export released
+    /// together on two connections, saving the pairs whose statuses
+    /// neither order of the two explains, then swap a directory for a
+    /// symlink under LOOKUPs and OPENs through it (needs --export)
+    #[arg(long)]
+    races: bool,
+
+    /// Check a proxy or re-export of --export at this address for stale
+    /// attributes and data: write through the target, read back through
+    /// the cache
+    #[arg(long, value_name = "ADDR")]
+    cache_tier: Option<SocketAddr>,
+
+    /// Export path on the cache, if not the same as --export
+    #[arg(long, value_name = "PATH")]
+    cache_export: Option<String>,
+
+    /// Seconds the cache may serve old attributes and data, its actimeo
+    #[arg(long, default_value_t = 3)]
+    cache_window: u64,
+
+    /// Learn the server's read, write, file size and name limits (v4)
+    /// and start with cases just below, at, above and at twice each,
+    /// kept per target under `boundaries/` in the output directory
+    #[arg(long)]
+    boundaries: bool,
+
+    /// Send v4.1 compounds on a session set up before the campaign, each
+    /// SEQUENCE resolved against its slot table as it goes out; the
+    /// connection it was set up on stays open as its backchannel, where
+    /// CB_NOTIFY_DEVICEIDs get malformed replies
+    #[arg(long)]
+    session: bool,
+
+    /// Set the session up under SP4_SSV state protection, first sending
+    /// SET_SSVs with bad digests and SSV-protected calls with bad
+    /// checksums and warning of any the server gets wrong
+    #[arg(long, requires = "session")]
+    ssv: bool,
+
+    /// Authenticate every case with a Kerberos context at this service
+    /// level, set up before the campaign; needs the krb5 feature
+    #[arg(long, value_name = "krb5|krb5i|krb5p", conflicts_with = "ssv")]
+    sec: Option<Service>,
+
+    /// Host-based service the Kerberos context is for, by default nfs@
+    /// and the target's address, which the KDC may not know it by
+    #[arg(long, value_name = "SERVICE@HOST", requires = "sec")]
+    gss_service: Option<String>,
+
+    /// Skip the middlebox canary sent over TCP before a campaign
+    #[arg(long)]
+    no_canary: bool,
+
+    /// Fuzzing mode
+    #[arg(long, value_enum, default_value_t = Mode::Nfs)]
+    mode: Mode,
+
+    /// RPC program number (rpc-generic mode); without it every program
+    /// portmap DUMP lists on the host is fuzzed in turn, 1000 cases each
+    /// unless --iterations says otherwise
+    #[arg(long)]
+    program: Option<u32>,
+
+    /// RPC program version (rpc-generic mode)
+    #[arg(long, default_value_t = 1)]
+    program_version: u32,
+
+    /// Highest procedure number to sweep (rpc-generic mode)
+    #[arg(long, default_value_t = 32)]
+    max_proc: u32,
+
+    /// AUTH_SYS identity `uid:gid[:gids][@machine]`; repeat to build a pool
+    #[arg(long = "identity")]
+    identities: Vec<Identity>,
+
+    /// Rotate AUTH_SYS identities per connection or per request
+    #[arg(long, value_enum)]
+    rotate: Option<Rotation>,
+
+    /// Per-request reply budget in milliseconds before a request counts as hung
+    #[arg(long, default_value_t = 5000)]
+    request_timeout: u64,
+
+    /// Memory ceiling for corpus and transcripts, in MiB
+    #[arg(long, default_value_t = 512)]
+    max_memory: usize,
+
+    /// Most connections open at once
+    #[arg(long, default_value_t = 16)]
+    max_connections: usize,
+
+    /// Most requests in flight at once
+    #[arg(long, default_value_t = 256)]
+    max_pending: usize,
+
+    /// Write this many generated test cases to the output directory and
+    /// exit without sending anything
+    #[arg(long, value_name = "N")]
+    generate_only: Option<usize>,
+
+    /// Add the NFS calls of this pcap or pcapng capture to the seeds;
+    /// repeat for more captures
+    #[arg(long = "seed-pcap", value_name = "FILE")]
+    seed_pcaps: Vec<PathBuf>,
+
+    /// RNG seed for generation and mutation (random if not given)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Mutators and their weights, e.g. `bitflip=4,havoc=2,interesting`
+    /// (default: every built-in, equally weighted)
+    #[arg(long, value_name = "SPEC")]
+    mutators: Option<Weights>,
+
+    /// Repair the length headers a mutation breaks: `off`, `all`, or the
+    /// constraint to leave broken (`opaque_length`, `record_mark`, ...)
+    #[arg(long, value_name = "MODE", default_value = "off")]
+    fixup: Fixup,
+
+    /// Simulate a client with its own case stream, sharing the campaign
+    /// with the others in proportion to RATE: `NAME:RATE[:MUTATORS]`,
+    /// repeatable
+    #[arg(long = "sim-client", value_name = "SPEC")]
+    sim_clients: Vec<SimClient>,
+
+    /// Export to MNT for a v3 root file handle, which replaces the
+    /// placeholder handle of the v3 seeds
+    #[arg(long, value_name = "PATH")]
+    export: Option<String>,
+
+    /// Inject faults into UDP sends (with `--proto udp`), e.g.
+    /// `drop=0.05,dup=0.01,reorder=0.02,delay=20ms,jitter=5ms`
+    #[arg(long, value_name = "SPEC")]
+    udp_faults: Option<FaultConfig>,
+
+    /// Listen for pause/resume/toggle/status commands on this address
+    /// (SIGUSR1 also toggles pausing)
+    #[arg(long, value_name = "ADDR")]
+    control: Option<SocketAddr>,
+
+    /// Stop after this many cases (default: run until interrupted)
+    #[arg(long, value_name = "N")]
+    iterations: Option<u64>,
+
+    /// Cases between NULL health probes
+    #[arg(long, default_value_t = 100)]
+    probe_every: u64,
+
+    /// Seconds a dead server gets to come back before the campaign stops
+    #[arg(long, default_value_t = 60)]
+    restart_wait: u64,
+
+    /// Times each finding is re-sent to measure its reproduction rate
+    /// (0 to skip)
+    #[arg(long, default_value_t = 5)]
+    verify_trials: u32,
+
+    /// Tests spent shrinking each crash to a minimal reproducer, written
+    /// to `minimized/` in the crash directory (0 to skip)
+    #[arg(long, default_value_t = 100)]
+    minimize_tests: u32,
+
+    /// Write every call and reply to this pcapng file, framed as TCP or
+    /// UDP over IP for Wireshark
+    #[arg(long)]
+    pcap: Option<PathBuf>,
+
+    /// Append every case sent, with its reply status and latency, to this
+    /// file as JSON lines
+    #[arg(long, value_name = "FILE")]
+    results_log: Option<PathBuf>,
+
+    /// Store every case and finding in this SQLite database, created if
+    /// need be; query it with `stats db`
+    #[arg(long, value_name = "FILE")]
+    db: Option<PathBuf>,
+
+    /// What restarts the target and collects its logs: `none`,
+    /// `ssh,host=root@nfs1[,service=UNIT,restart=CMD,...]`,
+    /// `libvirt,domain=nfs1[,uri=URI,...]`, `docker,container=C[,...]` or
+    /// `shell,restart=CMD[,reboot=CMD,logs=CMD,snapshot=CMD]`
+    #[arg(long, default_value = "none")]
+    controller: ControllerConfig,
+
+    /// Collect the target kernel's coverage through a KCOV agent over
+    /// ssh and mutate the cases that reach new edges:
+    /// `host=root@nfs1[,port=N][,key=PATH][,agent=CMD]`
+    #[arg(long, value_name = "SPEC")]
+    kcov: Option<KcovConfig>,
+
+    /// Follow the target's kernel log over ssh and report the oops, BUG
+    /// and WARN lines it logs while still answering:
+    /// `host=root@nfs1[,port=N][,key=PATH][,command=CMD]`
+    #[arg(long, value_name = "SPEC")]
+    monitor: Option<MonitorConfig>,
+
     /// Just test connectivity, don't fuzz
     #[arg(long)]
     test_connection: bool,
 
+    /// Campaign preset selecting strategies, oracles and procedures
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
     /// Output directory for results
     #[arg(short, long, default_value = "./fuzz-results")]
     output: String,
 
+    /// Also write every span as OTLP/JSON lines to this file
+    #[arg(long)]
+    otel_file: Option<PathBuf>,
+
+    /// Take the campaign up where the checkpoint in the output directory
+    /// left it: its seed, statistics, queue and findings, with the cases
+    /// it already sent skipped
+    #[arg(long, conflicts_with = "seed")]
+    resume: bool,
+
+    /// Cases between campaign checkpoints (0 to write one only at the end)
+    #[arg(long, default_value_t = checkpoint::DEFAULT_EVERY)]
+    checkpoint_every: u64,
+
     /// Verbosity level
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
@@ -39,7 +689,10 @@ struct Args {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
+    let campaign = CampaignId::random();
+    let builtins = [(campaign::RUN_ID, campaign.to_string())];
+    let argv = campaign::splice(std::env::args().collect(), &builtins)?;
+    let args = Args::parse_from(argv);
 
     // Set up logging
     let level = match args.verbose {
@@ -48,26 +701,1843 @@ async fn main() -> anyhow::Result<()> {
         _ => Level::TRACE,
     };
     
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(level)
-        .finish();
+    let otel = match &args.otel_file {
+        Some(path) => {
+            let file = std::fs::File::create(path)
+                .with_context(|| format!("creating {}", path.display()))?;
+            Some(OtlpFileLayer::new(campaign, file))
+        }
+        None => None,
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(fmt::layer().with_filter(LevelFilter::from_level(level)))
+        .with(otel);
     tracing::subscriber::set_global_default(subscriber)?;
 
-    let target: SocketAddr = format!("{}:{}", args.target, args.port).parse()?;
+    match &args.command {
+        Some(Command::Convert {
+            input,
+            replies,
+            output,
+        }) => return convert(input, replies.as_deref(), output),
+        Some(Command::Scenario {
+            command: ScenarioCommand::Run { file, target, export, report },
+        }) => {
+            let s = load_scenario(file)?;
+            let identity = default_identity(&args);
+            let timeouts = Timeouts::default();
+            let mounted = mount_root(Proto::Tcp, *target, None, export, timeouts, &identity).await?;
+            let mut conn = NfsConnection::connect(*target, timeouts)
+                .await
+                .with_context(|| format!("connecting to {}", target))?;
+            let result = scenario::run::run(&mut conn, &identity, &mounted.fh, &s)
+                .await
+                .with_context(|| format!("scenario {}", s.name))?;
+            log_unmet(&result);
+            if let Some(path) = report {
+                std::fs::write(path, serde_json::to_string_pretty(&result)?)
+                    .with_context(|| format!("writing {}", path.display()))?;
+            }
+            println!("{}: {} calls, {} steps not as expected", s.name, result.sent(), result.unmet().count());
+            return Ok(());
+        }
+        Some(Command::View { input, replies }) => {
+            view::run(load_transcript(input, replies.as_deref())?)?;
+            return Ok(());
+        }
+        Some(Command::Analyze { dir, vendors }) => {
+            let mut registry = vendor::Registry::new();
+            for path in vendors {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("reading {}", path.display()))?;
+                registry.register(vendor::SpecDecoder::from_json(&text)?);
+            }
+            let analyzer = analyze::analyze_dir_with(dir, registry)?;
+            for finding in &analyzer.findings {
+                println!("{}", finding);
+            }
+            for note in &analyzer.annotations {
+                println!("{}", note);
+            }
+            info!("{} findings", analyzer.findings.len());
+            return Ok(());
+        }
+        Some(Command::Stats {
+            command:
+                StatsCommand::Compare {
+                    baseline,
+                    candidate,
+                    alpha,
+                },
+        }) => {
+            let load = |dir: &Path| {
+                CampaignStats::load(dir).with_context(|| format!("loading {}", dir.display()))
+            };
+            println!("{}", stats::compare(&load(baseline)?, &load(candidate)?, *alpha));
+            return Ok(());
+        }
+        Some(Command::Stats {
+            command: StatsCommand::Report { dir },
+        }) => {
+            let stats = CampaignStats::load(dir)
+                .with_context(|| format!("loading {}", dir.display()))?;
+            print!("{}", stats::Report(&stats));
+            return Ok(());
+        }
+        Some(Command::Stats {
+            command: StatsCommand::Db { db, sql },
+        }) => {
+            let out = db::query(db, sql.as_deref().unwrap_or(db::SUMMARY))
+                .with_context(|| format!("querying {}", db.display()))?;
+            print!("{}", out);
+            return Ok(());
+        }
+        Some(Command::Corpus {
+            command:
+                CorpusCommand::Query {
+                    dir,
+                    nfs_version,
+                    procedure,
+                    strategy,
+                    behavior,
+                    extract,
+                },
+        }) => {
+            let query = corpus::Query {
+                version: *nfs_version,
+                procedure: procedure.clone(),
+                strategy: strategy.clone(),
+                behavior: behavior.clone(),
+            };
+            let corpus = corpus::Corpus::open(dir);
+            let entries = corpus.query(&query)?;
+            for entry in &entries {
+                println!("{}", entry);
+            }
+            if let Some(to) = extract {
+                corpus.extract(&entries, to)?;
+                info!("Extracted {} entries to {}", entries.len(), to.display());
+            }
+            return Ok(());
+        }
+        Some(Command::Corpus {
+            command:
+                CorpusCommand::Tag {
+                    dir,
+                    entry,
+                    behaviors,
+                },
+        }) => {
+            let corpus = corpus::Corpus::open(dir);
+            for behavior in behaviors {
+                corpus.add_behavior(entry, behavior)?;
+            }
+            return Ok(());
+        }
+        Some(Command::Discover {
+            broadcast,
+            wait,
+            targets,
+        }) => {
+            let servers = inventory::broadcast(*broadcast, Duration::from_millis(*wait))
+                .await
+                .with_context(|| format!("broadcasting to {}", broadcast))?;
+            for server in &servers {
+                println!("{}", server);
+            }
+            info!("{} portmappers answered", servers.len());
+            if let Some(path) = targets {
+                let list: String = servers
+                    .iter()
+                    .filter_map(|s| s.target())
+                    .map(|t| format!("{}\n", t))
+                    .collect();
+                std::fs::write(path, list)
+                    .with_context(|| format!("writing {}", path.display()))?;
+            }
+            return Ok(());
+        }
+        Some(Command::Populate {
+            target,
+            export,
+            proto,
+            root,
+            depth,
+            fanout,
+            files,
+            max_size,
+            weird,
+            seed,
+        }) => {
+            let shape = Shape {
+                depth: *depth,
+                fanout: *fanout,
+                files: *files,
+                max_size: *max_size,
+                weird: *weird,
+            };
+            let nodes = populate::plan(&shape, *seed);
+            let identity = default_identity(&args);
+            let timeouts = Timeouts::default();
+            let fh = populate::mount(*proto, *target, export, timeouts, &identity)
+                .await
+                .with_context(|| format!("mounting {}:{}", target.ip(), export))?;
+            let mut conn = Transport::connect(*proto, *target, timeouts)
+                .await
+                .with_context(|| format!("connecting to {}", target))?;
+            info!("Creating {} nodes in {}/{}", nodes.len(), export, root);
+            let summary = populate::populate(&mut conn, &identity, &fh, root, &nodes, *seed).await?;
+            println!("{}", summary);
+            return Ok(());
+        }
+        Some(Command::Replay {
+            path,
+            count,
+            delay,
+            to,
+        }) => {
+            let mut endpoint = Endpoint::load(path)
+                .with_context(|| format!("loading the connection saved with {}", path.display()))?;
+            if let Some(to) = to {
+                endpoint.target = *to;
+            }
+            let calls = replay::calls(path).with_context(|| format!("reading {}", path.display()))?;
+            info!("Replaying {} calls to {} ({:?})", calls.len(), endpoint.target, endpoint.proto);
+            let mut down = 0;
+            for round in 1..=*count {
+                if round > 1 {
+                    tokio::time::sleep(Duration::from_millis(*delay)).await;
+                }
+                let result = replay::replay(&endpoint, &calls).await;
+                println!("round {}:\n{}", round, result);
+                down += u32::from(!result.alive);
+            }
+            println!("server down after {} of {} rounds", down, count);
+            return Ok(());
+        }
+        Some(Command::Proxy {
+            listen,
+            upstream,
+            corrupt,
+            seed,
+        }) => {
+            let seed = seed.unwrap_or_else(rand::random);
+            let listener = tokio::net::TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("binding {}", listen))?;
+            info!("Relaying {} to {}", listener.local_addr()?, upstream);
+            if let Some(c) = corrupt {
+                info!("Corruption: {:?} (seed {})", c, seed);
+            }
+            proxy::serve(listener, *upstream, *corrupt, seed).await?;
+            return Ok(());
+        }
+        Some(Command::Serve {
+            listen,
+            upstream,
+            rate,
+            header,
+            mutators,
+            seed,
+            log,
+            templates,
+            client,
+            rounds,
+        }) => {
+            anyhow::ensure!((0.0..=1.0).contains(rate), "--rate must be between 0 and 1");
+            let seed = seed.unwrap_or_else(rand::random);
+            let listener = tokio::net::TcpListener::bind(listen)
+                .await
+                .with_context(|| format!("binding {}", listen))?;
+            info!(
+                "Serving {} from {}, mutating {} of replies (seed {})",
+                listener.local_addr()?,
+                upstream,
+                rate,
+                seed
+            );
+            let engine = Engine::from_weights(&mutators.clone().unwrap_or_default());
+            let config = serve::ServeConfig {
+                rate: *rate,
+                header: *header,
+                templates: templates.clone(),
+            };
+            let port = listener.local_addr()?.port();
+            let server = serve::serve(listener, *upstream, engine, config, seed, log.clone());
+            let Some(spec) = client else {
+                server.await?;
+                return Ok(());
+            };
+            let server = tokio::spawn(server);
+            let mut driver = ClientDriver::new(spec.clone(), port, *upstream);
+            let (rounds, log) = (*rounds, log.clone());
+            let drive = move || client::drive(&mut driver, rounds, log.as_deref());
+            let crashed = tokio::task::spawn_blocking(drive).await??;
+            server.abort();
+            if crashed.is_none() {
+                info!("Client survived {} rounds", rounds);
+            }
+            return Ok(());
+        }
+        Some(Command::Bundle {
+            bucket,
+            dir,
+            output,
+        }) => {
+            let bundle = Bundle::build(dir, bucket)?;
+            let path = bundle.save(output)?;
+            info!("Bundled {} ({} files) to {}", bundle.id, bundle.members.len(), path.display());
+            return Ok(());
+        }
+        Some(Command::Seeds {
+            command: SeedsCommand::List { nfs_version },
+        }) => {
+            for seed in seeds::seeds(*nfs_version) {
+                println!(
+                    "v{} {:>3} {:<22} {} bytes",
+                    seed.version,
+                    seed.number,
+                    seed.name,
+                    seed.args.len()
+                );
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let resumed = match args.resume {
+        true => Some(
+            Checkpoint::load(Path::new(&args.output))
+                .with_context(|| format!("loading the checkpoint in {}", args.output))?,
+        ),
+        false => None,
+    };
+    let seed = match &resumed {
+        Some(checkpoint) => checkpoint.seed,
+        None => args.seed.unwrap_or_else(rand::random),
+    };
+    if let Some(n) = args.generate_only {
+        let strategies = match args.preset {
+            Some(preset) => preset.campaign().strategies,
+            None => generate::STATELESS.to_vec(),
+        };
+        let identity = default_identity(&args);
+        let engine = Engine::from_weights(&args.mutators.clone().unwrap_or_default()).with_fixup(args.fixup);
+        let bases = seed_inputs(&strategies, seed, &args.seed_pcaps)?;
+        let inputs: Vec<_> = generate::stream_from(bases, seed, &engine).take(n).collect();
+        let dir = generate::write_cases(Path::new(&args.output), &inputs, &identity)
+            .with_context(|| format!("writing cases under {}", args.output))?;
+        info!("Wrote {} cases (seed {}) to {}", inputs.len(), seed, dir.display());
+        return Ok(());
+    }
+
+    let container = match &args.docker_target {
+        Some(image) => {
+            let name = format!("nfs-fuzzer-{}", &campaign.to_string()[..12]);
+            let spec = ContainerSpec::new(image, &name).with_options(args.docker_args.clone());
+            let container = Container::start(&spec).with_context(|| format!("starting {}", image))?;
+            info!("Started {} as container {}", image, name);
+            Some(container)
+        }
+        None => None,
+    };
+    let mut target: SocketAddr = match &container {
+        Some(container) => SocketAddr::new(container.address()?, args.port),
+        None => {
+            let target = args.target.as_deref().context("--target is required")?;
+            format!("{}:{}", target, args.port).parse()?
+        }
+    };
     
     info!("NFS Fuzzer starting");
-    info!("Target: {}", target);
-    info!("NFS Version: {}", args.nfs_version);
+    info!("Campaign: {}", campaign);
+    if let Some(path) = &args.campaign {
+        info!("Campaign template: {} ({} variables set)", path.display(), args.vars.len());
+    }
+    info!("Target: {} ({:?})", target, args.proto);
+    if args.mixed {
+        info!("NFS Version: 3 and 4 (mixed)");
+        info!("Interactions: {}", mixed::interactions().len());
+    } else {
+        info!("NFS Version: {}", args.nfs_version);
+    }
+    if args.races {
+        info!(
+            "Race pairs: {} at {} staggers",
+            race::sweep(&race::RaceOp::ALL).len(),
+            race::STAGGERS.len()
+        );
+        info!(
+            "Symlink swap probes: {} against {} link targets",
+            toctou::probes().len(),
+            toctou::TARGETS.len()
+        );
+    }
+    let budget = LatencyBudget {
+        request: Duration::from_millis(args.request_timeout),
+        ..LatencyBudget::default()
+    };
+    info!("Request budget: {:?}", budget.request);
+    if container.is_some() {
+        let timeout = Duration::from_secs(args.docker_ready);
+        let ready = container::wait_ready(args.proto, target, args.nfs_version, Timeouts::from(&budget), timeout)
+            .await
+            .with_context(|| format!("{} did not answer NULL within {:?}", target, timeout))?;
+        info!("Container answered after {:?}", ready);
+    }
+    let mut mountd = None;
+    if args.autodiscover {
+        (target, mountd) = discover(target, args.proto, args.nfs_version, Timeouts::from(&budget)).await?;
+        info!("Target: {}", target);
+    }
+    let governor = Governor::new(Limits {
+        max_memory: args.max_memory << 20,
+        max_connections: args.max_connections,
+        max_pending: args.max_pending,
+    });
+    info!("Limits: {:?}", governor.limits);
+    check_proto(&args)?;
+    if let Some(faults) = args.udp_faults {
+        anyhow::ensure!(args.proto == Proto::Udp, "--udp-faults needs --proto udp");
+        info!("UDP faults: {:?} (seed {})", faults, seed);
+    }
+    let gate = control::Gate::new();
+    #[cfg(unix)]
+    control::listen_sigusr1(gate.clone()).context("installing SIGUSR1 handler")?;
+    if let Some(addr) = args.control {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("binding control socket {}", addr))?;
+        info!("Control socket: {}", listener.local_addr()?);
+        tokio::spawn(control::serve(listener, gate.clone()));
+    }
+    if let Some(preset) = args.preset {
+        let campaign = preset.campaign();
+        info!("Preset: {:?}", preset);
+        info!("Strategies: {:?}", campaign.strategies);
+        info!("Oracles: {:?}", campaign.oracles);
+        info!("Procedures: {}", campaign.procedures.len());
+    }
+
+    let config = FuzzConfig {
+        proto: args.proto,
+        udp_faults: args.udp_faults.map(|faults| (faults, seed)),
+        budget,
+        identity: default_identity(&args),
+        identities: args.rotate.map(|rotation| {
+            let pool = IdentityPool::new(args.identities.clone(), rotation);
+            info!("Rotating {} identities {:?}", pool.identities().len(), rotation);
+            pool
+        }),
+        nfs_version: args.nfs_version,
+        probe_every: args.probe_every,
+        restart_wait: Duration::from_secs(args.restart_wait),
+        verify_trials: args.verify_trials,
+        minimize_tests: args.minimize_tests,
+        controller: match &container {
+            Some(container) => ControllerConfig::Docker(container.docker().clone()),
+            None => args.controller.clone(),
+        },
+        governor: governor.clone(),
+        oracles: args.preset.map_or_else(|| Oracle::ALL.to_vec(), |preset| preset.campaign().oracles),
+        ..FuzzConfig::new(target, &args.output)
+    };
 
     if args.test_connection {
         info!("Testing connection with NULL procedure...");
-        // TODO: Send NULL RPC and check response
         let msg = rpc::simple_rpc_call(rpc::program::NFS, args.nfs_version, 0);
-        info!("Would send {} bytes: {}", msg.len(), hex::encode(&msg));
+        let mut conn = Transport::connect(args.proto, target, Timeouts::from(&budget))
+            .await
+            .with_context(|| format!("connecting to {}", target))?;
+        if let Some(faults) = args.udp_faults {
+            conn = conn.with_faults(faults, seed);
+        }
+        let reply = conn.call(&msg).await.context("NULL call")?;
+        let reply = rpc::RpcReply::parse(&reply).context("NULL reply")?;
+        info!("NULL reply: {:?}", reply.status);
+        if args.nfs_version == 4 {
+            // v4.0 needs no session, so the root handle is one COMPOUND away
+            let identity = default_identity(&args);
+            let msg = nfsv4::CompoundBuilder::new(nfsv4::minor_version::V4_0)
+                .putrootfh()
+                .getfh()
+                .message(&identity);
+            let reply = conn.call(&msg).await.context("PUTROOTFH+GETFH")?;
+            let reply = rpc::RpcReply::parse(&reply).context("COMPOUND reply")?;
+            info!(
+                "PUTROOTFH+GETFH reply: {:?}, {} bytes of results",
+                reply.status,
+                reply.results.len()
+            );
+        } else if let Some(path) = &args.export {
+            // v3 handles start from MOUNT, not from the NFS program
+            let identity = default_identity(&args);
+            mount_root(args.proto, target, mountd, path, Timeouts::from(&budget), &identity).await?;
+        }
+    } else if args.mode == Mode::RpcGeneric {
+        let timeouts = Timeouts::from(&budget);
+        let portmap = SocketAddr::new(target.ip(), rpc::PORTMAP_PORT);
+        let mut pmap = Transport::connect(args.proto, portmap, timeouts).await;
+        let (services, cases) = match args.program {
+            Some(program) => {
+                let service = RpcService {
+                    max_proc: args.max_proc,
+                    ..RpcService::new(program, args.program_version)
+                };
+                // Not registered, or no portmap: the port given stands
+                let port = match &mut pmap {
+                    Ok(conn) => conn.getport(program, args.program_version).await.ok().flatten(),
+                    Err(_) => None,
+                };
+                (vec![(service, port.unwrap_or(target.port()))], u64::MAX)
+            }
+            None => {
+                let mappings = pmap
+                    .with_context(|| format!("connecting to portmap at {}", portmap))?
+                    .dump()
+                    .await
+                    .context("portmap DUMP")?;
+                let services = generic::services(&mappings, args.proto.ipproto(), args.max_proc);
+                anyhow::ensure!(!services.is_empty(), "portmap at {} lists no programs over {:?}", portmap, args.proto);
+                (services, GENERIC_CASES)
+            }
+        };
+        let cases = args.iterations.unwrap_or(cases) as usize;
+        for (n, (service, port)) in services.into_iter().enumerate() {
+            let addr = SocketAddr::new(target.ip(), port);
+            info!("Generic RPC program {} v{} at {}", service.program, service.version, addr);
+            let config = FuzzConfig {
+                target: addr,
+                output: Path::new(&args.output).join(format!("rpc{}_v{}", service.program, service.version)),
+                probe_program: service.program,
+                nfs_version: service.version,
+                ..config.clone()
+            };
+            let mut fuzzer = Fuzzer::new(config);
+            let inputs = generic::stream(service, seed.wrapping_add(n as u64), 16).take(cases);
+            fuzzer.run(inputs, &gate).await?;
+            info!("Sent {} cases, {} findings", fuzzer.sent(), fuzzer.findings.len());
+            log_findings(&fuzzer.findings);
+        }
+    } else if args.races {
+        let export = args.export.as_deref().context("--races needs --export")?;
+        run_races(&config, mountd, export, campaign, Path::new(&args.output)).await?;
+    } else if let Some(cache) = args.cache_tier {
+        let export = args.export.as_deref().context("--cache-tier needs --export")?;
+        let cache_export = args.cache_export.as_deref().unwrap_or(export);
+        let window = Duration::from_secs(args.cache_window);
+        run_cache_tier(&config, mountd, export, (cache, cache_export), window, Path::new(&args.output)).await?;
+    } else if args.mixed {
+        let export = args.export.as_deref().context("--mixed needs --export")?;
+        let rounds = args.iterations.unwrap_or(MIXED_ROUNDS);
+        run_mixed(&config, mountd, export, campaign, rounds, Path::new(&args.output)).await?;
     } else {
-        info!("Fuzzing not yet implemented - this is a skeleton!");
-        // TODO: Implement fuzzing loop
+        let selected = args.preset.map(Preset::campaign);
+        let strategies = match &selected {
+            Some(campaign) => campaign.strategies.clone(),
+            None => generate::STATELESS.to_vec(),
+        };
+        let unexported: Vec<Strategy> =
+            strategies.iter().copied().filter(|s| generate::NEED_EXPORT.contains(s)).collect();
+        if args.export.is_none() && !unexported.is_empty() {
+            anyhow::bail!("{:?} run on the export, which needs --export", unexported);
+        }
+        let engine = Engine::from_weights(&args.mutators.clone().unwrap_or_default()).with_fixup(args.fixup);
+        let nfs_version = args.nfs_version;
+        let mut bases = seed_inputs(&strategies, seed, &args.seed_pcaps)?;
+        if let (Some(path), true) = (&args.export, nfs_version == 3) {
+            let mounted =
+                mount_root(args.proto, target, mountd, path, Timeouts::from(&budget), &config.identity).await?;
+            for input in bases.iter_mut().filter(|i| i.version == 3) {
+                input.args = seeds::with_filehandle(&input.args, &mounted.fh);
+            }
+        }
+        let export = args.export.as_deref().map(referral::components);
+        if let Some(export) = &export {
+            for &strategy in &strategies {
+                bases.extend(generate::export_inputs(strategy, export, &config.identity));
+            }
+        }
+        if strategies.contains(&Strategy::CrossVersionFh) {
+            let (path, export) = args
+                .export
+                .as_deref()
+                .zip(export.as_ref())
+                .context("checked with NEED_EXPORT")?;
+            let v3 = mount_root(args.proto, target, mountd, path, Timeouts::from(&budget), &config.identity).await?.fh;
+            let v4 = export_handle(&config, export).await?;
+            bases.extend(generate::cross_version_inputs(&[v3], &[v4], &config.identity));
+        }
+        let secinfo = match strategies.contains(&Strategy::AuthDowngrade) {
+            true => {
+                let export = export.context("checked with NEED_EXPORT")?;
+                let advertised = advertised_flavors(&config, &export).await?;
+                info!("SECINFO of {}: {:?}", args.export.as_deref().unwrap_or_default(), advertised);
+                if !secinfo::requires_krb5(&advertised) {
+                    info!("The export takes more than krb5, so no downgrade can get round its policy");
+                }
+                Some((export, advertised))
+            }
+            false => None,
+        };
+        if args.boundaries {
+            let suite = boundary_suite(&config, Path::new(&args.output)).await?;
+            bases.splice(0..0, suite.inputs);
+        }
+        check_sendable(&bases, &selected, nfs_version)?;
+        let scenarios: Vec<Strategy> = strategies.iter().copied().filter(|s| scenario::STRATEGIES.contains(s)).collect();
+        // Without --export, the NEED_EXPORT strategies were refused above
+        if let Some(path) = args.export.as_deref() {
+            let output = Path::new(&args.output);
+            if !scenarios.is_empty() {
+                run_scenarios(&config, mountd, path, &scenarios, output).await?;
+            }
+            if strategies.contains(&Strategy::MixedVersion) {
+                run_mixed(&config, mountd, path, campaign, MIXED_ROUNDS, output).await?;
+            }
+            if strategies.contains(&Strategy::OpRaces) {
+                run_races(&config, mountd, path, campaign, output).await?;
+            }
+            if strategies.contains(&Strategy::LockReclaim) {
+                run_reclaims(&config, mountd, path, campaign, seed, output).await?;
+            }
+            if strategies.contains(&Strategy::NsmSpoof) {
+                run_nsm_spoof(&config, mountd, path, campaign, output).await?;
+            }
+        }
+        if let Some(selected) = &selected {
+            for (program, version) in selected.side_programs() {
+                let cases = args.iterations.unwrap_or(GENERIC_CASES) as usize;
+                run_side_program(&config, selected, (program, version), seed, cases, &gate).await?;
+            }
+        }
+        let pool = Pool::new(governor.memory.clone());
+        if resumed.is_some() {
+            let queue = checkpoint::saved_inputs(&Path::new(&args.output).join("queue"));
+            info!("Queue: {} cases", queue.len());
+            queue.into_iter().for_each(|input| pool.add(input));
+        }
+        // Each simulated client mutates with its own mutators and seed
+        let engines: Vec<Engine> = args
+            .sim_clients
+            .iter()
+            .map(|c| {
+                Engine::from_weights(&c.mutators.clone().or(args.mutators.clone()).unwrap_or_default())
+                    .with_fixup(args.fixup)
+            })
+            .collect();
+        let mut shares = None;
+        let inputs: Box<dyn Iterator<Item = _>> = if args.sim_clients.is_empty() {
+            Box::new(
+                generate::guided(bases, seed, &engine, pool.clone())
+                    .filter(|i| i.version == nfs_version && admitted(&selected, i)),
+            )
+        } else {
+            let mut scheduler = Scheduler::new();
+            for (n, (client, engine)) in args.sim_clients.iter().zip(&engines).enumerate() {
+                let selected = selected.clone();
+                let stream = generate::guided(bases.clone(), seed.wrapping_add(n as u64), engine, pool.clone())
+                    .filter(move |i| i.version == nfs_version && admitted(&selected, i));
+                scheduler = scheduler.with_client(&client.name, client.rate, fairness::DEFAULT_BURST, stream);
+            }
+            shares = Some(scheduler.shares());
+            Box::new(scheduler)
+        };
+        let inputs = inputs
+            .take(args.iterations.map_or(usize::MAX, |n| n as usize))
+            .skip(resumed.as_ref().map_or(0, |c| c.sent as usize));
+        info!("Fuzzing with seed {}", seed);
+        let tags = match (args.no_canary, config.proto) {
+            (false, Proto::Tcp) => canary_tags(&config).await,
+            _ => Vec::new(),
+        };
+        let session = match args.session {
+            true => Some(open_session(&config, campaign, args.ssv).await?),
+            false => None,
+        };
+        let sec = match args.sec {
+            Some(service) => Some(gss_context(&config, service, args.gss_service.as_deref()).await?),
+            None => None,
+        };
+        let mut fuzzer = Fuzzer::new(config).with_checkpoints(seed, args.checkpoint_every);
+        if let Some((table, gss)) = session {
+            fuzzer = fuzzer.with_session(table);
+            if let Some(ctx) = gss {
+                fuzzer = fuzzer.with_gss(ctx);
+            }
+        }
+        if let Some(ctx) = sec {
+            fuzzer = fuzzer.with_gss_auth(ctx);
+        }
+        if let Some((export, advertised)) = secinfo {
+            fuzzer = fuzzer.with_secinfo(export, advertised);
+        }
+        if let Some(path) = &args.pcap {
+            fuzzer = fuzzer.with_capture(pcap::Writer::create(path)?);
+            info!("Capturing traffic to {}", path.display());
+        }
+        if let Some(path) = &args.results_log {
+            let log = ResultLog::open(path)
+                .with_context(|| format!("opening {}", path.display()))?;
+            fuzzer = fuzzer.with_results(log);
+        }
+        if let Some(path) = &args.db {
+            let db = ResultsDb::open(path, &campaign.to_string())
+                .with_context(|| format!("opening {}", path.display()))?;
+            fuzzer = fuzzer.with_db(db);
+        }
+        if let Some(kcov) = &args.kcov {
+            let session = KcovSession::start(kcov).context("starting the coverage agent")?;
+            fuzzer = fuzzer.with_feedback(Feedback::new(session, pool));
+            info!("Coverage feedback from {} on {}", kcov.agent, kcov.ssh.host);
+        }
+        if let Some(spec) = &args.monitor {
+            let monitor = Monitor::start(spec).context("starting the kernel log monitor")?;
+            fuzzer = fuzzer.with_monitor(monitor);
+            info!("Following the kernel log of {} with {}", spec.ssh.host, spec.command);
+        }
+        if let Some(checkpoint) = resumed {
+            let stats = CampaignStats::load(Path::new(&args.output))
+                .context("loading the statistics to resume")?;
+            info!("Resuming after {} cases, {} findings", checkpoint.sent, checkpoint.findings.len());
+            fuzzer = fuzzer.resume(checkpoint, stats);
+        }
+        fuzzer.stats.tags.extend(tags);
+        fuzzer.run(inputs, &gate).await?;
+        info!(
+            "Sent {} cases, {} findings",
+            fuzzer.sent(),
+            fuzzer.findings.len()
+        );
+        if let Some(shares) = &shares {
+            let report = FairnessReport::new(shares, &fuzzer.findings);
+            report.save(Path::new(&args.output))?;
+            info!("Client shares:\n{}", report);
+        }
+        log_findings(&fuzzer.findings);
     }
 
     Ok(())
 }
+
+/// Cases sent to each program found by portmap DUMP without --iterations
+const GENERIC_CASES: u64 = 1000;
+
+/// Rounds of each mixed interaction without --iterations
+const MIXED_ROUNDS: u64 = 10;
+
+fn log_findings(findings: &[Finding]) {
+    for finding in findings {
+        let verdict = finding
+            .reproduction
+            .as_ref()
+            .map_or("unverified".to_string(), |r| r.verdict().to_string());
+        info!(
+            "{:?} {} ({}): {}",
+            finding.kind,
+            finding.name,
+            verdict,
+            finding.path.display()
+        );
+        if let Some(minimized) = &finding.minimized {
+            info!("  minimized: {}", minimized.display());
+        }
+    }
+}
+
+/// Log where NFS, MOUNT and NLM listen on `target`'s host, per its
+/// portmapper, and return the NFS address and the MOUNT address if it is
+/// registered; `target` stands when NFS is not registered, as v4-only
+/// servers often are not
+async fn discover(
+    target: SocketAddr,
+    proto: Proto,
+    nfs_version: u32,
+    timeouts: Timeouts,
+) -> anyhow::Result<(SocketAddr, Option<SocketAddr>)> {
+    let portmap = SocketAddr::new(target.ip(), rpc::PORTMAP_PORT);
+    let mut conn = Transport::connect(proto, portmap, timeouts)
+        .await
+        .with_context(|| format!("connecting to portmap at {}", portmap))?;
+    let (mut nfs, mut mountd) = (None, None);
+    let programs = [
+        ("NFS", rpc::program::NFS, nfs_version),
+        ("MOUNT", rpc::program::MOUNT, mount::VERSION),
+        ("NLM", rpc::program::NLM, 4),
+    ];
+    for (name, program, version) in programs {
+        let port = match conn.getport(program, version).await {
+            Ok(port) => port,
+            // rpcbind without the v2 portmap interface
+            Err(e) => {
+                info!("GETPORT {} failed ({}), trying GETADDR", name, e);
+                conn.getaddr(program, version).await?.map(|a| a.port())
+            }
+        };
+        match port {
+            Some(port) => info!("{} v{}: port {}", name, version, port),
+            None => info!("{} v{}: not registered", name, version),
+        }
+        match program {
+            rpc::program::NFS => nfs = port,
+            rpc::program::MOUNT => mountd = port,
+            _ => {}
+        }
+    }
+    let at = |port| SocketAddr::new(target.ip(), port);
+    Ok((nfs.map_or(target, at), mountd.map(at)))
+}
+
+/// MNT `export` for its root handle, at `mountd` or wherever portmap on
+/// `target`'s host says MOUNT listens
+async fn mount_root(
+    proto: Proto,
+    target: SocketAddr,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    timeouts: Timeouts,
+    identity: &Identity,
+) -> anyhow::Result<mount::Mounted> {
+    let mountd = match mountd {
+        Some(addr) => addr,
+        None => {
+            let portmap = SocketAddr::new(target.ip(), rpc::PORTMAP_PORT);
+            let port = Transport::connect(proto, portmap, timeouts)
+                .await
+                .with_context(|| format!("connecting to portmap at {}", portmap))?
+                .getport(rpc::program::MOUNT, mount::VERSION)
+                .await?
+                .with_context(|| format!("MOUNT v{} not registered at {}", mount::VERSION, portmap))?;
+            SocketAddr::new(target.ip(), port)
+        }
+    };
+    let mut conn = Transport::connect(proto, mountd, timeouts)
+        .await
+        .with_context(|| format!("connecting to MOUNT at {}", mountd))?;
+    let reply = conn.call(&mount::mnt(export.as_bytes()).message(identity)).await?;
+    let mounted = mount::root_filehandle(&reply).with_context(|| format!("MNT {}", export))?;
+    info!(
+        "MNT {}: {}-byte handle, auth flavors {:?}",
+        export,
+        mounted.fh.len(),
+        mounted.auth_flavors
+    );
+    Ok(mounted)
+}
+
+/// Run the built-in scenarios of `strategies` on `export` over v3,
+/// saving a report of each to `<output>/scenarios/`
+async fn run_scenarios(
+    config: &FuzzConfig,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    strategies: &[Strategy],
+    output: &Path,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let identity = &config.identity;
+    let mounted = mount_root(config.proto, config.target, mountd, export, timeouts, identity).await?;
+    let mut conn = NfsConnection::connect(config.target, timeouts)
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let (link_max, name_max) = scenario::run::pathconf(&mut conn, identity, &mounted.fh)
+        .await
+        .context("PATHCONF")?
+        .unwrap_or((scenario::LINK_MAX_TYPICAL[0], scenario::NAME_MAX as u64));
+    let dir = output.join("scenarios");
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    for s in strategies.iter().flat_map(|&st| scenario::builtin(st, link_max, name_max as usize)) {
+        info!("Scenario {}: {} steps, {} cleanup", s.name, s.steps.len(), s.cleanup.len());
+        let report = scenario::run::run(&mut conn, identity, &mounted.fh, &s)
+            .await
+            .with_context(|| format!("scenario {}", s.name))?;
+        log_unmet(&report);
+        let path = dir.join(format!("{}.json", s.name));
+        std::fs::write(&path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Run each mixed interaction `rounds` times on a fresh shared file,
+/// saving the reads and sizes that contradict the writes under `mixed/`
+async fn run_mixed(
+    config: &FuzzConfig,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    campaign: CampaignId,
+    rounds: u64,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let identity = &config.identity;
+    let mounted = mount_root(config.proto, config.target, mountd, export, timeouts, identity).await?;
+    let connect = || async {
+        NfsConnection::connect(config.target, timeouts)
+            .await
+            .with_context(|| format!("connecting to {}", config.target))
+    };
+    let owner = ClientOwner {
+        verifier: rand::random(),
+        ownerid: format!("nfs-fuzzer-{}-mixed", campaign).into_bytes(),
+    };
+    let mut sides = mixed::Sides::new(connect().await?, connect().await?, &owner, identity)
+        .await
+        .context("setting up the v4.1 session")?;
+    let dir = output.join("mixed");
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let mut found = Vec::new();
+    for interaction in mixed::interactions() {
+        for round in 0..rounds {
+            let file = sides.share(identity, &mounted.fh, export).await.context("sharing the mixed file")?;
+            let timed = sides.run(identity, &file, &interaction.steps).await;
+            for (step, incoherence) in mixed::check(&interaction.steps, &timed) {
+                let line = format!("{} round {} step {}: {:?}", interaction.name, round, step, incoherence);
+                warn!("{:?}: {}", Oracle::Coherence, line);
+                found.push(line);
+            }
+        }
+    }
+    sides.unshare(identity, &mounted.fh).await.context("removing the mixed file")?;
+    let path = dir.join("incoherences.txt");
+    std::fs::write(&path, found.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .with_context(|| format!("writing {}", path.display()))?;
+    info!("Mixed: {} interactions, {} rounds each, {} incoherent", mixed::interactions().len(), rounds, found.len());
+    Ok(())
+}
+
+/// The shared file, fresh, for one run of a race pair, with nothing the
+/// last run made left beside it; OPENs go under the v4.0 `clientid`
+async fn race_file(
+    sides: &mut mixed::Sides,
+    identity: &Identity,
+    dir3: &[u8],
+    export: &str,
+    clientid: u64,
+) -> anyhow::Result<SharedFile> {
+    for msg in race::leftovers(dir3, identity) {
+        sides.v3.call(&msg).await.context("REMOVE")?;
+    }
+    let file = sides.share(identity, dir3, export).await.context("sharing the race file")?;
+    Ok(SharedFile { clientid, ..file })
+}
+
+/// Sweep every race pair on two connections, each order one call after
+/// the other and then released together at each stagger, then run the
+/// symlink swap against each link target; saves the releases that came
+/// back with statuses neither order gives and the probes that escaped
+/// under `races/`
+async fn run_races(
+    config: &FuzzConfig,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    campaign: CampaignId,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let identity = &config.identity;
+    let mounted = mount_root(config.proto, config.target, mountd, export, timeouts, identity).await?;
+    let connect = || async {
+        NfsConnection::connect(config.target, timeouts)
+            .await
+            .with_context(|| format!("connecting to {}", config.target))
+    };
+    let owner = ClientOwner {
+        verifier: rand::random(),
+        ownerid: format!("nfs-fuzzer-{}-race", campaign).into_bytes(),
+    };
+    let mut sides = mixed::Sides::new(connect().await?, connect().await?, &owner, identity)
+        .await
+        .context("setting up the v4.1 session")?;
+    let clientid = session::confirm_client(&mut sides.v4, &owner, identity)
+        .await
+        .context("setting up the v4.0 client")?;
+    let dir = output.join("races");
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let pairs = race::sweep(&race::RaceOp::ALL);
+    let mut found = Vec::new();
+    for &pair in &pairs {
+        let mut serial = [[None; 2]; 2];
+        for (order, swap) in [(pair, false), (pair.swapped(), true)] {
+            let file = race_file(&mut sides, identity, &mounted.fh, export, clientid).await?;
+            let first = sides.v3.call(&order.first.message(&file, identity)).await;
+            let second = sides.v4.call(&order.second.message(&file, identity)).await;
+            serial[swap as usize] = race::outcome(&if swap { [second, first] } else { [first, second] });
+        }
+        for stagger in race::STAGGERS {
+            let file = race_file(&mut sides, identity, &mounted.fh, export, clientid).await?;
+            let calls = [pair.first.message(&file, identity), pair.second.message(&file, identity)];
+            let replies = race::release(&mut sides.v3, &mut sides.v4, [&calls[0], &calls[1]], stagger).await;
+            let got = race::outcome(&replies);
+            if race::diverges(got, &serial) {
+                let line = format!("{} at {:?}: {:?}, serially {:?}", pair.name(), stagger, got, serial);
+                warn!("Race: {}", line);
+                found.push(line);
+            }
+        }
+    }
+    for msg in race::leftovers(&mounted.fh, identity) {
+        sides.v3.call(&msg).await.context("REMOVE")?;
+    }
+    // The export's v4 handle, which the symlink swap runs in
+    let file = sides.share(identity, &mounted.fh, export).await.context("sharing the race file")?;
+    sides.unshare(identity, &mounted.fh).await.context("removing the race file")?;
+    let path = dir.join("divergences.txt");
+    std::fs::write(&path, found.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .with_context(|| format!("writing {}", path.display()))?;
+    info!("Races: {} pairs at {} staggers, {} divergent", pairs.len(), race::STAGGERS.len(), found.len());
+
+    let mut escapes = Vec::new();
+    for target in toctou::TARGETS {
+        let fixture = toctou::Fixture {
+            dir: file.dir4.clone(),
+            clientid,
+            owner: b"toctou".to_vec(),
+            target: target.to_vec(),
+        };
+        let setup = nfs_results(&sides.v3.call(&fixture.setup(identity)).await?)?;
+        let survey = nfs_results(&sides.v3.call(&fixture.survey(identity)).await?)?;
+        match (setup.starts_with(&[0; 4]), toctou::decode_known(&survey)) {
+            (true, Some(known)) => {
+                let tally = toctou::run(&mut sides.v3, &mut sides.v4, &fixture, &known, SWAP_ROUNDS, identity).await;
+                info!(
+                    "Symlink swap to {}: {} inside, {} refused, {} garbled, {} lost, {} escaped",
+                    String::from_utf8_lossy(target),
+                    tally.inside,
+                    tally.refused,
+                    tally.garbled,
+                    tally.lost,
+                    tally.escapes.len()
+                );
+                for (probe, component, level) in tally.escapes {
+                    let line = format!(
+                        "{} through {} to {}: reached {:?}, not {:?}",
+                        probe.name(),
+                        String::from_utf8_lossy(&component),
+                        String::from_utf8_lossy(target),
+                        level,
+                        known
+                    );
+                    warn!("Symlink swap: {}", line);
+                    escapes.push(line);
+                }
+            }
+            _ => warn!("Symlink swap to {}: the fixture could not be set up", String::from_utf8_lossy(target)),
+        }
+        for msg in fixture.cleanup(identity) {
+            sides.v3.call(&msg).await.context("symlink swap cleanup")?;
+        }
+    }
+    let path = dir.join("escapes.txt");
+    std::fs::write(&path, escapes.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+/// Times the symlink swap puts each form in place, per link target
+const SWAP_ROUNDS: usize = 200;
+
+/// Whether `input` is one of the procedures of the `selected` preset's
+/// campaign; all are, without a preset
+fn admitted(selected: &Option<Campaign>, input: &generate::Input) -> bool {
+    selected.as_ref().is_none_or(|c| c.admits(input.program, input.version, input.procedure))
+}
+
+/// Refuse a campaign the fuzz loop would send less of than was asked:
+/// one where a strategy of the `selected` preset with cases in `bases`
+/// has none the preset admits at NFS `version`, so all of them would be
+/// dropped, or where no base at all is sent, so the loop would wait
+/// forever for its first case. Without a preset, the stateless
+/// strategies of the other version are left out as before.
+fn check_sendable(
+    bases: &[generate::Input],
+    selected: &Option<Campaign>,
+    version: u32,
+) -> anyhow::Result<()> {
+    let sendable = |i: &generate::Input| i.version == version && admitted(selected, i);
+    let unsent: Vec<Strategy> = selected
+        .iter()
+        .flat_map(|c| &c.strategies)
+        .copied()
+        .filter(|s| {
+            let prefix = format!("{:?}:", s);
+            let mut cases = bases.iter().filter(|i| i.lineage.seed.starts_with(&prefix)).peekable();
+            cases.peek().is_some() && !cases.any(sendable)
+        })
+        .collect();
+    anyhow::ensure!(
+        unsent.is_empty(),
+        "{:?} have no cases the campaign sends at NFS v{}; pick the version with -V",
+        unsent,
+        version
+    );
+    anyhow::ensure!(
+        bases.iter().any(sendable),
+        "the campaign sends no case at NFS v{}; pick the version with -V",
+        version
+    );
+    Ok(())
+}
+
+/// Strategies run on TCP connections of their own rather than through
+/// the fuzz loop: the scenarios, the v4.1 sessions of the mixed and race
+/// runs, the reclaims and NSM spoofing
+const TCP_ONLY: &[Strategy] = &[
+    Strategy::DeepTree,
+    Strategy::LongNames,
+    Strategy::HardLinks,
+    Strategy::MixedVersion,
+    Strategy::OpRaces,
+    Strategy::LockReclaim,
+    Strategy::NsmSpoof,
+];
+
+/// Refuse `--proto udp` together with a run it would not apply to:
+/// `--races`, `--mixed`, `--cache-tier`, or a preset with strategies in
+/// [`TCP_ONLY`]
+fn check_proto(args: &Args) -> anyhow::Result<()> {
+    if args.proto == Proto::Tcp {
+        return Ok(());
+    }
+    let flags: Vec<&str> = [
+        ("--races", args.races),
+        ("--mixed", args.mixed),
+        ("--cache-tier", args.cache_tier.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(flag, set)| set.then_some(flag))
+    .collect();
+    anyhow::ensure!(flags.is_empty(), "{} need --proto tcp", flags.join(" and "));
+    let strategies: Vec<Strategy> = args
+        .preset
+        .map(|p| p.campaign().strategies)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|s| TCP_ONLY.contains(s))
+        .collect();
+    anyhow::ensure!(
+        strategies.is_empty(),
+        "{:?} run over TCP, so the preset needs --proto tcp",
+        strategies
+    );
+    Ok(())
+}
+
+/// The first `--identity`, or root without one
+fn default_identity(args: &Args) -> Identity {
+    args.identities
+        .first()
+        .cloned()
+        .unwrap_or_else(|| Identity::new(0, 0))
+}
+
+/// Fuzz `cases` generic calls to one of the programs other than NFS a
+/// preset lists, restricted to the procedures it lists of it
+async fn run_side_program(
+    config: &FuzzConfig,
+    selected: &Campaign,
+    (program, version): (u32, u32),
+    seed: u64,
+    cases: usize,
+    gate: &control::Gate,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let addr = match program {
+        rpc::program::PORTMAP => SocketAddr::new(config.target.ip(), rpc::PORTMAP_PORT),
+        _ => registered(config.proto, config.target, program, version, timeouts).await?,
+    };
+    info!("Preset program {} v{} at {}", program, version, addr);
+    let config = FuzzConfig {
+        target: addr,
+        output: config.output.join(format!("rpc{}_v{}", program, version)),
+        probe_program: program,
+        nfs_version: version,
+        ..config.clone()
+    };
+    let mut fuzzer = Fuzzer::new(config);
+    let inputs = generic::stream(RpcService::new(program, version), seed, 16)
+        .filter(|i| selected.admits(i.program, i.version, i.procedure))
+        .take(cases);
+    fuzzer.run(inputs, gate).await?;
+    info!("Sent {} cases, {} findings", fuzzer.sent(), fuzzer.findings.len());
+    log_findings(&fuzzer.findings);
+    Ok(())
+}
+
+/// Random reclaims LockReclaim sends after the fixed cases
+const RANDOM_RECLAIMS: usize = 16;
+
+/// Reboot a v4.1 client over and over against the shared file, sending
+/// each reclaim case into the window; saves the replies that break the
+/// grace rules under `reclaim/`
+async fn run_reclaims(
+    config: &FuzzConfig,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    campaign: CampaignId,
+    seed: u64,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let identity = &config.identity;
+    let mounted = mount_root(config.proto, config.target, mountd, export, timeouts, identity).await?;
+    let connect = || async {
+        NfsConnection::connect(config.target, timeouts)
+            .await
+            .with_context(|| format!("connecting to {}", config.target))
+    };
+    let owner = ClientOwner {
+        verifier: rand::random(),
+        ownerid: format!("nfs-fuzzer-{}-reclaim", campaign).into_bytes(),
+    };
+    let mut sides = mixed::Sides::new(connect().await?, connect().await?, &owner, identity)
+        .await
+        .context("setting up the v4.1 session")?;
+    let file = sides.share(identity, &mounted.fh, export).await.context("sharing the reclaim file")?;
+    let mut rng = StdRng::seed_from_u64(seed);
+    let reclaimed =
+        reclaim::run(&mut sides.v4, &mut rng, &owner, &file.dir4, &file.name, &file.file4, RANDOM_RECLAIMS, identity)
+            .await
+            .context("LockReclaim")?;
+    sides.unshare(identity, &mounted.fh).await.context("removing the reclaim file")?;
+    let dir = output.join("reclaim");
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let mut found = Vec::new();
+    for r in &reclaimed {
+        if let Some(violation) = r.violation {
+            let grace = if r.in_grace { "in grace" } else { "out of grace" };
+            let line = format!("{} ({:?}, {}): {}, status {}", r.case, r.kind, grace, violation, r.status);
+            warn!("{:?}: {}", Oracle::Grace, line);
+            found.push(line);
+        }
+    }
+    let path = dir.join("violations.txt");
+    std::fs::write(&path, found.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .with_context(|| format!("writing {}", path.display()))?;
+    let in_grace = reclaimed.iter().filter(|r| r.in_grace).count();
+    info!("LockReclaim: {} reclaims, {} in grace, {} violations", reclaimed.len(), in_grace, found.len());
+    Ok(())
+}
+
+/// Host name the NsmSpoof victim takes its NLM lock under
+const NSM_VICTIM: &[u8] = b"nfs-fuzzer-victim.example";
+
+/// How long statd is given to pass a notification on to lockd
+const NOTIFY_SETTLE: Duration = Duration::from_millis(500);
+
+/// Where portmap on `target`'s host says `program`/`version` listens
+async fn registered(
+    proto: Proto,
+    target: SocketAddr,
+    program: u32,
+    version: u32,
+    timeouts: Timeouts,
+) -> anyhow::Result<SocketAddr> {
+    let portmap = SocketAddr::new(target.ip(), rpc::PORTMAP_PORT);
+    let port = Transport::connect(proto, portmap, timeouts)
+        .await
+        .with_context(|| format!("connecting to portmap at {}", portmap))?
+        .getport(program, version)
+        .await?
+        .with_context(|| format!("program {} v{} not registered at {}", program, version, portmap))?;
+    Ok(SocketAddr::new(target.ip(), port))
+}
+
+/// For each spoofed SM_NOTIFY naming the victim: take the victim's NLM
+/// lock on the shared file, LOCKT it over v4 as another client, send the
+/// spoof to statd and LOCKT again; saves the locks a spoof released
+/// under `nsm/`
+async fn run_nsm_spoof(
+    config: &FuzzConfig,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    campaign: CampaignId,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let identity = &config.identity;
+    let mounted = mount_root(config.proto, config.target, mountd, export, timeouts, identity).await?;
+    let connect = |addr: SocketAddr| async move {
+        Transport::connect(config.proto, addr, timeouts)
+            .await
+            .with_context(|| format!("connecting to {}", addr))
+    };
+    let nlm_addr = registered(config.proto, config.target, rpc::program::NLM, nlm::VERSION, timeouts).await?;
+    let nsm_addr = registered(config.proto, config.target, rpc::program::NSM, nsm::VERSION, timeouts).await?;
+    let (mut lockd, mut statd) = (connect(nlm_addr).await?, connect(nsm_addr).await?);
+    let owner = ClientOwner {
+        verifier: rand::random(),
+        ownerid: format!("nfs-fuzzer-{}-nsm", campaign).into_bytes(),
+    };
+    let v3 = NfsConnection::connect(config.target, timeouts).await.context("connecting for v3")?;
+    let v4 = NfsConnection::connect(config.target, timeouts).await.context("connecting for v4")?;
+    let mut sides = mixed::Sides::new(v3, v4, &owner, identity).await.context("setting up the v4.1 session")?;
+    let file = sides.share(identity, &mounted.fh, export).await.context("sharing the NSM file")?;
+    let (offset, length) = (0, 4096);
+    let victim = nlm::Lock::new(NSM_VICTIM, &file.file3, 1, offset, length);
+    let probe = nsm::probe_case(&LockOwner::new(sides.session.clientid, b"nsm-probe".to_vec()), offset, length);
+    let dir = output.join("nsm");
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let mut found = Vec::new();
+    let spoofs = nsm::spoof_notifies(identity, NSM_VICTIM, None);
+    for spoof in &spoofs {
+        let take = nlm::lock(b"nsm", false, true, &victim, false, 1);
+        let taken = nfs_results(&lockd.call(&take.message(identity)).await?)?;
+        match nlm::decode_res(&taken).context("NLM LOCK")? {
+            (_, nlm::stat::GRANTED) => {}
+            (_, stat) => anyhow::bail!("NLM LOCK for the victim: {}", nlm::stat::name(stat).unwrap_or("unknown status")),
+        }
+        let before = on_file(&mut sides, &file, &probe, identity).await.context("LOCKT before")?;
+        statd.call(&spoof.call).await.with_context(|| format!("SM_NOTIFY {}", spoof.label))?;
+        tokio::time::sleep(NOTIFY_SETTLE).await;
+        let after = on_file(&mut sides, &file, &probe, identity).await.context("LOCKT after")?;
+        if before != nfsv4::status::NFS4ERR_DENIED {
+            info!("NsmSpoof {}: LOCKT before the spoof got status {}, not a conflict", spoof.label, before);
+        }
+        if let Some(hijack) = nsm::check_hijack(spoof, NSM_VICTIM, before, after) {
+            let line = format!(
+                "{} ({} state {}) released {}'s lock",
+                hijack.spoof,
+                String::from_utf8_lossy(&spoof.mon_name),
+                spoof.state,
+                String::from_utf8_lossy(&hijack.victim)
+            );
+            warn!("{:?}: {}", Oracle::LockHijack, line);
+            found.push(line);
+        }
+        lockd.call(&nlm::unlock(b"nsm", &victim).message(identity)).await.context("NLM UNLOCK")?;
+    }
+    sides.unshare(identity, &mounted.fh).await.context("removing the NSM file")?;
+    let path = dir.join("hijacks.txt");
+    std::fs::write(&path, found.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .with_context(|| format!("writing {}", path.display()))?;
+    info!("NsmSpoof: {} spoofed notifications, {} hijacks", spoofs.len(), found.len());
+    Ok(())
+}
+
+/// Send `case` on the session behind PUTFH of the shared file; the
+/// compound's status, that of the last op it ran
+async fn on_file(
+    sides: &mut mixed::Sides,
+    file: &SharedFile,
+    case: &FuzzCase,
+    identity: &Identity,
+) -> anyhow::Result<u32> {
+    let ops = std::iter::once(nfsv4::putfh(&file.file4)).chain(case.ops.iter().cloned());
+    let results = session::sequenced(&mut sides.v4, &mut sides.session, ops, identity).await?;
+    Ok(results.get(..4).map_or(u32::MAX, |w| u32::from_be_bytes(w.try_into().unwrap())))
+}
+
+/// What a cache is given past its window before it has to have caught up
+const CACHE_SLACK: Duration = Duration::from_secs(1);
+
+/// Name of the file the cache tier scenarios write and read back
+const CACHE_FILE: &[u8] = b"nfs-fuzzer-cache";
+
+/// NFS results of an accepted `reply`
+fn nfs_results(reply: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Ok(rpc::RpcReply::parse(reply)?.into_results()?.to_vec())
+}
+
+/// The flavors SECINFO lists for `export`, asked over v4.0 from its
+/// parent
+async fn advertised_flavors(config: &FuzzConfig, export: &Pathname) -> anyhow::Result<Vec<Secinfo>> {
+    let query = secinfo::query_cases(export)
+        .into_iter()
+        .find(|c| c.name == "secinfo")
+        .context("SECINFO names the export from its parent, so the export cannot be the root")?;
+    let mut conn = NfsConnection::connect(config.target, Timeouts::from(&config.budget))
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let msg = nfsv4::CompoundBuilder::new(nfsv4::minor_version::V4_0)
+        .putrootfh()
+        .ops(query.ops)
+        .message(&config.identity);
+    let results = nfs_results(&conn.call(&msg).await?)?;
+    secinfo::decode_query(&results).context("SECINFO of the export failed")
+}
+
+/// The export's v4 handle, walked to from the root over v4.0
+async fn export_handle(config: &FuzzConfig, export: &Pathname) -> anyhow::Result<Vec<u8>> {
+    let mut conn = NfsConnection::connect(config.target, Timeouts::from(&config.budget))
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let msg = nfsv4::CompoundBuilder::new(nfsv4::minor_version::V4_0)
+        .putrootfh()
+        .ops(export.iter().map(|c| nfsv4::lookup(c)))
+        .getfh()
+        .message(&config.identity);
+    let mut state = SessionState::default();
+    state.observe(&nfs_results(&conn.call(&msg).await?)?);
+    state.filehandles.pop().context("GETFH of the export failed")
+}
+
+/// v3 handle of `name` in `dir`
+async fn lookup3(conn: &mut NfsConnection, identity: &Identity, dir: &[u8], name: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let results = nfs_results(&conn.call(&nfsv3::lookup(dir, name).message(identity)).await?)?;
+    match populate::looked_up(&results) {
+        Some((_, Some(fh))) => Ok(fh),
+        found => anyhow::bail!("LOOKUP of {}: {:?}", String::from_utf8_lossy(name), found.map(|(status, _)| status)),
+    }
+}
+
+/// Run each cache tier scenario on a file emptied through the origin, the
+/// target, and read back through `cache`, an address and export path;
+/// saves what the cache served past its `window` under `cachetier/`
+async fn run_cache_tier(
+    config: &FuzzConfig,
+    mountd: Option<SocketAddr>,
+    export: &str,
+    cache: (SocketAddr, &str),
+    window: Duration,
+    output: &Path,
+) -> anyhow::Result<()> {
+    let timeouts = Timeouts::from(&config.budget);
+    let identity = &config.identity;
+    let origin_root = mount_root(config.proto, config.target, mountd, export, timeouts, identity).await?;
+    let cache_root = mount_root(config.proto, cache.0, None, cache.1, timeouts, identity).await?;
+    let mut origin = NfsConnection::connect(config.target, timeouts)
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let mut through = NfsConnection::connect(cache.0, timeouts)
+        .await
+        .with_context(|| format!("connecting to {}", cache.0))?;
+    let dir = output.join("cachetier");
+    std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+    let settle = window + CACHE_SLACK;
+    let mut found = Vec::new();
+    for poisoning in cachetier::poisonings(settle) {
+        let how = nfsv3::CreateHow::Unchecked(nfsv3::Sattr3 {
+            size: Some(0),
+            ..nfsv3::Sattr3::mode(0o644)
+        });
+        let create = nfsv3::create(&origin_root.fh, CACHE_FILE, &how).message(identity);
+        let results = nfs_results(&origin.call(&create).await?)?;
+        anyhow::ensure!(results.starts_with(&[0; 4]), "CREATE of the cache tier file failed");
+        // The cache has to have caught up with the empty file
+        tokio::time::sleep(settle).await;
+        let paths = cachetier::Paths {
+            origin: lookup3(&mut origin, identity, &origin_root.fh, CACHE_FILE).await?,
+            cache: lookup3(&mut through, identity, &cache_root.fh, CACHE_FILE).await?,
+        };
+        let conns = tokio::sync::Mutex::new((&mut origin, &mut through));
+        let conns = &conns;
+        let stale = cachetier::run(&poisoning.steps, &paths, window, |path, call| {
+            let msg = call.message(identity);
+            async move {
+                let mut conns = conns.lock().await;
+                let conn = match path {
+                    cachetier::Path::Origin => &mut *conns.0,
+                    cachetier::Path::Cache => &mut *conns.1,
+                };
+                nfs_results(&conn.call(&msg).await.ok()?).ok()
+            }
+        })
+        .await;
+        for (step, stale) in stale {
+            let line = format!("{} step {}: {:?}", poisoning.name, step, stale);
+            warn!("Cache tier: {}", line);
+            found.push(line);
+        }
+    }
+    let remove = nfsv3::remove(&origin_root.fh, CACHE_FILE).message(identity);
+    origin.call(&remove).await.context("REMOVE")?;
+    let path = dir.join("stale.txt");
+    std::fs::write(&path, found.iter().map(|l| format!("{}\n", l)).collect::<String>())
+        .with_context(|| format!("writing {}", path.display()))?;
+    info!("Cache tier: {} scenarios, {} stale", cachetier::poisonings(settle).len(), found.len());
+    Ok(())
+}
+
+fn log_unmet(report: &scenario::run::Report) {
+    for (i, step) in report.unmet() {
+        warn!("{} step {}: expected {:?}, got status {:?}", report.name, i, step.expect, step.status);
+    }
+}
+
+/// The scenario in `path`: a scenario file, its references resolved, if
+/// it is .ron, else JSON
+fn load_scenario(path: &Path) -> anyhow::Result<scenario::Scenario> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    if path.extension().is_some_and(|e| e == "ron") {
+        let file = ScenarioFile::parse(&text).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(file.compile().with_context(|| format!("resolving {}", path.display()))?)
+    } else {
+        serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))
+    }
+}
+
+/// Ask the target for its limits and save the boundary suite made from
+/// them; limits the server no longer reports are kept from the last suite
+async fn boundary_suite(config: &FuzzConfig, output: &Path) -> anyhow::Result<Suite> {
+    anyhow::ensure!(
+        config.nfs_version == 4,
+        "--boundaries needs NFSv4: a v3 file handle takes a MOUNT first"
+    );
+    let timeouts = Timeouts::from(&config.budget);
+    let mut conn = Transport::connect(config.proto, config.target, timeouts)
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let reply = conn
+        .call(&boundary::root_limits(&config.identity))
+        .await
+        .context("PUTROOTFH+GETFH+GETATTR")?;
+    let reply = rpc::RpcReply::parse(&reply).context("limits reply")?;
+    let (fh, mut limits) =
+        boundary::decode_root_limits(reply.results).context("limits reply does not decode")?;
+    let dir = Suite::dir(output, config.target, config.nfs_version);
+    if let Ok(saved) = Suite::load_limits(&dir) {
+        if saved != limits {
+            info!("Limits changed: were {:?}, now {:?}", saved, limits);
+        }
+        limits.merge(&saved);
+    }
+    let suite = Suite {
+        limits,
+        inputs: boundary::v4_inputs(&limits, &fh),
+    };
+    suite
+        .save(&dir, &config.identity)
+        .with_context(|| format!("saving {}", dir.display()))?;
+    info!(
+        "Boundary suite: {} cases from {:?} in {}",
+        suite.inputs.len(),
+        limits,
+        dir.display()
+    );
+    Ok(suite)
+}
+
+/// Set up the v4.1 session a campaign's compounds are sent on
+async fn open_session(
+    config: &FuzzConfig,
+    campaign: CampaignId,
+    ssv: bool,
+) -> anyhow::Result<(SlotTable, Option<DynContext>)> {
+    anyhow::ensure!(
+        config.nfs_version == 4 && config.proto == Proto::Tcp,
+        "--session needs NFSv4 over TCP"
+    );
+    let mut conn = NfsConnection::connect(config.target, Timeouts::from(&config.budget))
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let owner = ClientOwner {
+        verifier: rand::random(),
+        ownerid: format!("nfs-fuzzer-{}", campaign).into_bytes(),
+    };
+    let back = session::CONN_BACK_CHAN;
+    let (table, gss) = match ssv {
+        false => {
+            let exchange = reclaim::exchange_id(&owner, session::USE_NON_PNFS);
+            let (table, _) = session::establish_with(&mut conn, exchange, back, &config.identity)
+                .await
+                .context("setting up the v4.1 session")?;
+            (table, None)
+        }
+        true => {
+            let params = SsvParams::default();
+            let mut protected = ssv::establish(&mut conn, &owner, &params, back, &config.identity)
+                .await
+                .context("setting up the v4.1 session under SP4_SSV")?;
+            info!(
+                "SSV protection: {}-byte SSV, {} GSS handles",
+                protected.info.ssv_len,
+                protected.info.handles.len()
+            );
+            let fresh: Vec<u8> = (0..protected.info.ssv_len).map(|_| rand::random()).collect();
+            let (violations, key) = ssv::probe(&mut conn, &mut protected, &fresh, &config.identity)
+                .await
+                .context("probing SSV protection")?;
+            for violation in &violations {
+                warn!("SSV: {}", violation);
+            }
+            let gss = protected.context(&key, Service::Integrity).map(|ctx| ctx.boxed());
+            (protected.table, gss)
+        }
+    };
+    info!(
+        "Session {} of client {:#x}: {} slots",
+        table.sessionid.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+        table.clientid,
+        table.seqids.len()
+    );
+    tokio::spawn(async move {
+        match backchannel::serve(conn).await {
+            Ok(back) => info!(
+                "Backchannel closed after {} callbacks, {} CB_NOTIFY_DEVICEIDs",
+                back.answered, back.notified
+            ),
+            Err(e) => warn!("Backchannel: {}", e),
+        }
+    });
+    Ok((table, gss))
+}
+
+/// Set up the Kerberos context a campaign's cases are authenticated with
+#[cfg(feature = "krb5")]
+async fn gss_context(
+    config: &FuzzConfig,
+    service: Service,
+    name: Option<&str>,
+) -> anyhow::Result<DynContext> {
+    anyhow::ensure!(config.proto == Proto::Tcp, "--sec needs TCP");
+    let name = name.map_or_else(|| format!("nfs@{}", config.target.ip()), str::to_string);
+    let mech = Krb5::new(&name).with_context(|| format!("naming {}", name))?;
+    let mut conn = NfsConnection::connect(config.target, Timeouts::from(&config.budget))
+        .await
+        .with_context(|| format!("connecting to {}", config.target))?;
+    let ctx = gss::establish(&mut conn, mech, service, rpc::program::NFS, config.nfs_version)
+        .await
+        .with_context(|| format!("setting up a {} context with {}", service, name))?;
+    info!("{} context with {}: window {}", service, name, ctx.window);
+    Ok(ctx.boxed())
+}
+
+#[cfg(not(feature = "krb5"))]
+async fn gss_context(
+    _config: &FuzzConfig,
+    _service: Service,
+    _name: Option<&str>,
+) -> anyhow::Result<DynContext> {
+    anyhow::bail!("--sec needs a build with the krb5 feature")
+}
+
+/// Run the middlebox canary against the target and warn of whatever
+/// came between it and the server; the tags to put on the campaign
+async fn canary_tags(config: &FuzzConfig) -> Vec<String> {
+    let timeouts = Timeouts::from(&config.budget);
+    let print = canary::fingerprint(config.target, config.nfs_version, timeouts).await;
+    if print.baseline().is_none() {
+        warn!("Canary: plain NULL to {} went unanswered", config.target);
+        return Vec::new();
+    }
+    let found = print.interference();
+    for interference in &found {
+        warn!("Canary: {}", interference);
+    }
+    if found.is_empty() {
+        info!("Canary: nothing between us and {}", config.target);
+    } else {
+        warn!(
+            "Something on the path treats RPC records differently; \
+             findings may be middlebox artifacts"
+        );
+    }
+    found.iter().map(ToString::to_string).collect()
+}
+
+/// The base inputs of `strategies`, then the calls of each capture
+fn seed_inputs(
+    strategies: &[Strategy],
+    seed: u64,
+    captures: &[PathBuf],
+) -> anyhow::Result<Vec<generate::Input>> {
+    let mut inputs = generate::base_inputs(strategies, seed)?;
+    for path in captures {
+        let capture =
+            std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let messages =
+            pcap::messages(&capture).with_context(|| format!("parsing {}", path.display()))?;
+        let calls = generate::capture_inputs(&messages, &path.display().to_string());
+        info!("Seeded {} calls from {}", calls.len(), path.display());
+        inputs.extend(calls);
+    }
+    Ok(inputs)
+}
+
+/// The calls and replies of a .jsonl transcript, or of a raw client stream
+/// and optionally the server's
+fn load_transcript(input: &Path, replies: Option<&Path>) -> anyhow::Result<Vec<TranscriptEntry>> {
+    if input.extension().is_some_and(|e| e == "jsonl") {
+        let text = std::fs::read_to_string(input)
+            .with_context(|| format!("reading {}", input.display()))?;
+        return Ok(transcript::parse_jsonl(&text)?);
+    }
+    let calls = transcript::split_records(&std::fs::read(input)?)?;
+    let replies = match replies {
+        Some(path) => transcript::split_records(&std::fs::read(path)?)?,
+        None => Vec::new(),
+    };
+    Ok(transcript::pair_by_xid(calls, replies))
+}
+
+fn convert(input: &Path, replies: Option<&Path>, output: &Path) -> anyhow::Result<()> {
+    let entries = load_transcript(input, replies)?;
+
+    let name = input
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "transcript".to_string());
+    let conv = transcript::to_scenario(&name, &entries);
+    for reason in &conv.skipped {
+        info!("Skipped {}", reason);
+    }
+    let text = if output.extension().is_some_and(|e| e == "ron") {
+        ScenarioFile::from_scenario(&conv.scenario).to_ron()
+    } else {
+        serde_json::to_string_pretty(&conv.scenario)?
+    };
+    std::fs::write(output, text)
+        .with_context(|| format!("writing {}", output.display()))?;
+    info!(
+        "Wrote {} steps from {} calls to {}",
+        conv.scenario.steps.len(),
+        entries.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_check_sendable() {
+        let selected = Some(Preset::StateMachine.campaign());
+        let bases = generate::base_inputs(&selected.as_ref().unwrap().strategies, 1).unwrap();
+        // Its stateid, delegation and layout cases are all v4 COMPOUNDs
+        assert!(check_sendable(&bases, &selected, 3).is_err());
+        assert!(check_sendable(&bases, &selected, 4).is_ok());
+        assert!(check_sendable(&[], &selected, 4).is_err());
+        let bases = generate::base_inputs(generate::STATELESS, 1).unwrap();
+        assert!(check_sendable(&bases, &None, 3).is_ok());
+    }
+
+    #[test]
+    fn test_check_proto() {
+        let udp = |rest: &[&str]| {
+            let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--proto", "udp"].iter().chain(rest));
+            check_proto(&args)
+        };
+        assert!(udp(&[]).is_ok());
+        assert!(udp(&["--preset", "pre-auth"]).is_ok());
+        assert!(udp(&["--preset", "info-leak", "--export", "/e"]).is_ok());
+        assert!(udp(&["--races", "--export", "/e"]).is_err());
+        assert!(udp(&["--mixed", "--export", "/e"]).is_err());
+        assert!(udp(&["--cache-tier", "10.0.0.2:2049", "--export", "/e"]).is_err());
+        assert!(udp(&["--preset", "state-machine", "--export", "/e"]).is_err());
+        assert!(udp(&["--preset", "dos", "--export", "/e"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--races", "--export", "/e"]);
+        assert!(check_proto(&args).is_ok());
+    }
+
+    #[test]
+    fn test_args_are_consistent() {
+        Args::command().debug_assert();
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "10.0.0.1", "--preset", "dos"]);
+        assert_eq!(args.preset, Some(Preset::Dos));
+        let args = Args::parse_from([
+            "nfs-fuzzer", "-t", "h", "--identity", "0:0", "--identity", "1:1:5@m", "--rotate",
+            "per-request",
+        ]);
+        assert_eq!(args.identities.len(), 2);
+        let args = Args::parse_from([
+            "nfs-fuzzer", "-t", "h", "--proto", "udp", "--udp-faults", "drop=0.1,delay=3ms",
+        ]);
+        assert_eq!(args.udp_faults.map(|f| f.drop), Some(0.1));
+        assert_eq!(args.proto, Proto::Udp);
+        let args = Args::parse_from([
+            "nfs-fuzzer", "proxy", "127.0.0.1:2050", "10.0.0.1:2049", "--corrupt", "rate=0.1,marks",
+        ]);
+        assert!(matches!(args.command, Some(Command::Proxy { corrupt: Some(c), .. }) if c.marks));
+        // Without --program, rpc-generic mode fuzzes what portmap lists
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--mode", "rpc-generic"]);
+        assert_eq!((args.mode, args.program), (Mode::RpcGeneric, None));
+        assert!(Args::try_parse_from(["nfs-fuzzer"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "--generate-only", "100", "--seed", "7"]);
+        assert_eq!((args.generate_only, args.seed), (Some(100), Some(7)));
+        let args = Args::parse_from(["nfs-fuzzer", "--generate-only", "1", "--mutators", "havoc=3"]);
+        assert_eq!(args.mutators, Some(Weights(vec![("havoc", 3)])));
+        assert_eq!(args.fixup, Fixup::Off);
+        let args = Args::parse_from(["nfs-fuzzer", "--generate-only", "1", "--fixup", "gids_count"]);
+        assert_eq!(args.fixup, Fixup::Preserve("gids_count"));
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--iterations", "10", "--probe-every", "5"]);
+        assert_eq!((args.iterations, args.probe_every), (Some(10), 5));
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--controller", "libvirt,domain=nfs1"]);
+        assert!(matches!(args.controller, ControllerConfig::Libvirt(_)));
+        assert!(Args::try_parse_from(["nfs-fuzzer", "-t", "h", "--controller", "ssh"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "--docker-target", "ganesha", "--docker-arg", "--privileged"]);
+        assert_eq!((args.docker_target.as_deref(), &args.docker_args[..]), (Some("ganesha"), &["--privileged".to_string()][..]));
+        assert!(Args::try_parse_from(["nfs-fuzzer", "-t", "h", "--docker-target", "ganesha"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--kcov", "host=root@nfs1,agent=/opt/kcov-nfsd"]);
+        assert_eq!(args.kcov.map(|k| k.agent), Some("/opt/kcov-nfsd".to_string()));
+        // A template's options come first; the command line's win
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "lab", "--iterations", "5", "--campaign", "c", "-t", "h"]);
+        assert_eq!((args.target.as_deref(), args.iterations), (Some("h"), Some(5)));
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--sim-client", "a:3", "--sim-client", "b:1:havoc"]);
+        assert_eq!(args.sim_clients.iter().map(|c| c.rate).collect::<Vec<_>>(), [3.0, 1.0]);
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--monitor", "host=root@nfs1,command=journalctl -kf -n 0"]);
+        assert_eq!(args.monitor.map(|m| m.command), Some("journalctl -kf -n 0".to_string()));
+        let args = Args::parse_from(["nfs-fuzzer", "serve", "0.0.0.0:2049", "10.0.0.5:2049", "--rate", "0.5"]);
+        assert!(matches!(args.command, Some(Command::Serve { rate, header: false, .. }) if rate == 0.5));
+        let args = Args::parse_from(["nfs-fuzzer", "serve", "0.0.0.0:2049", "10.0.0.5:2049", "--template", "fattr4-overrun"]);
+        assert!(matches!(args.command, Some(Command::Serve { templates, .. }) if templates == [Template::Fattr4Overrun]));
+        let args = Args::parse_from(["nfs-fuzzer", "serve", "0.0.0.0:2049", "10.0.0.5:2049", "--client", "local", "--rounds", "5"]);
+        assert!(matches!(args.command, Some(Command::Serve { client: Some(_), rounds: 5, .. })));
+        let args = Args::parse_from(["nfs-fuzzer", "discover", "--broadcast", "10.0.0.255:111"]);
+        assert!(matches!(args.command, Some(Command::Discover { wait: 2000, .. })));
+        let args = Args::parse_from(["nfs-fuzzer", "populate", "10.0.0.5:2049", "--export", "/srv", "--depth", "2"]);
+        assert!(matches!(args.command, Some(Command::Populate { depth: 2, fanout: 3, seed: 0, .. })));
+        let args = Args::parse_from(["nfs-fuzzer", "replay", "out/crashes/00000007", "--count", "3"]);
+        assert!(matches!(args.command, Some(Command::Replay { count: 3, to: None, .. })));
+        assert!(Args::try_parse_from(["nfs-fuzzer", "--generate-only", "1", "--mutators", "x"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "convert", "t.jsonl", "-o", "s.json"]);
+        assert!(matches!(args.command, Some(Command::Convert { .. })));
+        let args = Args::parse_from(["nfs-fuzzer", "scenario", "run", "s.ron", "10.0.0.2:2049", "--export", "/srv"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Scenario {
+                command: ScenarioCommand::Run { report: None, .. }
+            })
+        ));
+        let args = Args::parse_from(["nfs-fuzzer", "view", "t.jsonl"]);
+        assert!(matches!(args.command, Some(Command::View { replies: None, .. })));
+        let args = Args::parse_from(["nfs-fuzzer", "analyze", "old-campaign"]);
+        assert!(matches!(args.command, Some(Command::Analyze { .. })));
+        let args = Args::parse_from([
+            "nfs-fuzzer", "corpus", "query", "c", "--procedure", "READ", "--extract", "out",
+        ]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Corpus {
+                command: CorpusCommand::Query { extract: Some(_), .. }
+            })
+        ));
+        assert!(Args::try_parse_from(["nfs-fuzzer", "corpus", "tag", "c", "e"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--sec", "krb5i", "--gss-service", "nfs@nfs1"]);
+        assert_eq!((args.sec, args.gss_service.as_deref()), (Some(Service::Integrity), Some("nfs@nfs1")));
+        assert!(Args::try_parse_from(["nfs-fuzzer", "-t", "h", "--sec", "krb5p", "--session", "--ssv"]).is_err());
+        assert!(Args::try_parse_from(["nfs-fuzzer", "-t", "h", "--ssv"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "-t", "h", "--resume", "--checkpoint-every", "0"]);
+        assert!(args.resume && args.checkpoint_every == 0);
+        assert!(Args::try_parse_from(["nfs-fuzzer", "-t", "h", "--resume", "--seed", "1"]).is_err());
+        let args = Args::parse_from(["nfs-fuzzer", "bundle", "8e52b764", "--dir", "out"]);
+        assert!(matches!(args.command, Some(Command::Bundle { bucket, .. }) if bucket == "8e52b764"));
+        let args = Args::parse_from(["nfs-fuzzer", "seeds", "list", "--nfs-version", "4"]);
+        assert!(matches!(args.command, Some(Command::Seeds { .. })));
+        let args = Args::parse_from(["nfs-fuzzer", "stats", "report", "out"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Stats {
+                command: StatsCommand::Report { .. }
+            })
+        ));
+        let args = Args::parse_from(["nfs-fuzzer", "stats", "compare", "a", "b", "--alpha", "0.01"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Stats {
+                command: StatsCommand::Compare { alpha, .. }
+            }) if alpha == 0.01
+        ));
+        let args = Args::parse_from(["nfs-fuzzer", "stats", "db", "r.sqlite", "--sql", "SELECT 1"]);
+        assert!(matches!(
+            args.command,
+            Some(Command::Stats {
+                command: StatsCommand::Db { sql: Some(ref sql), .. }
+            }) if sql == "SELECT 1"
+        ));
+    }
+}
@@ -0,0 +1,311 @@
+//! Shrinking crash reproducers
+//!
+//! A reproducer straight out of a campaign carries whatever the mutators
+//! left in it: cases that did nothing, trailing bytes the server never
+//! decoded, fields that could be zero. A [`Minimizer`] proposes smaller
+//! candidates one at a time, and the caller re-tests each against the
+//! target and reports back. A candidate that still reproduces replaces
+//! the current reproducer.
+//!
+//! Cases are dropped first, one at a time. Then each remaining case's
+//! arguments, the last case first, go through three passes:
+//!
+//! - binary-search truncation to the shortest word-aligned prefix
+//! - dropping the fields of [`Message::infer`] from the end towards the
+//!   front, with a length header and its body as one field
+//! - zeroing each field that is not zero already
+//!
+//! The search stops once the test budget is spent. Whatever reproduced
+//! last is the result.
+
+use crate::fields::{Kind, Message};
+use crate::generate::Input;
+use crate::xdr::xdr_pad_len;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Where the minimized reproducer of the finding at `path` goes: a
+/// directory inside a crash directory, or one beside a hang's input
+pub fn path_for(finding: &Path) -> PathBuf {
+    if finding.is_dir() {
+        finding.join("minimized")
+    } else {
+        finding.with_extension("min")
+    }
+}
+
+/// Where the search is
+#[derive(Debug, Clone)]
+enum Phase {
+    /// Dropping the case at this index
+    Cases(usize),
+    /// Shortest reproducing prefix of a case's arguments, in words,
+    /// is in `lo..=hi`
+    Truncate {
+        case: usize,
+        lo: usize,
+        hi: usize,
+    },
+    /// Dropping the fields of `msg` below `next`, highest first
+    Drop {
+        case: usize,
+        msg: Message,
+        next: usize,
+    },
+    /// Zeroing the fields of `msg` from `next` on
+    Simplify {
+        case: usize,
+        msg: Message,
+        next: usize,
+    },
+    Done,
+}
+
+/// Proposes smaller reproducers and keeps the ones that still reproduce
+#[derive(Debug, Clone)]
+pub struct Minimizer {
+    current: Vec<Input>,
+    candidate: Option<Vec<Input>>,
+    phase: Phase,
+    tests: u32,
+    budget: u32,
+}
+
+impl Minimizer {
+    /// Start from `cases`, a reproducer, with at most `budget` tests
+    pub fn new(cases: Vec<Input>, budget: u32) -> Self {
+        Self {
+            current: cases,
+            candidate: None,
+            phase: Phase::Cases(0),
+            tests: 0,
+            budget,
+        }
+    }
+
+    /// Tests run so far
+    pub fn tests(&self) -> u32 {
+        self.tests
+    }
+
+    /// The smallest reproducer found
+    pub fn finish(self) -> Vec<Input> {
+        self.current
+    }
+
+    fn with_args(&self, case: usize, args: Vec<u8>) -> Vec<Input> {
+        let mut cases = self.current.clone();
+        cases[case].args = args;
+        cases
+    }
+
+    fn truncate(&self, case: usize) -> Phase {
+        Phase::Truncate {
+            case,
+            lo: 0,
+            hi: self.current[case].args.len().div_ceil(4),
+        }
+    }
+
+    /// After the field passes over `case`, the case before it or the end
+    fn next_case(&self, case: usize) -> Phase {
+        match case.checked_sub(1) {
+            Some(case) => self.truncate(case),
+            None => Phase::Done,
+        }
+    }
+
+    /// The bytes dropping field `index` of `msg` removes
+    fn drop_range(msg: &Message, index: usize) -> Option<Range<usize>> {
+        let field = &msg.fields[index];
+        if field.kind == Kind::Bytes && msg.header_of(index).is_some() {
+            // Goes with its header
+            return None;
+        }
+        let body = msg.fields.get(index + 1);
+        match body {
+            Some(body) if msg.header_of(index + 1) == Some(index) => {
+                let end = body.offset + body.len + xdr_pad_len(body.len);
+                Some(field.offset..end.min(msg.bytes.len()))
+            }
+            _ => Some(field.range()),
+        }
+    }
+
+    /// The next candidate to test, or `None` when the search is over
+    pub fn propose(&mut self) -> Option<Vec<Input>> {
+        loop {
+            if self.tests >= self.budget || self.current.is_empty() {
+                return None;
+            }
+            let candidate = match &mut self.phase {
+                Phase::Cases(i) => {
+                    if self.current.len() <= 1 || *i >= self.current.len() {
+                        self.phase = self.truncate(self.current.len() - 1);
+                        continue;
+                    }
+                    let mut cases = self.current.clone();
+                    cases.remove(*i);
+                    cases
+                }
+                &mut Phase::Truncate { case, lo, hi } => {
+                    if lo >= hi {
+                        let msg = Message::infer(&self.current[case].args);
+                        let next = msg.fields.len();
+                        self.phase = Phase::Drop { case, msg, next };
+                        continue;
+                    }
+                    let args = &self.current[case].args;
+                    let mid = (lo + hi) / 2;
+                    let prefix = args[..(mid * 4).min(args.len())].to_vec();
+                    self.with_args(case, prefix)
+                }
+                Phase::Drop { case, msg, next } => {
+                    let case = *case;
+                    let Some(index) = next.checked_sub(1) else {
+                        let msg = Message::infer(&self.current[case].args);
+                        self.phase = Phase::Simplify { case, msg, next: 0 };
+                        continue;
+                    };
+                    let Some(range) = Self::drop_range(msg, index) else {
+                        *next = index;
+                        continue;
+                    };
+                    let mut args = self.current[case].args.clone();
+                    args.drain(range);
+                    self.with_args(case, args)
+                }
+                Phase::Simplify { case, msg, next } => {
+                    let case = *case;
+                    let Some(field) = msg.fields.get(*next) else {
+                        self.phase = self.next_case(case);
+                        continue;
+                    };
+                    let mut args = self.current[case].args.clone();
+                    let bytes = &mut args[field.range()];
+                    if bytes.iter().all(|&b| b == 0) {
+                        *next += 1;
+                        continue;
+                    }
+                    bytes.fill(0);
+                    self.with_args(case, args)
+                }
+                Phase::Done => return None,
+            };
+            self.tests += 1;
+            self.candidate = Some(candidate.clone());
+            return Some(candidate);
+        }
+    }
+
+    /// Whether the candidate [`Minimizer::propose`] handed out reproduced
+    pub fn report(&mut self, reproduced: bool) {
+        let Some(candidate) = self.candidate.take() else {
+            return;
+        };
+        match &mut self.phase {
+            Phase::Cases(i) => {
+                if !reproduced {
+                    *i += 1;
+                }
+            }
+            Phase::Truncate { lo, hi, .. } => {
+                let mid = (*lo + *hi) / 2;
+                if reproduced {
+                    *hi = mid;
+                } else {
+                    *lo = mid + 1;
+                }
+            }
+            Phase::Drop { next, .. } => *next -= 1,
+            Phase::Simplify { next, .. } => *next += 1,
+            Phase::Done => {}
+        }
+        if reproduced {
+            self.current = candidate;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineage::Lineage;
+    use crate::xdr::XdrEncoder;
+
+    fn input(name: &str, args: Vec<u8>) -> Input {
+        Input {
+            name: name.to_string(),
+            program: 100003,
+            version: 3,
+            procedure: 4,
+            args,
+            lineage: Lineage::new(name),
+            auth: None,
+        }
+    }
+
+    /// Run the search against `reproduces`
+    fn minimize(
+        cases: Vec<Input>,
+        budget: u32,
+        reproduces: impl Fn(&[Input]) -> bool,
+    ) -> Minimizer {
+        let mut m = Minimizer::new(cases, budget);
+        while let Some(candidate) = m.propose() {
+            let ok = reproduces(&candidate);
+            m.report(ok);
+        }
+        m
+    }
+
+    #[test]
+    fn test_minimizes_cases_and_arguments() {
+        // The server dies on a word 0xdead anywhere in the arguments
+        let killer = |args: &[u8]| args.chunks(4).any(|w| w == [0, 0, 0xde, 0xad]);
+        let mut enc = XdrEncoder::new();
+        enc.put_opaque(b"file-handle");
+        enc.put_u32(7);
+        enc.put_u32(0xdead);
+        enc.put_string("trailing name");
+        enc.put_u64(99);
+        let cases = vec![
+            input("setup", vec![1, 2, 3, 4]),
+            input("killer", enc.as_bytes().to_vec()),
+        ];
+        let m = minimize(cases, 500, |cases| cases.iter().any(|c| killer(&c.args)));
+        assert!(m.tests() < 500);
+        let cases = m.finish();
+        assert_eq!(cases.len(), 1);
+        assert_eq!(cases[0].name, "killer");
+        assert_eq!(cases[0].args, [0, 0, 0xde, 0xad]);
+    }
+
+    #[test]
+    fn test_keeps_what_the_crash_needs_within_budget() {
+        // Needs a length header of 4 and its body intact, after a nonzero
+        // word
+        let needs = |c: &[Input]| {
+            let a = &c[0].args;
+            a.len() >= 12 && a[..4] != [0; 4] && a[4..8] == [0, 0, 0, 4] && &a[8..12] == b"boom"
+        };
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(5);
+        enc.put_opaque(b"boom");
+        enc.put_u32(5);
+        let m = minimize(vec![input("x", enc.as_bytes().to_vec())], 500, needs);
+        assert_eq!(
+            m.finish()[0].args,
+            [0, 0, 0, 5, 0, 0, 0, 4, b'b', b'o', b'o', b'm']
+        );
+
+        // A spent budget stops the search with the last reproducer
+        let m = minimize(vec![input("x", vec![9; 64])], 1, |_| true);
+        assert_eq!(m.tests(), 1);
+        assert_eq!(m.finish()[0].args.len(), 32);
+        assert_eq!(
+            path_for(Path::new("/no/hang.bin")),
+            Path::new("/no/hang.min")
+        );
+    }
+}
@@ -0,0 +1,979 @@
+//! Mixed v3+v4 campaigns
+//!
+//! A server that speaks both versions keeps one set of files behind two
+//! state models: v4 has opens, delegations and change attributes, v3 has
+//! none of them and still has to trigger delegation recalls and keep
+//! sizes and data coherent. Interactions here pair v4 state on a shared
+//! file with v3 writes, truncations, removes and renames of the same
+//! file. Each interaction runs either as a seeded interleaving of its two
+//! lanes or with the lanes free-running concurrently, and a [`FileModel`]
+//! of what was written checks what v4 reads back. [`Sides`] runs them
+//! concurrently, v3 on one connection and v4 on a session on another;
+//! [`check`] goes by which changes were answered before each read went
+//! out.
+
+use crate::auth::Identity;
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::nfsv3::{self, CreateHow, Sattr3};
+use crate::nfsv4::open::{share_access, Open};
+use crate::nfsv4::reclaim::ClientOwner;
+use crate::nfsv4::session::{self, SessionError, SlotTable};
+use crate::nfsv4::state::SessionState;
+use crate::nfsv4::{
+    attr, bitmap_from_bits, bits_of_bitmap, close, delegreturn, getattr, getfh, lookup,
+    minor_version, op, putfh, putrootfh, read, stable_how, status, write, CompoundBuilder, Op,
+    Stateid, PROC_COMPOUND,
+};
+use crate::pattern::Pattern;
+use crate::populate::{created, looked_up};
+use crate::rpc::{auth_none, next_xid, program, RpcCall, RpcError, RpcReply};
+use crate::xdr::XdrDecoder;
+use bytes::BytesMut;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::cell::Cell;
+use std::future::Future;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Name of the shared file in the export's root
+pub const SHARED: &[u8] = b"nfs-fuzzer-mixed";
+
+/// Name the rename interaction gives it
+pub const RENAMED: &[u8] = b"mixed-renamed";
+
+#[derive(Error, Debug)]
+pub enum MixedError {
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error(transparent)]
+    Session(#[from] SessionError),
+    #[error("{op} failed with status {status}")]
+    Status { op: &'static str, status: u32 },
+    #[error("{0} reply does not decode")]
+    Decode(&'static str),
+}
+
+/// Which protocol a step goes out on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lane {
+    V3,
+    V4,
+}
+
+/// One step on the shared file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// v4 OPEN of the shared file with these share_access bits, want
+    /// flags included; produces the stateid later v4 steps use
+    Open {
+        access: u32,
+    },
+    Read {
+        offset: u64,
+        count: u32,
+    },
+    Write4 {
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// v4 GETATTR of size and change
+    Getattr,
+    DelegReturn,
+    Close,
+    Write3 {
+        offset: u64,
+        data: Vec<u8>,
+        stable: u32,
+    },
+    /// v3 SETATTR of size
+    Truncate {
+        size: u64,
+    },
+    Remove,
+    Rename {
+        to: Vec<u8>,
+    },
+}
+
+impl Action {
+    pub fn lane(&self) -> Lane {
+        match self {
+            Action::Write3 { .. }
+            | Action::Truncate { .. }
+            | Action::Remove
+            | Action::Rename { .. } => Lane::V3,
+            _ => Lane::V4,
+        }
+    }
+}
+
+/// The shared file as each version sees it, filled in at run time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedFile {
+    pub name: Vec<u8>,
+    /// v3 handles from MOUNT/LOOKUP
+    pub dir3: Vec<u8>,
+    pub file3: Vec<u8>,
+    /// v4 handles from LOOKUP/GETFH
+    pub dir4: Vec<u8>,
+    pub file4: Vec<u8>,
+    pub clientid: u64,
+    pub owner: Vec<u8>,
+}
+
+impl SharedFile {
+    /// v3 procedure and arguments of a v3 step
+    pub fn v3_call(&self, action: &Action) -> Option<(u32, Vec<u8>)> {
+        let call = match action {
+            Action::Write3 {
+                offset,
+                data,
+                stable,
+            } => nfsv3::write(&self.file3, *offset, data.len() as u32, *stable, data),
+            Action::Truncate { size } => {
+                let attrs = Sattr3 {
+                    size: Some(*size),
+                    ..Sattr3::default()
+                };
+                nfsv3::setattr(&self.file3, &attrs, None)
+            }
+            Action::Remove => nfsv3::remove(&self.dir3, &self.name),
+            Action::Rename { to } => nfsv3::rename(&self.dir3, &self.name, &self.dir3, to),
+            _ => return None,
+        };
+        Some((call.procedure, call.args))
+    }
+
+    /// COMPOUND ops of a v4 step; `stateid` is the one the interaction's
+    /// OPEN returned, or anonymous before it
+    pub fn v4_ops(&self, action: &Action, stateid: &Stateid) -> Option<Vec<Op>> {
+        let ops = match action {
+            Action::Open { access } => {
+                let mut open = Open::existing(self.clientid, &self.owner, &self.name);
+                open.share_access = *access;
+                vec![putfh(&self.dir4), open.op()]
+            }
+            Action::Read { offset, count } => {
+                vec![putfh(&self.file4), read(stateid, *offset, *count)]
+            }
+            Action::Write4 { offset, data } => vec![
+                putfh(&self.file4),
+                write(stateid, *offset, stable_how::FILE_SYNC, data),
+            ],
+            Action::Getattr => vec![
+                putfh(&self.file4),
+                getattr(&bitmap_from_bits(&[attr::CHANGE, attr::SIZE])),
+            ],
+            Action::DelegReturn => vec![putfh(&self.file4), delegreturn(stateid)],
+            Action::Close => vec![putfh(&self.file4), close(0, stateid)],
+            _ => return None,
+        };
+        Some(ops)
+    }
+}
+
+/// A cross-protocol interaction on one shared file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interaction {
+    pub name: &'static str,
+    pub steps: Vec<Action>,
+}
+
+/// Delegations, opens and cached data on the v4 side against v3
+/// modifications of the same file
+pub fn interactions() -> Vec<Interaction> {
+    use share_access::*;
+    use Action::*;
+    // Stamped so a bad read shows whose data it returned, and from where
+    let data = |tag: u8, offset| Pattern::Stamp { tag: tag.into() }.fill(offset, 512);
+    let w3 = |offset, tag, stable| Write3 {
+        offset,
+        data: data(tag, offset),
+        stable,
+    };
+    let read = Read {
+        offset: 0,
+        count: 4096,
+    };
+    vec![
+        Interaction {
+            name: "v3_write_under_read_deleg",
+            steps: vec![
+                Open {
+                    access: READ | WANT_READ_DELEG,
+                },
+                read.clone(),
+                w3(0, b'a', stable_how::FILE_SYNC),
+                Getattr,
+                read.clone(),
+                Close,
+            ],
+        },
+        Interaction {
+            name: "v3_write_under_write_deleg",
+            steps: vec![
+                Open {
+                    access: BOTH | WANT_WRITE_DELEG,
+                },
+                Write4 {
+                    offset: 0,
+                    data: data(b'b', 0),
+                },
+                w3(256, b'c', stable_how::FILE_SYNC),
+                read.clone(),
+                Getattr,
+                DelegReturn,
+                Close,
+            ],
+        },
+        Interaction {
+            name: "v3_unstable_write_then_v4_read",
+            steps: vec![
+                Open { access: READ },
+                w3(4096, b'd', stable_how::UNSTABLE),
+                Read {
+                    offset: 4096,
+                    count: 512,
+                },
+                Getattr,
+                Close,
+            ],
+        },
+        Interaction {
+            name: "v3_truncate_under_read_deleg",
+            steps: vec![
+                Open {
+                    access: READ | WANT_READ_DELEG,
+                },
+                read.clone(),
+                Truncate { size: 0 },
+                Getattr,
+                read.clone(),
+                Close,
+            ],
+        },
+        Interaction {
+            name: "v3_extend_under_write_deleg",
+            steps: vec![
+                Open {
+                    access: BOTH | WANT_WRITE_DELEG,
+                },
+                Truncate { size: 1 << 20 },
+                Write4 {
+                    offset: (1 << 20) - 16,
+                    data: data(b'e', (1 << 20) - 16),
+                },
+                Getattr,
+                Close,
+            ],
+        },
+        Interaction {
+            name: "v3_remove_open_file",
+            steps: vec![
+                Open {
+                    access: BOTH | WANT_ANY_DELEG,
+                },
+                Remove,
+                Write4 {
+                    offset: 0,
+                    data: data(b'f', 0),
+                },
+                read.clone(),
+                Close,
+            ],
+        },
+        Interaction {
+            name: "v3_rename_under_read_deleg",
+            steps: vec![
+                Open {
+                    access: READ | WANT_READ_DELEG,
+                },
+                Rename {
+                    to: RENAMED.to_vec(),
+                },
+                Getattr,
+                read,
+                Close,
+            ],
+        },
+    ]
+}
+
+/// A step order that keeps each lane's own order: indices into `steps`
+pub type Schedule = Vec<usize>;
+
+/// The written order first, then `n - 1` random merges of the v3 and v4
+/// lanes of `steps`
+pub fn interleavings<R: Rng + ?Sized>(rng: &mut R, steps: &[Action], n: usize) -> Vec<Schedule> {
+    let mut out: Vec<Schedule> = vec![(0..steps.len()).collect()];
+    let lanes: Vec<Lane> = steps.iter().map(Action::lane).collect();
+    for _ in 1..n {
+        let mut picks: Vec<Lane> = lanes.clone();
+        picks.shuffle(rng);
+        let mut next = [0usize, 0usize];
+        let schedule = picks
+            .into_iter()
+            .map(|lane| {
+                let k = lane as usize;
+                let index = (next[k]..steps.len()).find(|&i| lanes[i] == lane).unwrap();
+                next[k] = index + 1;
+                index
+            })
+            .collect();
+        if !out.contains(&schedule) {
+            out.push(schedule);
+        }
+    }
+    out
+}
+
+/// Run the v3 and v4 lanes of `steps` at the same time, each lane in its
+/// own order; returns each step's result in step order
+pub async fn run_concurrent<T, F3, F4>(
+    steps: &[Action],
+    mut v3: impl FnMut(usize, &Action) -> F3,
+    mut v4: impl FnMut(usize, &Action) -> F4,
+) -> Vec<T>
+where
+    F3: Future<Output = T>,
+    F4: Future<Output = T>,
+{
+    let lane = |lane: Lane| {
+        steps
+            .iter()
+            .enumerate()
+            .filter(move |(_, a)| a.lane() == lane)
+    };
+    let run3 = async {
+        let mut out = Vec::new();
+        for (i, action) in lane(Lane::V3) {
+            out.push((i, v3(i, action).await));
+        }
+        out
+    };
+    let run4 = async {
+        let mut out = Vec::new();
+        for (i, action) in lane(Lane::V4) {
+            out.push((i, v4(i, action).await));
+        }
+        out
+    };
+    let (a, b) = tokio::join!(run3, run4);
+    let mut all: Vec<(usize, T)> = a.into_iter().chain(b).collect();
+    all.sort_by_key(|(i, _)| *i);
+    all.into_iter().map(|(_, r)| r).collect()
+}
+
+/// A read or size that contradicts what the campaign wrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Incoherence {
+    Data { offset: u64, expected: u8, got: u8 },
+    Size { expected: u64, got: u64 },
+}
+
+/// What the shared file must contain, from the writes and truncations
+/// both lanes made; bytes never written are unknown
+#[derive(Debug, Clone, Default)]
+pub struct FileModel {
+    bytes: Vec<Option<u8>>,
+    /// Exact size, known once the file was truncated
+    size: Option<u64>,
+}
+
+impl FileModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, offset: u64, data: &[u8]) {
+        let end = offset as usize + data.len();
+        if self.bytes.len() < end {
+            self.bytes.resize(end, None);
+        }
+        for (slot, &b) in self.bytes[offset as usize..end].iter_mut().zip(data) {
+            *slot = Some(b);
+        }
+        self.size = self.size.map(|s| s.max(end as u64));
+    }
+
+    pub fn truncate(&mut self, size: u64) {
+        let old = self.size;
+        self.bytes.resize(size as usize, None);
+        // Bytes past the old end read back as zeros
+        if let Some(old) = old {
+            for b in self.bytes.iter_mut().skip(old as usize) {
+                *b = Some(0);
+            }
+        }
+        self.size = Some(size);
+    }
+
+    /// Apply a step that modifies the file
+    pub fn apply(&mut self, action: &Action) {
+        match action {
+            Action::Write3 { offset, data, .. } | Action::Write4 { offset, data } => {
+                self.write(*offset, data)
+            }
+            Action::Truncate { size } => self.truncate(*size),
+            _ => {}
+        }
+    }
+
+    /// Take in a change that may or may not have happened: what it
+    /// touched is no longer known
+    pub fn unsure(&mut self, action: &Action) {
+        match action {
+            Action::Write3 { offset, data, .. } | Action::Write4 { offset, data } => {
+                let end = *offset as usize + data.len();
+                for b in self.bytes.iter_mut().take(end).skip(*offset as usize) {
+                    *b = None;
+                }
+                if self.size.is_some_and(|s| s < end as u64) {
+                    self.size = None;
+                }
+            }
+            Action::Truncate { size } => {
+                self.bytes.truncate(*size as usize);
+                self.size = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Check `data` read at `offset`; only the first mismatch is reported
+    pub fn check_read(&self, offset: u64, data: &[u8]) -> Option<Incoherence> {
+        if let Some(size) = self.size {
+            let end = offset + data.len() as u64;
+            if end > size {
+                return Some(Incoherence::Size {
+                    expected: size,
+                    got: end,
+                });
+            }
+        }
+        data.iter().enumerate().find_map(|(i, &got)| {
+            let at = offset + i as u64;
+            match self.bytes.get(at as usize) {
+                Some(Some(expected)) if *expected != got => Some(Incoherence::Data {
+                    offset: at,
+                    expected: *expected,
+                    got,
+                }),
+                _ => None,
+            }
+        })
+    }
+
+    /// Check a size from GETATTR
+    pub fn check_size(&self, got: u64) -> Option<Incoherence> {
+        let written = self.bytes.len() as u64;
+        match self.size {
+            Some(expected) if expected != got => Some(Incoherence::Size { expected, got }),
+            None if got < written => Some(Incoherence::Size {
+                expected: written,
+                got,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// A step as it went: when it was sent and when it was answered, in
+/// ticks of a clock both lanes share, and its results if a reply came
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timed {
+    pub sent: u64,
+    pub answered: u64,
+    pub results: Option<Vec<u8>>,
+}
+
+impl Timed {
+    /// Whether the reply came with an OK status
+    pub fn succeeded(&self) -> bool {
+        let status = self.results.as_ref().and_then(|r| r.get(..4));
+        status == Some(&[0; 4])
+    }
+
+    fn overlaps(&self, other: &Timed) -> bool {
+        self.sent < other.answered && other.sent < self.answered
+    }
+}
+
+/// What the v4 reads and GETATTRs of `timed` saw that no order of the
+/// steps allows, on a file [`Sides::share`] left empty. A change that
+/// succeeded and was answered before a check went out must show in it;
+/// one in flight alongside the check, one racing another change, or one
+/// whose reply never came may or may not.
+pub fn check(steps: &[Action], timed: &[Timed]) -> Vec<(usize, Incoherence)> {
+    let changes: Vec<(&Action, &Timed)> = steps
+        .iter()
+        .zip(timed)
+        .filter(|(a, _)| {
+            matches!(
+                a,
+                Action::Write3 { .. } | Action::Write4 { .. } | Action::Truncate { .. }
+            )
+        })
+        .collect();
+    let mut found = Vec::new();
+    for (i, (action, t)) in steps.iter().zip(timed).enumerate() {
+        let Some(results) = &t.results else {
+            continue;
+        };
+        if !matches!(action, Action::Read { .. } | Action::Getattr) {
+            continue;
+        }
+        let done = |c: &Timed| c.succeeded() && c.answered < t.sent;
+        let mut before: Vec<&(&Action, &Timed)> = changes.iter().filter(|(_, c)| done(c)).collect();
+        before.sort_by_key(|(_, c)| c.answered);
+        let mut model = FileModel::new();
+        model.truncate(0);
+        for (change, _) in &before {
+            model.apply(change);
+        }
+        for (change, c) in &changes {
+            let unsure = match done(c) {
+                true => before
+                    .iter()
+                    .any(|(_, d)| !std::ptr::eq(*d, *c) && d.overlaps(c)),
+                false => (c.results.is_none() || c.succeeded()) && c.sent < t.answered,
+            };
+            if unsure {
+                model.unsure(change);
+            }
+        }
+        let incoherence = match action {
+            Action::Read { offset, .. } => {
+                read_data(results).and_then(|data| model.check_read(*offset, &data))
+            }
+            _ => getattr_size(results).and_then(|size| model.check_size(size)),
+        };
+        found.extend(incoherence.map(|x| (i, x)));
+    }
+    found
+}
+
+/// The decoder at the results of the `opcode` that follows PUTFH, and
+/// SEQUENCE if there is one, in COMPOUND4res, if all succeeded
+fn op_results(results: &[u8], opcode: u32) -> Option<XdrDecoder<'_>> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    for _ in 0..dec.get_u32().ok()? {
+        let this = dec.get_u32().ok()?;
+        if dec.get_u32().ok()? != status::NFS4_OK {
+            return None;
+        }
+        match this {
+            op::SEQUENCE => {
+                dec.get_opaque_fixed(36).ok()?;
+            }
+            op::PUTFH => {}
+            _ if this == opcode => return Some(dec),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The data of a READ step
+fn read_data(results: &[u8]) -> Option<Vec<u8>> {
+    let mut dec = op_results(results, op::READ)?;
+    dec.get_bool().ok()?;
+    Some(dec.get_opaque().ok()?.to_vec())
+}
+
+/// The size a GETATTR step read back
+fn getattr_size(results: &[u8]) -> Option<u64> {
+    let mut dec = op_results(results, op::GETATTR)?;
+    let mut mask = Vec::new();
+    for _ in 0..dec.get_u32().ok()? {
+        mask.push(dec.get_u32().ok()?);
+    }
+    let mut vals = XdrDecoder::new(dec.get_opaque().ok()?);
+    for bit in bits_of_bitmap(&mask) {
+        match bit {
+            attr::CHANGE => {
+                vals.get_u64().ok()?;
+            }
+            attr::SIZE => return vals.get_u64().ok(),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The record-marked v4.1 compound of `ops` on `session`
+fn compound(session: &SlotTable, ops: Vec<Op>, identity: &Identity) -> BytesMut {
+    let args = CompoundBuilder::new(minor_version::V4_1)
+        .with_tag(b"mixed")
+        .ops(ops)
+        .build();
+    let args = session.stamp(&args).unwrap_or(args);
+    RpcCall::new(next_xid(), program::NFS, 4, PROC_COMPOUND, true)
+        .with_auth(&identity.credential(), &auth_none())
+        .with_args(&args)
+        .build()
+}
+
+async fn results<S>(conn: &mut NfsConnection<S>, msg: &[u8]) -> Result<Vec<u8>, MixedError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let reply = conn.call(msg).await?;
+    Ok(RpcReply::parse(&reply)?.into_results()?.to_vec())
+}
+
+/// The two connections interactions run over, v4 on a session of its own
+pub struct Sides<S = TcpStream> {
+    pub v3: NfsConnection<S>,
+    pub v4: NfsConnection<S>,
+    pub session: SlotTable,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Sides<S> {
+    /// Set up a session for `owner` on `v4`
+    pub async fn new(
+        v3: NfsConnection<S>,
+        mut v4: NfsConnection<S>,
+        owner: &ClientOwner,
+        identity: &Identity,
+    ) -> Result<Self, MixedError> {
+        let session = session::establish(&mut v4, owner, identity).await?;
+        Ok(Self { v3, v4, session })
+    }
+
+    async fn call3(
+        &mut self,
+        call: nfsv3::Call,
+        identity: &Identity,
+    ) -> Result<Vec<u8>, MixedError> {
+        results(&mut self.v3, &call.message(identity)).await
+    }
+
+    /// Empty the shared file in `dir3`, the export's root, creating it if
+    /// need be, and find it from both sides; v4 looks it up under
+    /// `export` from the pseudo-root
+    pub async fn share(
+        &mut self,
+        identity: &Identity,
+        dir3: &[u8],
+        export: &str,
+    ) -> Result<SharedFile, MixedError> {
+        let how = CreateHow::Unchecked(Sattr3 {
+            size: Some(0),
+            ..Sattr3::mode(0o644)
+        });
+        let made = self
+            .call3(nfsv3::create(dir3, SHARED, &how), identity)
+            .await?;
+        let file3 = match created(&made).ok_or(MixedError::Decode("CREATE"))? {
+            (nfsv3::stat::OK, Some(fh)) => fh,
+            (nfsv3::stat::OK, None) => {
+                let found = self.call3(nfsv3::lookup(dir3, SHARED), identity).await?;
+                match looked_up(&found).ok_or(MixedError::Decode("LOOKUP"))? {
+                    (_, Some(fh)) => fh,
+                    (status, None) => {
+                        return Err(MixedError::Status {
+                            op: "LOOKUP",
+                            status,
+                        })
+                    }
+                }
+            }
+            (status, _) => {
+                return Err(MixedError::Status {
+                    op: "CREATE",
+                    status,
+                })
+            }
+        };
+        let mut ops = vec![putrootfh()];
+        let components = export.split('/').filter(|c| !c.is_empty());
+        ops.extend(components.map(|c| lookup(c.as_bytes())));
+        ops.extend([getfh(), lookup(SHARED), getfh()]);
+        let msg = compound(&self.session, ops, identity);
+        let found = results(&mut self.v4, &msg).await?;
+        self.session.record(&found);
+        let mut state = SessionState::default();
+        state.observe(&found);
+        let [dir4, file4]: [Vec<u8>; 2] = state.filehandles.try_into().map_err(|_| {
+            let status = XdrDecoder::new(&found).get_u32().unwrap_or(0);
+            MixedError::Status {
+                op: "LOOKUP",
+                status,
+            }
+        })?;
+        Ok(SharedFile {
+            name: SHARED.to_vec(),
+            dir3: dir3.to_vec(),
+            file3,
+            dir4,
+            file4,
+            clientid: self.session.clientid,
+            owner: b"mixed".to_vec(),
+        })
+    }
+
+    /// Run `steps` on `file` with the two lanes free-running, each on its
+    /// own connection; each step's timing and results, in step order. v4
+    /// steps use the stateid the latest OPEN returned, DELEGRETURN the
+    /// delegation.
+    pub async fn run(
+        &mut self,
+        identity: &Identity,
+        file: &SharedFile,
+        steps: &[Action],
+    ) -> Vec<Timed> {
+        let clock = Cell::new(0);
+        let tick = &|| clock.replace(clock.get() + 1);
+        let v3 = &Mutex::new(&mut self.v3);
+        let v4 = &Mutex::new((&mut self.v4, &mut self.session, SessionState::default()));
+        run_concurrent(
+            steps,
+            |_, action| {
+                let call = file.v3_call(action);
+                async move {
+                    let Some((procedure, args)) = call else {
+                        return Timed::default();
+                    };
+                    let msg = nfsv3::Call::with_args(procedure, args).message(identity);
+                    let mut conn = v3.lock().await;
+                    let sent = tick();
+                    let results = results(&mut conn, &msg).await.ok();
+                    Timed {
+                        sent,
+                        answered: tick(),
+                        results,
+                    }
+                }
+            },
+            |_, action| {
+                let action = action.clone();
+                async move {
+                    let mut v4 = v4.lock().await;
+                    let (conn, session, state) = &mut *v4;
+                    let stateid = match action {
+                        Action::DelegReturn => state.delegations.last(),
+                        _ => state.opens.last(),
+                    };
+                    let stateid = stateid.copied().unwrap_or(Stateid::ANONYMOUS);
+                    let Some(ops) = file.v4_ops(&action, &stateid) else {
+                        return Timed::default();
+                    };
+                    let msg = compound(session, ops, identity);
+                    let sent = tick();
+                    let results = results(conn, &msg).await.ok();
+                    let answered = tick();
+                    if let Some(r) = &results {
+                        session.record(r);
+                        state.observe(r);
+                    }
+                    Timed {
+                        sent,
+                        answered,
+                        results,
+                    }
+                }
+            },
+        )
+        .await
+    }
+
+    /// Remove the shared file from `dir3`, under both names it goes by
+    pub async fn unshare(&mut self, identity: &Identity, dir3: &[u8]) -> Result<(), MixedError> {
+        for name in [SHARED, RENAMED] {
+            self.call3(nfsv3::remove(dir3, name), identity).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::put_bitmap;
+    use crate::xdr::XdrEncoder;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_interleavings_keep_lane_order() {
+        let steps = &interactions()[1].steps;
+        let mut rng = StdRng::seed_from_u64(3);
+        let schedules = interleavings(&mut rng, steps, 20);
+        assert_eq!(schedules[0], (0..steps.len()).collect::<Vec<_>>());
+        assert!(schedules.len() > 1);
+        for s in &schedules {
+            let mut sorted = s.clone();
+            sorted.sort();
+            assert_eq!(sorted, (0..steps.len()).collect::<Vec<_>>());
+            for lane in [Lane::V3, Lane::V4] {
+                let order: Vec<usize> = s
+                    .iter()
+                    .copied()
+                    .filter(|&i| steps[i].lane() == lane)
+                    .collect();
+                assert!(order.windows(2).all(|w| w[0] < w[1]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_file_model() {
+        let mut m = FileModel::new();
+        m.apply(&Action::Write3 {
+            offset: 4,
+            data: b"abcd".to_vec(),
+            stable: stable_how::FILE_SYNC,
+        });
+        assert_eq!(m.check_read(0, b"\0\0\0\0abcd"), None);
+        assert_eq!(
+            m.check_read(4, b"abXd"),
+            Some(Incoherence::Data {
+                offset: 6,
+                expected: b'c',
+                got: b'X'
+            })
+        );
+        assert!(m.check_size(7).is_some() && m.check_size(100).is_none());
+        m.apply(&Action::Truncate { size: 6 });
+        assert_eq!(
+            m.check_size(8),
+            Some(Incoherence::Size {
+                expected: 6,
+                got: 8
+            })
+        );
+        assert!(m.check_read(4, b"abc").is_some());
+        m.truncate(10);
+        assert_eq!(m.check_read(4, b"ab\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn test_check_goes_by_what_was_answered_first() {
+        let steps = [
+            Action::Write3 {
+                offset: 0,
+                data: b"abcd".to_vec(),
+                stable: stable_how::FILE_SYNC,
+            },
+            Action::Read {
+                offset: 0,
+                count: 4,
+            },
+            Action::Getattr,
+        ];
+        // PUTFH, then READ or GETATTR of size
+        let reply = |opcode: u32, body: &dyn Fn(&mut XdrEncoder)| {
+            let mut enc = XdrEncoder::new();
+            for w in [
+                status::NFS4_OK,
+                0,
+                2,
+                op::PUTFH,
+                status::NFS4_OK,
+                opcode,
+                status::NFS4_OK,
+            ] {
+                enc.put_u32(w);
+            }
+            body(&mut enc);
+            Some(enc.as_bytes().to_vec())
+        };
+        let timed = |(sent, answered), read: &[u8], size: u64| {
+            let read = reply(op::READ, &|enc| {
+                enc.put_bool(true);
+                enc.put_opaque(read);
+            });
+            let size = reply(op::GETATTR, &|enc| {
+                put_bitmap(enc, &bitmap_from_bits(&[attr::SIZE]));
+                enc.put_opaque(&size.to_be_bytes());
+            });
+            vec![
+                Timed {
+                    sent,
+                    answered,
+                    results: Some(vec![0; 4]),
+                },
+                Timed {
+                    sent: 2,
+                    answered: 3,
+                    results: read,
+                },
+                Timed {
+                    sent: 4,
+                    answered: 5,
+                    results: size,
+                },
+            ]
+        };
+        // Answered before the read went out, so both checks see it
+        assert!(check(&steps, &timed((0, 1), b"abcd", 4)).is_empty());
+        assert_eq!(
+            check(&steps, &timed((0, 1), b"abXd", 2)),
+            vec![
+                (
+                    1,
+                    Incoherence::Data {
+                        offset: 2,
+                        expected: b'c',
+                        got: b'X'
+                    }
+                ),
+                (
+                    2,
+                    Incoherence::Size {
+                        expected: 4,
+                        got: 2
+                    }
+                ),
+            ]
+        );
+        // In flight alongside the read, so it may not have landed yet
+        assert!(check(&steps, &timed((1, 4), b"\0\0", 0)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_run_concurrent_and_encoding() {
+        let file = SharedFile {
+            name: b"shared".to_vec(),
+            dir3: vec![1; 8],
+            file3: vec![2; 8],
+            dir4: vec![3; 16],
+            file4: vec![4; 16],
+            clientid: 7,
+            owner: b"mixed".to_vec(),
+        };
+        let steps = &interactions()[0].steps;
+        let results = run_concurrent(
+            steps,
+            |i, a| {
+                let call = file.v3_call(a).map(|(p, _)| p);
+                async move { (i, Lane::V3, call) }
+            },
+            |i, a| {
+                let ops = file
+                    .v4_ops(a, &Stateid::ANONYMOUS)
+                    .map(|ops| ops.len() as u32);
+                async move { (i, Lane::V4, ops) }
+            },
+        )
+        .await;
+        assert_eq!(results.len(), steps.len());
+        for (i, (index, lane, out)) in results.into_iter().enumerate() {
+            assert_eq!((index, lane), (i, steps[i].lane()));
+            assert!(out.is_some());
+        }
+        assert_eq!(
+            file.v3_call(&Action::Truncate { size: 0 })
+                .map(|(p, a)| (p, a.len())),
+            Some((nfsv3::proc::SETATTR, 12 + 4 * 4 + 8 + 4 + 4 + 4))
+        );
+    }
+}
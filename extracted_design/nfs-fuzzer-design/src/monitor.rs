@@ -0,0 +1,226 @@
+//! Kernel log monitoring of the target
+//!
+//! The health probe only notices a server that stopped answering. A
+//! kernel that logs a WARN, a KASAN report or an oops in a worker thread
+//! and carries on looks healthy from the network, and those are often
+//! the first sign of memory corruption. The monitor follows the target's
+//! kernel log over `ssh` for the whole campaign, `dmesg --follow-new` by
+//! default (`journalctl -kf -n 0` does as well), and passes on every line
+//! carrying one of [`CRASH_MARKS`] with the time it arrived. The fuzzer
+//! pins those to the cases sent in the [`LOOKBACK`] before them, since a
+//! line is logged while the request is handled or soon after.
+
+use crate::client::CRASH_MARKS;
+use crate::controller::Ssh;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{self, error::TryRecvError};
+
+/// Command run on the target when none is given; follows the log from
+/// now on, skipping what was in the ring buffer
+pub const DEFAULT_COMMAND: &str = "dmesg --follow-new";
+
+/// How long before a kernel line arrived a case can have caused it
+pub const LOOKBACK: Duration = Duration::from_secs(2);
+
+/// How long a trial waits for the kernel to log after its last case
+pub const SETTLE: Duration = Duration::from_millis(500);
+
+#[derive(Error, Debug)]
+pub enum MonitorError {
+    #[error("could not start the kernel log monitor: {0}")]
+    Spawn(std::io::Error),
+    #[error("kernel log monitor exited")]
+    Closed,
+}
+
+/// Where and how the kernel log is followed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorConfig {
+    pub ssh: Ssh,
+    /// Command line following the log on the target
+    pub command: String,
+}
+
+/// `host=H[,port=N][,key=PATH][,command=CMD]`
+impl FromStr for MonitorConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ssh = None;
+        let (mut port, mut key, mut command) = (None, None, DEFAULT_COMMAND.to_string());
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let (k, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", part))?;
+            match k {
+                "host" => ssh = Some(Ssh::new(value)),
+                "port" => port = Some(value.parse().map_err(|_| format!("bad port {:?}", value))?),
+                "key" => key = Some(PathBuf::from(value)),
+                "command" => command = value.to_string(),
+                _ => return Err(format!("unknown monitor option {:?}", k)),
+            }
+        }
+        let mut ssh = ssh.ok_or("monitor needs host=")?;
+        ssh.port = port;
+        ssh.key = key;
+        Ok(Self { ssh, command })
+    }
+}
+
+/// A kernel log line carrying a crash mark
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelEvent {
+    /// When the line reached the fuzzer
+    pub at: Instant,
+    pub line: String,
+}
+
+/// A running kernel log follower
+#[derive(Debug)]
+pub struct Monitor {
+    config: Option<MonitorConfig>,
+    _child: Child,
+    events: mpsc::UnboundedReceiver<KernelEvent>,
+}
+
+impl Monitor {
+    /// Follow the kernel log of `config`'s target
+    pub fn start(config: &MonitorConfig) -> Result<Self, MonitorError> {
+        let monitor = Self::spawn(Command::from(config.ssh.command(&config.command)))?;
+        Ok(Self {
+            config: Some(config.clone()),
+            ..monitor
+        })
+    }
+
+    /// Follow the log again on a new connection, as after the target
+    /// rebooted; lines not yet drained are lost
+    pub fn reopen(&mut self) -> Result<(), MonitorError> {
+        if let Some(config) = self.config.clone() {
+            *self = Self::start(&config)?;
+        }
+        Ok(())
+    }
+
+    fn spawn(mut cmd: Command) -> Result<Self, MonitorError> {
+        let mut child = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(MonitorError::Spawn)?;
+        let stdout = child.stdout.take().ok_or(MonitorError::Closed)?;
+        let (tx, events) = mpsc::unbounded_channel();
+        // Ends when the follower exits, which dropping the child makes it
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if !CRASH_MARKS.iter().any(|m| line.contains(m)) {
+                    continue;
+                }
+                let event = KernelEvent {
+                    at: Instant::now(),
+                    line,
+                };
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            config: None,
+            _child: child,
+            events,
+        })
+    }
+
+    /// The events that arrived since the last call; an error once the
+    /// follower has exited and everything it sent was taken
+    pub fn drain(&mut self) -> Result<Vec<KernelEvent>, MonitorError> {
+        let mut out = Vec::new();
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => out.push(event),
+                Err(TryRecvError::Empty) => return Ok(out),
+                Err(TryRecvError::Disconnected) if out.is_empty() => {
+                    return Err(MonitorError::Closed)
+                }
+                Err(TryRecvError::Disconnected) => return Ok(out),
+            }
+        }
+    }
+}
+
+/// Indices into `sent` (send times, oldest first) of the cases that may
+/// have caused lines arriving from `first` to `last`: those sent in the
+/// [`LOOKBACK`] before `first` and up to `last`, or failing that the last
+/// one sent before `last`
+pub fn correlate(sent: &[Instant], first: Instant, last: Instant) -> Vec<usize> {
+    let from = first.checked_sub(LOOKBACK).unwrap_or(first);
+    let batch: Vec<usize> = (0..sent.len())
+        .filter(|&i| sent[i] >= from && sent[i] <= last)
+        .collect();
+    if !batch.is_empty() {
+        return batch;
+    }
+    sent.iter().rposition(|&t| t <= last).into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_and_correlate() {
+        let config: MonitorConfig = "host=root@nfs1,key=/k,command=journalctl -kf -n 0"
+            .parse()
+            .unwrap();
+        assert_eq!(config.ssh.host, "root@nfs1");
+        assert_eq!(config.ssh.key, Some(PathBuf::from("/k")));
+        assert_eq!(config.command, "journalctl -kf -n 0");
+        let config: MonitorConfig = "host=nfs1".parse().unwrap();
+        assert_eq!(config.command, DEFAULT_COMMAND);
+        assert!("key=/k".parse::<MonitorConfig>().is_err());
+        assert!("host=h,lines=3".parse::<MonitorConfig>().is_err());
+
+        let t0 = Instant::now();
+        let at = |ms: u64| t0 + Duration::from_millis(ms);
+        let sent = [at(0), at(1000), at(2500), at(3000), at(9000)];
+        // A line at 4s: the cases from 2s on, not the one after it
+        assert_eq!(correlate(&sent, at(4000), at(4000)), [2, 3]);
+        assert_eq!(correlate(&sent, at(4000), at(9500)), [2, 3, 4]);
+        // Nothing recent: the last case before the line
+        assert_eq!(correlate(&sent, at(8000), at(8000)), [3]);
+        assert!(correlate(&sent[..0], at(1), at(1)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_monitor_passes_on_marked_lines() {
+        let mut follower = Command::new("sh");
+        follower.args([
+            "-c",
+            "echo '[ 12.5] nfsd: starting'; \
+             echo '[ 13.1] WARNING: CPU: 1 PID: 912 at fs/nfsd/nfs4state.c:1234'; \
+             echo '[ 13.2] BUG: KASAN: slab-out-of-bounds in nfsd4_encode_fattr4'",
+        ]);
+        let mut monitor = Monitor::spawn(follower).unwrap();
+        let mut lines = Vec::new();
+        loop {
+            match monitor.drain() {
+                Ok(events) => lines.extend(events.into_iter().map(|e| e.line)),
+                Err(MonitorError::Closed) => break,
+                Err(e) => panic!("{}", e),
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("WARNING: CPU: 1"));
+        assert!(lines[1].contains("KASAN"));
+    }
+}
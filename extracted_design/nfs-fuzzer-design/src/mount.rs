@@ -0,0 +1,248 @@
+//! MOUNT v3 client (RFC 1813 Appendix I)
+//!
+//! NFSv3 has no way to look up a root, so the first real file handle
+//! comes from MNT on an export path. These are the calls that get one,
+//! list what is mounted and exported, and give mounts back, together
+//! with decoders for their replies.
+
+use crate::exports::{self, Export, ExportsError};
+use crate::nfsv3::FHSIZE;
+use crate::rpc::{
+    program, Accepted, Program, ProgramCall, Rejected, ReplyStatus, RpcError, RpcReply,
+};
+use crate::xdr::{XdrDecoder, XdrError};
+use thiserror::Error;
+
+/// MOUNT protocol version carrying v3 handles
+pub const VERSION: u32 = 3;
+
+/// MNTPATHLEN: longest path MNT and UMNT take
+pub const MNTPATHLEN: usize = exports::MNTPATHLEN;
+
+/// MNTNAMLEN: longest host name in a mount list
+pub const MNTNAMLEN: usize = 255;
+
+/// Procedure numbers
+pub mod proc {
+    pub const NULL: u32 = 0;
+    pub const MNT: u32 = 1;
+    pub const DUMP: u32 = 2;
+    pub const UMNT: u32 = 3;
+    pub const UMNTALL: u32 = 4;
+    pub const EXPORT: u32 = 5;
+}
+
+/// MNT reply statuses (mountstat3)
+pub mod stat {
+    pub const OK: u32 = 0;
+    pub const PERM: u32 = 1;
+    pub const NOENT: u32 = 2;
+    pub const IO: u32 = 5;
+    pub const ACCES: u32 = 13;
+    pub const NOTDIR: u32 = 20;
+    pub const INVAL: u32 = 22;
+    pub const NAMETOOLONG: u32 = 63;
+    pub const NOTSUPP: u32 = 10004;
+    pub const SERVERFAULT: u32 = 10006;
+
+    pub fn name(stat: u32) -> Option<&'static str> {
+        Some(match stat {
+            OK => "MNT3_OK",
+            PERM => "MNT3ERR_PERM",
+            NOENT => "MNT3ERR_NOENT",
+            IO => "MNT3ERR_IO",
+            ACCES => "MNT3ERR_ACCES",
+            NOTDIR => "MNT3ERR_NOTDIR",
+            INVAL => "MNT3ERR_INVAL",
+            NAMETOOLONG => "MNT3ERR_NAMETOOLONG",
+            NOTSUPP => "MNT3ERR_NOTSUPP",
+            SERVERFAULT => "MNT3ERR_SERVERFAULT",
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MountError {
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error("MOUNT call not accepted: {0:?}")]
+    NotAccepted(Accepted),
+    #[error("MOUNT call denied: {0:?}")]
+    Denied(Rejected),
+    #[error("MNT failed: {}", stat::name(*.0).map_or_else(|| .0.to_string(), String::from))]
+    Status(u32),
+    #[error("MNT reply: {0}")]
+    Xdr(#[from] XdrError),
+    #[error(transparent)]
+    Exports(#[from] ExportsError),
+}
+
+/// A successful MNT: the export's root handle and the auth flavors the
+/// server will take with it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mounted {
+    pub fh: Vec<u8>,
+    pub auth_flavors: Vec<u32>,
+}
+
+/// One mountbody of a DUMP reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountEntry {
+    pub hostname: Vec<u8>,
+    pub directory: Vec<u8>,
+}
+
+/// The MOUNT program, at [`VERSION`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount;
+
+impl Program for Mount {
+    const PROGRAM: u32 = program::MOUNT;
+    const VERSION: u32 = VERSION;
+}
+
+/// One MOUNT call: the procedure and its encoded arguments
+pub type Call = ProgramCall<Mount>;
+
+pub fn null() -> Call {
+    Call::new(proc::NULL, |_| {})
+}
+
+/// MNT `path`; the path is sent as given, however long
+pub fn mnt(path: &[u8]) -> Call {
+    Call::new(proc::MNT, |enc| enc.put_opaque(path))
+}
+
+pub fn dump() -> Call {
+    Call::new(proc::DUMP, |_| {})
+}
+
+pub fn umnt(path: &[u8]) -> Call {
+    Call::new(proc::UMNT, |enc| enc.put_opaque(path))
+}
+
+pub fn umntall() -> Call {
+    Call::new(proc::UMNTALL, |_| {})
+}
+
+pub fn export() -> Call {
+    Call::new(proc::EXPORT, |_| {})
+}
+
+/// The results of a reply to any MOUNT call, once it was accepted
+pub fn results(reply: &[u8]) -> Result<&[u8], MountError> {
+    let reply = RpcReply::parse(reply)?;
+    match reply.status {
+        ReplyStatus::Accepted {
+            stat: Accepted::Success,
+            ..
+        } => Ok(reply.results),
+        ReplyStatus::Accepted { stat, .. } => Err(MountError::NotAccepted(stat)),
+        ReplyStatus::Denied(why) => Err(MountError::Denied(why)),
+    }
+}
+
+/// Decode MNT results (mountres3). Handles longer than FHSIZE3 are an
+/// error: a server sending one is already worth a finding.
+pub fn decode_mnt(results: &[u8]) -> Result<Mounted, MountError> {
+    let mut dec = XdrDecoder::new(results);
+    match dec.get_u32()? {
+        stat::OK => {}
+        status => return Err(MountError::Status(status)),
+    }
+    let fh = dec.get_opaque_max(FHSIZE)?.to_vec();
+    let count = dec.get_u32()?;
+    // Stops at the first missing flavor, however large the count
+    let auth_flavors = (0..count)
+        .map(|_| dec.get_u32())
+        .collect::<Result<_, _>>()?;
+    Ok(Mounted { fh, auth_flavors })
+}
+
+/// Decode DUMP results (a linked list of mountbody)
+pub fn decode_dump(results: &[u8]) -> Result<Vec<MountEntry>, MountError> {
+    let mut dec = XdrDecoder::new(results);
+    let mut entries = Vec::new();
+    while dec.get_bool()? {
+        entries.push(MountEntry {
+            hostname: dec.get_opaque()?.to_vec(),
+            directory: dec.get_opaque()?.to_vec(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Decode EXPORT results, see [`exports::decode_export_list`]
+pub fn decode_export(results: &[u8]) -> Result<Vec<Export>, MountError> {
+    Ok(exports::decode_export_list(results)?)
+}
+
+/// The root handle in a reply to MNT
+pub fn root_filehandle(reply: &[u8]) -> Result<Mounted, MountError> {
+    decode_mnt(results(reply)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+    use crate::rpc::success_reply;
+    use crate::xdr::XdrEncoder;
+
+    #[test]
+    fn test_calls() {
+        let call = mnt(b"/export");
+        assert_eq!(call.procedure, proc::MNT);
+        assert_eq!(call.args, [&[0, 0, 0, 7][..], b"/export\0"].concat());
+        assert_eq!(umnt(b"/e").args, [0, 0, 0, 2, b'/', b'e', 0, 0]);
+        assert!(dump().args.is_empty() && export().args.is_empty());
+        let msg = call.message(&Identity::new(0, 0));
+        // Record mark, xid, CALL, rpcvers, then program and version
+        assert_eq!(msg[16..24], [0, 1, 0x86, 0xa5, 0, 0, 0, 3]);
+        assert!(msg.ends_with(b"/export\0"));
+    }
+
+    #[test]
+    fn test_root_filehandle() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(stat::OK);
+        enc.put_opaque(&[0xab; 32]);
+        enc.put_u32(2);
+        enc.put_u32(1);
+        enc.put_u32(6);
+        let mounted = root_filehandle(&success_reply(7, enc.as_bytes())).unwrap();
+        assert_eq!(mounted.fh, [0xab; 32]);
+        assert_eq!(mounted.auth_flavors, [1, 6]);
+
+        let denied = success_reply(7, &stat::ACCES.to_be_bytes());
+        let err = root_filehandle(&denied).unwrap_err();
+        assert_eq!(err, MountError::Status(stat::ACCES));
+        assert_eq!(err.to_string(), "MNT failed: MNT3ERR_ACCES");
+
+        let mut long = XdrEncoder::new();
+        long.put_u32(stat::OK);
+        long.put_opaque(&[0; FHSIZE + 1]);
+        assert!(matches!(
+            decode_mnt(long.as_bytes()),
+            Err(MountError::Xdr(XdrError::TooLong { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_decode_dump() {
+        let mut enc = XdrEncoder::new();
+        enc.put_bool(true);
+        enc.put_opaque(b"client1");
+        enc.put_opaque(b"/srv");
+        enc.put_bool(false);
+        assert_eq!(
+            decode_dump(enc.as_bytes()).unwrap(),
+            [MountEntry {
+                hostname: b"client1".to_vec(),
+                directory: b"/srv".to_vec()
+            }]
+        );
+        assert!(decode_dump(&[0, 0, 0, 1]).is_err());
+    }
+}
@@ -0,0 +1,760 @@
+//! Byte-level mutation of encoded RPC arguments
+//!
+//! A [`Mutator`] changes an encoded payload in place and describes what
+//! it did as a lineage [`Step`], so every variant can be traced back to
+//! its seed. An [`Engine`] holds a weighted set of mutators and picks one
+//! per mutation; the built-in ones are selected and weighted by name with
+//! a spec such as `bitflip=4,havoc=2,interesting`, and callers can add
+//! their own.
+//!
+//! XDR aligns everything to four bytes, so the block mutators move whole
+//! words: a duplicated or deleted block keeps the fields after it aligned
+//! and the server's decoder sees shifted but well-formed items rather
+//! than a stream it rejects at the first misaligned length. The `fields`
+//! mutator goes further and changes one XDR field of the payload's
+//! [`Message`] layout at a time, and the `sequence` mutator changes the
+//! SEQUENCE that opens a v4.1 compound.
+//!
+//! With a [`Fixup`] mode the engine repairs the length headers a
+//! mutation broke as a side effect (see [`crate::fixup`]), so the server
+//! decodes past them to the field that was meant to change.
+
+use crate::fields::{Kind, Message};
+use crate::fixup::{self, Fixup};
+use crate::lineage::Step;
+use crate::nfsv4::session;
+use crate::nfsv4::BOUNDARY_COUNTS;
+use rand::{Rng, RngCore};
+use std::fmt;
+use std::str::FromStr;
+
+/// One way of mutating a payload
+pub trait Mutator: Send + Sync {
+    /// Strategy name recorded in the lineage and used in weight specs
+    fn name(&self) -> &'static str;
+
+    /// Mutate `data` in place; `None` when the mutator cannot apply to
+    /// it (too short for a block, say), leaving it untouched
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step>;
+}
+
+/// Flip one bit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BitFlip;
+
+impl Mutator for BitFlip {
+    fn name(&self) -> &'static str {
+        "bitflip"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        if data.is_empty() {
+            return None;
+        }
+        let offset = rng.gen_range(0..data.len());
+        let bit = rng.gen_range(0..8);
+        data[offset] ^= 1 << bit;
+        Some(
+            Step::new(self.name())
+                .with("offset", offset)
+                .with("bit", bit),
+        )
+    }
+}
+
+/// Overwrite up to `max` bytes anywhere with random values
+#[derive(Debug, Clone, Copy)]
+pub struct Havoc {
+    pub max: usize,
+}
+
+impl Default for Havoc {
+    fn default() -> Self {
+        Self { max: 8 }
+    }
+}
+
+impl Mutator for Havoc {
+    fn name(&self) -> &'static str {
+        "havoc"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        if data.is_empty() {
+            return None;
+        }
+        let n = rng.gen_range(1..=self.max.max(1));
+        let mut offsets: Vec<usize> = (0..n).map(|_| rng.gen_range(0..data.len())).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        for &o in &offsets {
+            data[o] = rng.gen();
+        }
+        Some(Step::new(self.name()).with("offsets", offsets))
+    }
+}
+
+/// Values around sign bits and type limits, by width in bytes
+pub fn interesting_values(width: usize) -> Vec<u64> {
+    match width {
+        1 => vec![0, 1, 0x7f, 0x80, 0xff],
+        2 => vec![0, 1, 0x7f, 0x80, 0xff, 0x100, 0x7fff, 0x8000, 0xffff],
+        4 => BOUNDARY_COUNTS.iter().map(|&c| u64::from(c)).collect(),
+        _ => vec![
+            0,
+            1,
+            0xffff_ffff,
+            0x1_0000_0000,
+            i64::MAX as u64,
+            i64::MIN as u64,
+            u64::MAX - 1,
+            u64::MAX,
+        ],
+    }
+}
+
+/// Replace an integer with an interesting value, big-endian as XDR
+/// writes it; four- and eight-byte values land on word boundaries
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Interesting;
+
+impl Mutator for Interesting {
+    fn name(&self) -> &'static str {
+        "interesting"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let widths: Vec<usize> = [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&w| w <= data.len())
+            .collect();
+        let width = *widths.get(rng.gen_range(0..widths.len().max(1)))?;
+        let align = width.min(4);
+        let offset = rng.gen_range(0..=(data.len() - width) / align) * align;
+        let values = interesting_values(width);
+        let value = values[rng.gen_range(0..values.len())];
+        data[offset..offset + width].copy_from_slice(&value.to_be_bytes()[8 - width..]);
+        Some(
+            Step::new(self.name())
+                .with("offset", offset)
+                .with("width", width)
+                .with("value", value),
+        )
+    }
+}
+
+/// A random word-aligned block of up to `max_words` words of `data`, as
+/// (offset, length) in bytes
+fn word_block(rng: &mut dyn RngCore, data: &[u8], max_words: usize) -> Option<(usize, usize)> {
+    let words = data.len() / 4;
+    if words == 0 {
+        return None;
+    }
+    let start = rng.gen_range(0..words);
+    let len = rng.gen_range(1..=max_words.max(1).min(words - start));
+    Some((start * 4, len * 4))
+}
+
+/// Copy a block of words and insert the copy at another word boundary
+#[derive(Debug, Clone, Copy)]
+pub struct Duplicate {
+    pub max_words: usize,
+}
+
+impl Default for Duplicate {
+    fn default() -> Self {
+        Self { max_words: 16 }
+    }
+}
+
+impl Mutator for Duplicate {
+    fn name(&self) -> &'static str {
+        "duplicate"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let (offset, len) = word_block(rng, data, self.max_words)?;
+        let at = rng.gen_range(0..=data.len() / 4) * 4;
+        let block = data[offset..offset + len].to_vec();
+        data.splice(at..at, block);
+        Some(
+            Step::new(self.name())
+                .with("offset", offset)
+                .with("len", len)
+                .with("at", at),
+        )
+    }
+}
+
+/// Remove a block of words
+#[derive(Debug, Clone, Copy)]
+pub struct Delete {
+    pub max_words: usize,
+}
+
+impl Default for Delete {
+    fn default() -> Self {
+        Self { max_words: 16 }
+    }
+}
+
+impl Mutator for Delete {
+    fn name(&self) -> &'static str {
+        "delete"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let (offset, len) = word_block(rng, data, self.max_words)?;
+        data.drain(offset..offset + len);
+        Some(
+            Step::new(self.name())
+                .with("offset", offset)
+                .with("len", len),
+        )
+    }
+}
+
+/// Cut the payload short at any byte
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Truncate;
+
+impl Mutator for Truncate {
+    fn name(&self) -> &'static str {
+        "truncate"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        if data.is_empty() {
+            return None;
+        }
+        let len = rng.gen_range(0..data.len());
+        data.truncate(len);
+        Some(Step::new(self.name()).with("len", len))
+    }
+}
+
+/// Append up to 16 random bytes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Append;
+
+impl Mutator for Append {
+    fn name(&self) -> &'static str {
+        "append"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let len = rng.gen_range(1..=16);
+        data.extend((0..len).map(|_| rng.gen::<u8>()));
+        Some(Step::new(self.name()).with("len", len))
+    }
+}
+
+/// Change one field of the payload's XDR layout: a length header or
+/// array count to a limit while its body stays as it is, a union
+/// discriminant or integer to an interesting value, or an opaque body
+/// cut short under its header, grown past typical limits with the header
+/// to match, or replaced with a path-like string
+#[derive(Debug, Clone, Copy)]
+pub struct Fields {
+    /// Bytes a grown body is given
+    pub grow_to: usize,
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self { grow_to: 4097 }
+    }
+}
+
+/// Bodies that mean something to path and name handling
+const SPECIAL_BODIES: [&[u8]; 5] = [b"", b".", b"..", b"/", b"a\0b"];
+
+impl Fields {
+    /// Mutate one field of `msg`, keeping its layout in step with the
+    /// bytes
+    pub fn mutate_message(&self, rng: &mut dyn RngCore, msg: &mut Message) -> Option<Step> {
+        if msg.fields.is_empty() {
+            return None;
+        }
+        let index = rng.gen_range(0..msg.fields.len());
+        let field = msg.fields[index].clone();
+        let step = Step::new(self.name())
+            .with("field", field.name.as_str())
+            .with("offset", field.offset);
+        match field.kind {
+            Kind::Length | Kind::Count => {
+                let body = msg.u32_at(index);
+                let values = [
+                    0xffff_ffff,
+                    0x8000_0000,
+                    0x7fff_ffff,
+                    0,
+                    body.wrapping_add(1),
+                    body.wrapping_add(4),
+                    body.wrapping_sub(1),
+                ];
+                let value = values[rng.gen_range(0..values.len())];
+                msg.set_u32(index, value);
+                Some(step.with("value", value))
+            }
+            Kind::Word | Kind::Discriminant => {
+                let values = interesting_values(4);
+                let value = values[rng.gen_range(0..values.len())] as u32;
+                msg.set_u32(index, value);
+                Some(step.with("value", value))
+            }
+            Kind::Hyper => {
+                let values = interesting_values(8);
+                let value = values[rng.gen_range(0..values.len())];
+                msg.set_u64(index, value);
+                Some(step.with("value", value))
+            }
+            Kind::Bytes => {
+                let body = msg.bytes[field.range()].to_vec();
+                let (action, data, fix_header) = match rng.gen_range(0..3) {
+                    0 if !body.is_empty() => ("short", body[..body.len() / 2].to_vec(), false),
+                    1 => ("grow", vec![b'A'; self.grow_to], true),
+                    _ => {
+                        let special = SPECIAL_BODIES[rng.gen_range(0..SPECIAL_BODIES.len())];
+                        ("special", special.to_vec(), true)
+                    }
+                };
+                msg.splice(index, &data);
+                if let Some(header) = msg.header_of(index).filter(|_| fix_header) {
+                    msg.set_u32(header, data.len() as u32);
+                }
+                Some(step.with("action", action).with("len", data.len()))
+            }
+        }
+    }
+}
+
+impl Mutator for Fields {
+    fn name(&self) -> &'static str {
+        "fields"
+    }
+
+    /// Mutate one field of the layout [`Message::infer`] gives `data`
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let mut msg = Message::infer(data);
+        let step = self.mutate_message(rng, &mut msg)?;
+        *data = msg.bytes;
+        Some(step)
+    }
+}
+
+/// Change one field of the SEQUENCE that opens a v4.1 compound, putting
+/// in the placeholder first where there is none: the sequence id offset
+/// to a retry of the last request, a skip or a wrap, the slot id past
+/// any table, highest_slotid below the slot or at the limit, cachethis
+/// flipped, or a byte of the session id. Offsets and the all-zero session
+/// id are resolved against a live session when the compound is sent (see
+/// [`crate::nfsv4::session`]).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionSlot;
+
+impl Mutator for SessionSlot {
+    fn name(&self) -> &'static str {
+        "sequence"
+    }
+
+    fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let mut args = session::with_placeholder(data).unwrap_or_else(|| data.clone());
+        let at = session::sequence_at(&args)?;
+        let word =
+            |off: usize| u32::from_be_bytes(args[at + off..at + off + 4].try_into().unwrap());
+        let slotid = word(20);
+        let pick = |rng: &mut dyn RngCore, values: &[u32]| values[rng.gen_range(0..values.len())];
+        let (field, off, value) = match rng.gen_range(0..5) {
+            0 => (
+                "sequenceid",
+                16,
+                pick(rng, &[u32::MAX, u32::MAX - 1, 1, 2, 0x8000_0000]),
+            ),
+            1 => ("slotid", 20, pick(rng, &[1, 2, 63, 1024, u32::MAX])),
+            2 => (
+                "highest_slotid",
+                24,
+                pick(
+                    rng,
+                    &[
+                        0,
+                        slotid.wrapping_sub(1),
+                        slotid.wrapping_add(1),
+                        1023,
+                        u32::MAX,
+                    ],
+                ),
+            ),
+            3 => ("cachethis", 28, word(28) ^ 1),
+            _ => {
+                let byte = rng.gen_range(0..16);
+                args[at + byte] ^= 1 << rng.gen_range(0..8);
+                *data = args;
+                return Some(
+                    Step::new(self.name())
+                        .with("field", "sessionid")
+                        .with("byte", byte),
+                );
+            }
+        };
+        args[at + off..at + off + 4].copy_from_slice(&value.to_be_bytes());
+        *data = args;
+        Some(
+            Step::new(self.name())
+                .with("field", field)
+                .with("value", value),
+        )
+    }
+}
+
+/// Names of the built-in mutators, in the order specs list them
+pub const BUILTIN: [&str; 9] = [
+    "bitflip",
+    "havoc",
+    "interesting",
+    "duplicate",
+    "delete",
+    "truncate",
+    "append",
+    "fields",
+    "sequence",
+];
+
+/// The built-in mutator called `name`, with default parameters
+pub fn builtin(name: &str) -> Option<Box<dyn Mutator>> {
+    Some(match name {
+        "bitflip" => Box::new(BitFlip),
+        "havoc" => Box::new(Havoc::default()),
+        "interesting" => Box::new(Interesting),
+        "duplicate" => Box::new(Duplicate::default()),
+        "delete" => Box::new(Delete::default()),
+        "truncate" => Box::new(Truncate),
+        "append" => Box::new(Append),
+        "fields" => Box::new(Fields::default()),
+        "sequence" => Box::new(SessionSlot),
+        _ => return None,
+    })
+}
+
+/// Relative weights of the built-in mutators
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Weights(pub Vec<(&'static str, u32)>);
+
+impl Default for Weights {
+    /// Every built-in, equally often
+    fn default() -> Self {
+        Self(BUILTIN.iter().map(|&n| (n, 1)).collect())
+    }
+}
+
+/// `bitflip=4,havoc=2,interesting`: only the listed mutators, a missing
+/// weight meaning 1
+impl FromStr for Weights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = Vec::new();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once('=').unwrap_or((part, "1"));
+            let name = BUILTIN.iter().find(|&&n| n == key).ok_or_else(|| {
+                format!(
+                    "unknown mutator {:?}, expected one of {}",
+                    key,
+                    BUILTIN.join(", ")
+                )
+            })?;
+            let weight = value
+                .parse()
+                .map_err(|_| format!("weight of {} must be a count, got {:?}", key, value))?;
+            weights.retain(|(n, _)| n != name);
+            weights.push((*name, weight));
+        }
+        if weights.iter().all(|&(_, w)| w == 0) {
+            return Err("at least one mutator needs a nonzero weight".to_string());
+        }
+        Ok(Self(weights))
+    }
+}
+
+/// A weighted set of mutators
+pub struct Engine {
+    mutators: Vec<(Box<dyn Mutator>, u32)>,
+    fixup: Fixup,
+}
+
+impl Engine {
+    /// An engine with no mutators; add them with [`Engine::with`]
+    pub fn new() -> Self {
+        Self {
+            mutators: Vec::new(),
+            fixup: Fixup::Off,
+        }
+    }
+
+    /// The built-in mutators, weighted as given
+    pub fn from_weights(weights: &Weights) -> Self {
+        weights
+            .0
+            .iter()
+            .filter(|&&(_, w)| w > 0)
+            .fold(Self::new(), |engine, &(name, w)| {
+                engine.with(builtin(name).expect("weights name built-ins"), w)
+            })
+    }
+
+    /// Add `mutator`, picked `weight` times as often as a weight of one
+    pub fn with(mut self, mutator: Box<dyn Mutator>, weight: u32) -> Self {
+        if weight > 0 {
+            self.mutators.push((mutator, weight));
+        }
+        self
+    }
+
+    /// Repair the headers each mutation breaks as `mode` asks
+    pub fn with_fixup(mut self, mode: Fixup) -> Self {
+        self.fixup = mode;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.mutators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mutators.is_empty()
+    }
+
+    /// Index of a mutator picked by weight among those `allowed`
+    fn pick_index(&self, rng: &mut dyn RngCore, allowed: &[bool]) -> Option<usize> {
+        let weight = |i: usize| u64::from(self.mutators[i].1) * u64::from(allowed[i]);
+        let total: u64 = (0..self.mutators.len()).map(weight).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut n = rng.gen_range(0..total);
+        for i in 0..self.mutators.len() {
+            match n.checked_sub(weight(i)) {
+                Some(rest) => n = rest,
+                None => return Some(i),
+            }
+        }
+        None
+    }
+
+    /// A mutator picked by weight
+    pub fn pick(&self, rng: &mut dyn RngCore) -> Option<&dyn Mutator> {
+        let i = self.pick_index(rng, &vec![true; self.mutators.len()])?;
+        Some(self.mutators[i].0.as_ref())
+    }
+
+    /// Apply one mutation to `data`. A mutator that cannot apply is set
+    /// aside and another picked from the rest; `None` if none can. The
+    /// headers the fixup pass repaired are the step's `fixed`.
+    pub fn mutate(&self, rng: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+        let before = (self.fixup != Fixup::Off).then(|| data.clone());
+        let mut allowed = vec![true; self.mutators.len()];
+        loop {
+            let i = self.pick_index(rng, &allowed)?;
+            if let Some(step) = self.mutators[i].0.mutate(rng, data) {
+                let Some(before) = before else {
+                    return Some(step);
+                };
+                let mut layout = fixup::args_layout(&before);
+                layout.follow(&before, data);
+                let fixed = layout.apply(data, self.fixup);
+                return Some(if fixed.is_empty() {
+                    step
+                } else {
+                    step.with("fixed", fixed.join(","))
+                });
+            }
+            allowed[i] = false;
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::from_weights(&Weights::default())
+    }
+}
+
+impl fmt::Debug for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.mutators.iter().map(|(m, w)| (m.name(), w)))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_weights_spec() {
+        let w: Weights = "bitflip=4,havoc,delete=0".parse().unwrap();
+        assert_eq!(w.0, [("bitflip", 4), ("havoc", 1), ("delete", 0)]);
+        assert_eq!(Engine::from_weights(&w).len(), 2);
+        assert!("flip".parse::<Weights>().is_err());
+        assert!("havoc=x".parse::<Weights>().is_err());
+        assert!("havoc=0".parse::<Weights>().is_err());
+        assert_eq!(Engine::default().len(), BUILTIN.len());
+        assert!(BUILTIN.iter().all(|&n| builtin(n).unwrap().name() == n));
+    }
+
+    #[test]
+    fn test_block_mutators_keep_alignment() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let data: Vec<u8> = (0..40).collect();
+        for _ in 0..50 {
+            let mut d = data.clone();
+            let step = Duplicate::default().mutate(&mut rng, &mut d).unwrap();
+            assert_eq!(d.len() % 4, 0);
+            assert_eq!(d.len() - 40, step.params["len"].as_u64().unwrap() as usize);
+            let mut d = data.clone();
+            Delete::default().mutate(&mut rng, &mut d).unwrap();
+            assert_eq!(d.len() % 4, 0);
+            assert!(d.len() < 40);
+            let mut d = data.clone();
+            let step = Interesting.mutate(&mut rng, &mut d).unwrap();
+            let width = step.params["width"].as_u64().unwrap();
+            assert_eq!(step.params["offset"].as_u64().unwrap() % width.min(4), 0);
+        }
+        assert!(Delete::default()
+            .mutate(&mut rng, &mut vec![1, 2])
+            .is_none());
+    }
+
+    #[test]
+    fn test_fields_mutates_one_field() {
+        use crate::fields::Annotated;
+        let mut rng = StdRng::seed_from_u64(3);
+        let build = || {
+            let mut enc = Annotated::new();
+            enc.put_opaque("fh", &[7; 8]);
+            enc.put_u64("offset", 0);
+            enc.finish()
+        };
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let mut msg = build();
+            let step = Fields::default()
+                .mutate_message(&mut rng, &mut msg)
+                .unwrap();
+            let field = step.params["field"].as_str().unwrap().to_string();
+            seen.insert(field.clone());
+            match step.params.get("action").and_then(|a| a.as_str()) {
+                // A header set to anything leaves its body of eight
+                None => assert_eq!(msg.bytes.len(), 20),
+                Some("short") => assert_eq!((msg.u32_at(0), msg.fields[1].len), (8, 4)),
+                Some("grow") => assert_eq!(msg.u32_at(0), 4097),
+                _ => {}
+            }
+            // The layout still matches the bytes
+            assert_eq!(msg.fields[2].range().end, msg.bytes.len());
+        }
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[test]
+    fn test_engine_is_deterministic_and_pluggable() {
+        struct Zero;
+        impl Mutator for Zero {
+            fn name(&self) -> &'static str {
+                "zero"
+            }
+            fn mutate(&self, _: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+                data.fill(0);
+                Some(Step::new("zero"))
+            }
+        }
+        let engine = Engine::new().with(Box::new(Zero), 1);
+        let mut data = vec![1; 8];
+        let step = engine.mutate(&mut StdRng::seed_from_u64(1), &mut data);
+        assert_eq!(step.unwrap().strategy, "zero");
+        assert_eq!(data, [0; 8]);
+        assert!(Engine::new()
+            .mutate(&mut StdRng::seed_from_u64(1), &mut data)
+            .is_none());
+
+        let run = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut data = vec![0x55; 64];
+            let steps: Vec<Step> = (0..20)
+                .filter_map(|_| Engine::default().mutate(&mut rng, &mut data))
+                .collect();
+            (data, steps)
+        };
+        assert_eq!(run(9), run(9));
+        assert_eq!(run(9).1.len(), 20);
+    }
+
+    #[test]
+    fn test_engine_fixup_repairs_lengths() {
+        struct Grow;
+        impl Mutator for Grow {
+            fn name(&self) -> &'static str {
+                "grow"
+            }
+            fn mutate(&self, _: &mut dyn RngCore, data: &mut Vec<u8>) -> Option<Step> {
+                data.extend_from_slice(b"more");
+                Some(Step::new("grow"))
+            }
+        }
+        // A word, then a four-byte opaque the mutator appends to
+        let args = [0, 0, 0, 1, 0, 0, 0, 4, b'a', b'b', b'c', b'd'];
+        let run = |mode| {
+            let engine = Engine::new().with(Box::new(Grow), 1).with_fixup(mode);
+            let mut data = args.to_vec();
+            let step = engine.mutate(&mut StdRng::seed_from_u64(1), &mut data);
+            (
+                data[4..8].to_vec(),
+                step.unwrap().params.get("fixed").cloned(),
+            )
+        };
+        assert_eq!(
+            run(Fixup::All),
+            (vec![0, 0, 0, 8], Some("opaque_length".into()))
+        );
+        assert_eq!(run(Fixup::Off), (vec![0, 0, 0, 4], None));
+        assert_eq!(
+            run(Fixup::Preserve("opaque_length")),
+            (vec![0, 0, 0, 4], None)
+        );
+    }
+
+    #[test]
+    fn test_sequence_mutator() {
+        use crate::nfsv4::{minor_version, session, CompoundBuilder};
+        let mut rng = StdRng::seed_from_u64(9);
+        let compound = CompoundBuilder::new(minor_version::V4_1)
+            .putrootfh()
+            .build();
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let mut data = compound.clone();
+            let step = SessionSlot.mutate(&mut rng, &mut data).unwrap();
+            let at = session::sequence_at(&data).unwrap();
+            let field = step.params["field"].as_str().unwrap().to_string();
+            let word =
+                |off: usize| u32::from_be_bytes(data[at + off..at + off + 4].try_into().unwrap());
+            match field.as_str() {
+                "sequenceid" => assert_ne!(word(16), 0),
+                "slotid" => assert_ne!(word(20), 0),
+                "cachethis" => assert_eq!(word(28), 1),
+                "sessionid" => assert!(data[at..at + 16].iter().any(|&b| b != 0)),
+                _ => assert_eq!(u64::from(word(24)), step.params["value"].as_u64().unwrap()),
+            }
+            seen.insert(field);
+        }
+        assert_eq!(seen.len(), 5);
+        let v40 = CompoundBuilder::new(minor_version::V4_0)
+            .putrootfh()
+            .build();
+        assert!(SessionSlot.mutate(&mut rng, &mut v40.clone()).is_none());
+    }
+}
@@ -0,0 +1,301 @@
+//! Simulated packet loss and reordering for UDP campaigns
+//!
+//! Over UDP the client retransmits, so a server sees the same XID twice,
+//! sees calls out of order, or never sees one at all. Its duplicate
+//! request cache and the idempotency of every non-idempotent procedure
+//! are only exercised when that happens. A [`FaultySocket`] drops,
+//! duplicates, reorders and delays outgoing datagrams itself, so no
+//! tc/netem setup on the fuzzing host is needed and every fault is
+//! reproducible from the campaign seed.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+/// Fault rates and delays; all zero sends every datagram once, at once
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Probability a datagram is never sent
+    pub drop: f64,
+    /// Probability a datagram is sent twice
+    pub duplicate: f64,
+    /// Probability a datagram is held back and sent after the next one
+    pub reorder: f64,
+    pub delay: Duration,
+    /// Each delivery's delay varies by up to this much either way
+    pub jitter: Duration,
+}
+
+fn parse_duration(v: &str) -> Result<Duration, String> {
+    let (num, unit) = v
+        .find(|c: char| !c.is_ascii_digit())
+        .map(|i| v.split_at(i))
+        .unwrap_or((v, "ms"));
+    let n: u64 = num
+        .parse()
+        .map_err(|e| format!("bad duration {:?}: {}", v, e))?;
+    match unit {
+        "us" => Ok(Duration::from_micros(n)),
+        "ms" => Ok(Duration::from_millis(n)),
+        "s" => Ok(Duration::from_secs(n)),
+        _ => Err(format!("bad duration unit in {:?}", v)),
+    }
+}
+
+/// `drop=0.05,dup=0.01,reorder=0.02,delay=20ms,jitter=5ms`; any subset
+impl FromStr for FaultConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", part))?;
+            let rate = || match value.parse::<f64>() {
+                Ok(p) if (0.0..=1.0).contains(&p) => Ok(p),
+                _ => Err(format!("{} must be a probability, got {:?}", key, value)),
+            };
+            match key {
+                "drop" => config.drop = rate()?,
+                "dup" | "duplicate" => config.duplicate = rate()?,
+                "reorder" => config.reorder = rate()?,
+                "delay" => config.delay = parse_duration(value)?,
+                "jitter" => config.jitter = parse_duration(value)?,
+                _ => return Err(format!("unknown fault {:?}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Faults applied so far, for the campaign report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FaultStats {
+    pub sent: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+}
+
+/// One datagram to put on the wire after `delay`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delivery {
+    pub delay: Duration,
+    pub data: Vec<u8>,
+}
+
+/// Decides the fate of each datagram
+#[derive(Debug)]
+pub struct FaultInjector {
+    config: FaultConfig,
+    rng: StdRng,
+    held: Option<Vec<u8>>,
+    pub stats: FaultStats,
+}
+
+impl FaultInjector {
+    pub fn new(config: FaultConfig, seed: u64) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            held: None,
+            stats: FaultStats::default(),
+        }
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = self.config.jitter.as_nanos() as i128;
+        let offset = if jitter > 0 {
+            self.rng.gen_range(-jitter..=jitter)
+        } else {
+            0
+        };
+        let nanos = (self.config.delay.as_nanos() as i128 + offset).max(0);
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// The deliveries `datagram` turns into. A held datagram goes out
+    /// right after the next one that is sent.
+    pub fn plan(&mut self, datagram: &[u8]) -> Vec<Delivery> {
+        let mut out = Vec::new();
+        if self.rng.gen_bool(self.config.drop) {
+            self.stats.dropped += 1;
+            return out;
+        }
+        if self.held.is_none() && self.rng.gen_bool(self.config.reorder) {
+            self.stats.reordered += 1;
+            self.held = Some(datagram.to_vec());
+            return out;
+        }
+        let copies = if self.rng.gen_bool(self.config.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let delay = self.delay();
+            out.push(Delivery {
+                delay,
+                data: datagram.to_vec(),
+            });
+        }
+        out.extend(self.flush());
+        self.stats.sent += out.len() as u64;
+        out
+    }
+
+    /// Release a held datagram, e.g. when no more calls follow
+    pub fn flush(&mut self) -> Option<Delivery> {
+        let data = self.held.take()?;
+        let delay = self.delay().max(Duration::from_micros(1));
+        Some(Delivery { delay, data })
+    }
+}
+
+/// A connected UDP socket whose sends go through a [`FaultInjector`]
+#[derive(Debug, Clone)]
+pub struct FaultySocket {
+    socket: Arc<UdpSocket>,
+    injector: Arc<Mutex<FaultInjector>>,
+}
+
+impl FaultySocket {
+    pub fn new(socket: UdpSocket, config: FaultConfig, seed: u64) -> Self {
+        Self {
+            socket: Arc::new(socket),
+            injector: Arc::new(Mutex::new(FaultInjector::new(config, seed))),
+        }
+    }
+
+    /// The same socket with a fresh injector
+    pub fn with_faults(self, config: FaultConfig, seed: u64) -> Self {
+        Self {
+            socket: self.socket,
+            injector: Arc::new(Mutex::new(FaultInjector::new(config, seed))),
+        }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn stats(&self) -> FaultStats {
+        self.injector.lock().unwrap().stats
+    }
+
+    /// Send `datagram` as the injector decides; delayed copies are sent
+    /// from background tasks, so this returns without waiting for them
+    pub async fn send(&self, datagram: &[u8]) -> std::io::Result<()> {
+        let deliveries = self.injector.lock().unwrap().plan(datagram);
+        self.deliver(deliveries).await
+    }
+
+    /// Send a datagram still held for reordering
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let held = self.injector.lock().unwrap().flush();
+        self.deliver(held.into_iter().collect()).await
+    }
+
+    async fn deliver(&self, deliveries: Vec<Delivery>) -> std::io::Result<()> {
+        let mut delayed = Duration::ZERO;
+        for d in deliveries {
+            // Keep the planned order even when jitter would swap copies
+            delayed = delayed.max(d.delay);
+            if delayed.is_zero() {
+                self.socket.send(&d.data).await?;
+            } else {
+                let socket = self.socket.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delayed).await;
+                    let _ = socket.send(&d.data).await;
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_plan() {
+        let config: FaultConfig = "drop=0,dup=1,delay=5ms,jitter=2ms".parse().unwrap();
+        assert_eq!(config.duplicate, 1.0);
+        assert_eq!(config.delay, Duration::from_millis(5));
+        assert!("drop=2".parse::<FaultConfig>().is_err());
+        assert!("loss=0.1".parse::<FaultConfig>().is_err());
+        assert!("delay=5h".parse::<FaultConfig>().is_err());
+        assert_eq!("".parse::<FaultConfig>(), Ok(FaultConfig::default()));
+
+        let mut dup = FaultInjector::new(config, 1);
+        let copies = dup.plan(b"call");
+        assert_eq!(copies.len(), 2);
+        for d in &copies {
+            assert!(d.delay >= Duration::from_millis(3) && d.delay <= Duration::from_millis(7));
+        }
+
+        let mut clean = FaultInjector::new(FaultConfig::default(), 1);
+        assert_eq!(
+            clean.plan(b"a"),
+            [Delivery {
+                delay: Duration::ZERO,
+                data: b"a".to_vec()
+            }]
+        );
+
+        let reorder = FaultConfig {
+            reorder: 1.0,
+            ..FaultConfig::default()
+        };
+        let mut r = FaultInjector::new(reorder, 1);
+        assert!(r.plan(b"first").is_empty());
+        let out: Vec<Vec<u8>> = r.plan(b"second").into_iter().map(|d| d.data).collect();
+        assert_eq!(out, [b"second".to_vec(), b"first".to_vec()]);
+        assert_eq!(
+            r.stats,
+            FaultStats {
+                sent: 2,
+                reordered: 1,
+                ..FaultStats::default()
+            }
+        );
+
+        let drop = FaultConfig {
+            drop: 1.0,
+            ..FaultConfig::default()
+        };
+        let mut d = FaultInjector::new(drop, 1);
+        assert!(d.plan(b"x").is_empty() && d.flush().is_none());
+        assert_eq!(d.stats.dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_faulty_socket_over_loopback() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+        let config = FaultConfig {
+            duplicate: 1.0,
+            delay: Duration::from_millis(5),
+            ..FaultConfig::default()
+        };
+        let socket = FaultySocket::new(client, config, 9);
+        socket.send(b"xid1").await.unwrap();
+        let mut buf = [0; 16];
+        for _ in 0..2 {
+            let n = tokio::time::timeout(Duration::from_secs(1), server.recv(&mut buf))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(&buf[..n], b"xid1");
+        }
+        assert_eq!(socket.stats().duplicated, 1);
+    }
+}
@@ -0,0 +1,471 @@
+//! NFSv3 procedure arguments (RFC 1813)
+//!
+//! Every procedure takes its arguments as one XDR structure, so a call is
+//! a procedure number and the encoded arguments. The builders take
+//! handles and names as raw bytes and counts as given rather than derived
+//! from the data, so structurally valid calls can still carry oversized
+//! handles, non-UTF-8 names or counts that disagree with the payload.
+
+use crate::rpc::{program, Program, ProgramCall};
+use crate::xdr::XdrEncoder;
+
+/// NFS protocol version
+pub const VERSION: u32 = 3;
+
+/// NFS3_FHSIZE: the largest v3 file handle
+pub const FHSIZE: usize = 64;
+
+/// NFS3_COOKIEVERFSIZE, NFS3_CREATEVERFSIZE, NFS3_WRITEVERFSIZE
+pub const VERFSIZE: usize = 8;
+
+/// Procedure numbers
+pub mod proc {
+    pub const NULL: u32 = 0;
+    pub const GETATTR: u32 = 1;
+    pub const SETATTR: u32 = 2;
+    pub const LOOKUP: u32 = 3;
+    pub const ACCESS: u32 = 4;
+    pub const READLINK: u32 = 5;
+    pub const READ: u32 = 6;
+    pub const WRITE: u32 = 7;
+    pub const CREATE: u32 = 8;
+    pub const MKDIR: u32 = 9;
+    pub const SYMLINK: u32 = 10;
+    pub const MKNOD: u32 = 11;
+    pub const REMOVE: u32 = 12;
+    pub const RMDIR: u32 = 13;
+    pub const RENAME: u32 = 14;
+    pub const LINK: u32 = 15;
+    pub const READDIR: u32 = 16;
+    pub const READDIRPLUS: u32 = 17;
+    pub const FSSTAT: u32 = 18;
+    pub const FSINFO: u32 = 19;
+    pub const PATHCONF: u32 = 20;
+    pub const COMMIT: u32 = 21;
+}
+
+/// Reply statuses (nfsstat3) the fuzzer's own calls look for
+pub mod stat {
+    pub const OK: u32 = 0;
+    pub const EXIST: u32 = 17;
+}
+
+/// File types (ftype3)
+pub mod ftype {
+    pub const REG: u32 = 1;
+    pub const DIR: u32 = 2;
+    pub const BLK: u32 = 3;
+    pub const CHR: u32 = 4;
+    pub const LNK: u32 = 5;
+    pub const SOCK: u32 = 6;
+    pub const FIFO: u32 = 7;
+}
+
+/// ACCESS3 permission bits
+pub mod access {
+    pub const READ: u32 = 0x0001;
+    pub const LOOKUP: u32 = 0x0002;
+    pub const MODIFY: u32 = 0x0004;
+    pub const EXTEND: u32 = 0x0008;
+    pub const DELETE: u32 = 0x0010;
+    pub const EXECUTE: u32 = 0x0020;
+    pub const ALL: u32 = 0x003f;
+}
+
+/// WRITE stability (stable_how)
+pub mod stable_how {
+    pub const UNSTABLE: u32 = 0;
+    pub const DATA_SYNC: u32 = 1;
+    pub const FILE_SYNC: u32 = 2;
+}
+
+/// CREATE modes (createmode3)
+pub mod createmode {
+    pub const UNCHECKED: u32 = 0;
+    pub const GUARDED: u32 = 1;
+    pub const EXCLUSIVE: u32 = 2;
+}
+
+/// How SETATTR sets a time (time_how)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SetTime {
+    #[default]
+    DontChange,
+    ServerTime,
+    /// Seconds and nanoseconds (nfstime3)
+    Client(u32, u32),
+}
+
+impl SetTime {
+    fn encode(&self, enc: &mut XdrEncoder) {
+        match *self {
+            SetTime::DontChange => enc.put_u32(0),
+            SetTime::ServerTime => enc.put_u32(1),
+            SetTime::Client(seconds, nseconds) => {
+                enc.put_u32(2);
+                enc.put_u32(seconds);
+                enc.put_u32(nseconds);
+            }
+        }
+    }
+}
+
+/// Attributes to set (sattr3); `None` leaves a field unchanged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sattr3 {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub size: Option<u64>,
+    pub atime: SetTime,
+    pub mtime: SetTime,
+}
+
+impl Sattr3 {
+    pub fn mode(mode: u32) -> Self {
+        Self {
+            mode: Some(mode),
+            ..Self::default()
+        }
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        for v in [self.mode, self.uid, self.gid] {
+            enc.put_bool(v.is_some());
+            if let Some(v) = v {
+                enc.put_u32(v);
+            }
+        }
+        enc.put_bool(self.size.is_some());
+        if let Some(size) = self.size {
+            enc.put_u64(size);
+        }
+        self.atime.encode(enc);
+        self.mtime.encode(enc);
+    }
+}
+
+/// How CREATE creates (createhow3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CreateHow {
+    Unchecked(Sattr3),
+    Guarded(Sattr3),
+    Exclusive([u8; VERFSIZE]),
+}
+
+impl CreateHow {
+    fn encode(&self, enc: &mut XdrEncoder) {
+        match self {
+            CreateHow::Unchecked(attrs) => {
+                enc.put_u32(createmode::UNCHECKED);
+                attrs.encode(enc);
+            }
+            CreateHow::Guarded(attrs) => {
+                enc.put_u32(createmode::GUARDED);
+                attrs.encode(enc);
+            }
+            CreateHow::Exclusive(verf) => {
+                enc.put_u32(createmode::EXCLUSIVE);
+                enc.put_opaque_fixed(verf);
+            }
+        }
+    }
+}
+
+/// The v3 program, at [`VERSION`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nfs3;
+
+impl Program for Nfs3 {
+    const PROGRAM: u32 = program::NFS;
+    const VERSION: u32 = VERSION;
+}
+
+/// One v3 call: the procedure and its encoded arguments
+pub type Call = ProgramCall<Nfs3>;
+
+/// Encode a diropargs3
+pub fn put_dirop(enc: &mut XdrEncoder, dir: &[u8], name: &[u8]) {
+    enc.put_opaque(dir);
+    enc.put_opaque(name);
+}
+
+/// NULL (no arguments)
+pub fn null() -> Call {
+    Call::new(proc::NULL, |_| {})
+}
+
+/// Encode GETATTR3args
+pub fn getattr(fh: &[u8]) -> Call {
+    Call::new(proc::GETATTR, |enc| enc.put_opaque(fh))
+}
+
+/// Encode SETATTR3args; `guard` is the ctime (seconds, nanoseconds) the
+/// object must still have
+pub fn setattr(fh: &[u8], attrs: &Sattr3, guard: Option<(u32, u32)>) -> Call {
+    Call::new(proc::SETATTR, |enc| {
+        enc.put_opaque(fh);
+        attrs.encode(enc);
+        enc.put_bool(guard.is_some());
+        if let Some((seconds, nseconds)) = guard {
+            enc.put_u32(seconds);
+            enc.put_u32(nseconds);
+        }
+    })
+}
+
+/// Encode LOOKUP3args
+pub fn lookup(dir: &[u8], name: &[u8]) -> Call {
+    Call::new(proc::LOOKUP, |enc| put_dirop(enc, dir, name))
+}
+
+/// Encode ACCESS3args
+pub fn access(fh: &[u8], bits: u32) -> Call {
+    Call::new(proc::ACCESS, |enc| {
+        enc.put_opaque(fh);
+        enc.put_u32(bits);
+    })
+}
+
+/// Encode READLINK3args
+pub fn readlink(fh: &[u8]) -> Call {
+    Call::new(proc::READLINK, |enc| enc.put_opaque(fh))
+}
+
+/// Encode READ3args
+pub fn read(fh: &[u8], offset: u64, count: u32) -> Call {
+    Call::new(proc::READ, |enc| {
+        enc.put_opaque(fh);
+        enc.put_u64(offset);
+        enc.put_u32(count);
+    })
+}
+
+/// Encode WRITE3args; `count` is sent as given, whatever `data` holds
+pub fn write(fh: &[u8], offset: u64, count: u32, stable: u32, data: &[u8]) -> Call {
+    Call::new(proc::WRITE, |enc| {
+        enc.put_opaque(fh);
+        enc.put_u64(offset);
+        enc.put_u32(count);
+        enc.put_u32(stable);
+        enc.put_opaque(data);
+    })
+}
+
+/// Encode CREATE3args
+pub fn create(dir: &[u8], name: &[u8], how: &CreateHow) -> Call {
+    Call::new(proc::CREATE, |enc| {
+        put_dirop(enc, dir, name);
+        how.encode(enc);
+    })
+}
+
+/// Encode MKDIR3args
+pub fn mkdir(dir: &[u8], name: &[u8], attrs: &Sattr3) -> Call {
+    Call::new(proc::MKDIR, |enc| {
+        put_dirop(enc, dir, name);
+        attrs.encode(enc);
+    })
+}
+
+/// Encode SYMLINK3args
+pub fn symlink(dir: &[u8], name: &[u8], attrs: &Sattr3, target: &[u8]) -> Call {
+    Call::new(proc::SYMLINK, |enc| {
+        put_dirop(enc, dir, name);
+        attrs.encode(enc);
+        enc.put_opaque(target);
+    })
+}
+
+/// Encode MKNOD3args. The mknoddata3 arm follows `ftype`: attributes and
+/// device numbers for CHR and BLK, attributes for SOCK and FIFO, and
+/// nothing for any other type, valid or not
+pub fn mknod(dir: &[u8], name: &[u8], ftype: u32, attrs: &Sattr3, rdev: (u32, u32)) -> Call {
+    Call::new(proc::MKNOD, |enc| {
+        put_dirop(enc, dir, name);
+        enc.put_u32(ftype);
+        match ftype {
+            ftype::CHR | ftype::BLK => {
+                attrs.encode(enc);
+                enc.put_u32(rdev.0);
+                enc.put_u32(rdev.1);
+            }
+            ftype::SOCK | ftype::FIFO => attrs.encode(enc),
+            _ => {}
+        }
+    })
+}
+
+/// Encode REMOVE3args
+pub fn remove(dir: &[u8], name: &[u8]) -> Call {
+    Call::new(proc::REMOVE, |enc| put_dirop(enc, dir, name))
+}
+
+/// Encode RMDIR3args
+pub fn rmdir(dir: &[u8], name: &[u8]) -> Call {
+    Call::new(proc::RMDIR, |enc| put_dirop(enc, dir, name))
+}
+
+/// Encode RENAME3args
+pub fn rename(from_dir: &[u8], from: &[u8], to_dir: &[u8], to: &[u8]) -> Call {
+    Call::new(proc::RENAME, |enc| {
+        put_dirop(enc, from_dir, from);
+        put_dirop(enc, to_dir, to);
+    })
+}
+
+/// Encode LINK3args: a new name in `dir` for `fh`
+pub fn link(fh: &[u8], dir: &[u8], name: &[u8]) -> Call {
+    Call::new(proc::LINK, |enc| {
+        enc.put_opaque(fh);
+        put_dirop(enc, dir, name);
+    })
+}
+
+/// Encode READDIR3args
+pub fn readdir(dir: &[u8], cookie: u64, verf: &[u8; VERFSIZE], count: u32) -> Call {
+    Call::new(proc::READDIR, |enc| {
+        enc.put_opaque(dir);
+        enc.put_u64(cookie);
+        enc.put_opaque_fixed(verf);
+        enc.put_u32(count);
+    })
+}
+
+/// Encode READDIRPLUS3args
+pub fn readdirplus(
+    dir: &[u8],
+    cookie: u64,
+    verf: &[u8; VERFSIZE],
+    dircount: u32,
+    maxcount: u32,
+) -> Call {
+    Call::new(proc::READDIRPLUS, |enc| {
+        enc.put_opaque(dir);
+        enc.put_u64(cookie);
+        enc.put_opaque_fixed(verf);
+        enc.put_u32(dircount);
+        enc.put_u32(maxcount);
+    })
+}
+
+/// Encode FSSTAT3args
+pub fn fsstat(root: &[u8]) -> Call {
+    Call::new(proc::FSSTAT, |enc| enc.put_opaque(root))
+}
+
+/// Encode FSINFO3args
+pub fn fsinfo(root: &[u8]) -> Call {
+    Call::new(proc::FSINFO, |enc| enc.put_opaque(root))
+}
+
+/// Encode PATHCONF3args
+pub fn pathconf(fh: &[u8]) -> Call {
+    Call::new(proc::PATHCONF, |enc| enc.put_opaque(fh))
+}
+
+/// Encode COMMIT3args
+pub fn commit(fh: &[u8], offset: u64, count: u32) -> Call {
+    Call::new(proc::COMMIT, |enc| {
+        enc.put_opaque(fh);
+        enc.put_u64(offset);
+        enc.put_u32(count);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+
+    fn words(args: &[u8]) -> Vec<u32> {
+        args.chunks(4)
+            .map(|c| u32::from_be_bytes(c.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_sattr3_and_setattr() {
+        let attrs = Sattr3 {
+            size: Some(1 << 32),
+            mtime: SetTime::Client(5, 6),
+            atime: SetTime::ServerTime,
+            ..Sattr3::mode(0o644)
+        };
+        let call = setattr(b"fh", &attrs, Some((7, 8)));
+        assert_eq!(call.procedure, proc::SETATTR);
+        let expected = [
+            &[2, 0x6668_0000][..], // handle "fh", padded
+            &[1, 0o644, 0, 0],     // mode set, uid and gid not
+            &[1, 1, 0],            // size 1 << 32
+            &[1],                  // atime: server time
+            &[2, 5, 6],            // mtime: client time
+            &[1, 7, 8],            // guard ctime
+        ]
+        .concat();
+        assert_eq!(words(&call.args), expected);
+        assert_eq!(words(&setattr(&[], &Sattr3::default(), None).args), [0; 8]);
+    }
+
+    #[test]
+    fn test_create_and_mknod_arms() {
+        let excl = create(b"d", b"f", &CreateHow::Exclusive(*b"verifier"));
+        assert_eq!(excl.args.len(), 8 + 8 + 4 + 8);
+        assert_eq!(&excl.args[16..20], &createmode::EXCLUSIVE.to_be_bytes());
+        assert!(excl.args.ends_with(b"verifier"));
+        let guarded = create(b"d", b"f", &CreateHow::Guarded(Sattr3::default()));
+        assert_eq!(guarded.args.len(), 8 + 8 + 4 + 6 * 4);
+
+        let attrs = Sattr3::default();
+        let dirop = 8 + 8;
+        let chr = mknod(b"d", b"n", ftype::CHR, &attrs, (1, 3));
+        assert_eq!(chr.args.len(), dirop + 4 + 24 + 8);
+        assert!(chr.args.ends_with(&[0, 0, 0, 1, 0, 0, 0, 3]));
+        assert_eq!(
+            mknod(b"d", b"n", ftype::FIFO, &attrs, (1, 3)).args.len(),
+            dirop + 4 + 24
+        );
+        assert_eq!(mknod(b"d", b"n", 99, &attrs, (1, 3)).args.len(), dirop + 4);
+    }
+
+    #[test]
+    fn test_procedures_and_message() {
+        let verf = [0; VERFSIZE];
+        let calls = [
+            null(),
+            getattr(b"fh"),
+            setattr(b"fh", &Sattr3::default(), None),
+            lookup(b"fh", b"n"),
+            access(b"fh", access::ALL),
+            readlink(b"fh"),
+            read(b"fh", 0, 4096),
+            write(b"fh", 0, 99, stable_how::FILE_SYNC, b"abc"),
+            create(b"fh", b"n", &CreateHow::Unchecked(Sattr3::default())),
+            mkdir(b"fh", b"n", &Sattr3::mode(0o755)),
+            symlink(b"fh", b"n", &Sattr3::default(), b"/etc/passwd"),
+            mknod(b"fh", b"n", ftype::SOCK, &Sattr3::default(), (0, 0)),
+            remove(b"fh", b"n"),
+            rmdir(b"fh", b"n"),
+            rename(b"fh", b"a", b"fh", b"b"),
+            link(b"fh", b"fh", b"n"),
+            readdir(b"fh", 0, &verf, 512),
+            readdirplus(b"fh", 0, &verf, 512, 8192),
+            fsstat(b"fh"),
+            fsinfo(b"fh"),
+            pathconf(b"fh"),
+            commit(b"fh", 0, 0),
+        ];
+        for (i, call) in calls.iter().enumerate() {
+            assert_eq!(call.procedure, i as u32);
+            assert_eq!(call.args.len() % 4, 0);
+        }
+        // The count is sent as given, not taken from the data
+        assert_eq!(&calls[7].args[16..20], &99u32.to_be_bytes());
+
+        let msg = calls[1].message(&Identity::new(0, 0));
+        assert_eq!(&msg[16..20], &program::NFS.to_be_bytes());
+        assert_eq!(&msg[20..24], &VERSION.to_be_bytes());
+        assert_eq!(&msg[24..28], &proc::GETATTR.to_be_bytes());
+        assert!(msg.ends_with(&calls[1].args));
+    }
+}
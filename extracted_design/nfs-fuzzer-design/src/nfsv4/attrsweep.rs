@@ -0,0 +1,366 @@
+//! GETATTR/READDIR attribute bitmap sweep (RFC 8881 §5)
+//!
+//! Every attribute has its own encoder on the server, and a request for
+//! several at once has to encode them back to back into one reply buffer.
+//! The sweep first asks for each attribute on its own, which gives the
+//! length and status each one produces, then for pseudo-random
+//! combinations. A combination whose reply status or length is not what
+//! its single-attribute replies predict, or which takes far longer to
+//! answer, points at an encoder that misbehaves in company.
+
+use super::{bitmap_from_bits, bits_of_bitmap, getattr, op, readdir, status, FuzzCase};
+use crate::xdr::XdrDecoder;
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Highest attribute number defined through v4.2 (xattr_support, RFC 8276)
+pub const ATTR_MAX: u32 = 82;
+
+/// Undefined bits: just past the range, at word edges and far out
+pub const UNDEFINED_BITS: &[u32] = &[83, 95, 96, 127, 255, 1023];
+
+/// One requested attribute set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttrMask {
+    pub name: String,
+    pub bits: Vec<u32>,
+}
+
+impl AttrMask {
+    pub fn new(mut bits: Vec<u32>) -> Self {
+        bits.sort_unstable();
+        bits.dedup();
+        let list: Vec<String> = bits.iter().map(|b| b.to_string()).collect();
+        Self {
+            name: format!("attrs_{}", list.join("_")),
+            bits,
+        }
+    }
+
+    pub fn words(&self) -> Vec<u32> {
+        bitmap_from_bits(&self.bits)
+    }
+
+    pub fn from_words(words: &[u32]) -> Self {
+        Self::new(bits_of_bitmap(words).collect())
+    }
+}
+
+/// Each defined and undefined attribute on its own, then `combos` random
+/// sets of 2..=`max_bits` distinct defined attributes drawn from `seed`
+pub fn sweep_masks(seed: u64, combos: usize, max_bits: usize) -> Vec<AttrMask> {
+    let mut masks: Vec<AttrMask> = (0..=ATTR_MAX)
+        .chain(UNDEFINED_BITS.iter().copied())
+        .map(|b| AttrMask::new(vec![b]))
+        .collect();
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..combos {
+        let n = rng.gen_range(2..=max_bits.clamp(2, ATTR_MAX as usize + 1));
+        let bits = index::sample(&mut rng, ATTR_MAX as usize + 1, n);
+        masks.push(AttrMask::new(bits.iter().map(|b| b as u32).collect()));
+    }
+    masks
+}
+
+pub fn getattr_cases(masks: &[AttrMask]) -> Vec<FuzzCase> {
+    masks
+        .iter()
+        .map(|m| FuzzCase::new(format!("getattr_{}", m.name), vec![getattr(&m.words())]))
+        .collect()
+}
+
+pub fn readdir_cases(masks: &[AttrMask]) -> Vec<FuzzCase> {
+    masks
+        .iter()
+        .map(|m| {
+            FuzzCase::new(
+                format!("readdir_{}", m.name),
+                vec![readdir(0, &[0; 8], 4096, 32768, &m.words())],
+            )
+        })
+        .collect()
+}
+
+/// What the reply to one mask looked like
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttrReply {
+    pub status: u32,
+    /// Length of attr_vals (of the first entry, for READDIR)
+    pub attr_len: usize,
+    pub latency: Duration,
+}
+
+fn get_bitmap(dec: &mut XdrDecoder<'_>) -> Option<Vec<u32>> {
+    let words = dec.get_u32().ok()?;
+    (0..words).map(|_| dec.get_u32().ok()).collect()
+}
+
+/// The mask a [`getattr_cases`] compound asked for and the reply to it,
+/// which took `latency`; none if the compound failed before GETATTR or
+/// is not one of the sweep's
+pub fn decode_getattr(
+    args: &[u8],
+    results: &[u8],
+    latency: Duration,
+) -> Option<(AttrMask, AttrReply)> {
+    let (mut args, mut res) = (XdrDecoder::new(args), XdrDecoder::new(results));
+    // Tag and minor version, then status and tag
+    args.get_opaque().ok()?;
+    args.get_u32().ok()?;
+    res.get_u32().ok()?;
+    res.get_opaque().ok()?;
+    let count = args.get_u32().ok()?.min(res.get_u32().ok()?);
+    for _ in 0..count {
+        let opcode = args.get_u32().ok()?;
+        if res.get_u32().ok()? != opcode {
+            return None;
+        }
+        let stat = res.get_u32().ok()?;
+        match opcode {
+            op::GETATTR => {
+                let mask = AttrMask::from_words(&get_bitmap(&mut args)?);
+                let attr_len = match stat {
+                    status::NFS4_OK => {
+                        get_bitmap(&mut res)?;
+                        res.get_opaque().ok()?.len()
+                    }
+                    _ => 0,
+                };
+                let reply = AttrReply {
+                    status: stat,
+                    attr_len,
+                    latency,
+                };
+                return Some((mask, reply));
+            }
+            op::SEQUENCE => {
+                args.get_raw(32).ok()?;
+                if stat == status::NFS4_OK {
+                    res.get_raw(36).ok()?;
+                }
+            }
+            op::PUTROOTFH => {}
+            _ => return None,
+        }
+        if stat != status::NFS4_OK {
+            return None;
+        }
+    }
+    None
+}
+
+/// A combination that its single-attribute replies do not explain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrAnomaly {
+    /// Singles all succeeded, the combination did not (or vice versa)
+    Status {
+        mask: AttrMask,
+        got: u32,
+        singles: u32,
+    },
+    /// attr_vals length differs from the sum of the singles' lengths
+    Length {
+        mask: AttrMask,
+        got: usize,
+        expected: usize,
+    },
+    /// Reply took more than `LATENCY_FACTOR` times the singles' median
+    Latency {
+        mask: AttrMask,
+        got: Duration,
+        median: Duration,
+    },
+}
+
+/// How much slower than the median single-attribute reply counts as dramatic
+pub const LATENCY_FACTOR: u32 = 10;
+
+/// Collects sweep replies and reports combinations that stand out
+#[derive(Debug, Default)]
+pub struct SweepRecorder {
+    singles: HashMap<u32, AttrReply>,
+    combos: Vec<(AttrMask, AttrReply)>,
+}
+
+impl SweepRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, mask: &AttrMask, reply: AttrReply) {
+        match mask.bits[..] {
+            [bit] => {
+                self.singles.insert(bit, reply);
+            }
+            _ => self.combos.push((mask.clone(), reply)),
+        }
+    }
+
+    fn median_latency(&self) -> Duration {
+        let mut v: Vec<Duration> = self.singles.values().map(|r| r.latency).collect();
+        v.sort_unstable();
+        v.get(v.len() / 2).copied().unwrap_or_default()
+    }
+
+    /// Combinations with an unexpected status, length or latency; a
+    /// combination is only judged on structure once all its bits have
+    /// been swept on their own
+    pub fn anomalies(&self, ok: u32) -> Vec<AttrAnomaly> {
+        let median = self.median_latency();
+        self.combos
+            .iter()
+            .flat_map(|(mask, reply)| self.judged(mask, reply, ok, median))
+            .collect()
+    }
+
+    /// What stands out about the reply to one combination, against the
+    /// singles recorded so far; singles are the yardstick, never judged
+    pub fn judge(&self, mask: &AttrMask, reply: &AttrReply, ok: u32) -> Vec<AttrAnomaly> {
+        match mask.bits.len() {
+            0 | 1 => Vec::new(),
+            _ => self.judged(mask, reply, ok, self.median_latency()),
+        }
+    }
+
+    fn judged(
+        &self,
+        mask: &AttrMask,
+        reply: &AttrReply,
+        ok: u32,
+        median: Duration,
+    ) -> Vec<AttrAnomaly> {
+        let mut out = Vec::new();
+        if !median.is_zero() && reply.latency > median * LATENCY_FACTOR {
+            out.push(AttrAnomaly::Latency {
+                mask: mask.clone(),
+                got: reply.latency,
+                median,
+            });
+        }
+        let Some(singles) = mask
+            .bits
+            .iter()
+            .map(|b| self.singles.get(b))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return out;
+        };
+        let failed = singles.iter().find(|s| s.status != ok);
+        let expected_status = failed.map_or(ok, |s| s.status);
+        if (reply.status == ok) != failed.is_none() {
+            out.push(AttrAnomaly::Status {
+                mask: mask.clone(),
+                got: reply.status,
+                singles: expected_status,
+            });
+        } else if reply.status == ok {
+            let expected = singles.iter().map(|s| s.attr_len).sum();
+            if reply.attr_len != expected {
+                out.push(AttrAnomaly::Length {
+                    mask: mask.clone(),
+                    got: reply.attr_len,
+                    expected,
+                });
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::status::{NFS4ERR_INVAL, NFS4_OK};
+
+    #[test]
+    fn test_sweep_is_deterministic() {
+        let a = sweep_masks(7, 20, 6);
+        assert_eq!(a, sweep_masks(7, 20, 6));
+        assert_ne!(a, sweep_masks(8, 20, 6));
+        let singles = ATTR_MAX as usize + 1 + UNDEFINED_BITS.len();
+        assert_eq!(a.len(), singles + 20);
+        assert_eq!(a[4].name, "attrs_4");
+        assert_eq!(a[singles - 1].words().len(), 32);
+        let cases = getattr_cases(&a[..1]);
+        assert_eq!(cases[0].name, "getattr_attrs_0");
+        assert!(a[singles..].iter().all(|m| (2..=6).contains(&m.bits.len())));
+        assert_eq!(readdir_cases(&a).len(), a.len());
+    }
+
+    #[test]
+    fn test_recorder_flags_unexplained_combos() {
+        let reply = |status, attr_len, ms| AttrReply {
+            status,
+            attr_len,
+            latency: Duration::from_millis(ms),
+        };
+        let mut r = SweepRecorder::new();
+        r.record(&AttrMask::new(vec![1]), reply(NFS4_OK, 4, 1));
+        r.record(&AttrMask::new(vec![4]), reply(NFS4_OK, 8, 1));
+        r.record(&AttrMask::new(vec![9]), reply(NFS4ERR_INVAL, 0, 1));
+
+        r.record(&AttrMask::new(vec![1, 4]), reply(NFS4_OK, 12, 2));
+        r.record(&AttrMask::new(vec![4, 1, 4]), reply(NFS4_OK, 16, 1));
+        r.record(&AttrMask::new(vec![1, 9]), reply(NFS4_OK, 4, 1));
+        r.record(&AttrMask::new(vec![1, 60]), reply(NFS4_OK, 4, 50));
+
+        let found = r.anomalies(NFS4_OK);
+        assert_eq!(found.len(), 3, "{:?}", found);
+        assert!(matches!(
+            &found[0],
+            AttrAnomaly::Length {
+                expected: 12,
+                got: 16,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &found[1],
+            AttrAnomaly::Status {
+                singles: NFS4ERR_INVAL,
+                ..
+            }
+        ));
+        assert!(matches!(&found[2], AttrAnomaly::Latency { mask, .. } if mask.bits == [1, 60]));
+        let single = AttrMask::new(vec![9]);
+        assert!(r.judge(&single, &reply(NFS4_OK, 4, 1), NFS4_OK).is_empty());
+        let combo = AttrMask::new(vec![1, 4]);
+        let judged = r.judge(&combo, &reply(NFS4_OK, 16, 1), NFS4_OK);
+        assert!(matches!(&judged[..], [AttrAnomaly::Length { got: 16, .. }]));
+    }
+
+    #[test]
+    fn test_getattr_replies_decode_to_their_mask() {
+        use crate::generate::compound_args;
+        use crate::nfsv4::put_bitmap;
+        use crate::xdr::XdrEncoder;
+
+        let mask = AttrMask::new(vec![1, 33]);
+        let args = compound_args(&getattr_cases(std::slice::from_ref(&mask))[0].ops);
+        let results = |stat, vals: &[u8]| {
+            let mut enc = XdrEncoder::new();
+            enc.put_u32(stat);
+            enc.put_opaque(b"");
+            enc.put_u32(2);
+            enc.put_u32(op::PUTROOTFH);
+            enc.put_u32(NFS4_OK);
+            enc.put_u32(op::GETATTR);
+            enc.put_u32(stat);
+            if stat == NFS4_OK {
+                put_bitmap(&mut enc, &mask.words());
+                enc.put_opaque(vals);
+            }
+            enc.as_bytes().to_vec()
+        };
+        let latency = Duration::from_millis(3);
+        let (got, reply) = decode_getattr(&args, &results(NFS4_OK, &[0; 12]), latency).unwrap();
+        assert_eq!(got, mask);
+        assert_eq!((reply.status, reply.attr_len), (NFS4_OK, 12));
+        let (_, reply) = decode_getattr(&args, &results(NFS4ERR_INVAL, &[]), latency).unwrap();
+        assert_eq!((reply.status, reply.attr_len), (NFS4ERR_INVAL, 0));
+        assert!(decode_getattr(&args, &results(NFS4_OK, &[])[..12], latency).is_none());
+    }
+}
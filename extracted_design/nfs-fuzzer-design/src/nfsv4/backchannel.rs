@@ -0,0 +1,308 @@
+//! The client end of a v4.1 session backchannel (RFC 8881 §2.10.3.1, §20)
+//!
+//! A session created with [`CONN_BACK_CHAN`](super::session::CONN_BACK_CHAN)
+//! has the server send its CB_COMPOUNDs back down the creating
+//! connection, as calls to [`CB_PROGRAM`]. [`serve`] answers them there
+//! for as long as the connection lasts. CB_NULL and callbacks other than
+//! CB_NOTIFY_DEVICEID get well-formed replies, so the server keeps the
+//! callback path up; each CB_NOTIFY_DEVICEID gets the next of
+//! [`cb_notify_deviceid_replies`] in turn, most of them malformed, so the
+//! metadata server's handling of bad notification results is fuzzed
+//! whenever it notifies.
+
+use super::pnfs::{cb_notify_deviceid_replies, put_cb_sequence_ok, CbSequence};
+use super::{cb_op, status};
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::rpc::{accept_stat, auth_flavor, msg_type, reply_stat};
+use crate::xdr::{XdrDecoder, XdrEncoder};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{debug, info};
+
+/// The callback program number CREATE_SESSION asks the server to use
+pub const CB_PROGRAM: u32 = 0x4000_0000;
+
+/// Callback procedures
+pub mod cb_proc {
+    pub const CB_NULL: u32 = 0;
+    pub const CB_COMPOUND: u32 = 1;
+}
+
+/// A CB_COMPOUND as far as answering it needs
+#[derive(Debug, Clone)]
+pub struct CbCompound {
+    pub tag: Vec<u8>,
+    /// Operations in the argarray
+    pub count: u32,
+    /// The CB_SEQUENCE in front, if there is one
+    pub sequence: Option<CbSequence>,
+    /// The operation after the CB_SEQUENCE
+    pub next_op: Option<u32>,
+}
+
+/// Decode the CB_COMPOUND4args in `args`, up to the opcode after its
+/// CB_SEQUENCE
+pub fn decode_cb_compound(args: &[u8]) -> Option<CbCompound> {
+    let mut dec = XdrDecoder::new(args);
+    let tag = dec.get_opaque().ok()?.to_vec();
+    // minorversion, callback_ident
+    dec.get_u32().ok()?;
+    dec.get_u32().ok()?;
+    let count = dec.get_u32().ok()?;
+    let mut compound = CbCompound {
+        tag,
+        count,
+        sequence: None,
+        next_op: None,
+    };
+    if count == 0 || dec.get_u32().ok()? != cb_op::CB_SEQUENCE {
+        return Some(compound);
+    }
+    let sessionid = dec.get_opaque_fixed(16).ok()?.try_into().ok()?;
+    let sequenceid = dec.get_u32().ok()?;
+    let slotid = dec.get_u32().ok()?;
+    let highest_slotid = dec.get_u32().ok()?;
+    dec.get_bool().ok()?;
+    // csa_referring_call_lists: a session id and its (sequenceid, slotid)
+    // pairs each
+    for _ in 0..dec.get_u32().ok()? {
+        dec.get_opaque_fixed(16).ok()?;
+        let calls = dec.get_u32().ok()? as usize;
+        dec.get_raw(calls.checked_mul(8)?).ok()?;
+    }
+    compound.sequence = Some(CbSequence {
+        sessionid,
+        sequenceid,
+        slotid,
+        highest_slotid,
+        target_highest_slotid: highest_slotid,
+    });
+    if count > 1 {
+        compound.next_op = Some(dec.get_u32().ok()?);
+    }
+    Some(compound)
+}
+
+/// Callbacks whose results are a status alone, which can be answered
+/// without acting on them
+fn status_only(opcode: u32) -> bool {
+    matches!(
+        opcode,
+        cb_op::CB_RECALL..=cb_op::CB_RECALL_SLOT | cb_op::CB_WANTS_CANCELLED..=cb_op::CB_OFFLOAD
+    )
+}
+
+/// CB_COMPOUND4res for `compound` as a client that does nothing about
+/// it would answer: its CB_SEQUENCE, then NFS4_OK for a status-only
+/// callback and NFS4ERR_NOTSUPP for anything else, which stops there
+fn plain_results(compound: &CbCompound, seq: &CbSequence) -> Vec<u8> {
+    let next = compound.next_op.map(|opcode| match status_only(opcode) {
+        true => (opcode, status::NFS4_OK),
+        false => (opcode, status::NFS4ERR_NOTSUPP),
+    });
+    let mut enc = XdrEncoder::new();
+    enc.put_u32(next.map_or(status::NFS4_OK, |(_, st)| st));
+    enc.put_opaque(&compound.tag);
+    enc.put_u32(1 + next.is_some() as u32);
+    put_cb_sequence_ok(&mut enc, seq);
+    if let Some((opcode, st)) = next {
+        enc.put_u32(opcode);
+        enc.put_u32(st);
+    }
+    enc.as_bytes().to_vec()
+}
+
+/// An accepted reply to `xid` with `stat` and, on success, `results`
+fn reply(xid: u32, stat: u32, results: &[u8]) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    for word in [
+        xid,
+        msg_type::REPLY,
+        reply_stat::MSG_ACCEPTED,
+        auth_flavor::AUTH_NONE,
+        0,
+        stat,
+    ] {
+        enc.put_u32(word);
+    }
+    enc.put_raw(results);
+    enc.as_bytes().to_vec()
+}
+
+/// Answers to one session's callbacks
+#[derive(Debug, Default)]
+pub struct Backchannel {
+    /// CB_NOTIFY_DEVICEIDs answered so far, which picks the next reply
+    pub notified: usize,
+    /// Calls answered in all
+    pub answered: u64,
+}
+
+impl Backchannel {
+    /// The reply to `call`, an RPC call without its record mark, and
+    /// what it was; `None` for a message that is not a call
+    pub fn answer(&mut self, call: &[u8]) -> Option<(Vec<u8>, &'static str)> {
+        let mut dec = XdrDecoder::new(call);
+        let xid = dec.get_u32().ok()?;
+        if dec.get_u32().ok()? != msg_type::CALL {
+            return None;
+        }
+        self.answered += 1;
+        let mut header = || -> Option<(u32, u32)> {
+            dec.get_u32().ok()?;
+            let program = dec.get_u32().ok()?;
+            dec.get_u32().ok()?;
+            let procedure = dec.get_u32().ok()?;
+            // credentials and verifier
+            for _ in 0..2 {
+                dec.get_u32().ok()?;
+                dec.get_opaque().ok()?;
+            }
+            Some((program, procedure))
+        };
+        let answer = match header() {
+            None => (reply(xid, accept_stat::GARBAGE_ARGS, &[]), "garbage"),
+            Some((program, _)) if program != CB_PROGRAM => {
+                (reply(xid, accept_stat::PROG_UNAVAIL, &[]), "prog_unavail")
+            }
+            Some((_, cb_proc::CB_NULL)) => (reply(xid, accept_stat::SUCCESS, &[]), "cb_null"),
+            Some((_, cb_proc::CB_COMPOUND)) => self.compound(xid, dec.rest()),
+            Some(_) => (reply(xid, accept_stat::PROC_UNAVAIL, &[]), "proc_unavail"),
+        };
+        Some(answer)
+    }
+
+    fn compound(&mut self, xid: u32, args: &[u8]) -> (Vec<u8>, &'static str) {
+        let Some(compound) = decode_cb_compound(args) else {
+            return (reply(xid, accept_stat::GARBAGE_ARGS, &[]), "garbage");
+        };
+        let Some(seq) = &compound.sequence else {
+            let mut enc = XdrEncoder::new();
+            enc.put_u32(status::NFS4ERR_OP_NOT_IN_SESSION);
+            enc.put_opaque(&compound.tag);
+            enc.put_u32(0);
+            return (
+                reply(xid, accept_stat::SUCCESS, enc.as_bytes()),
+                "no_sequence",
+            );
+        };
+        if compound.next_op != Some(cb_op::CB_NOTIFY_DEVICEID) {
+            return (
+                reply(xid, accept_stat::SUCCESS, &plain_results(&compound, seq)),
+                "plain",
+            );
+        }
+        let mut replies = cb_notify_deviceid_replies(&compound.tag, seq);
+        let (name, results) = replies.swap_remove(self.notified % replies.len());
+        self.notified += 1;
+        (reply(xid, accept_stat::SUCCESS, &results), name)
+    }
+}
+
+/// Answer the callbacks that arrive on `conn`, the connection a session
+/// was created on with its backchannel, until the server closes it
+pub async fn serve<S>(mut conn: NfsConnection<S>) -> Result<Backchannel, ConnectionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut back = Backchannel::default();
+    loop {
+        let call = match conn.next_record().await {
+            Err(ConnectionError::Closed) => return Ok(back),
+            r => r?,
+        };
+        let Some((reply, name)) = back.answer(&call) else {
+            continue;
+        };
+        match name {
+            "plain" | "cb_null" => debug!("Backchannel: answered a callback"),
+            name => info!("Backchannel: answered a callback with {}", name),
+        }
+        let mut record = (0x8000_0000 | reply.len() as u32).to_be_bytes().to_vec();
+        record.extend_from_slice(&reply);
+        conn.send(&record).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::RpcReply;
+
+    /// A CB_COMPOUND call of CB_SEQUENCE then `next`, with no args
+    /// after the opcode
+    fn cb_call(xid: u32, next: Option<u32>) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        for word in [
+            xid,
+            msg_type::CALL,
+            2,
+            CB_PROGRAM,
+            1,
+            cb_proc::CB_COMPOUND,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            enc.put_u32(word);
+        }
+        enc.put_opaque(b"cb");
+        enc.put_u32(1);
+        enc.put_u32(0);
+        enc.put_u32(1 + next.is_some() as u32);
+        enc.put_u32(cb_op::CB_SEQUENCE);
+        enc.put_opaque_fixed(&[0x5e; 16]);
+        for word in [7, 0, 0, 0] {
+            enc.put_u32(word);
+        }
+        // one referring call list with one call
+        enc.put_u32(1);
+        enc.put_opaque_fixed(&[0x5e; 16]);
+        for word in [1, 3, 0] {
+            enc.put_u32(word);
+        }
+        if let Some(opcode) = next {
+            enc.put_u32(opcode);
+        }
+        enc.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_decode_cb_compound() {
+        let call = cb_call(9, Some(cb_op::CB_NOTIFY_DEVICEID));
+        let compound = decode_cb_compound(&call[40..]).unwrap();
+        assert_eq!((compound.tag.as_slice(), compound.count), (&b"cb"[..], 2));
+        assert_eq!(compound.next_op, Some(cb_op::CB_NOTIFY_DEVICEID));
+        assert_eq!(compound.sequence.unwrap().sequenceid, 7);
+    }
+
+    #[test]
+    fn test_answers() {
+        let mut back = Backchannel::default();
+        let (plain, name) = back.answer(&cb_call(1, Some(cb_op::CB_RECALL))).unwrap();
+        assert_eq!(name, "plain");
+        let results = RpcReply::parse(&plain)
+            .unwrap()
+            .into_results()
+            .unwrap()
+            .to_vec();
+        // status, tag, count 2, then CB_RECALL's NFS4_OK at the end
+        assert_eq!(results[..4], [0; 4]);
+        assert_eq!(results[results.len() - 8..], [0, 0, 0, 4, 0, 0, 0, 0]);
+
+        let names: Vec<_> = (0..9)
+            .map(|xid| {
+                back.answer(&cb_call(xid, Some(cb_op::CB_NOTIFY_DEVICEID)))
+                    .unwrap()
+                    .1
+            })
+            .collect();
+        assert_eq!(names[0], "ok");
+        assert_eq!(names[8], "ok");
+        assert_eq!(back.notified, 9);
+
+        let (garbage, _) = back.answer(&cb_call(2, None)[..30]).unwrap();
+        assert_eq!(garbage[20..24], accept_stat::GARBAGE_ARGS.to_be_bytes());
+        assert!(back.answer(&[0, 0, 0, 1, 0, 0, 0, 1]).is_none());
+    }
+}
@@ -0,0 +1,308 @@
+//! Delegation requests (RFC 8881 §10.4, §18.39, §18.49)
+//!
+//! v4.1 lets clients ask for delegations explicitly: "want" flags packed
+//! into OPEN's share_access, the WANT_DELEGATION operation, and directory
+//! delegations via GET_DIR_DELEGATION. All three are optional and rarely
+//! exercised, so the cases here lean on combinations the spec forbids.
+
+use super::open::{
+    claim_type, delegation_type, share_access, share_deny, CreateHow, Open, OpenClaim,
+};
+use super::{bitmap_from_bits, op, put_bitmap, put_nfstime, Fattr, FuzzCase, Op, Stateid};
+
+/// Directory notification bit numbers (notify_type4)
+pub mod notify_type {
+    pub const CHANGE_CHILD_ATTRS: u32 = 0;
+    pub const CHANGE_DIR_ATTRS: u32 = 1;
+    pub const REMOVE_ENTRY: u32 = 2;
+    pub const ADD_ENTRY: u32 = 3;
+    pub const RENAME_ENTRY: u32 = 4;
+    pub const CHANGE_COOKIE_VERIFIER: u32 = 5;
+}
+
+/// WANT_DELEGATION claim (deleg_claim4)
+#[derive(Debug, Clone)]
+pub enum DelegClaim {
+    Fh,
+    DelegPrevFh,
+    Previous(u32),
+    /// Any other discriminant, which has a void arm
+    Other(u32),
+}
+
+/// Encode WANT_DELEGATION4args
+pub fn want_delegation(want: u32, claim: &DelegClaim) -> Op {
+    Op::new(op::WANT_DELEGATION, |enc| {
+        enc.put_u32(want);
+        match claim {
+            DelegClaim::Fh => enc.put_u32(claim_type::FH),
+            DelegClaim::DelegPrevFh => enc.put_u32(claim_type::DELEG_PREV_FH),
+            DelegClaim::Previous(deleg) => {
+                enc.put_u32(claim_type::PREVIOUS);
+                enc.put_u32(*deleg);
+            }
+            DelegClaim::Other(discriminant) => enc.put_u32(*discriminant),
+        }
+    })
+}
+
+/// GET_DIR_DELEGATION4args
+#[derive(Debug, Clone)]
+pub struct GetDirDelegation {
+    pub signal_deleg_avail: bool,
+    pub notification_types: Vec<u32>,
+    pub child_attr_delay: (i64, u32),
+    pub dir_attr_delay: (i64, u32),
+    pub child_attributes: Vec<u32>,
+    pub dir_attributes: Vec<u32>,
+}
+
+impl Default for GetDirDelegation {
+    fn default() -> Self {
+        Self {
+            signal_deleg_avail: false,
+            notification_types: bitmap_from_bits(&[
+                notify_type::REMOVE_ENTRY,
+                notify_type::ADD_ENTRY,
+                notify_type::RENAME_ENTRY,
+            ]),
+            child_attr_delay: (0, 0),
+            dir_attr_delay: (0, 0),
+            child_attributes: Vec::new(),
+            dir_attributes: Vec::new(),
+        }
+    }
+}
+
+impl GetDirDelegation {
+    pub fn op(&self) -> Op {
+        Op::new(op::GET_DIR_DELEGATION, |enc| {
+            enc.put_bool(self.signal_deleg_avail);
+            put_bitmap(enc, &self.notification_types);
+            put_nfstime(enc, self.child_attr_delay.0, self.child_attr_delay.1);
+            put_nfstime(enc, self.dir_attr_delay.0, self.dir_attr_delay.1);
+            put_bitmap(enc, &self.child_attributes);
+            put_bitmap(enc, &self.dir_attributes);
+        })
+    }
+}
+
+/// OPENs whose delegation wants contradict their access, deny mode or claim
+pub fn open_want_cases(clientid: u64, owner: &[u8], name: &[u8]) -> Vec<FuzzCase> {
+    use share_access::*;
+    let open = |label: &str, access: u32, deny: u32| {
+        let mut o = Open::existing(clientid, owner, name);
+        o.share_access = access;
+        o.share_deny = deny;
+        (label.to_string(), o)
+    };
+
+    let mut opens = vec![
+        open(
+            "write_deleg_read_access",
+            READ | WANT_WRITE_DELEG,
+            share_deny::NONE,
+        ),
+        open(
+            "read_deleg_deny_read",
+            READ | WANT_READ_DELEG,
+            share_deny::READ,
+        ),
+        open(
+            "write_deleg_deny_both",
+            BOTH | WANT_WRITE_DELEG,
+            share_deny::BOTH,
+        ),
+        open(
+            "no_deleg_with_signal",
+            BOTH | WANT_NO_DELEG | WANT_SIGNAL_DELEG_WHEN_RESRC_AVAIL,
+            share_deny::NONE,
+        ),
+        open(
+            "no_deleg_with_push",
+            BOTH | WANT_NO_DELEG | WANT_PUSH_DELEG_WHEN_UNCONTENDED,
+            share_deny::NONE,
+        ),
+        open("cancel_on_open", BOTH | WANT_CANCEL, share_deny::NONE),
+        open("want_without_access", WANT_ANY_DELEG, share_deny::NONE),
+        open("undefined_want_0600", BOTH | 0x0600, share_deny::NONE),
+        open(
+            "undefined_want_ff00",
+            BOTH | WANT_DELEG_MASK,
+            share_deny::NONE,
+        ),
+        open("undefined_high_bits", BOTH | 0xfff0_0000, share_deny::NONE),
+        open("all_bits", 0xffff_ffff, 0xffff_ffff),
+    ];
+
+    let (label, mut o) = open(
+        "previous_claim_no_deleg",
+        BOTH | WANT_NO_DELEG,
+        share_deny::NONE,
+    );
+    o.claim = OpenClaim::Previous(delegation_type::WRITE);
+    opens.push((label, o));
+
+    let (label, mut o) = open(
+        "deleg_cur_claim_wants_read",
+        READ | WANT_READ_DELEG,
+        share_deny::NONE,
+    );
+    o.claim = OpenClaim::DelegateCur(Stateid::ANONYMOUS, name.to_vec());
+    opens.push((label, o));
+
+    let (label, mut o) = open(
+        "claim_fh_wants_any",
+        BOTH | WANT_ANY_DELEG,
+        share_deny::BOTH,
+    );
+    o.claim = OpenClaim::Fh;
+    opens.push((label, o));
+
+    let (label, mut o) = open(
+        "exclusive_create_wants_write_deny_both",
+        BOTH | WANT_WRITE_DELEG,
+        share_deny::BOTH,
+    );
+    o.create = Some(CreateHow::Exclusive41([0x5a; 8], Fattr::default()));
+    opens.push((label, o));
+
+    opens
+        .into_iter()
+        .map(|(label, o)| FuzzCase::new(format!("open_want_{}", label), vec![o.op()]))
+        .collect()
+}
+
+/// WANT_DELEGATION with every want value and contradictory claims
+pub fn want_delegation_cases() -> Vec<FuzzCase> {
+    use share_access::*;
+    let mut cases = Vec::new();
+    for want in [
+        WANT_NO_PREFERENCE,
+        WANT_READ_DELEG,
+        WANT_WRITE_DELEG,
+        WANT_ANY_DELEG,
+        WANT_NO_DELEG,
+        WANT_CANCEL,
+        0x0600,
+        WANT_DELEG_MASK,
+    ] {
+        cases.push(FuzzCase::new(
+            format!("want_delegation_{:#06x}_fh", want),
+            vec![want_delegation(want, &DelegClaim::Fh)],
+        ));
+    }
+
+    let contradictory: Vec<(&str, u32, DelegClaim)> = vec![
+        ("share_bits_in_want", BOTH | WANT_READ_DELEG, DelegClaim::Fh),
+        (
+            "cancel_with_signal",
+            WANT_CANCEL | WANT_SIGNAL_DELEG_WHEN_RESRC_AVAIL,
+            DelegClaim::Fh,
+        ),
+        (
+            "no_deleg_with_push",
+            WANT_NO_DELEG | WANT_PUSH_DELEG_WHEN_UNCONTENDED,
+            DelegClaim::Fh,
+        ),
+        (
+            "previous_none_ext",
+            WANT_READ_DELEG,
+            DelegClaim::Previous(delegation_type::NONE_EXT),
+        ),
+        (
+            "previous_bogus_type",
+            WANT_ANY_DELEG,
+            DelegClaim::Previous(0xffff_ffff),
+        ),
+        ("cancel_prev_fh", WANT_CANCEL, DelegClaim::DelegPrevFh),
+        ("claim_null_void", WANT_READ_DELEG, DelegClaim::Other(0)),
+        (
+            "claim_deleg_cur_void",
+            WANT_WRITE_DELEG,
+            DelegClaim::Other(2),
+        ),
+        ("claim_undefined", WANT_ANY_DELEG, DelegClaim::Other(7)),
+        ("claim_max", WANT_ANY_DELEG, DelegClaim::Other(0xffff_ffff)),
+    ];
+    for (label, want, claim) in contradictory {
+        cases.push(FuzzCase::new(
+            format!("want_delegation_{}", label),
+            vec![want_delegation(want, &claim)],
+        ));
+    }
+
+    // Ask twice in one compound, then cancel and ask again
+    cases.push(FuzzCase::new(
+        "want_delegation_ask_cancel_ask",
+        vec![
+            want_delegation(WANT_WRITE_DELEG, &DelegClaim::Fh),
+            want_delegation(WANT_CANCEL, &DelegClaim::Fh),
+            want_delegation(WANT_READ_DELEG, &DelegClaim::Fh),
+        ],
+    ));
+    cases
+}
+
+/// GET_DIR_DELEGATION with hostile notification masks, delays and attrs
+pub fn get_dir_delegation_cases() -> Vec<FuzzCase> {
+    let variant = |name: &str, f: &dyn Fn(&mut GetDirDelegation)| {
+        let mut g = GetDirDelegation::default();
+        f(&mut g);
+        FuzzCase::new(format!("get_dir_delegation_{}", name), vec![g.op()])
+    };
+
+    vec![
+        variant("baseline", &|_| {}),
+        variant("no_notifications", &|g| g.notification_types.clear()),
+        variant("all_notifications", &|g| {
+            g.notification_types = bitmap_from_bits(&(0..=5).collect::<Vec<_>>());
+            g.child_attributes = vec![0xffff_ffff; 3];
+            g.dir_attributes = vec![0xffff_ffff; 3];
+        }),
+        variant("undefined_notifications", &|g| {
+            g.notification_types = vec![0xffff_ffc0];
+        }),
+        variant("huge_bitmaps", &|g| {
+            g.notification_types = vec![0xffff_ffff; 1024];
+            g.child_attributes = vec![0xffff_ffff; 1024];
+            g.dir_attributes = vec![0xffff_ffff; 1024];
+        }),
+        variant("negative_delays", &|g| {
+            g.child_attr_delay = (-1, 0);
+            g.dir_attr_delay = (i64::MIN, 0);
+        }),
+        variant("nsec_overflow_delays", &|g| {
+            g.child_attr_delay = (0, 1_000_000_000);
+            g.dir_attr_delay = (i64::MAX, u32::MAX);
+        }),
+        variant("attr_delay_without_attr_notify", &|g| {
+            g.child_attr_delay = (3600, 0);
+            g.child_attributes = vec![0xffff_ffff, 0xffff_ffff];
+        }),
+        variant("signal_with_no_notifications", &|g| {
+            g.signal_deleg_avail = true;
+            g.notification_types.clear();
+        }),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_want_delegation_encoding() {
+        let op = want_delegation(share_access::WANT_READ_DELEG, &DelegClaim::Previous(1));
+        assert_eq!(op.args, vec![0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 1]);
+        let op = want_delegation(0, &DelegClaim::Other(7));
+        assert_eq!(op.args.len(), 8);
+    }
+
+    #[test]
+    fn test_get_dir_delegation_default_len() {
+        let op = GetDirDelegation::default().op();
+        // bool (4) + notify bitmap (8) + two nfstime4 (24) + two empty bitmaps (8)
+        assert_eq!(op.args.len(), 44);
+    }
+}
@@ -0,0 +1,449 @@
+//! Flexible File layout bodies (RFC 8435)
+//!
+//! The flex-files layout type carries its own XDR inside the opaque layout
+//! bodies of LAYOUTGET, GETDEVICEINFO and LAYOUTRETURN: mirror arrays of
+//! data servers with per-server filehandles and synthetic owner strings,
+//! and device addresses as universal address strings. Servers (pNFS MDS)
+//! parse the return body; clients parse the layout and device address.
+
+use super::pnfs::{layout_iomode, layout_type, layoutreturn_file, DeviceId, GrantedLayout};
+use super::{status, FuzzCase, Stateid, BOUNDARY_COUNTS};
+use crate::xdr::XdrEncoder;
+
+/// Flags in ffl_flags (ffl_flags4)
+pub mod ff_flags {
+    pub const NO_LAYOUTCOMMIT: u32 = 0x1;
+    pub const NO_IO_THRU_MDS: u32 = 0x2;
+    pub const NO_READ_IO: u32 = 0x4;
+    pub const WRITE_ONE_MIRROR: u32 = 0x8;
+}
+
+/// A network address (netaddr4): netid plus universal address
+#[derive(Debug, Clone)]
+pub struct NetAddr {
+    pub netid: String,
+    pub uaddr: String,
+}
+
+impl NetAddr {
+    /// IPv4 universal address "h1.h2.h3.h4.p1.p2"
+    pub fn tcp(ip: [u8; 4], port: u16) -> Self {
+        Self {
+            netid: "tcp".to_string(),
+            uaddr: format!(
+                "{}.{}.{}.{}.{}.{}",
+                ip[0],
+                ip[1],
+                ip[2],
+                ip[3],
+                port >> 8,
+                port & 0xff
+            ),
+        }
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_string(&self.netid);
+        enc.put_string(&self.uaddr);
+    }
+}
+
+/// One data server in a mirror (ff_data_server4)
+#[derive(Debug, Clone)]
+pub struct DataServer {
+    pub deviceid: DeviceId,
+    pub efficiency: u32,
+    pub stateid: Stateid,
+    pub fh_versions: Vec<Vec<u8>>,
+    pub user: Vec<u8>,
+    pub group: Vec<u8>,
+}
+
+impl DataServer {
+    pub fn new(deviceid: DeviceId, fh: Vec<u8>) -> Self {
+        Self {
+            deviceid,
+            efficiency: 1,
+            stateid: Stateid::ANONYMOUS,
+            fh_versions: vec![fh],
+            user: b"0".to_vec(),
+            group: b"0".to_vec(),
+        }
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_opaque_fixed(&self.deviceid);
+        enc.put_u32(self.efficiency);
+        self.stateid.encode(enc);
+        enc.put_u32(self.fh_versions.len() as u32);
+        for fh in &self.fh_versions {
+            enc.put_opaque(fh);
+        }
+        enc.put_opaque(&self.user);
+        enc.put_opaque(&self.group);
+    }
+}
+
+/// Flex-files layout body (ff_layout4)
+#[derive(Debug, Clone)]
+pub struct FlexLayout {
+    pub stripe_unit: u64,
+    pub mirrors: Vec<Vec<DataServer>>,
+    pub flags: u32,
+    pub stats_collect_hint: u32,
+}
+
+impl FlexLayout {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        enc.put_u64(self.stripe_unit);
+        enc.put_u32(self.mirrors.len() as u32);
+        for mirror in &self.mirrors {
+            enc.put_u32(mirror.len() as u32);
+            for ds in mirror {
+                ds.encode(&mut enc);
+            }
+        }
+        enc.put_u32(self.flags);
+        enc.put_u32(self.stats_collect_hint);
+        enc.into_bytes().to_vec()
+    }
+}
+
+/// Synthetic owner/group strings: numeric ids at the edges and odd forms
+pub const SYNTHETIC_IDS: &[&[u8]] = &[
+    b"0",
+    b"-1",
+    b"4294967295",
+    b"4294967296",
+    b"99999999999999999999",
+    b"0x0",
+    b"root",
+    b"root@",
+    b"@localdomain",
+    b"nobody@nowhere.invalid",
+    b"",
+    b"0\x00root",
+    b"\xff\xfe",
+];
+
+/// Universal addresses that break naive "h.h.h.h.p.p" parsers
+pub fn hostile_netaddrs() -> Vec<(&'static str, NetAddr)> {
+    let addr = |netid: &str, uaddr: &str| NetAddr {
+        netid: netid.to_string(),
+        uaddr: uaddr.to_string(),
+    };
+    vec![
+        ("loopback", NetAddr::tcp([127, 0, 0, 1], 2049)),
+        ("port_zero", NetAddr::tcp([127, 0, 0, 1], 0)),
+        ("octet_overflow", addr("tcp", "256.0.0.1.8.1")),
+        ("port_overflow", addr("tcp", "127.0.0.1.999.999")),
+        ("missing_port", addr("tcp", "127.0.0.1")),
+        ("extra_fields", addr("tcp", "127.0.0.1.8.1.8.1")),
+        ("negative", addr("tcp", "-1.-1.-1.-1.-1.-1")),
+        ("empty", addr("tcp", "")),
+        ("dots_only", addr("tcp", ".....")),
+        ("ipv6_under_tcp", addr("tcp", "::1.8.1")),
+        ("tcp6_bad", addr("tcp6", ":::::::::1.8.1")),
+        ("unknown_netid", addr("xprt_bogus", "127.0.0.1.8.1")),
+        ("rdma", addr("rdma", "127.0.0.1.79.235")),
+        ("long", addr("tcp", &"1.".repeat(4096))),
+        ("embedded_nul", addr("tcp", "127.0.0.1\0.8.1")),
+    ]
+}
+
+/// Flex-files device address bodies (ff_device_addr4) for GETDEVICEINFO
+/// replies, one per hostile address plus malformed version arrays
+pub fn device_addr_bodies() -> Vec<(String, Vec<u8>)> {
+    let body = |addrs: &[&NetAddr], versions: &[(u32, u32, u32, u32, bool)]| {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(addrs.len() as u32);
+        for a in addrs {
+            a.encode(&mut enc);
+        }
+        enc.put_u32(versions.len() as u32);
+        for &(vers, minor, rsize, wsize, tightly_coupled) in versions {
+            enc.put_u32(vers);
+            enc.put_u32(minor);
+            enc.put_u32(rsize);
+            enc.put_u32(wsize);
+            enc.put_bool(tightly_coupled);
+        }
+        enc.into_bytes().to_vec()
+    };
+    let v3 = (3, 0, 1 << 20, 1 << 20, false);
+
+    let mut out = Vec::new();
+    for (label, addr) in hostile_netaddrs() {
+        out.push((format!("addr_{}", label), body(&[&addr], &[v3])));
+    }
+
+    let lo = NetAddr::tcp([127, 0, 0, 1], 2049);
+    out.push(("no_addrs".to_string(), body(&[], &[v3])));
+    out.push(("no_versions".to_string(), body(&[&lo], &[])));
+    out.push(("many_addrs".to_string(), body(&vec![&lo; 1024], &[v3])));
+    out.push((
+        "zero_rsize_wsize".to_string(),
+        body(&[&lo], &[(3, 0, 0, 0, false)]),
+    ));
+    out.push((
+        "bogus_version".to_string(),
+        body(
+            &[&lo],
+            &[(0xffff_ffff, 0xffff_ffff, u32::MAX, u32::MAX, true)],
+        ),
+    ));
+    out
+}
+
+/// Flex-files layout bodies (ff_layout4) with mutated mirror arrays and
+/// synthetic uid/gid strings, for LAYOUTGET replies
+pub fn layout_bodies(deviceid: &DeviceId, fh: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let ds = DataServer::new(*deviceid, fh.to_vec());
+    let layout = |mirrors: Vec<Vec<DataServer>>| FlexLayout {
+        stripe_unit: 0,
+        mirrors,
+        flags: 0,
+        stats_collect_hint: 0,
+    };
+
+    let mut out = vec![
+        (
+            "baseline".to_string(),
+            layout(vec![vec![ds.clone()]]).encode(),
+        ),
+        ("no_mirrors".to_string(), layout(vec![]).encode()),
+        ("empty_mirror".to_string(), layout(vec![vec![]]).encode()),
+        (
+            "many_mirrors".to_string(),
+            layout(vec![vec![ds.clone()]; 4096]).encode(),
+        ),
+        (
+            "many_data_servers".to_string(),
+            layout(vec![vec![ds.clone(); 4096]]).encode(),
+        ),
+        (
+            "mirror_mismatched_fanout".to_string(),
+            layout(vec![vec![ds.clone()], vec![ds.clone(); 3], vec![]]).encode(),
+        ),
+    ];
+
+    let mut no_fh = ds.clone();
+    no_fh.fh_versions.clear();
+    out.push((
+        "no_fh_versions".to_string(),
+        layout(vec![vec![no_fh]]).encode(),
+    ));
+
+    let mut big_fh = ds.clone();
+    big_fh.fh_versions = vec![vec![0xaa; 129], vec![]];
+    out.push((
+        "oversized_fh".to_string(),
+        layout(vec![vec![big_fh]]).encode(),
+    ));
+
+    let mut max_eff = ds.clone();
+    max_eff.efficiency = u32::MAX;
+    out.push((
+        "max_efficiency".to_string(),
+        layout(vec![vec![max_eff]]).encode(),
+    ));
+
+    for (i, id) in SYNTHETIC_IDS.iter().enumerate() {
+        let mut d = ds.clone();
+        d.user = id.to_vec();
+        d.group = SYNTHETIC_IDS[SYNTHETIC_IDS.len() - 1 - i].to_vec();
+        out.push((
+            format!("synthetic_ids_{}", i),
+            layout(vec![vec![d]]).encode(),
+        ));
+    }
+
+    let mut flagged = layout(vec![vec![ds.clone()]]);
+    flagged.flags = 0xffff_ffff;
+    flagged.stripe_unit = u64::MAX;
+    out.push(("all_flags_max_stripe".to_string(), flagged.encode()));
+
+    // Mirror count claims more entries than are present
+    let mut lying = XdrEncoder::new();
+    lying.put_u64(0);
+    lying.put_u32(0xffff_ffff);
+    lying.put_u32(1);
+    ds.encode(&mut lying);
+    out.push((
+        "mirror_count_overflow".to_string(),
+        lying.into_bytes().to_vec(),
+    ));
+
+    out
+}
+
+/// Per-device error report entry (device_error4)
+#[derive(Debug, Clone)]
+pub struct DeviceError {
+    pub deviceid: DeviceId,
+    pub status: u32,
+    pub opnum: u32,
+}
+
+/// Encode a flex-files LAYOUTRETURN body (ff_layoutreturn4)
+///
+/// Only I/O error reports are populated; the stats array is left empty
+/// unless `stats_count` forces a (lying) count.
+pub fn layoutreturn_body(
+    offset: u64,
+    length: u64,
+    stateid: &Stateid,
+    errors: &[DeviceError],
+    stats_count: u32,
+) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    enc.put_u32(1); // fflr_ioerr_report count
+    enc.put_u64(offset);
+    enc.put_u64(length);
+    stateid.encode(&mut enc);
+    enc.put_u32(errors.len() as u32);
+    for e in errors {
+        enc.put_opaque_fixed(&e.deviceid);
+        enc.put_u32(e.status);
+        enc.put_u32(e.opnum);
+    }
+    enc.put_u32(stats_count);
+    enc.into_bytes().to_vec()
+}
+
+/// LAYOUTRETURNs of a granted flex-files layout with malformed bodies
+pub fn layoutreturn_cases(granted: &GrantedLayout, devices: &[DeviceId]) -> Vec<FuzzCase> {
+    let dev = devices.first().copied().unwrap_or([0; 16]);
+    let ret = |name: String, body: Vec<u8>| {
+        FuzzCase::new(
+            name,
+            vec![layoutreturn_file(
+                false,
+                layout_type::FLEX_FILES,
+                layout_iomode::RW,
+                granted.offset,
+                granted.length,
+                &granted.stateid,
+                &body,
+            )],
+        )
+    };
+    let err = |status, opnum| DeviceError {
+        deviceid: dev,
+        status,
+        opnum,
+    };
+
+    let mut cases = vec![
+        ret(
+            "ff_return_io_error".to_string(),
+            layoutreturn_body(
+                granted.offset,
+                granted.length,
+                &granted.stateid,
+                &[err(status::NFS4ERR_IO, super::op::WRITE)],
+                0,
+            ),
+        ),
+        ret(
+            "ff_return_unknown_device".to_string(),
+            layoutreturn_body(
+                granted.offset,
+                granted.length,
+                &granted.stateid,
+                &[DeviceError {
+                    deviceid: [0xee; 16],
+                    status: status::NFS4ERR_IO,
+                    opnum: super::op::READ,
+                }],
+                0,
+            ),
+        ),
+        ret(
+            "ff_return_bogus_status_opnum".to_string(),
+            layoutreturn_body(
+                granted.offset,
+                granted.length,
+                &granted.stateid,
+                &[err(0xffff_ffff, 0xffff_ffff)],
+                0,
+            ),
+        ),
+        ret(
+            "ff_return_error_outside_range".to_string(),
+            layoutreturn_body(
+                granted.end(),
+                u64::MAX,
+                &granted.stateid,
+                &[err(status::NFS4ERR_IO, super::op::WRITE)],
+                0,
+            ),
+        ),
+        ret(
+            "ff_return_many_errors".to_string(),
+            layoutreturn_body(
+                granted.offset,
+                granted.length,
+                &granted.stateid,
+                &vec![err(status::NFS4ERR_IO, super::op::WRITE); 8192],
+                0,
+            ),
+        ),
+        ret("ff_return_truncated_body".to_string(), vec![0, 0, 0, 1]),
+        ret("ff_return_garbage_body".to_string(), vec![0xff; 64]),
+    ];
+    for &count in BOUNDARY_COUNTS.iter().filter(|&&c| c > 1) {
+        cases.push(ret(
+            format!("ff_return_stats_count_{:#x}", count),
+            layoutreturn_body(granted.offset, granted.length, &granted.stateid, &[], count),
+        ));
+    }
+
+    // Per-mirror device list drawn from observed devices, repeated
+    if devices.len() > 1 {
+        let errors: Vec<DeviceError> = devices
+            .iter()
+            .chain(devices.iter())
+            .map(|&d| DeviceError {
+                deviceid: d,
+                status: status::NFS4ERR_IO,
+                opnum: super::op::WRITE,
+            })
+            .collect();
+        cases.push(ret(
+            "ff_return_duplicate_devices".to_string(),
+            layoutreturn_body(granted.offset, granted.length, &granted.stateid, &errors, 0),
+        ));
+    }
+    cases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netaddr_tcp() {
+        let a = NetAddr::tcp([10, 0, 0, 1], 2049);
+        assert_eq!(a.uaddr, "10.0.0.1.8.1");
+    }
+
+    #[test]
+    fn test_layout_baseline_encoding() {
+        let bodies = layout_bodies(&[1; 16], &[2; 8]);
+        let baseline = &bodies.iter().find(|(n, _)| n == "baseline").unwrap().1;
+        // stripe unit (8) + mirror count (4) + ds count (4)
+        // + deviceid (16) + efficiency (4) + stateid (16) + fh array (4 + 4 + 8)
+        // + user "0" (8) + group "0" (8) + flags (4) + stats hint (4)
+        assert_eq!(baseline.len(), 8 + 4 + 4 + 16 + 4 + 16 + 16 + 8 + 8 + 4 + 4);
+    }
+
+    #[test]
+    fn test_layoutreturn_body_counts() {
+        let body = layoutreturn_body(0, 10, &Stateid::ANONYMOUS, &[], 7);
+        // ioerr count, offset, length, stateid, error count, stats count
+        assert_eq!(body.len(), 4 + 16 + 16 + 4 + 4);
+        assert_eq!(&body[body.len() - 4..], &[0, 0, 0, 7]);
+    }
+}
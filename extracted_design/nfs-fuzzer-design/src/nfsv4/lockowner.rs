@@ -0,0 +1,287 @@
+//! Lock-owner lifetime (RFC 8881 §18.10, §18.12, RFC 7530 §16.37)
+//!
+//! Lock-owners live in a server-side table from the first LOCK until
+//! RELEASE_LOCKOWNER or lease expiry, and each one pins the lock
+//! stateids created under it. Cases release owners that still hold
+//! locks, keep using owners and stateids after the release, and create
+//! tens of thousands of distinct owners to see whether the table grows
+//! without bound or is cleaned up.
+
+use super::{op, FuzzCase, Op, Stateid};
+use crate::xdr::XdrEncoder;
+
+/// nfs_lock_type4
+pub mod lock_type {
+    pub const READ: u32 = 1;
+    pub const WRITE: u32 = 2;
+    pub const READW: u32 = 3;
+    pub const WRITEW: u32 = 4;
+}
+
+/// Byte range length meaning "to the end of the file"
+pub const TO_EOF: u64 = u64::MAX;
+
+/// lock_owner4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockOwner {
+    pub clientid: u64,
+    pub owner: Vec<u8>,
+}
+
+impl LockOwner {
+    pub fn new(clientid: u64, owner: impl Into<Vec<u8>>) -> Self {
+        Self {
+            clientid,
+            owner: owner.into(),
+        }
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_u64(self.clientid);
+        enc.put_opaque(&self.owner);
+    }
+}
+
+/// locker4: a new lock-owner tied to an open, or an existing lock stateid
+#[derive(Debug, Clone)]
+pub enum Locker {
+    New {
+        open_seqid: u32,
+        open_stateid: Stateid,
+        lock_seqid: u32,
+        owner: LockOwner,
+    },
+    Existing {
+        lock_stateid: Stateid,
+        lock_seqid: u32,
+    },
+}
+
+impl Locker {
+    /// First lock for `owner` under `open_stateid`, v4.1 seqids
+    pub fn new_owner(open_stateid: &Stateid, owner: &LockOwner) -> Self {
+        Locker::New {
+            open_seqid: 0,
+            open_stateid: *open_stateid,
+            lock_seqid: 0,
+            owner: owner.clone(),
+        }
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        match self {
+            Locker::New {
+                open_seqid,
+                open_stateid,
+                lock_seqid,
+                owner,
+            } => {
+                enc.put_bool(true);
+                enc.put_u32(*open_seqid);
+                open_stateid.encode(enc);
+                enc.put_u32(*lock_seqid);
+                owner.encode(enc);
+            }
+            Locker::Existing {
+                lock_stateid,
+                lock_seqid,
+            } => {
+                enc.put_bool(false);
+                lock_stateid.encode(enc);
+                enc.put_u32(*lock_seqid);
+            }
+        }
+    }
+}
+
+/// Encode LOCK4args
+pub fn lock(locktype: u32, reclaim: bool, offset: u64, length: u64, locker: &Locker) -> Op {
+    Op::new(op::LOCK, |enc| {
+        enc.put_u32(locktype);
+        enc.put_bool(reclaim);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+        locker.encode(enc);
+    })
+}
+
+/// Encode LOCKU4args
+pub fn locku(locktype: u32, seqid: u32, stateid: &Stateid, offset: u64, length: u64) -> Op {
+    Op::new(op::LOCKU, |enc| {
+        enc.put_u32(locktype);
+        enc.put_u32(seqid);
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+    })
+}
+
+/// Encode LOCKT4args
+pub fn lockt(locktype: u32, offset: u64, length: u64, owner: &LockOwner) -> Op {
+    Op::new(op::LOCKT, |enc| {
+        enc.put_u32(locktype);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+        owner.encode(enc);
+    })
+}
+
+/// Encode RELEASE_LOCKOWNER4args
+pub fn release_lockowner(owner: &LockOwner) -> Op {
+    Op::new(op::RELEASE_LOCKOWNER, |enc| owner.encode(enc))
+}
+
+/// Release with locks held, twice, before any lock, and unlock after
+///
+/// The lock stateid is carried with the v4.1 current stateid, which
+/// RELEASE_LOCKOWNER leaves untouched, so the ops after a release act on
+/// the released owner's stateid.
+pub fn release_cases(open_stateid: &Stateid, owner: &LockOwner) -> Vec<FuzzCase> {
+    let current = Stateid::CURRENT;
+    let take = || {
+        lock(
+            lock_type::WRITE,
+            false,
+            0,
+            TO_EOF,
+            &Locker::new_owner(open_stateid, owner),
+        )
+    };
+    let unlock = || locku(lock_type::WRITE, 0, &current, 0, TO_EOF);
+    let release = || release_lockowner(owner);
+    vec![
+        // Must fail with NFS4ERR_LOCKS_HELD
+        FuzzCase::new("release_locks_held", vec![take(), release()]),
+        FuzzCase::new("release_then_unlock", vec![take(), release(), unlock()]),
+        FuzzCase::new("release_unlocked", vec![take(), unlock(), release()]),
+        FuzzCase::new(
+            "release_twice",
+            vec![take(), unlock(), release(), release()],
+        ),
+        FuzzCase::new("release_unknown_owner", vec![release()]),
+    ]
+}
+
+/// Keep using an owner after releasing it
+pub fn reuse_released_cases(open_stateid: &Stateid, owner: &LockOwner) -> Vec<FuzzCase> {
+    let current = Stateid::CURRENT;
+    let fresh = Locker::new_owner(open_stateid, owner);
+    let stale = Locker::Existing {
+        lock_stateid: current,
+        lock_seqid: 0,
+    };
+    let prologue = || {
+        vec![
+            lock(lock_type::WRITE, false, 0, 1, &fresh),
+            locku(lock_type::WRITE, 0, &current, 0, 1),
+            release_lockowner(owner),
+        ]
+    };
+    let with = |name: &str, tail: Vec<Op>| {
+        let mut ops = prologue();
+        ops.extend(tail);
+        FuzzCase::new(name, ops)
+    };
+    vec![
+        // The released owner's lock stateid must be unknown now
+        with(
+            "reuse_released_stateid",
+            vec![lock(lock_type::WRITE, false, 0, 1, &stale)],
+        ),
+        with(
+            "reuse_released_stateid_unlock",
+            vec![locku(lock_type::WRITE, 0, &current, 0, 1)],
+        ),
+        // Same owner name again starts from scratch
+        with(
+            "reuse_released_owner",
+            vec![
+                lock(lock_type::READ, false, 0, TO_EOF, &fresh),
+                locku(lock_type::READ, 0, &current, 0, TO_EOF),
+                release_lockowner(owner),
+            ],
+        ),
+    ]
+}
+
+/// Owner name for flood index `i`
+pub fn flood_owner(clientid: u64, i: u32) -> LockOwner {
+    LockOwner::new(clientid, format!("lockowner-{:08x}", i))
+}
+
+/// `total` distinct lock-owners, `per_compound` per COMPOUND, each
+/// locking its own byte; with `hold` the locks stay, otherwise each is
+/// unlocked right away so only the owner remains in the table
+pub fn owner_flood_cases(
+    open_stateid: Stateid,
+    clientid: u64,
+    total: u32,
+    per_compound: u32,
+    hold: bool,
+) -> impl Iterator<Item = FuzzCase> {
+    let per_compound = per_compound.max(1);
+    (0..total).step_by(per_compound as usize).map(move |start| {
+        let end = start.saturating_add(per_compound).min(total);
+        let mut ops = Vec::new();
+        for i in start..end {
+            let owner = flood_owner(clientid, i);
+            let locker = Locker::new_owner(&open_stateid, &owner);
+            ops.push(lock(lock_type::WRITE, false, i as u64, 1, &locker));
+            if !hold {
+                ops.push(locku(lock_type::WRITE, 0, &Stateid::CURRENT, i as u64, 1));
+            }
+        }
+        FuzzCase::new(format!("owner_flood_{}_{}", start, end), ops)
+    })
+}
+
+/// RELEASE_LOCKOWNER for every flood owner, to check the cleanup path
+pub fn owner_release_cases(
+    clientid: u64,
+    total: u32,
+    per_compound: u32,
+) -> impl Iterator<Item = FuzzCase> {
+    let per_compound = per_compound.max(1);
+    (0..total).step_by(per_compound as usize).map(move |start| {
+        let end = start.saturating_add(per_compound).min(total);
+        let ops = (start..end)
+            .map(|i| release_lockowner(&flood_owner(clientid, i)))
+            .collect();
+        FuzzCase::new(format!("owner_release_{}_{}", start, end), ops)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_encoding() {
+        let owner = LockOwner::new(7, b"lo".to_vec());
+        let op = lock(
+            lock_type::WRITE,
+            false,
+            0,
+            TO_EOF,
+            &Locker::new_owner(&Stateid::ANONYMOUS, &owner),
+        );
+        // type, reclaim, offset, length (24) + new_lock_owner (4)
+        // + open_seqid, stateid, lock_seqid (24) + owner (8 + 8)
+        assert_eq!(op.args.len(), 68);
+        assert_eq!(&op.args[24..28], &[0, 0, 0, 1]);
+        assert_eq!(release_lockowner(&owner).args.len(), 16);
+    }
+
+    #[test]
+    fn test_owner_flood_batches() {
+        let cases: Vec<FuzzCase> =
+            owner_flood_cases(Stateid::ANONYMOUS, 1, 20_001, 500, false).collect();
+        assert_eq!(cases.len(), 41);
+        assert_eq!(cases[0].ops.len(), 1000);
+        assert_eq!(cases[40].name, "owner_flood_20000_20001");
+        assert_eq!(cases[40].ops.len(), 2);
+        let held: Vec<FuzzCase> = owner_flood_cases(Stateid::ANONYMOUS, 1, 10, 4, true).collect();
+        assert_eq!(held.iter().map(|c| c.ops.len()).sum::<usize>(), 10);
+        assert_eq!(owner_release_cases(1, 10, 4).count(), 3);
+    }
+}
@@ -0,0 +1,695 @@
+//! NFSv4 operation encoding
+//!
+//! RFC 7530 (v4.0), RFC 8881 (v4.1) and RFC 7862 (v4.2). Every v4 request
+//! is a COMPOUND whose argarray is a list of (opcode, arguments) pairs, so
+//! fuzz cases are built as lists of individually encoded operations and
+//! framed into a call with [`CompoundBuilder`].
+
+use crate::auth::Identity;
+use crate::fields::{Annotated, Message};
+use crate::rpc::{auth_none, next_xid, program, RpcCall};
+use crate::xdr::XdrEncoder;
+use bytes::BytesMut;
+
+pub mod attrsweep;
+pub mod backchannel;
+pub mod delegation;
+pub mod flexfiles;
+pub mod lockowner;
+pub mod namedattr;
+pub mod open;
+pub mod pnfs;
+pub mod reclaim;
+pub mod reexport;
+pub mod referral;
+pub mod replies;
+pub mod replycache;
+pub mod sandwich;
+pub mod savedfh;
+pub mod secinfo;
+pub mod session;
+pub mod sparse;
+pub mod ssv;
+pub mod state;
+pub mod stateids;
+pub mod times;
+
+/// NFSv4 uses a single RPC procedure for all operations
+pub const PROC_COMPOUND: u32 = 1;
+
+/// Minor versions carried in COMPOUND4args
+pub mod minor_version {
+    pub const V4_0: u32 = 0;
+    pub const V4_1: u32 = 1;
+    pub const V4_2: u32 = 2;
+}
+
+/// Operation numbers (nfs_opnum4)
+pub mod op {
+    pub const ACCESS: u32 = 3;
+    pub const CLOSE: u32 = 4;
+    pub const COMMIT: u32 = 5;
+    pub const CREATE: u32 = 6;
+    pub const DELEGPURGE: u32 = 7;
+    pub const DELEGRETURN: u32 = 8;
+    pub const GETATTR: u32 = 9;
+    pub const GETFH: u32 = 10;
+    pub const LINK: u32 = 11;
+    pub const LOCK: u32 = 12;
+    pub const LOCKT: u32 = 13;
+    pub const LOCKU: u32 = 14;
+    pub const LOOKUP: u32 = 15;
+    pub const LOOKUPP: u32 = 16;
+    pub const NVERIFY: u32 = 17;
+    pub const OPEN: u32 = 18;
+    pub const OPENATTR: u32 = 19;
+    pub const OPEN_CONFIRM: u32 = 20;
+    pub const OPEN_DOWNGRADE: u32 = 21;
+    pub const PUTFH: u32 = 22;
+    pub const PUTPUBFH: u32 = 23;
+    pub const PUTROOTFH: u32 = 24;
+    pub const READ: u32 = 25;
+    pub const READDIR: u32 = 26;
+    pub const READLINK: u32 = 27;
+    pub const REMOVE: u32 = 28;
+    pub const RENAME: u32 = 29;
+    pub const RENEW: u32 = 30;
+    pub const RESTOREFH: u32 = 31;
+    pub const SAVEFH: u32 = 32;
+    pub const SECINFO: u32 = 33;
+    pub const SETATTR: u32 = 34;
+    pub const SETCLIENTID: u32 = 35;
+    pub const SETCLIENTID_CONFIRM: u32 = 36;
+    pub const VERIFY: u32 = 37;
+    pub const WRITE: u32 = 38;
+    pub const RELEASE_LOCKOWNER: u32 = 39;
+    // v4.1
+    pub const BACKCHANNEL_CTL: u32 = 40;
+    pub const BIND_CONN_TO_SESSION: u32 = 41;
+    pub const EXCHANGE_ID: u32 = 42;
+    pub const CREATE_SESSION: u32 = 43;
+    pub const DESTROY_SESSION: u32 = 44;
+    pub const FREE_STATEID: u32 = 45;
+    pub const GET_DIR_DELEGATION: u32 = 46;
+    pub const GETDEVICEINFO: u32 = 47;
+    pub const GETDEVICELIST: u32 = 48;
+    pub const LAYOUTCOMMIT: u32 = 49;
+    pub const LAYOUTGET: u32 = 50;
+    pub const LAYOUTRETURN: u32 = 51;
+    pub const SECINFO_NO_NAME: u32 = 52;
+    pub const SEQUENCE: u32 = 53;
+    pub const SET_SSV: u32 = 54;
+    pub const TEST_STATEID: u32 = 55;
+    pub const WANT_DELEGATION: u32 = 56;
+    pub const DESTROY_CLIENTID: u32 = 57;
+    pub const RECLAIM_COMPLETE: u32 = 58;
+    // v4.2
+    pub const ALLOCATE: u32 = 59;
+    pub const COPY: u32 = 60;
+    pub const COPY_NOTIFY: u32 = 61;
+    pub const DEALLOCATE: u32 = 62;
+    pub const IO_ADVISE: u32 = 63;
+    pub const LAYOUTERROR: u32 = 64;
+    pub const LAYOUTSTATS: u32 = 65;
+    pub const OFFLOAD_CANCEL: u32 = 66;
+    pub const OFFLOAD_STATUS: u32 = 67;
+    pub const READ_PLUS: u32 = 68;
+    pub const SEEK: u32 = 69;
+    pub const WRITE_SAME: u32 = 70;
+    pub const CLONE: u32 = 71;
+    pub const ILLEGAL: u32 = 10044;
+}
+
+/// Callback operation numbers (nfs_cb_opnum4)
+pub mod cb_op {
+    pub const CB_GETATTR: u32 = 3;
+    pub const CB_RECALL: u32 = 4;
+    pub const CB_LAYOUTRECALL: u32 = 5;
+    pub const CB_NOTIFY: u32 = 6;
+    pub const CB_PUSH_DELEG: u32 = 7;
+    pub const CB_RECALL_ANY: u32 = 8;
+    pub const CB_RECALLABLE_OBJ_AVAIL: u32 = 9;
+    pub const CB_RECALL_SLOT: u32 = 10;
+    pub const CB_SEQUENCE: u32 = 11;
+    pub const CB_WANTS_CANCELLED: u32 = 12;
+    pub const CB_NOTIFY_LOCK: u32 = 13;
+    pub const CB_NOTIFY_DEVICEID: u32 = 14;
+    pub const CB_OFFLOAD: u32 = 15;
+    pub const CB_ILLEGAL: u32 = 10044;
+}
+
+/// Attribute numbers (RFC 8881 §5.8) used by the generators
+pub mod attr {
+    pub const TYPE: u32 = 1;
+    pub const CHANGE: u32 = 3;
+    pub const SIZE: u32 = 4;
+    pub const FSID: u32 = 8;
+    pub const FILEHANDLE: u32 = 19;
+    pub const FILEID: u32 = 20;
+    pub const FS_LOCATIONS: u32 = 24;
+    pub const MAXFILESIZE: u32 = 27;
+    pub const MAXNAME: u32 = 29;
+    pub const MAXREAD: u32 = 30;
+    pub const MAXWRITE: u32 = 31;
+    pub const MODE: u32 = 33;
+    pub const NUMLINKS: u32 = 35;
+    pub const OWNER: u32 = 36;
+    pub const OWNER_GROUP: u32 = 37;
+    pub const TIME_ACCESS: u32 = 47;
+    pub const TIME_ACCESS_SET: u32 = 48;
+    pub const TIME_CREATE: u32 = 50;
+    pub const TIME_DELTA: u32 = 51;
+    pub const TIME_METADATA: u32 = 52;
+    pub const TIME_MODIFY: u32 = 53;
+    pub const TIME_MODIFY_SET: u32 = 54;
+    pub const MOUNTED_ON_FILEID: u32 = 55;
+    pub const FS_STATUS: u32 = 61;
+    pub const FS_LOCATIONS_INFO: u32 = 67;
+}
+
+/// WRITE stability levels (stable_how4)
+pub mod stable_how {
+    pub const UNSTABLE: u32 = 0;
+    pub const DATA_SYNC: u32 = 1;
+    pub const FILE_SYNC: u32 = 2;
+}
+
+/// Status codes (nfsstat4) - only those the fuzzer produces or inspects
+pub mod status {
+    pub const NFS4_OK: u32 = 0;
+    pub const NFS4ERR_PERM: u32 = 1;
+    pub const NFS4ERR_NOENT: u32 = 2;
+    pub const NFS4ERR_IO: u32 = 5;
+    pub const NFS4ERR_ACCESS: u32 = 13;
+    pub const NFS4ERR_NOTDIR: u32 = 20;
+    pub const NFS4ERR_INVAL: u32 = 22;
+    pub const NFS4ERR_NOTSUPP: u32 = 10004;
+    pub const NFS4ERR_SERVERFAULT: u32 = 10006;
+    pub const NFS4ERR_DENIED: u32 = 10010;
+    pub const NFS4ERR_GRACE: u32 = 10013;
+    pub const NFS4ERR_WRONGSEC: u32 = 10016;
+    pub const NFS4ERR_MOVED: u32 = 10019;
+    pub const NFS4ERR_STALE_CLIENTID: u32 = 10022;
+    pub const NFS4ERR_BAD_STATEID: u32 = 10025;
+    pub const NFS4ERR_SYMLINK: u32 = 10029;
+    pub const NFS4ERR_NO_GRACE: u32 = 10033;
+    pub const NFS4ERR_RECLAIM_BAD: u32 = 10034;
+    pub const NFS4ERR_RECLAIM_CONFLICT: u32 = 10035;
+    pub const NFS4ERR_BADXDR: u32 = 10036;
+    pub const NFS4ERR_OP_ILLEGAL: u32 = 10044;
+    pub const NFS4ERR_BAD_SESSION_DIGEST: u32 = 10051;
+    pub const NFS4ERR_BADSLOT: u32 = 10053;
+    pub const NFS4ERR_COMPLETE_ALREADY: u32 = 10054;
+    pub const NFS4ERR_SEQ_MISORDERED: u32 = 10063;
+    pub const NFS4ERR_REP_TOO_BIG: u32 = 10066;
+    pub const NFS4ERR_REP_TOO_BIG_TO_CACHE: u32 = 10067;
+    pub const NFS4ERR_RETRY_UNCACHED_REP: u32 = 10068;
+    pub const NFS4ERR_OP_NOT_IN_SESSION: u32 = 10071;
+    pub const NFS4ERR_HASH_ALG_UNSUPP: u32 = 10072;
+    pub const NFS4ERR_SEQ_FALSE_RETRY: u32 = 10076;
+    pub const NFS4ERR_ENCR_ALG_UNSUPP: u32 = 10079;
+}
+
+/// Session identifier (sessionid4)
+pub type SessionId = [u8; 16];
+
+/// State identifier (stateid4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Stateid {
+    pub seqid: u32,
+    pub other: [u8; 12],
+}
+
+impl Stateid {
+    /// Anonymous stateid (all zeros)
+    pub const ANONYMOUS: Stateid = Stateid {
+        seqid: 0,
+        other: [0; 12],
+    };
+
+    /// READ bypass stateid (all ones)
+    pub const READ_BYPASS: Stateid = Stateid {
+        seqid: u32::MAX,
+        other: [0xff; 12],
+    };
+
+    /// "Current stateid" (v4.1): use the stateid from the previous op
+    pub const CURRENT: Stateid = Stateid {
+        seqid: 1,
+        other: [0; 12],
+    };
+
+    /// Reserved stateid that must always be rejected (v4.1)
+    pub const INVALID: Stateid = Stateid {
+        seqid: u32::MAX,
+        other: [0; 12],
+    };
+
+    /// All of the special stateids above
+    pub const SPECIAL: [Stateid; 4] = [
+        Self::ANONYMOUS,
+        Self::READ_BYPASS,
+        Self::CURRENT,
+        Self::INVALID,
+    ];
+
+    pub fn new(seqid: u32, other: [u8; 12]) -> Self {
+        Self { seqid, other }
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_u32(self.seqid);
+        enc.put_opaque_fixed(&self.other);
+    }
+}
+
+/// Counts that commonly trip sizing logic: zero, one, sign bits, maxima
+pub const BOUNDARY_COUNTS: &[u32] = &[
+    0,
+    1,
+    2,
+    0x7f,
+    0x80,
+    0xff,
+    0x100,
+    0xffff,
+    0x10000,
+    0x7fff_ffff,
+    0x8000_0000,
+    0xffff_fffe,
+    0xffff_ffff,
+];
+
+/// A single operation from a COMPOUND argarray, with pre-encoded arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Op {
+    pub opcode: u32,
+    pub args: Vec<u8>,
+}
+
+impl Op {
+    /// Build an operation by encoding its arguments with `f`
+    pub fn new(opcode: u32, f: impl FnOnce(&mut XdrEncoder)) -> Self {
+        let mut enc = XdrEncoder::with_capacity(64);
+        f(&mut enc);
+        Self {
+            opcode,
+            args: enc.into_bytes().to_vec(),
+        }
+    }
+
+    /// Build an operation from arbitrary (possibly malformed) argument bytes
+    pub fn raw(opcode: u32, args: Vec<u8>) -> Self {
+        Self { opcode, args }
+    }
+
+    /// Append the nfs_argop4 encoding (opcode followed by arguments)
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_u32(self.opcode);
+        enc.put_raw(&self.args);
+    }
+}
+
+/// A named fuzz case: the operations of interest for one COMPOUND
+///
+/// Cases exclude the SEQUENCE/PUTFH framing needed to reach them, which
+/// depends on the session and filehandles of the running campaign.
+#[derive(Debug, Clone)]
+pub struct FuzzCase {
+    pub name: String,
+    pub ops: Vec<Op>,
+}
+
+impl FuzzCase {
+    pub fn new(name: impl Into<String>, ops: Vec<Op>) -> Self {
+        Self {
+            name: name.into(),
+            ops,
+        }
+    }
+}
+
+/// COMPOUND4args under construction: tag, minor version, then the ops
+/// in the order they are added
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompoundBuilder {
+    tag: Vec<u8>,
+    minor_version: u32,
+    ops: Vec<Op>,
+}
+
+impl CompoundBuilder {
+    pub fn new(minor_version: u32) -> Self {
+        Self {
+            tag: Vec::new(),
+            minor_version,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Set the tag the server echoes back (empty by default)
+    pub fn with_tag(mut self, tag: &[u8]) -> Self {
+        self.tag = tag.to_vec();
+        self
+    }
+
+    pub fn op(mut self, op: Op) -> Self {
+        self.ops.push(op);
+        self
+    }
+
+    pub fn ops(mut self, ops: impl IntoIterator<Item = Op>) -> Self {
+        self.ops.extend(ops);
+        self
+    }
+
+    /// Append the operations of a fuzz case
+    pub fn case(self, case: &FuzzCase) -> Self {
+        self.ops(case.ops.iter().cloned())
+    }
+
+    pub fn putrootfh(self) -> Self {
+        self.op(putrootfh())
+    }
+
+    pub fn putfh(self, fh: &[u8]) -> Self {
+        self.op(putfh(fh))
+    }
+
+    pub fn lookup(self, name: &[u8]) -> Self {
+        self.op(lookup(name))
+    }
+
+    pub fn getfh(self) -> Self {
+        self.op(getfh())
+    }
+
+    pub fn getattr(self, mask: &[u32]) -> Self {
+        self.op(getattr(mask))
+    }
+
+    pub fn open(self, open: &open::Open) -> Self {
+        self.op(open.op())
+    }
+
+    pub fn read(self, stateid: &Stateid, offset: u64, count: u32) -> Self {
+        self.op(read(stateid, offset, count))
+    }
+
+    pub fn write(self, stateid: &Stateid, offset: u64, stable: u32, data: &[u8]) -> Self {
+        self.op(write(stateid, offset, stable, data))
+    }
+
+    pub fn close(self, seqid: u32, stateid: &Stateid) -> Self {
+        self.op(close(seqid, stateid))
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Encode COMPOUND4args
+    pub fn build(&self) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        enc.put_opaque(&self.tag);
+        enc.put_u32(self.minor_version);
+        enc.put_u32(self.ops.len() as u32);
+        for op in &self.ops {
+            op.encode(&mut enc);
+        }
+        enc.into_bytes().to_vec()
+    }
+
+    /// COMPOUND4args with their fields annotated: tag, minor version,
+    /// op count and opcodes exactly, each op's arguments as
+    /// [`Message::infer`] lays them out
+    pub fn annotated(&self) -> Message {
+        let mut enc = Annotated::new();
+        enc.put_opaque("tag", &self.tag);
+        enc.put_u32("minorversion", self.minor_version);
+        enc.put_count("argarray", self.ops.len() as u32);
+        for (i, op) in self.ops.iter().enumerate() {
+            enc.scope(&format!("op{}", i), |enc| {
+                enc.put_discriminant("argop", op.opcode);
+                enc.put_inferred("args", &op.args);
+            });
+        }
+        enc.finish()
+    }
+
+    /// The record-marked COMPOUND call, sent as `identity`
+    pub fn message(&self, identity: &Identity) -> BytesMut {
+        RpcCall::new(next_xid(), program::NFS, 4, PROC_COMPOUND, true)
+            .with_auth(&identity.credential(), &auth_none())
+            .with_args(&self.build())
+            .build()
+    }
+}
+
+/// Build bitmap4 words with the given bit numbers set
+pub fn bitmap_from_bits(bits: &[u32]) -> Vec<u32> {
+    let words = bits.iter().map(|&b| b / 32 + 1).max().unwrap_or(0);
+    let mut map = vec![0u32; words as usize];
+    for &b in bits {
+        map[(b / 32) as usize] |= 1 << (b % 32);
+    }
+    map
+}
+
+/// Bit numbers set in bitmap4 words, lowest first
+pub fn bits_of_bitmap(words: &[u32]) -> impl Iterator<Item = u32> + '_ {
+    words.iter().enumerate().flat_map(|(word, &w)| {
+        (0..32)
+            .filter(move |bit| w & (1 << bit) != 0)
+            .map(move |bit| word as u32 * 32 + bit)
+    })
+}
+
+/// Encode a bitmap4 (counted array of 32-bit words)
+pub fn put_bitmap(enc: &mut XdrEncoder, words: &[u32]) {
+    enc.put_u32(words.len() as u32);
+    for &w in words {
+        enc.put_u32(w);
+    }
+}
+
+/// Attribute set (fattr4): bitmap plus the packed attribute values
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fattr {
+    pub mask: Vec<u32>,
+    pub vals: Vec<u8>,
+}
+
+/// Encode a fattr4
+pub fn put_fattr(enc: &mut XdrEncoder, attrs: &Fattr) {
+    put_bitmap(enc, &attrs.mask);
+    enc.put_opaque(&attrs.vals);
+}
+
+/// Encode an nfstime4 (signed seconds, unsigned nanoseconds)
+pub fn put_nfstime(enc: &mut XdrEncoder, seconds: i64, nseconds: u32) {
+    enc.put_i64(seconds);
+    enc.put_u32(nseconds);
+}
+
+/// Encode PUTFH4args
+pub fn putfh(fh: &[u8]) -> Op {
+    Op::new(op::PUTFH, |enc| enc.put_opaque(fh))
+}
+
+/// PUTROOTFH (no arguments)
+pub fn putrootfh() -> Op {
+    Op::raw(op::PUTROOTFH, Vec::new())
+}
+
+/// GETFH (no arguments)
+pub fn getfh() -> Op {
+    Op::raw(op::GETFH, Vec::new())
+}
+
+/// Encode GETATTR4args
+pub fn getattr(mask: &[u32]) -> Op {
+    Op::new(op::GETATTR, |enc| put_bitmap(enc, mask))
+}
+
+/// Encode SETATTR4args
+pub fn setattr(stateid: &Stateid, attrs: &Fattr) -> Op {
+    Op::new(op::SETATTR, |enc| {
+        stateid.encode(enc);
+        put_fattr(enc, attrs);
+    })
+}
+
+/// Encode LOOKUP4args
+pub fn lookup(name: &[u8]) -> Op {
+    Op::new(op::LOOKUP, |enc| enc.put_opaque(name))
+}
+
+/// LOOKUPP (no arguments)
+pub fn lookupp() -> Op {
+    Op::raw(op::LOOKUPP, Vec::new())
+}
+
+/// SAVEFH (no arguments)
+pub fn savefh() -> Op {
+    Op::raw(op::SAVEFH, Vec::new())
+}
+
+/// RESTOREFH (no arguments)
+pub fn restorefh() -> Op {
+    Op::raw(op::RESTOREFH, Vec::new())
+}
+
+/// Encode OPENATTR4args
+pub fn openattr(createdir: bool) -> Op {
+    Op::new(op::OPENATTR, |enc| enc.put_bool(createdir))
+}
+
+/// Encode REMOVE4args
+pub fn remove(name: &[u8]) -> Op {
+    Op::new(op::REMOVE, |enc| enc.put_opaque(name))
+}
+
+/// Encode RENAME4args (from the saved to the current filehandle)
+pub fn rename(oldname: &[u8], newname: &[u8]) -> Op {
+    Op::new(op::RENAME, |enc| {
+        enc.put_opaque(oldname);
+        enc.put_opaque(newname);
+    })
+}
+
+/// Encode LINK4args (the saved filehandle linked into the current one)
+pub fn link(newname: &[u8]) -> Op {
+    Op::new(op::LINK, |enc| enc.put_opaque(newname))
+}
+
+/// Encode READ4args
+pub fn read(stateid: &Stateid, offset: u64, count: u32) -> Op {
+    Op::new(op::READ, |enc| {
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u32(count);
+    })
+}
+
+/// Encode WRITE4args
+pub fn write(stateid: &Stateid, offset: u64, stable: u32, data: &[u8]) -> Op {
+    Op::new(op::WRITE, |enc| {
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u32(stable);
+        enc.put_opaque(data);
+    })
+}
+
+/// Encode CLOSE4args
+pub fn close(seqid: u32, stateid: &Stateid) -> Op {
+    Op::new(op::CLOSE, |enc| {
+        enc.put_u32(seqid);
+        stateid.encode(enc);
+    })
+}
+
+/// Encode READDIR4args
+pub fn readdir(
+    cookie: u64,
+    cookieverf: &[u8; 8],
+    dircount: u32,
+    maxcount: u32,
+    attrs: &[u32],
+) -> Op {
+    Op::new(op::READDIR, |enc| {
+        enc.put_u64(cookie);
+        enc.put_opaque_fixed(cookieverf);
+        enc.put_u32(dircount);
+        enc.put_u32(maxcount);
+        put_bitmap(enc, attrs);
+    })
+}
+
+/// Encode DELEGRETURN4args
+pub fn delegreturn(stateid: &Stateid) -> Op {
+    Op::new(op::DELEGRETURN, |enc| stateid.encode(enc))
+}
+
+/// Encodes one operation's results in [`ok_results`]
+#[cfg(test)]
+pub(crate) type ResultBody<'a> = &'a dyn Fn(&mut XdrEncoder);
+
+/// COMPOUND4res with `ops` all OK, each opcode followed by its results
+#[cfg(test)]
+pub(crate) fn ok_results(ops: &[(u32, ResultBody<'_>)]) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    enc.put_u32(status::NFS4_OK);
+    enc.put_opaque(b"");
+    enc.put_u32(ops.len() as u32);
+    for (opcode, body) in ops {
+        enc.put_u32(*opcode);
+        enc.put_u32(status::NFS4_OK);
+        body(&mut enc);
+    }
+    enc.as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap_from_bits() {
+        assert_eq!(bitmap_from_bits(&[]), Vec::<u32>::new());
+        assert_eq!(bitmap_from_bits(&[0, 3]), vec![0x9]);
+        assert_eq!(bitmap_from_bits(&[1, 33]), vec![0x2, 0x2]);
+    }
+
+    #[test]
+    fn test_op_encode() {
+        let op = Op::new(op::GETFH, |_| {});
+        let mut enc = XdrEncoder::new();
+        op.encode(&mut enc);
+        assert_eq!(enc.as_bytes(), &[0, 0, 0, 10]);
+    }
+
+    #[test]
+    fn test_compound_builder() {
+        let compound = CompoundBuilder::new(minor_version::V4_1)
+            .with_tag(b"tag")
+            .putrootfh()
+            .lookup(b"export")
+            .getfh()
+            .getattr(&bitmap_from_bits(&[attr::SIZE]));
+        assert_eq!(compound.len(), 4);
+        let args = compound.build();
+        assert_eq!(
+            &args[..20],
+            &[0, 0, 0, 3, b't', b'a', b'g', 0, 0, 0, 0, 1, 0, 0, 0, 4, 0, 0, 0, 24]
+        );
+        // LOOKUP "export", GETFH, then GETATTR with a one-word mask
+        assert_eq!(&args[20..24], &op::LOOKUP.to_be_bytes());
+        assert_eq!(&args[36..40], &op::GETFH.to_be_bytes());
+        assert_eq!(&args[40..], &[0, 0, 0, 9, 0, 0, 0, 1, 0, 0, 0, 0x10]);
+
+        let annotated = compound.annotated();
+        assert_eq!(annotated.bytes, args);
+        let field = |name: &str| annotated.fields.iter().find(|f| f.name == name).unwrap();
+        assert_eq!(field("argarray").kind, crate::fields::Kind::Count);
+        assert_eq!(field("op1.argop").offset, 20);
+        assert_eq!(field("op1.args@24").kind, crate::fields::Kind::Length);
+
+        let msg = CompoundBuilder::new(minor_version::V4_0).message(&Identity::new(0, 0));
+        assert_eq!(&msg[20..28], &[0, 0, 0, 4, 0, 0, 0, 1]);
+        assert!(msg.ends_with(&[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn test_stateid_encode() {
+        let mut enc = XdrEncoder::new();
+        Stateid::READ_BYPASS.encode(&mut enc);
+        assert_eq!(enc.len(), 16);
+        assert!(enc.as_bytes().iter().all(|&b| b == 0xff));
+    }
+}
@@ -0,0 +1,190 @@
+//! Named attributes (RFC 8881 §5.3, §18.17)
+//!
+//! OPENATTR turns the current filehandle into that of the object's named
+//! attribute directory, a hidden namespace that servers map onto xattrs,
+//! alternate data streams or a side directory. Few clients ever use it,
+//! so the VFS glue behind it sees little real traffic. Cases open that
+//! directory, with and without createdir, then create, write, read,
+//! rename and remove attributes inside it with hostile names and sizes,
+//! and try to move out of or nest the namespace.
+
+use super::open::{share_access, share_deny, CreateHow, Open, OpenClaim};
+use super::{
+    attr, bitmap_from_bits, close, getfh, lookup, lookupp, openattr, read, readdir, remove, rename,
+    restorefh, savefh, setattr, stable_how, write, Fattr, FuzzCase, Op, Stateid,
+};
+use crate::scenario::{NAME_MAX, PATH_MAX};
+
+/// Attribute names that collide with conventions of the backing store or
+/// that no sane store accepts
+pub fn hostile_names() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("empty", Vec::new()),
+        ("dot", b".".to_vec()),
+        ("dotdot", b"..".to_vec()),
+        ("slash", b"a/b".to_vec()),
+        ("nul", b"a\0b".to_vec()),
+        ("invalid_utf8", vec![0xff, 0xfe, 0x80]),
+        // Linux xattr namespaces the server may pass straight through
+        ("user_prefix", b"user.nfs".to_vec()),
+        ("trusted_prefix", b"trusted.overlay.opaque".to_vec()),
+        ("security_prefix", b"security.selinux".to_vec()),
+        ("system_acl", b"system.posix_acl_access".to_vec()),
+        // Solaris system attributes, macOS resource forks, NTFS streams
+        ("sunw_attr", b"SUNWattr_rw".to_vec()),
+        ("namedfork", b"..namedfork/rsrc".to_vec()),
+        ("ads", b"file::$DATA".to_vec()),
+        ("name_max", vec![b'a'; NAME_MAX]),
+        ("name_max_plus_1", vec![b'a'; NAME_MAX + 1]),
+        ("path_max", vec![b'a'; PATH_MAX]),
+    ]
+}
+
+/// Sizes around page, xattr (XATTR_SIZE_MAX is 64 KiB) and 32-bit limits
+pub const HOSTILE_SIZES: &[u64] = &[
+    0,
+    1,
+    4095,
+    4096,
+    65535,
+    65536,
+    65537,
+    1 << 20,
+    u32::MAX as u64,
+    u32::MAX as u64 + 1,
+    i64::MAX as u64,
+    u64::MAX,
+];
+
+fn open_create(clientid: u64, owner: &[u8], name: &[u8]) -> Op {
+    Open {
+        seqid: 0,
+        share_access: share_access::BOTH,
+        share_deny: share_deny::NONE,
+        clientid,
+        owner: owner.to_vec(),
+        create: Some(CreateHow::Unchecked(Fattr::default())),
+        claim: OpenClaim::Null(name.to_vec()),
+    }
+    .op()
+}
+
+fn size_attr(size: u64) -> Fattr {
+    Fattr {
+        mask: bitmap_from_bits(&[attr::SIZE]),
+        vals: size.to_be_bytes().to_vec(),
+    }
+}
+
+/// OPENATTR on its own and composed with the ops that leave or nest the
+/// named attribute namespace
+pub fn openattr_cases() -> Vec<FuzzCase> {
+    let listing = readdir(0, &[0; 8], 4096, 32768, &bitmap_from_bits(&[attr::TYPE]));
+    vec![
+        FuzzCase::new("openattr_nocreate", vec![openattr(false), getfh()]),
+        FuzzCase::new("openattr_createdir", vec![openattr(true), getfh()]),
+        FuzzCase::new("openattr_readdir", vec![openattr(true), listing]),
+        // Attribute directories of attribute directories are not allowed
+        FuzzCase::new(
+            "openattr_nested",
+            vec![openattr(true), openattr(true), getfh()],
+        ),
+        FuzzCase::new(
+            "openattr_lookupp",
+            vec![openattr(true), lookupp(), getfh(), lookupp(), getfh()],
+        ),
+        FuzzCase::new(
+            "openattr_restore_crossing",
+            vec![
+                savefh(),
+                openattr(true),
+                restorefh(),
+                openattr(false),
+                getfh(),
+            ],
+        ),
+    ]
+}
+
+/// Create, write, read back, rename and remove one attribute per hostile
+/// name, chaining stateids with the v4.1 current stateid
+pub fn named_attr_name_cases(clientid: u64, owner: &[u8]) -> Vec<FuzzCase> {
+    let current = Stateid::CURRENT;
+    hostile_names()
+        .into_iter()
+        .map(|(label, name)| {
+            FuzzCase::new(
+                format!("namedattr_name_{}", label),
+                vec![
+                    openattr(true),
+                    open_create(clientid, owner, &name),
+                    write(&current, 0, stable_how::FILE_SYNC, b"named attribute"),
+                    read(&current, 0, 4096),
+                    close(0, &current),
+                    lookupp(),
+                    openattr(false),
+                    savefh(),
+                    rename(&name, b"renamed"),
+                    lookup(b"renamed"),
+                    lookupp(),
+                    remove(b"renamed"),
+                ],
+            )
+        })
+        .collect()
+}
+
+/// Size an attribute with SETATTR, then write one byte at the end and
+/// read across it
+pub fn named_attr_size_cases(clientid: u64, owner: &[u8]) -> Vec<FuzzCase> {
+    let current = Stateid::CURRENT;
+    HOSTILE_SIZES
+        .iter()
+        .map(|&size| {
+            FuzzCase::new(
+                format!("namedattr_size_{:#x}", size),
+                vec![
+                    openattr(true),
+                    open_create(clientid, owner, b"fuzz.size"),
+                    setattr(&current, &size_attr(size)),
+                    write(&current, size, stable_how::UNSTABLE, b"x"),
+                    read(&current, size.saturating_sub(1), 4096),
+                    close(0, &current),
+                ],
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::op;
+
+    #[test]
+    fn test_every_case_enters_the_namespace() {
+        let cases: Vec<FuzzCase> = openattr_cases()
+            .into_iter()
+            .chain(named_attr_name_cases(1, b"o"))
+            .chain(named_attr_size_cases(1, b"o"))
+            .collect();
+        for case in &cases {
+            let first = case.ops.iter().position(|o| o.opcode == op::OPENATTR);
+            assert!(first.is_some(), "{}", case.name);
+        }
+        assert_eq!(cases[0].ops[0].args, [0, 0, 0, 0]);
+        assert_eq!(cases[1].ops[0].args, [0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_size_case_offsets() {
+        let cases = named_attr_size_cases(1, b"o");
+        assert_eq!(cases.len(), HOSTILE_SIZES.len());
+        let last = cases.last().unwrap();
+        assert_eq!(last.name, "namedattr_size_0xffffffffffffffff");
+        // SETATTR: stateid, bitmap (count + 1 word), opaque length, size
+        assert_eq!(&last.ops[2].args[28..], &[0xff; 8]);
+        // WRITE offset follows the stateid
+        assert_eq!(&last.ops[3].args[16..24], &[0xff; 8]);
+    }
+}
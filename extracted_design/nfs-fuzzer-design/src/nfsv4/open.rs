@@ -0,0 +1,196 @@
+//! OPEN arguments (RFC 8881 §18.16)
+//!
+//! OPEN carries most of the v4 state machine's inputs at once: share
+//! modes (plus v4.1 delegation "want" flags packed into share_access),
+//! the open-owner, an optional create mode and one of seven claim types.
+
+use super::{op, put_fattr, Fattr, Op, Stateid};
+use crate::xdr::XdrEncoder;
+
+/// share_access bits, including the v4.1 delegation wants
+pub mod share_access {
+    pub const READ: u32 = 0x1;
+    pub const WRITE: u32 = 0x2;
+    pub const BOTH: u32 = 0x3;
+
+    pub const WANT_DELEG_MASK: u32 = 0xff00;
+    pub const WANT_NO_PREFERENCE: u32 = 0x0000;
+    pub const WANT_READ_DELEG: u32 = 0x0100;
+    pub const WANT_WRITE_DELEG: u32 = 0x0200;
+    pub const WANT_ANY_DELEG: u32 = 0x0300;
+    pub const WANT_NO_DELEG: u32 = 0x0400;
+    pub const WANT_CANCEL: u32 = 0x0500;
+
+    pub const WANT_SIGNAL_DELEG_WHEN_RESRC_AVAIL: u32 = 0x10000;
+    pub const WANT_PUSH_DELEG_WHEN_UNCONTENDED: u32 = 0x20000;
+}
+
+/// share_deny values
+pub mod share_deny {
+    pub const NONE: u32 = 0;
+    pub const READ: u32 = 1;
+    pub const WRITE: u32 = 2;
+    pub const BOTH: u32 = 3;
+}
+
+/// Claim type discriminants (open_claim_type4)
+pub mod claim_type {
+    pub const NULL: u32 = 0;
+    pub const PREVIOUS: u32 = 1;
+    pub const DELEGATE_CUR: u32 = 2;
+    pub const DELEGATE_PREV: u32 = 3;
+    pub const FH: u32 = 4;
+    pub const DELEG_PREV_FH: u32 = 5;
+    pub const DELEG_CUR_FH: u32 = 6;
+}
+
+/// Delegation types (open_delegation_type4)
+pub mod delegation_type {
+    pub const NONE: u32 = 0;
+    pub const READ: u32 = 1;
+    pub const WRITE: u32 = 2;
+    pub const NONE_EXT: u32 = 3;
+}
+
+/// Create modes (createmode4)
+pub mod create_mode {
+    pub const UNCHECKED: u32 = 0;
+    pub const GUARDED: u32 = 1;
+    pub const EXCLUSIVE: u32 = 2;
+    pub const EXCLUSIVE4_1: u32 = 3;
+}
+
+/// How the file being opened is named (open_claim4)
+#[derive(Debug, Clone)]
+pub enum OpenClaim {
+    Null(Vec<u8>),
+    Previous(u32),
+    DelegateCur(Stateid, Vec<u8>),
+    DelegatePrev(Vec<u8>),
+    Fh,
+    DelegPrevFh,
+    DelegCurFh(Stateid),
+}
+
+impl OpenClaim {
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        match self {
+            OpenClaim::Null(name) => {
+                enc.put_u32(claim_type::NULL);
+                enc.put_opaque(name);
+            }
+            OpenClaim::Previous(deleg) => {
+                enc.put_u32(claim_type::PREVIOUS);
+                enc.put_u32(*deleg);
+            }
+            OpenClaim::DelegateCur(stateid, name) => {
+                enc.put_u32(claim_type::DELEGATE_CUR);
+                stateid.encode(enc);
+                enc.put_opaque(name);
+            }
+            OpenClaim::DelegatePrev(name) => {
+                enc.put_u32(claim_type::DELEGATE_PREV);
+                enc.put_opaque(name);
+            }
+            OpenClaim::Fh => enc.put_u32(claim_type::FH),
+            OpenClaim::DelegPrevFh => enc.put_u32(claim_type::DELEG_PREV_FH),
+            OpenClaim::DelegCurFh(stateid) => {
+                enc.put_u32(claim_type::DELEG_CUR_FH);
+                stateid.encode(enc);
+            }
+        }
+    }
+}
+
+/// Create mode and its attributes (createhow4)
+#[derive(Debug, Clone)]
+pub enum CreateHow {
+    Unchecked(Fattr),
+    Guarded(Fattr),
+    Exclusive([u8; 8]),
+    Exclusive41([u8; 8], Fattr),
+}
+
+impl CreateHow {
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        match self {
+            CreateHow::Unchecked(attrs) => {
+                enc.put_u32(create_mode::UNCHECKED);
+                put_fattr(enc, attrs);
+            }
+            CreateHow::Guarded(attrs) => {
+                enc.put_u32(create_mode::GUARDED);
+                put_fattr(enc, attrs);
+            }
+            CreateHow::Exclusive(verf) => {
+                enc.put_u32(create_mode::EXCLUSIVE);
+                enc.put_opaque_fixed(verf);
+            }
+            CreateHow::Exclusive41(verf, attrs) => {
+                enc.put_u32(create_mode::EXCLUSIVE4_1);
+                enc.put_opaque_fixed(verf);
+                put_fattr(enc, attrs);
+            }
+        }
+    }
+}
+
+/// OPEN4args
+#[derive(Debug, Clone)]
+pub struct Open {
+    pub seqid: u32,
+    pub share_access: u32,
+    pub share_deny: u32,
+    pub clientid: u64,
+    pub owner: Vec<u8>,
+    /// `None` for OPEN4_NOCREATE
+    pub create: Option<CreateHow>,
+    pub claim: OpenClaim,
+}
+
+impl Open {
+    /// Plain read/write open of an existing file by name
+    pub fn existing(clientid: u64, owner: &[u8], name: &[u8]) -> Self {
+        Self {
+            seqid: 0,
+            share_access: share_access::BOTH,
+            share_deny: share_deny::NONE,
+            clientid,
+            owner: owner.to_vec(),
+            create: None,
+            claim: OpenClaim::Null(name.to_vec()),
+        }
+    }
+
+    pub fn op(&self) -> Op {
+        Op::new(op::OPEN, |enc| {
+            enc.put_u32(self.seqid);
+            enc.put_u32(self.share_access);
+            enc.put_u32(self.share_deny);
+            enc.put_u64(self.clientid);
+            enc.put_opaque(&self.owner);
+            match &self.create {
+                None => enc.put_u32(0),
+                Some(how) => {
+                    enc.put_u32(1);
+                    how.encode(enc);
+                }
+            }
+            self.claim.encode(enc);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_nocreate_encoding() {
+        let op = Open::existing(0x1122, b"own", b"f").op();
+        // seqid + access + deny (12) + clientid (8) + owner (8)
+        // + opentype (4) + claim type (4) + name (8)
+        assert_eq!(op.args.len(), 44);
+        assert_eq!(&op.args[28..32], &[0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,726 @@
+//! pNFS device and layout operations (RFC 8881 §12, §18.40–18.44, §20.12)
+//!
+//! GETDEVICELIST and GETDEVICEINFO hand a device id, layout type and
+//! client-chosen size limits to the metadata server, which uses them to
+//! look up device tables and size the reply. CB_NOTIFY_DEVICEID is the
+//! matching backchannel operation, answered by [`super::backchannel`].
+//!
+//! LAYOUTGET/LAYOUTRETURN/LAYOUTCOMMIT tie layout state to open and
+//! delegation stateids; the desync cases below break that bookkeeping.
+
+use super::{
+    bitmap_from_bits, cb_op, delegreturn, op, put_bitmap, put_nfstime, status, FuzzCase, Op,
+    SessionId, Stateid, BOUNDARY_COUNTS,
+};
+use crate::xdr::XdrEncoder;
+use rand::Rng;
+
+/// Layout types (layouttype4)
+pub mod layout_type {
+    pub const NFSV4_1_FILES: u32 = 1;
+    pub const OSD2_OBJECTS: u32 = 2;
+    pub const BLOCK_VOLUME: u32 = 3;
+    pub const FLEX_FILES: u32 = 4;
+    pub const SCSI: u32 = 5;
+}
+
+/// Layout I/O modes (layoutiomode4)
+pub mod layout_iomode {
+    pub const READ: u32 = 1;
+    pub const RW: u32 = 2;
+    pub const ANY: u32 = 3;
+}
+
+/// LAYOUTRETURN scopes (layoutreturn_type4)
+pub mod layoutreturn_type {
+    pub const FILE: u32 = 1;
+    pub const FSID: u32 = 2;
+    pub const ALL: u32 = 3;
+}
+
+/// Device notification bit numbers (notify_deviceid_type4)
+pub mod notify_deviceid {
+    pub const CHANGE: u32 = 1;
+    pub const DELETE: u32 = 2;
+}
+
+/// Device ids are fixed 16-byte opaques
+pub const DEVICEID_SIZE: usize = 16;
+
+pub type DeviceId = [u8; DEVICEID_SIZE];
+
+/// Layout type values to try, including unassigned and private-range ones
+pub const LAYOUT_TYPES: &[u32] = &[
+    0,
+    layout_type::NFSV4_1_FILES,
+    layout_type::OSD2_OBJECTS,
+    layout_type::BLOCK_VOLUME,
+    layout_type::FLEX_FILES,
+    layout_type::SCSI,
+    6,
+    0x8000_0000,
+    0xffff_ffff,
+];
+
+/// Encode GETDEVICEINFO4args
+pub fn getdeviceinfo(device_id: &DeviceId, layout: u32, maxcount: u32, notify: &[u32]) -> Op {
+    Op::new(op::GETDEVICEINFO, |enc| {
+        enc.put_opaque_fixed(device_id);
+        enc.put_u32(layout);
+        enc.put_u32(maxcount);
+        put_bitmap(enc, notify);
+    })
+}
+
+/// Encode GETDEVICELIST4args
+pub fn getdevicelist(layout: u32, maxdevices: u32, cookie: u64, cookieverf: &[u8; 8]) -> Op {
+    Op::new(op::GETDEVICELIST, |enc| {
+        enc.put_u32(layout);
+        enc.put_u32(maxdevices);
+        enc.put_u64(cookie);
+        enc.put_opaque_fixed(cookieverf);
+    })
+}
+
+/// Device ids worth trying, derived from ids the server actually returned
+///
+/// Servers commonly pack an index or generation number into the id, so
+/// neighbours of observed ids probe table bounds as well as validation.
+pub fn device_id_variants(observed: &[DeviceId]) -> Vec<(String, DeviceId)> {
+    let mut out = vec![
+        ("zero".to_string(), [0u8; DEVICEID_SIZE]),
+        ("ones".to_string(), [0xff; DEVICEID_SIZE]),
+    ];
+    for (i, id) in observed.iter().enumerate() {
+        out.push((format!("observed{}", i), *id));
+
+        let mut first = *id;
+        first[0] ^= 0x80;
+        out.push((format!("observed{}_flip_first", i), first));
+
+        let mut last = *id;
+        last[DEVICEID_SIZE - 1] ^= 0x01;
+        out.push((format!("observed{}_flip_last", i), last));
+
+        // Treat the tail as a big-endian index and step past it
+        let tail = u32::from_be_bytes([id[12], id[13], id[14], id[15]]);
+        for (label, v) in [
+            ("next", tail.wrapping_add(1)),
+            ("prev", tail.wrapping_sub(1)),
+            ("max", u32::MAX),
+        ] {
+            let mut n = *id;
+            n[12..].copy_from_slice(&v.to_be_bytes());
+            out.push((format!("observed{}_{}", i, label), n));
+        }
+    }
+    out
+}
+
+/// Deterministic GETDEVICEINFO cases
+pub fn getdeviceinfo_cases(observed: &[DeviceId]) -> Vec<FuzzCase> {
+    let all_notify = bitmap_from_bits(&[notify_deviceid::CHANGE, notify_deviceid::DELETE]);
+    let base = observed.first().copied().unwrap_or([0u8; DEVICEID_SIZE]);
+    let mut cases = Vec::new();
+
+    for (label, id) in device_id_variants(observed) {
+        for &layout in LAYOUT_TYPES {
+            cases.push(FuzzCase::new(
+                format!("getdeviceinfo_id_{}_layout_{:#x}", label, layout),
+                vec![getdeviceinfo(&id, layout, 4096, &all_notify)],
+            ));
+        }
+    }
+
+    for &maxcount in BOUNDARY_COUNTS {
+        cases.push(FuzzCase::new(
+            format!("getdeviceinfo_maxcount_{:#x}", maxcount),
+            vec![getdeviceinfo(
+                &base,
+                layout_type::NFSV4_1_FILES,
+                maxcount,
+                &all_notify,
+            )],
+        ));
+    }
+
+    let bitmaps: Vec<(&str, Vec<u32>)> = vec![
+        ("empty", vec![]),
+        ("change", bitmap_from_bits(&[notify_deviceid::CHANGE])),
+        ("undefined_bits", vec![!all_notify[0]]),
+        ("all_ones", vec![0xffff_ffff]),
+        ("high_word", bitmap_from_bits(&[1023])),
+        ("many_words", vec![0xffff_ffff; 256]),
+    ];
+    for (label, map) in bitmaps {
+        cases.push(FuzzCase::new(
+            format!("getdeviceinfo_notify_{}", label),
+            vec![getdeviceinfo(&base, layout_type::NFSV4_1_FILES, 4096, &map)],
+        ));
+    }
+
+    // Encoding errors the typed builder can't express
+    let mut lying_bitmap = XdrEncoder::new();
+    lying_bitmap.put_opaque_fixed(&base);
+    lying_bitmap.put_u32(layout_type::NFSV4_1_FILES);
+    lying_bitmap.put_u32(4096);
+    lying_bitmap.put_u32(0xffff_ffff); // bitmap word count
+    lying_bitmap.put_u32(all_notify[0]);
+    cases.push(FuzzCase::new(
+        "getdeviceinfo_notify_count_overflow",
+        vec![Op::raw(op::GETDEVICEINFO, lying_bitmap.as_bytes().to_vec())],
+    ));
+
+    cases.push(FuzzCase::new(
+        "getdeviceinfo_truncated_id",
+        vec![Op::raw(op::GETDEVICEINFO, base[..8].to_vec())],
+    ));
+
+    cases
+}
+
+/// Deterministic GETDEVICELIST cases
+pub fn getdevicelist_cases() -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for &layout in LAYOUT_TYPES {
+        cases.push(FuzzCase::new(
+            format!("getdevicelist_layout_{:#x}", layout),
+            vec![getdevicelist(layout, 16, 0, &[0; 8])],
+        ));
+    }
+    for &maxdevices in BOUNDARY_COUNTS {
+        cases.push(FuzzCase::new(
+            format!("getdevicelist_maxdevices_{:#x}", maxdevices),
+            vec![getdevicelist(
+                layout_type::NFSV4_1_FILES,
+                maxdevices,
+                0,
+                &[0; 8],
+            )],
+        ));
+    }
+    for cookie in [1, 2, u32::MAX as u64, 1 << 63, u64::MAX] {
+        cases.push(FuzzCase::new(
+            format!("getdevicelist_cookie_{:#x}", cookie),
+            vec![getdevicelist(
+                layout_type::NFSV4_1_FILES,
+                16,
+                cookie,
+                &[0xff; 8],
+            )],
+        ));
+    }
+    cases
+}
+
+/// Random GETDEVICEINFO with fields drawn from the boundary tables
+pub fn random_getdeviceinfo<R: Rng + ?Sized>(rng: &mut R, observed: &[DeviceId]) -> Op {
+    let variants = device_id_variants(observed);
+    let (_, id) = &variants[rng.gen_range(0..variants.len())];
+    let layout = LAYOUT_TYPES[rng.gen_range(0..LAYOUT_TYPES.len())];
+    let maxcount = if rng.gen_bool(0.5) {
+        BOUNDARY_COUNTS[rng.gen_range(0..BOUNDARY_COUNTS.len())]
+    } else {
+        rng.gen()
+    };
+    let words = (0..rng.gen_range(0..4))
+        .map(|_| rng.gen())
+        .collect::<Vec<u32>>();
+    getdeviceinfo(id, layout, maxcount, &words)
+}
+
+/// Encode LAYOUTGET4args
+pub fn layoutget(
+    layout_type: u32,
+    iomode: u32,
+    offset: u64,
+    length: u64,
+    minlength: u64,
+    stateid: &Stateid,
+    maxcount: u32,
+) -> Op {
+    Op::new(op::LAYOUTGET, |enc| {
+        enc.put_bool(false); // signal_layout_avail
+        enc.put_u32(layout_type);
+        enc.put_u32(iomode);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+        enc.put_u64(minlength);
+        stateid.encode(enc);
+        enc.put_u32(maxcount);
+    })
+}
+
+/// Encode LAYOUTRETURN4args for a byte range of the current file
+pub fn layoutreturn_file(
+    reclaim: bool,
+    layout_type: u32,
+    iomode: u32,
+    offset: u64,
+    length: u64,
+    stateid: &Stateid,
+    body: &[u8],
+) -> Op {
+    Op::new(op::LAYOUTRETURN, |enc| {
+        enc.put_bool(reclaim);
+        enc.put_u32(layout_type);
+        enc.put_u32(iomode);
+        enc.put_u32(layoutreturn_type::FILE);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+        stateid.encode(enc);
+        enc.put_opaque(body);
+    })
+}
+
+/// Encode LAYOUTRETURN4args returning every layout on the fsid or client
+pub fn layoutreturn_bulk(layout_type: u32, iomode: u32, returntype: u32) -> Op {
+    Op::new(op::LAYOUTRETURN, |enc| {
+        enc.put_bool(false);
+        enc.put_u32(layout_type);
+        enc.put_u32(iomode);
+        enc.put_u32(returntype);
+    })
+}
+
+/// LAYOUTCOMMIT4args
+#[derive(Debug, Clone)]
+pub struct LayoutCommit {
+    pub offset: u64,
+    pub length: u64,
+    pub reclaim: bool,
+    pub stateid: Stateid,
+    pub last_write_offset: Option<u64>,
+    /// Modification time as (seconds, nanoseconds)
+    pub time_modify: Option<(i64, u32)>,
+    pub layout_type: u32,
+    pub body: Vec<u8>,
+}
+
+impl LayoutCommit {
+    /// Commit covering a granted layout, reporting a write up to its end
+    pub fn covering(granted: &GrantedLayout) -> Self {
+        Self {
+            offset: granted.offset,
+            length: granted.length,
+            reclaim: false,
+            stateid: granted.stateid,
+            last_write_offset: Some(granted.end().saturating_sub(1)),
+            time_modify: None,
+            layout_type: granted.layout_type,
+            body: Vec::new(),
+        }
+    }
+
+    pub fn op(&self) -> Op {
+        Op::new(op::LAYOUTCOMMIT, |enc| {
+            enc.put_u64(self.offset);
+            enc.put_u64(self.length);
+            enc.put_bool(self.reclaim);
+            self.stateid.encode(enc);
+            enc.put_bool(self.last_write_offset.is_some());
+            if let Some(off) = self.last_write_offset {
+                enc.put_u64(off);
+            }
+            enc.put_bool(self.time_modify.is_some());
+            if let Some((sec, nsec)) = self.time_modify {
+                put_nfstime(enc, sec, nsec);
+            }
+            enc.put_u32(self.layout_type);
+            enc.put_opaque(&self.body);
+        })
+    }
+}
+
+/// A layout the server granted in a LAYOUTGET reply
+#[derive(Debug, Clone)]
+pub struct GrantedLayout {
+    pub stateid: Stateid,
+    pub layout_type: u32,
+    pub iomode: u32,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl GrantedLayout {
+    /// First byte past the layout (saturating; u64::MAX means "to EOF")
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(self.length)
+    }
+
+    fn return_op(&self, stateid: &Stateid) -> Op {
+        layoutreturn_file(
+            false,
+            self.layout_type,
+            self.iomode,
+            self.offset,
+            self.length,
+            stateid,
+            &[],
+        )
+    }
+}
+
+/// LAYOUTRETURNs for layouts the server never granted
+///
+/// `open_stateid` should be a live open stateid with no layout taken out
+/// against it, so only the layout bookkeeping is wrong.
+pub fn return_ungranted_cases(open_stateid: &Stateid) -> Vec<FuzzCase> {
+    let ret = |stateid: &Stateid| {
+        layoutreturn_file(
+            false,
+            layout_type::NFSV4_1_FILES,
+            layout_iomode::ANY,
+            0,
+            u64::MAX,
+            stateid,
+            &[],
+        )
+    };
+
+    let mut fabricated = *open_stateid;
+    fabricated.other[0] ^= 0xff;
+
+    let mut cases = vec![
+        FuzzCase::new("return_ungranted_open_stateid", vec![ret(open_stateid)]),
+        FuzzCase::new("return_ungranted_fabricated", vec![ret(&fabricated)]),
+        FuzzCase::new("return_ungranted_anonymous", vec![ret(&Stateid::ANONYMOUS)]),
+        FuzzCase::new("return_ungranted_bypass", vec![ret(&Stateid::READ_BYPASS)]),
+        FuzzCase::new("return_ungranted_current", vec![ret(&Stateid::CURRENT)]),
+        FuzzCase::new("return_ungranted_invalid", vec![ret(&Stateid::INVALID)]),
+        FuzzCase::new(
+            "return_ungranted_reclaim",
+            vec![layoutreturn_file(
+                true,
+                layout_type::NFSV4_1_FILES,
+                layout_iomode::RW,
+                0,
+                u64::MAX,
+                open_stateid,
+                &[],
+            )],
+        ),
+        FuzzCase::new(
+            "return_ungranted_zero_length",
+            vec![layoutreturn_file(
+                false,
+                layout_type::NFSV4_1_FILES,
+                layout_iomode::RW,
+                4096,
+                0,
+                open_stateid,
+                &[],
+            )],
+        ),
+        FuzzCase::new(
+            "return_all_before_layoutget",
+            vec![layoutreturn_bulk(
+                layout_type::NFSV4_1_FILES,
+                layout_iomode::ANY,
+                layoutreturn_type::ALL,
+            )],
+        ),
+        FuzzCase::new(
+            "return_fsid_before_layoutget",
+            vec![layoutreturn_bulk(
+                layout_type::NFSV4_1_FILES,
+                layout_iomode::ANY,
+                layoutreturn_type::FSID,
+            )],
+        ),
+    ];
+
+    for &lt in LAYOUT_TYPES {
+        cases.push(FuzzCase::new(
+            format!("return_ungranted_layout_{:#x}", lt),
+            vec![layoutreturn_file(
+                false,
+                lt,
+                layout_iomode::RW,
+                0,
+                u64::MAX,
+                open_stateid,
+                &[],
+            )],
+        ));
+    }
+    cases
+}
+
+/// LAYOUTCOMMITs reaching outside (or otherwise misdescribing) a granted layout
+pub fn commit_beyond_grant_cases(granted: &GrantedLayout) -> Vec<FuzzCase> {
+    let end = granted.end();
+    let variant = |name: &str, f: &dyn Fn(&mut LayoutCommit)| {
+        let mut lc = LayoutCommit::covering(granted);
+        f(&mut lc);
+        FuzzCase::new(name, vec![lc.op()])
+    };
+
+    let mut cases = vec![
+        variant("commit_past_end", &|lc| {
+            lc.offset = end;
+            lc.length = 4096;
+            lc.last_write_offset = Some(end.saturating_add(4095));
+        }),
+        variant("commit_straddle_end", &|lc| {
+            lc.offset = end.saturating_sub(1);
+            lc.length = 4096;
+        }),
+        variant("commit_length_wraps", &|lc| {
+            lc.offset = granted.offset.max(1);
+            lc.length = u64::MAX;
+        }),
+        variant("commit_last_write_beyond", &|lc| {
+            lc.last_write_offset = Some(end.saturating_add(1 << 20));
+        }),
+        variant("commit_last_write_max", &|lc| {
+            lc.last_write_offset = Some(u64::MAX);
+        }),
+        variant("commit_wrong_layout_type", &|lc| {
+            lc.layout_type = if granted.layout_type == layout_type::FLEX_FILES {
+                layout_type::NFSV4_1_FILES
+            } else {
+                layout_type::FLEX_FILES
+            };
+        }),
+        variant("commit_reclaim_outside_grace", &|lc| lc.reclaim = true),
+        variant("commit_time_nsec_overflow", &|lc| {
+            lc.time_modify = Some((0, 1_000_000_000));
+        }),
+        variant("commit_time_negative", &|lc| {
+            lc.time_modify = Some((i64::MIN, 0));
+        }),
+        variant("commit_stale_seqid", &|lc| {
+            lc.stateid.seqid = granted.stateid.seqid.wrapping_sub(1);
+        }),
+        variant("commit_future_seqid", &|lc| {
+            lc.stateid.seqid = granted.stateid.seqid.wrapping_add(1);
+        }),
+        variant("commit_oversized_body", &|lc| lc.body = vec![0x41; 65536]),
+    ];
+
+    if granted.offset > 0 {
+        cases.push(variant("commit_before_start", &|lc| {
+            lc.offset = granted.offset.saturating_sub(4096);
+            lc.length = 4096;
+        }));
+    }
+    if granted.iomode == layout_iomode::READ {
+        // Only RW layouts may be committed
+        cases.push(variant("commit_read_layout", &|_| {}));
+    }
+    cases
+}
+
+/// Layout operations interleaved with returning the state they depend on
+pub fn after_delegreturn_cases(deleg: &Stateid, granted: &GrantedLayout) -> Vec<FuzzCase> {
+    let commit = LayoutCommit::covering(granted).op();
+    let ret = granted.return_op(&granted.stateid);
+    let get_on_deleg = layoutget(
+        granted.layout_type,
+        granted.iomode,
+        granted.offset,
+        granted.length,
+        0,
+        deleg,
+        4096,
+    );
+
+    vec![
+        FuzzCase::new(
+            "delegreturn_then_layoutreturn",
+            vec![delegreturn(deleg), ret.clone()],
+        ),
+        FuzzCase::new(
+            "delegreturn_then_layoutcommit",
+            vec![delegreturn(deleg), commit.clone()],
+        ),
+        FuzzCase::new(
+            "delegreturn_then_layoutget",
+            vec![delegreturn(deleg), get_on_deleg],
+        ),
+        FuzzCase::new(
+            "delegreturn_twice_then_layoutreturn",
+            vec![delegreturn(deleg), delegreturn(deleg), ret.clone()],
+        ),
+        FuzzCase::new(
+            "layoutreturn_with_deleg_stateid",
+            vec![delegreturn(deleg), granted.return_op(deleg)],
+        ),
+        FuzzCase::new("layoutreturn_twice", vec![ret.clone(), ret.clone()]),
+        FuzzCase::new("layoutreturn_then_commit", vec![ret, commit]),
+    ]
+}
+
+/// CB_SEQUENCE fields echoed in a backchannel reply
+#[derive(Debug, Clone)]
+pub struct CbSequence {
+    pub sessionid: SessionId,
+    pub sequenceid: u32,
+    pub slotid: u32,
+    pub highest_slotid: u32,
+    pub target_highest_slotid: u32,
+}
+
+pub(super) fn put_cb_sequence_ok(enc: &mut XdrEncoder, seq: &CbSequence) {
+    enc.put_u32(cb_op::CB_SEQUENCE);
+    enc.put_u32(status::NFS4_OK);
+    enc.put_opaque_fixed(&seq.sessionid);
+    enc.put_u32(seq.sequenceid);
+    enc.put_u32(seq.slotid);
+    enc.put_u32(seq.highest_slotid);
+    enc.put_u32(seq.target_highest_slotid);
+}
+
+/// CB_COMPOUND4res bodies answering a server's CB_NOTIFY_DEVICEID
+///
+/// `tag` and `seq` echo the server's request, and every reply except
+/// "ok" is malformed in one way.
+pub fn cb_notify_deviceid_replies(tag: &[u8], seq: &CbSequence) -> Vec<(&'static str, Vec<u8>)> {
+    let build = |compound_status: u32, count: u32, f: &dyn Fn(&mut XdrEncoder)| {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(compound_status);
+        enc.put_opaque(tag);
+        enc.put_u32(count);
+        f(&mut enc);
+        enc.into_bytes().to_vec()
+    };
+    let notify_res = |enc: &mut XdrEncoder, resop: u32, st: u32| {
+        enc.put_u32(resop);
+        enc.put_u32(st);
+    };
+
+    let mut bad_slot = seq.clone();
+    bad_slot.slotid = seq.slotid.wrapping_add(1);
+    bad_slot.sequenceid = seq.sequenceid.wrapping_sub(1);
+
+    let mut out = vec![
+        (
+            "ok",
+            build(status::NFS4_OK, 2, &|enc| {
+                put_cb_sequence_ok(enc, seq);
+                notify_res(enc, cb_op::CB_NOTIFY_DEVICEID, status::NFS4_OK);
+            }),
+        ),
+        (
+            "missing_sequence",
+            build(status::NFS4_OK, 1, &|enc| {
+                notify_res(enc, cb_op::CB_NOTIFY_DEVICEID, status::NFS4_OK);
+            }),
+        ),
+        (
+            "wrong_resop",
+            build(status::NFS4_OK, 2, &|enc| {
+                put_cb_sequence_ok(enc, seq);
+                notify_res(enc, cb_op::CB_NOTIFY_LOCK, status::NFS4_OK);
+            }),
+        ),
+        (
+            "status_mismatch",
+            build(status::NFS4_OK, 2, &|enc| {
+                put_cb_sequence_ok(enc, seq);
+                notify_res(enc, cb_op::CB_NOTIFY_DEVICEID, status::NFS4ERR_SERVERFAULT);
+            }),
+        ),
+        (
+            "extra_result",
+            build(status::NFS4ERR_OP_ILLEGAL, 3, &|enc| {
+                put_cb_sequence_ok(enc, seq);
+                notify_res(enc, cb_op::CB_NOTIFY_DEVICEID, status::NFS4_OK);
+                notify_res(enc, cb_op::CB_ILLEGAL, status::NFS4ERR_OP_ILLEGAL);
+            }),
+        ),
+        (
+            "count_overflow",
+            build(status::NFS4_OK, 0xffff_ffff, &|enc| {
+                put_cb_sequence_ok(enc, seq);
+                notify_res(enc, cb_op::CB_NOTIFY_DEVICEID, status::NFS4_OK);
+            }),
+        ),
+        (
+            "bad_slot",
+            build(status::NFS4_OK, 2, &|enc| {
+                put_cb_sequence_ok(enc, &bad_slot);
+                notify_res(enc, cb_op::CB_NOTIFY_DEVICEID, status::NFS4_OK);
+            }),
+        ),
+    ];
+
+    // Cut the reply off right after the second resop number
+    let mut truncated = out[0].1.clone();
+    truncated.truncate(truncated.len() - 4);
+    out.push(("truncated", truncated));
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_getdeviceinfo_encoding() {
+        let op = getdeviceinfo(&[7; 16], layout_type::FLEX_FILES, 4096, &[0x6]);
+        // deviceid (16) + layout (4) + maxcount (4) + bitmap count (4) + word (4)
+        assert_eq!(op.args.len(), 32);
+        assert_eq!(&op.args[16..20], &[0, 0, 0, 4]);
+        assert_eq!(&op.args[24..32], &[0, 0, 0, 1, 0, 0, 0, 6]);
+    }
+
+    #[test]
+    fn test_device_id_neighbours() {
+        let mut id = [0u8; 16];
+        id[15] = 5;
+        let variants = device_id_variants(&[id]);
+        let next = variants
+            .iter()
+            .find(|(l, _)| l == "observed0_next")
+            .unwrap();
+        assert_eq!(next.1[15], 6);
+    }
+
+    fn granted() -> GrantedLayout {
+        GrantedLayout {
+            stateid: Stateid::new(2, [9; 12]),
+            layout_type: layout_type::NFSV4_1_FILES,
+            iomode: layout_iomode::READ,
+            offset: 0,
+            length: 1 << 20,
+        }
+    }
+
+    #[test]
+    fn test_layoutcommit_encoding() {
+        let op = LayoutCommit::covering(&granted()).op();
+        // offset + length (16) + reclaim (4) + stateid (16) + newoffset (4 + 8)
+        // + newtime (4) + layout type (4) + empty body (4)
+        assert_eq!(op.args.len(), 60);
+        assert_eq!(&op.args[40..48], &((1u64 << 20) - 1).to_be_bytes());
+    }
+
+    #[test]
+    fn test_commit_beyond_grant() {
+        let cases = commit_beyond_grant_cases(&granted());
+        assert!(cases.iter().any(|c| c.name == "commit_read_layout"));
+        assert!(!cases.iter().any(|c| c.name == "commit_before_start"));
+        let past = cases.iter().find(|c| c.name == "commit_past_end").unwrap();
+        assert_eq!(&past.ops[0].args[0..8], &(1u64 << 20).to_be_bytes());
+    }
+
+    #[test]
+    fn test_cb_notify_replies() {
+        let seq = CbSequence {
+            sessionid: [1; 16],
+            sequenceid: 1,
+            slotid: 0,
+            highest_slotid: 0,
+            target_highest_slotid: 0,
+        };
+        let replies = cb_notify_deviceid_replies(b"", &seq);
+        let ok = &replies.iter().find(|(n, _)| *n == "ok").unwrap().1;
+        // status + tag + count + CB_SEQUENCE (4 + 4 + 16 + 16) + notify result (8)
+        assert_eq!(ok.len(), 12 + 40 + 8);
+        let truncated = &replies.iter().find(|(n, _)| *n == "truncated").unwrap().1;
+        assert_eq!(truncated.len(), ok.len() - 4);
+    }
+}
@@ -0,0 +1,513 @@
+//! Lock reclaim after a client reboot (RFC 8881 §8.4.2, §18.35, §18.51)
+//!
+//! A client that reboots comes back with the same client owner and a new
+//! verifier; the server must drop the old client's state, and reclaims
+//! (OPEN with CLAIM_PREVIOUS, LOCK with `reclaim` set) are only valid
+//! during the server's grace period, only for state actually held, and
+//! only until RECLAIM_COMPLETE. NLM clients announce the reboot through
+//! statd instead. The scenario establishes locks, reboots the client and
+//! sends legitimate and bogus reclaims into the window; which replies
+//! are violations depends on whether the server is in grace. [`run`]
+//! does all of that against a live server, one reboot per reclaim case.
+
+use super::backchannel::CB_PROGRAM;
+use super::lockowner::{lock, lock_type, LockOwner, Locker, TO_EOF};
+use super::open::{delegation_type, Open, OpenClaim};
+use super::session::{self, SessionError};
+use super::{lookupp, op, putfh, status, FuzzCase, Op, Stateid};
+use crate::auth::Identity;
+use crate::connection::NfsConnection;
+use crate::nsm;
+use bytes::BytesMut;
+use rand::Rng;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// client_owner4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientOwner {
+    pub verifier: [u8; 8],
+    pub ownerid: Vec<u8>,
+}
+
+impl ClientOwner {
+    /// The same client after a reboot: same owner id, new verifier
+    pub fn reboot<R: Rng + ?Sized>(&self, rng: &mut R) -> Self {
+        let mut verifier = self.verifier;
+        while verifier == self.verifier {
+            verifier = rng.gen();
+        }
+        Self {
+            verifier,
+            ownerid: self.ownerid.clone(),
+        }
+    }
+}
+
+/// Encode EXCHANGE_ID4args with SP4_NONE and no implementation id
+pub fn exchange_id(owner: &ClientOwner, flags: u32) -> Op {
+    Op::new(op::EXCHANGE_ID, |enc| {
+        enc.put_opaque_fixed(&owner.verifier);
+        enc.put_opaque(&owner.ownerid);
+        enc.put_u32(flags);
+        enc.put_u32(0);
+        enc.put_u32(0);
+    })
+}
+
+/// Encode SETCLIENTID4args with a null callback program and an empty
+/// address, so the server never tries to call back
+pub fn setclientid(owner: &ClientOwner) -> Op {
+    Op::new(op::SETCLIENTID, |enc| {
+        enc.put_opaque_fixed(&owner.verifier);
+        enc.put_opaque(&owner.ownerid);
+        enc.put_u32(0);
+        enc.put_opaque(b"tcp");
+        enc.put_opaque(b"");
+        enc.put_u32(0);
+    })
+}
+
+/// Encode SETCLIENTID_CONFIRM4args
+pub fn setclientid_confirm(clientid: u64, verifier: &[u8; 8]) -> Op {
+    Op::new(op::SETCLIENTID_CONFIRM, |enc| {
+        enc.put_u64(clientid);
+        enc.put_opaque_fixed(verifier);
+    })
+}
+
+/// Encode CREATE_SESSION4args with modest channel attributes, csa_flags
+/// `flags` and an AUTH_NONE backchannel
+pub fn create_session(clientid: u64, sequenceid: u32, flags: u32) -> Op {
+    Op::new(op::CREATE_SESSION, |enc| {
+        enc.put_u64(clientid);
+        enc.put_u32(sequenceid);
+        enc.put_u32(flags);
+        for (size, cached, ops, reqs) in [(1 << 20, 64 << 10, 16, 8), (4096, 0, 2, 1)] {
+            enc.put_u32(0);
+            enc.put_u32(size);
+            enc.put_u32(size);
+            enc.put_u32(cached);
+            enc.put_u32(ops);
+            enc.put_u32(reqs);
+            enc.put_u32(0);
+        }
+        enc.put_u32(CB_PROGRAM);
+        enc.put_u32(1);
+        enc.put_u32(0);
+    })
+}
+
+/// Encode RECLAIM_COMPLETE4args
+pub fn reclaim_complete(one_fs: bool) -> Op {
+    Op::new(op::RECLAIM_COMPLETE, |enc| enc.put_bool(one_fs))
+}
+
+/// A byte-range lock held before the reboot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeldLock {
+    pub locktype: u32,
+    pub offset: u64,
+    pub length: u64,
+}
+
+/// A write lock on the first page and a read lock from the third to EOF
+pub const HELD: &[HeldLock] = &[
+    HeldLock {
+        locktype: lock_type::WRITE,
+        offset: 0,
+        length: 4096,
+    },
+    HeldLock {
+        locktype: lock_type::READ,
+        offset: 8192,
+        length: TO_EOF,
+    },
+];
+
+/// The locks `held` taken one after another, the first creating the
+/// lock-owner under the OPEN and the rest extending its lock stateid
+fn lock_ops(
+    held: &[HeldLock],
+    reclaim: bool,
+    open_stateid: &Stateid,
+    owner: &LockOwner,
+) -> Vec<Op> {
+    held.iter()
+        .enumerate()
+        .map(|(i, h)| {
+            let locker = if i == 0 {
+                Locker::new_owner(open_stateid, owner)
+            } else {
+                Locker::Existing {
+                    lock_stateid: Stateid::CURRENT,
+                    lock_seqid: 0,
+                }
+            };
+            lock(h.locktype, reclaim, h.offset, h.length, &locker)
+        })
+        .collect()
+}
+
+/// Before the reboot: open `name` in the current directory and take
+/// `held`
+pub fn establish_case(
+    clientid: u64,
+    owner: &LockOwner,
+    name: &[u8],
+    held: &[HeldLock],
+) -> FuzzCase {
+    let mut ops = vec![Open::existing(clientid, &owner.owner, name).op()];
+    ops.extend(lock_ops(held, false, &Stateid::CURRENT, owner));
+    FuzzCase::new("establish_locks", ops)
+}
+
+/// What a reclaim case claims
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// State the client held before the reboot
+    Legit,
+    /// State it never held, or more than it held
+    Bogus,
+    /// A reclaim after the client's RECLAIM_COMPLETE
+    AfterComplete,
+    /// New, non-reclaim state
+    NonReclaim,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReclaimCase {
+    pub case: FuzzCase,
+    pub kind: Kind,
+}
+
+fn open_previous(clientid: u64, owner: &LockOwner, deleg: u32) -> Op {
+    let mut open = Open::existing(clientid, &owner.owner, b"");
+    open.claim = OpenClaim::Previous(deleg);
+    open.op()
+}
+
+/// After the reboot, with the new clientid; the current filehandle is
+/// the file that `establish_case` locked
+pub fn reclaim_cases(
+    clientid: u64,
+    owner: &LockOwner,
+    name: &[u8],
+    held: &[HeldLock],
+) -> Vec<ReclaimCase> {
+    let reopen = open_previous(clientid, owner, delegation_type::NONE);
+    let current = Stateid::CURRENT;
+    let case = |name: &str, kind, ops: Vec<Op>| ReclaimCase {
+        case: FuzzCase::new(name, ops),
+        kind,
+    };
+    let with_reopen = |tail: Vec<Op>| {
+        let mut ops = vec![reopen.clone()];
+        ops.extend(tail);
+        ops
+    };
+    let first = held.first().copied().unwrap_or(HELD[0]);
+    let new_owner = Locker::new_owner(&current, owner);
+    let reclaim_lock = |locktype, offset, length| lock(locktype, true, offset, length, &new_owner);
+
+    let mut legit = with_reopen(lock_ops(held, true, &current, owner));
+    legit.push(reclaim_complete(false));
+    let twice = with_reopen(lock_ops(&[first, first], true, &current, owner));
+    let mut new_open = vec![lookupp()];
+    new_open.push(Open::existing(clientid, &owner.owner, name).op());
+
+    vec![
+        case("reclaim_open_and_locks", Kind::Legit, legit),
+        case(
+            "reclaim_open_only",
+            Kind::Legit,
+            with_reopen(vec![reclaim_complete(false)]),
+        ),
+        case(
+            "reclaim_never_held_range",
+            Kind::Bogus,
+            with_reopen(vec![reclaim_lock(lock_type::WRITE, 1 << 40, 4096)]),
+        ),
+        case(
+            "reclaim_wider_range",
+            Kind::Bogus,
+            with_reopen(vec![reclaim_lock(first.locktype, 0, TO_EOF)]),
+        ),
+        case(
+            "reclaim_read_as_write",
+            Kind::Bogus,
+            with_reopen(
+                held.iter()
+                    .filter(|h| h.locktype == lock_type::READ)
+                    .map(|h| reclaim_lock(lock_type::WRITE, h.offset, h.length))
+                    .collect(),
+            ),
+        ),
+        case(
+            "reclaim_write_deleg_never_held",
+            Kind::Bogus,
+            vec![open_previous(clientid, owner, delegation_type::WRITE)],
+        ),
+        case(
+            "reclaim_lock_without_open",
+            Kind::Bogus,
+            vec![lock(
+                first.locktype,
+                true,
+                first.offset,
+                first.length,
+                &Locker::new_owner(&Stateid::ANONYMOUS, owner),
+            )],
+        ),
+        case("reclaim_same_lock_twice", Kind::Bogus, twice),
+        case(
+            "reclaim_after_complete",
+            Kind::AfterComplete,
+            vec![reclaim_complete(false), reopen.clone()],
+        ),
+        case(
+            "reclaim_complete_twice",
+            Kind::AfterComplete,
+            vec![reclaim_complete(false), reclaim_complete(false)],
+        ),
+        case("new_open_in_grace", Kind::NonReclaim, new_open),
+        case(
+            "new_lock_in_grace",
+            Kind::NonReclaim,
+            with_reopen(vec![lock(
+                first.locktype,
+                false,
+                first.offset,
+                first.length,
+                &new_owner,
+            )]),
+        ),
+    ]
+}
+
+/// `n` reclaims of random ranges and types, some exactly a held lock and
+/// the rest overlapping or beside one
+pub fn random_reclaims<R: Rng + ?Sized>(
+    rng: &mut R,
+    clientid: u64,
+    owner: &LockOwner,
+    held: &[HeldLock],
+    n: usize,
+) -> Vec<ReclaimCase> {
+    let reopen = open_previous(clientid, owner, delegation_type::NONE);
+    (0..n)
+        .map(|i| {
+            let base = held[rng.gen_range(0..held.len())];
+            let claim = match rng.gen_range(0..4) {
+                0 => base,
+                1 => HeldLock {
+                    offset: base.offset.wrapping_add(rng.gen_range(1..4096)),
+                    ..base
+                },
+                2 => HeldLock {
+                    length: rng.gen_range(1..=u64::MAX),
+                    ..base
+                },
+                _ => HeldLock {
+                    locktype: rng.gen_range(lock_type::READ..=lock_type::WRITEW),
+                    ..base
+                },
+            };
+            let legit = held.contains(&claim)
+                || held.iter().any(|h| {
+                    // A blocking variant of a held type claims the same lock
+                    h.offset == claim.offset
+                        && h.length == claim.length
+                        && claim.locktype.checked_sub(2) == Some(h.locktype)
+                });
+            ReclaimCase {
+                case: FuzzCase::new(
+                    format!("reclaim_random_{}", i),
+                    vec![
+                        reopen.clone(),
+                        lock(
+                            claim.locktype,
+                            true,
+                            claim.offset,
+                            claim.length,
+                            &Locker::new_owner(&Stateid::CURRENT, owner),
+                        ),
+                    ],
+                ),
+                kind: if legit { Kind::Legit } else { Kind::Bogus },
+            }
+        })
+        .collect()
+}
+
+/// What a reply to a reclaim case shows, if it violates grace rules;
+/// `last_status` is that of the case's last op
+pub fn grace_violation(kind: Kind, in_grace: bool, last_status: u32) -> Option<&'static str> {
+    if last_status != status::NFS4_OK {
+        return None;
+    }
+    match (kind, in_grace) {
+        (Kind::Legit, false) => Some("reclaim accepted outside the grace period"),
+        (Kind::Bogus, _) => Some("reclaim of state never held accepted"),
+        (Kind::AfterComplete, _) => Some("reclaim accepted after RECLAIM_COMPLETE"),
+        (Kind::NonReclaim, true) => Some("new state granted during the grace period"),
+        _ => None,
+    }
+}
+
+/// One simulated client reboot
+#[derive(Debug, Clone)]
+pub struct Reboot {
+    pub before: ClientOwner,
+    pub after: ClientOwner,
+    /// EXCHANGE_ID the rebooted client starts with
+    pub exchange_id: Op,
+    /// SM_NOTIFY for the client's NLM locks, if it holds any
+    pub nsm_notify: Option<BytesMut>,
+}
+
+/// Reboot `owner`; `nlm` is the client's statd identity, host name and
+/// state number before the reboot
+pub fn simulate_reboot<R: Rng + ?Sized>(
+    rng: &mut R,
+    owner: &ClientOwner,
+    nlm: Option<(&Identity, &[u8], i32)>,
+) -> Reboot {
+    let after = owner.reboot(rng);
+    Reboot {
+        before: owner.clone(),
+        exchange_id: exchange_id(&after, 0),
+        after,
+        nsm_notify: nlm.map(|(identity, host, state)| {
+            nsm::notify_call(identity, host, nsm::next_state(state))
+        }),
+    }
+}
+
+/// How the server answered one reclaim case of a [`run`]
+#[derive(Debug, Clone)]
+pub struct Reclaimed {
+    pub case: String,
+    pub kind: Kind,
+    /// Whether the server refused new state with NFS4ERR_GRACE just
+    /// before the reboot
+    pub in_grace: bool,
+    /// Status of the case's compound, that of its last op
+    pub status: u32,
+    pub violation: Option<&'static str>,
+}
+
+/// A compound's status, that of the last op it ran
+fn compound_status(results: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(results.get(..4)?.try_into().ok()?))
+}
+
+/// Run every reclaim case and `random` random ones against `file`,
+/// `name` in directory `dir`, on a v4.1 `conn`. For each case the client
+/// starts afresh with a reboot of `owner`, opens the file and takes
+/// [`HELD`], then reboots again and sends the case behind PUTFH of the
+/// file, before any RECLAIM_COMPLETE. The server takes the client from
+/// the session, so the cases carry client id 0 in their owners.
+#[allow(clippy::too_many_arguments)]
+pub async fn run<S, R>(
+    conn: &mut NfsConnection<S>,
+    rng: &mut R,
+    owner: &ClientOwner,
+    dir: &[u8],
+    name: &[u8],
+    file: &[u8],
+    random: usize,
+    identity: &Identity,
+) -> Result<Vec<Reclaimed>, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: Rng + ?Sized,
+{
+    let locks = LockOwner::new(0, b"reclaim".to_vec());
+    let mut cases = reclaim_cases(0, &locks, name, HELD);
+    cases.extend(random_reclaims(rng, 0, &locks, HELD, random));
+    let establish = establish_case(0, &locks, name, HELD);
+    let mut owner = owner.clone();
+    let mut out = Vec::new();
+    for reclaim in cases {
+        owner = owner.reboot(rng);
+        let mut table = session::establish(conn, &owner, identity).await?;
+        let ops = std::iter::once(putfh(dir)).chain(establish.ops.iter().cloned());
+        let held = session::sequenced(conn, &mut table, ops, identity).await?;
+        let in_grace = match compound_status(&held) {
+            Some(status::NFS4_OK) => false,
+            Some(status::NFS4ERR_GRACE) => true,
+            Some(status) => return Err(SessionError::Status { op: "LOCK", status }),
+            None => return Err(SessionError::Decode("COMPOUND")),
+        };
+        let reboot = simulate_reboot(rng, &owner, None);
+        let (mut table, _) = session::create(conn, reboot.exchange_id, 0, identity).await?;
+        owner = reboot.after;
+        let ops = std::iter::once(putfh(file)).chain(reclaim.case.ops);
+        let results = session::sequenced(conn, &mut table, ops, identity).await?;
+        let status = compound_status(&results).ok_or(SessionError::Decode("COMPOUND"))?;
+        out.push(Reclaimed {
+            case: reclaim.case.name,
+            kind: reclaim.kind,
+            in_grace,
+            status,
+            violation: grace_violation(reclaim.kind, in_grace, status),
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_reboot_keeps_owner_and_changes_verifier() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let owner = ClientOwner {
+            verifier: [1; 8],
+            ownerid: b"client-a".to_vec(),
+        };
+        let r = simulate_reboot(
+            &mut rng,
+            &owner,
+            Some((&Identity::new(0, 0), b"client-a", 5)),
+        );
+        assert_eq!(r.after.ownerid, owner.ownerid);
+        assert_ne!(r.after.verifier, owner.verifier);
+        assert_eq!(&r.exchange_id.args[..8], &r.after.verifier);
+        let notify = r.nsm_notify.unwrap();
+        assert!(notify.ends_with(&nsm::stat_chge(b"client-a", 7)));
+        assert!(simulate_reboot(&mut rng, &owner, None).nsm_notify.is_none());
+    }
+
+    #[test]
+    fn test_reclaim_cases_and_verdicts() {
+        let owner = LockOwner::new(9, b"lock-owner".to_vec());
+        let cases = reclaim_cases(9, &owner, b"file", HELD);
+        let legit = &cases[0];
+        assert_eq!(legit.kind, Kind::Legit);
+        let opcodes: Vec<u32> = legit.case.ops.iter().map(|o| o.opcode).collect();
+        assert_eq!(
+            opcodes,
+            [op::OPEN, op::LOCK, op::LOCK, op::RECLAIM_COMPLETE]
+        );
+        // LOCK4args: type, then the reclaim flag
+        assert_eq!(&legit.case.ops[1].args[4..8], &[0, 0, 0, 1]);
+        assert!(cases.iter().any(|c| c.kind == Kind::NonReclaim));
+        let establish = establish_case(9, &owner, b"file", HELD);
+        assert_eq!(&establish.ops[1].args[4..8], &[0, 0, 0, 0]);
+
+        assert!(grace_violation(Kind::Legit, true, status::NFS4_OK).is_none());
+        assert!(grace_violation(Kind::Legit, false, status::NFS4_OK).is_some());
+        assert!(grace_violation(Kind::Bogus, true, status::NFS4ERR_RECLAIM_BAD).is_none());
+        assert!(grace_violation(Kind::NonReclaim, true, status::NFS4ERR_GRACE).is_none());
+        assert!(grace_violation(Kind::NonReclaim, false, status::NFS4_OK).is_none());
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let random = random_reclaims(&mut rng, 9, &owner, HELD, 64);
+        assert!(random.iter().any(|c| c.kind == Kind::Legit));
+        assert!(random.iter().any(|c| c.kind == Kind::Bogus));
+    }
+}
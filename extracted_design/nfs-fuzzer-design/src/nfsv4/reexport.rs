@@ -0,0 +1,270 @@
+//! Re-export loops and nesting
+//!
+//! A server that re-exports an NFS mount is itself a client of the
+//! server behind it. Two re-exporters pointed at each other, or one
+//! re-exporting an export that leads back to itself, form a loop that
+//! only depth limits and cycle checks in the re-export path can break.
+//! Walks here descend the export path repeated many times over, cross a
+//! mount point down and back up again and again, and plant symlinks
+//! that lead back up the tree, asking for fsid and fileid at every level.
+//! [`decode_walk`] recovers those levels from the reply, and
+//! [`first_cycle`] and [`crossing_drift`] say where the walk went round
+//! or came back somewhere else.
+
+use super::{
+    attr, bitmap_from_bits, getattr, lookup, lookupp, op, putrootfh, referral::Pathname, remove,
+    status, FuzzCase, Op,
+};
+use crate::xdr::XdrDecoder;
+
+/// How many times the export path is repeated in the repeat walks
+pub const DEPTHS: &[usize] = &[1, 2, 4, 8, 16, 32, 64];
+
+/// Where each level of a walk is
+pub fn level_mask() -> Vec<u32> {
+    bitmap_from_bits(&[attr::FSID, attr::FILEID])
+}
+
+fn step(name: &[u8]) -> [Op; 2] {
+    [lookup(name), getattr(&level_mask())]
+}
+
+/// From the root: `export`'s components repeated `depth` times, each
+/// LOOKUP followed by a GETATTR of the level.
+pub fn repeat_cases(export: &Pathname, depths: &[usize]) -> Vec<FuzzCase> {
+    depths
+        .iter()
+        .map(|&depth| {
+            let mut ops = vec![putrootfh(), getattr(&level_mask())];
+            for c in export.iter().cycle().take(export.len() * depth) {
+                ops.extend(step(c));
+            }
+            FuzzCase::new(format!("reexport_repeat_{}", depth), ops)
+        })
+        .collect()
+}
+
+/// From the root to the parent of `mount`, then into the mount point and
+/// back out `times` times, with the level after every crossing
+pub fn crossing_cases(mount: &Pathname, times: &[usize]) -> Vec<FuzzCase> {
+    let Some((last, parent)) = mount.split_last() else {
+        return Vec::new();
+    };
+    times
+        .iter()
+        .map(|&n| {
+            let mut ops = vec![putrootfh()];
+            ops.extend(parent.iter().map(|c| lookup(c)));
+            ops.push(getattr(&level_mask()));
+            for _ in 0..n {
+                ops.extend(step(last));
+                ops.extend([lookupp(), getattr(&level_mask())]);
+            }
+            FuzzCase::new(format!("reexport_cross_{}", n), ops)
+        })
+        .collect()
+}
+
+/// Symlinks leading back up from a directory: to itself, its parent,
+/// the root, the directory by absolute path, and a pair naming each other
+pub fn loop_links(dir: &Pathname) -> Vec<(&'static str, Vec<u8>)> {
+    let absolute: Vec<u8> = dir.iter().flat_map(|c| [&b"/"[..], c].concat()).collect();
+    vec![
+        ("loop_self", b".".to_vec()),
+        ("loop_up", b"..".to_vec()),
+        ("loop_root", b"/".to_vec()),
+        ("loop_abs", absolute),
+        ("loop_a", b"loop_b".to_vec()),
+        ("loop_b", b"loop_a".to_vec()),
+    ]
+}
+
+/// CREATE of a symlink `name` to `target` in the current directory
+pub fn symlink(name: &[u8], target: &[u8]) -> Op {
+    Op::new(op::CREATE, |enc| {
+        // NF4LNK and its linkdata, the name, an empty fattr4
+        enc.put_u32(5);
+        enc.put_opaque(target);
+        enc.put_opaque(name);
+        enc.put_u32(0);
+        enc.put_u32(0);
+    })
+}
+
+/// Plant the [`loop_links`] of writable `dir`, then for each: LOOKUP it,
+/// READLINK it and LOOKUP through it, which the server has to refuse
+/// with NFS4ERR_SYMLINK rather than follow. The last case removes them.
+pub fn symlink_loop_cases(dir: &Pathname) -> Vec<FuzzCase> {
+    let walk = || {
+        let mut ops = vec![putrootfh()];
+        ops.extend(dir.iter().map(|c| lookup(c)));
+        ops
+    };
+    let links = loop_links(dir);
+    let mut create = walk();
+    create.extend(
+        links
+            .iter()
+            .map(|(name, target)| symlink(name.as_bytes(), target)),
+    );
+    let mut cases = vec![FuzzCase::new("reexport_symlinks_create", create)];
+    for (name, _) in &links {
+        let mut ops = walk();
+        ops.extend([
+            lookup(name.as_bytes()),
+            Op::raw(op::READLINK, Vec::new()),
+            lookup(dir.last().map_or(&b"."[..], |c| c)),
+            getattr(&level_mask()),
+        ]);
+        cases.push(FuzzCase::new(format!("reexport_through_{}", name), ops));
+    }
+    let mut cleanup = walk();
+    cleanup.extend(links.iter().map(|(name, _)| remove(name.as_bytes())));
+    cases.push(FuzzCase::new("reexport_symlinks_remove", cleanup));
+    cases
+}
+
+/// Where one level of a walk was
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Level {
+    pub fsid: (u64, u64),
+    pub fileid: u64,
+}
+
+/// What a walk's reply says about it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Walk {
+    /// Each GETATTR's level, in order
+    pub levels: Vec<Level>,
+    /// Index and status of the op the walk stopped at, if one failed;
+    /// an NFS4ERR_SYMLINK or depth error here is the limit the server
+    /// enforces
+    pub failed: Option<(usize, u32)>,
+}
+
+/// Levels of a walk from its COMPOUND4res; `None` if the reply does not
+/// decode. GETATTRs that returned neither fsid nor fileid are skipped.
+pub fn decode_walk(results: &[u8]) -> Option<Walk> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    let count = dec.get_u32().ok()?;
+    let mut walk = Walk::default();
+    for i in 0..count as usize {
+        let opcode = dec.get_u32().ok()?;
+        let stat = dec.get_u32().ok()?;
+        if stat != status::NFS4_OK {
+            walk.failed = Some((i, stat));
+            break;
+        }
+        if opcode != op::GETATTR {
+            continue;
+        }
+        let words = dec.get_u32().ok()?;
+        let mut mask = Vec::new();
+        for _ in 0..words {
+            mask.push(dec.get_u32().ok()?);
+        }
+        let vals = dec.get_opaque().ok()?;
+        let has = |bit: u32| mask.first().is_some_and(|w| w & (1 << bit) != 0);
+        let mut vals = XdrDecoder::new(vals);
+        let mut level = Level {
+            fsid: (0, 0),
+            fileid: 0,
+        };
+        if has(attr::FSID) {
+            level.fsid = (vals.get_u64().ok()?, vals.get_u64().ok()?);
+        }
+        if has(attr::FILEID) {
+            level.fileid = vals.get_u64().ok()?;
+        }
+        if has(attr::FSID) || has(attr::FILEID) {
+            walk.levels.push(level);
+        }
+    }
+    Some(walk)
+}
+
+/// The first level a walk reached twice, as (first, again): going down
+/// the tree led back to somewhere it had already been
+pub fn first_cycle(levels: &[Level]) -> Option<(usize, usize)> {
+    levels.iter().enumerate().find_map(|(again, level)| {
+        levels[..again]
+            .iter()
+            .position(|l| l == level)
+            .map(|first| (first, again))
+    })
+}
+
+/// The first level of a crossing walk that is not where it should be:
+/// even levels are the parent and odd levels the mount point
+pub fn crossing_drift(levels: &[Level]) -> Option<usize> {
+    let (parent, child) = (levels.first()?, levels.get(1)?);
+    levels
+        .iter()
+        .enumerate()
+        .position(|(i, l)| l != if i.is_multiple_of(2) { parent } else { child })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xdr::XdrEncoder;
+
+    fn export() -> Pathname {
+        vec![b"srv".to_vec(), b"nfs".to_vec()]
+    }
+
+    #[test]
+    fn test_walk_cases() {
+        let cases = repeat_cases(&export(), DEPTHS);
+        assert_eq!(cases.len(), DEPTHS.len());
+        // PUTROOTFH, GETATTR, then LOOKUP and GETATTR per component
+        assert_eq!(cases[2].name, "reexport_repeat_4");
+        assert_eq!(cases[2].ops.len(), 2 + 2 * 2 * 4);
+        let cross = crossing_cases(&export(), &[3]);
+        assert_eq!(cross[0].ops.len(), 1 + 1 + 1 + 4 * 3);
+        assert!(crossing_cases(&Vec::new(), &[3]).is_empty());
+        let links = symlink_loop_cases(&export());
+        assert_eq!(links.len(), loop_links(&export()).len() + 2);
+        assert_eq!(loop_links(&export())[3].1, b"/srv/nfs");
+    }
+
+    #[test]
+    fn test_decode_walk_and_cycles() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(status::NFS4ERR_NOENT);
+        enc.put_opaque(b"");
+        enc.put_u32(4);
+        enc.put_u32(op::PUTROOTFH);
+        enc.put_u32(0);
+        for (fsid, fileid) in [(1, 2), (7, 2)] {
+            enc.put_u32(op::GETATTR);
+            enc.put_u32(0);
+            enc.put_u32(1);
+            enc.put_u32(1 << attr::FSID | 1 << attr::FILEID);
+            let mut vals = XdrEncoder::new();
+            vals.put_u64(fsid);
+            vals.put_u64(0);
+            vals.put_u64(fileid);
+            enc.put_opaque(vals.as_bytes());
+        }
+        enc.put_u32(op::LOOKUP);
+        enc.put_u32(status::NFS4ERR_NOENT);
+        let walk = decode_walk(enc.as_bytes()).unwrap();
+        assert_eq!(walk.levels.len(), 2);
+        assert_eq!(walk.levels[1].fsid, (7, 0));
+        assert_eq!(walk.failed, Some((3, status::NFS4ERR_NOENT)));
+        assert!(decode_walk(&enc.as_bytes()[..30]).is_none());
+
+        let l = |fsid, fileid| Level {
+            fsid: (fsid, 0),
+            fileid,
+        };
+        assert_eq!(first_cycle(&[l(1, 1), l(2, 1), l(3, 1)]), None);
+        assert_eq!(first_cycle(&[l(1, 1), l(2, 5), l(1, 1)]), Some((0, 2)));
+        assert_eq!(crossing_drift(&[l(1, 1), l(2, 1), l(1, 1), l(2, 1)]), None);
+        // Back out of the mount point into the re-exported server's root
+        assert_eq!(crossing_drift(&[l(1, 1), l(2, 1), l(2, 2)]), Some(2));
+    }
+}
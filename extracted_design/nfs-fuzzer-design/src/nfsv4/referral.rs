@@ -0,0 +1,418 @@
+//! Referrals and migration (RFC 8881 §11, §11.10)
+//!
+//! A directory that is the root of an absent filesystem answers most ops
+//! with NFS4ERR_MOVED; only a few attributes, fs_locations and
+//! fs_locations_info among them, may still be fetched, and they tell the
+//! client where the filesystem went. That error path, the attribute
+//! encoders behind it and the client-driven follow-up are rarely
+//! exercised. Cases request and decode the location attributes, send
+//! the not-allowed ops to absent filesystems, and follow the returned
+//! rootpaths both as given and mutated.
+
+use super::{
+    attr, bitmap_from_bits, getattr, getfh, lookup, lookupp, op, put_fattr, putrootfh, readdir,
+    setattr, Fattr, FuzzCase, Op, Stateid,
+};
+use crate::scenario::NAME_MAX;
+use crate::xdr::{XdrDecoder, XdrEncoder, XdrError};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ReferralError {
+    #[error("location attribute truncated at offset {0}")]
+    Truncated(usize),
+}
+
+impl From<XdrError> for ReferralError {
+    fn from(e: XdrError) -> Self {
+        ReferralError::Truncated(e.offset())
+    }
+}
+
+/// pathname4: components, outermost first
+pub type Pathname = Vec<Vec<u8>>;
+
+/// The components of `/`-separated `path`, as an export is named
+pub fn components(path: &str) -> Pathname {
+    path.split('/')
+        .filter(|c| !c.is_empty())
+        .map(|c| c.as_bytes().to_vec())
+        .collect()
+}
+
+/// fs_location4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsLocation {
+    pub servers: Vec<Vec<u8>>,
+    pub rootpath: Pathname,
+}
+
+/// fs_locations4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsLocations {
+    pub fs_root: Pathname,
+    pub locations: Vec<FsLocation>,
+}
+
+/// fs_locations_server4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsLocationsServer {
+    pub currency: i32,
+    pub info: Vec<u8>,
+    pub server: Vec<u8>,
+}
+
+/// fs_locations_item4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsLocationsItem {
+    pub entries: Vec<FsLocationsServer>,
+    pub rootpath: Pathname,
+}
+
+/// fs_locations_info4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsLocationsInfo {
+    pub flags: u32,
+    pub valid_for: i32,
+    pub fs_root: Pathname,
+    pub items: Vec<FsLocationsItem>,
+}
+
+fn put_pathname(enc: &mut XdrEncoder, path: &Pathname) {
+    enc.put_u32(path.len() as u32);
+    for c in path {
+        enc.put_opaque(c);
+    }
+}
+
+impl FsLocations {
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        put_pathname(enc, &self.fs_root);
+        enc.put_u32(self.locations.len() as u32);
+        for loc in &self.locations {
+            enc.put_u32(loc.servers.len() as u32);
+            for s in &loc.servers {
+                enc.put_opaque(s);
+            }
+            put_pathname(enc, &loc.rootpath);
+        }
+    }
+
+    /// Decode from the start of `buf`; returns the value and bytes used
+    pub fn decode(buf: &[u8]) -> Result<(Self, usize), ReferralError> {
+        let mut r = XdrDecoder::new(buf);
+        let fs_root = pathname(&mut r)?;
+        let locations = array(&mut r, |r| {
+            Ok(FsLocation {
+                servers: array(r, opaque)?,
+                rootpath: pathname(r)?,
+            })
+        })?;
+        Ok((Self { fs_root, locations }, r.position()))
+    }
+}
+
+impl FsLocationsInfo {
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_u32(self.flags);
+        enc.put_i32(self.valid_for);
+        put_pathname(enc, &self.fs_root);
+        enc.put_u32(self.items.len() as u32);
+        for item in &self.items {
+            enc.put_u32(item.entries.len() as u32);
+            for e in &item.entries {
+                enc.put_i32(e.currency);
+                enc.put_opaque(&e.info);
+                enc.put_opaque(&e.server);
+            }
+            put_pathname(enc, &item.rootpath);
+        }
+    }
+
+    /// Decode from the start of `buf`; returns the value and bytes used
+    pub fn decode(buf: &[u8]) -> Result<(Self, usize), ReferralError> {
+        let mut r = XdrDecoder::new(buf);
+        let flags = r.get_u32()?;
+        let valid_for = r.get_i32()?;
+        let fs_root = pathname(&mut r)?;
+        let items = array(&mut r, |r| {
+            Ok(FsLocationsItem {
+                entries: array(r, |r| {
+                    Ok(FsLocationsServer {
+                        currency: r.get_i32()?,
+                        info: opaque(r)?,
+                        server: opaque(r)?,
+                    })
+                })?,
+                rootpath: pathname(r)?,
+            })
+        })?;
+        let info = Self {
+            flags,
+            valid_for,
+            fs_root,
+            items,
+        };
+        Ok((info, r.position()))
+    }
+}
+
+fn opaque(r: &mut XdrDecoder<'_>) -> Result<Vec<u8>, ReferralError> {
+    Ok(r.get_opaque()?.to_vec())
+}
+
+/// Counted array; every element takes at least 4 bytes, so a count
+/// larger than the rest of the buffer allows is truncation
+fn array<'a, T>(
+    r: &mut XdrDecoder<'a>,
+    mut f: impl FnMut(&mut XdrDecoder<'a>) -> Result<T, ReferralError>,
+) -> Result<Vec<T>, ReferralError> {
+    let at = r.position();
+    let n = r.get_u32()? as usize;
+    if n > r.remaining() / 4 {
+        return Err(ReferralError::Truncated(at));
+    }
+    (0..n).map(|_| f(r)).collect()
+}
+
+fn pathname(r: &mut XdrDecoder<'_>) -> Result<Pathname, ReferralError> {
+    array(r, opaque)
+}
+
+/// The attributes an absent filesystem must still return
+pub fn location_mask() -> Vec<u32> {
+    bitmap_from_bits(&[attr::FSID, attr::FS_LOCATIONS, attr::MOUNTED_ON_FILEID])
+}
+
+/// Probes of the referral point `name` in the current directory
+pub fn absent_fs_cases(name: &[u8]) -> Vec<FuzzCase> {
+    let at = |label: &str, tail: Vec<Op>| {
+        let mut ops = vec![lookup(name)];
+        ops.extend(tail);
+        FuzzCase::new(format!("absent_fs_{}", label), ops)
+    };
+    let info = bitmap_from_bits(&[attr::FS_LOCATIONS_INFO, attr::FS_STATUS]);
+    let everything = bitmap_from_bits(&[
+        attr::TYPE,
+        attr::SIZE,
+        attr::FSID,
+        attr::FS_LOCATIONS,
+        attr::MOUNTED_ON_FILEID,
+        attr::FS_LOCATIONS_INFO,
+    ]);
+    vec![
+        at("fs_locations", vec![getattr(&location_mask())]),
+        at("fs_locations_info", vec![getattr(&info)]),
+        // Mixing in ordinary attributes must turn the reply into MOVED
+        at("mixed_attrs", vec![getattr(&everything)]),
+        at("getfh", vec![getfh()]),
+        at(
+            "readdir",
+            vec![readdir(0, &[0; 8], 4096, 32768, &location_mask())],
+        ),
+        at("lookup_below", vec![lookup(b"below"), getfh()]),
+        at("lookupp", vec![lookupp(), getfh()]),
+    ]
+}
+
+/// Rootpaths derived from `path`: as given, then with escapes, empty and
+/// overlong components and huge depth
+pub fn mutated_rootpaths(path: &Pathname) -> Vec<(String, Pathname)> {
+    let with = |f: &dyn Fn(&mut Pathname)| {
+        let mut p = path.clone();
+        f(&mut p);
+        p
+    };
+    vec![
+        ("as_given".to_string(), path.clone()),
+        (
+            "dotdot_prefix".to_string(),
+            with(&|p| p.insert(0, b"..".to_vec())),
+        ),
+        (
+            "dotdot_suffix".to_string(),
+            with(&|p| p.push(b"..".to_vec())),
+        ),
+        ("empty_component".to_string(), with(&|p| p.push(Vec::new()))),
+        (
+            "slash_component".to_string(),
+            with(&|p| p.push(b"a/b".to_vec())),
+        ),
+        (
+            "long_component".to_string(),
+            with(&|p| p.push(vec![b'a'; NAME_MAX + 1])),
+        ),
+        (
+            "deep".to_string(),
+            with(&|p| p.extend(vec![b"a".to_vec(); 1000])),
+        ),
+        ("root".to_string(), Vec::new()),
+    ]
+}
+
+/// Walk to each location's rootpath on this server, as given and mutated,
+/// and ask for locations again at the end (referral loops)
+pub fn follow_cases(locations: &FsLocations) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for (i, loc) in locations.locations.iter().enumerate() {
+        for (label, path) in mutated_rootpaths(&loc.rootpath) {
+            let mut ops = vec![putrootfh()];
+            ops.extend(path.iter().map(|c| lookup(c)));
+            ops.push(getfh());
+            ops.push(getattr(&location_mask()));
+            cases.push(FuzzCase::new(format!("follow_{}_{}", i, label), ops));
+        }
+    }
+    cases
+}
+
+/// fs_locations values no server should produce
+pub fn hostile_locations() -> Vec<(&'static str, FsLocations)> {
+    let loc = |servers: Vec<Vec<u8>>, rootpath: Pathname| FsLocation { servers, rootpath };
+    let root = vec![b"export".to_vec()];
+    vec![
+        (
+            "no_locations",
+            FsLocations {
+                fs_root: root.clone(),
+                locations: Vec::new(),
+            },
+        ),
+        (
+            "no_servers",
+            FsLocations {
+                fs_root: root.clone(),
+                locations: vec![loc(Vec::new(), root.clone())],
+            },
+        ),
+        (
+            "self_loop",
+            FsLocations {
+                fs_root: root.clone(),
+                locations: vec![loc(vec![b"localhost".to_vec()], root.clone())],
+            },
+        ),
+        (
+            "many_locations",
+            FsLocations {
+                fs_root: root.clone(),
+                locations: vec![loc(vec![b"s".to_vec()], root.clone()); 4096],
+            },
+        ),
+        (
+            "hostile_server_names",
+            FsLocations {
+                fs_root: root.clone(),
+                locations: vec![loc(
+                    vec![
+                        Vec::new(),
+                        b"[::1]:2049".to_vec(),
+                        b"a\0b".to_vec(),
+                        vec![b'h'; 1024],
+                    ],
+                    vec![b"..".to_vec()],
+                )],
+            },
+        ),
+    ]
+}
+
+/// SETATTR of the read-only fs_locations attribute with hostile values;
+/// the server must reject it, but decodes it first
+pub fn setattr_locations_cases() -> Vec<FuzzCase> {
+    hostile_locations()
+        .into_iter()
+        .map(|(label, locs)| {
+            let mut enc = XdrEncoder::new();
+            locs.encode(&mut enc);
+            let attrs = Fattr {
+                mask: bitmap_from_bits(&[attr::FS_LOCATIONS]),
+                vals: enc.as_bytes().to_vec(),
+            };
+            FuzzCase::new(
+                format!("setattr_fs_locations_{}", label),
+                vec![setattr(&Stateid::ANONYMOUS, &attrs)],
+            )
+        })
+        .collect()
+}
+
+/// VERIFY and NVERIFY of fs_locations_info against `info`, which makes
+/// the server encode the attribute and compare it byte for byte
+pub fn verify_locations_info_cases(info: &FsLocationsInfo) -> Vec<FuzzCase> {
+    let mut vals = XdrEncoder::new();
+    info.encode(&mut vals);
+    let attrs = Fattr {
+        mask: bitmap_from_bits(&[attr::FS_LOCATIONS_INFO]),
+        vals: vals.as_bytes().to_vec(),
+    };
+    [("verify", op::VERIFY), ("nverify", op::NVERIFY)]
+        .into_iter()
+        .map(|(label, opcode)| {
+            FuzzCase::new(
+                format!("{}_fs_locations_info", label),
+                vec![Op::new(opcode, |enc| put_fattr(enc, &attrs))],
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> FsLocations {
+        FsLocations {
+            fs_root: vec![b"vol".to_vec(), b"a".to_vec()],
+            locations: vec![FsLocation {
+                servers: vec![b"server1".to_vec(), b"10.0.0.2".to_vec()],
+                rootpath: vec![b"exports".to_vec(), b"vol".to_vec()],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_locations_roundtrip_and_truncation() {
+        let mut enc = XdrEncoder::new();
+        sample().encode(&mut enc);
+        let bytes = enc.as_bytes();
+        assert_eq!(FsLocations::decode(bytes), Ok((sample(), bytes.len())));
+        assert!(FsLocations::decode(&bytes[..bytes.len() - 4]).is_err());
+        // A location count far beyond the buffer is rejected, not allocated
+        assert_eq!(
+            FsLocations::decode(&[0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]),
+            Err(ReferralError::Truncated(4))
+        );
+
+        let info = FsLocationsInfo {
+            flags: 1,
+            valid_for: -1,
+            fs_root: vec![b"vol".to_vec()],
+            items: vec![FsLocationsItem {
+                entries: vec![FsLocationsServer {
+                    currency: -5,
+                    info: vec![0; 7],
+                    server: b"s".to_vec(),
+                }],
+                rootpath: Vec::new(),
+            }],
+        };
+        let mut enc = XdrEncoder::new();
+        info.encode(&mut enc);
+        assert_eq!(FsLocationsInfo::decode(enc.as_bytes()).unwrap().0, info);
+        let verify = verify_locations_info_cases(&info);
+        // Bitmap word count: bit 67 needs three words
+        assert_eq!(&verify[0].ops[0].args[..4], &[0, 0, 0, 3]);
+    }
+
+    #[test]
+    fn test_follow_cases_walk_rootpath() {
+        let cases = follow_cases(&sample());
+        assert_eq!(cases.len(), mutated_rootpaths(&Vec::new()).len());
+        // PUTROOTFH, LOOKUP exports, LOOKUP vol, GETFH, GETATTR
+        assert_eq!(cases[0].name, "follow_0_as_given");
+        assert_eq!(cases[0].ops.len(), 5);
+        assert_eq!(absent_fs_cases(b"ref").len(), 7);
+        assert_eq!(setattr_locations_cases().len(), hostile_locations().len());
+    }
+}
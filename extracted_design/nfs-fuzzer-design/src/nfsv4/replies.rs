@@ -0,0 +1,185 @@
+//! Checks of v4 replies against the compounds that asked for them
+//!
+//! Some replies are wrong in ways only the request shows: READ_PLUS
+//! segments that do not cover the range asked for, or a time read back
+//! after SETATTR that is not what was set, nor anything a filesystem
+//! could round or clamp it to. [`check`] walks a compound's arguments and
+//! results in step, over the operations the sparse-file and timestamp
+//! cases use, and stops at the first it cannot skip or that failed.
+
+use super::sparse::{self, check_read_plus};
+use super::times::{self, check_time};
+use super::{op, status};
+use crate::preset::Oracle;
+use crate::xdr::{XdrDecoder, XdrError};
+
+fn get_bitmap(dec: &mut XdrDecoder<'_>) -> Result<Vec<u32>, XdrError> {
+    let mut mask = Vec::new();
+    for _ in 0..dec.get_u32()? {
+        mask.push(dec.get_u32()?);
+    }
+    Ok(mask)
+}
+
+/// What `args` and `results` of one compound break, each under the
+/// oracle that catches it
+pub fn check(args: &[u8], results: &[u8]) -> Vec<(Oracle, String)> {
+    let mut found = Vec::new();
+    let _ = walk(
+        &mut XdrDecoder::new(args),
+        &mut XdrDecoder::new(results),
+        &mut found,
+    );
+    found
+}
+
+fn walk(
+    args: &mut XdrDecoder<'_>,
+    res: &mut XdrDecoder<'_>,
+    found: &mut Vec<(Oracle, String)>,
+) -> Result<(), XdrError> {
+    // Tag and minor version, then status and tag
+    args.get_opaque()?;
+    args.get_u32()?;
+    res.get_u32()?;
+    res.get_opaque()?;
+    let count = args.get_u32()?.min(res.get_u32()?);
+    // Client times of the last SETATTR that took
+    let mut set = Vec::new();
+    for i in 0..count {
+        let opcode = args.get_u32()?;
+        if res.get_u32()? != opcode {
+            break;
+        }
+        let ok = res.get_u32()? == status::NFS4_OK;
+        match opcode {
+            op::PUTROOTFH | op::PUTPUBFH | op::SAVEFH | op::RESTOREFH | op::GETFH => {
+                if opcode == op::GETFH && ok {
+                    res.get_opaque()?;
+                }
+            }
+            op::PUTFH | op::LOOKUP => {
+                args.get_opaque()?;
+            }
+            op::SEQUENCE => {
+                args.get_opaque_fixed(32)?;
+                if ok {
+                    res.get_opaque_fixed(36)?;
+                }
+            }
+            op::ALLOCATE | op::DEALLOCATE => {
+                args.get_opaque_fixed(32)?;
+            }
+            op::SEEK => {
+                args.get_opaque_fixed(28)?;
+                if ok {
+                    res.get_opaque_fixed(12)?;
+                }
+            }
+            op::READ_PLUS => {
+                // Stateid, then offset and count
+                args.get_opaque_fixed(16)?;
+                let (offset, count) = (args.get_u64()?, args.get_u32()?);
+                if ok {
+                    match sparse::decode_read_plus(res) {
+                        Some((_, segments)) => {
+                            if let Err(e) = check_read_plus(offset, count, &segments) {
+                                found.push((Oracle::ReadPlus, format!("op {}: {}", i, e)));
+                            }
+                        }
+                        None => {
+                            let e = format!("op {}: READ_PLUS results do not decode", i);
+                            found.push((Oracle::ReadPlus, e));
+                            break;
+                        }
+                    }
+                }
+            }
+            op::SETATTR => {
+                args.get_opaque_fixed(16)?;
+                let mask = get_bitmap(args)?;
+                let vals = args.get_opaque()?;
+                // attrsset, there whether or not it took
+                get_bitmap(res)?;
+                if ok {
+                    set = times::set_times(&mask, vals).unwrap_or_default();
+                }
+            }
+            op::GETATTR => {
+                get_bitmap(args)?;
+                if ok {
+                    let mask = get_bitmap(res)?;
+                    let vals = res.get_opaque()?;
+                    let read = times::read_times(&mask, vals).unwrap_or_default();
+                    for (attr, t) in &set {
+                        let Some((_, got)) = read.iter().find(|(a, _)| a == attr) else {
+                            continue;
+                        };
+                        if let Err(e) = check_time(*t, *got) {
+                            let e = format!("op {}: attribute {}: {}", i, attr, e);
+                            found.push((Oracle::Timestamps, e));
+                        }
+                    }
+                }
+            }
+            _ => break,
+        }
+        if !ok {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::compound_args;
+    use crate::nfsv4::times::{time_attrs, SetTime};
+    use crate::nfsv4::{attr, bitmap_from_bits, ok_results, put_bitmap, put_nfstime, Stateid};
+    use crate::xdr::XdrEncoder;
+
+    #[test]
+    fn test_read_plus_and_times_are_checked() {
+        let stateid = Stateid::ANONYMOUS;
+        let args = compound_args(&[sparse::read_plus(&stateid, 4096, 100)]);
+        let reply = |offset: u64| {
+            ok_results(&[
+                (op::PUTROOTFH, &|_| {}),
+                (op::READ_PLUS, &move |enc| {
+                    enc.put_u32(1);
+                    enc.put_u32(1);
+                    enc.put_u32(sparse::content::HOLE);
+                    enc.put_u64(offset);
+                    enc.put_u64(4096);
+                }),
+            ])
+        };
+        assert!(check(&args, &reply(4096)).is_empty());
+        let found = check(&args, &reply(8192));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Oracle::ReadPlus);
+
+        let t = SetTime::Client(u32::MAX as i64 + 1, 0);
+        let args = compound_args(&[
+            crate::nfsv4::setattr(&stateid, &time_attrs(None, None, Some(t))),
+            times::get_times(),
+        ]);
+        let read_back = |secs: i64| {
+            ok_results(&[
+                (op::PUTROOTFH, &|_| {}),
+                (op::SETATTR, &|enc| put_bitmap(enc, &[0, 1 << 22])),
+                (op::GETATTR, &move |enc| {
+                    put_bitmap(enc, &bitmap_from_bits(&[attr::TIME_MODIFY]));
+                    let mut vals = XdrEncoder::new();
+                    put_nfstime(&mut vals, secs, 0);
+                    enc.put_opaque(vals.as_bytes());
+                }),
+            ])
+        };
+        assert!(check(&args, &read_back(u32::MAX as i64)).is_empty());
+        let found = check(&args, &read_back(0));
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Oracle::Timestamps);
+    }
+}
@@ -0,0 +1,295 @@
+//! v4.1 reply-cache probing (RFC 8881 §2.10.6)
+//!
+//! With sa_cachethis set the server must keep the whole reply for a slot
+//! so a retry with the same (slot, sequenceid) gets it back verbatim.
+//! Marking expensive compounds as cacheable and then replaying, falsely
+//! retrying and reusing their slots exercises the storage and retrieval
+//! of large cached replies.
+
+use super::session::Sequence;
+use super::{getattr, putfh, putrootfh, read, readdir, status, Op, SessionId, Stateid};
+
+/// What a correct server does with a replayed step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expect {
+    /// A new request on the slot; executed normally
+    Fresh,
+    /// Byte-identical to the reply of the step at this index
+    CachedFrom(usize),
+    /// Same slot and sequenceid but different operations
+    FalseRetry,
+    /// Retry of a request that was not cached
+    RetryUncached,
+    /// Sequence id neither current nor next
+    Misordered,
+}
+
+impl Expect {
+    /// SEQUENCE status codes a correct server may return for this step
+    pub fn acceptable_status(&self) -> &'static [u32] {
+        match self {
+            Expect::Fresh | Expect::CachedFrom(_) => &[status::NFS4_OK],
+            // Servers may detect the mismatch or just return the cached reply
+            Expect::FalseRetry => &[status::NFS4ERR_SEQ_FALSE_RETRY, status::NFS4_OK],
+            // Servers may cache the reply anyway (RFC 8881 §2.10.6.1.3),
+            // in which case it must be step 0's
+            Expect::RetryUncached => &[status::NFS4ERR_RETRY_UNCACHED_REP, status::NFS4_OK],
+            Expect::Misordered => &[status::NFS4ERR_SEQ_MISORDERED],
+        }
+    }
+}
+
+/// One COMPOUND in a replay plan: SEQUENCE plus the operations after it
+#[derive(Debug, Clone)]
+pub struct ReplayStep {
+    pub sequence: Sequence,
+    pub ops: Vec<Op>,
+    pub expect: Expect,
+}
+
+impl ReplayStep {
+    /// The full argarray, SEQUENCE first
+    pub fn all_ops(&self) -> Vec<Op> {
+        let mut ops = vec![self.sequence.op()];
+        ops.extend(self.ops.iter().cloned());
+        ops
+    }
+}
+
+/// A named sequence of COMPOUNDs on one slot
+#[derive(Debug, Clone)]
+pub struct ReplayPlan {
+    pub name: String,
+    pub steps: Vec<ReplayStep>,
+}
+
+/// Compounds with large replies, worth forcing into the cache
+pub fn high_cost_compounds(dir_fh: &[u8], file_fh: &[u8]) -> Vec<(&'static str, Vec<Op>)> {
+    let all_attrs = [0xffff_ffff; 3];
+    vec![
+        (
+            "readdir_all_attrs",
+            vec![
+                putfh(dir_fh),
+                readdir(0, &[0; 8], 1 << 20, 1 << 20, &all_attrs),
+            ],
+        ),
+        (
+            "read_1m",
+            vec![putfh(file_fh), read(&Stateid::ANONYMOUS, 0, 1 << 20)],
+        ),
+        ("getattr_x64", {
+            let mut ops = vec![putfh(dir_fh)];
+            ops.extend((0..64).map(|_| getattr(&all_attrs)));
+            ops
+        }),
+        ("root_getattr_all", vec![putrootfh(), getattr(&all_attrs)]),
+    ]
+}
+
+/// Current state of the slot a plan runs on
+#[derive(Debug, Clone)]
+pub struct SlotState {
+    pub sessionid: SessionId,
+    pub slotid: u32,
+    pub highest_slotid: u32,
+    /// Sequence id the server expects next on this slot
+    pub next_seqid: u32,
+}
+
+impl SlotState {
+    fn seq(&self, offset: u32, cachethis: bool) -> Sequence {
+        Sequence {
+            sessionid: self.sessionid,
+            sequenceid: self.next_seqid.wrapping_add(offset),
+            slotid: self.slotid,
+            highest_slotid: self.highest_slotid,
+            cachethis,
+        }
+    }
+}
+
+/// Replay plans for one expensive compound on one slot
+///
+/// `decoy` is a cheap compound used as the falsely-retried body.
+pub fn replay_plans(slot: &SlotState, name: &str, ops: &[Op], decoy: &[Op]) -> Vec<ReplayPlan> {
+    let step = |offset, cachethis, ops: &[Op], expect| ReplayStep {
+        sequence: slot.seq(offset, cachethis),
+        ops: ops.to_vec(),
+        expect,
+    };
+
+    vec![
+        ReplayPlan {
+            name: format!("{}_cached_replay", name),
+            steps: vec![
+                step(0, true, ops, Expect::Fresh),
+                step(0, true, ops, Expect::CachedFrom(0)),
+                step(0, true, ops, Expect::CachedFrom(0)),
+            ],
+        },
+        ReplayPlan {
+            name: format!("{}_false_retry", name),
+            steps: vec![
+                step(0, true, ops, Expect::Fresh),
+                step(0, true, decoy, Expect::FalseRetry),
+                step(0, false, ops, Expect::CachedFrom(0)),
+            ],
+        },
+        ReplayPlan {
+            name: format!("{}_uncached_retry", name),
+            steps: vec![
+                step(0, false, ops, Expect::Fresh),
+                step(0, true, ops, Expect::RetryUncached),
+            ],
+        },
+        ReplayPlan {
+            name: format!("{}_slot_reuse", name),
+            steps: vec![
+                step(0, true, ops, Expect::Fresh),
+                step(1, true, decoy, Expect::Fresh),
+                // The large entry must have been evicted by the decoy
+                step(0, true, ops, Expect::Misordered),
+                step(1, true, decoy, Expect::CachedFrom(1)),
+            ],
+        },
+        ReplayPlan {
+            name: format!("{}_seqid_wrap", name),
+            steps: vec![
+                step(0, true, ops, Expect::Fresh),
+                step(u32::MAX, true, ops, Expect::Misordered),
+                step(2, true, ops, Expect::Misordered),
+            ],
+        },
+    ]
+}
+
+/// Fill every slot up to `highest_slotid` with the same cached compound,
+/// then replay them all, holding the maximum reply-cache footprint
+///
+/// Assumes every slot expects `slot.next_seqid`, as on a fresh session.
+pub fn fill_all_slots(slot: &SlotState, ops: &[Op]) -> ReplayPlan {
+    let mut steps = Vec::new();
+    let slots = 0..=slot.highest_slotid;
+    for slotid in slots.clone() {
+        let mut s = slot.clone();
+        s.slotid = slotid;
+        steps.push(ReplayStep {
+            sequence: s.seq(0, true),
+            ops: ops.to_vec(),
+            expect: Expect::Fresh,
+        });
+    }
+    for (i, slotid) in slots.enumerate() {
+        let mut s = slot.clone();
+        s.slotid = slotid;
+        steps.push(ReplayStep {
+            sequence: s.seq(0, true),
+            ops: ops.to_vec(),
+            expect: Expect::CachedFrom(i),
+        });
+    }
+    ReplayPlan {
+        name: "fill_all_slots".to_string(),
+        steps,
+    }
+}
+
+/// Check a replayed reply against the step's expectation
+///
+/// `replies` holds the COMPOUND reply bodies (after the RPC header) seen
+/// so far in the plan, indexed by step; an `Err` describes a violation.
+pub fn check_replay(
+    step: &ReplayStep,
+    sequence_status: u32,
+    reply: &[u8],
+    replies: &[Vec<u8>],
+) -> Result<(), String> {
+    if !step.expect.acceptable_status().contains(&sequence_status) {
+        return Err(format!(
+            "{:?}: unexpected SEQUENCE status {}",
+            step.expect, sequence_status
+        ));
+    }
+    let cached_from = match step.expect {
+        Expect::CachedFrom(i) => Some(i),
+        Expect::RetryUncached if sequence_status == status::NFS4_OK => Some(0),
+        _ => None,
+    };
+    if let Some(i) = cached_from {
+        match replies.get(i) {
+            Some(original) if original.as_slice() != reply => {
+                return Err(format!(
+                    "cached replay of step {} differs ({} vs {} bytes)",
+                    i,
+                    original.len(),
+                    reply.len()
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slot() -> SlotState {
+        SlotState {
+            sessionid: [1; 16],
+            slotid: 0,
+            highest_slotid: 3,
+            next_seqid: 7,
+        }
+    }
+
+    #[test]
+    fn test_replay_plans_reuse_seqid() {
+        let plans = replay_plans(&slot(), "x", &[putrootfh()], &[putrootfh()]);
+        let cached = &plans[0];
+        assert!(cached
+            .steps
+            .iter()
+            .all(|s| s.sequence.sequenceid == 7 && s.sequence.cachethis));
+        assert_eq!(
+            cached.steps[1].all_ops()[0].opcode,
+            super::super::op::SEQUENCE
+        );
+    }
+
+    #[test]
+    fn test_check_replay_detects_divergence() {
+        let step = ReplayStep {
+            sequence: slot().seq(0, true),
+            ops: vec![],
+            expect: Expect::CachedFrom(0),
+        };
+        let first = vec![vec![1, 2, 3]];
+        assert!(check_replay(&step, status::NFS4_OK, &[1, 2, 3], &first).is_ok());
+        assert!(check_replay(&step, status::NFS4_OK, &[1, 2], &first).is_err());
+        assert!(check_replay(&step, status::NFS4ERR_BADSLOT, &[1, 2, 3], &first).is_err());
+    }
+
+    #[test]
+    fn test_check_replay_uncached_retry_may_be_cached() {
+        let step = ReplayStep {
+            sequence: slot().seq(0, true),
+            ops: vec![],
+            expect: Expect::RetryUncached,
+        };
+        let first = vec![vec![1, 2, 3]];
+        let uncached = status::NFS4ERR_RETRY_UNCACHED_REP;
+        assert!(check_replay(&step, uncached, &[4], &first).is_ok());
+        assert!(check_replay(&step, status::NFS4_OK, &[1, 2, 3], &first).is_ok());
+        assert!(check_replay(&step, status::NFS4_OK, &[1, 2, 4], &first).is_err());
+    }
+
+    #[test]
+    fn test_fill_all_slots() {
+        let plan = fill_all_slots(&slot(), &[putrootfh()]);
+        assert_eq!(plan.steps.len(), 8);
+        assert_eq!(plan.steps[7].expect, Expect::CachedFrom(3));
+    }
+}
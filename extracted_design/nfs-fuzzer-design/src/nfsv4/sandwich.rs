@@ -0,0 +1,294 @@
+//! Compound framing for single operations
+//!
+//! Most v4 operations act on the current filehandle, and some also on the
+//! saved one or on an open stateid, so an operation sent on its own is
+//! rejected with NOFILEHANDLE or BAD_STATEID before its arguments are
+//! looked at. A sandwich puts the shortest prefix that reaches the
+//! operation in front of it: SEQUENCE where a session is known, then
+//! PUTROOTFH and a LOOKUP per path component, the file's LOOKUP or OPEN
+//! when the operation wants a file, and SAVEFH when it reads the saved
+//! filehandle. A GETATTR after it reads back the object the operation
+//! left current, so a reply shows whether the operation changed it.
+//!
+//! Stateful operations are reached through an OPEN in the same compound;
+//! on v4.1 and later their stateid should be [`Stateid::CURRENT`] to use
+//! it. v4.0 has no current stateid, so there the OPEN only provides the
+//! share reservation.
+//!
+//! [`Stateid::CURRENT`]: super::Stateid::CURRENT
+
+use super::open::Open;
+use super::session::Sequence;
+use super::{
+    attr, bitmap_from_bits, getattr, lookup, minor_version, op, putrootfh, savefh, CompoundBuilder,
+    FuzzCase, Op,
+};
+
+/// What has to be in place before an operation can run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reach {
+    /// Runs anywhere in a compound, with no filehandle
+    Nothing,
+    /// Starts a compound of its own, without SEQUENCE (v4.1 session and
+    /// client setup)
+    Alone,
+    /// Current filehandle of the directory, or of any object
+    Dir,
+    /// Current filehandle of the file
+    File,
+    /// The file opened, with its open stateid current
+    Opened,
+    /// The directory both saved and current (RENAME, RESTOREFH)
+    SavedDir,
+    /// The file saved and the directory current (LINK)
+    SavedFile,
+    /// The file opened, saved and current (COPY, CLONE)
+    SavedOpened,
+}
+
+impl Reach {
+    /// What `opcode` needs; unknown opcodes need nothing
+    pub fn of(opcode: u32) -> Self {
+        match opcode {
+            op::EXCHANGE_ID
+            | op::CREATE_SESSION
+            | op::DESTROY_SESSION
+            | op::BIND_CONN_TO_SESSION
+            | op::DESTROY_CLIENTID
+            | op::SEQUENCE => Reach::Alone,
+            op::ACCESS
+            | op::CREATE
+            | op::GETATTR
+            | op::GETFH
+            | op::LOOKUP
+            | op::LOOKUPP
+            | op::NVERIFY
+            | op::OPEN
+            | op::OPENATTR
+            | op::READDIR
+            | op::REMOVE
+            | op::SAVEFH
+            | op::SECINFO
+            | op::SECINFO_NO_NAME
+            | op::VERIFY
+            | op::GET_DIR_DELEGATION
+            | op::GETDEVICELIST => Reach::Dir,
+            op::COMMIT
+            | op::DELEGRETURN
+            | op::LOCKT
+            | op::READLINK
+            | op::SETATTR
+            | op::WANT_DELEGATION
+            | op::OFFLOAD_CANCEL
+            | op::OFFLOAD_STATUS => Reach::File,
+            op::CLOSE
+            | op::LOCK
+            | op::LOCKU
+            | op::OPEN_CONFIRM
+            | op::OPEN_DOWNGRADE
+            | op::READ
+            | op::WRITE
+            | op::LAYOUTCOMMIT
+            | op::LAYOUTGET
+            | op::LAYOUTRETURN
+            | op::ALLOCATE
+            | op::COPY_NOTIFY
+            | op::DEALLOCATE
+            | op::IO_ADVISE
+            | op::LAYOUTERROR
+            | op::LAYOUTSTATS
+            | op::READ_PLUS
+            | op::SEEK
+            | op::WRITE_SAME => Reach::Opened,
+            op::RENAME | op::RESTOREFH => Reach::SavedDir,
+            op::LINK => Reach::SavedFile,
+            op::COPY | op::CLONE => Reach::SavedOpened,
+            _ => Reach::Nothing,
+        }
+    }
+
+    /// Whether a filehandle is current once the prefix has run
+    pub fn has_fh(self) -> bool {
+        !matches!(self, Reach::Nothing | Reach::Alone)
+    }
+}
+
+/// The object sandwiches reach, and the state they reach it with
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub minor_version: u32,
+    /// Path of the directory from the root, one component per LOOKUP
+    pub dir: Vec<Vec<u8>>,
+    /// The file in that directory
+    pub name: Vec<u8>,
+    /// Client and open-owner the OPEN uses
+    pub clientid: u64,
+    pub owner: Vec<u8>,
+    /// SEQUENCE to start v4.1+ compounds with; without one the server
+    /// answers OP_NOT_IN_SESSION, which is still a useful reply offline
+    pub sequence: Option<Sequence>,
+}
+
+impl Target {
+    /// The file `name` directly under the root
+    pub fn new(minor_version: u32, name: &[u8]) -> Self {
+        Self {
+            minor_version,
+            dir: Vec::new(),
+            name: name.to_vec(),
+            clientid: 0,
+            owner: b"sandwich".to_vec(),
+            sequence: None,
+        }
+    }
+
+    pub fn with_dir(mut self, components: &[&[u8]]) -> Self {
+        self.dir = components.iter().map(|c| c.to_vec()).collect();
+        self
+    }
+
+    pub fn with_owner(mut self, clientid: u64, owner: &[u8]) -> Self {
+        self.clientid = clientid;
+        self.owner = owner.to_vec();
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: Sequence) -> Self {
+        self.sequence = Some(sequence);
+        self
+    }
+
+    fn walk(&self, ops: &mut Vec<Op>) {
+        ops.push(putrootfh());
+        ops.extend(self.dir.iter().map(|c| lookup(c)));
+    }
+
+    fn open(&self) -> Op {
+        Open::existing(self.clientid, &self.owner, &self.name).op()
+    }
+
+    /// The shortest prefix that makes an operation needing `reach`
+    /// reachable
+    pub fn prefix(&self, reach: Reach) -> Vec<Op> {
+        let mut ops = Vec::new();
+        if self.minor_version >= minor_version::V4_1 && reach != Reach::Alone {
+            ops.extend(self.sequence.as_ref().map(Sequence::op));
+        }
+        match reach {
+            Reach::Nothing | Reach::Alone => {}
+            Reach::Dir => self.walk(&mut ops),
+            Reach::File => {
+                self.walk(&mut ops);
+                ops.push(lookup(&self.name));
+            }
+            Reach::Opened => {
+                self.walk(&mut ops);
+                ops.push(self.open());
+            }
+            Reach::SavedDir => {
+                self.walk(&mut ops);
+                ops.push(savefh());
+            }
+            Reach::SavedFile => {
+                // LOOKUPP from a file is NOTDIR, so walk back down
+                self.walk(&mut ops);
+                ops.push(lookup(&self.name));
+                ops.push(savefh());
+                self.walk(&mut ops);
+            }
+            Reach::SavedOpened => {
+                self.walk(&mut ops);
+                ops.push(self.open());
+                ops.push(savefh());
+            }
+        }
+        ops
+    }
+
+    /// `ops` framed for their first operation, which decides the prefix,
+    /// with a GETATTR after them when a filehandle is current
+    pub fn wrap(&self, ops: &[Op]) -> CompoundBuilder {
+        let reach = ops.first().map_or(Reach::Nothing, |o| Reach::of(o.opcode));
+        let sets_fh = ops
+            .iter()
+            .any(|o| matches!(o.opcode, op::PUTFH | op::PUTROOTFH | op::PUTPUBFH));
+        let builder = CompoundBuilder::new(self.minor_version)
+            .ops(self.prefix(reach))
+            .ops(ops.iter().cloned());
+        if reach.has_fh() || sets_fh {
+            builder.ops(suffix())
+        } else {
+            builder
+        }
+    }
+
+    /// A fuzz case framed by [`Target::wrap`] and tagged with its name
+    pub fn case(&self, case: &FuzzCase) -> CompoundBuilder {
+        self.wrap(&case.ops).with_tag(case.name.as_bytes())
+    }
+}
+
+/// Attributes that show what an operation did to the current object
+pub const VERIFY_ATTRS: [u32; 4] = [attr::TYPE, attr::CHANGE, attr::SIZE, attr::TIME_MODIFY];
+
+/// The verification suffix: GETATTR of [`VERIFY_ATTRS`]
+pub fn suffix() -> Vec<Op> {
+    vec![getattr(&bitmap_from_bits(&VERIFY_ATTRS))]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::{read, rename, Stateid};
+
+    #[test]
+    fn test_prefixes() {
+        let target = Target::new(minor_version::V4_0, b"f").with_dir(&[b"export", b"dir"]);
+        let codes = |reach| -> Vec<u32> { target.prefix(reach).iter().map(|o| o.opcode).collect() };
+        assert!(codes(Reach::Nothing).is_empty());
+        assert_eq!(codes(Reach::Dir), [op::PUTROOTFH, op::LOOKUP, op::LOOKUP]);
+        assert_eq!(codes(Reach::Opened)[3], op::OPEN);
+        assert_eq!(
+            codes(Reach::SavedFile),
+            [
+                op::PUTROOTFH,
+                op::LOOKUP,
+                op::LOOKUP,
+                op::LOOKUP,
+                op::SAVEFH,
+                op::PUTROOTFH,
+                op::LOOKUP,
+                op::LOOKUP
+            ]
+        );
+        assert_eq!(Reach::of(op::READ), Reach::Opened);
+        assert_eq!(Reach::of(op::RENAME), Reach::SavedDir);
+        assert_eq!(Reach::of(op::ILLEGAL), Reach::Nothing);
+    }
+
+    #[test]
+    fn test_wrap() {
+        let seq = Sequence {
+            sessionid: [1; 16],
+            sequenceid: 1,
+            slotid: 0,
+            highest_slotid: 0,
+            cachethis: false,
+        };
+        let target = Target::new(minor_version::V4_1, b"f").with_sequence(seq.clone());
+        let compound = target.wrap(&[read(&Stateid::CURRENT, 0, 4096)]);
+        // SEQUENCE, PUTROOTFH, OPEN, READ, GETATTR
+        assert_eq!(compound.len(), 5);
+        assert_eq!(compound.build()[12..16], op::SEQUENCE.to_be_bytes());
+
+        // Session setup goes alone, and leaves no filehandle to verify
+        let alone = target.wrap(&[Op::raw(op::DESTROY_SESSION, vec![1; 16])]);
+        assert_eq!(alone.len(), 1);
+        // An explicit PUTROOTFH still gets the GETATTR
+        assert_eq!(target.wrap(&[putrootfh()]).len(), 3);
+
+        let case = FuzzCase::new("rename", vec![rename(b"f", b"g")]);
+        let args = Target::new(minor_version::V4_0, b"f").case(&case).build();
+        assert_eq!(args[..10], [0, 0, 0, 6, b'r', b'e', b'n', b'a', b'm', b'e']);
+    }
+}
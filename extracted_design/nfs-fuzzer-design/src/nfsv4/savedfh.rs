@@ -0,0 +1,247 @@
+//! Saved filehandle confusion (RFC 8881 §16.2.3.1, §18.27, §18.28)
+//!
+//! A compound carries two filehandle slots, current and saved, and on
+//! v4.1 a current and saved stateid alongside them. SAVEFH copies the
+//! current pair into the saved one and RESTOREFH copies it back; RENAME,
+//! LINK and COPY read both. Servers keep these slots in per-compound
+//! state that is reset, reference-counted and freed by hand, so cases
+//! here use the saved slot before anything was saved, keep it across
+//! operations that destroy what it refers to, and pair it with current
+//! handles that were mutated or never validated.
+
+use super::open::Open;
+use super::session::destroy_session;
+use super::{
+    attr, bitmap_from_bits, close, getattr, getfh, link, lookup, lookupp, op, openattr, putfh,
+    putrootfh, read, remove, rename, restorefh, savefh, stable_how, write, FuzzCase, Op, SessionId,
+    Stateid,
+};
+use crate::crossfh::{handle_variants, NFS4_FHSIZE};
+use crate::mixed::SharedFile;
+
+/// Names RENAME and LINK give the shared file
+pub const RENAMED: &[u8] = b"savedfh-renamed";
+pub const LINKED: &[u8] = b"savedfh-link";
+
+fn attrs() -> Op {
+    getattr(&bitmap_from_bits(&[
+        attr::TYPE,
+        attr::CHANGE,
+        attr::SIZE,
+        attr::FILEHANDLE,
+    ]))
+}
+
+/// Encode DESTROY_CLIENTID4args
+fn destroy_clientid(clientid: u64) -> Op {
+    Op::new(op::DESTROY_CLIENTID, |enc| enc.put_u64(clientid))
+}
+
+/// The saved slot used before anything was saved into it, or reused;
+/// these need no server state
+pub fn unsaved_cases() -> Vec<FuzzCase> {
+    vec![
+        FuzzCase::new(
+            "savedfh_restore_unsaved",
+            vec![putrootfh(), restorefh(), getfh()],
+        ),
+        FuzzCase::new(
+            "savedfh_save_without_current",
+            vec![savefh(), restorefh(), getfh()],
+        ),
+        FuzzCase::new(
+            "savedfh_rename_unsaved",
+            vec![putrootfh(), rename(b"a", b"b")],
+        ),
+        FuzzCase::new("savedfh_link_unsaved", vec![putrootfh(), link(LINKED)]),
+        FuzzCase::new(
+            "savedfh_restore_twice",
+            vec![
+                putrootfh(),
+                savefh(),
+                getfh(),
+                restorefh(),
+                restorefh(),
+                getfh(),
+            ],
+        ),
+        // The saved slot is replaced, not stacked
+        FuzzCase::new(
+            "savedfh_save_twice",
+            vec![putrootfh(), savefh(), savefh(), restorefh(), attrs()],
+        ),
+    ]
+}
+
+/// The saved slot kept across operations that remove, close or tear
+/// down what it refers to, then restored and used
+pub fn across_cases(file: &SharedFile, sessionid: &SessionId) -> Vec<FuzzCase> {
+    let open = Open::existing(file.clientid, &file.owner, &file.name).op();
+    let current = Stateid::CURRENT;
+    vec![
+        FuzzCase::new(
+            "savedfh_across_remove",
+            vec![
+                putfh(&file.file4),
+                savefh(),
+                putfh(&file.dir4),
+                remove(&file.name),
+                restorefh(),
+                attrs(),
+                write(&Stateid::ANONYMOUS, 0, stable_how::FILE_SYNC, b"removed"),
+            ],
+        ),
+        FuzzCase::new(
+            "savedfh_across_rename",
+            vec![
+                putfh(&file.dir4),
+                savefh(),
+                rename(&file.name, RENAMED),
+                restorefh(),
+                lookup(&file.name),
+                getfh(),
+            ],
+        ),
+        // SAVEFH saves the current stateid too, so the READ gets the
+        // open stateid the CLOSE just retired
+        FuzzCase::new(
+            "savedfh_across_close",
+            vec![
+                putfh(&file.dir4),
+                open,
+                savefh(),
+                close(0, &current),
+                restorefh(),
+                read(&current, 0, 4096),
+                attrs(),
+            ],
+        ),
+        FuzzCase::new(
+            "savedfh_across_destroy_session",
+            vec![
+                putfh(&file.file4),
+                savefh(),
+                destroy_session(sessionid),
+                restorefh(),
+                attrs(),
+            ],
+        ),
+        FuzzCase::new(
+            "savedfh_across_destroy_clientid",
+            vec![
+                putfh(&file.file4),
+                savefh(),
+                destroy_clientid(file.clientid),
+                restorefh(),
+                attrs(),
+            ],
+        ),
+        // Restoring from inside the named attribute namespace, and back
+        // out of it
+        FuzzCase::new(
+            "savedfh_across_openattr",
+            vec![
+                putfh(&file.file4),
+                openattr(false),
+                savefh(),
+                putfh(&file.file4),
+                restorefh(),
+                lookupp(),
+                getfh(),
+            ],
+        ),
+    ]
+}
+
+/// Each mutation of the file's handle as the current handle against a
+/// valid saved one, and saved against a valid current one
+pub fn interleave_cases(file: &SharedFile) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for (label, fh) in handle_variants(&file.file4, NFS4_FHSIZE)
+        .into_iter()
+        .skip(1)
+    {
+        let name = |what: &str| format!("savedfh_mutated_{}_{}", label, what);
+        cases.push(FuzzCase::new(
+            name("rename_target"),
+            vec![
+                putfh(&file.dir4),
+                savefh(),
+                putfh(&fh),
+                rename(&file.name, RENAMED),
+            ],
+        ));
+        cases.push(FuzzCase::new(
+            name("link_target"),
+            vec![putfh(&file.file4), savefh(), putfh(&fh), link(LINKED)],
+        ));
+        cases.push(FuzzCase::new(
+            name("link_source"),
+            vec![putfh(&fh), savefh(), putfh(&file.dir4), link(LINKED)],
+        ));
+        cases.push(FuzzCase::new(
+            name("restored"),
+            vec![
+                putfh(&fh),
+                savefh(),
+                putfh(&file.dir4),
+                restorefh(),
+                attrs(),
+                getfh(),
+            ],
+        ));
+    }
+    cases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file() -> SharedFile {
+        SharedFile {
+            name: b"f".to_vec(),
+            dir3: vec![1; 32],
+            file3: vec![2; 32],
+            dir4: vec![3; 16],
+            file4: vec![4; 16],
+            clientid: 9,
+            owner: b"o".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_unsaved_and_across_cases() {
+        let unsaved = unsaved_cases();
+        assert_eq!(unsaved[0].ops[1].opcode, op::RESTOREFH);
+        assert!(unsaved.iter().all(|c| c
+            .ops
+            .iter()
+            .any(|o| matches!(o.opcode, op::RESTOREFH | op::RENAME | op::LINK))));
+
+        let across = across_cases(&file(), &[7; 16]);
+        assert_eq!(across.len(), 6);
+        for case in &across {
+            let codes: Vec<u32> = case.ops.iter().map(|o| o.opcode).collect();
+            let save = codes.iter().position(|&c| c == op::SAVEFH).unwrap();
+            let restore = codes.iter().position(|&c| c == op::RESTOREFH).unwrap();
+            assert!(save + 1 < restore, "{}", case.name);
+        }
+    }
+
+    #[test]
+    fn test_interleave_cases() {
+        let file = file();
+        let variants = handle_variants(&file.file4, NFS4_FHSIZE).len() - 1;
+        let cases = interleave_cases(&file);
+        assert_eq!(cases.len(), 4 * variants);
+        // No case uses the handle unmutated where the mutation goes
+        let valid = putfh(&file.file4);
+        let restored = cases
+            .iter()
+            .find(|c| c.name == "savedfh_mutated_truncated_restored")
+            .unwrap();
+        assert_ne!(restored.ops[0], valid);
+        assert_eq!(restored.ops[0], putfh(&file.file4[..8]));
+    }
+}
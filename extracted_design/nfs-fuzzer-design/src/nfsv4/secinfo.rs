@@ -0,0 +1,353 @@
+//! Security flavor downgrade (RFC 8881 §2.6.3)
+//!
+//! SECINFO tells a client which flavors an export accepts; an export that
+//! lists only RPCSEC_GSS/krb5 must answer other flavors with
+//! NFS4ERR_WRONGSEC. Servers enforce that per operation, and whether
+//! every path honours the policy is a different question from whether
+//! lookup does. Cases ask the export for its flavors, then try every
+//! operation with AUTH_SYS and AUTH_NONE anyway; an operation that
+//! succeeds under an unlisted flavor is a policy bypass.
+
+use super::{
+    attr, bitmap_from_bits, getattr, getfh, lookup, op, read, readdir, referral::Pathname, remove,
+    setattr, status, write, Fattr, FuzzCase, Op, Stateid,
+};
+use crate::auth::Identity;
+use crate::rpc::{auth_flavor, auth_none};
+use crate::xdr::{XdrDecoder, XdrEncoder};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SecinfoError {
+    #[error("SECINFO result truncated at offset {0}")]
+    Truncated(usize),
+}
+
+/// secinfo_style4
+pub mod style {
+    pub const CURRENT_FH: u32 = 0;
+    pub const PARENT: u32 = 1;
+}
+
+/// rpc_gss_svc_t
+pub use crate::rpc::gss_service;
+
+/// Kerberos V5 mechanism OID (1.2.840.113554.1.2.2), DER encoded
+pub const KRB5_OID: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02,
+];
+
+/// secinfo4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Secinfo {
+    Flavor(u32),
+    Gss {
+        oid: Vec<u8>,
+        qop: u32,
+        service: u32,
+    },
+}
+
+impl Secinfo {
+    pub fn flavor(&self) -> u32 {
+        match self {
+            Secinfo::Flavor(f) => *f,
+            Secinfo::Gss { .. } => auth_flavor::RPCSEC_GSS,
+        }
+    }
+
+    pub fn is_krb5(&self) -> bool {
+        matches!(self, Secinfo::Gss { oid, .. } if oid == KRB5_OID)
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_u32(self.flavor());
+        if let Secinfo::Gss { oid, qop, service } = self {
+            enc.put_opaque(oid);
+            enc.put_u32(*qop);
+            enc.put_u32(*service);
+        }
+    }
+}
+
+fn take<'a>(buf: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8], SecinfoError> {
+    let out = pos
+        .checked_add(n)
+        .and_then(|end| buf.get(*pos..end))
+        .ok_or(SecinfoError::Truncated(*pos))?;
+    *pos += n;
+    Ok(out)
+}
+
+fn take_u32(buf: &[u8], pos: &mut usize) -> Result<u32, SecinfoError> {
+    Ok(u32::from_be_bytes(take(buf, pos, 4)?.try_into().unwrap()))
+}
+
+/// Decode SECINFO4resok (also SECINFO_NO_NAME4resok); returns the
+/// entries and the bytes consumed
+pub fn decode_secinfo(buf: &[u8]) -> Result<(Vec<Secinfo>, usize), SecinfoError> {
+    let mut pos = 0;
+    let n = take_u32(buf, &mut pos)? as usize;
+    // Every entry takes at least its flavor word
+    if n > (buf.len() - pos) / 4 {
+        return Err(SecinfoError::Truncated(0));
+    }
+    let mut out = Vec::with_capacity(n);
+    for _ in 0..n {
+        let flavor = take_u32(buf, &mut pos)?;
+        if flavor != auth_flavor::RPCSEC_GSS {
+            out.push(Secinfo::Flavor(flavor));
+            continue;
+        }
+        let len = take_u32(buf, &mut pos)? as usize;
+        let oid = take(buf, &mut pos, len)?.to_vec();
+        take(buf, &mut pos, (4 - len % 4) % 4)?;
+        let qop = take_u32(buf, &mut pos)?;
+        let service = take_u32(buf, &mut pos)?;
+        out.push(Secinfo::Gss { oid, qop, service });
+    }
+    Ok((out, pos))
+}
+
+/// The flavors in the reply to a [`query_cases`] compound, framed from
+/// PUTROOTFH; `None` if the walk or the query failed
+pub fn decode_query(results: &[u8]) -> Option<Vec<Secinfo>> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    for _ in 0..dec.get_u32().ok()? {
+        let opcode = dec.get_u32().ok()?;
+        if dec.get_u32().ok()? != status::NFS4_OK {
+            return None;
+        }
+        match opcode {
+            op::SEQUENCE => {
+                dec.get_opaque_fixed(36).ok()?;
+            }
+            op::PUTROOTFH | op::LOOKUP => {}
+            op::SECINFO | op::SECINFO_NO_NAME => {
+                return decode_secinfo(dec.rest()).ok().map(|(flavors, _)| flavors);
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Encode SECINFO4args
+pub fn secinfo(name: &[u8]) -> Op {
+    Op::new(op::SECINFO, |enc| enc.put_opaque(name))
+}
+
+/// Encode SECINFO_NO_NAME4args
+pub fn secinfo_no_name(style: u32) -> Op {
+    Op::new(op::SECINFO_NO_NAME, |enc| enc.put_u32(style))
+}
+
+fn walk(export: &[Vec<u8>]) -> Vec<Op> {
+    export.iter().map(|c| lookup(c)).collect()
+}
+
+/// Cases asking for the flavors of `export`: SECINFO on its last
+/// component from the parent, and SECINFO_NO_NAME on the export itself
+pub fn query_cases(export: &Pathname) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    if let Some((last, parent)) = export.split_last() {
+        let mut ops = walk(parent);
+        ops.push(secinfo(last));
+        cases.push(FuzzCase::new("secinfo", ops));
+    }
+    let mut ops = walk(export);
+    ops.push(secinfo_no_name(style::CURRENT_FH));
+    cases.push(FuzzCase::new("secinfo_no_name", ops));
+    cases
+}
+
+/// Whether the advertised flavors put `export` behind krb5, with AUTH_SYS
+/// and AUTH_NONE not allowed
+pub fn requires_krb5(advertised: &[Secinfo]) -> bool {
+    advertised.iter().any(Secinfo::is_krb5)
+        && !advertised
+            .iter()
+            .any(|s| matches!(s.flavor(), auth_flavor::AUTH_SYS | auth_flavor::AUTH_NONE))
+}
+
+/// The flavors to retry with, AUTH_SYS as `identity` and AUTH_NONE
+pub fn downgrade_credentials(identity: &Identity) -> Vec<(u32, Vec<u8>)> {
+    vec![
+        (auth_flavor::AUTH_SYS, identity.credential()),
+        (auth_flavor::AUTH_NONE, auth_none()),
+    ]
+}
+
+/// One case per operation on `export`; the status of the last op tells
+/// whether the policy held
+pub fn probe_cases(export: &Pathname) -> Vec<FuzzCase> {
+    let mask = bitmap_from_bits(&[attr::TYPE, attr::MODE, attr::OWNER, attr::SIZE]);
+    let anon = Stateid::ANONYMOUS;
+    let probes: Vec<(&str, Vec<Op>)> = vec![
+        ("getfh", vec![getfh()]),
+        ("getattr", vec![getattr(&mask)]),
+        ("readdir", vec![readdir(0, &[0; 8], 4096, 8192, &mask)]),
+        ("lookup_dot", vec![lookup(b".")]),
+        ("access", vec![Op::new(op::ACCESS, |enc| enc.put_u32(0x3f))]),
+        ("readlink", vec![Op::new(op::READLINK, |_| {})]),
+        ("read", vec![read(&anon, 0, 4096)]),
+        ("write", vec![write(&anon, 0, 0, b"downgrade")]),
+        (
+            "setattr_mode",
+            vec![setattr(
+                &anon,
+                &Fattr {
+                    mask: bitmap_from_bits(&[attr::MODE]),
+                    vals: 0o777u32.to_be_bytes().to_vec(),
+                },
+            )],
+        ),
+        (
+            "create_dir",
+            vec![Op::new(op::CREATE, |enc| {
+                // NF4DIR, name, empty fattr4
+                enc.put_u32(2);
+                enc.put_opaque(b"downgrade");
+                enc.put_u32(0);
+                enc.put_u32(0);
+            })],
+        ),
+        ("remove", vec![remove(b"downgrade")]),
+        ("secinfo_no_name", vec![secinfo_no_name(style::PARENT)]),
+    ];
+    probes
+        .into_iter()
+        .map(|(name, tail)| {
+            let mut ops = walk(export);
+            ops.extend(tail);
+            FuzzCase::new(format!("downgrade_{}", name), ops)
+        })
+        .collect()
+}
+
+/// An operation that succeeded under a flavor the export does not list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyBypass {
+    pub export: Pathname,
+    pub case: String,
+    pub flavor: u32,
+}
+
+impl fmt::Display for PolicyBypass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path: Vec<String> = self
+            .export
+            .iter()
+            .map(|c| String::from_utf8_lossy(c).into_owned())
+            .collect();
+        let flavor = match self.flavor {
+            auth_flavor::AUTH_NONE => "AUTH_NONE".to_string(),
+            auth_flavor::AUTH_SYS => "AUTH_SYS".to_string(),
+            other => format!("flavor {}", other),
+        };
+        write!(
+            f,
+            "/{}: {} succeeded with {} on a krb5-only export",
+            path.join("/"),
+            self.case,
+            flavor
+        )
+    }
+}
+
+/// Judge one probe: `status` is that of the case's last operation
+pub fn check_probe(
+    advertised: &[Secinfo],
+    export: &Pathname,
+    case: &str,
+    flavor: u32,
+    last_status: u32,
+) -> Option<PolicyBypass> {
+    let listed = advertised.iter().any(|s| s.flavor() == flavor);
+    (requires_krb5(advertised) && !listed && last_status == status::NFS4_OK).then(|| PolicyBypass {
+        export: export.clone(),
+        case: case.to_string(),
+        flavor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn krb5(service: u32) -> Secinfo {
+        Secinfo::Gss {
+            oid: KRB5_OID.to_vec(),
+            qop: 0,
+            service,
+        }
+    }
+
+    #[test]
+    fn test_decode_secinfo() {
+        let entries = vec![
+            krb5(gss_service::PRIVACY),
+            krb5(gss_service::NONE),
+            Secinfo::Flavor(auth_flavor::AUTH_SYS),
+        ];
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(entries.len() as u32);
+        for e in &entries {
+            e.encode(&mut enc);
+        }
+        let bytes = enc.as_bytes();
+        assert_eq!(decode_secinfo(bytes), Ok((entries, bytes.len())));
+        assert!(decode_secinfo(&bytes[..bytes.len() - 2]).is_err());
+        assert_eq!(
+            decode_secinfo(&[0xff, 0xff, 0xff, 0xff]),
+            Err(SecinfoError::Truncated(0))
+        );
+
+        let mut reply = XdrEncoder::new();
+        for word in [status::NFS4_OK, 0, 2, op::PUTROOTFH, 0, op::SECINFO, 0] {
+            reply.put_u32(word);
+        }
+        let reply = [reply.as_bytes(), bytes].concat();
+        let flavors = decode_query(&reply).unwrap();
+        assert_eq!(flavors.len(), 3);
+        assert!(requires_krb5(&flavors[..2]) && !requires_krb5(&flavors));
+        assert_eq!(decode_query(&reply[..12]), None);
+    }
+
+    #[test]
+    fn test_bypass_only_on_krb5_only_exports() {
+        let export: Pathname = vec![b"secure".to_vec()];
+        let krb5_only = [krb5(gss_service::INTEGRITY)];
+        let mixed = [
+            krb5(gss_service::INTEGRITY),
+            Secinfo::Flavor(auth_flavor::AUTH_SYS),
+        ];
+        assert!(requires_krb5(&krb5_only) && !requires_krb5(&mixed));
+
+        let hit = check_probe(
+            &krb5_only,
+            &export,
+            "downgrade_read",
+            auth_flavor::AUTH_SYS,
+            status::NFS4_OK,
+        )
+        .unwrap();
+        assert_eq!(
+            hit.to_string(),
+            "/secure: downgrade_read succeeded with AUTH_SYS on a krb5-only export"
+        );
+        for (advertised, st) in [
+            (&krb5_only[..], status::NFS4ERR_WRONGSEC),
+            (&mixed[..], status::NFS4_OK),
+        ] {
+            assert!(check_probe(advertised, &export, "c", auth_flavor::AUTH_SYS, st).is_none());
+        }
+
+        let cases = probe_cases(&export);
+        assert!(cases.iter().all(|c| c.ops[0].opcode == op::LOOKUP));
+        assert_eq!(query_cases(&export)[0].ops.len(), 1);
+    }
+}
@@ -0,0 +1,640 @@
+//! NFSv4.1 sessions (RFC 8881 §2.10, §18.46)
+//!
+//! Every v4.1 COMPOUND after session creation starts with SEQUENCE, which
+//! names a slot in the session's slot table and that slot's sequence id.
+//! The server's reply cache is keyed on (slot, sequenceid).
+//!
+//! [`establish`] sets a session up with EXCHANGE_ID, CREATE_SESSION and
+//! a RECLAIM_COMPLETE, and hands back its [`SlotTable`]. Generated v4.1
+//! compounds carry a placeholder SEQUENCE whose fields are relative to
+//! that table: an all-zero session id stands for the session's own, and
+//! the sequence id is an offset from the one the slot expects next, so
+//! zero is the valid next request and `u32::MAX` a retry of the last.
+//! [`SlotTable::stamp`] resolves them just before a compound is sent.
+//! Mutating the placeholder's slot id, offset and highest_slotid then
+//! probes the server's replay handling against a live session.
+
+use super::reclaim::{
+    create_session, exchange_id, reclaim_complete, setclientid, setclientid_confirm, ClientOwner,
+};
+use super::replycache::SlotState;
+use super::{minor_version, op, status, CompoundBuilder, Op, SessionId};
+use crate::auth::Identity;
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::gss::GssError;
+use crate::rpc::{RpcError, RpcReply};
+use crate::xdr::XdrDecoder;
+use bytes::BytesMut;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Bytes of SEQUENCE4args
+pub const SEQUENCE_LEN: usize = 32;
+
+/// EXCHGID4_FLAG_USE_NON_PNFS
+pub const USE_NON_PNFS: u32 = 0x0001_0000;
+
+/// CREATE_SESSION4_FLAG_CONN_BACK_CHAN: the creating connection carries
+/// the backchannel too
+pub const CONN_BACK_CHAN: u32 = 0x2;
+
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error(transparent)]
+    Gss(#[from] GssError),
+    #[error("{op} failed with status {status}")]
+    Status { op: &'static str, status: u32 },
+    #[error("{0} reply does not decode")]
+    Decode(&'static str),
+}
+
+/// SEQUENCE4args
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sequence {
+    pub sessionid: SessionId,
+    pub sequenceid: u32,
+    pub slotid: u32,
+    pub highest_slotid: u32,
+    pub cachethis: bool,
+}
+
+impl Sequence {
+    pub fn op(&self) -> Op {
+        Op::new(op::SEQUENCE, |enc| {
+            enc.put_opaque_fixed(&self.sessionid);
+            enc.put_u32(self.sequenceid);
+            enc.put_u32(self.slotid);
+            enc.put_u32(self.highest_slotid);
+            enc.put_bool(self.cachethis);
+        })
+    }
+
+    /// The SEQUENCE generated compounds carry, relative to the slot table
+    /// (see the module documentation): this session, slot 0, the next
+    /// sequence id on it
+    pub fn placeholder() -> Self {
+        Self {
+            sessionid: [0; 16],
+            sequenceid: 0,
+            slotid: 0,
+            highest_slotid: 0,
+            cachethis: false,
+        }
+    }
+}
+
+/// Encode DESTROY_SESSION4args
+pub fn destroy_session(sessionid: &SessionId) -> Op {
+    Op::new(op::DESTROY_SESSION, |enc| enc.put_opaque_fixed(sessionid))
+}
+
+/// Offset of the ops of COMPOUND4args, its minor version and op count
+fn compound_header(args: &[u8]) -> Option<(usize, u32, u32)> {
+    let mut dec = XdrDecoder::new(args);
+    dec.get_opaque().ok()?;
+    let minor = dec.get_u32().ok()?;
+    let count = dec.get_u32().ok()?;
+    Some((args.len() - dec.rest().len(), minor, count))
+}
+
+/// Offset of the SEQUENCE4args that open v4.1+ COMPOUND4args `args`
+pub fn sequence_at(args: &[u8]) -> Option<usize> {
+    let (ops, minor, count) = compound_header(args)?;
+    let opcode = args.get(ops..ops + 4)?;
+    let at = ops + 4;
+    (minor >= minor_version::V4_1
+        && count > 0
+        && opcode == op::SEQUENCE.to_be_bytes()
+        && args.len() >= at + SEQUENCE_LEN)
+        .then_some(at)
+}
+
+/// v4.1+ COMPOUND4args `args` with [`Sequence::placeholder`] in front,
+/// if they do not start with SEQUENCE already
+pub fn with_placeholder(args: &[u8]) -> Option<Vec<u8>> {
+    let (ops, minor, count) = compound_header(args)?;
+    // Anything else is not COMPOUND4args from this tree
+    let plausible = (minor_version::V4_1..=minor_version::V4_2).contains(&minor)
+        && (count as usize).saturating_mul(4) <= args.len() - ops;
+    if !plausible || sequence_at(args).is_some() {
+        return None;
+    }
+    let mut out = args[..ops - 4].to_vec();
+    out.extend_from_slice(&count.wrapping_add(1).to_be_bytes());
+    let placeholder = Sequence::placeholder().op();
+    out.extend_from_slice(&placeholder.opcode.to_be_bytes());
+    out.extend_from_slice(&placeholder.args);
+    out.extend_from_slice(&args[ops..]);
+    Some(out)
+}
+
+fn u32_at(buf: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(buf[at..at + 4].try_into().unwrap())
+}
+
+/// The most slots a table keeps, whatever ca_maxrequests the server
+/// grants; more only means more slots that are never used
+pub const MAX_SLOTS: u32 = 1024;
+
+/// A session's slots and the sequence id each expects next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlotTable {
+    pub clientid: u64,
+    pub sessionid: SessionId,
+    /// Next sequence id of each slot the server granted
+    pub seqids: Vec<u32>,
+    /// The server's last target_highest_slotid
+    pub target_highest_slotid: u32,
+}
+
+impl SlotTable {
+    /// `slots` fresh slots, up to [`MAX_SLOTS`], whose first request
+    /// has sequence id 1
+    pub fn new(clientid: u64, sessionid: SessionId, slots: u32) -> Self {
+        let slots = slots.clamp(1, MAX_SLOTS);
+        Self {
+            clientid,
+            sessionid,
+            seqids: vec![1; slots as usize],
+            target_highest_slotid: slots - 1,
+        }
+    }
+
+    pub fn highest_slotid(&self) -> u32 {
+        self.seqids.len() as u32 - 1
+    }
+
+    /// The valid next SEQUENCE on `slotid`, if the session has it
+    pub fn sequence(&self, slotid: u32, cachethis: bool) -> Option<Sequence> {
+        Some(Sequence {
+            sessionid: self.sessionid,
+            sequenceid: *self.seqids.get(slotid as usize)?,
+            slotid,
+            highest_slotid: self.highest_slotid(),
+            cachethis,
+        })
+    }
+
+    /// `slotid` as a [`replycache`](super::replycache) plan runs on it
+    pub fn slot(&self, slotid: u32) -> Option<SlotState> {
+        Some(SlotState {
+            sessionid: self.sessionid,
+            slotid,
+            highest_slotid: self.highest_slotid(),
+            next_seqid: *self.seqids.get(slotid as usize)?,
+        })
+    }
+
+    /// Resolve the SEQUENCE at the head of COMPOUND4args `args`, putting
+    /// in the placeholder if there is none: an all-zero session id
+    /// becomes this session's, and on a slot the session has, the
+    /// sequence id becomes an offset from the slot's next. Other fields
+    /// go as they are. Compounds below v4.1 are left alone.
+    pub fn stamp(&self, args: &[u8]) -> Option<Vec<u8>> {
+        let mut out = with_placeholder(args).unwrap_or_else(|| args.to_vec());
+        let at = sequence_at(&out)?;
+        if out[at..at + 16].iter().all(|&b| b == 0) {
+            out[at..at + 16].copy_from_slice(&self.sessionid);
+        }
+        let slotid = u32_at(&out, at + 20);
+        if let Some(&next) = self.seqids.get(slotid as usize) {
+            let offset = u32_at(&out, at + 16);
+            out[at + 16..at + 20].copy_from_slice(&next.wrapping_add(offset).to_be_bytes());
+        }
+        Some(out)
+    }
+
+    /// Take in the results of a compound sent on the session: a SEQUENCE
+    /// that succeeded for a slot's next sequence id moves the slot on.
+    /// The SEQUENCE status, if the results start with one.
+    pub fn record(&mut self, results: &[u8]) -> Option<u32> {
+        let mut dec = XdrDecoder::new(results);
+        dec.get_u32().ok()?;
+        dec.get_opaque().ok()?;
+        if dec.get_u32().ok()? == 0 || dec.get_u32().ok()? != op::SEQUENCE {
+            return None;
+        }
+        let stat = dec.get_u32().ok()?;
+        if stat != status::NFS4_OK {
+            return Some(stat);
+        }
+        let sessionid = dec.get_opaque_fixed(16).ok()?;
+        let (seqid, slotid) = (dec.get_u32().ok()?, dec.get_u32().ok()?);
+        dec.get_u32().ok()?;
+        let target = dec.get_u32().ok()?;
+        if sessionid == self.sessionid {
+            self.target_highest_slotid = target;
+            if let Some(next) = self.seqids.get_mut(slotid as usize) {
+                if *next == seqid {
+                    *next = next.wrapping_add(1);
+                }
+            }
+        }
+        Some(stat)
+    }
+
+    /// DESTROY_SESSION of the session, in a compound of its own
+    pub fn destroy(&self, identity: &Identity) -> BytesMut {
+        CompoundBuilder::new(minor_version::V4_1)
+            .op(destroy_session(&self.sessionid))
+            .message(identity)
+    }
+}
+
+/// EXCHANGE_ID4resok as far as a session needs it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExchangeId {
+    pub clientid: u64,
+    pub sequenceid: u32,
+    pub flags: u32,
+}
+
+/// CREATE_SESSION4resok as far as a slot table needs it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreatedSession {
+    pub sessionid: SessionId,
+    pub sequenceid: u32,
+    /// ca_maxrequests of the fore channel: the number of slots
+    pub max_requests: u32,
+}
+
+/// The status and decoder at the results of the first op of a compound,
+/// if it is `opcode`
+pub(super) fn first_op(results: &[u8], opcode: u32) -> Option<(u32, XdrDecoder<'_>)> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    if dec.get_u32().ok()? == 0 || dec.get_u32().ok()? != opcode {
+        return None;
+    }
+    Some((dec.get_u32().ok()?, dec))
+}
+
+pub fn decode_exchange_id(results: &[u8]) -> Result<ExchangeId, SessionError> {
+    let (stat, mut dec) =
+        first_op(results, op::EXCHANGE_ID).ok_or(SessionError::Decode("EXCHANGE_ID"))?;
+    if stat != status::NFS4_OK {
+        return Err(SessionError::Status {
+            op: "EXCHANGE_ID",
+            status: stat,
+        });
+    }
+    let mut fields = || -> Option<ExchangeId> {
+        Some(ExchangeId {
+            clientid: dec.get_u64().ok()?,
+            sequenceid: dec.get_u32().ok()?,
+            flags: dec.get_u32().ok()?,
+        })
+    };
+    fields().ok_or(SessionError::Decode("EXCHANGE_ID"))
+}
+
+pub fn decode_create_session(results: &[u8]) -> Result<CreatedSession, SessionError> {
+    let (stat, mut dec) =
+        first_op(results, op::CREATE_SESSION).ok_or(SessionError::Decode("CREATE_SESSION"))?;
+    if stat != status::NFS4_OK {
+        return Err(SessionError::Status {
+            op: "CREATE_SESSION",
+            status: stat,
+        });
+    }
+    let mut fields = || -> Option<CreatedSession> {
+        let sessionid = dec.get_opaque_fixed(16).ok()?.try_into().ok()?;
+        let sequenceid = dec.get_u32().ok()?;
+        // csr_flags, then the fore channel's headerpadsize, request and
+        // response sizes, cached response size and maxoperations
+        for _ in 0..6 {
+            dec.get_u32().ok()?;
+        }
+        Some(CreatedSession {
+            sessionid,
+            sequenceid,
+            max_requests: dec.get_u32().ok()?,
+        })
+    };
+    fields().ok_or(SessionError::Decode("CREATE_SESSION"))
+}
+
+/// Send `ops` as a v4.1 compound and return its results
+pub(super) async fn compound<S>(
+    conn: &mut NfsConnection<S>,
+    ops: impl IntoIterator<Item = Op>,
+    identity: &Identity,
+) -> Result<Vec<u8>, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let msg = CompoundBuilder::new(minor_version::V4_1)
+        .with_tag(b"session")
+        .ops(ops)
+        .message(identity);
+    let reply = conn.call(&msg).await?;
+    Ok(RpcReply::parse(&reply)?.into_results()?.to_vec())
+}
+
+/// Send `op` as a v4.0 compound and return its results
+async fn compound_v40<S>(
+    conn: &mut NfsConnection<S>,
+    op: Op,
+    identity: &Identity,
+) -> Result<Vec<u8>, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let msg = CompoundBuilder::new(minor_version::V4_0)
+        .with_tag(b"session")
+        .op(op)
+        .message(identity);
+    let reply = conn.call(&msg).await?;
+    Ok(RpcReply::parse(&reply)?.into_results()?.to_vec())
+}
+
+/// Set up a session for `owner` on `conn`: EXCHANGE_ID, CREATE_SESSION,
+/// then a SEQUENCE with RECLAIM_COMPLETE on slot 0 so the server lets the
+/// client create state
+pub async fn establish<S>(
+    conn: &mut NfsConnection<S>,
+    owner: &ClientOwner,
+    identity: &Identity,
+) -> Result<SlotTable, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let exchange = exchange_id(owner, USE_NON_PNFS);
+    Ok(establish_with(conn, exchange, 0, identity).await?.0)
+}
+
+/// [`establish`] with `exchange` as the EXCHANGE_ID, for state
+/// protection other than SP4_NONE, and `flags` as the CREATE_SESSION
+/// csa_flags; the session and the EXCHANGE_ID results
+pub async fn establish_with<S>(
+    conn: &mut NfsConnection<S>,
+    exchange: Op,
+    flags: u32,
+    identity: &Identity,
+) -> Result<(SlotTable, Vec<u8>), SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut table, exchanged) = create(conn, exchange, flags, identity).await?;
+    sequenced(conn, &mut table, [reclaim_complete(false)], identity).await?;
+    Ok((table, exchanged))
+}
+
+/// EXCHANGE_ID and CREATE_SESSION with csa_flags `flags` alone, for a
+/// rebooted client that has state to reclaim before its
+/// RECLAIM_COMPLETE; the session and the EXCHANGE_ID results
+pub async fn create<S>(
+    conn: &mut NfsConnection<S>,
+    exchange: Op,
+    flags: u32,
+    identity: &Identity,
+) -> Result<(SlotTable, Vec<u8>), SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let exchanged = compound(conn, [exchange], identity).await?;
+    let client = decode_exchange_id(&exchanged)?;
+    let create = create_session(client.clientid, client.sequenceid, flags);
+    let results = compound(conn, [create], identity).await?;
+    let created = decode_create_session(&results)?;
+    let table = SlotTable::new(client.clientid, created.sessionid, created.max_requests);
+    Ok((table, exchanged))
+}
+
+/// Send `ops` on `table`'s session behind a SEQUENCE on slot 0 and
+/// return the results, whatever the ops did; an error if the SEQUENCE
+/// failed
+pub async fn sequenced<S>(
+    conn: &mut NfsConnection<S>,
+    table: &mut SlotTable,
+    ops: impl IntoIterator<Item = Op>,
+    identity: &Identity,
+) -> Result<Vec<u8>, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // SlotTable::new gives every table slot 0
+    let sequence = table
+        .sequence(0, false)
+        .ok_or(SessionError::Decode("CREATE_SESSION"))?;
+    let results = compound(conn, std::iter::once(sequence.op()).chain(ops), identity).await?;
+    match table.record(&results) {
+        Some(status::NFS4_OK) => Ok(results),
+        Some(status) => Err(SessionError::Status {
+            op: "SEQUENCE",
+            status,
+        }),
+        None => Err(SessionError::Decode("SEQUENCE")),
+    }
+}
+
+/// Set up a v4.0 client for `owner` on `conn`, SETCLIENTID then
+/// SETCLIENTID_CONFIRM, for v4.0 OPENs; the confirmed client id
+pub async fn confirm_client<S>(
+    conn: &mut NfsConnection<S>,
+    owner: &ClientOwner,
+    identity: &Identity,
+) -> Result<u64, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let results = compound_v40(conn, setclientid(owner), identity).await?;
+    let (stat, mut dec) =
+        first_op(&results, op::SETCLIENTID).ok_or(SessionError::Decode("SETCLIENTID"))?;
+    if stat != status::NFS4_OK {
+        return Err(SessionError::Status {
+            op: "SETCLIENTID",
+            status: stat,
+        });
+    }
+    let mut fields = || -> Option<(u64, [u8; 8])> {
+        let clientid = dec.get_u64().ok()?;
+        Some((clientid, dec.get_opaque_fixed(8).ok()?.try_into().ok()?))
+    };
+    let (clientid, verifier) = fields().ok_or(SessionError::Decode("SETCLIENTID"))?;
+    let confirm = setclientid_confirm(clientid, &verifier);
+    let results = compound_v40(conn, confirm, identity).await?;
+    match first_op(&results, op::SETCLIENTID_CONFIRM) {
+        Some((status::NFS4_OK, _)) => Ok(clientid),
+        Some((status, _)) => Err(SessionError::Status {
+            op: "SETCLIENTID_CONFIRM",
+            status,
+        }),
+        None => Err(SessionError::Decode("SETCLIENTID_CONFIRM")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Timeouts;
+    use crate::hang::read_record;
+    use crate::xdr::XdrEncoder;
+    use tokio::io::AsyncWriteExt;
+
+    /// COMPOUND4res of one successful op with `body`, then `more`
+    fn results(opcode: u32, body: impl FnOnce(&mut XdrEncoder), more: &[u32]) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(status::NFS4_OK);
+        enc.put_opaque(b"session");
+        enc.put_u32(1 + more.len() as u32 / 2);
+        enc.put_u32(opcode);
+        enc.put_u32(status::NFS4_OK);
+        body(&mut enc);
+        for &w in more {
+            enc.put_u32(w);
+        }
+        enc.as_bytes().to_vec()
+    }
+
+    fn sequence_res(sessionid: &SessionId, seqid: u32, slotid: u32, more: &[u32]) -> Vec<u8> {
+        results(
+            op::SEQUENCE,
+            |enc| {
+                enc.put_opaque_fixed(sessionid);
+                for w in [seqid, slotid, 3, 3, 0] {
+                    enc.put_u32(w);
+                }
+            },
+            more,
+        )
+    }
+
+    #[test]
+    fn test_sequence_encoding() {
+        let seq = Sequence {
+            sessionid: [0xab; 16],
+            sequenceid: 1,
+            slotid: 2,
+            highest_slotid: 3,
+            cachethis: true,
+        };
+        let op = seq.op();
+        assert_eq!(op.args.len(), 32);
+        assert_eq!(
+            &op.args[16..],
+            &[0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_placeholder_and_stamp() {
+        let compound = CompoundBuilder::new(minor_version::V4_1)
+            .with_tag(b"t")
+            .putrootfh()
+            .build();
+        let args = with_placeholder(&compound).unwrap();
+        // tag, minor version, count, then SEQUENCE ahead of PUTROOTFH
+        assert_eq!(args[12..16], 2u32.to_be_bytes());
+        assert_eq!(sequence_at(&args), Some(20));
+        assert!(with_placeholder(&args).is_none());
+        let old = CompoundBuilder::new(minor_version::V4_0)
+            .putrootfh()
+            .build();
+        assert!(with_placeholder(&old).is_none());
+        assert!(sequence_at(&old).is_none());
+
+        let huge = SlotTable::new(7, [9; 16], u32::MAX);
+        assert_eq!(huge.seqids.len(), MAX_SLOTS as usize);
+        assert_eq!(huge.highest_slotid(), MAX_SLOTS - 1);
+        assert_eq!(huge.target_highest_slotid, MAX_SLOTS - 1);
+
+        let mut table = SlotTable::new(7, [9; 16], 4);
+        table.seqids[0] = 10;
+        let stamped = table.stamp(&compound).unwrap();
+        assert_eq!(stamped[20..36], [9; 16]);
+        assert_eq!(stamped[36..40], 10u32.to_be_bytes());
+        // A retry of the last request, on a slot the table has
+        let mut retry = args.clone();
+        retry[36..40].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(table.stamp(&retry).unwrap()[36..40], 9u32.to_be_bytes());
+        // Slots past the table keep their sequence id as it is
+        retry[40..44].copy_from_slice(&1024u32.to_be_bytes());
+        assert_eq!(table.stamp(&retry).unwrap()[36..40], u32::MAX.to_be_bytes());
+        assert!(table.stamp(&old).is_none());
+
+        // Only a success for the slot's next sequence id moves it on
+        assert_eq!(table.record(&sequence_res(&[9; 16], 9, 0, &[])), Some(0));
+        assert_eq!(table.seqids[0], 10);
+        assert_eq!(table.record(&sequence_res(&[9; 16], 10, 0, &[])), Some(0));
+        assert_eq!(table.seqids[0], 11);
+        assert_eq!(table.record(&sequence_res(&[1; 16], 11, 0, &[])), Some(0));
+        assert_eq!(table.seqids[0], 11);
+        let mut enc = XdrEncoder::new();
+        for w in [status::NFS4ERR_SEQ_MISORDERED, 0, 1, op::SEQUENCE] {
+            enc.put_u32(w);
+        }
+        enc.put_u32(status::NFS4ERR_SEQ_MISORDERED);
+        assert_eq!(
+            table.record(enc.as_bytes()),
+            Some(status::NFS4ERR_SEQ_MISORDERED)
+        );
+        assert_eq!(table.slot(2).unwrap().next_seqid, 1);
+        assert!(table.sequence(4, false).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_establish() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let mut conn = NfsConnection::new(client, Timeouts::default());
+        let owner = ClientOwner {
+            verifier: [1; 8],
+            ownerid: b"test".to_vec(),
+        };
+        let identity = Identity::new(0, 0);
+        let serve = async {
+            let replies = [
+                results(
+                    op::EXCHANGE_ID,
+                    |enc| {
+                        enc.put_u64(0xc1);
+                        enc.put_u32(5);
+                        enc.put_u32(USE_NON_PNFS);
+                    },
+                    &[],
+                ),
+                results(
+                    op::CREATE_SESSION,
+                    |enc| {
+                        enc.put_opaque_fixed(&[0x5e; 16]);
+                        // sequenceid, flags, then the fore channel
+                        for w in [5, 0, 0, 1 << 20, 1 << 20, 65536, 16, 4] {
+                            enc.put_u32(w);
+                        }
+                    },
+                    &[],
+                ),
+                sequence_res(&[0x5e; 16], 1, 0, &[op::RECLAIM_COMPLETE, 0]),
+            ];
+            let mut calls = Vec::new();
+            for results in replies {
+                let call = read_record(&mut server).await.unwrap();
+                let mut reply = call[..4].to_vec();
+                reply.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+                reply.extend_from_slice(&[0; 8]);
+                reply.extend_from_slice(&results);
+                let mut record = (0x8000_0000 | reply.len() as u32).to_be_bytes().to_vec();
+                record.extend_from_slice(&reply);
+                server.write_all(&record).await.unwrap();
+                calls.push(call);
+            }
+            calls
+        };
+        let (calls, table) = tokio::join!(serve, establish(&mut conn, &owner, &identity));
+        let table = table.unwrap();
+        assert_eq!((table.clientid, table.sessionid), (0xc1, [0x5e; 16]));
+        assert_eq!(table.seqids, [2, 1, 1, 1]);
+        // CREATE_SESSION4args, 84 bytes at the end of the call, name the
+        // client and its EXCHANGE_ID sequence id
+        let create = calls[1].len() - 84;
+        assert_eq!(
+            calls[1][create..create + 12],
+            [0, 0, 0, 0, 0, 0, 0, 0xc1, 0, 0, 0, 5]
+        );
+    }
+}
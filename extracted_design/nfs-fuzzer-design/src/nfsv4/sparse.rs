@@ -0,0 +1,353 @@
+//! Sparse-file operations (RFC 7862 §15.1, §15.4, §15.10, §15.11)
+//!
+//! v4.2 exposes the server's extent map through SEEK and READ_PLUS and
+//! lets clients change it with ALLOCATE and DEALLOCATE. Offsets are u64
+//! on the wire but usually end up as a signed loff_t in the server, so
+//! the interesting values sit around extent edges and the i64/u64 limits.
+
+use super::{op, FuzzCase, Op, Stateid};
+use crate::xdr::XdrDecoder;
+
+/// data_content4 discriminants
+pub mod content {
+    pub const DATA: u32 = 0;
+    pub const HOLE: u32 = 1;
+}
+
+/// Encode SEEK4args
+pub fn seek(stateid: &Stateid, offset: u64, what: u32) -> Op {
+    Op::new(op::SEEK, |enc| {
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u32(what);
+    })
+}
+
+/// Encode ALLOCATE4args
+pub fn allocate(stateid: &Stateid, offset: u64, length: u64) -> Op {
+    Op::new(op::ALLOCATE, |enc| {
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+    })
+}
+
+/// Encode DEALLOCATE4args
+pub fn deallocate(stateid: &Stateid, offset: u64, length: u64) -> Op {
+    Op::new(op::DEALLOCATE, |enc| {
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u64(length);
+    })
+}
+
+/// Encode READ_PLUS4args
+pub fn read_plus(stateid: &Stateid, offset: u64, count: u32) -> Op {
+    Op::new(op::READ_PLUS, |enc| {
+        stateid.encode(enc);
+        enc.put_u64(offset);
+        enc.put_u32(count);
+    })
+}
+
+/// Offsets just inside, on and just outside each extent edge, plus the
+/// signed and unsigned limits
+pub fn boundary_offsets(extents: &[(u64, u64)]) -> Vec<u64> {
+    let mut out = vec![
+        0,
+        1,
+        i64::MAX as u64 - 1,
+        i64::MAX as u64,
+        i64::MAX as u64 + 1,
+        u64::MAX - 1,
+        u64::MAX,
+    ];
+    for &(offset, length) in extents {
+        let end = offset.saturating_add(length);
+        for edge in [offset, end] {
+            out.extend([edge.wrapping_sub(1), edge, edge.wrapping_add(1)]);
+        }
+    }
+    out.sort_unstable();
+    out.dedup();
+    out
+}
+
+/// SEEK for data and holes at every boundary offset, plus undefined
+/// content types
+pub fn seek_cases(stateid: &Stateid, extents: &[(u64, u64)]) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for offset in boundary_offsets(extents) {
+        for (label, what) in [("data", content::DATA), ("hole", content::HOLE)] {
+            cases.push(FuzzCase::new(
+                format!("seek_{}_{:#x}", label, offset),
+                vec![seek(stateid, offset, what)],
+            ));
+        }
+    }
+    for what in [2, 0x7fff_ffff, u32::MAX] {
+        cases.push(FuzzCase::new(
+            format!("seek_undefined_what_{:#x}", what),
+            vec![seek(stateid, 0, what)],
+        ));
+    }
+    cases
+}
+
+/// (label, offset, length) ranges for ALLOCATE and DEALLOCATE
+fn hostile_ranges(extents: &[(u64, u64)]) -> Vec<(String, u64, u64)> {
+    let mut ranges: Vec<(String, u64, u64)> = vec![
+        ("zero_at_0".into(), 0, 0),
+        ("zero_at_max".into(), u64::MAX, 0),
+        ("one_at_max".into(), u64::MAX, 1),
+        ("wrap".into(), u64::MAX - 1, 2),
+        ("whole_u64".into(), 0, u64::MAX),
+        ("to_i64_max".into(), 0, i64::MAX as u64),
+        ("past_i64_max".into(), i64::MAX as u64, 1),
+        ("negative_offset".into(), i64::MAX as u64 + 1, 4096),
+        ("end_exactly_u64_max".into(), 1, u64::MAX - 1),
+    ];
+    for (i, &(offset, length)) in extents.iter().enumerate() {
+        let end = offset.saturating_add(length);
+        ranges.push((format!("extent{}_zero_at_start", i), offset, 0));
+        ranges.push((format!("extent{}_zero_at_end", i), end, 0));
+        ranges.push((
+            format!("extent{}_straddle_start", i),
+            offset.saturating_sub(1),
+            2,
+        ));
+        ranges.push((
+            format!("extent{}_straddle_end", i),
+            end.saturating_sub(1),
+            2,
+        ));
+        ranges.push((format!("extent{}_to_u64_max", i), offset, u64::MAX - offset));
+    }
+    ranges
+}
+
+/// ALLOCATE and DEALLOCATE over zero-length, wrapping and edge ranges
+pub fn allocate_cases(stateid: &Stateid, extents: &[(u64, u64)]) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for (label, offset, length) in hostile_ranges(extents) {
+        cases.push(FuzzCase::new(
+            format!("allocate_{}", label),
+            vec![allocate(stateid, offset, length)],
+        ));
+        cases.push(FuzzCase::new(
+            format!("deallocate_{}", label),
+            vec![deallocate(stateid, offset, length)],
+        ));
+    }
+    cases
+}
+
+/// Punch a hole into a fresh allocation and read the map back in the
+/// same compound, so the replies can be cross-checked
+pub fn hole_punch_cases(stateid: &Stateid, offset: u64, length: u64) -> Vec<FuzzCase> {
+    let mid = offset.saturating_add(length / 4);
+    let hole = length / 2;
+    let probe = |what| {
+        vec![
+            seek(stateid, offset, what),
+            seek(stateid, mid, what),
+            seek(stateid, mid.saturating_add(hole), what),
+            read_plus(stateid, offset, length.min(u32::MAX as u64) as u32),
+        ]
+    };
+    let mut punched = vec![
+        allocate(stateid, offset, length),
+        deallocate(stateid, mid, hole),
+    ];
+    punched.extend(probe(content::DATA));
+    punched.extend(probe(content::HOLE));
+
+    vec![
+        FuzzCase::new("hole_punch_and_probe", punched),
+        FuzzCase::new(
+            "deallocate_twice",
+            vec![
+                deallocate(stateid, mid, hole),
+                deallocate(stateid, mid, hole),
+                read_plus(stateid, mid.saturating_sub(1), 2),
+            ],
+        ),
+        FuzzCase::new(
+            "reallocate_hole",
+            vec![
+                deallocate(stateid, mid, hole),
+                allocate(stateid, mid, hole),
+                seek(stateid, mid, content::HOLE),
+            ],
+        ),
+    ]
+}
+
+/// READ_PLUS at boundary offsets with edge counts
+pub fn read_plus_cases(stateid: &Stateid, extents: &[(u64, u64)]) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for offset in boundary_offsets(extents) {
+        for count in [0, 1, 4096, u32::MAX] {
+            cases.push(FuzzCase::new(
+                format!("read_plus_{:#x}_{}", offset, count),
+                vec![read_plus(stateid, offset, count)],
+            ));
+        }
+    }
+    cases
+}
+
+/// One decoded read_plus_content4 entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadPlusSegment {
+    Data { offset: u64, len: u64 },
+    Hole { offset: u64, length: u64 },
+}
+
+impl ReadPlusSegment {
+    pub fn offset(&self) -> u64 {
+        match self {
+            ReadPlusSegment::Data { offset, .. } | ReadPlusSegment::Hole { offset, .. } => *offset,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        match self {
+            ReadPlusSegment::Data { len, .. } => *len,
+            ReadPlusSegment::Hole { length, .. } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Decode READ_PLUS4resok: whether the read reached the end of the
+/// file, then its segments; `None` if the reply does not decode
+pub fn decode_read_plus(dec: &mut XdrDecoder<'_>) -> Option<(bool, Vec<ReadPlusSegment>)> {
+    let eof = dec.get_bool().ok()?;
+    let mut segments = Vec::new();
+    for _ in 0..dec.get_u32().ok()? {
+        let what = dec.get_u32().ok()?;
+        let offset = dec.get_u64().ok()?;
+        segments.push(match what {
+            content::DATA => ReadPlusSegment::Data {
+                offset,
+                len: dec.get_opaque().ok()?.len() as u64,
+            },
+            content::HOLE => ReadPlusSegment::Hole {
+                offset,
+                length: dec.get_u64().ok()?,
+            },
+            _ => return None,
+        });
+    }
+    Some((eof, segments))
+}
+
+/// Check that a READ_PLUS reply describes the requested range coherently
+///
+/// Segments must be ordered, contiguous and non-wrapping, the first must
+/// cover the requested offset, data must stay within `count`, and holes
+/// must have a length. Holes may extend past the requested range.
+pub fn check_read_plus(
+    offset: u64,
+    count: u32,
+    segments: &[ReadPlusSegment],
+) -> Result<(), String> {
+    let mut data_bytes = 0u64;
+    let mut expected = None;
+    for (i, seg) in segments.iter().enumerate() {
+        let end = seg
+            .offset()
+            .checked_add(seg.len())
+            .ok_or_else(|| format!("segment {} wraps past u64::MAX", i))?;
+        match seg {
+            ReadPlusSegment::Hole { length: 0, .. } => {
+                return Err(format!("segment {} is a zero-length hole", i));
+            }
+            ReadPlusSegment::Data { len, .. } => data_bytes += len,
+            ReadPlusSegment::Hole { .. } => {}
+        }
+        match expected {
+            None if seg.offset() > offset || (end <= offset && !seg.is_empty()) => {
+                return Err(format!(
+                    "first segment {:#x}+{:#x} does not cover requested offset {:#x}",
+                    seg.offset(),
+                    seg.len(),
+                    offset
+                ));
+            }
+            Some(next) if seg.offset() != next => {
+                return Err(format!(
+                    "segment {} starts at {:#x}, expected {:#x}",
+                    i,
+                    seg.offset(),
+                    next
+                ));
+            }
+            _ => {}
+        }
+        if matches!(seg, ReadPlusSegment::Data { .. }) && seg.offset() < offset {
+            return Err(format!("data segment {} starts before the request", i));
+        }
+        expected = Some(end);
+    }
+    if data_bytes > count as u64 {
+        return Err(format!(
+            "{} data bytes returned for count {}",
+            data_bytes, count
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boundary_offsets_cover_extent_edges() {
+        let offsets = boundary_offsets(&[(4096, 4096)]);
+        for o in [4095, 4096, 4097, 8191, 8192, 8193, i64::MAX as u64 + 1] {
+            assert!(offsets.contains(&o), "{:#x}", o);
+        }
+        assert_eq!(seek(&Stateid::ANONYMOUS, 0, content::HOLE).args.len(), 28);
+    }
+
+    #[test]
+    fn test_check_read_plus() {
+        use ReadPlusSegment::*;
+        let good = [
+            Hole {
+                offset: 0,
+                length: 8192,
+            },
+            Data {
+                offset: 8192,
+                len: 100,
+            },
+        ];
+        assert!(check_read_plus(4096, 8192, &good).is_ok());
+
+        let gap = [
+            Data { offset: 0, len: 10 },
+            Data {
+                offset: 20,
+                len: 10,
+            },
+        ];
+        assert!(check_read_plus(0, 100, &gap).is_err());
+        let zero_hole = [Hole {
+            offset: 0,
+            length: 0,
+        }];
+        assert!(check_read_plus(0, 100, &zero_hole).is_err());
+        let too_much = [Data {
+            offset: 0,
+            len: 200,
+        }];
+        assert!(check_read_plus(0, 100, &too_much).is_err());
+    }
+}
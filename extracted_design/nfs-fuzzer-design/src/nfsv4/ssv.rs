@@ -0,0 +1,834 @@
+//! SSV state protection (RFC 8881 §2.10.8.3, §2.10.9, §18.35, §18.47)
+//!
+//! A client can ask in EXCHANGE_ID for SP4_SSV: the operations that
+//! manage its client id and sessions must then carry a digest under a
+//! secret state verifier (SSV) that only the client and server know.
+//! The client sets the SSV with SET_SSV, each new one XORed into the
+//! last, the first into all zeros. The digest of a SET_SSV is the HMAC
+//! of an ssv_mic_plain_tkn4 around its SEQUENCE arguments, the reply's
+//! around the SEQUENCE results, and the RPCSEC_GSS handles EXCHANGE_ID
+//! hands back use the SSV GSS mechanism, whose checksums are HMACs under
+//! keys derived from the SSV. Hardly any client asks for SP4_SSV, so
+//! these are some of the least exercised paths of a server.
+//!
+//! [`exchange_id`] negotiates SSV protection and [`establish`] sets a
+//! session up under it. [`set_ssv_plan`] is a run of SET_SSVs with bad
+//! digests, SEQUENCE arguments they do not cover and SSVs of the wrong
+//! length, ending in a valid one; [`SsvMech`] puts the SSV behind a
+//! [`Context`], whose [`Context::fuzz_calls`] then send protected
+//! operations with truncated and corrupted checksums. [`probe`] sends
+//! both on a live session. [`cases`] needs no session: malformed SP4_SSV
+//! parameters and SET_SSVs whose digests cannot be right.
+//!
+//! The hash is SHA-256, the only one offered. No cipher ships with this
+//! crate, so [`SsvMech`] cannot seal, and the privacy-service calls are
+//! left out of its contexts' fuzz calls.
+
+use super::reclaim::ClientOwner;
+use super::replycache::SlotState;
+use super::session::{self, first_op, Sequence, SessionError, SlotTable, USE_NON_PNFS};
+use super::{bitmap_from_bits, minor_version, op, put_bitmap, status, CompoundBuilder};
+use super::{FuzzCase, Op, PROC_COMPOUND};
+use crate::auth::Identity;
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::gss::{Context, GssError, Mechanism, Service};
+use crate::rpc::{program, RpcReply};
+use crate::xdr::{XdrDecoder, XdrEncoder};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// state_protect_how4
+pub mod how {
+    pub const NONE: u32 = 0;
+    pub const MACH_CRED: u32 = 1;
+    pub const SSV: u32 = 2;
+}
+
+/// ssv_subkey4
+pub mod subkey {
+    pub const MIC_I2T: u32 = 1;
+    pub const MIC_T2I: u32 = 2;
+    pub const SEAL_I2T: u32 = 3;
+    pub const SEAL_T2I: u32 = 4;
+}
+
+/// id-sha256, DER-encoded
+pub const SHA256_OID: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01,
+];
+
+/// id-aes256-CBC, DER-encoded
+pub const AES256_CBC_OID: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a,
+];
+
+/// Operations whose state SSV protection is for (RFC 8881 §2.10.8.3)
+pub const ENFORCED: &[u32] = &[
+    op::BIND_CONN_TO_SESSION,
+    op::EXCHANGE_ID,
+    op::CREATE_SESSION,
+    op::DESTROY_SESSION,
+    op::DESTROY_CLIENTID,
+];
+
+/// ssv_sp_parms4
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsvParams {
+    /// Operations that must be sent under the SSV once it is set
+    pub must_enforce: Vec<u32>,
+    /// Operations that may be
+    pub must_allow: Vec<u32>,
+    /// sec_oid4s of the hashes offered
+    pub hash_algs: Vec<Vec<u8>>,
+    pub encr_algs: Vec<Vec<u8>>,
+    /// SSV sequence numbers the server keeps digests for
+    pub window: u32,
+    /// RPCSEC_GSS handles asked for
+    pub num_gss_handles: u32,
+}
+
+impl Default for SsvParams {
+    fn default() -> Self {
+        Self {
+            must_enforce: ENFORCED.to_vec(),
+            must_allow: Vec::new(),
+            hash_algs: vec![SHA256_OID.to_vec()],
+            encr_algs: vec![AES256_CBC_OID.to_vec()],
+            window: 16,
+            num_gss_handles: 2,
+        }
+    }
+}
+
+impl SsvParams {
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        put_bitmap(enc, &bitmap_from_bits(&self.must_enforce));
+        put_bitmap(enc, &bitmap_from_bits(&self.must_allow));
+        for algs in [&self.hash_algs, &self.encr_algs] {
+            enc.put_u32(algs.len() as u32);
+            for oid in algs {
+                enc.put_opaque(oid);
+            }
+        }
+        enc.put_u32(self.window);
+        enc.put_u32(self.num_gss_handles);
+    }
+}
+
+/// Encode EXCHANGE_ID4args asking for SP4_SSV with `params`
+pub fn exchange_id(owner: &ClientOwner, flags: u32, params: &SsvParams) -> Op {
+    Op::new(op::EXCHANGE_ID, |enc| {
+        enc.put_opaque_fixed(&owner.verifier);
+        enc.put_opaque(&owner.ownerid);
+        enc.put_u32(flags);
+        enc.put_u32(how::SSV);
+        params.encode(enc);
+        enc.put_u32(0);
+    })
+}
+
+/// ssv_prot_info4: what the server granted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsvInfo {
+    pub must_enforce: Vec<u32>,
+    pub must_allow: Vec<u32>,
+    /// Index into the hashes offered
+    pub hash_alg: u32,
+    pub encr_alg: u32,
+    /// Bytes of the SSV
+    pub ssv_len: u32,
+    pub window: u32,
+    /// RPCSEC_GSS handles of the SSV GSS mechanism
+    pub handles: Vec<Vec<u8>>,
+}
+
+fn get_bitmap(dec: &mut XdrDecoder) -> Option<Vec<u32>> {
+    let words = dec.get_u32().ok()?;
+    (0..words).map(|_| dec.get_u32().ok()).collect()
+}
+
+/// The SSV protection the EXCHANGE_ID results `results` grant
+pub fn decode_ssv_info(results: &[u8]) -> Result<SsvInfo, SessionError> {
+    let (stat, mut dec) =
+        first_op(results, op::EXCHANGE_ID).ok_or(SessionError::Decode("EXCHANGE_ID"))?;
+    if stat != status::NFS4_OK {
+        return Err(SessionError::Status {
+            op: "EXCHANGE_ID",
+            status: stat,
+        });
+    }
+    let mut fields = || -> Option<SsvInfo> {
+        // Client id, sequence id and flags
+        dec.get_raw(16).ok()?;
+        if dec.get_u32().ok()? != how::SSV {
+            return None;
+        }
+        let must_enforce = get_bitmap(&mut dec)?;
+        let must_allow = get_bitmap(&mut dec)?;
+        let (hash_alg, encr_alg) = (dec.get_u32().ok()?, dec.get_u32().ok()?);
+        let (ssv_len, window) = (dec.get_u32().ok()?, dec.get_u32().ok()?);
+        let handles = dec.get_u32().ok()?;
+        let handles = (0..handles)
+            .map(|_| dec.get_opaque().ok().map(<[u8]>::to_vec))
+            .collect::<Option<_>>()?;
+        Some(SsvInfo {
+            must_enforce,
+            must_allow,
+            hash_alg,
+            encr_alg,
+            ssv_len,
+            window,
+            handles,
+        })
+    };
+    fields().ok_or(SessionError::Decode("EXCHANGE_ID"))
+}
+
+/// HMAC-SHA-256 of `msg` under `key` (RFC 2104)
+fn hmac_sha256(key: &[u8], msg: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(msg);
+    mac.finalize().into_bytes().into()
+}
+
+/// The SSV as client and server hold it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsvKey {
+    pub ssv: Vec<u8>,
+    /// SSV sequence number: the SET_SSVs done so far
+    pub seq: u32,
+}
+
+impl SsvKey {
+    /// The SSV of a client id no SET_SSV has been done for: `len` zeros
+    pub fn initial(len: u32) -> Self {
+        Self {
+            ssv: vec![0; len as usize],
+            seq: 0,
+        }
+    }
+
+    /// The SSV after a SET_SSV of `ssv`
+    pub fn set(&self, ssv: &[u8]) -> Self {
+        let mut next = self.ssv.clone();
+        for (b, x) in next.iter_mut().zip(ssv) {
+            *b ^= x;
+        }
+        Self {
+            ssv: next,
+            seq: self.seq.wrapping_add(1),
+        }
+    }
+
+    /// HMAC of `msg` under the `kind` subkey, itself the HMAC of the XDR
+    /// of `kind` under the SSV
+    pub fn digest(&self, kind: u32, msg: &[u8]) -> Vec<u8> {
+        let subkey = hmac_sha256(&self.ssv, &kind.to_be_bytes());
+        hmac_sha256(&subkey, msg).to_vec()
+    }
+
+    /// smt_hmac over `msg`: the [`digest`](Self::digest) under the `kind`
+    /// subkey of the ssv_mic_plain_tkn4 of the SSV's sequence number and
+    /// `msg`
+    pub fn mic_hmac(&self, kind: u32, msg: &[u8]) -> Vec<u8> {
+        let mut plain = XdrEncoder::new();
+        plain.put_u32(self.seq);
+        plain.put_opaque(msg);
+        self.digest(kind, plain.as_bytes())
+    }
+}
+
+/// The SSV GSS mechanism: checksums are ssv_mic_tkn4s under the key's
+/// MIC subkeys
+#[derive(Debug, Clone)]
+pub struct SsvMech {
+    pub key: SsvKey,
+}
+
+impl SsvMech {
+    /// ssv_mic_tkn4 over `msg` under the `kind` subkey
+    fn mic(&self, kind: u32, msg: &[u8]) -> Vec<u8> {
+        let mut token = XdrEncoder::new();
+        token.put_u32(self.key.seq);
+        token.put_opaque(&self.key.mic_hmac(kind, msg));
+        token.as_bytes().to_vec()
+    }
+}
+
+impl Mechanism for SsvMech {
+    fn step(&mut self, _input: Option<&[u8]>) -> Result<Option<Vec<u8>>, GssError> {
+        Err(GssError::Mechanism(
+            "SSV contexts come from EXCHANGE_ID, not INIT".to_string(),
+        ))
+    }
+
+    fn get_mic(&self, msg: &[u8]) -> Result<Vec<u8>, GssError> {
+        Ok(self.mic(subkey::MIC_I2T, msg))
+    }
+
+    fn verify_mic(&self, msg: &[u8], mic: &[u8]) -> bool {
+        self.mic(subkey::MIC_T2I, msg) == mic
+    }
+
+    /// The mechanism does not [seal](Mechanism::seals)
+    fn wrap(&self, _msg: &[u8]) -> Result<Vec<u8>, GssError> {
+        Err(GssError::Mechanism(
+            "SSV contexts carry no privacy service".to_string(),
+        ))
+    }
+
+    fn seals(&self) -> bool {
+        false
+    }
+
+    fn unwrap(&self, _token: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Encode SET_SSV4args
+pub fn set_ssv(ssv: &[u8], digest: &[u8]) -> Op {
+    Op::new(op::SET_SSV, |enc| {
+        enc.put_opaque(ssv);
+        enc.put_opaque(digest);
+    })
+}
+
+/// ssa_digest of a SET_SSV sent with `sequence`: the smt_hmac over the
+/// XDR of its ssa_digest_input4, which is the SEQUENCE4args
+pub fn set_ssv_digest(key: &SsvKey, sequence: &Sequence) -> Vec<u8> {
+    key.mic_hmac(subkey::MIC_I2T, &sequence.op().args)
+}
+
+/// One SET_SSV compound of a plan
+#[derive(Debug, Clone)]
+pub struct SsvCase {
+    pub name: String,
+    pub sequence: Sequence,
+    /// The ssa_ssv sent
+    pub ssv: Vec<u8>,
+    pub set: Op,
+    /// SET_SSV statuses a correct server may return
+    pub expect: &'static [u32],
+}
+
+impl SsvCase {
+    pub fn all_ops(&self) -> Vec<Op> {
+        vec![self.sequence.op(), self.set.clone()]
+    }
+}
+
+/// SET_SSVs on successive sequence ids of `slot`, all under `key`: bad
+/// digests, then SSVs of the wrong length, then `fresh` with a valid
+/// digest
+pub fn set_ssv_plan(slot: &SlotState, key: &SsvKey, fresh: &[u8]) -> Vec<SsvCase> {
+    let len = key.ssv.len();
+    let digest = |sequence: &Sequence| set_ssv_digest(key, sequence);
+    let edit = |f: fn(&mut Vec<u8>)| {
+        move |sequence: &Sequence| {
+            let mut d = digest(sequence);
+            f(&mut d);
+            d
+        }
+    };
+    const BAD_DIGEST: &[u32] = &[status::NFS4ERR_BAD_SESSION_DIGEST];
+    const BAD_SSV: &[u32] = &[status::NFS4ERR_INVAL];
+    type Digest<'a> = Box<dyn Fn(&Sequence) -> Vec<u8> + 'a>;
+    let plan: Vec<(&str, Vec<u8>, Digest, &'static [u32])> = vec![
+        (
+            "digest_flipped",
+            fresh.to_vec(),
+            Box::new(edit(|d| d[0] ^= 1)),
+            BAD_DIGEST,
+        ),
+        (
+            "digest_truncated",
+            fresh.to_vec(),
+            Box::new(edit(|d| d.truncate(d.len() / 2))),
+            BAD_DIGEST,
+        ),
+        (
+            "digest_empty",
+            fresh.to_vec(),
+            Box::new(|_: &Sequence| Vec::new()),
+            BAD_DIGEST,
+        ),
+        (
+            "digest_oversized",
+            fresh.to_vec(),
+            Box::new(edit(|d| d.resize(4096, 0))),
+            BAD_DIGEST,
+        ),
+        (
+            "digest_t2i_subkey",
+            fresh.to_vec(),
+            Box::new(|s: &Sequence| key.mic_hmac(subkey::MIC_T2I, &s.op().args)),
+            BAD_DIGEST,
+        ),
+        (
+            "digest_other_slot",
+            fresh.to_vec(),
+            Box::new(|s: &Sequence| {
+                let mut other = s.clone();
+                other.slotid = other.slotid.wrapping_add(1);
+                digest(&other)
+            }),
+            BAD_DIGEST,
+        ),
+        (
+            "digest_next_ssv",
+            fresh.to_vec(),
+            Box::new(|s: &Sequence| set_ssv_digest(&key.set(fresh), s)),
+            BAD_DIGEST,
+        ),
+        ("ssv_empty", Vec::new(), Box::new(digest), BAD_SSV),
+        (
+            "ssv_short",
+            vec![0x5a; len.saturating_sub(1)],
+            Box::new(digest),
+            BAD_SSV,
+        ),
+        ("ssv_long", vec![0x5a; len + 1], Box::new(digest), BAD_SSV),
+        ("ssv_64k", vec![0x5a; 64 << 10], Box::new(digest), BAD_SSV),
+        (
+            "valid",
+            fresh.to_vec(),
+            Box::new(digest),
+            &[status::NFS4_OK],
+        ),
+    ];
+    plan.into_iter()
+        .enumerate()
+        .map(|(i, (name, ssv, digest, expect))| {
+            let sequence = Sequence {
+                sessionid: slot.sessionid,
+                sequenceid: slot.next_seqid.wrapping_add(i as u32),
+                slotid: slot.slotid,
+                highest_slotid: slot.highest_slotid,
+                cachethis: false,
+            };
+            SsvCase {
+                name: format!("set_ssv_{}", name),
+                set: set_ssv(&ssv, &digest(&sequence)),
+                sequence,
+                ssv,
+                expect,
+            }
+        })
+        .collect()
+}
+
+/// SEQUENCE4res and SET_SSV4res of a SET_SSV compound
+#[derive(Debug)]
+struct SetSsvRes<'a> {
+    /// SEQUENCE4res as sent, which the reply digest covers
+    seqres: &'a [u8],
+    status: u32,
+    digest: Option<&'a [u8]>,
+}
+
+fn decode_set_ssv(results: &[u8]) -> Option<SetSsvRes<'_>> {
+    let (stat, mut dec) = first_op(results, op::SEQUENCE)?;
+    let seqres = &results[results.len() - dec.rest().len() - 4..];
+    if stat != status::NFS4_OK {
+        return None;
+    }
+    dec.get_raw(16 + 5 * 4).ok()?;
+    let seqres = &seqres[..seqres.len() - dec.rest().len()];
+    if dec.get_u32().ok()? != op::SET_SSV {
+        return None;
+    }
+    let status = dec.get_u32().ok()?;
+    let digest = match status {
+        status::NFS4_OK => Some(dec.get_opaque().ok()?),
+        _ => None,
+    };
+    Some(SetSsvRes {
+        seqres,
+        status,
+        digest,
+    })
+}
+
+/// Check the results of `case`, whose SSV would make `next` the key: a
+/// status it may return, and on success a reply digest under `next`
+pub fn check_set_ssv(case: &SsvCase, results: &[u8], next: &SsvKey) -> Result<(), String> {
+    let res =
+        decode_set_ssv(results).ok_or_else(|| format!("{}: results do not decode", case.name))?;
+    if !case.expect.contains(&res.status) {
+        return Err(format!(
+            "{}: SET_SSV status {}, expected {:?}",
+            case.name, res.status, case.expect
+        ));
+    }
+    match res.digest {
+        Some(d) if d != next.mic_hmac(subkey::MIC_T2I, res.seqres) => Err(format!(
+            "{}: reply digest does not verify under the new SSV",
+            case.name
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Cases that need no session: SP4_SSV EXCHANGE_IDs with malformed
+/// parameters, and SET_SSVs whose digests cannot be right
+pub fn cases() -> Vec<FuzzCase> {
+    let owner = ClientOwner {
+        verifier: [0x55; 8],
+        ownerid: b"nfs-fuzzer-ssv".to_vec(),
+    };
+    let base = SsvParams::default();
+    let variants = [
+        (
+            "no_hashes",
+            SsvParams {
+                hash_algs: Vec::new(),
+                ..base.clone()
+            },
+        ),
+        (
+            "no_ciphers",
+            SsvParams {
+                encr_algs: Vec::new(),
+                ..base.clone()
+            },
+        ),
+        (
+            "unknown_oids",
+            SsvParams {
+                hash_algs: vec![vec![0x06, 0x03, 0x2a, 0x03, 0x04]],
+                encr_algs: vec![vec![0x06, 0x03, 0x2a, 0x03, 0x05]],
+                ..base.clone()
+            },
+        ),
+        (
+            "empty_oids",
+            SsvParams {
+                hash_algs: vec![Vec::new()],
+                encr_algs: vec![Vec::new()],
+                ..base.clone()
+            },
+        ),
+        (
+            "oid_length_overrun",
+            SsvParams {
+                hash_algs: vec![vec![0x06, 0x7f, 0x60, 0x86]],
+                ..base.clone()
+            },
+        ),
+        (
+            "oid_flood",
+            SsvParams {
+                hash_algs: vec![SHA256_OID.to_vec(); 4096],
+                ..base.clone()
+            },
+        ),
+        (
+            "window_zero",
+            SsvParams {
+                window: 0,
+                ..base.clone()
+            },
+        ),
+        (
+            "window_max",
+            SsvParams {
+                window: u32::MAX,
+                ..base.clone()
+            },
+        ),
+        (
+            "no_handles",
+            SsvParams {
+                num_gss_handles: 0,
+                ..base.clone()
+            },
+        ),
+        (
+            "handles_max",
+            SsvParams {
+                num_gss_handles: u32::MAX,
+                ..base.clone()
+            },
+        ),
+        (
+            "enforce_unknown_ops",
+            SsvParams {
+                must_enforce: vec![op::ILLEGAL, 1000, 4095],
+                ..base.clone()
+            },
+        ),
+        (
+            "enforce_and_allow_all",
+            SsvParams {
+                must_enforce: (0..=op::CLONE).collect(),
+                must_allow: (0..=op::CLONE).collect(),
+                ..base.clone()
+            },
+        ),
+    ];
+    let mut cases: Vec<FuzzCase> = variants
+        .iter()
+        .map(|(name, params)| {
+            FuzzCase::new(
+                format!("exchange_id_ssv_{}", name),
+                vec![exchange_id(&owner, USE_NON_PNFS, params)],
+            )
+        })
+        .collect();
+    // SP4_SSV cut off after the bitmaps
+    let truncated = Op::new(op::EXCHANGE_ID, |enc| {
+        enc.put_opaque_fixed(&owner.verifier);
+        enc.put_opaque(&owner.ownerid);
+        enc.put_u32(USE_NON_PNFS);
+        enc.put_u32(how::SSV);
+        put_bitmap(enc, &bitmap_from_bits(ENFORCED));
+        put_bitmap(enc, &[]);
+    });
+    cases.push(FuzzCase::new("exchange_id_ssv_truncated", vec![truncated]));
+    let key = SsvKey::initial(32);
+    for (name, ssv, digest) in [
+        ("zero_key", vec![0x5a; 32], key.digest(subkey::MIC_I2T, &[])),
+        ("empty", Vec::new(), Vec::new()),
+        ("digest_64k", vec![0x5a; 32], vec![0xa5; 64 << 10]),
+        ("ssv_64k", vec![0x5a; 64 << 10], vec![0; 32]),
+    ] {
+        cases.push(FuzzCase::new(
+            format!("set_ssv_{}", name),
+            vec![set_ssv(&ssv, &digest)],
+        ));
+    }
+    cases
+}
+
+/// A session under SSV protection
+#[derive(Debug, Clone)]
+pub struct Protected {
+    pub table: SlotTable,
+    pub info: SsvInfo,
+    /// The SSV before any SET_SSV
+    pub key: SsvKey,
+}
+
+impl Protected {
+    /// An SSV GSS context on the first handle the server granted, for the
+    /// SSV `key`
+    pub fn context(&self, key: &SsvKey, service: Service) -> Option<Context<SsvMech>> {
+        Some(Context::new(
+            SsvMech { key: key.clone() },
+            self.info.handles.first()?.clone(),
+            service,
+            self.info.window,
+            program::NFS,
+            4,
+        ))
+    }
+}
+
+/// Set up a session for `owner` on `conn` under SP4_SSV with `params`,
+/// creating it with csa_flags `flags`
+pub async fn establish<S>(
+    conn: &mut NfsConnection<S>,
+    owner: &ClientOwner,
+    params: &SsvParams,
+    flags: u32,
+    identity: &Identity,
+) -> Result<Protected, SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let exchange = exchange_id(owner, USE_NON_PNFS, params);
+    let (table, exchanged) = session::establish_with(conn, exchange, flags, identity).await?;
+    let info = decode_ssv_info(&exchanged)?;
+    Ok(Protected {
+        key: SsvKey::initial(info.ssv_len),
+        table,
+        info,
+    })
+}
+
+/// Send the [`set_ssv_plan`] on slot 0 of `protected`, setting `fresh`,
+/// then [`Context::fuzz_calls`] of a SEQUENCE and PUTROOTFH under the
+/// SSV. What the server got wrong: a SET_SSV status or reply digest it
+/// should not have sent, or a call with a bad checksum it carried out.
+/// Calls it dropped are fine. The key the server holds at the end.
+pub async fn probe<S>(
+    conn: &mut NfsConnection<S>,
+    protected: &mut Protected,
+    fresh: &[u8],
+    identity: &Identity,
+) -> Result<(Vec<String>, SsvKey), SessionError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut violations = Vec::new();
+    let mut key = protected.key.clone();
+    let slot = protected
+        .table
+        .slot(0)
+        .ok_or(SessionError::Decode("CREATE_SESSION"))?;
+    for case in set_ssv_plan(&slot, &key, fresh) {
+        let results = session::compound(conn, case.all_ops(), identity).await?;
+        protected.table.record(&results);
+        let next = key.set(&case.ssv);
+        if let Err(e) = check_set_ssv(&case, &results, &next) {
+            violations.push(e);
+        }
+        if decode_set_ssv(&results).is_some_and(|r| r.status == status::NFS4_OK) {
+            key = next;
+        }
+    }
+    let Some(ctx) = protected.context(&key, Service::Integrity) else {
+        return Ok((violations, key));
+    };
+    let sequence = protected
+        .table
+        .sequence(0, false)
+        .ok_or(SessionError::Decode("CREATE_SESSION"))?;
+    let args = CompoundBuilder::new(minor_version::V4_1)
+        .with_tag(b"ssv")
+        .op(sequence.op())
+        .putrootfh()
+        .build();
+    for (name, call) in ctx.fuzz_calls(PROC_COMPOUND, &args)? {
+        if !name.contains("mic") {
+            continue;
+        }
+        match conn.call(&call).await {
+            Ok(reply) => {
+                if let Ok(reply) = RpcReply::parse(&reply) {
+                    if reply.is_success() {
+                        protected.table.record(reply.results);
+                        violations.push(format!("{} carried out", name));
+                    }
+                }
+            }
+            Err(ConnectionError::Timeout { .. }) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok((violations, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hmac_and_ssv() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let key = SsvKey::initial(4).set(&[1, 2, 3, 4]).set(&[1, 0, 0, 0]);
+        assert_eq!((key.ssv, key.seq), (vec![0, 2, 3, 4], 2));
+    }
+
+    #[test]
+    fn test_exchange_id_and_info() {
+        let owner = ClientOwner {
+            verifier: [1; 8],
+            ownerid: b"o".to_vec(),
+        };
+        let op = exchange_id(&owner, USE_NON_PNFS, &SsvParams::default());
+        // Verifier, owner id, flags, then SP4_SSV and the must_enforce
+        // bitmap of ops 41 to 44 and 57
+        assert_eq!(op.args[20..24], how::SSV.to_be_bytes());
+        assert_eq!(
+            op.args[24..36],
+            [0, 0, 0, 2, 0, 0, 0, 0, 0x02, 0x00, 0x1e, 0x00]
+        );
+
+        let mut enc = XdrEncoder::new();
+        for w in [status::NFS4_OK, 0, 1, op::EXCHANGE_ID, status::NFS4_OK] {
+            enc.put_u32(w);
+        }
+        enc.put_u64(0xc1);
+        for w in [1, USE_NON_PNFS, how::SSV, 1, 1 << 11, 0, 0, 0, 32, 16, 1] {
+            enc.put_u32(w);
+        }
+        enc.put_opaque(b"handle");
+        let info = decode_ssv_info(enc.as_bytes()).unwrap();
+        assert_eq!(info.must_enforce, [1 << 11]);
+        assert_eq!((info.ssv_len, info.window), (32, 16));
+        assert_eq!(info.handles, [b"handle".to_vec()]);
+        assert!(cases().iter().all(|c| c.ops.len() == 1));
+
+        // Nothing is sealed, so nothing goes out under the privacy service
+        let protected = Protected {
+            key: SsvKey::initial(32),
+            table: SlotTable::new(0xc1, [0x5e; 16], 1),
+            info,
+        };
+        let ctx = protected
+            .context(&protected.key, Service::Integrity)
+            .unwrap();
+        let calls = ctx.fuzz_calls(PROC_COMPOUND, &[]).unwrap();
+        assert!(calls.iter().any(|(name, _)| name.starts_with("gss_integ")));
+        assert!(calls
+            .iter()
+            .all(|(name, _)| !name.contains("priv") && !name.contains("krb5p")));
+    }
+
+    #[test]
+    fn test_set_ssv_plan_and_check() {
+        let slot = SlotState {
+            sessionid: [7; 16],
+            slotid: 0,
+            highest_slotid: 3,
+            next_seqid: 5,
+        };
+        let key = SsvKey::initial(32);
+        let fresh = [0x11; 32];
+        let plan = set_ssv_plan(&slot, &key, &fresh);
+        let valid = plan.last().unwrap();
+        assert_eq!(valid.expect, [status::NFS4_OK]);
+        assert_eq!(valid.sequence.sequenceid, 5 + plan.len() as u32 - 1);
+        let mut want = set_ssv(&fresh, &set_ssv_digest(&key, &valid.sequence));
+        assert_eq!(valid.set, want);
+        let mut plain = XdrEncoder::new();
+        plain.put_u32(0);
+        plain.put_opaque(&valid.sequence.op().args);
+        let digest = set_ssv_digest(&key, &valid.sequence);
+        assert_eq!(digest, key.digest(subkey::MIC_I2T, plain.as_bytes()));
+        want = set_ssv(&fresh, &set_ssv_digest(&key, &plan[0].sequence));
+        assert_ne!(plan[0].set, want);
+        assert_eq!(plan[0].set.args.len(), want.args.len());
+
+        let next = key.set(&fresh);
+        let results = |stat: u32, digest: &dyn Fn(&[u8]) -> Vec<u8>| {
+            let mut seqres = XdrEncoder::new();
+            seqres.put_u32(status::NFS4_OK);
+            seqres.put_opaque_fixed(&[7; 16]);
+            for w in [5, 0, 3, 3, 0] {
+                seqres.put_u32(w);
+            }
+            let mut enc = XdrEncoder::new();
+            for w in [stat, 0, 2, op::SEQUENCE] {
+                enc.put_u32(w);
+            }
+            enc.put_raw(seqres.as_bytes());
+            enc.put_u32(op::SET_SSV);
+            enc.put_u32(stat);
+            if stat == status::NFS4_OK {
+                enc.put_opaque(&digest(seqres.as_bytes()));
+            }
+            enc.as_bytes().to_vec()
+        };
+        let good = |s: &[u8]| next.mic_hmac(subkey::MIC_T2I, s);
+        assert!(check_set_ssv(valid, &results(status::NFS4_OK, &good), &next).is_ok());
+        let stale = |s: &[u8]| key.mic_hmac(subkey::MIC_T2I, s);
+        assert!(check_set_ssv(valid, &results(status::NFS4_OK, &stale), &next).is_err());
+        let bad = status::NFS4ERR_BAD_SESSION_DIGEST;
+        assert!(check_set_ssv(&plan[0], &results(bad, &good), &next).is_ok());
+        assert!(check_set_ssv(&plan[0], &results(status::NFS4_OK, &good), &next).is_err());
+
+        let mech = SsvMech { key: next };
+        let mic = mech.get_mic(b"header").unwrap();
+        assert!(mech.wrap(b"body").is_err());
+        assert!(!mech.verify_mic(b"header", &mic));
+        assert_eq!(mic[..8], [0, 0, 0, 1, 0, 0, 0, 32]);
+    }
+}
@@ -0,0 +1,518 @@
+//! Client state harvested from replies (RFC 8881 §8, §9)
+//!
+//! A v4 server checks the client id, the stateid and the current handle
+//! of nearly every operation before it looks at anything else, so cases
+//! made up without state never get past the first check. [`SessionState`]
+//! keeps the client id, open, lock, delegation and layout stateids, the
+//! pNFS device ids and the filehandles that replies hand out, and
+//! resolves placeholders in generated compounds against them just
+//! before they are sent: a [`stateid`] placeholder names a kind and an
+//! index, counted back from the latest harvested, with its seqid an
+//! offset from the harvested one; a [`filehandle`] placeholder names a
+//! handle the same way, a [`deviceid`] placeholder a device id with its
+//! last word an offset from the harvested one's, and [`CLIENTID`] stands
+//! for the client id. A placeholder that a mutation damaged goes out as
+//! it is, so mutated cases mix live state with broken state, and until a
+//! kind has been harvested its placeholders go out unresolved too.
+
+use super::lockowner::{lock, lock_type, locku, LockOwner, Locker, TO_EOF};
+use super::open::{delegation_type, Open};
+use super::pnfs::{layout_type, DeviceId, DEVICEID_SIZE};
+use super::stateids::test_stateid;
+use super::{
+    close, delegreturn, getfh, op, putfh, read, stable_how, status, write, FuzzCase, Stateid,
+};
+use crate::xdr::{XdrDecoder, XdrEncoder, XdrError};
+
+/// Marker bytes that open every stateid and filehandle placeholder
+pub const MAGIC: [u8; 8] = *b"fuzstate";
+
+/// Placeholder for the client id
+pub const CLIENTID: u64 = u64::from_be_bytes(*b"fuzclien");
+
+/// Harvested values kept per kind; the oldest go first
+pub const MAX_KEPT: usize = 32;
+
+/// What a placeholder stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Open,
+    Lock,
+    Delegation,
+    Filehandle,
+    Layout,
+    Device,
+}
+
+impl Kind {
+    fn tag(self) -> u8 {
+        match self {
+            Kind::Open => 1,
+            Kind::Lock => 2,
+            Kind::Delegation => 3,
+            Kind::Filehandle => 4,
+            Kind::Layout => 5,
+            Kind::Device => 6,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Kind::Open),
+            2 => Some(Kind::Lock),
+            3 => Some(Kind::Delegation),
+            4 => Some(Kind::Filehandle),
+            5 => Some(Kind::Layout),
+            6 => Some(Kind::Device),
+            _ => None,
+        }
+    }
+}
+
+fn marker(kind: Kind, index: u8) -> [u8; 12] {
+    let mut out = [0; 12];
+    out[..8].copy_from_slice(&MAGIC);
+    out[8] = kind.tag();
+    out[11] = index;
+    out
+}
+
+/// The kind and index of the marker at the start of `buf`, if it is one
+fn marker_at(buf: &[u8]) -> Option<(Kind, usize)> {
+    let m = buf.get(..12)?;
+    if m[..8] != MAGIC || m[9..11] != [0, 0] {
+        return None;
+    }
+    Some((Kind::from_tag(m[8])?, m[11] as usize))
+}
+
+/// Placeholder for the `index`th latest stateid of `kind`, at the
+/// harvested seqid
+pub fn stateid(kind: Kind, index: u8) -> Stateid {
+    Stateid::new(0, marker(kind, index))
+}
+
+/// Placeholder for the `index`th latest filehandle
+pub fn filehandle(index: u8) -> Vec<u8> {
+    marker(Kind::Filehandle, index).to_vec()
+}
+
+/// Placeholder for the `index`th latest device id
+pub fn deviceid(index: u8) -> DeviceId {
+    let mut out = [0; DEVICEID_SIZE];
+    out[..12].copy_from_slice(&marker(Kind::Device, index));
+    out
+}
+
+fn get_stateid(dec: &mut XdrDecoder<'_>) -> Result<Stateid, XdrError> {
+    let seqid = dec.get_u32()?;
+    let other = dec.get_opaque_fixed(12)?.try_into().unwrap();
+    Ok(Stateid::new(seqid, other))
+}
+
+fn skip_bitmap(dec: &mut XdrDecoder<'_>) -> Result<(), XdrError> {
+    for _ in 0..dec.get_u32()? {
+        dec.get_u32()?;
+    }
+    Ok(())
+}
+
+/// Add `s` as the latest of `list`, in place of an older seqid of it
+fn keep(list: &mut Vec<Stateid>, s: Stateid) {
+    if Stateid::SPECIAL.contains(&s) || s.other == [0; 12] {
+        return;
+    }
+    list.retain(|o| o.other != s.other);
+    list.push(s);
+    if list.len() > MAX_KEPT {
+        list.remove(0);
+    }
+}
+
+/// Add `v` as the latest of `list`, in place of an equal older one
+fn remember<T: PartialEq>(list: &mut Vec<T>, v: T) {
+    list.retain(|o| *o != v);
+    list.push(v);
+    if list.len() > MAX_KEPT {
+        list.remove(0);
+    }
+}
+
+/// The device id opening a files or flex-files layout body
+fn layout_device(layout: u32, body: &[u8]) -> Option<DeviceId> {
+    let at = match layout {
+        layout_type::NFSV4_1_FILES => 0,
+        // Stripe unit, mirror count, data server count
+        layout_type::FLEX_FILES => 16,
+        _ => return None,
+    };
+    body.get(at..at + DEVICEID_SIZE)?.try_into().ok()
+}
+
+/// The `index`th latest of `list`, wrapping round
+fn latest<T>(list: &[T], index: usize) -> Option<&T> {
+    (!list.is_empty()).then(|| &list[list.len() - 1 - index % list.len()])
+}
+
+/// State the server has handed this client so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionState {
+    pub clientid: Option<u64>,
+    /// Oldest first, as are the other lists
+    pub opens: Vec<Stateid>,
+    pub locks: Vec<Stateid>,
+    pub delegations: Vec<Stateid>,
+    pub filehandles: Vec<Vec<u8>>,
+    pub layouts: Vec<Stateid>,
+    pub devices: Vec<DeviceId>,
+}
+
+impl SessionState {
+    /// Take in the results of a compound: the client id from
+    /// SETCLIENTID or EXCHANGE_ID, stateids from OPEN, OPEN_CONFIRM,
+    /// OPEN_DOWNGRADE, CLOSE, LOCK, LOCKU, LAYOUTGET and LAYOUTRETURN,
+    /// device ids from GETDEVICELIST and LAYOUTGET and handles from GETFH,
+    /// up to the first failed op or one whose results cannot be skipped
+    pub fn observe(&mut self, results: &[u8]) {
+        let _ = self.walk(&mut XdrDecoder::new(results));
+    }
+
+    fn walk(&mut self, dec: &mut XdrDecoder<'_>) -> Result<(), XdrError> {
+        let _status = dec.get_u32()?;
+        dec.get_opaque()?;
+        for _ in 0..dec.get_u32()? {
+            let opcode = dec.get_u32()?;
+            if dec.get_u32()? != status::NFS4_OK {
+                break;
+            }
+            match opcode {
+                op::GETFH => remember(&mut self.filehandles, dec.get_opaque()?.to_vec()),
+                op::SETCLIENTID => {
+                    self.clientid = Some(dec.get_u64()?);
+                    dec.get_opaque_fixed(8)?;
+                }
+                op::EXCHANGE_ID => {
+                    self.clientid = Some(dec.get_u64()?);
+                    break;
+                }
+                op::OPEN => {
+                    keep(&mut self.opens, get_stateid(dec)?);
+                    // change_info4, rflags, attrset
+                    dec.get_opaque_fixed(20)?;
+                    dec.get_u32()?;
+                    skip_bitmap(dec)?;
+                    match dec.get_u32()? {
+                        delegation_type::NONE => {}
+                        delegation_type::READ | delegation_type::WRITE => {
+                            keep(&mut self.delegations, get_stateid(dec)?);
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+                op::OPEN_CONFIRM | op::OPEN_DOWNGRADE | op::CLOSE => {
+                    keep(&mut self.opens, get_stateid(dec)?)
+                }
+                op::LOCK | op::LOCKU => keep(&mut self.locks, get_stateid(dec)?),
+                op::LAYOUTGET => {
+                    // logr_return_on_close
+                    dec.get_u32()?;
+                    keep(&mut self.layouts, get_stateid(dec)?);
+                    for _ in 0..dec.get_u32()? {
+                        // Offset, length, iomode
+                        dec.get_opaque_fixed(20)?;
+                        let layout = dec.get_u32()?;
+                        if let Some(id) = layout_device(layout, dec.get_opaque()?) {
+                            remember(&mut self.devices, id);
+                        }
+                    }
+                }
+                op::LAYOUTRETURN => {
+                    if dec.get_u32()? != 0 {
+                        keep(&mut self.layouts, get_stateid(dec)?);
+                    }
+                }
+                op::GETDEVICELIST => {
+                    // Cookie and cookie verifier
+                    dec.get_opaque_fixed(16)?;
+                    for _ in 0..dec.get_u32()? {
+                        let id = dec.get_opaque_fixed(DEVICEID_SIZE)?;
+                        remember(&mut self.devices, id.try_into().unwrap());
+                    }
+                    dec.get_u32()?;
+                }
+                op::SEQUENCE => {
+                    dec.get_opaque_fixed(36)?;
+                }
+                op::GETATTR => {
+                    skip_bitmap(dec)?;
+                    dec.get_opaque()?;
+                }
+                op::ACCESS => {
+                    dec.get_opaque_fixed(8)?;
+                }
+                op::PUTFH
+                | op::PUTPUBFH
+                | op::PUTROOTFH
+                | op::SAVEFH
+                | op::RESTOREFH
+                | op::LOOKUP
+                | op::LOOKUPP
+                | op::DELEGRETURN
+                | op::RENEW
+                | op::SETCLIENTID_CONFIRM
+                | op::FREE_STATEID
+                | op::RELEASE_LOCKOWNER
+                | op::RECLAIM_COMPLETE => {}
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn stateids(&self, kind: Kind) -> &[Stateid] {
+        match kind {
+            Kind::Open => &self.opens,
+            Kind::Lock => &self.locks,
+            Kind::Delegation => &self.delegations,
+            Kind::Layout => &self.layouts,
+            Kind::Filehandle | Kind::Device => &[],
+        }
+    }
+
+    /// The length of the placeholder at the start of `buf` and what it
+    /// resolves to, if it is one and something has been harvested for it
+    fn resolve_at(&self, buf: &[u8]) -> Option<(usize, Vec<u8>)> {
+        if buf.get(..8) == Some(&CLIENTID.to_be_bytes()[..]) {
+            return Some((8, self.clientid?.to_be_bytes().to_vec()));
+        }
+        if let Some((Kind::Device, index)) = marker_at(buf) {
+            let mut id = *latest(&self.devices, index)?;
+            let offset = u32::from_be_bytes(buf.get(12..16)?.try_into().unwrap());
+            let tail = u32::from_be_bytes(id[12..].try_into().unwrap());
+            id[12..].copy_from_slice(&tail.wrapping_add(offset).to_be_bytes());
+            return Some((DEVICEID_SIZE, id.to_vec()));
+        }
+        let word = u32::from_be_bytes(buf.get(..4)?.try_into().unwrap());
+        match marker_at(&buf[4..])? {
+            (Kind::Filehandle, index) if word == 12 => {
+                let mut enc = XdrEncoder::new();
+                enc.put_opaque(latest(&self.filehandles, index)?);
+                Some((16, enc.as_bytes().to_vec()))
+            }
+            (Kind::Filehandle, _) => None,
+            (kind, index) => {
+                let s = latest(self.stateids(kind), index)?;
+                let mut enc = XdrEncoder::new();
+                Stateid::new(s.seqid.wrapping_add(word), s.other).encode(&mut enc);
+                Some((16, enc.as_bytes().to_vec()))
+            }
+        }
+    }
+
+    /// `args` with every intact placeholder that can be resolved
+    /// replaced by the harvested value it names
+    pub fn resolve(&self, args: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(args.len());
+        let mut i = 0;
+        while i < args.len() {
+            match self.resolve_at(&args[i..]) {
+                Some((len, value)) => {
+                    out.extend_from_slice(&value);
+                    i += len;
+                }
+                None => {
+                    out.push(args[i]);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Compounds working on harvested state: an OPEN of `name` with GETFH
+/// to harvest from, then reads, writes, locks and returns on the latest
+/// open, lock and delegation, and state used on the wrong handle or as
+/// the wrong kind
+pub fn cases(name: &[u8]) -> Vec<FuzzCase> {
+    let fh = || putfh(&filehandle(0));
+    let open = stateid(Kind::Open, 0);
+    let lock_state = stateid(Kind::Lock, 0);
+    let delegation = stateid(Kind::Delegation, 0);
+    let owner = LockOwner::new(CLIENTID, b"state".to_vec());
+    vec![
+        FuzzCase::new(
+            "open_getfh",
+            vec![Open::existing(CLIENTID, b"state", name).op(), getfh()],
+        ),
+        FuzzCase::new("read", vec![fh(), read(&open, 0, 4096)]),
+        FuzzCase::new(
+            "write",
+            vec![fh(), write(&open, 0, stable_how::UNSTABLE, b"state")],
+        ),
+        FuzzCase::new(
+            "lock",
+            vec![
+                fh(),
+                lock(
+                    lock_type::WRITE,
+                    false,
+                    0,
+                    TO_EOF,
+                    &Locker::new_owner(&open, &owner),
+                ),
+            ],
+        ),
+        FuzzCase::new(
+            "lock_existing",
+            vec![
+                fh(),
+                lock(
+                    lock_type::WRITE,
+                    false,
+                    0,
+                    4096,
+                    &Locker::Existing {
+                        lock_stateid: lock_state,
+                        lock_seqid: 0,
+                    },
+                ),
+            ],
+        ),
+        FuzzCase::new(
+            "locku",
+            vec![fh(), locku(lock_type::WRITE, 0, &lock_state, 0, TO_EOF)],
+        ),
+        FuzzCase::new("read_lock_stateid", vec![fh(), read(&lock_state, 0, 4096)]),
+        FuzzCase::new(
+            "read_other_fh",
+            vec![putfh(&filehandle(1)), read(&open, 0, 4096)],
+        ),
+        FuzzCase::new("delegreturn", vec![fh(), delegreturn(&delegation)]),
+        FuzzCase::new(
+            "test_stateid",
+            vec![test_stateid(&[open, lock_state, delegation])],
+        ),
+        FuzzCase::new("close", vec![fh(), close(0, &open)]),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::{minor_version, ok_results, CompoundBuilder};
+
+    fn open_res(s: Stateid) -> impl Fn(&mut XdrEncoder) {
+        move |enc| {
+            s.encode(enc);
+            enc.put_opaque_fixed(&[0; 20]);
+            enc.put_u32(0);
+            enc.put_u32(1);
+            enc.put_u32(0);
+            enc.put_u32(delegation_type::NONE);
+        }
+    }
+
+    #[test]
+    fn test_observe_harvests_state() {
+        let open = Stateid::new(1, [7; 12]);
+        let locked = Stateid::new(1, [9; 12]);
+        let mut state = SessionState::default();
+        state.observe(&ok_results(&[
+            (op::PUTROOTFH, &|_| {}),
+            (op::OPEN, &open_res(open)),
+            (op::GETFH, &|enc| enc.put_opaque(b"handle")),
+            (op::LOCK, &|enc| locked.encode(enc)),
+        ]));
+        assert_eq!(state.opens, [open]);
+        assert_eq!(state.locks, [locked]);
+        assert_eq!(state.filehandles, [b"handle".to_vec()]);
+
+        // A newer seqid of the same open replaces it; a v4.1 CLOSE's
+        // special stateid and ops after an unknown one are not kept
+        state.observe(&ok_results(&[
+            (op::OPEN_DOWNGRADE, &|enc| {
+                Stateid::new(2, [7; 12]).encode(enc)
+            }),
+            (op::CLOSE, &|enc| Stateid::INVALID.encode(enc)),
+            (op::READDIR, &|enc| enc.put_u32(0)),
+            (op::GETFH, &|enc| enc.put_opaque(b"other")),
+        ]));
+        assert_eq!(state.opens, [Stateid::new(2, [7; 12])]);
+        assert_eq!(state.filehandles.len(), 1);
+        state.observe(&ok_results(&[(op::EXCHANGE_ID, &|enc| enc.put_u64(0x42))]));
+        assert_eq!(state.clientid, Some(0x42));
+    }
+
+    #[test]
+    fn test_resolve_placeholders() {
+        let case = &cases(b"f")[3];
+        let args = CompoundBuilder::new(minor_version::V4_1)
+            .ops(case.ops.iter().cloned())
+            .build();
+        let mut state = SessionState::default();
+        assert_eq!(state.resolve(&args), args);
+
+        state.clientid = Some(0x1122);
+        state.opens = vec![Stateid::new(5, [7; 12])];
+        state.filehandles = vec![b"old".to_vec(), b"fh".to_vec()];
+        let resolved = state.resolve(&args);
+        let mut fh = XdrEncoder::new();
+        fh.put_opaque(b"fh");
+        let find = |needle: &[u8]| resolved.windows(needle.len()).any(|w| w == needle);
+        assert!(find(fh.as_bytes()));
+        assert!(find(&[&5u32.to_be_bytes()[..], &[7; 12]].concat()));
+        assert!(find(&0x1122u64.to_be_bytes()));
+        assert!(!find(&MAGIC) && !find(&CLIENTID.to_be_bytes()));
+
+        // A seqid offset moves off the harvested seqid; a damaged
+        // marker is left alone
+        let mut ahead = Vec::new();
+        ahead.extend_from_slice(&1u32.to_be_bytes());
+        ahead.extend_from_slice(&stateid(Kind::Open, 0).other);
+        let mut resolved = state.resolve(&ahead);
+        assert_eq!(&resolved[..4], &6u32.to_be_bytes());
+        ahead[5] ^= 1;
+        resolved = state.resolve(&ahead);
+        assert_eq!(resolved, ahead);
+    }
+
+    #[test]
+    fn test_layout_state_and_devices() {
+        let layout = Stateid::new(1, [5; 12]);
+        let mut state = SessionState::default();
+        state.observe(&ok_results(&[
+            (op::GETDEVICELIST, &|enc| {
+                enc.put_opaque_fixed(&[0; 16]);
+                enc.put_u32(1);
+                enc.put_opaque_fixed(&[1; 16]);
+                enc.put_bool(true);
+            }),
+            (op::LAYOUTGET, &|enc| {
+                enc.put_bool(false);
+                layout.encode(enc);
+                enc.put_u32(1);
+                enc.put_opaque_fixed(&[0; 20]);
+                enc.put_u32(layout_type::NFSV4_1_FILES);
+                enc.put_opaque(&[2; 40]);
+            }),
+        ]));
+        assert_eq!(state.layouts, [layout]);
+        assert_eq!(state.devices, [[1; 16], [2; 16]]);
+
+        let encoded = |s: Stateid| {
+            let mut enc = XdrEncoder::new();
+            s.encode(&mut enc);
+            enc.as_bytes().to_vec()
+        };
+        let resolved = state.resolve(&encoded(stateid(Kind::Layout, 0)));
+        assert_eq!(resolved, encoded(layout));
+        let mut next = deviceid(1);
+        next[15] = 1;
+        let mut expected = [1; 16];
+        expected[15] = 2;
+        assert_eq!(state.resolve(&deviceid(0)), [2; 16]);
+        assert_eq!(state.resolve(&next), expected);
+    }
+}
@@ -0,0 +1,276 @@
+//! Stateid guessing with TEST_STATEID/FREE_STATEID (RFC 8881 §18.48, §18.38)
+//!
+//! Servers build the 12-byte `other` field from things like a boot epoch,
+//! client id and a table index or counter. Guesses derived from observed
+//! stateids probe how lookups handle near misses, and any fabricated
+//! stateid that TEST_STATEID reports as valid means stateids are
+//! predictable enough for one client to act on another's state.
+
+use super::{op, status, FuzzCase, Op, Stateid};
+use crate::xdr::XdrDecoder;
+use rand::Rng;
+use std::collections::HashSet;
+
+/// Encode TEST_STATEID4args
+pub fn test_stateid(stateids: &[Stateid]) -> Op {
+    Op::new(op::TEST_STATEID, |enc| {
+        enc.put_u32(stateids.len() as u32);
+        for s in stateids {
+            s.encode(enc);
+        }
+    })
+}
+
+/// Encode FREE_STATEID4args
+pub fn free_stateid(stateid: &Stateid) -> Op {
+    Op::new(op::FREE_STATEID, |enc| stateid.encode(enc))
+}
+
+/// The per-stateid statuses of the TEST_STATEID in COMPOUND4res
+/// `results`, past the SEQUENCE and PUTROOTFH before it; `None` if it
+/// failed or the reply does not get that far
+pub fn decode_test_stateid(results: &[u8]) -> Option<Vec<u32>> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    for _ in 0..dec.get_u32().ok()? {
+        let opcode = dec.get_u32().ok()?;
+        if dec.get_u32().ok()? != status::NFS4_OK {
+            return None;
+        }
+        match opcode {
+            op::SEQUENCE => {
+                dec.get_opaque_fixed(36).ok()?;
+            }
+            op::PUTROOTFH | op::PUTFH => {}
+            op::TEST_STATEID => {
+                let n = dec.get_u32().ok()?;
+                return (0..n).map(|_| dec.get_u32().ok()).collect();
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+fn other_word(s: &Stateid, i: usize) -> u32 {
+    u32::from_be_bytes(s.other[i * 4..i * 4 + 4].try_into().unwrap())
+}
+
+fn with_other_word(s: &Stateid, i: usize, v: u32) -> Stateid {
+    let mut out = *s;
+    out.other[i * 4..i * 4 + 4].copy_from_slice(&v.to_be_bytes());
+    out
+}
+
+/// Structured guesses around the observed stateids
+///
+/// Each of the three `other` words is stepped as both a big- and
+/// little-endian counter, seqids are pushed to their edges, and words
+/// are swapped between different observed stateids.
+pub fn guesses(observed: &[Stateid]) -> Vec<Stateid> {
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    let mut push = |s: Stateid| {
+        if seen.insert(s) {
+            out.push(s);
+        }
+    };
+
+    for s in Stateid::SPECIAL {
+        push(s);
+    }
+
+    for s in observed {
+        for seqid in [
+            0,
+            s.seqid.wrapping_sub(1),
+            s.seqid.wrapping_add(1),
+            u32::MAX,
+        ] {
+            push(Stateid::new(seqid, s.other));
+        }
+        for i in 0..3 {
+            let be = other_word(s, i);
+            let le = be.swap_bytes();
+            for delta in [1u32, 2, 0x100] {
+                push(with_other_word(s, i, be.wrapping_add(delta)));
+                push(with_other_word(s, i, be.wrapping_sub(delta)));
+                push(with_other_word(s, i, le.wrapping_add(delta).swap_bytes()));
+                push(with_other_word(s, i, le.wrapping_sub(delta).swap_bytes()));
+            }
+        }
+    }
+
+    for a in observed {
+        for b in observed {
+            if a != b {
+                // Client part of one stateid, index part of another
+                let mut mixed = *a;
+                mixed.other[8..].copy_from_slice(&b.other[8..]);
+                push(mixed);
+            }
+        }
+    }
+
+    out
+}
+
+/// A guess TEST_STATEID reported valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hit {
+    /// An `other` no observed stateid has
+    Fabricated(Stateid),
+    /// An observed `other` under a seqid neither it nor 0 has; seqid 0
+    /// asks for the current generation (RFC 8881 §8.2.2)
+    Seqid(Stateid),
+}
+
+/// Continuous TEST_STATEID/FREE_STATEID sweep over fabricated stateids
+#[derive(Debug)]
+pub struct StateidSweep {
+    observed: Vec<Stateid>,
+    batch_size: usize,
+    /// Fabricated stateids the server reported as valid
+    pub hits: Vec<Stateid>,
+    /// Observed stateids under a wrong seqid the server reported as valid
+    pub seqid_hits: Vec<Stateid>,
+}
+
+impl StateidSweep {
+    pub fn new(observed: Vec<Stateid>, batch_size: usize) -> Self {
+        Self {
+            observed,
+            batch_size,
+            hits: Vec::new(),
+            seqid_hits: Vec::new(),
+        }
+    }
+
+    /// Add a stateid harvested from a reply, widening future guesses
+    pub fn observe(&mut self, stateid: Stateid) {
+        if !self.observed.contains(&stateid) {
+            self.observed.push(stateid);
+        }
+    }
+
+    /// A batch of structured guesses topped up with random stateids
+    pub fn next_batch<R: Rng + ?Sized>(&self, rng: &mut R) -> Vec<Stateid> {
+        let pool = guesses(&self.observed);
+        (0..self.batch_size)
+            .map(|_| {
+                if rng.gen_bool(0.75) {
+                    pool[rng.gen_range(0..pool.len())]
+                } else {
+                    Stateid::new(rng.gen(), rng.gen())
+                }
+            })
+            .collect()
+    }
+
+    /// TEST_STATEID over the next batch; returns the batch for `record_results`
+    pub fn test_case<R: Rng + ?Sized>(&self, rng: &mut R) -> (FuzzCase, Vec<Stateid>) {
+        let batch = self.next_batch(rng);
+        let case = FuzzCase::new(
+            format!("test_stateid_batch_{}", batch.len()),
+            vec![test_stateid(&batch)],
+        );
+        (case, batch)
+    }
+
+    /// FREE_STATEID of a random guess
+    pub fn free_case<R: Rng + ?Sized>(&self, rng: &mut R) -> FuzzCase {
+        let pool = guesses(&self.observed);
+        let target = pool[rng.gen_range(0..pool.len())];
+        FuzzCase::new("free_stateid_guess", vec![free_stateid(&target)])
+    }
+
+    /// Feed back the per-stateid status codes from a TEST_STATEID reply
+    ///
+    /// Returns the newly found guesses the server accepted: those whose
+    /// `other` was never observed, and apart from them those that only
+    /// change an observed stateid's seqid to one other than 0.
+    pub fn record_results(&mut self, batch: &[Stateid], statuses: &[u32]) -> Vec<Hit> {
+        let mut new_hits = Vec::new();
+        for (s, &st) in batch.iter().zip(statuses) {
+            if st != status::NFS4_OK
+                || s.seqid == 0
+                || self.observed.contains(s)
+                || Stateid::SPECIAL.contains(s)
+            {
+                continue;
+            }
+            let (list, hit) = match self.observed.iter().any(|o| o.other == s.other) {
+                true => (&mut self.seqid_hits, Hit::Seqid(*s)),
+                false => (&mut self.hits, Hit::Fabricated(*s)),
+            };
+            if !list.contains(s) {
+                list.push(*s);
+                new_hits.push(hit);
+            }
+        }
+        new_hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_guesses_step_counters() {
+        let s = Stateid::new(1, [0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3]);
+        let g = guesses(&[s]);
+        assert!(g.contains(&with_other_word(&s, 2, 4)));
+        assert!(g.contains(&Stateid::new(2, s.other)));
+        assert!(!g.contains(&s));
+    }
+
+    #[test]
+    fn test_record_results_flags_fabricated_only() {
+        let s = Stateid::new(1, [7; 12]);
+        let mut sweep = StateidSweep::new(vec![s], 8);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1);
+        let (case, batch) = sweep.test_case(&mut rng);
+        assert_eq!(case.ops[0].args.len(), 4 + 8 * 16);
+
+        let fabricated = Stateid::new(1, [8; 12]);
+        let newer = Stateid::new(2, [7; 12]);
+        let hits = sweep.record_results(
+            &[s, fabricated, newer, Stateid::ANONYMOUS],
+            &[status::NFS4_OK; 4],
+        );
+        assert_eq!(hits, vec![Hit::Fabricated(fabricated), Hit::Seqid(newer)]);
+        assert_eq!((sweep.hits.len(), sweep.seqid_hits.len()), (1, 1));
+        assert_eq!(batch.len(), 8);
+    }
+
+    #[test]
+    fn test_seqid_zero_is_the_current_stateid() {
+        let s = Stateid::new(3, [7; 12]);
+        let mut sweep = StateidSweep::new(vec![s], 8);
+        assert!(guesses(&[s]).contains(&Stateid::new(0, s.other)));
+        let hits = sweep.record_results(&[Stateid::new(0, s.other)], &[status::NFS4_OK]);
+        assert!(hits.is_empty());
+        assert!(sweep.hits.is_empty() && sweep.seqid_hits.is_empty());
+    }
+
+    #[test]
+    fn test_decode_test_stateid() {
+        let mut enc = crate::xdr::XdrEncoder::new();
+        for word in [status::NFS4_OK, 0, 2, op::PUTROOTFH, status::NFS4_OK] {
+            enc.put_u32(word);
+        }
+        for word in [op::TEST_STATEID, status::NFS4_OK, 2] {
+            enc.put_u32(word);
+        }
+        enc.put_u32(status::NFS4_OK);
+        enc.put_u32(status::NFS4ERR_BAD_STATEID);
+        assert_eq!(
+            decode_test_stateid(enc.as_bytes()),
+            Some(vec![status::NFS4_OK, status::NFS4ERR_BAD_STATEID])
+        );
+        assert_eq!(decode_test_stateid(&enc.as_bytes()[..24]), None);
+    }
+}
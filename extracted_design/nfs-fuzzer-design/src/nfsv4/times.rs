@@ -0,0 +1,260 @@
+//! Timestamp edges (RFC 8881 §3.3.1, §5.8.2)
+//!
+//! nfstime4 carries signed 64-bit seconds and unsigned nanoseconds, but
+//! server filesystems store far less: 32-bit seconds that wrap in 2038 or
+//! 2106, or a nanosecond field that must stay below 10^9. Setting times
+//! at those edges and reading them back shows whether the server rejects,
+//! clamps or normalizes the value, or silently corrupts it.
+
+use super::{
+    attr, bitmap_from_bits, bits_of_bitmap, getattr, put_nfstime, setattr, Fattr, FuzzCase, Op,
+    Stateid,
+};
+use crate::xdr::{XdrDecoder, XdrEncoder};
+
+/// time_how4 discriminants
+pub mod time_how {
+    pub const SET_TO_SERVER_TIME: u32 = 0;
+    pub const SET_TO_CLIENT_TIME: u32 = 1;
+}
+
+pub const NSEC_PER_SEC: u32 = 1_000_000_000;
+
+/// Seconds around the epoch and the common storage limits
+pub const EDGE_SECONDS: &[(&str, i64)] = &[
+    ("epoch", 0),
+    ("epoch_minus_1", -1),
+    ("epoch_plus_1", 1),
+    ("y2038_last", i32::MAX as i64),
+    ("y2038_wrap", i32::MAX as i64 + 1),
+    ("y1901", i32::MIN as i64),
+    ("before_y1901", i32::MIN as i64 - 1),
+    ("y2106_last", u32::MAX as i64),
+    ("y2106_wrap", u32::MAX as i64 + 1),
+    ("y2446_ext4_max", 0x3_7fff_ffff),
+    ("y9999", 253_402_300_799),
+    ("year_1", -62_135_596_800),
+    ("i64_max", i64::MAX),
+    ("i64_min", i64::MIN),
+];
+
+/// Nanoseconds: valid edges, the first invalid value, and values that are
+/// negative when read as a signed 32-bit field
+pub const EDGE_NSECONDS: &[(&str, u32)] = &[
+    ("ns0", 0),
+    ("ns_max", NSEC_PER_SEC - 1),
+    ("ns_1e9", NSEC_PER_SEC),
+    ("ns_neg_min", 0x8000_0000),
+    ("ns_neg1", u32::MAX),
+];
+
+/// settime4
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetTime {
+    Server,
+    Client(i64, u32),
+    /// Undefined discriminant with no body
+    Raw(u32),
+}
+
+impl SetTime {
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        match *self {
+            SetTime::Server => enc.put_u32(time_how::SET_TO_SERVER_TIME),
+            SetTime::Client(sec, nsec) => {
+                enc.put_u32(time_how::SET_TO_CLIENT_TIME);
+                put_nfstime(enc, sec, nsec);
+            }
+            SetTime::Raw(how) => enc.put_u32(how),
+        }
+    }
+}
+
+/// fattr4 setting any of time_access_set, time_create and time_modify_set
+pub fn time_attrs(
+    access: Option<SetTime>,
+    create: Option<(i64, u32)>,
+    modify: Option<SetTime>,
+) -> Fattr {
+    let mut bits = Vec::new();
+    let mut enc = XdrEncoder::new();
+    // Values are packed in attribute-number order
+    if let Some(t) = access {
+        bits.push(attr::TIME_ACCESS_SET);
+        t.encode(&mut enc);
+    }
+    if let Some((sec, nsec)) = create {
+        bits.push(attr::TIME_CREATE);
+        put_nfstime(&mut enc, sec, nsec);
+    }
+    if let Some(t) = modify {
+        bits.push(attr::TIME_MODIFY_SET);
+        t.encode(&mut enc);
+    }
+    Fattr {
+        mask: bitmap_from_bits(&bits),
+        vals: enc.into_bytes().to_vec(),
+    }
+}
+
+/// GETATTR for every time attribute, to read back what was stored
+pub fn get_times() -> Op {
+    getattr(&bitmap_from_bits(&[
+        attr::TIME_ACCESS,
+        attr::TIME_CREATE,
+        attr::TIME_DELTA,
+        attr::TIME_METADATA,
+        attr::TIME_MODIFY,
+    ]))
+}
+
+/// SETATTR of every edge second/nanosecond pair, each followed by a
+/// GETATTR of the times in the same compound
+pub fn timestamp_cases(stateid: &Stateid) -> Vec<FuzzCase> {
+    let mut cases = Vec::new();
+    for &(sec_label, sec) in EDGE_SECONDS {
+        for &(nsec_label, nsec) in EDGE_NSECONDS {
+            let t = SetTime::Client(sec, nsec);
+            cases.push(FuzzCase::new(
+                format!("settime_{}_{}", sec_label, nsec_label),
+                vec![
+                    setattr(stateid, &time_attrs(Some(t), None, Some(t))),
+                    get_times(),
+                ],
+            ));
+        }
+        cases.push(FuzzCase::new(
+            format!("settime_create_{}", sec_label),
+            vec![
+                setattr(stateid, &time_attrs(None, Some((sec, 0)), None)),
+                get_times(),
+            ],
+        ));
+    }
+
+    cases.push(FuzzCase::new(
+        "settime_server_then_client_wrap",
+        vec![
+            setattr(
+                stateid,
+                &time_attrs(Some(SetTime::Server), None, Some(SetTime::Server)),
+            ),
+            get_times(),
+            setattr(
+                stateid,
+                &time_attrs(None, None, Some(SetTime::Client(u32::MAX as i64 + 1, 0))),
+            ),
+            get_times(),
+        ],
+    ));
+    for how in [2, u32::MAX] {
+        cases.push(FuzzCase::new(
+            format!("settime_undefined_how_{:#x}", how),
+            vec![setattr(
+                stateid,
+                &time_attrs(Some(SetTime::Raw(how)), None, Some(SetTime::Raw(how))),
+            )],
+        ));
+    }
+    cases
+}
+
+fn get_nfstime(dec: &mut XdrDecoder<'_>) -> Option<(i64, u32)> {
+    Some((dec.get_i64().ok()?, dec.get_u32().ok()?))
+}
+
+/// The client times a SETATTR of `mask` and `vals` sets, each under the
+/// attribute it reads back as: time_access_set as time_access,
+/// time_modify_set as time_modify. `None` if the fattr4 holds anything
+/// but times or does not decode.
+pub fn set_times(mask: &[u32], vals: &[u8]) -> Option<Vec<(u32, (i64, u32))>> {
+    let mut dec = XdrDecoder::new(vals);
+    let mut set = Vec::new();
+    for bit in bits_of_bitmap(mask) {
+        let read_as = match bit {
+            attr::TIME_ACCESS_SET => attr::TIME_ACCESS,
+            attr::TIME_MODIFY_SET => attr::TIME_MODIFY,
+            attr::TIME_CREATE => {
+                set.push((bit, get_nfstime(&mut dec)?));
+                continue;
+            }
+            _ => return None,
+        };
+        match dec.get_u32().ok()? {
+            time_how::SET_TO_CLIENT_TIME => set.push((read_as, get_nfstime(&mut dec)?)),
+            time_how::SET_TO_SERVER_TIME => {}
+            _ => return None,
+        }
+    }
+    Some(set)
+}
+
+/// The times in a GETATTR reply of `mask` and `vals`, by attribute;
+/// `None` if it holds anything but times or does not decode
+pub fn read_times(mask: &[u32], vals: &[u8]) -> Option<Vec<(u32, (i64, u32))>> {
+    let mut dec = XdrDecoder::new(vals);
+    let mut read = Vec::new();
+    for bit in bits_of_bitmap(mask) {
+        match bit {
+            attr::TIME_ACCESS
+            | attr::TIME_CREATE
+            | attr::TIME_DELTA
+            | attr::TIME_METADATA
+            | attr::TIME_MODIFY => read.push((bit, get_nfstime(&mut dec)?)),
+            _ => return None,
+        }
+    }
+    Some(read)
+}
+
+/// Check a time read back after SETATTR accepted `set`
+///
+/// Rounding to the filesystem's granularity and clamping to its range are
+/// fine; an unnormalized nanosecond field, a flipped sign or a value off
+/// by a multiple of 2^32 seconds means the server corrupted the time.
+pub fn check_time(set: (i64, u32), got: (i64, u32)) -> Result<(), String> {
+    if got.1 >= NSEC_PER_SEC {
+        return Err(format!("nseconds {} not normalized", got.1));
+    }
+    let Some(secs) = set.0.checked_add((set.1 / NSEC_PER_SEC) as i64) else {
+        return Ok(());
+    };
+    if secs == got.0 {
+        return Ok(());
+    }
+    if secs != 0 && got.0 != 0 && secs.signum() != got.0.signum() {
+        return Err(format!(
+            "set {}s but read back {}s (sign flipped)",
+            secs, got.0
+        ));
+    }
+    let diff = secs.wrapping_sub(got.0);
+    if diff % (1 << 32) == 0 {
+        return Err(format!("set {}s but read back {}s (wrapped)", secs, got.0));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_attrs_packing() {
+        let a = time_attrs(Some(SetTime::Server), None, Some(SetTime::Client(1, 2)));
+        assert_eq!(a.mask, vec![0, (1 << 16) | (1 << 22)]);
+        // how (4) + how (4) + nfstime4 (12)
+        assert_eq!(a.vals.len(), 20);
+        assert_eq!(&a.vals[..4], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_check_time() {
+        assert!(check_time((5, NSEC_PER_SEC + 1), (6, 1)).is_ok());
+        assert!(check_time((5, 0), (5, NSEC_PER_SEC)).is_err());
+        assert!(check_time((i32::MAX as i64 + 1, 0), (i32::MIN as i64, 0)).is_err());
+        assert!(check_time((u32::MAX as i64 + 5, 0), (4, 0)).is_err());
+        // Clamped to a filesystem limit
+        assert!(check_time((i64::MAX - 1, 0), (0x3_7fff_ffff, 0)).is_ok());
+    }
+}
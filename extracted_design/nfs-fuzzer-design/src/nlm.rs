@@ -0,0 +1,395 @@
+//! Network Lock Manager v4 (program 100021, XNFS chapter 10)
+//!
+//! NFSv3 has no locking of its own; lockd does it beside it, in the
+//! kernel on Linux, and sees little fuzzing. A lock is named by the
+//! caller's host name, the file handle, an opaque owner handle (`oh`) and
+//! a 32-bit `svid`, usually the locking process id, and covers a 64-bit
+//! byte range. The calls here are LOCK, TEST, UNLOCK, CANCEL and GRANTED
+//! (the callback a server sends a blocked client, unsolicited here) with
+//! every field open to mutation, and [`fuzz_calls`] builds sequences that
+//! take one lock and then prod it with other owners, owner handles at and
+//! past their limits and ranges that overlap, split, touch or wrap it.
+
+use crate::rpc::{program, Program, ProgramCall};
+use crate::xdr::{XdrDecoder, XdrEncoder, XdrError};
+
+/// NLM protocol version with 64-bit offsets
+pub const VERSION: u32 = 4;
+
+/// LM_MAXSTRLEN: longest caller name
+pub const LM_MAXSTRLEN: usize = 1024;
+
+/// MAXNETOBJ_SZ: longest cookie, handle and owner handle
+pub const MAXNETOBJ_SZ: usize = 1024;
+
+/// Procedure numbers
+pub mod proc {
+    pub const NULL: u32 = 0;
+    pub const TEST: u32 = 1;
+    pub const LOCK: u32 = 2;
+    pub const CANCEL: u32 = 3;
+    pub const UNLOCK: u32 = 4;
+    pub const GRANTED: u32 = 5;
+}
+
+/// Reply statuses (nlm4_stats)
+pub mod stat {
+    pub const GRANTED: u32 = 0;
+    pub const DENIED: u32 = 1;
+    pub const DENIED_NOLOCKS: u32 = 2;
+    pub const BLOCKED: u32 = 3;
+    pub const DENIED_GRACE_PERIOD: u32 = 4;
+    pub const DEADLCK: u32 = 5;
+    pub const ROFS: u32 = 6;
+    pub const STALE_FH: u32 = 7;
+    pub const FBIG: u32 = 8;
+    pub const FAILED: u32 = 9;
+
+    pub fn name(stat: u32) -> Option<&'static str> {
+        Some(match stat {
+            GRANTED => "NLM4_GRANTED",
+            DENIED => "NLM4_DENIED",
+            DENIED_NOLOCKS => "NLM4_DENIED_NOLOCKS",
+            BLOCKED => "NLM4_BLOCKED",
+            DENIED_GRACE_PERIOD => "NLM4_DENIED_GRACE_PERIOD",
+            DEADLCK => "NLM4_DEADLCK",
+            ROFS => "NLM4_ROFS",
+            STALE_FH => "NLM4_STALE_FH",
+            FBIG => "NLM4_FBIG",
+            FAILED => "NLM4_FAILED",
+            _ => return None,
+        })
+    }
+}
+
+/// nlm4_lock: who holds what
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lock {
+    pub caller_name: Vec<u8>,
+    pub fh: Vec<u8>,
+    pub oh: Vec<u8>,
+    pub svid: i32,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Lock {
+    /// Lock on `fh` for `caller_name`'s process `svid`, the owner handle
+    /// spelled the way Linux clients do
+    pub fn new(caller_name: &[u8], fh: &[u8], svid: i32, offset: u64, len: u64) -> Self {
+        let mut oh = svid.to_be_bytes().to_vec();
+        oh.push(b'@');
+        oh.extend_from_slice(caller_name);
+        Self {
+            caller_name: caller_name.to_vec(),
+            fh: fh.to_vec(),
+            oh,
+            svid,
+            offset,
+            len,
+        }
+    }
+
+    pub fn with_oh(mut self, oh: impl Into<Vec<u8>>) -> Self {
+        self.oh = oh.into();
+        self
+    }
+
+    pub fn with_svid(mut self, svid: i32) -> Self {
+        self.svid = svid;
+        self
+    }
+
+    pub fn with_range(mut self, offset: u64, len: u64) -> Self {
+        self.offset = offset;
+        self.len = len;
+        self
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_opaque(&self.caller_name);
+        enc.put_opaque(&self.fh);
+        enc.put_opaque(&self.oh);
+        enc.put_i32(self.svid);
+        enc.put_u64(self.offset);
+        enc.put_u64(self.len);
+    }
+}
+
+/// The NLM program, at [`VERSION`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nlm;
+
+impl Program for Nlm {
+    const PROGRAM: u32 = program::NLM;
+    const VERSION: u32 = VERSION;
+}
+
+/// One NLM call: the procedure and its encoded arguments
+pub type Call = ProgramCall<Nlm>;
+
+pub fn null() -> Call {
+    Call::new(proc::NULL, |_| {})
+}
+
+/// Encode nlm4_testargs
+pub fn test(cookie: &[u8], exclusive: bool, lock: &Lock) -> Call {
+    Call::new(proc::TEST, |enc| {
+        enc.put_opaque(cookie);
+        enc.put_bool(exclusive);
+        lock.encode(enc);
+    })
+}
+
+/// Encode nlm4_lockargs
+pub fn lock(
+    cookie: &[u8],
+    block: bool,
+    exclusive: bool,
+    lock: &Lock,
+    reclaim: bool,
+    state: i32,
+) -> Call {
+    Call::new(proc::LOCK, |enc| {
+        enc.put_opaque(cookie);
+        enc.put_bool(block);
+        enc.put_bool(exclusive);
+        lock.encode(enc);
+        enc.put_bool(reclaim);
+        enc.put_i32(state);
+    })
+}
+
+/// Encode nlm4_cancargs
+pub fn cancel(cookie: &[u8], block: bool, exclusive: bool, lock: &Lock) -> Call {
+    Call::new(proc::CANCEL, |enc| {
+        enc.put_opaque(cookie);
+        enc.put_bool(block);
+        enc.put_bool(exclusive);
+        lock.encode(enc);
+    })
+}
+
+/// Encode nlm4_unlockargs
+pub fn unlock(cookie: &[u8], lock: &Lock) -> Call {
+    Call::new(proc::UNLOCK, |enc| {
+        enc.put_opaque(cookie);
+        lock.encode(enc);
+    })
+}
+
+/// Encode the nlm4_testargs of GRANTED
+pub fn granted(cookie: &[u8], exclusive: bool, lock: &Lock) -> Call {
+    Call::new(proc::GRANTED, |enc| {
+        enc.put_opaque(cookie);
+        enc.put_bool(exclusive);
+        lock.encode(enc);
+    })
+}
+
+/// The conflicting lock a denied TEST names (nlm4_holder)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Holder {
+    pub exclusive: bool,
+    pub svid: i32,
+    pub oh: Vec<u8>,
+    pub offset: u64,
+    pub len: u64,
+}
+
+/// Decode nlm4_res, the results of LOCK, CANCEL, UNLOCK and GRANTED:
+/// the cookie echoed back and the status
+pub fn decode_res(results: &[u8]) -> Result<(Vec<u8>, u32), XdrError> {
+    let mut dec = XdrDecoder::new(results);
+    let cookie = dec.get_opaque_max(MAXNETOBJ_SZ)?.to_vec();
+    Ok((cookie, dec.get_u32()?))
+}
+
+/// Decode nlm4_testres: the cookie, the status and, if denied, the
+/// holder of the conflicting lock
+pub fn decode_testres(results: &[u8]) -> Result<(Vec<u8>, u32, Option<Holder>), XdrError> {
+    let mut dec = XdrDecoder::new(results);
+    let cookie = dec.get_opaque_max(MAXNETOBJ_SZ)?.to_vec();
+    let status = dec.get_u32()?;
+    let holder = match status {
+        stat::DENIED => Some(Holder {
+            exclusive: dec.get_bool()?,
+            svid: dec.get_i32()?,
+            oh: dec.get_opaque_max(MAXNETOBJ_SZ)?.to_vec(),
+            offset: dec.get_u64()?,
+            len: dec.get_u64()?,
+        }),
+        _ => None,
+    };
+    Ok((cookie, status, holder))
+}
+
+/// Ranges to try against a lock on `[offset, offset + len)`: overlapping
+/// either end, inside it so an unlock splits it, touching it, the whole
+/// file, and ranges whose end wraps past 2^64
+pub fn ranges(offset: u64, len: u64) -> Vec<(&'static str, u64, u64)> {
+    let end = offset.saturating_add(len);
+    let mid = offset.saturating_add(len / 2);
+    vec![
+        ("same", offset, len),
+        ("overlap_head", offset.saturating_sub(1), 2),
+        ("overlap_tail", end.saturating_sub(1), 2),
+        ("inside", mid, 1),
+        ("touch_before", offset.saturating_sub(1), 1),
+        ("touch_after", end, 1),
+        ("whole_file", 0, 0),
+        ("to_eof", mid, 0),
+        ("wrapping", u64::MAX - 1, 4),
+        ("max_offset", u64::MAX, 1),
+        ("max_len", offset, u64::MAX),
+        ("signed_edge", i64::MAX as u64, 2),
+    ]
+}
+
+/// Owner handles to hold or test a lock with: empty, at and past
+/// MAXNETOBJ_SZ, near misses of `oh` and one that differs only in case
+pub fn owner_handles(oh: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+    let mut flipped = oh.to_vec();
+    if let Some(last) = flipped.last_mut() {
+        *last ^= 1;
+    }
+    let mut longer = oh.to_vec();
+    longer.push(0);
+    vec![
+        ("empty", Vec::new()),
+        ("max", vec![b'o'; MAXNETOBJ_SZ]),
+        ("oversized", vec![b'o'; MAXNETOBJ_SZ + 1]),
+        ("huge", vec![b'o'; 1 << 16]),
+        ("flipped", flipped),
+        ("nul_suffix", longer),
+        ("uppercase", oh.to_ascii_uppercase()),
+    ]
+}
+
+/// svids a server may compare, truncate or index by
+pub const SVIDS: &[i32] = &[0, 1, -1, i32::MAX, i32::MIN];
+
+/// Labelled calls against `held`: LOCK it, then TEST, LOCK, UNLOCK and
+/// CANCEL over the overlapping [`ranges`] from another owner and from
+/// the holder, every [`owner_handles`] and [`SVIDS`] variant of the
+/// holder, unsolicited GRANTEDs and a final UNLOCK of the original range
+pub fn fuzz_calls(held: &Lock) -> Vec<(String, Call)> {
+    let cookie = b"nlmfuzz".as_slice();
+    let other = held
+        .clone()
+        .with_oh(b"other".to_vec())
+        .with_svid(held.svid ^ 1);
+    let mut out = vec![(
+        "lock_held".to_string(),
+        lock(cookie, false, true, held, false, 1),
+    )];
+    for (label, offset, len) in ranges(held.offset, held.len) {
+        let theirs = other.clone().with_range(offset, len);
+        let ours = held.clone().with_range(offset, len);
+        out.push((format!("test_other_{}", label), test(cookie, true, &theirs)));
+        out.push((
+            format!("lock_other_{}", label),
+            lock(cookie, false, true, &theirs, false, 1),
+        ));
+        out.push((
+            format!("lock_blocking_other_{}", label),
+            lock(cookie, true, true, &theirs, false, 1),
+        ));
+        out.push((
+            format!("cancel_other_{}", label),
+            cancel(cookie, true, true, &theirs),
+        ));
+        out.push((
+            format!("lock_shared_self_{}", label),
+            lock(cookie, false, false, &ours, false, 1),
+        ));
+        out.push((format!("unlock_self_{}", label), unlock(cookie, &ours)));
+    }
+    for (label, oh) in owner_handles(&held.oh) {
+        let variant = held.clone().with_oh(oh);
+        out.push((format!("test_oh_{}", label), test(cookie, true, &variant)));
+        out.push((format!("unlock_oh_{}", label), unlock(cookie, &variant)));
+    }
+    for &svid in SVIDS {
+        let variant = held.clone().with_svid(svid);
+        out.push((format!("unlock_svid_{}", svid), unlock(cookie, &variant)));
+    }
+    out.push((
+        "lock_reclaim_outside_grace".to_string(),
+        lock(cookie, false, true, &other, true, 3),
+    ));
+    out.push((
+        "granted_unsolicited".to_string(),
+        granted(cookie, true, &other),
+    ));
+    out.push((
+        "granted_oversized_cookie".to_string(),
+        granted(&vec![0xc0; MAXNETOBJ_SZ + 1], true, held),
+    ));
+    out.push(("unlock_held".to_string(), unlock(cookie, held)));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+
+    #[test]
+    fn test_lock_encoding_and_replies() {
+        let held = Lock::new(b"client", b"fh", 7, 100, 50);
+        assert_eq!(held.oh, b"\0\0\0\x07@client");
+        let call = lock(b"c", true, false, &held, false, 3);
+        // cookie (8), block, exclusive (8), caller_name (12), fh (8),
+        // oh (16), svid, offset, len (20), reclaim, state (8)
+        assert_eq!(call.args.len(), 80);
+        assert_eq!(&call.args[8..12], &1u32.to_be_bytes());
+        assert_eq!(&call.args[72..], &[0, 0, 0, 0, 0, 0, 0, 3]);
+        let msg = call.message(&Identity::new(0, 0));
+        assert_eq!(&msg[16..20], &program::NLM.to_be_bytes());
+        assert_eq!(&msg[20..28], &[0, 0, 0, 4, 0, 0, 0, 2]);
+
+        let mut enc = XdrEncoder::new();
+        enc.put_opaque(b"c");
+        enc.put_u32(stat::DENIED);
+        enc.put_bool(true);
+        enc.put_i32(9);
+        enc.put_opaque(b"owner");
+        enc.put_u64(100);
+        enc.put_u64(50);
+        let (cookie, status, holder) = decode_testres(enc.as_bytes()).unwrap();
+        assert_eq!((cookie.as_slice(), status), (&b"c"[..], stat::DENIED));
+        assert_eq!(holder.unwrap().oh, b"owner");
+        assert_eq!(
+            decode_res(&enc.as_bytes()[..12]).unwrap(),
+            (b"c".to_vec(), stat::DENIED)
+        );
+        assert_eq!(
+            stat::name(stat::DENIED_GRACE_PERIOD),
+            Some("NLM4_DENIED_GRACE_PERIOD")
+        );
+    }
+
+    #[test]
+    fn test_fuzz_calls() {
+        let held = Lock::new(b"client", b"fh", 7, 100, 50);
+        let r = ranges(100, 50);
+        assert!(r.contains(&("overlap_tail", 149, 2)));
+        assert!(r.contains(&("touch_after", 150, 1)));
+        let calls = fuzz_calls(&held);
+        assert_eq!(calls.len(), 1 + r.len() * 6 + 7 * 2 + SVIDS.len() + 4);
+        assert_eq!(calls[0].1.procedure, proc::LOCK);
+        assert_eq!(calls.last().unwrap().1, unlock(b"nlmfuzz", &held));
+        let (_, granted) = calls
+            .iter()
+            .find(|(l, _)| l == "granted_unsolicited")
+            .unwrap();
+        assert_eq!(granted.procedure, proc::GRANTED);
+        // The oversized owner handle goes out as given
+        let (_, oversized) = calls
+            .iter()
+            .find(|(l, _)| l == "test_oh_oversized")
+            .unwrap();
+        assert!(oversized.args.len() > MAXNETOBJ_SZ + 1);
+    }
+}
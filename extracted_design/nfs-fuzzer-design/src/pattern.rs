@@ -0,0 +1,285 @@
+//! Write patterns whose reads can be verified
+//!
+//! Data-integrity oracles write known bytes and compare what comes back.
+//! A constant fill only shows that bytes changed; these patterns are a
+//! function of the file offset, so a read also shows where the bytes it
+//! returned were meant to go. Counter and LFSR data are cheap and dense;
+//! stamped data carries a self-checking signature every 16 bytes naming
+//! the write and the offset it was written at, so stale data from an
+//! earlier write and data from the wrong offset are told apart without
+//! any search.
+
+use serde::{Deserialize, Serialize};
+
+/// Verification granularity, and the size of one stamp
+pub const BLOCK: usize = 16;
+
+/// LFSR streams restart every sector so any offset can be generated
+pub const LFSR_SECTOR: u64 = 512;
+
+/// The bytes a write puts at each file offset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    /// Big-endian u32 words counting up from `start`, one per 4-byte
+    /// file offset
+    Counter { start: u32 },
+    /// Galois LFSR output keyed by `seed` and the sector
+    Lfsr { seed: u32 },
+    /// 16-byte signatures: `tag`, the offset, and a check word
+    Stamp { tag: u32 },
+}
+
+/// What is wrong with a run of read data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Damage {
+    /// Pattern data, but written for the file offset `from`
+    Misplaced { from: u64 },
+    /// Another write's stamps: `tag` at offset `from`
+    Stale { tag: u32, from: u64 },
+    /// All zeros where data was written
+    Zeroed,
+    /// Anything else
+    Corrupt,
+}
+
+/// A run of read data that does not match the pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mismatch {
+    pub offset: u64,
+    pub len: u64,
+    pub damage: Damage,
+}
+
+fn lfsr_step(state: u32) -> u32 {
+    // Taps 32, 22, 2, 1: maximal length
+    (state >> 1) ^ ((state & 1).wrapping_neg() & 0x8020_0003)
+}
+
+/// splitmix-style mix, so neighbouring sectors and tags start far apart
+fn mix(a: u32, b: u64) -> u32 {
+    let mut z = (u64::from(a) << 32 ^ b).wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (z ^ (z >> 31)) as u32
+}
+
+fn stamp(tag: u32, offset: u64) -> [u8; BLOCK] {
+    let mut out = [0; BLOCK];
+    out[..4].copy_from_slice(&tag.to_be_bytes());
+    out[4..12].copy_from_slice(&offset.to_be_bytes());
+    out[12..].copy_from_slice(&mix(tag, offset).to_be_bytes());
+    out
+}
+
+/// Tag and offset of a well-formed stamp
+fn parse_stamp(b: &[u8]) -> Option<(u32, u64)> {
+    let tag = u32::from_be_bytes(b.get(..4)?.try_into().ok()?);
+    let offset = u64::from_be_bytes(b.get(4..12)?.try_into().ok()?);
+    let check = u32::from_be_bytes(b.get(12..16)?.try_into().ok()?);
+    (check == mix(tag, offset) && offset % BLOCK as u64 == 0).then_some((tag, offset))
+}
+
+impl Pattern {
+    /// The `len` bytes written at `offset`
+    pub fn fill(&self, offset: u64, len: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity(len);
+        let end = offset + len as u64;
+        let (unit, first) = match self {
+            Pattern::Counter { .. } => (4, offset / 4 * 4),
+            Pattern::Lfsr { .. } => (LFSR_SECTOR, offset / LFSR_SECTOR * LFSR_SECTOR),
+            Pattern::Stamp { .. } => (BLOCK as u64, offset / BLOCK as u64 * BLOCK as u64),
+        };
+        let mut at = first;
+        while at < end {
+            let chunk = self.unit(at, unit as usize);
+            let lo = offset.saturating_sub(at) as usize;
+            let hi = (end - at).min(unit) as usize;
+            out.extend_from_slice(&chunk[lo..hi]);
+            at += unit;
+        }
+        out
+    }
+
+    /// One aligned unit of the pattern starting at `at`
+    fn unit(&self, at: u64, len: usize) -> Vec<u8> {
+        match *self {
+            Pattern::Counter { start } => {
+                start.wrapping_add((at / 4) as u32).to_be_bytes().to_vec()
+            }
+            Pattern::Lfsr { seed } => {
+                let mut state = mix(seed, at / LFSR_SECTOR) | 1;
+                (0..len)
+                    .map(|_| {
+                        state = lfsr_step(state);
+                        state as u8
+                    })
+                    .collect()
+            }
+            Pattern::Stamp { tag } => stamp(tag, at).to_vec(),
+        }
+    }
+
+    /// Where `data` was meant to go: the file offset at which this
+    /// pattern writes exactly these bytes. LFSR data is searched for
+    /// below `limit`; the other patterns decode their offset.
+    pub fn locate(&self, data: &[u8], limit: u64) -> Option<u64> {
+        if data.len() < 8 {
+            return None;
+        }
+        let candidates: Vec<u64> = match *self {
+            Pattern::Counter { start } => (0..4)
+                .filter_map(|phase| {
+                    let w = u32::from_be_bytes(data.get(phase..phase + 4)?.try_into().ok()?);
+                    let word = u64::from(w.wrapping_sub(start));
+                    (word * 4).checked_sub(phase as u64)
+                })
+                .collect(),
+            Pattern::Stamp { tag } => (0..BLOCK)
+                .filter_map(|phase| match parse_stamp(data.get(phase..)?) {
+                    Some((t, from)) if t == tag => from.checked_sub(phase as u64),
+                    _ => None,
+                })
+                .collect(),
+            Pattern::Lfsr { .. } => {
+                let space = self.fill(0, limit as usize);
+                return space
+                    .windows(data.len())
+                    .position(|w| w == data)
+                    .map(|p| p as u64);
+            }
+        };
+        candidates
+            .into_iter()
+            .find(|&from| self.fill(from, data.len()) == data)
+    }
+
+    /// Compare `data` read at `offset` with what the pattern wrote there.
+    /// Data is judged in aligned 16-byte blocks, and adjacent blocks with
+    /// the same damage are merged; `limit` bounds the LFSR search.
+    pub fn verify(&self, offset: u64, data: &[u8], limit: u64) -> Vec<Mismatch> {
+        let mut out: Vec<Mismatch> = Vec::new();
+        let expected = self.fill(offset, data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let at = offset + i as u64;
+            let next = ((at / BLOCK as u64 + 1) * BLOCK as u64 - offset) as usize;
+            let end = next.min(data.len());
+            let got = &data[i..end];
+            if got != &expected[i..end] {
+                let damage = self.diagnose(got, limit);
+                let len = got.len() as u64;
+                match out.last_mut() {
+                    Some(last) if last.offset + last.len == at && continues(last, damage) => {
+                        last.len += len
+                    }
+                    _ => out.push(Mismatch {
+                        offset: at,
+                        len,
+                        damage,
+                    }),
+                }
+            }
+            i = end;
+        }
+        out
+    }
+
+    fn diagnose(&self, got: &[u8], limit: u64) -> Damage {
+        if got.iter().all(|&b| b == 0) {
+            return Damage::Zeroed;
+        }
+        if let Some(from) = self.locate(got, limit) {
+            return Damage::Misplaced { from };
+        }
+        if let Some((tag, from)) = parse_stamp(got) {
+            return Damage::Stale { tag, from };
+        }
+        Damage::Corrupt
+    }
+}
+
+/// Whether `damage` in the block after `last` extends it
+fn continues(last: &Mismatch, damage: Damage) -> bool {
+    match (last.damage, damage) {
+        (Damage::Misplaced { from: a }, Damage::Misplaced { from: b }) => a + last.len == b,
+        (Damage::Stale { tag: t, from: a }, Damage::Stale { tag: u, from: b }) => {
+            t == u && a + last.len == b
+        }
+        (a, b) => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: [Pattern; 3] = [
+        Pattern::Counter { start: 7 },
+        Pattern::Lfsr { seed: 1 },
+        Pattern::Stamp { tag: 0xfeed },
+    ];
+
+    #[test]
+    fn test_fill_is_a_function_of_offset() {
+        for p in PATTERNS {
+            let whole = p.fill(0, 4096);
+            assert_eq!(p.fill(1000, 77), whole[1000..1077], "{:?}", p);
+            assert_eq!(p.fill(3, 1), whole[3..4]);
+            assert!(p.verify(1000, &whole[1000..3000], 4096).is_empty());
+            assert_eq!(p.locate(&whole[1234..1300], 4096), Some(1234), "{:?}", p);
+        }
+        assert_eq!(PATTERNS[0].fill(4, 4), [0, 0, 0, 8]);
+        assert_ne!(
+            Pattern::Lfsr { seed: 1 }.fill(0, 64),
+            Pattern::Lfsr { seed: 2 }.fill(0, 64)
+        );
+        assert_eq!(parse_stamp(&PATTERNS[2].fill(32, 16)), Some((0xfeed, 32)));
+    }
+
+    #[test]
+    fn test_verify_classifies_damage() {
+        for p in PATTERNS {
+            // Read at 1024: 64 bytes from 4096, 32 zeros, then garbage
+            let mut data = p.fill(1024, 256);
+            data[..64].copy_from_slice(&p.fill(4096, 64));
+            data[64..96].fill(0);
+            data[200] ^= 0x55;
+            let found = p.verify(1024, &data, 8192);
+            assert_eq!(
+                found,
+                [
+                    Mismatch {
+                        offset: 1024,
+                        len: 64,
+                        damage: Damage::Misplaced { from: 4096 }
+                    },
+                    Mismatch {
+                        offset: 1088,
+                        len: 32,
+                        damage: Damage::Zeroed
+                    },
+                    Mismatch {
+                        offset: 1216,
+                        len: 16,
+                        damage: Damage::Corrupt
+                    },
+                ],
+                "{:?}",
+                p
+            );
+        }
+
+        // An earlier write's stamps at the right offset
+        let old = Pattern::Stamp { tag: 1 }.fill(64, 48);
+        assert_eq!(
+            Pattern::Stamp { tag: 2 }.verify(64, &old, 0),
+            [Mismatch {
+                offset: 64,
+                len: 48,
+                damage: Damage::Stale { tag: 1, from: 64 }
+            }]
+        );
+    }
+}
@@ -0,0 +1,749 @@
+//! pcapng capture of campaign traffic
+//!
+//! Wireshark's NFS dissector only runs over IP, so every call and reply
+//! is written as a raw IPv4 or IPv6 packet (LINKTYPE_RAW) with real
+//! TCP or UDP framing around it. Checksums are computed, and each TCP
+//! connection opens with a three-way handshake and carries consistent
+//! sequence numbers, so the stream reassembles as if it had been
+//! sniffed. TCP payloads keep their record marks and UDP payloads go
+//! without, as on the wire. Retransmissions of one UDP call are written
+//! once.
+//!
+//! [`messages`] goes the other way, for captures of legitimate traffic
+//! taken elsewhere (`tcpdump -w` on a Linux client mount, say): it reads
+//! classic pcap or pcapng over Ethernet, Linux cooked, loopback or raw
+//! IP links, reassembles each TCP stream and splits it at its record
+//! marks, and returns every RPC message found. TCP streams are read from
+//! their first captured segment and end at the first gap, so connections
+//! the capture joined halfway through, or lost segments of, yield only
+//! what can be framed. IP fragments and IPv6 extension headers are not
+//! followed.
+
+use crate::connection::{self, Proto};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Raw IP packets, version taken from the first nibble
+pub const LINKTYPE_RAW: u16 = 101;
+
+/// Largest TCP payload put in one segment
+pub const MAX_SEGMENT: usize = 65000;
+
+mod block {
+    pub const SECTION_HEADER: u32 = 0x0A0D_0D0A;
+    pub const INTERFACE: u32 = 1;
+    pub const PACKET: u32 = 2;
+    pub const SIMPLE_PACKET: u32 = 3;
+    pub const ENHANCED_PACKET: u32 = 6;
+}
+
+mod linktype {
+    pub const NULL: u16 = 0;
+    pub const ETHERNET: u16 = 1;
+    pub const LINUX_SLL: u16 = 113;
+    pub const IPV4: u16 = 228;
+    pub const IPV6: u16 = 229;
+    pub const LINUX_SLL2: u16 = 276;
+}
+
+mod tcp_flag {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+}
+
+/// The one's-complement sum RFC 1071 checksums fold
+fn sum(data: &[u8], mut acc: u32) -> u32 {
+    for chunk in data.chunks(2) {
+        let word = match chunk {
+            [a, b] => u16::from_be_bytes([*a, *b]),
+            [a] => u16::from_be_bytes([*a, 0]),
+            _ => unreachable!(),
+        };
+        acc += u32::from(word);
+    }
+    acc
+}
+
+fn fold(mut acc: u32) -> u16 {
+    while acc > 0xffff {
+        acc = (acc & 0xffff) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// An IP packet from `src` to `dst` carrying `segment` of `protocol`,
+/// whose checksum at `checksum_at` is filled in over the pseudo-header
+fn ip_packet(
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    mut segment: Vec<u8>,
+    checksum_at: usize,
+) -> Vec<u8> {
+    let len = segment.len();
+    let (mut packet, pseudo) = match (src, dst) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            let mut header = vec![0x45, 0];
+            header.extend_from_slice(&((20 + len) as u16).to_be_bytes());
+            // Identification, don't fragment, TTL 64, protocol
+            header.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+            header.extend_from_slice(&s.octets());
+            header.extend_from_slice(&d.octets());
+            let checksum = fold(sum(&header, 0));
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            let mut pseudo = [s.octets(), d.octets()].concat();
+            pseudo.extend_from_slice(&[0, protocol]);
+            pseudo.extend_from_slice(&(len as u16).to_be_bytes());
+            (header, pseudo)
+        }
+        _ => {
+            let v6 = |ip| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let (s, d) = (v6(src).octets(), v6(dst).octets());
+            let mut header = vec![0x60, 0, 0, 0];
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+            header.extend_from_slice(&[protocol, 64]);
+            header.extend_from_slice(&s);
+            header.extend_from_slice(&d);
+            let mut pseudo = [s, d].concat();
+            pseudo.extend_from_slice(&(len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, protocol]);
+            (header, pseudo)
+        }
+    };
+    let mut checksum = fold(sum(&segment, sum(&pseudo, 0)));
+    // UDP sends a zero sum as all ones; zero means none
+    if protocol == 17 && checksum == 0 {
+        checksum = 0xffff;
+    }
+    segment[checksum_at..checksum_at + 2].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(&segment);
+    packet
+}
+
+fn tcp(src: SocketAddr, dst: SocketAddr, seq: u32, ack: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    // Five-word header, full window, checksum and urgent pointer
+    segment.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend_from_slice(payload);
+    ip_packet(src.ip(), dst.ip(), 6, segment, 16)
+}
+
+fn udp(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(8 + payload.len());
+    segment.extend_from_slice(&src.port().to_be_bytes());
+    segment.extend_from_slice(&dst.port().to_be_bytes());
+    segment.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    segment.extend_from_slice(&[0, 0]);
+    segment.extend_from_slice(payload);
+    ip_packet(src.ip(), dst.ip(), 17, segment, 6)
+}
+
+/// Next sequence numbers of one TCP connection
+#[derive(Debug, Clone, Copy)]
+struct Flow {
+    client: u32,
+    server: u32,
+}
+
+/// Writes a pcapng file of campaign traffic
+#[derive(Debug)]
+pub struct Writer<W: Write> {
+    out: W,
+    flows: HashMap<(SocketAddr, SocketAddr), Flow>,
+}
+
+impl Writer<io::BufWriter<std::fs::File>> {
+    pub fn create(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        Writer::new(io::BufWriter::new(std::fs::File::create(path)?))
+    }
+}
+
+impl<W: Write> Writer<W> {
+    /// Start a capture with its section header and one raw-IP interface
+    pub fn new(mut out: W) -> io::Result<Self> {
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&0x1A2B_3C4Du32.to_le_bytes());
+        shb.extend_from_slice(&1u16.to_le_bytes());
+        shb.extend_from_slice(&0u16.to_le_bytes());
+        // Section length unknown
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut out, block::SECTION_HEADER, &shb)?;
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+        idb.extend_from_slice(&0u16.to_le_bytes());
+        // No snap length limit
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        write_block(&mut out, block::INTERFACE, &idb)?;
+        Ok(Self {
+            out,
+            flows: HashMap::new(),
+        })
+    }
+
+    fn packet(&mut self, at: SystemTime, packet: &[u8]) -> io::Result<()> {
+        // Microseconds, the default timestamp resolution
+        let micros = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let mut epb = Vec::with_capacity(20 + packet.len() + 3);
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(micros as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        epb.extend_from_slice(packet);
+        epb.resize(epb.len().next_multiple_of(4), 0);
+        write_block(&mut self.out, block::ENHANCED_PACKET, &epb)
+    }
+
+    /// The flow of a connection, opened with a handshake if it is new
+    fn flow(&mut self, at: SystemTime, client: SocketAddr, server: SocketAddr) -> io::Result<Flow> {
+        if let Some(flow) = self.flows.get(&(client, server)) {
+            return Ok(*flow);
+        }
+        let flow = Flow {
+            client: 1,
+            server: 1,
+        };
+        self.packet(at, &tcp(client, server, 0, 0, tcp_flag::SYN, &[]))?;
+        self.packet(
+            at,
+            &tcp(server, client, 0, 1, tcp_flag::SYN | tcp_flag::ACK, &[]),
+        )?;
+        self.packet(at, &tcp(client, server, 1, 1, tcp_flag::ACK, &[]))?;
+        self.flows.insert((client, server), flow);
+        Ok(flow)
+    }
+
+    fn send(
+        &mut self,
+        at: SystemTime,
+        proto: Proto,
+        from: SocketAddr,
+        to: SocketAddr,
+        payload: &[u8],
+        reply: bool,
+    ) -> io::Result<()> {
+        if proto == Proto::Udp {
+            return self.packet(at, &udp(from, to, payload));
+        }
+        let (client, server) = if reply { (to, from) } else { (from, to) };
+        let mut flow = self.flow(at, client, server)?;
+        let (seq, ack) = if reply {
+            (&mut flow.server, flow.client)
+        } else {
+            (&mut flow.client, flow.server)
+        };
+        for chunk in payload.chunks(MAX_SEGMENT) {
+            let flags = tcp_flag::PSH | tcp_flag::ACK;
+            self.packet(at, &tcp(from, to, *seq, ack, flags, chunk))?;
+            *seq = seq.wrapping_add(chunk.len() as u32);
+        }
+        self.flows.insert((client, server), flow);
+        Ok(())
+    }
+
+    /// A record-marked call `client` sent at `at`; over UDP it goes
+    /// without its mark, and one that is not a well-formed record was
+    /// never sent
+    pub fn call(
+        &mut self,
+        at: SystemTime,
+        proto: Proto,
+        client: SocketAddr,
+        server: SocketAddr,
+        msg: &[u8],
+    ) -> io::Result<()> {
+        match proto {
+            Proto::Tcp => self.send(at, proto, client, server, msg, false),
+            Proto::Udp => match connection::unmark(msg) {
+                Ok(datagram) => self.send(at, proto, client, server, &datagram, false),
+                Err(_) => Ok(()),
+            },
+        }
+    }
+
+    /// A reply `client` received at `at`, without any record mark
+    pub fn reply(
+        &mut self,
+        at: SystemTime,
+        proto: Proto,
+        client: SocketAddr,
+        server: SocketAddr,
+        reply: &[u8],
+    ) -> io::Result<()> {
+        match proto {
+            Proto::Tcp => {
+                let mut record = (0x8000_0000 | reply.len() as u32).to_be_bytes().to_vec();
+                record.extend_from_slice(reply);
+                self.send(at, proto, server, client, &record, true)
+            }
+            Proto::Udp => self.send(at, proto, server, client, reply, true),
+        }
+    }
+
+    /// The end of a TCP connection, closed by the client
+    pub fn close(
+        &mut self,
+        at: SystemTime,
+        client: SocketAddr,
+        server: SocketAddr,
+    ) -> io::Result<()> {
+        let Some(flow) = self.flows.remove(&(client, server)) else {
+            return Ok(());
+        };
+        let fin = tcp_flag::FIN | tcp_flag::ACK;
+        self.packet(at, &tcp(client, server, flow.client, flow.server, fin, &[]))?;
+        let next = flow.client.wrapping_add(1);
+        self.packet(at, &tcp(server, client, flow.server, next, fin, &[]))?;
+        let last = flow.server.wrapping_add(1);
+        self.packet(at, &tcp(client, server, next, last, tcp_flag::ACK, &[]))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// A pcapng block: type, total length, body, total length again
+fn write_block(out: &mut impl Write, kind: u32, body: &[u8]) -> io::Result<()> {
+    let len = (12 + body.len()) as u32;
+    out.write_all(&kind.to_le_bytes())?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(body)?;
+    out.write_all(&len.to_le_bytes())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PcapError {
+    #[error("not a pcap or pcapng capture")]
+    Format,
+    #[error("malformed block at byte {0}")]
+    Block(usize),
+}
+
+/// One captured frame and the link type of its interface
+struct Frame<'a> {
+    linktype: u16,
+    data: &'a [u8],
+    /// Bytes cut off by the snap length
+    truncated: bool,
+}
+
+fn u16_at(data: &[u8], at: usize, le: bool) -> Option<u16> {
+    let b: [u8; 2] = data.get(at..at + 2)?.try_into().ok()?;
+    Some(if le {
+        u16::from_le_bytes(b)
+    } else {
+        u16::from_be_bytes(b)
+    })
+}
+
+fn u32_at(data: &[u8], at: usize, le: bool) -> Option<u32> {
+    let b: [u8; 4] = data.get(at..at + 4)?.try_into().ok()?;
+    Some(if le {
+        u32::from_le_bytes(b)
+    } else {
+        u32::from_be_bytes(b)
+    })
+}
+
+/// Frames of a classic pcap file; a record cut short ends the capture
+fn pcap_frames(capture: &[u8]) -> Result<Vec<Frame<'_>>, PcapError> {
+    let le = match capture.get(..4) {
+        Some([0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1]) => true,
+        Some([0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d]) => false,
+        _ => return Err(PcapError::Format),
+    };
+    let linktype = u32_at(capture, 20, le).ok_or(PcapError::Format)? as u16;
+    let mut frames = Vec::new();
+    let mut at = 24;
+    while let (Some(caplen), Some(len)) =
+        (u32_at(capture, at + 8, le), u32_at(capture, at + 12, le))
+    {
+        let start = at + 16;
+        let Some(data) = capture.get(start..start + caplen as usize) else {
+            break;
+        };
+        frames.push(Frame {
+            linktype,
+            data,
+            truncated: caplen < len,
+        });
+        at = start + caplen as usize;
+    }
+    Ok(frames)
+}
+
+/// Frames of a pcapng file, every section and interface of it
+fn pcapng_frames(capture: &[u8]) -> Result<Vec<Frame<'_>>, PcapError> {
+    let mut frames = Vec::new();
+    let mut interfaces = Vec::new();
+    let mut le = true;
+    let mut at = 0;
+    while at + 12 <= capture.len() {
+        if capture[at..at + 4] == block::SECTION_HEADER.to_le_bytes() {
+            le = match capture.get(at + 8..at + 12) {
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => true,
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => false,
+                _ => return Err(PcapError::Block(at)),
+            };
+            interfaces.clear();
+        }
+        let kind = u32_at(capture, at, le).ok_or(PcapError::Block(at))?;
+        let len = u32_at(capture, at + 4, le).ok_or(PcapError::Block(at))? as usize;
+        if len < 12 || !len.is_multiple_of(4) {
+            return Err(PcapError::Block(at));
+        }
+        let Some(body) = capture.get(at + 8..at + len - 4) else {
+            break;
+        };
+        let mut push = |interface: usize, caplen: usize, len: usize, offset: usize| {
+            let data = body.get(offset..offset + caplen)?;
+            let linktype = *interfaces.get(interface)?;
+            frames.push(Frame {
+                linktype,
+                data,
+                truncated: caplen < len,
+            });
+            Some(())
+        };
+        match kind {
+            block::INTERFACE => interfaces.push(u16_at(body, 0, le).ok_or(PcapError::Block(at))?),
+            block::ENHANCED_PACKET => {
+                if let (Some(interface), Some(caplen), Some(len)) = (
+                    u32_at(body, 0, le),
+                    u32_at(body, 12, le),
+                    u32_at(body, 16, le),
+                ) {
+                    push(interface as usize, caplen as usize, len as usize, 20);
+                }
+            }
+            block::SIMPLE_PACKET => {
+                if let Some(len) = u32_at(body, 0, le) {
+                    let caplen = (len as usize).min(body.len() - 4);
+                    push(0, caplen, len as usize, 4);
+                }
+            }
+            block::PACKET => {
+                if let (Some(interface), Some(caplen), Some(len)) = (
+                    u16_at(body, 0, le),
+                    u32_at(body, 12, le),
+                    u32_at(body, 16, le),
+                ) {
+                    push(interface as usize, caplen as usize, len as usize, 20);
+                }
+            }
+            _ => {}
+        }
+        at += len;
+    }
+    Ok(frames)
+}
+
+/// The IP packet inside a frame
+fn ip_of<'a>(frame: &Frame<'a>) -> Option<&'a [u8]> {
+    let data = frame.data;
+    let (mut ethertype, mut rest) = match frame.linktype {
+        LINKTYPE_RAW | linktype::IPV4 | linktype::IPV6 => return Some(data),
+        // A host-order address family the version nibble stands in for
+        linktype::NULL => return data.get(4..),
+        linktype::ETHERNET => (u16_at(data, 12, false)?, data.get(14..)?),
+        linktype::LINUX_SLL => (u16_at(data, 14, false)?, data.get(16..)?),
+        linktype::LINUX_SLL2 => (u16_at(data, 0, false)?, data.get(20..)?),
+        _ => return None,
+    };
+    // 802.1Q and 802.1ad tags
+    while matches!(ethertype, 0x8100 | 0x88a8) {
+        ethertype = u16_at(rest, 2, false)?;
+        rest = rest.get(4..)?;
+    }
+    matches!(ethertype, 0x0800 | 0x86dd).then_some(rest)
+}
+
+/// Addresses, protocol and payload of an IP packet that is not a
+/// fragment; the payload is cut short if the frame was
+fn transport_of(packet: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 => {
+            let header = usize::from(packet[0] & 0x0f) * 4;
+            let total = usize::from(u16_at(packet, 2, false)?);
+            // More fragments, or an offset
+            if u16_at(packet, 6, false)? & 0x3fff != 0 || header < 20 {
+                return None;
+            }
+            let src: [u8; 4] = packet.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            let payload = packet.get(header..total.max(header).min(packet.len()))?;
+            Some((src.into(), dst.into(), packet[9], payload))
+        }
+        6 => {
+            let len = usize::from(u16_at(packet, 4, false)?);
+            let src: [u8; 16] = packet.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            let payload = packet.get(40..(40 + len).min(packet.len()))?;
+            Some((src.into(), dst.into(), packet[6], payload))
+        }
+        _ => None,
+    }
+}
+
+/// Segments of one direction of a TCP connection
+#[derive(Debug, Default)]
+struct Stream {
+    /// Sequence number of the first byte
+    start: Option<u32>,
+    segments: Vec<(u32, Vec<u8>)>,
+}
+
+impl Stream {
+    /// The bytes from the start up to the first gap
+    fn reassemble(mut self) -> Vec<u8> {
+        let Some(start) = self.start else {
+            return Vec::new();
+        };
+        let mut bytes = Vec::new();
+        let mut segments: Vec<(u32, Vec<u8>)> = self
+            .segments
+            .drain(..)
+            .map(|(seq, data)| (seq.wrapping_sub(start), data))
+            // Retransmissions of what came before the start
+            .filter(|(offset, _)| *offset < 1 << 31)
+            .collect();
+        segments.sort_by_key(|(offset, _)| *offset);
+        for (offset, data) in segments {
+            let offset = offset as usize;
+            if offset > bytes.len() {
+                break;
+            }
+            if let Some(new) = data.get(bytes.len() - offset..) {
+                bytes.extend_from_slice(new);
+            }
+        }
+        bytes
+    }
+}
+
+/// The whole records of a record-marked stream
+fn records(stream: &[u8]) -> Vec<Vec<u8>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut at = 0;
+    while let Some(mark) = u32_at(stream, at, false) {
+        let len = (mark & 0x7fff_ffff) as usize;
+        let Some(fragment) = stream.get(at + 4..at + 4 + len) else {
+            break;
+        };
+        record.extend_from_slice(fragment);
+        if mark & 0x8000_0000 != 0 {
+            records.push(std::mem::take(&mut record));
+        }
+        at += 4 + len;
+    }
+    records
+}
+
+/// Every RPC message, calls and replies alike, carried over TCP or UDP
+/// in `capture`, without record marks. UDP datagrams come in capture
+/// order and TCP records stream by stream after them.
+pub fn messages(capture: &[u8]) -> Result<Vec<Vec<u8>>, PcapError> {
+    let frames = match capture.get(..4) {
+        Some(magic) if magic == block::SECTION_HEADER.to_le_bytes() => pcapng_frames(capture)?,
+        _ => pcap_frames(capture)?,
+    };
+    let mut datagrams = Vec::new();
+    let mut streams: HashMap<(SocketAddr, SocketAddr), Stream> = HashMap::new();
+    let mut finished = Vec::new();
+    for frame in &frames {
+        let Some((src, dst, protocol, payload)) = ip_of(frame).and_then(transport_of) else {
+            continue;
+        };
+        let (Some(sport), Some(dport)) = (u16_at(payload, 0, false), u16_at(payload, 2, false))
+        else {
+            continue;
+        };
+        let key = (SocketAddr::new(src, sport), SocketAddr::new(dst, dport));
+        match protocol {
+            17 => {
+                let len = usize::from(u16_at(payload, 4, false).unwrap_or(0));
+                if let Some(datagram) = payload.get(8..len.max(8)) {
+                    if !frame.truncated && !datagram.is_empty() {
+                        datagrams.push(datagram.to_vec());
+                    }
+                }
+            }
+            6 => {
+                let (Some(seq), Some(&words), Some(&flags)) =
+                    (u32_at(payload, 4, false), payload.get(12), payload.get(13))
+                else {
+                    continue;
+                };
+                let data = payload
+                    .get(usize::from(words >> 4) * 4..)
+                    .unwrap_or_default();
+                if flags & tcp_flag::SYN != 0 {
+                    // A new connection on the same ports ends the old one
+                    if let Some(old) = streams.remove(&key) {
+                        finished.push(old);
+                    }
+                    let stream = streams.entry(key).or_default();
+                    stream.start = Some(seq.wrapping_add(1));
+                    continue;
+                }
+                if data.is_empty() {
+                    continue;
+                }
+                let stream = streams.entry(key).or_default();
+                stream.start.get_or_insert(seq);
+                stream.segments.push((seq, data.to_vec()));
+            }
+            _ => {}
+        }
+    }
+    let mut keys: Vec<_> = streams.keys().copied().collect();
+    keys.sort();
+    finished.extend(keys.into_iter().filter_map(|k| streams.remove(&k)));
+    for stream in finished {
+        datagrams.extend(records(&stream.reassemble()));
+    }
+    Ok(datagrams)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bodies of the enhanced packet blocks in `capture`
+    fn packets(capture: &[u8]) -> Vec<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut rest = capture;
+        while !rest.is_empty() {
+            let kind = u32::from_le_bytes(rest[..4].try_into().unwrap());
+            let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+            assert_eq!(rest[len - 4..len], rest[4..8]);
+            if kind == block::ENHANCED_PACKET {
+                let caplen = u32::from_le_bytes(rest[20..24].try_into().unwrap()) as usize;
+                out.push(rest[28..28 + caplen].to_vec());
+            }
+            rest = &rest[len..];
+        }
+        out
+    }
+
+    #[test]
+    fn test_tcp_connection_framing() {
+        let client: SocketAddr = "10.0.0.1:876".parse().unwrap();
+        let server: SocketAddr = "10.0.0.2:2049".parse().unwrap();
+        let mut w = Writer::new(Vec::new()).unwrap();
+        let at = UNIX_EPOCH;
+        w.call(at, Proto::Tcp, client, server, &[0x80, 0, 0, 4, 1, 2, 3, 4])
+            .unwrap();
+        w.reply(at, Proto::Tcp, client, server, &[1, 2, 3, 4, 5])
+            .unwrap();
+        w.close(at, client, server).unwrap();
+        let capture = w.into_inner();
+        assert_eq!(
+            u32::from_le_bytes(capture[..4].try_into().unwrap()),
+            block::SECTION_HEADER
+        );
+        let packets = packets(&capture);
+        // Handshake, call, reply, teardown
+        assert_eq!(packets.len(), 3 + 2 + 3);
+        for p in &packets {
+            assert_eq!(fold(sum(&p[..20], 0)), 0, "IPv4 header checksum");
+            let mut pseudo = p[12..20].to_vec();
+            pseudo.extend_from_slice(&[0, 6]);
+            pseudo.extend_from_slice(&((p.len() - 20) as u16).to_be_bytes());
+            assert_eq!(fold(sum(&p[20..], sum(&pseudo, 0))), 0, "TCP checksum");
+        }
+        let seq = |p: &[u8]| u32::from_be_bytes(p[24..28].try_into().unwrap());
+        let ack = |p: &[u8]| u32::from_be_bytes(p[28..32].try_into().unwrap());
+        let (call, reply, fin) = (&packets[3], &packets[4], &packets[5]);
+        assert_eq!(&call[40..], [0x80, 0, 0, 4, 1, 2, 3, 4]);
+        assert_eq!(&reply[40..44], [0x80, 0, 0, 5]);
+        assert_eq!((seq(call), ack(call)), (1, 1));
+        assert_eq!((seq(reply), ack(reply)), (1, 9));
+        assert_eq!((seq(fin), ack(fin)), (9, 10));
+    }
+
+    #[test]
+    fn test_udp_over_ipv6() {
+        let client: SocketAddr = "[fd00::1]:900".parse().unwrap();
+        let server: SocketAddr = "[fd00::2]:2049".parse().unwrap();
+        let mut w = Writer::new(Vec::new()).unwrap();
+        let mut msg = vec![0x80, 0, 0, 7];
+        msg.extend_from_slice(&[9; 7]);
+        w.call(UNIX_EPOCH, Proto::Udp, client, server, &msg)
+            .unwrap();
+        let packets = packets(&w.into_inner());
+        assert_eq!(packets.len(), 1);
+        let p = &packets[0];
+        assert_eq!((p[0] >> 4, p[6]), (6, 17));
+        assert_eq!(u16::from_be_bytes([p[4], p[5]]), 8 + 7);
+        let mut pseudo = p[8..40].to_vec();
+        pseudo.extend_from_slice(&15u32.to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, 17]);
+        assert_eq!(fold(sum(&p[40..], sum(&pseudo, 0))), 0, "UDP checksum");
+        assert_eq!(&p[48..], [9; 7]);
+    }
+
+    #[test]
+    fn test_messages_from_classic_pcap() {
+        let client: SocketAddr = "10.0.0.1:876".parse().unwrap();
+        let server: SocketAddr = "10.0.0.2:2049".parse().unwrap();
+        // Little-endian microsecond pcap over Ethernet
+        let mut capture = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        capture.extend_from_slice(&[0; 8]);
+        capture.extend_from_slice(&65535u32.to_le_bytes());
+        capture.extend_from_slice(&u32::from(linktype::ETHERNET).to_le_bytes());
+        let mut frame = |packet: Vec<u8>, vlan: bool| {
+            let mut data = vec![0; 12];
+            if vlan {
+                data.extend_from_slice(&[0x81, 0, 0, 5]);
+            }
+            data.extend_from_slice(&[0x08, 0]);
+            data.extend_from_slice(&packet);
+            capture.extend_from_slice(&[0; 8]);
+            capture.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            capture.extend_from_slice(&data);
+        };
+        // Two records in two fragments each, sent in three
+        // segments that arrive out of order with a retransmission
+        let stream = [
+            &[0, 0, 0, 3, 1, 2, 3, 0x80, 0, 0, 0][..],
+            &[0, 0, 0, 2, 4, 5, 0x80, 0, 0, 1, 6][..],
+        ]
+        .concat();
+        let (a, b, c) = (&stream[..5], &stream[5..13], &stream[13..]);
+        let flags = tcp_flag::PSH | tcp_flag::ACK;
+        frame(tcp(client, server, 99, 0, tcp_flag::SYN, &[]), false);
+        frame(tcp(client, server, 100, 1, flags, a), true);
+        frame(tcp(client, server, 113, 1, flags, c), false);
+        frame(tcp(client, server, 105, 1, flags, b), false);
+        frame(tcp(client, server, 100, 1, flags, a), false);
+        frame(udp(client, server, &[7; 12]), false);
+        // Half a record at the end of the stream
+        frame(tcp(server, client, 1, 1, flags, &[0x80, 0, 0, 9, 1]), false);
+        // UDP first, then the TCP stream's records
+        let found = messages(&capture).unwrap();
+        assert_eq!(found, [vec![7; 12], vec![1, 2, 3], vec![4, 5, 6]]);
+        assert!(matches!(messages(b"not a capture"), Err(PcapError::Format)));
+    }
+}
@@ -0,0 +1,505 @@
+//! Synthetic trees on the export
+//!
+//! Stateful strategies only reach the interesting paths of a server when
+//! there is something to walk: directories several levels deep, files
+//! of awkward sizes, names a client would never produce. A [`Shape`] and
+//! a seed make a [`plan`] of such a tree, the same plan for the same seed
+//! on every run, and [`populate`] creates it over v3 under one directory
+//! of the export. Names are plain (`d0`, `f1`, `l`) unless drawn weird:
+//! 255 bytes long, with spaces or control characters, a leading dash or
+//! dot, invalid UTF-8, or the same word composed and decomposed. Every
+//! directory gets one symbolic link, to a file beside it, up, or to
+//! nothing. The bytes of each file follow from the seed and its path
+//! (see [`contents`]), so a later read can tell the server returned what
+//! was written. Populating again over an existing tree looks up what is
+//! there and rewrites the files, leaving the tree as planned.
+
+use crate::auth::Identity;
+use crate::connection::{ConnectionError, Proto, Timeouts, Transport};
+use crate::mount::{self, MountError};
+use crate::nfsv3::{self, stable_how, CreateHow, Sattr3};
+use crate::rpc::{program, RpcError, RpcReply};
+use crate::xdr::XdrDecoder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use thiserror::Error;
+
+/// Directory of the export the tree goes in, by default
+pub const ROOT: &str = "nfs-fuzzer-tree";
+
+/// Bytes per WRITE
+const CHUNK: usize = 32 * 1024;
+
+/// Sizes files are drawn from besides a random one: empty, one byte and
+/// either side of a page
+const SIZES: &[u64] = &[0, 1, 4095, 4096, 4097, 65536];
+
+#[derive(Error, Debug)]
+pub enum PopulateError {
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Mount(#[from] MountError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error("no MOUNT v3 service registered with portmap on {0}")]
+    NoMountd(IpAddr),
+    #[error("{op} {path}: NFS3ERR_{status}")]
+    Status {
+        op: &'static str,
+        path: String,
+        status: u32,
+    },
+    #[error("{op} {path}: reply does not decode")]
+    Decode { op: &'static str, path: String },
+}
+
+/// How big and how strange a tree to make
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shape {
+    /// Levels of directories below the tree's own
+    pub depth: u32,
+    /// Directories in each directory above the last level
+    pub fanout: u32,
+    /// Files in each directory
+    pub files: u32,
+    /// Largest file, in bytes
+    pub max_size: u64,
+    /// Probability each name is a weird one
+    pub weird: f64,
+}
+
+impl Default for Shape {
+    fn default() -> Self {
+        Self {
+            depth: 3,
+            fanout: 3,
+            files: 4,
+            max_size: 1 << 20,
+            weird: 0.2,
+        }
+    }
+}
+
+/// What a node of the tree is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Dir,
+    File { size: u64 },
+    Symlink { target: Vec<u8> },
+}
+
+/// One node, by its path from the tree's directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub path: Vec<Vec<u8>>,
+    pub kind: Kind,
+}
+
+/// `path` for messages, bytes outside printable ASCII escaped
+pub fn display(path: &[Vec<u8>]) -> String {
+    path.iter()
+        .map(|c| c.escape_ascii().to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The `i`th name starting with `prefix`, made weird in one of the ways
+/// servers get wrong if `weird` says so
+fn name(rng: &mut StdRng, prefix: &str, i: u32, weird: f64) -> Vec<u8> {
+    let plain = format!("{}{}", prefix, i);
+    if !rng.gen_bool(weird.clamp(0.0, 1.0)) {
+        return plain.into_bytes();
+    }
+    let mut name = match rng.gen_range(0..7) {
+        0 => format!("{}-", plain).into_bytes(),
+        1 => format!("{} with  spaces ", plain).into_bytes(),
+        2 => format!("-{}", plain).into_bytes(),
+        3 => format!(".{}", plain).into_bytes(),
+        4 => [plain.as_bytes(), b"-\xff\xfe\xc0\x80"].concat(),
+        5 => format!("{}-caf\u{e9}", plain).into_bytes(),
+        _ => format!("{}-\x01\t\x7f", plain).into_bytes(),
+    };
+    if name.ends_with(b"-") {
+        // NAME_MAX on most servers
+        name.resize(255, b'x');
+    }
+    if rng.gen_bool(0.5) && name.ends_with("\u{e9}".as_bytes()) {
+        // The same name decomposed
+        name.truncate(name.len() - 2);
+        name.extend_from_slice("e\u{301}".as_bytes());
+    }
+    name
+}
+
+/// The size of a file: one of [`SIZES`] or random, at most `max`
+fn size(rng: &mut StdRng, max: u64) -> u64 {
+    let size = match rng.gen_range(0..=SIZES.len()) {
+        i if i < SIZES.len() => SIZES[i],
+        _ => rng.gen_range(0..=max),
+    };
+    size.min(max)
+}
+
+/// The nodes of the tree `shape` and `seed` make, each directory before
+/// what is in it
+pub fn plan(shape: &Shape, seed: u64) -> Vec<Node> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut nodes = Vec::new();
+    fill(&mut rng, shape, &[], 0, &mut nodes);
+    nodes
+}
+
+fn fill(rng: &mut StdRng, shape: &Shape, dir: &[Vec<u8>], level: u32, nodes: &mut Vec<Node>) {
+    let child = |name: Vec<u8>| [dir, &[name]].concat();
+    let mut files = Vec::new();
+    for i in 0..shape.files {
+        let file = name(rng, "f", i, shape.weird);
+        files.push(file.clone());
+        nodes.push(Node {
+            path: child(file),
+            kind: Kind::File {
+                size: size(rng, shape.max_size),
+            },
+        });
+    }
+    let target = match rng.gen_range(0..3) {
+        0 if !files.is_empty() => files[rng.gen_range(0..files.len())].clone(),
+        1 => b"..".to_vec(),
+        _ => b"missing".to_vec(),
+    };
+    nodes.push(Node {
+        path: child(b"l".to_vec()),
+        kind: Kind::Symlink { target },
+    });
+    if level == shape.depth {
+        return;
+    }
+    for i in 0..shape.fanout {
+        let path = child(name(rng, "d", i, shape.weird));
+        nodes.push(Node {
+            path: path.clone(),
+            kind: Kind::Dir,
+        });
+        fill(rng, shape, &path, level + 1, nodes);
+    }
+}
+
+/// `len` bytes from `offset` of the file at `path` in the tree of `seed`
+pub fn contents(seed: u64, path: &[Vec<u8>], offset: u64, len: usize) -> Vec<u8> {
+    // FNV-1a of the path, then splitmix64 of each word's index
+    let mut key = seed ^ 0xcbf2_9ce4_8422_2325;
+    for byte in path.join(&b'/') {
+        key = (key ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3);
+    }
+    let word = |index: u64| {
+        let mut z = key.wrapping_add(index.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)).to_be_bytes()
+    };
+    (offset..offset + len as u64)
+        .map(|at| word(at / 8)[(at % 8) as usize])
+        .collect()
+}
+
+/// What [`populate`] made
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub dirs: u64,
+    pub files: u64,
+    pub symlinks: u64,
+    pub bytes: u64,
+    /// Nodes that were there already
+    pub existing: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} directories, {} files ({} bytes), {} symlinks; {} already there",
+            self.dirs, self.files, self.bytes, self.symlinks, self.existing
+        )
+    }
+}
+
+/// The root handle of `export` on `target`'s host, from the MOUNT service
+/// portmap there knows of
+pub async fn mount(
+    proto: Proto,
+    target: SocketAddr,
+    export: &str,
+    timeouts: Timeouts,
+    identity: &Identity,
+) -> Result<Vec<u8>, PopulateError> {
+    let host = target.ip();
+    let mut portmap = Transport::connect(proto, SocketAddr::new(host, 111), timeouts).await?;
+    let port = portmap
+        .getport(program::MOUNT, mount::VERSION)
+        .await?
+        .ok_or(PopulateError::NoMountd(host))?;
+    let mut mountd = Transport::connect(proto, SocketAddr::new(host, port), timeouts).await?;
+    let reply = mountd
+        .call(&mount::mnt(export.as_bytes()).message(identity))
+        .await?;
+    Ok(mount::root_filehandle(&reply)?.fh)
+}
+
+/// Status and, if there is one, the handle of a MKDIR, CREATE or SYMLINK
+/// reply's results: nfsstat3, then post_op_fh3 when it succeeded
+pub fn created(results: &[u8]) -> Option<(u32, Option<Vec<u8>>)> {
+    let mut dec = XdrDecoder::new(results);
+    let status = dec.get_u32().ok()?;
+    if status != nfsv3::stat::OK || !dec.get_bool().ok()? {
+        return Some((status, None));
+    }
+    let fh = dec.get_opaque_max(nfsv3::FHSIZE).ok()?;
+    Some((status, Some(fh.to_vec())))
+}
+
+/// Status and, if it succeeded, the handle of LOOKUP results
+pub fn looked_up(results: &[u8]) -> Option<(u32, Option<Vec<u8>>)> {
+    let mut dec = XdrDecoder::new(results);
+    let status = dec.get_u32().ok()?;
+    if status != nfsv3::stat::OK {
+        return Some((status, None));
+    }
+    let fh = dec.get_opaque_max(nfsv3::FHSIZE).ok()?;
+    Some((status, Some(fh.to_vec())))
+}
+
+/// Sends the calls of one tree
+struct Populator<'a> {
+    conn: &'a mut Transport,
+    identity: &'a Identity,
+    summary: Summary,
+}
+
+impl Populator<'_> {
+    /// The NFS results of `call`, the first word being its status
+    async fn call(
+        &mut self,
+        call: nfsv3::Call,
+        op: &'static str,
+        path: &[Vec<u8>],
+    ) -> Result<Vec<u8>, PopulateError> {
+        let reply = self.conn.call(&call.message(self.identity)).await?;
+        let results = RpcReply::parse(&reply)?.into_results()?;
+        if results.len() < 4 {
+            return Err(PopulateError::Decode {
+                op,
+                path: display(path),
+            });
+        }
+        Ok(results.to_vec())
+    }
+
+    /// The handle of `path`, made by `call` in `dir` or, if it exists
+    /// already, looked up there
+    async fn make(
+        &mut self,
+        call: nfsv3::Call,
+        op: &'static str,
+        dir: &[u8],
+        path: &[Vec<u8>],
+    ) -> Result<Vec<u8>, PopulateError> {
+        let name = path.last().map_or(&[][..], Vec::as_slice);
+        let results = self.call(call, op, path).await?;
+        let decode = || PopulateError::Decode {
+            op,
+            path: display(path),
+        };
+        match created(&results).ok_or_else(decode)? {
+            (nfsv3::stat::OK, Some(fh)) => return Ok(fh),
+            (nfsv3::stat::OK, None) => {}
+            (nfsv3::stat::EXIST, _) => self.summary.existing += 1,
+            (status, _) => {
+                return Err(PopulateError::Status {
+                    op,
+                    path: display(path),
+                    status,
+                })
+            }
+        }
+        let results = self.call(nfsv3::lookup(dir, name), "LOOKUP", path).await?;
+        match looked_up(&results).ok_or_else(decode)? {
+            (_, Some(fh)) => Ok(fh),
+            (status, None) => Err(PopulateError::Status {
+                op: "LOOKUP",
+                path: display(path),
+                status,
+            }),
+        }
+    }
+
+    async fn write(
+        &mut self,
+        fh: &[u8],
+        seed: u64,
+        path: &[Vec<u8>],
+        size: u64,
+    ) -> Result<(), PopulateError> {
+        let mut offset = 0;
+        while offset < size {
+            let len = CHUNK.min((size - offset) as usize);
+            let data = contents(seed, path, offset, len);
+            let call = nfsv3::write(fh, offset, len as u32, stable_how::FILE_SYNC, &data);
+            let results = self.call(call, "WRITE", path).await?;
+            let status = u32::from_be_bytes(results[..4].try_into().unwrap());
+            if status != nfsv3::stat::OK {
+                return Err(PopulateError::Status {
+                    op: "WRITE",
+                    path: display(path),
+                    status,
+                });
+            }
+            offset += len as u64;
+        }
+        self.summary.bytes += size;
+        Ok(())
+    }
+}
+
+/// Create the `nodes` of the tree of `seed` in directory `name` of the
+/// export whose root handle is `root`
+pub async fn populate(
+    conn: &mut Transport,
+    identity: &Identity,
+    root: &[u8],
+    name: &str,
+    nodes: &[Node],
+    seed: u64,
+) -> Result<Summary, PopulateError> {
+    let mut p = Populator {
+        conn,
+        identity,
+        summary: Summary::default(),
+    };
+    let top = vec![name.as_bytes().to_vec()];
+    let call = nfsv3::mkdir(root, name.as_bytes(), &Sattr3::mode(0o755));
+    let fh = p.make(call, "MKDIR", root, &top).await?;
+    let mut dirs: HashMap<Vec<Vec<u8>>, Vec<u8>> = HashMap::new();
+    dirs.insert(Vec::new(), fh);
+    for node in nodes {
+        let (parent, name) = node.path.split_at(node.path.len() - 1);
+        let dir = dirs[parent].clone();
+        let name = &name[0];
+        let path = [&top[..], &node.path].concat();
+        match &node.kind {
+            Kind::Dir => {
+                let call = nfsv3::mkdir(&dir, name, &Sattr3::mode(0o755));
+                let fh = p.make(call, "MKDIR", &dir, &path).await?;
+                dirs.insert(node.path.clone(), fh);
+                p.summary.dirs += 1;
+            }
+            Kind::File { size } => {
+                // Unchecked with size 0 truncates a file left from before
+                let attrs = Sattr3 {
+                    size: Some(0),
+                    ..Sattr3::mode(0o644)
+                };
+                let call = nfsv3::create(&dir, name, &CreateHow::Unchecked(attrs));
+                let fh = p.make(call, "CREATE", &dir, &path).await?;
+                p.write(&fh, seed, &node.path, *size).await?;
+                p.summary.files += 1;
+            }
+            Kind::Symlink { target } => {
+                let call = nfsv3::symlink(&dir, name, &Sattr3::mode(0o777), target);
+                let results = p.call(call, "SYMLINK", &path).await?;
+                match created(&results).map(|(status, _)| status) {
+                    Some(nfsv3::stat::OK) => p.summary.symlinks += 1,
+                    Some(nfsv3::stat::EXIST) => p.summary.existing += 1,
+                    Some(status) => {
+                        return Err(PopulateError::Status {
+                            op: "SYMLINK",
+                            path: display(&path),
+                            status,
+                        })
+                    }
+                    None => {
+                        return Err(PopulateError::Decode {
+                            op: "SYMLINK",
+                            path: display(&path),
+                        })
+                    }
+                }
+            }
+        }
+    }
+    Ok(p.summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_is_reproducible() {
+        let shape = Shape {
+            depth: 2,
+            fanout: 2,
+            files: 3,
+            max_size: 5000,
+            weird: 0.5,
+        };
+        let nodes = plan(&shape, 7);
+        assert_eq!(nodes, plan(&shape, 7));
+        assert_ne!(nodes, plan(&shape, 8));
+        let count = |f: fn(&Kind) -> bool| nodes.iter().filter(|n| f(&n.kind)).count();
+        // Two directories, then two in each of them
+        assert_eq!(count(|k| matches!(k, Kind::Dir)), 2 + 4);
+        assert_eq!(count(|k| matches!(k, Kind::File { .. })), 3 * 7);
+        assert_eq!(count(|k| matches!(k, Kind::Symlink { .. })), 7);
+        assert!(nodes.iter().all(|n| match n.kind {
+            Kind::File { size } => size <= 5000,
+            _ => true,
+        }));
+        // Every directory comes before what is in it
+        for (i, node) in nodes.iter().enumerate() {
+            let parent = &node.path[..node.path.len() - 1];
+            assert!(parent.is_empty() || nodes[..i].iter().any(|n| n.path == parent));
+        }
+        assert!(nodes.iter().any(|n| n.path.last().unwrap().len() == 255));
+        assert!(plan(
+            &Shape {
+                weird: 0.0,
+                ..shape
+            },
+            7
+        )
+        .iter()
+        .all(|n| n
+            .path
+            .iter()
+            .all(|c| c.iter().all(u8::is_ascii_alphanumeric))));
+
+        let path = &nodes[0].path;
+        let whole = contents(7, path, 0, 100);
+        assert_eq!(contents(7, path, 37, 20), whole[37..57]);
+        assert_ne!(contents(8, path, 0, 100), whole);
+    }
+
+    #[test]
+    fn test_decode_results() {
+        let created_dir = [0u32, 1, 2, 0x6668_0000]
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect::<Vec<_>>();
+        assert_eq!(created(&created_dir), Some((0, Some(b"fh".to_vec()))));
+        assert_eq!(created(&[0, 0, 0, 0, 0, 0, 0, 0]), Some((0, None)));
+        assert_eq!(
+            created(&nfsv3::stat::EXIST.to_be_bytes()),
+            Some((nfsv3::stat::EXIST, None))
+        );
+        assert_eq!(created(&[0, 0]), None);
+        assert_eq!(looked_up(&created_dir[4..]), Some((1, None)));
+        assert_eq!(
+            looked_up(&[0, 0, 0, 0, 0, 0, 0, 2, 0x66, 0x68, 0, 0]),
+            Some((0, Some(b"fh".to_vec())))
+        );
+    }
+}
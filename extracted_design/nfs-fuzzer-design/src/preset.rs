@@ -0,0 +1,359 @@
+//! Campaign presets
+//!
+//! Each preset bundles the strategies, oracles and RPC procedures that
+//! make sense together for one attack surface, so a campaign can be
+//! started with `--preset <name>` instead of picking every piece by hand.
+
+use crate::rpc::program;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// Named campaign bundles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// Everything reachable before a mount or session exists
+    PreAuth,
+    /// v4.x stateids, sessions, delegations and layouts
+    StateMachine,
+    /// Resource exhaustion: deep trees, link counts, replay caches
+    Dos,
+    /// Replies that could expose other clients' state or stale data
+    InfoLeak,
+}
+
+/// Input generators
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// Malformed credential and verifier headers and out-of-range
+    /// procedures on NULL
+    RpcHeader,
+    /// AUTH_SYS credential mutations
+    AuthSys,
+    StateidSweep,
+    ReplyCache,
+    Delegations,
+    Layouts,
+    Sparse,
+    Timestamps,
+    DeepTree,
+    LongNames,
+    HardLinks,
+    /// GETATTR/READDIR over single attributes and random combinations
+    AttrSweep,
+    /// OPENATTR and file operations inside the named attribute namespace
+    NamedAttrs,
+    /// Lock-owner release, reuse and table flooding
+    LockOwners,
+    /// WebNFS public filehandle and PUTPUBFH lookups
+    PublicFh,
+    /// fs_locations requests on absent filesystems and referral following
+    Referrals,
+    /// v3 handles replayed in v4 PUTFH and v4 handles in v3 procedures
+    CrossVersionFh,
+    /// Every operation with AUTH_SYS and AUTH_NONE on krb5-only exports
+    AuthDowngrade,
+    /// v3 writes, truncations and renames against v4 opens and delegations
+    MixedVersion,
+    /// Legitimate and bogus lock reclaims after a simulated client reboot
+    LockReclaim,
+    /// Spoofed SM_NOTIFYs claiming other clients rebooted
+    NsmSpoof,
+    /// Pairs of operations on one file released together on separate
+    /// connections
+    OpRaces,
+    /// SAVEFH/RESTOREFH with nothing saved, across destroyed state and
+    /// with mutated current handles
+    SavedFh,
+    /// Repeated export-path walks, mount point crossings and symlinks
+    /// leading back up on servers that re-export NFS mounts
+    ReExportLoops,
+    /// Opens, locks, reads and writes on the client id, stateids and
+    /// handles harvested from earlier replies
+    Stateful,
+    /// SP4_SSV EXCHANGE_IDs with malformed parameters and SET_SSVs with
+    /// bad digests
+    StateProtection,
+    /// Corrupted MIC verifiers, reused and out-of-window sequence numbers
+    /// and channel-binding requests on the campaign's GSS context
+    GssWindow,
+    /// RPCSEC_GSS INITs with malformed tokens and control calls on
+    /// handles the server never issued; no mechanism needed
+    GssControl,
+}
+
+/// Checks applied to the server's behaviour
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Oracle {
+    /// Server stops answering NULL after a case
+    Liveness,
+    /// No reply within the case's time budget
+    Hang,
+    /// Cached replays differ from the original reply
+    ReplyCache,
+    /// Fabricated stateids accepted by TEST_STATEID
+    StateidLeak,
+    /// READ_PLUS segments that do not describe the range coherently
+    ReadPlus,
+    /// Times read back corrupted rather than normalized
+    Timestamps,
+    /// A credential's AUTH_ERROR verdict changes mid-campaign
+    AuthFlip,
+    /// Boot verifiers change, so the server restarted
+    Restart,
+    /// Operations succeed with a flavor the export's SECINFO does not list
+    SecPolicy,
+    /// v4 reads or sizes contradict what either version wrote
+    Coherence,
+    /// Reclaims accepted outside grace or for state never held, or new
+    /// state granted during grace
+    Grace,
+    /// Another client's locks released after a spoofed SM_NOTIFY
+    LockHijack,
+    /// Calls with a broken checksum, or a replayed or out-of-window
+    /// sequence number, carried out on a GSS context
+    GssSequence,
+    /// Attribute combinations answered with a status, length or delay
+    /// their single-attribute replies do not explain
+    AttrSweep,
+}
+
+impl Oracle {
+    /// Every oracle, which a campaign without a preset checks
+    pub const ALL: &'static [Oracle] = &[
+        Oracle::Liveness,
+        Oracle::Hang,
+        Oracle::ReplyCache,
+        Oracle::StateidLeak,
+        Oracle::ReadPlus,
+        Oracle::Timestamps,
+        Oracle::AuthFlip,
+        Oracle::Restart,
+        Oracle::SecPolicy,
+        Oracle::Coherence,
+        Oracle::Grace,
+        Oracle::LockHijack,
+        Oracle::GssSequence,
+        Oracle::AttrSweep,
+    ];
+}
+
+/// One RPC procedure in a campaign
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Procedure {
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+}
+
+const fn proc(program: u32, version: u32, procedure: u32) -> Procedure {
+    Procedure {
+        program,
+        version,
+        procedure,
+    }
+}
+
+/// What a preset expands to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Campaign {
+    pub strategies: Vec<Strategy>,
+    pub oracles: Vec<Oracle>,
+    pub procedures: Vec<Procedure>,
+}
+
+impl Campaign {
+    /// Whether the campaign sends `procedure` of `program` `version`
+    pub fn admits(&self, program: u32, version: u32, procedure: u32) -> bool {
+        self.procedures.contains(&proc(program, version, procedure))
+    }
+
+    /// The programs other than NFS the campaign lists procedures of, each
+    /// version once, in the order first listed
+    pub fn side_programs(&self) -> Vec<(u32, u32)> {
+        let mut out: Vec<(u32, u32)> = Vec::new();
+        for p in &self.procedures {
+            if p.program != program::NFS && !out.contains(&(p.program, p.version)) {
+                out.push((p.program, p.version));
+            }
+        }
+        out
+    }
+}
+
+/// v4 COMPOUND, for every minor version
+const COMPOUND: Procedure = proc(program::NFS, 4, 1);
+
+/// NULL of both NFS versions, and the procedures just past the last of
+/// each and at the top of the range, for RpcHeader
+const RPC_HEADER: [Procedure; 6] = [
+    proc(program::NFS, 3, 0),
+    proc(program::NFS, 4, 0),
+    proc(program::NFS, 3, 22),
+    proc(program::NFS, 3, u32::MAX),
+    proc(program::NFS, 4, 2),
+    proc(program::NFS, 4, u32::MAX),
+];
+
+impl Preset {
+    pub fn campaign(self) -> Campaign {
+        use Oracle as O;
+        use Strategy as S;
+        match self {
+            Preset::PreAuth => Campaign {
+                strategies: vec![
+                    S::RpcHeader,
+                    S::AuthSys,
+                    S::PublicFh,
+                    S::AuthDowngrade,
+                    S::StateProtection,
+                    S::GssControl,
+                    S::GssWindow,
+                ],
+                oracles: vec![
+                    O::Liveness,
+                    O::Hang,
+                    O::AuthFlip,
+                    O::SecPolicy,
+                    O::GssSequence,
+                ],
+                procedures: vec![
+                    proc(program::PORTMAP, 2, 0),
+                    proc(program::PORTMAP, 2, 3),
+                    proc(program::PORTMAP, 2, 4),
+                    proc(program::MOUNT, 3, 0),
+                    proc(program::MOUNT, 3, 1),
+                    proc(program::MOUNT, 3, 5),
+                    // WebNFS: GETATTR, LOOKUP and READDIR on the public handle
+                    proc(program::NFS, 3, 1),
+                    proc(program::NFS, 3, 3),
+                    proc(program::NFS, 3, 16),
+                    // EXCHANGE_ID and friends need no prior state
+                    COMPOUND,
+                ]
+                .into_iter()
+                .chain(RPC_HEADER)
+                .collect(),
+            },
+            Preset::StateMachine => Campaign {
+                strategies: vec![
+                    S::StateidSweep,
+                    S::ReplyCache,
+                    S::Delegations,
+                    S::Layouts,
+                    S::LockOwners,
+                    S::Referrals,
+                    S::MixedVersion,
+                    S::LockReclaim,
+                    S::NsmSpoof,
+                    S::OpRaces,
+                    S::SavedFh,
+                    S::Stateful,
+                ],
+                oracles: vec![
+                    O::Liveness,
+                    O::Hang,
+                    O::ReplyCache,
+                    O::StateidLeak,
+                    O::Restart,
+                    O::Coherence,
+                    O::Grace,
+                    O::LockHijack,
+                ],
+                procedures: vec![
+                    // v3 SETATTR, WRITE, REMOVE and RENAME on files v4 holds open
+                    proc(program::NFS, 3, 2),
+                    proc(program::NFS, 3, 7),
+                    proc(program::NFS, 3, 12),
+                    proc(program::NFS, 3, 14),
+                    // SM_NOTIFY
+                    proc(program::NSM, 1, 6),
+                    COMPOUND,
+                ],
+            },
+            Preset::Dos => Campaign {
+                strategies: vec![
+                    S::RpcHeader,
+                    S::DeepTree,
+                    S::LongNames,
+                    S::HardLinks,
+                    S::ReplyCache,
+                    S::AttrSweep,
+                    S::LockOwners,
+                    S::ReExportLoops,
+                ],
+                oracles: vec![O::Liveness, O::Hang, O::Restart, O::AttrSweep],
+                procedures: [COMPOUND].into_iter().chain(RPC_HEADER).collect(),
+            },
+            Preset::InfoLeak => Campaign {
+                strategies: vec![
+                    S::StateidSweep,
+                    S::Sparse,
+                    S::Timestamps,
+                    S::AuthSys,
+                    S::NamedAttrs,
+                    S::CrossVersionFh,
+                ],
+                oracles: vec![
+                    O::Liveness,
+                    O::Hang,
+                    O::StateidLeak,
+                    O::ReadPlus,
+                    O::Timestamps,
+                ],
+                procedures: vec![
+                    // v3 GETATTR, LOOKUP, ACCESS, READDIR and FSSTAT on v4 handles
+                    proc(program::NFS, 3, 1),
+                    proc(program::NFS, 3, 3),
+                    proc(program::NFS, 3, 4),
+                    proc(program::NFS, 3, 16),
+                    proc(program::NFS, 3, 18),
+                    COMPOUND,
+                ],
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_preset_is_runnable() {
+        for preset in Preset::value_variants() {
+            let c = preset.campaign();
+            assert!(!c.strategies.is_empty() && !c.oracles.is_empty());
+            assert!(!c.procedures.is_empty());
+            // Crashes and hangs are found whatever the preset
+            assert!(c.oracles.starts_with(&[Oracle::Liveness, Oracle::Hang]));
+        }
+    }
+
+    #[test]
+    fn test_campaign_admits_listed_procedures() {
+        let c = Preset::PreAuth.campaign();
+        assert!(c.admits(program::NFS, 4, 1));
+        assert!(c.admits(program::NFS, 3, u32::MAX));
+        assert!(!c.admits(program::NFS, 3, 7));
+        assert!(!c.admits(program::MOUNT, 3, 3));
+        assert_eq!(
+            c.side_programs(),
+            [(program::PORTMAP, 2), (program::MOUNT, 3)]
+        );
+        let c = Preset::StateMachine.campaign();
+        assert_eq!(c.side_programs(), [(program::NSM, 1)]);
+        assert!(Preset::Dos.campaign().side_programs().is_empty());
+    }
+
+    #[test]
+    fn test_preset_cli_names() {
+        assert_eq!(Preset::from_str("pre-auth", false), Ok(Preset::PreAuth));
+        assert_eq!(
+            Preset::from_str("state-machine", false),
+            Ok(Preset::StateMachine)
+        );
+        assert!(Preset::from_str("dos", false).is_ok());
+        assert!(Preset::from_str("info-leak", false).is_ok());
+    }
+}
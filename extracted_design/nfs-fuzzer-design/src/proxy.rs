@@ -0,0 +1,362 @@
+//! Man-in-the-middle TCP relay with in-record corruption
+//!
+//! The relay sits between a real client and the server and forwards both
+//! directions byte for byte, following the record marks as they pass. It
+//! can damage bytes inside record fragments the way a faulty middlebox
+//! would, leaving the marks themselves alone: the server still sees
+//! records of the length it was told, but their contents are wrong, so
+//! its XDR decoding and error paths run on otherwise live traffic. With
+//! `marks` set the four-byte marks are fair game too, and the server's
+//! record-mark parser has to resynchronize or drop the connection.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info};
+
+/// Which way corrupted bytes travel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    /// Calls from the client
+    #[default]
+    ToServer,
+    /// Replies from the server
+    ToClient,
+    Both,
+}
+
+/// How much damage to do
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Corruption {
+    /// Probability a fragment is damaged
+    pub rate: f64,
+    /// Most bytes flipped in one damaged fragment
+    pub bytes: usize,
+    pub direction: Direction,
+    /// Damage record marks as well as their fragments
+    pub marks: bool,
+}
+
+impl Default for Corruption {
+    fn default() -> Self {
+        Self {
+            rate: 0.0,
+            bytes: 1,
+            direction: Direction::default(),
+            marks: false,
+        }
+    }
+}
+
+/// `rate=0.01,bytes=4,dir=both,marks`; any subset
+impl FromStr for Corruption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut c = Self::default();
+        for part in s.split(',').filter(|p| !p.is_empty()) {
+            let (key, value) = part.split_once('=').unwrap_or((part, ""));
+            match key {
+                "rate" => {
+                    c.rate = match value.parse::<f64>() {
+                        Ok(p) if (0.0..=1.0).contains(&p) => p,
+                        _ => return Err(format!("rate must be a probability, got {:?}", value)),
+                    }
+                }
+                "bytes" => {
+                    c.bytes = match value.parse() {
+                        Ok(n) if n > 0 => n,
+                        _ => return Err(format!("bytes must be positive, got {:?}", value)),
+                    }
+                }
+                "dir" => {
+                    c.direction = match value {
+                        "to-server" => Direction::ToServer,
+                        "to-client" => Direction::ToClient,
+                        "both" => Direction::Both,
+                        _ => {
+                            return Err(format!(
+                                "dir must be to-server, to-client or both, got {:?}",
+                                value
+                            ))
+                        }
+                    }
+                }
+                "marks" => c.marks = true,
+                _ => return Err(format!("unknown corruption option {:?}", key)),
+            }
+        }
+        Ok(c)
+    }
+}
+
+/// Record-mark state of one direction of the stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// `have` bytes of the next mark seen so far
+    Mark { have: usize, value: u32 },
+    /// `left` bytes of the current fragment still to come
+    Fragment { left: u32 },
+}
+
+/// Damages one direction of a record-marked stream as it passes
+#[derive(Debug)]
+pub struct Corruptor {
+    config: Corruption,
+    rng: StdRng,
+    position: Position,
+    /// Fragment bytes still to damage, see [`Corruptor::start_fragment`]
+    targets: Vec<u32>,
+    /// XOR masks for the bytes of the current mark
+    mark_damage: [u8; 4],
+    /// Fragments and marks damaged so far
+    pub damaged: u64,
+}
+
+impl Corruptor {
+    pub fn new(config: Corruption, seed: u64) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            position: Position::Mark { have: 0, value: 0 },
+            targets: Vec::new(),
+            mark_damage: [0; 4],
+            damaged: 0,
+        }
+    }
+
+    /// The next nonzero XOR mask
+    fn flip(&mut self) -> u8 {
+        self.rng.gen_range(1..=255)
+    }
+
+    /// Damage `chunk`, the next bytes of the stream, in place. Marks are
+    /// read before they are damaged, so tracking never loses its place.
+    pub fn apply(&mut self, chunk: &mut [u8]) {
+        let mut i = 0;
+        while i < chunk.len() {
+            match self.position {
+                Position::Mark { have, value } => {
+                    if have == 0 {
+                        self.start_mark();
+                    }
+                    let value = value << 8 | u32::from(chunk[i]);
+                    chunk[i] ^= self.mark_damage[have];
+                    i += 1;
+                    self.position = match (have, value & 0x7fff_ffff) {
+                        (3, 0) => Position::Mark { have: 0, value: 0 },
+                        (3, left) => {
+                            self.start_fragment(left);
+                            Position::Fragment { left }
+                        }
+                        _ => Position::Mark {
+                            have: have + 1,
+                            value,
+                        },
+                    };
+                }
+                Position::Fragment { left } => {
+                    let n = left.min((chunk.len() - i) as u32);
+                    while let Some(&t) = self.targets.last() {
+                        if t <= left - n {
+                            break;
+                        }
+                        self.targets.pop();
+                        chunk[i + (left - t) as usize] ^= self.flip();
+                    }
+                    i += n as usize;
+                    self.position = match left - n {
+                        0 => Position::Mark { have: 0, value: 0 },
+                        left => Position::Fragment { left },
+                    };
+                }
+            }
+        }
+    }
+
+    /// Decide whether the mark starting now is damaged, and how
+    fn start_mark(&mut self) {
+        self.mark_damage = [0; 4];
+        if self.config.marks && self.rng.gen_bool(self.config.rate) {
+            self.damaged += 1;
+            let at = self.rng.gen_range(0..4);
+            self.mark_damage[at] = self.flip();
+        }
+    }
+
+    /// Pick the bytes of a new fragment of `len` bytes to damage
+    fn start_fragment(&mut self, len: u32) {
+        self.targets.clear();
+        if !self.rng.gen_bool(self.config.rate) {
+            return;
+        }
+        self.damaged += 1;
+        let n = self.rng.gen_range(1..=self.config.bytes);
+        // Each byte is named by how many fragment bytes are left when it
+        // arrives, so the largest comes first and they pop in stream order
+        let mut targets: Vec<u32> = (0..n).map(|_| self.rng.gen_range(1..=len)).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        self.targets = targets;
+    }
+}
+
+/// Copy `from` to `to`, damaging bytes when `corruptor` is set
+async fn pump<R, W>(
+    mut from: R,
+    mut to: W,
+    mut corruptor: Option<Corruptor>,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; 64 * 1024];
+    let mut total = 0;
+    loop {
+        let n = from.read(&mut buf).await?;
+        if n == 0 {
+            to.shutdown().await?;
+            return Ok(corruptor.map_or(0, |c| c.damaged));
+        }
+        if let Some(c) = corruptor.as_mut() {
+            c.apply(&mut buf[..n]);
+        }
+        to.write_all(&buf[..n]).await?;
+        total += n as u64;
+        debug!("relayed {} bytes", total);
+    }
+}
+
+/// Relay one client connection to `upstream`
+pub async fn relay(
+    client: TcpStream,
+    upstream: SocketAddr,
+    corruption: Option<Corruption>,
+    seed: u64,
+) -> std::io::Result<u64> {
+    let server = TcpStream::connect(upstream).await?;
+    let (client_rd, client_wr) = client.into_split();
+    let (server_rd, server_wr) = server.into_split();
+    let corruptor = |dir: Direction, seed| {
+        corruption
+            .filter(|c| c.direction == dir || c.direction == Direction::Both)
+            .map(|c| Corruptor::new(c, seed))
+    };
+    let (calls, replies) = tokio::join!(
+        pump(client_rd, server_wr, corruptor(Direction::ToServer, seed)),
+        pump(server_rd, client_wr, corruptor(Direction::ToClient, !seed)),
+    );
+    Ok(calls? + replies?)
+}
+
+/// Accept clients on `listener` and relay each to `upstream`; connection
+/// `n` corrupts with seed `seed + n`
+pub async fn serve(
+    listener: TcpListener,
+    upstream: SocketAddr,
+    corruption: Option<Corruption>,
+    seed: u64,
+) -> std::io::Result<()> {
+    let connections = Arc::new(AtomicU64::new(0));
+    loop {
+        let (client, peer) = listener.accept().await?;
+        let n = connections.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            match relay(client, upstream, corruption, seed.wrapping_add(n)).await {
+                Ok(damaged) => info!("{} closed, {} records damaged", peer, damaged),
+                Err(e) => info!("{} closed: {}", peer, e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(body: &[u8]) -> Vec<u8> {
+        let mark = 0x8000_0000 | body.len() as u32;
+        [&mark.to_be_bytes()[..], body].concat()
+    }
+
+    #[test]
+    fn test_parse() {
+        let c: Corruption = "rate=0.5,bytes=3,dir=both,marks".parse().unwrap();
+        assert_eq!(
+            c,
+            Corruption {
+                rate: 0.5,
+                bytes: 3,
+                direction: Direction::Both,
+                marks: true
+            }
+        );
+        assert!("rate=1.5".parse::<Corruption>().is_err());
+        assert!("bytes=0".parse::<Corruption>().is_err());
+        assert!("dir=up".parse::<Corruption>().is_err());
+    }
+
+    #[test]
+    fn test_marks_survive_and_bodies_are_damaged() {
+        let config = Corruption {
+            rate: 1.0,
+            bytes: 4,
+            ..Corruption::default()
+        };
+        let stream = [record(&[0; 40]), record(&[]), record(&[0; 13])].concat();
+        let mut whole = stream.clone();
+        let mut c = Corruptor::new(config, 3);
+        c.apply(&mut whole);
+        // The same damage whatever the chunking
+        let mut chunked = stream.clone();
+        let mut c2 = Corruptor::new(config, 3);
+        for piece in chunked.chunks_mut(3) {
+            c2.apply(piece);
+        }
+        assert_eq!(whole, chunked);
+        assert_eq!(c.damaged, 2);
+        assert_eq!(whole[..4], stream[..4]);
+        assert_eq!(whole[44..48], stream[44..48]);
+        assert_eq!(whole[48..52], stream[48..52]);
+        let flipped = |r: std::ops::Range<usize>| whole[r].iter().filter(|&&b| b != 0).count();
+        assert!((1..=4).contains(&flipped(4..44)));
+        assert!((1..=4).contains(&flipped(52..65)));
+
+        let mut clean = stream.clone();
+        Corruptor::new(Corruption::default(), 3).apply(&mut clean);
+        assert_eq!(clean, stream);
+    }
+
+    #[tokio::test]
+    async fn test_relay_damages_calls_only() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = server.local_addr().unwrap();
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = proxy.local_addr().unwrap();
+        let corruption = Corruption {
+            rate: 1.0,
+            ..Corruption::default()
+        };
+        tokio::spawn(serve(proxy, upstream, Some(corruption), 1));
+
+        let call = record(&[0; 8]);
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(&call).await.unwrap();
+        let (mut conn, _) = server.accept().await.unwrap();
+        let mut got = vec![0; call.len()];
+        conn.read_exact(&mut got).await.unwrap();
+        assert_eq!(got[..4], call[..4]);
+        assert_ne!(got, call);
+
+        conn.write_all(&call).await.unwrap();
+        let mut reply = vec![0; call.len()];
+        client.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, call);
+    }
+}
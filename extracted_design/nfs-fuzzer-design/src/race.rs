@@ -0,0 +1,311 @@
+//! Operation concurrency matrix
+//!
+//! Many server races need two specific operations on the same object to
+//! overlap: a RENAME while a READDIR walks the directory, a SETATTR
+//! truncating a file a READ is copying out of, a REMOVE against an OPEN
+//! of the same name. Random traffic almost never lines those up. Here
+//! every pair from a set of operations on one shared file is built ahead
+//! of time, and the two calls of a pair are launched on two different
+//! connections at a chosen stagger, so only the network and that stagger
+//! separate their arrival. Each pair is swept in both orders.
+
+use crate::auth::Identity;
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::launch::{Launch, DEFAULT_SPIN};
+use crate::mixed::SharedFile;
+use crate::nfsv3::{self, stable_how, CreateHow, Sattr3, VERFSIZE};
+use crate::nfsv4::open::Open;
+use crate::nfsv4::{minor_version, CompoundBuilder};
+use crate::pattern::Pattern;
+use crate::rpc::RpcReply;
+use bytes::BytesMut;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// How far ahead a release is scheduled, so both calls are ready and
+/// the spin is short
+const LEAD: Duration = DEFAULT_SPIN;
+
+/// Name RENAME and LINK give the shared file, and CREATE a new one
+pub const RENAMED: &[u8] = b"race-renamed";
+pub const LINKED: &[u8] = b"race-link";
+pub const CREATED: &[u8] = b"race-new";
+
+/// One side of a pair; all act on the shared file or its directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RaceOp {
+    Lookup,
+    Read,
+    Write,
+    /// SETATTR truncating to zero
+    Setattr,
+    Readdir,
+    Create,
+    Remove,
+    Rename,
+    Link,
+    /// v4.0 OPEN of the shared name
+    Open,
+}
+
+impl RaceOp {
+    pub const ALL: [RaceOp; 10] = [
+        RaceOp::Lookup,
+        RaceOp::Read,
+        RaceOp::Write,
+        RaceOp::Setattr,
+        RaceOp::Readdir,
+        RaceOp::Create,
+        RaceOp::Remove,
+        RaceOp::Rename,
+        RaceOp::Link,
+        RaceOp::Open,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RaceOp::Lookup => "lookup",
+            RaceOp::Read => "read",
+            RaceOp::Write => "write",
+            RaceOp::Setattr => "setattr",
+            RaceOp::Readdir => "readdir",
+            RaceOp::Create => "create",
+            RaceOp::Remove => "remove",
+            RaceOp::Rename => "rename",
+            RaceOp::Link => "link",
+            RaceOp::Open => "open",
+        }
+    }
+
+    /// Whether the operation changes the file or its directory
+    pub fn mutates(self) -> bool {
+        !matches!(self, RaceOp::Lookup | RaceOp::Read | RaceOp::Readdir)
+    }
+
+    /// The record-marked call, sent as `identity`
+    pub fn message(self, file: &SharedFile, identity: &Identity) -> BytesMut {
+        let call = match self {
+            RaceOp::Lookup => nfsv3::lookup(&file.dir3, &file.name),
+            RaceOp::Read => nfsv3::read(&file.file3, 0, 4096),
+            RaceOp::Write => {
+                let data = Pattern::Stamp { tag: 0x7ace }.fill(0, 512);
+                nfsv3::write(&file.file3, 0, 512, stable_how::FILE_SYNC, &data)
+            }
+            RaceOp::Setattr => {
+                let attrs = Sattr3 {
+                    size: Some(0),
+                    ..Sattr3::default()
+                };
+                nfsv3::setattr(&file.file3, &attrs, None)
+            }
+            RaceOp::Readdir => nfsv3::readdir(&file.dir3, 0, &[0; VERFSIZE], 4096),
+            RaceOp::Create => nfsv3::create(
+                &file.dir3,
+                CREATED,
+                &CreateHow::Unchecked(Sattr3::mode(0o644)),
+            ),
+            RaceOp::Remove => nfsv3::remove(&file.dir3, &file.name),
+            RaceOp::Rename => nfsv3::rename(&file.dir3, &file.name, &file.dir3, RENAMED),
+            RaceOp::Link => nfsv3::link(&file.file3, &file.dir3, LINKED),
+            RaceOp::Open => {
+                return CompoundBuilder::new(minor_version::V4_0)
+                    .with_tag(b"race")
+                    .putfh(&file.dir4)
+                    .open(&Open::existing(file.clientid, &file.owner, &file.name))
+                    .message(identity)
+            }
+        };
+        call.message(identity)
+    }
+}
+
+/// Two operations released together; `first` is written first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pair {
+    pub first: RaceOp,
+    pub second: RaceOp,
+}
+
+impl Pair {
+    /// `rename_vs_readdir`
+    pub fn name(&self) -> String {
+        format!("{}_vs_{}", self.first.name(), self.second.name())
+    }
+
+    pub fn swapped(self) -> Self {
+        Self {
+            first: self.second,
+            second: self.first,
+        }
+    }
+}
+
+/// Every unordered pair of `ops`, an operation against itself included,
+/// where at least one side mutates: two reads do not race
+pub fn matrix(ops: &[RaceOp]) -> Vec<Pair> {
+    let mut out = Vec::new();
+    for (i, &first) in ops.iter().enumerate() {
+        for &second in &ops[i..] {
+            if first.mutates() || second.mutates() {
+                out.push(Pair { first, second });
+            }
+        }
+    }
+    out
+}
+
+/// The matrix in the order a campaign runs it: each pair in its written
+/// order, then swapped, so either call gets the head start
+pub fn sweep(ops: &[RaceOp]) -> Vec<Pair> {
+    matrix(ops)
+        .into_iter()
+        .flat_map(|p| {
+            if p.first == p.second {
+                vec![p]
+            } else {
+                vec![p, p.swapped()]
+            }
+        })
+        .collect()
+}
+
+/// Head starts a pair is swept over: the second call follows the first
+/// by this much
+pub const STAGGERS: [Duration; 4] = [
+    Duration::ZERO,
+    Duration::from_micros(20),
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+];
+
+/// Write `calls[0]` to `a` and, `stagger` later, `calls[1]` to `b`, then
+/// collect both replies. The shared file has to be recreated before the
+/// next pair, since most pairs consume it.
+pub async fn release<S>(
+    a: &mut NfsConnection<S>,
+    b: &mut NfsConnection<S>,
+    calls: [&[u8]; 2],
+    stagger: Duration,
+) -> [Result<Vec<u8>, ConnectionError>; 2]
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let at = Instant::now() + LEAD;
+    let mut sent = Launch::new()
+        .slot(a, calls[0], Duration::ZERO)
+        .slot(b, calls[1], stagger)
+        .fire(at)
+        .await
+        .into_iter();
+    let (xa, xb) = (sent.next().unwrap(), sent.next().unwrap());
+    let (ra, rb) = tokio::join!(async { a.recv(xa?.xid).await }, async {
+        b.recv(xb?.xid).await
+    });
+    [ra, rb]
+}
+
+/// Removes from `dir3` of what the pairs leave behind besides the shared
+/// file, so the next run starts from the same directory
+pub fn leftovers(dir3: &[u8], identity: &Identity) -> Vec<BytesMut> {
+    [RENAMED, LINKED, CREATED]
+        .iter()
+        .map(|name| nfsv3::remove(dir3, name).message(identity))
+        .collect()
+}
+
+/// The status each call of a pair came back with, first in its results
+/// for v3 and v4 alike; none where no reply came
+pub type Outcome = [Option<u32>; 2];
+
+pub fn outcome(replies: &[Result<Vec<u8>, ConnectionError>; 2]) -> Outcome {
+    replies.each_ref().map(|reply| {
+        let reply = RpcReply::parse(reply.as_deref().ok()?).ok()?;
+        let results = reply.into_results().ok()?;
+        Some(u32::from_be_bytes(results.get(..4)?.try_into().unwrap()))
+    })
+}
+
+/// Whether `got` is explained by neither of `serial`, the outcomes of
+/// the pair sent one call after the other in each order. A call that got
+/// no reply could have gone either way.
+pub fn diverges(got: Outcome, serial: &[Outcome; 2]) -> bool {
+    serial.iter().all(|s| {
+        s.iter()
+            .zip(&got)
+            .any(|(a, b)| matches!((a, b), (Some(a), Some(b)) if a != b))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Timeouts;
+    use tokio::io::AsyncWriteExt;
+
+    fn file() -> SharedFile {
+        SharedFile {
+            name: b"race-file".to_vec(),
+            dir3: vec![1; 32],
+            file3: vec![2; 32],
+            dir4: vec![3; 16],
+            file4: vec![4; 16],
+            clientid: 9,
+            owner: b"race".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_matrix() {
+        let pairs = matrix(&RaceOp::ALL);
+        // 55 unordered pairs, less the 6 among the three readers
+        assert_eq!(pairs.len(), 49);
+        let names: Vec<String> = pairs.iter().map(Pair::name).collect();
+        assert!(names.contains(&"setattr_vs_readdir".to_string()));
+        assert!(names.contains(&"rename_vs_rename".to_string()));
+        assert!(!names.contains(&"read_vs_readdir".to_string()));
+        // Each distinct pair twice, the 7 mutators against themselves once
+        assert_eq!(sweep(&RaceOp::ALL).len(), 2 * 49 - 7);
+
+        let file = file();
+        let id = Identity::new(0, 0);
+        for op in RaceOp::ALL {
+            let msg = op.message(&file, &id);
+            let expect = if op == RaceOp::Open { 4 } else { 3 };
+            assert_eq!(msg[20..24], [0, 0, 0, expect], "{:?}", op);
+        }
+        assert!(RaceOp::Rename
+            .message(&file, &id)
+            .ends_with(b"race-renamed"));
+    }
+
+    #[test]
+    fn test_outcomes_either_serial_order_explains() {
+        let serial = [[Some(0), Some(2)], [Some(2), Some(0)]];
+        assert!(!diverges([Some(0), Some(2)], &serial));
+        assert!(!diverges([Some(2), None], &serial));
+        assert!(diverges([Some(0), Some(0)], &serial));
+        assert!(diverges([Some(2), Some(2)], &serial));
+    }
+
+    #[tokio::test]
+    async fn test_release_on_two_connections() {
+        let (ca, mut sa) = tokio::io::duplex(4096);
+        let (cb, sb) = tokio::io::duplex(4096);
+        let timeouts = Timeouts::default();
+        let mut a = NfsConnection::new(ca, timeouts);
+        let mut b = NfsConnection::new(cb, timeouts);
+        let id = Identity::new(0, 0);
+        let file = file();
+        let ma = RaceOp::Remove.message(&file, &id);
+        let mb = RaceOp::Open.message(&file, &id);
+        let server = async {
+            sa.write_all(&[0x80, 0, 0, 4]).await.unwrap();
+            sa.write_all(&ma[4..8]).await.unwrap();
+            // b's server hangs up without replying
+            drop(sb);
+        };
+        let (_, [ra, rb]) = tokio::join!(server, release(&mut a, &mut b, [&ma, &mb], STAGGERS[1]));
+        assert_eq!(ra.unwrap(), ma[4..8]);
+        assert!(rb.is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Re-sending saved findings
+//!
+//! A finding keeps its calls exactly as they went out, credentials and
+//! all, and an [`Endpoint`] beside them records where and how: target
+//! address, transport, the program and version health checks use, and
+//! the timeouts. Replaying needs nothing else, so a finding directory can be
+//! handed to a vendor as it is. Each round sends the calls in order on
+//! one connection, reconnecting after one the server closed as the
+//! campaign did, then checks whether the server still answers a NULL.
+
+use crate::connection::{ConnectionError, Proto, Timeouts, Transport};
+use crate::fuzz::FuzzConfig;
+use crate::repro;
+use crate::rpc::{self, program};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How a finding's calls reached the target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Endpoint {
+    pub target: SocketAddr,
+    pub proto: Proto,
+    /// Program the health check's NULL goes to
+    #[serde(default = "nfs")]
+    pub program: u32,
+    /// Version of it the NULL goes to
+    pub nfs_version: u32,
+    pub connect_ms: u64,
+    pub request_ms: u64,
+}
+
+fn nfs() -> u32 {
+    program::NFS
+}
+
+impl Endpoint {
+    pub const FILE: &'static str = "connection.json";
+
+    pub fn of(config: &FuzzConfig) -> Self {
+        Self {
+            target: config.target,
+            proto: config.proto,
+            program: config.probe_program,
+            nfs_version: config.nfs_version,
+            connect_ms: config.budget.connect.as_millis() as u64,
+            request_ms: config.budget.request.as_millis() as u64,
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        let request = Duration::from_millis(self.request_ms);
+        Timeouts {
+            connect: Duration::from_millis(self.connect_ms),
+            read: request,
+            write: request,
+        }
+    }
+
+    /// Where the endpoint of the finding at `finding` goes
+    pub fn path_for(finding: &Path) -> PathBuf {
+        repro::finding_file(finding, Self::FILE)
+    }
+
+    pub fn save(&self, finding: &Path) -> io::Result<()> {
+        std::fs::write(Self::path_for(finding), serde_json::to_vec_pretty(self)?)
+    }
+
+    /// The endpoint of `finding`, or for one entry of a crash directory
+    /// the directory's
+    pub fn load(finding: &Path) -> io::Result<Self> {
+        let mut path = Self::path_for(finding);
+        if !path.exists() {
+            if let Some(dir) = finding.parent() {
+                path = dir.join(Self::FILE);
+            }
+        }
+        Ok(serde_json::from_slice(&std::fs::read(path)?)?)
+    }
+}
+
+/// The calls of a finding by stem: every `.bin` of a crash directory in
+/// the order they were sent, or the one file given
+pub fn calls(path: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        for dirent in std::fs::read_dir(path)? {
+            let file = dirent?.path();
+            if file.extension().is_some_and(|e| e == "bin") {
+                files.push(file);
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+    files
+        .into_iter()
+        .map(|file| {
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            Ok((stem.into_owned(), std::fs::read(&file)?))
+        })
+        .collect()
+}
+
+/// What one call got back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Replied { len: usize, elapsed: Duration },
+    Timeout,
+    Closed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Replied { len, elapsed } => {
+                write!(f, "replied ({} bytes, {:?})", len, elapsed)
+            }
+            Outcome::Timeout => write!(f, "timed out"),
+            Outcome::Closed(e) => write!(f, "failed: {}", e),
+        }
+    }
+}
+
+/// One replay of a finding's calls
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Round {
+    pub outcomes: Vec<(String, Outcome)>,
+    /// Whether the server answered a NULL afterwards
+    pub alive: bool,
+}
+
+impl fmt::Display for Round {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stem, outcome) in &self.outcomes {
+            writeln!(f, "  {}: {}", stem, outcome)?;
+        }
+        let health = if self.alive { "up" } else { "down" };
+        write!(f, "  server {}", health)
+    }
+}
+
+/// Send `calls` to `endpoint` once, then check on the server
+pub async fn replay(endpoint: &Endpoint, calls: &[(String, Vec<u8>)]) -> Round {
+    let timeouts = endpoint.timeouts();
+    let connect = || Transport::connect(endpoint.proto, endpoint.target, timeouts);
+    let mut conn = None;
+    let mut outcomes = Vec::new();
+    for (stem, call) in calls {
+        let start = Instant::now();
+        let result = match conn.take() {
+            Some(c) => Ok(c),
+            None => connect().await,
+        };
+        let outcome = match result {
+            Ok(mut c) => match c.call(call).await {
+                Ok(reply) => {
+                    conn = Some(c);
+                    Outcome::Replied {
+                        len: reply.len(),
+                        elapsed: start.elapsed(),
+                    }
+                }
+                Err(ConnectionError::Timeout { .. }) => Outcome::Timeout,
+                Err(e) => Outcome::Closed(e.to_string()),
+            },
+            Err(e) => Outcome::Closed(e.to_string()),
+        };
+        outcomes.push((stem.clone(), outcome));
+    }
+    let null = rpc::simple_rpc_call(endpoint.program, endpoint.nfs_version, 0);
+    let alive = match connect().await {
+        Ok(mut c) => c.call(&null).await.is_ok(),
+        Err(_) => false,
+    };
+    Round { outcomes, alive }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hang::read_record;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    fn endpoint(target: SocketAddr) -> Endpoint {
+        Endpoint {
+            target,
+            proto: Proto::Tcp,
+            program: program::NFS,
+            nfs_version: 3,
+            connect_ms: 500,
+            request_ms: 200,
+        }
+    }
+
+    #[test]
+    fn test_endpoint_beside_findings() {
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-replay-{}", std::process::id()));
+        let crash = dir.join("crashes").join("00000007");
+        std::fs::create_dir_all(&crash).unwrap();
+        let e = endpoint("10.0.0.1:2049".parse().unwrap());
+        e.save(&crash).unwrap();
+        // An entry of the crash directory finds the directory's endpoint
+        let entry = crash.join("001_killer.bin");
+        std::fs::write(&entry, [0, 0, 0, 0]).unwrap();
+        std::fs::write(crash.join("000_before.bin"), [1]).unwrap();
+        assert_eq!(Endpoint::load(&entry).unwrap(), e);
+        let hang = dir.join("00000009_x.bin");
+        std::fs::write(&hang, [2]).unwrap();
+        e.save(&hang).unwrap();
+        assert!(dir.join("00000009_x.connection.json").exists());
+        assert_eq!(Endpoint::load(&hang).unwrap(), e);
+
+        let stems: Vec<String> = calls(&crash).unwrap().into_iter().map(|c| c.0).collect();
+        assert_eq!(stems, ["000_before", "001_killer"]);
+        assert_eq!(calls(&hang).unwrap(), [("00000009_x".to_string(), vec![2])]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_round() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Answers procedure 0 and 1; closes the connection on anything else
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    while let Ok(call) = read_record(&mut stream).await {
+                        if u32::from_be_bytes(call[20..24].try_into().unwrap()) > 1 {
+                            return;
+                        }
+                        let mut reply = vec![0x80, 0, 0, 24];
+                        reply.extend_from_slice(&call[..4]);
+                        reply.extend_from_slice(&[0, 0, 0, 1]);
+                        reply.extend_from_slice(&[0; 16]);
+                        stream.write_all(&reply).await.unwrap();
+                    }
+                });
+            }
+        });
+        let call = |procedure| rpc::simple_rpc_call(program::NFS, 3, procedure).to_vec();
+        let calls = vec![
+            ("a".to_string(), call(1)),
+            ("b".to_string(), call(7)),
+            ("c".to_string(), call(1)),
+        ];
+        let round = replay(&endpoint(addr), &calls).await;
+        assert!(round.alive);
+        assert!(matches!(
+            round.outcomes[0].1,
+            Outcome::Replied { len: 24, .. }
+        ));
+        assert!(matches!(round.outcomes[1].1, Outcome::Closed(_)));
+        // The next call went out on a new connection
+        assert!(matches!(round.outcomes[2].1, Outcome::Replied { .. }));
+        assert!(round.to_string().ends_with("server up"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Reproduction rates of findings
+//!
+//! A finding is re-sent a number of times against a live target,
+//! restarted after each trial it took down, and the share of trials that
+//! reproduced it is kept with the finding. A rate of one points at a
+//! deterministic bug a single reproducer triggers; anything lower at a
+//! race or at state the reproducer does not rebuild. Before the trials,
+//! crash windows are cut down to their last case when that alone is
+//! enough, so the recorded reproducer is the shortest one found.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where `file` of the finding at `finding` goes: inside a crash
+/// directory, or beside a hang's input
+pub fn finding_file(finding: &Path, file: &str) -> PathBuf {
+    if finding.is_dir() {
+        finding.join(file)
+    } else {
+        finding.with_extension(file)
+    }
+}
+
+/// How reliably a finding reproduces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// Every trial reproduced it
+    Deterministic,
+    /// Some trials did
+    Flaky,
+    /// None did
+    NotReproduced,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Verdict::Deterministic => "deterministic",
+            Verdict::Flaky => "flaky",
+            Verdict::NotReproduced => "not reproduced",
+        })
+    }
+}
+
+/// Outcome of re-sending one finding's reproducer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reproduction {
+    /// Names of the cases re-sent each trial, in order
+    pub reproducer: Vec<String>,
+    pub trials: u32,
+    pub reproduced: u32,
+}
+
+impl Reproduction {
+    pub const FILE: &'static str = "repro.json";
+
+    pub fn rate(&self) -> f64 {
+        if self.trials == 0 {
+            0.0
+        } else {
+            f64::from(self.reproduced) / f64::from(self.trials)
+        }
+    }
+
+    pub fn verdict(&self) -> Verdict {
+        match self.reproduced {
+            0 => Verdict::NotReproduced,
+            n if n == self.trials => Verdict::Deterministic,
+            _ => Verdict::Flaky,
+        }
+    }
+
+    /// Where the metadata of the finding at `finding` goes
+    pub fn path_for(finding: &Path) -> PathBuf {
+        finding_file(finding, Self::FILE)
+    }
+
+    pub fn save(&self, finding: &Path) -> io::Result<PathBuf> {
+        #[derive(Serialize)]
+        struct Record<'a> {
+            #[serde(flatten)]
+            repro: &'a Reproduction,
+            rate: f64,
+            verdict: Verdict,
+        }
+        let path = Self::path_for(finding);
+        let record = Record {
+            repro: self,
+            rate: self.rate(),
+            verdict: self.verdict(),
+        };
+        std::fs::write(&path, serde_json::to_vec_pretty(&record)?)?;
+        Ok(path)
+    }
+
+    pub fn load(finding: &Path) -> io::Result<Self> {
+        let text = std::fs::read_to_string(Self::path_for(finding))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verdicts_and_roundtrip() {
+        let mut r = Reproduction {
+            reproducer: vec!["killer".to_string()],
+            trials: 4,
+            reproduced: 4,
+        };
+        assert_eq!((r.rate(), r.verdict()), (1.0, Verdict::Deterministic));
+        r.reproduced = 1;
+        assert_eq!((r.rate(), r.verdict()), (0.25, Verdict::Flaky));
+        r.reproduced = 0;
+        assert_eq!(r.verdict(), Verdict::NotReproduced);
+
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-repro-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let hang = dir.join("00000001_x.bin");
+        assert_eq!(
+            Reproduction::path_for(&hang),
+            dir.join("00000001_x.repro.json")
+        );
+        r.save(&dir).unwrap();
+        assert_eq!(Reproduction::load(&dir).unwrap(), r);
+        let text = std::fs::read_to_string(dir.join(Reproduction::FILE)).unwrap();
+        assert!(text.contains("\"verdict\": \"not_reproduced\""));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
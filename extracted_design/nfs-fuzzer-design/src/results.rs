@@ -0,0 +1,221 @@
+//! Per-case results log
+//!
+//! Statistics say how often a status came back, findings say which cases
+//! ended badly; neither says what happened to case 40 213 of a campaign
+//! that went fine. With a results log attached, the fuzzer appends one
+//! JSON line per case it sends: when, the xid, the procedure, the
+//! mutation that made the case, the call as sent in base64, the reply's
+//! status and how long it took. The log is only ever appended to, so a
+//! resumed campaign carries on the same file, and grepping it for a
+//! status or feeding [`read`] to a script is enough to pull out the cases
+//! behind an interesting reply.
+
+use crate::generate::Input;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// One case and what came of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// When the call was sent, in seconds since the epoch
+    pub timestamp: f64,
+    pub xid: u32,
+    /// Name of the case, such as `v3:GETATTR`
+    pub name: String,
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    /// The last mutation applied, none for a seed sent as is
+    pub strategy: Option<String>,
+    /// The call, without its record mark
+    #[serde(serialize_with = "to_base64", deserialize_with = "from_base64")]
+    pub request: Vec<u8>,
+    /// Status as the statistics count it: the reply's, or `timeout` or
+    /// `closed`
+    pub status: String,
+    /// Milliseconds from sending the call to the reply or its failure
+    pub latency_ms: f64,
+}
+
+impl Record {
+    /// The record of `input`, sent at `at` as the record-marked `msg`
+    pub fn new(input: &Input, msg: &[u8], at: SystemTime, status: &str, latency: Duration) -> Self {
+        let call = msg.get(4..).unwrap_or_default();
+        Self {
+            timestamp: at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            xid: call
+                .get(..4)
+                .map_or(0, |b| u32::from_be_bytes(b.try_into().unwrap())),
+            name: input.name.clone(),
+            program: input.program,
+            version: input.version,
+            procedure: input.procedure,
+            strategy: input.lineage.steps.last().map(|s| s.strategy.clone()),
+            request: call.to_vec(),
+            status: status.to_string(),
+            latency_ms: latency.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// A results log open for appending
+#[derive(Debug)]
+pub struct ResultLog {
+    out: BufWriter<File>,
+}
+
+impl ResultLog {
+    /// Open `path` for appending, creating it if need be
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            out: BufWriter::new(file),
+        })
+    }
+
+    /// Append `record` as a line, flushed so a crash of the fuzzer loses
+    /// nothing already sent
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        serde_json::to_writer(&mut self.out, record)?;
+        writeln!(self.out)?;
+        self.out.flush()
+    }
+}
+
+/// The records of the log at `path`, in the order they were appended
+pub fn read(path: &Path) -> io::Result<Vec<Record>> {
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !line.as_ref().is_ok_and(|l| l.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64, padded
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, b[0], b[1], b[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The bytes of padded base64 `text`, or `None` if it is not
+pub fn unbase64(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    for (i, chunk) in text.chunks(4).enumerate() {
+        let pad = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if pad > 2 || (pad > 0 && (i + 1) * 4 != text.len()) {
+            return None;
+        }
+        let mut n = 0u32;
+        for &c in &chunk[..4 - pad] {
+            n = n << 6 | ALPHABET.iter().position(|&a| a == c)? as u32;
+        }
+        n <<= 6 * pad;
+        out.extend_from_slice(&n.to_be_bytes()[1..4 - pad]);
+    }
+    Some(out)
+}
+
+fn to_base64<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&base64(bytes))
+}
+
+fn from_base64<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+    let text = String::deserialize(d)?;
+    unbase64(&text).ok_or_else(|| serde::de::Error::custom("invalid base64"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineage::{Lineage, Step};
+    use crate::rpc::program;
+
+    #[test]
+    fn test_base64() {
+        for (bytes, text) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foobar", "Zm9vYmFy"),
+            (&[0xff, 0xfe, 0x00, 0x80], "//4AgA=="),
+        ] {
+            assert_eq!(base64(bytes), text);
+            assert_eq!(unbase64(text).as_deref(), Some(bytes));
+        }
+        assert_eq!(unbase64("Zm9"), None);
+        assert_eq!(unbase64("Zg==Zm9v"), None);
+        assert_eq!(unbase64("Z!=="), None);
+    }
+
+    #[test]
+    fn test_log_appends() {
+        let path =
+            std::env::temp_dir().join(format!("nfs-fuzzer-results-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let input = Input {
+            name: "v3:GETATTR".to_string(),
+            program: program::NFS,
+            version: 3,
+            procedure: 1,
+            args: vec![0, 0, 0, 0],
+            lineage: Lineage::new("v3:GETATTR").then(Step::new("bitflip").with("offset", 3)),
+            auth: None,
+        };
+        let msg = [0x80, 0, 0, 8, 0, 0, 0x12, 0x34, 0, 0, 0, 0];
+        let at = UNIX_EPOCH + Duration::from_millis(1_760_000_000_250);
+        let record = Record::new(
+            &input,
+            &msg,
+            at,
+            "NFS3ERR_BADHANDLE",
+            Duration::from_micros(1500),
+        );
+        assert_eq!(record.xid, 0x1234);
+        assert_eq!(record.strategy.as_deref(), Some("bitflip"));
+        assert_eq!(record.latency_ms, 1.5);
+
+        ResultLog::open(&path).unwrap().append(&record).unwrap();
+        let timeout = Record {
+            status: "timeout".to_string(),
+            strategy: None,
+            ..record.clone()
+        };
+        ResultLog::open(&path).unwrap().append(&timeout).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text
+            .lines()
+            .next()
+            .unwrap()
+            .contains(r#""request":"AAASNAAAAAA=""#));
+        assert_eq!(read(&path).unwrap(), [record, timeout]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
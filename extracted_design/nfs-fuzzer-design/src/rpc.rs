@@ -1,10 +1,13 @@
-//! Sun RPC (ONC RPC) message construction
+//! Sun RPC (ONC RPC) message construction and reply parsing
 //! 
 //! RFC 5531 defines the RPC protocol used by NFS.
 
-use crate::xdr::XdrEncoder;
+use crate::auth::Identity;
+use crate::xdr::{XdrDecoder, XdrEncoder, XdrError};
 use bytes::BytesMut;
+use std::marker::PhantomData;
 use std::sync::atomic::{AtomicU32, Ordering};
+use thiserror::Error;
 
 // Global XID counter for unique transaction IDs
 static XID_COUNTER: AtomicU32 = AtomicU32::new(1);
@@ -25,6 +28,8 @@ pub mod program {
     pub const PORTMAP: u32 = 100000;
     pub const NFS: u32 = 100003;
     pub const MOUNT: u32 = 100005;
+    pub const NLM: u32 = 100021;
+    pub const NSM: u32 = 100024;
 }
 
 /// RPC version (always 2 for current RPC)
@@ -39,6 +44,42 @@ pub mod auth_flavor {
     pub const RPCSEC_GSS: u32 = 6;
 }
 
+/// Reply status (reply_stat)
+pub mod reply_stat {
+    pub const MSG_ACCEPTED: u32 = 0;
+    pub const MSG_DENIED: u32 = 1;
+}
+
+/// How an accepted call fared (accept_stat)
+pub mod accept_stat {
+    pub const SUCCESS: u32 = 0;
+    pub const PROG_UNAVAIL: u32 = 1;
+    pub const PROG_MISMATCH: u32 = 2;
+    pub const PROC_UNAVAIL: u32 = 3;
+    pub const GARBAGE_ARGS: u32 = 4;
+    pub const SYSTEM_ERR: u32 = 5;
+}
+
+/// Why a call was denied (reject_stat)
+pub mod reject_stat {
+    pub const RPC_MISMATCH: u32 = 0;
+    pub const AUTH_ERROR: u32 = 1;
+}
+
+/// AUTH_ERROR sub-statuses (auth_stat)
+pub mod auth_stat {
+    pub const AUTH_OK: u32 = 0;
+    pub const AUTH_BADCRED: u32 = 1;
+    pub const AUTH_REJECTEDCRED: u32 = 2;
+    pub const AUTH_BADVERF: u32 = 3;
+    pub const AUTH_REJECTEDVERF: u32 = 4;
+    pub const AUTH_TOOWEAK: u32 = 5;
+    pub const AUTH_INVALIDRESP: u32 = 6;
+    pub const AUTH_FAILED: u32 = 7;
+    pub const RPCSEC_GSS_CREDPROBLEM: u32 = 13;
+    pub const RPCSEC_GSS_CTXPROBLEM: u32 = 14;
+}
+
 /// Build AUTH_NONE credentials (no authentication)
 pub fn auth_none() -> Vec<u8> {
     let mut enc = XdrEncoder::new();
@@ -69,6 +110,115 @@ pub fn auth_sys(machine_name: &str, uid: u32, gid: u32, gids: &[u32]) -> Vec<u8>
     enc.into_bytes().to_vec()
 }
 
+/// RPCSEC_GSS control procedures (rpc_gss_proc_t, RFC 2203 §5)
+pub mod gss_proc {
+    pub const DATA: u32 = 0;
+    pub const INIT: u32 = 1;
+    pub const CONTINUE_INIT: u32 = 2;
+    pub const DESTROY: u32 = 3;
+    /// RPCSEC_GSSv2 (RFC 5403 §2.2)
+    pub const BIND_CHANNEL: u32 = 4;
+}
+
+/// RPCSEC_GSS protection levels (rpc_gss_service_t)
+pub mod gss_service {
+    pub const NONE: u32 = 1;
+    pub const INTEGRITY: u32 = 2;
+    pub const PRIVACY: u32 = 3;
+}
+
+/// Sequence numbers of an RPCSEC_GSS context stay below this (MAXSEQ)
+pub const GSS_MAXSEQ: u32 = 0x8000_0000;
+
+/// Build RPCSEC_GSS version 1 credentials
+pub fn rpcsec_gss_cred(proc: u32, seq: u32, service: u32, handle: &[u8]) -> Vec<u8> {
+    let mut body = XdrEncoder::new();
+    body.put_u32(1); // RPCSEC_GSS_VERS_1
+    body.put_u32(proc);
+    body.put_u32(seq);
+    body.put_u32(service);
+    body.put_opaque(handle);
+
+    let mut enc = XdrEncoder::new();
+    enc.put_u32(auth_flavor::RPCSEC_GSS);
+    enc.put_opaque(body.as_bytes());
+    enc.into_bytes().to_vec()
+}
+
+/// rpc_gss_integ_data: the sequence number and `body` as one opaque,
+/// then the checksum `mic` makes of that opaque's contents; fails as
+/// `mic` does
+pub fn gss_integ_body<E>(
+    seq: u32,
+    body: &[u8],
+    mic: impl FnOnce(&[u8]) -> Result<Vec<u8>, E>,
+) -> Result<Vec<u8>, E> {
+    let mut data = XdrEncoder::new();
+    data.put_u32(seq);
+    data.put_raw(body);
+    let checksum = mic(data.as_bytes())?;
+
+    let mut enc = XdrEncoder::new();
+    enc.put_opaque(data.as_bytes());
+    enc.put_opaque(&checksum);
+    Ok(enc.into_bytes().to_vec())
+}
+
+/// The body inside rpc_gss_integ_data, if `verify` accepts its checksum
+/// and it carries sequence number `seq`
+pub fn gss_integ_unwrap(
+    data: &[u8],
+    seq: u32,
+    verify: impl FnOnce(&[u8], &[u8]) -> bool,
+) -> Result<&[u8], RpcError> {
+    let mut dec = XdrDecoder::new(data);
+    let databody = dec.get_opaque()?;
+    let checksum = dec.get_opaque()?;
+    if !verify(databody, checksum) {
+        return Err(RpcError::GssChecksum);
+    }
+    gss_seq_body(databody, seq)
+}
+
+/// rpc_gss_priv_data: the sequence number and `body`, sealed by `wrap`;
+/// fails as `wrap` does
+pub fn gss_priv_body<E>(
+    seq: u32,
+    body: &[u8],
+    wrap: impl FnOnce(&[u8]) -> Result<Vec<u8>, E>,
+) -> Result<Vec<u8>, E> {
+    let mut data = XdrEncoder::new();
+    data.put_u32(seq);
+    data.put_raw(body);
+
+    let mut enc = XdrEncoder::new();
+    enc.put_opaque(&wrap(data.as_bytes())?);
+    Ok(enc.into_bytes().to_vec())
+}
+
+/// The body inside rpc_gss_priv_data, if `unwrap` opens it and it
+/// carries sequence number `seq`
+pub fn gss_priv_unwrap(
+    data: &[u8],
+    seq: u32,
+    unwrap: impl FnOnce(&[u8]) -> Option<Vec<u8>>,
+) -> Result<Vec<u8>, RpcError> {
+    let mut dec = XdrDecoder::new(data);
+    let sealed = dec.get_opaque()?;
+    let databody = unwrap(sealed).ok_or(RpcError::GssChecksum)?;
+    gss_seq_body(&databody, seq).map(<[u8]>::to_vec)
+}
+
+/// What follows the sequence number at the head of a GSS data body
+fn gss_seq_body(databody: &[u8], seq: u32) -> Result<&[u8], RpcError> {
+    let mut dec = XdrDecoder::new(databody);
+    let got = dec.get_u32()?;
+    if got != seq {
+        return Err(RpcError::GssSeq { sent: seq, got });
+    }
+    Ok(dec.rest())
+}
+
 /// RPC CALL message builder
 pub struct RpcCall {
     enc: XdrEncoder,
@@ -120,6 +270,21 @@ impl RpcCall {
         self
     }
 
+    /// Add RPCSEC_GSS credentials, and as verifier the checksum `mic`
+    /// makes of the header from the xid through the credentials; fails
+    /// as `mic` does
+    pub fn with_gss<E>(
+        mut self,
+        cred: &[u8],
+        mic: impl FnOnce(&[u8]) -> Result<Vec<u8>, E>,
+    ) -> Result<Self, E> {
+        self.enc.put_raw(cred);
+        let checksum = mic(&self.enc.as_bytes()[self.body_start..])?;
+        self.enc.put_u32(auth_flavor::RPCSEC_GSS);
+        self.enc.put_opaque(&checksum);
+        Ok(self)
+    }
+
     /// Add AUTH_NONE for both credentials and verifier
     pub fn with_auth_none(self) -> Self {
         let auth = auth_none();
@@ -158,6 +323,331 @@ impl RpcCall {
     }
 }
 
+/// An RPC program and version, as [`ProgramCall`] calls it
+pub trait Program {
+    const PROGRAM: u32;
+    const VERSION: u32;
+}
+
+/// One call to `P`: the procedure and its encoded arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramCall<P> {
+    pub procedure: u32,
+    pub args: Vec<u8>,
+    program: PhantomData<P>,
+}
+
+impl<P: Program> ProgramCall<P> {
+    pub fn new(procedure: u32, f: impl FnOnce(&mut XdrEncoder)) -> Self {
+        let mut enc = XdrEncoder::new();
+        f(&mut enc);
+        Self::with_args(procedure, enc.into_bytes().to_vec())
+    }
+
+    /// A call whose arguments are already encoded
+    pub fn with_args(procedure: u32, args: Vec<u8>) -> Self {
+        Self {
+            procedure,
+            args,
+            program: PhantomData,
+        }
+    }
+
+    /// The record-marked call, sent as `identity`
+    pub fn message(&self, identity: &Identity) -> BytesMut {
+        RpcCall::new(next_xid(), P::PROGRAM, P::VERSION, self.procedure, true)
+            .with_auth(&identity.credential(), &auth_none())
+            .with_args(&self.args)
+            .build()
+    }
+}
+
+/// Errors parsing an RPC reply
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RpcError {
+    #[error("reply: {0}")]
+    Xdr(#[from] XdrError),
+    #[error("msg_type {0} is not REPLY")]
+    NotReply(u32),
+    #[error("undefined reply_stat {0}")]
+    ReplyStat(u32),
+    #[error("undefined accept_stat {0}")]
+    AcceptStat(u32),
+    #[error("undefined reject_stat {0}")]
+    RejectStat(u32),
+    #[error("call not accepted: {0:?}")]
+    NotAccepted(Accepted),
+    #[error("call denied: {0:?}")]
+    Denied(Rejected),
+    #[error("port {0} out of range")]
+    BadPort(u32),
+    #[error("RPCSEC_GSS checksum does not verify")]
+    GssChecksum,
+    #[error("RPCSEC_GSS sequence number {got}, sent {sent}")]
+    GssSeq { sent: u32, got: u32 },
+}
+
+/// An opaque_auth: flavor and body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpaqueAuth<'a> {
+    pub flavor: u32,
+    pub body: &'a [u8],
+}
+
+/// Outcome of an accepted call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Accepted {
+    Success,
+    ProgUnavail,
+    /// Versions of the program the server supports
+    ProgMismatch {
+        low: u32,
+        high: u32,
+    },
+    ProcUnavail,
+    GarbageArgs,
+    SystemErr,
+}
+
+/// Why a call was denied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejected {
+    /// RPC versions the server supports
+    RpcMismatch { low: u32, high: u32 },
+    /// auth_stat
+    AuthError(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplyStatus<'a> {
+    Accepted {
+        verifier: OpaqueAuth<'a>,
+        stat: Accepted,
+    },
+    Denied(Rejected),
+}
+
+/// A parsed RPC reply; `results` holds the procedure's reply body when
+/// the call succeeded and is empty otherwise
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcReply<'a> {
+    pub xid: u32,
+    pub status: ReplyStatus<'a>,
+    pub results: &'a [u8],
+}
+
+impl<'a> RpcReply<'a> {
+    /// Parse a reply message without its record mark
+    pub fn parse(buf: &'a [u8]) -> Result<Self, RpcError> {
+        let mut dec = XdrDecoder::new(buf);
+        let xid = dec.get_u32()?;
+        let mtype = dec.get_u32()?;
+        if mtype != msg_type::REPLY {
+            return Err(RpcError::NotReply(mtype));
+        }
+        let status = match dec.get_u32()? {
+            reply_stat::MSG_ACCEPTED => {
+                let verifier = OpaqueAuth {
+                    flavor: dec.get_u32()?,
+                    body: dec.get_opaque()?,
+                };
+                let stat = match dec.get_u32()? {
+                    accept_stat::SUCCESS => Accepted::Success,
+                    accept_stat::PROG_UNAVAIL => Accepted::ProgUnavail,
+                    accept_stat::PROG_MISMATCH => Accepted::ProgMismatch {
+                        low: dec.get_u32()?,
+                        high: dec.get_u32()?,
+                    },
+                    accept_stat::PROC_UNAVAIL => Accepted::ProcUnavail,
+                    accept_stat::GARBAGE_ARGS => Accepted::GarbageArgs,
+                    accept_stat::SYSTEM_ERR => Accepted::SystemErr,
+                    other => return Err(RpcError::AcceptStat(other)),
+                };
+                ReplyStatus::Accepted { verifier, stat }
+            }
+            reply_stat::MSG_DENIED => ReplyStatus::Denied(match dec.get_u32()? {
+                reject_stat::RPC_MISMATCH => Rejected::RpcMismatch {
+                    low: dec.get_u32()?,
+                    high: dec.get_u32()?,
+                },
+                reject_stat::AUTH_ERROR => Rejected::AuthError(dec.get_u32()?),
+                other => return Err(RpcError::RejectStat(other)),
+            }),
+            other => return Err(RpcError::ReplyStat(other)),
+        };
+        let results = match status {
+            ReplyStatus::Accepted {
+                stat: Accepted::Success,
+                ..
+            } => dec.rest(),
+            _ => &[],
+        };
+        Ok(Self {
+            xid,
+            status,
+            results,
+        })
+    }
+
+    /// Accepted with SUCCESS
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self.status,
+            ReplyStatus::Accepted {
+                stat: Accepted::Success,
+                ..
+            }
+        )
+    }
+
+    /// The server's verifier, for accepted calls
+    pub fn verifier(&self) -> Option<&OpaqueAuth<'a>> {
+        match &self.status {
+            ReplyStatus::Accepted { verifier, .. } => Some(verifier),
+            ReplyStatus::Denied(_) => None,
+        }
+    }
+
+    /// The results of a successful call, else why there are none
+    pub fn into_results(self) -> Result<&'a [u8], RpcError> {
+        match self.status {
+            ReplyStatus::Accepted {
+                stat: Accepted::Success,
+                ..
+            } => Ok(self.results),
+            ReplyStatus::Accepted { stat, .. } => Err(RpcError::NotAccepted(stat)),
+            ReplyStatus::Denied(why) => Err(RpcError::Denied(why)),
+        }
+    }
+}
+
+/// An accepted SUCCESS reply to `xid` with an AUTH_NONE verifier, then
+/// `results`, for tests to answer calls with
+#[cfg(test)]
+pub(crate) fn success_reply(xid: u32, results: &[u8]) -> Vec<u8> {
+    let mut enc = XdrEncoder::new();
+    for word in [
+        xid,
+        msg_type::REPLY,
+        reply_stat::MSG_ACCEPTED,
+        auth_flavor::AUTH_NONE,
+        0,
+        accept_stat::SUCCESS,
+    ] {
+        enc.put_u32(word);
+    }
+    enc.put_raw(results);
+    enc.into_bytes().to_vec()
+}
+
+/// Port portmap and rpcbind listen on
+pub const PORTMAP_PORT: u16 = 111;
+
+/// Portmap v2 (RFC 1833 §3)
+pub mod pmap {
+    pub const VERSION: u32 = 2;
+    pub const NULL: u32 = 0;
+    pub const SET: u32 = 1;
+    pub const UNSET: u32 = 2;
+    pub const GETPORT: u32 = 3;
+    pub const DUMP: u32 = 4;
+    pub const CALLIT: u32 = 5;
+    pub const IPPROTO_TCP: u32 = 6;
+    pub const IPPROTO_UDP: u32 = 17;
+}
+
+/// rpcbind v3 and v4 (RFC 1833 §2), same program number as portmap
+pub mod rpcb {
+    pub const V3: u32 = 3;
+    pub const V4: u32 = 4;
+    pub const GETADDR: u32 = 3;
+}
+
+/// PMAPPROC_GETPORT for `program`/`version` over `protocol`
+/// (`pmap::IPPROTO_TCP` or `pmap::IPPROTO_UDP`)
+pub fn getport_call(program: u32, version: u32, protocol: u32) -> BytesMut {
+    let mut args = XdrEncoder::new();
+    args.put_u32(program);
+    args.put_u32(version);
+    args.put_u32(protocol);
+    args.put_u32(0); // port, ignored
+    RpcCall::new(next_xid(), program::PORTMAP, pmap::VERSION, pmap::GETPORT, true)
+        .with_auth_none()
+        .with_args(args.as_bytes())
+        .build()
+}
+
+/// The port in GETPORT results; `None` when the program is not registered
+pub fn getport_result(results: &[u8]) -> Result<Option<u16>, RpcError> {
+    let port = XdrDecoder::new(results).get_u32()?;
+    match port {
+        0 => Ok(None),
+        p => u16::try_from(p).map(Some).map_err(|_| RpcError::BadPort(p)),
+    }
+}
+
+/// One registration in a portmap DUMP reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    pub program: u32,
+    pub version: u32,
+    /// `pmap::IPPROTO_TCP` or `pmap::IPPROTO_UDP`
+    pub protocol: u32,
+    pub port: u32,
+}
+
+/// PMAPPROC_DUMP: every program, version and port registered
+pub fn dump_call() -> BytesMut {
+    simple_rpc_call(program::PORTMAP, pmap::VERSION, pmap::DUMP)
+}
+
+/// The mappings in DUMP results (a linked list of pmap entries)
+pub fn dump_result(results: &[u8]) -> Result<Vec<Mapping>, RpcError> {
+    let mut dec = XdrDecoder::new(results);
+    let mut mappings = Vec::new();
+    while dec.get_bool()? {
+        mappings.push(Mapping {
+            program: dec.get_u32()?,
+            version: dec.get_u32()?,
+            protocol: dec.get_u32()?,
+            port: dec.get_u32()?,
+        });
+    }
+    Ok(mappings)
+}
+
+/// RPCBPROC_GETADDR (rpcbind `version` 3 or 4) for `program`/`version`
+/// over `netid` (`tcp`, `udp`, `tcp6`, ...)
+pub fn getaddr_call(rpcb_version: u32, program: u32, version: u32, netid: &str) -> BytesMut {
+    let mut args = XdrEncoder::new();
+    args.put_u32(program);
+    args.put_u32(version);
+    args.put_string(netid);
+    args.put_string(""); // r_addr, ignored
+    args.put_string(""); // r_owner, ignored
+    RpcCall::new(next_xid(), program::PORTMAP, rpcb_version, rpcb::GETADDR, true)
+        .with_auth_none()
+        .with_args(args.as_bytes())
+        .build()
+}
+
+/// The universal address in GETADDR results; `None` when the program is
+/// not registered
+pub fn getaddr_result(results: &[u8]) -> Result<Option<String>, RpcError> {
+    let uaddr = XdrDecoder::new(results).get_string()?;
+    Ok((!uaddr.is_empty()).then(|| uaddr.to_string()))
+}
+
+/// A universal address (`h1.h2.h3.h4.p1.p2`, or an IPv6 address with
+/// `.p1.p2` appended) as a socket address
+pub fn parse_uaddr(uaddr: &str) -> Option<std::net::SocketAddr> {
+    let (rest, lo) = uaddr.rsplit_once('.')?;
+    let (host, hi) = rest.rsplit_once('.')?;
+    let port = u16::from(hi.parse::<u8>().ok()?) << 8 | u16::from(lo.parse::<u8>().ok()?);
+    let ip: std::net::IpAddr = host.parse().ok()?;
+    Some((ip, port).into())
+}
+
 /// Helper to build a simple RPC call with no arguments
 pub fn simple_rpc_call(
     program: u32,
@@ -188,6 +678,106 @@ mod tests {
         assert_eq!(&msg[16..20], &[0x00, 0x01, 0x86, 0xA3]);
     }
 
+    fn reply(words: &[u32]) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        for &w in words {
+            enc.put_u32(w);
+        }
+        enc.into_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_parse_accepted_replies() {
+        let mut ok = reply(&[7, msg_type::REPLY, reply_stat::MSG_ACCEPTED]);
+        ok.extend(reply(&[
+            auth_flavor::AUTH_SYS,
+            4,
+            0xabcd,
+            accept_stat::SUCCESS,
+            0,
+            42,
+        ]));
+        let r = RpcReply::parse(&ok).unwrap();
+        assert_eq!(r.xid, 7);
+        assert!(r.is_success());
+        assert_eq!(
+            r.verifier(),
+            Some(&OpaqueAuth {
+                flavor: auth_flavor::AUTH_SYS,
+                body: &[0, 0, 0xab, 0xcd]
+            })
+        );
+        assert_eq!(r.results, &[0, 0, 0, 0, 0, 0, 0, 42]);
+
+        let buf = success_reply(8, &[0, 0, 0, 1]);
+        let r = RpcReply::parse(&buf).unwrap();
+        assert_eq!((r.xid, r.verifier().map(|v| v.flavor)), (8, Some(0)));
+        assert_eq!(r.into_results(), Ok(&[0, 0, 0, 1][..]));
+
+        // The same header up to its accept_stat, then `tail`
+        let accepted = |tail: &[u32]| {
+            let mut buf = success_reply(1, &[]);
+            buf.truncate(20);
+            buf.extend(reply(tail));
+            buf
+        };
+        let stat = |buf: &[u8]| match RpcReply::parse(buf).unwrap().status {
+            ReplyStatus::Accepted { stat, .. } => stat,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(
+            stat(&accepted(&[accept_stat::PROG_MISMATCH, 2, 4])),
+            Accepted::ProgMismatch { low: 2, high: 4 }
+        );
+        assert_eq!(
+            stat(&accepted(&[accept_stat::GARBAGE_ARGS])),
+            Accepted::GarbageArgs
+        );
+        assert_eq!(
+            stat(&accepted(&[accept_stat::SYSTEM_ERR])),
+            Accepted::SystemErr
+        );
+        let buf = accepted(&[accept_stat::GARBAGE_ARGS, 9]);
+        let garbage = RpcReply::parse(&buf).unwrap();
+        assert!(!garbage.is_success() && garbage.results.is_empty());
+        assert_eq!(
+            RpcReply::parse(&accepted(&[9])),
+            Err(RpcError::AcceptStat(9))
+        );
+    }
+
+    #[test]
+    fn test_parse_denied_and_malformed() {
+        let denied = reply(&[
+            3,
+            msg_type::REPLY,
+            reply_stat::MSG_DENIED,
+            reject_stat::AUTH_ERROR,
+            auth_stat::AUTH_TOOWEAK,
+        ]);
+        let r = RpcReply::parse(&denied).unwrap();
+        assert_eq!(
+            r.status,
+            ReplyStatus::Denied(Rejected::AuthError(auth_stat::AUTH_TOOWEAK))
+        );
+        assert_eq!(r.verifier(), None);
+        let mismatch = reply(&[3, msg_type::REPLY, reply_stat::MSG_DENIED, 0, 2, 2]);
+        assert_eq!(
+            RpcReply::parse(&mismatch).unwrap().status,
+            ReplyStatus::Denied(Rejected::RpcMismatch { low: 2, high: 2 })
+        );
+        assert_eq!(
+            RpcReply::parse(&reply(&[3, msg_type::CALL])),
+            Err(RpcError::NotReply(msg_type::CALL))
+        );
+        assert_eq!(
+            RpcReply::parse(&reply(&[3, msg_type::REPLY, 2])),
+            Err(RpcError::ReplyStat(2))
+        );
+        let err = RpcReply::parse(&denied[..16]).unwrap_err();
+        assert_eq!(err.to_string(), "reply: need 4 bytes at offset 16, 0 left");
+    }
+
     #[test]
     fn test_auth_sys() {
         let auth = auth_sys("fuzzer", 0, 0, &[]);
@@ -196,4 +786,44 @@ mod tests {
         // = 4 + 4 + 4 + 4 + 8 + 4 + 4 + 4 = 36 bytes
         assert_eq!(auth.len(), 36);
     }
+
+    #[test]
+    fn test_portmap_and_rpcbind() {
+        let msg = getport_call(program::MOUNT, 3, pmap::IPPROTO_UDP);
+        assert_eq!(&msg[16..28], &[0, 1, 0x86, 0xa0, 0, 0, 0, 2, 0, 0, 0, 3]);
+        assert!(msg.ends_with(&[0, 1, 0x86, 0xa5, 0, 0, 0, 3, 0, 0, 0, 17, 0, 0, 0, 0]));
+        assert_eq!(getport_result(&[0, 0, 0x4e, 0x25]), Ok(Some(20005)));
+        assert_eq!(getport_result(&[0, 0, 0, 0]), Ok(None));
+        assert_eq!(getport_result(&[0, 1, 0, 0]), Err(RpcError::BadPort(65536)));
+
+        let msg = getaddr_call(rpcb::V4, program::NFS, 3, "tcp");
+        assert_eq!(&msg[20..28], &[0, 0, 0, 4, 0, 0, 0, 3]);
+        let mut enc = XdrEncoder::new();
+        enc.put_string("10.0.0.1.8.1");
+        assert_eq!(getaddr_result(enc.as_bytes()), Ok(Some("10.0.0.1.8.1".into())));
+        assert_eq!(getaddr_result(&[0, 0, 0, 0]), Ok(None));
+        assert_eq!(parse_uaddr("10.0.0.1.8.1"), Some("10.0.0.1:2049".parse().unwrap()));
+        assert_eq!(parse_uaddr("::1.0.111"), Some("[::1]:111".parse().unwrap()));
+        assert_eq!(parse_uaddr("10.0.0.1.300.1"), None);
+
+        assert_eq!(&dump_call()[20..28], &[0, 0, 0, 2, 0, 0, 0, 4]);
+        let mut enc = XdrEncoder::new();
+        for entry in [[100000, 2, 6, 111], [100005, 3, 17, 20048]] {
+            enc.put_bool(true);
+            entry.iter().for_each(|&w| enc.put_u32(w));
+        }
+        enc.put_bool(false);
+        let mappings = dump_result(enc.as_bytes()).unwrap();
+        assert_eq!(mappings.len(), 2);
+        assert_eq!(mappings[1], Mapping { program: 100005, version: 3, protocol: 17, port: 20048 });
+        assert!(dump_result(&enc.as_bytes()[..8]).is_err());
+
+        let denied = reply(&[
+            1, msg_type::REPLY, reply_stat::MSG_DENIED, reject_stat::AUTH_ERROR, 1,
+        ]);
+        assert_eq!(
+            RpcReply::parse(&denied).unwrap().into_results(),
+            Err(RpcError::Denied(Rejected::AuthError(1)))
+        );
+    }
 }
@@ -0,0 +1,496 @@
+//! Scenario files
+//!
+//! A RON format for writing scenarios by hand. Steps can carry an `id`,
+//! and operations name their handles and stateids symbolically: `"root"`
+//! (or `"anonymous"` for stateids), the `id` of an earlier step, or
+//! `"#N"` for the step at index N. Loading resolves these to step
+//! indices; the executor maps those to the handles and stateids the
+//! steps produced as it runs (see [`super::run`], which
+//! `nfs-fuzzer scenario run FILE` drives).
+//!
+//! ```ron
+//! (
+//!     name: "write_after_rename",
+//!     steps: [
+//!         (id: "dir", op: Mkdir(dir: "root", name: "fuzz")),
+//!         (id: "f", op: Open(dir: "dir", name: "victim")),
+//!         (op: Rename(from_dir: "dir", from_name: "victim", to_dir: "dir", to_name: "moved")),
+//!         (op: Write(file: "f", stateid: "f", offset: 0, data: "hello")),
+//!         (op: Close(file: "f", stateid: "f"), expect: Either),
+//!     ],
+//!     cleanup: [
+//!         (op: Remove(dir: "dir", name: "moved"), expect: Either),
+//!         (op: Rmdir(dir: "root", name: "fuzz"), expect: Either),
+//!     ],
+//! )
+//! ```
+
+use super::{name_serde, Action, FhRef, Outcome, Scenario, StateRef, Step};
+use ron::extensions::Extensions;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DslError {
+    #[error("parse error: {0}")]
+    Parse(#[from] ron::error::SpannedError),
+    #[error("step {step}: unknown reference {reference:?}")]
+    Unknown { step: usize, reference: String },
+    #[error("step {step}: {reference:?} is not an earlier step")]
+    Forward { step: usize, reference: String },
+    #[error("step {step}: {reference:?} produces no {what}")]
+    Produces {
+        step: usize,
+        reference: String,
+        what: &'static str,
+    },
+    #[error("step {step}: duplicate id {id:?}")]
+    Duplicate { step: usize, id: String },
+    #[error("cleanup step {step}: ids are only allowed on steps")]
+    CleanupId { step: usize },
+}
+
+/// An operation with symbolic references
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    Mkdir {
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Create {
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Lookup {
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Rename {
+        from_dir: String,
+        #[serde(with = "name_serde")]
+        from_name: Vec<u8>,
+        to_dir: String,
+        #[serde(with = "name_serde")]
+        to_name: Vec<u8>,
+    },
+    Link {
+        file: String,
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Remove {
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Rmdir {
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Open {
+        dir: String,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Read {
+        file: String,
+        stateid: String,
+        offset: u64,
+        count: u32,
+    },
+    Write {
+        file: String,
+        stateid: String,
+        offset: u64,
+        #[serde(with = "name_serde")]
+        data: Vec<u8>,
+    },
+    Close {
+        file: String,
+        stateid: String,
+    },
+}
+
+fn success() -> Outcome {
+    Outcome::Success
+}
+
+fn is_success(o: &Outcome) -> bool {
+    *o == Outcome::Success
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DslStep {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub op: Op,
+    #[serde(default = "success", skip_serializing_if = "is_success")]
+    pub expect: Outcome,
+}
+
+/// A scenario as written in a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScenarioFile {
+    pub name: String,
+    pub steps: Vec<DslStep>,
+    #[serde(default)]
+    pub cleanup: Vec<DslStep>,
+}
+
+struct Resolver<'a> {
+    ids: HashMap<&'a str, usize>,
+    steps: &'a [Step],
+    /// Index of the step being resolved; only earlier steps are visible
+    step: usize,
+    limit: usize,
+}
+
+impl Resolver<'_> {
+    fn index(&self, reference: &str) -> Result<usize, DslError> {
+        let index = match reference.strip_prefix('#') {
+            Some(n) => n.parse().ok(),
+            None => self.ids.get(reference).copied(),
+        };
+        let index = index.ok_or_else(|| DslError::Unknown {
+            step: self.step,
+            reference: reference.to_string(),
+        })?;
+        if index >= self.limit {
+            return Err(DslError::Forward {
+                step: self.step,
+                reference: reference.to_string(),
+            });
+        }
+        Ok(index)
+    }
+
+    fn fh(&self, reference: &str) -> Result<FhRef, DslError> {
+        if reference == "root" {
+            return Ok(FhRef::Root);
+        }
+        let index = self.index(reference)?;
+        if !self.steps[index].action.produces_fh() {
+            return Err(DslError::Produces {
+                step: self.step,
+                reference: reference.to_string(),
+                what: "filehandle",
+            });
+        }
+        Ok(FhRef::Step(index))
+    }
+
+    fn stateid(&self, reference: &str) -> Result<StateRef, DslError> {
+        if reference == "anonymous" {
+            return Ok(StateRef::Anonymous);
+        }
+        let index = self.index(reference)?;
+        if !matches!(self.steps[index].action, Action::Open { .. }) {
+            return Err(DslError::Produces {
+                step: self.step,
+                reference: reference.to_string(),
+                what: "stateid",
+            });
+        }
+        Ok(StateRef::Step(index))
+    }
+
+    fn action(&self, op: &Op) -> Result<Action, DslError> {
+        Ok(match op {
+            Op::Mkdir { dir, name } => Action::Mkdir {
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Create { dir, name } => Action::Create {
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Lookup { dir, name } => Action::Lookup {
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Rename {
+                from_dir,
+                from_name,
+                to_dir,
+                to_name,
+            } => Action::Rename {
+                from_dir: self.fh(from_dir)?,
+                from_name: from_name.clone(),
+                to_dir: self.fh(to_dir)?,
+                to_name: to_name.clone(),
+            },
+            Op::Link { file, dir, name } => Action::Link {
+                file: self.fh(file)?,
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Remove { dir, name } => Action::Remove {
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Rmdir { dir, name } => Action::Rmdir {
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Open { dir, name } => Action::Open {
+                dir: self.fh(dir)?,
+                name: name.clone(),
+            },
+            Op::Read {
+                file,
+                stateid,
+                offset,
+                count,
+            } => Action::Read {
+                file: self.fh(file)?,
+                stateid: self.stateid(stateid)?,
+                offset: *offset,
+                count: *count,
+            },
+            Op::Write {
+                file,
+                stateid,
+                offset,
+                data,
+            } => Action::Write {
+                file: self.fh(file)?,
+                stateid: self.stateid(stateid)?,
+                offset: *offset,
+                data: data.clone(),
+            },
+            Op::Close { file, stateid } => Action::Close {
+                file: self.fh(file)?,
+                stateid: self.stateid(stateid)?,
+            },
+        })
+    }
+}
+
+impl ScenarioFile {
+    /// Parse a scenario file; `id: "x"` needs no `Some(..)` wrapper
+    pub fn parse(text: &str) -> Result<Self, DslError> {
+        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        Ok(options.from_str(text)?)
+    }
+
+    /// Resolve symbolic references into a runnable scenario
+    pub fn compile(&self) -> Result<Scenario, DslError> {
+        let mut scenario = Scenario::new(self.name.clone());
+        let mut ids = HashMap::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let action = Resolver {
+                ids: ids.clone(),
+                steps: &scenario.steps,
+                step: i,
+                limit: i,
+            }
+            .action(&step.op)?;
+            scenario.push(Step {
+                action,
+                expect: step.expect,
+            });
+            if let Some(id) = &step.id {
+                if ids.insert(id.as_str(), i).is_some() {
+                    return Err(DslError::Duplicate {
+                        step: i,
+                        id: id.clone(),
+                    });
+                }
+            }
+        }
+        for (i, step) in self.cleanup.iter().enumerate() {
+            if step.id.is_some() {
+                return Err(DslError::CleanupId { step: i });
+            }
+            let action = Resolver {
+                ids: ids.clone(),
+                steps: &scenario.steps,
+                step: i,
+                limit: scenario.steps.len(),
+            }
+            .action(&step.op)?;
+            scenario.cleanup.push(Step {
+                action,
+                expect: step.expect,
+            });
+        }
+        Ok(scenario)
+    }
+
+    /// Express a scenario in the file format, giving every step whose
+    /// handle is referenced an id of the form `sN`
+    pub fn from_scenario(scenario: &Scenario) -> Self {
+        let fh = |r: &FhRef| match r {
+            FhRef::Root => "root".to_string(),
+            FhRef::Step(i) => format!("s{}", i),
+        };
+        let st = |r: &StateRef| match r {
+            StateRef::Anonymous => "anonymous".to_string(),
+            StateRef::Step(i) => format!("s{}", i),
+        };
+        let op = |a: &Action| match a {
+            Action::Mkdir { dir, name } => Op::Mkdir {
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Create { dir, name } => Op::Create {
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Lookup { dir, name } => Op::Lookup {
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Rename {
+                from_dir,
+                from_name,
+                to_dir,
+                to_name,
+            } => Op::Rename {
+                from_dir: fh(from_dir),
+                from_name: from_name.clone(),
+                to_dir: fh(to_dir),
+                to_name: to_name.clone(),
+            },
+            Action::Link { file, dir, name } => Op::Link {
+                file: fh(file),
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Remove { dir, name } => Op::Remove {
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Rmdir { dir, name } => Op::Rmdir {
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Open { dir, name } => Op::Open {
+                dir: fh(dir),
+                name: name.clone(),
+            },
+            Action::Read {
+                file,
+                stateid,
+                offset,
+                count,
+            } => Op::Read {
+                file: fh(file),
+                stateid: st(stateid),
+                offset: *offset,
+                count: *count,
+            },
+            Action::Write {
+                file,
+                stateid,
+                offset,
+                data,
+            } => Op::Write {
+                file: fh(file),
+                stateid: st(stateid),
+                offset: *offset,
+                data: data.clone(),
+            },
+            Action::Close { file, stateid } => Op::Close {
+                file: fh(file),
+                stateid: st(stateid),
+            },
+        };
+        let convert = |steps: &[Step], ids: bool| {
+            steps
+                .iter()
+                .enumerate()
+                .map(|(i, s)| DslStep {
+                    id: (ids && s.action.produces_fh()).then(|| format!("s{}", i)),
+                    op: op(&s.action),
+                    expect: s.expect,
+                })
+                .collect()
+        };
+        Self {
+            name: scenario.name.clone(),
+            steps: convert(&scenario.steps, true),
+            cleanup: convert(&scenario.cleanup, false),
+        }
+    }
+
+    pub fn to_ron(&self) -> String {
+        let config = ron::ser::PrettyConfig::default().extensions(Extensions::IMPLICIT_SOME);
+        ron::ser::to_string_pretty(self, config).expect("scenario files always serialize")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = r##"(
+        name: "write_after_rename",
+        steps: [
+            (id: "dir", op: Mkdir(dir: "root", name: "fuzz")),
+            (id: "f", op: Open(dir: "dir", name: "victim")),
+            (op: Rename(from_dir: "dir", from_name: "victim", to_dir: "dir", to_name: "moved")),
+            (op: Write(file: "#1", stateid: "f", offset: 0, data: "hello"), expect: Error(10025)),
+        ],
+        cleanup: [(op: Rmdir(dir: "root", name: "fuzz"), expect: Either)],
+    )"##;
+
+    #[test]
+    fn test_compile_resolves_references() {
+        let s = ScenarioFile::parse(EXAMPLE).unwrap().compile().unwrap();
+        assert_eq!(
+            s.steps[3],
+            Step {
+                action: Action::Write {
+                    file: FhRef::Step(1),
+                    stateid: StateRef::Step(1),
+                    offset: 0,
+                    data: b"hello".to_vec(),
+                },
+                expect: Outcome::Error(10025),
+            }
+        );
+        assert_eq!(s.cleanup[0].expect, Outcome::Either);
+    }
+
+    #[test]
+    fn test_compile_rejects_bad_references() {
+        let bad = |steps: &str| {
+            let text = format!("(name: \"x\", steps: [{}])", steps);
+            ScenarioFile::parse(&text).unwrap().compile().unwrap_err()
+        };
+        assert!(matches!(
+            bad(r#"(op: Mkdir(dir: "nope", name: "a"))"#),
+            DslError::Unknown { .. }
+        ));
+        assert!(matches!(
+            bad(r#"(id: "a", op: Mkdir(dir: "a", name: "a"))"#),
+            DslError::Unknown { .. }
+        ));
+        assert!(matches!(
+            bad(r##"(op: Mkdir(dir: "#0", name: "a"))"##),
+            DslError::Forward { .. }
+        ));
+        assert!(matches!(
+            bad(
+                r#"(id: "d", op: Mkdir(dir: "root", name: "a")), (op: Close(file: "d", stateid: "d"))"#
+            ),
+            DslError::Produces { .. }
+        ));
+    }
+
+    #[test]
+    fn test_from_scenario_roundtrip() {
+        let original = super::super::deep_tree(3, 8);
+        let file = ScenarioFile::from_scenario(&original);
+        let text = file.to_ron();
+        let back = ScenarioFile::parse(&text).unwrap().compile().unwrap();
+        assert_eq!(back, original);
+    }
+}
@@ -0,0 +1,685 @@
+//! Filesystem scenarios
+//!
+//! A scenario is an ordered list of namespace operations whose targets are
+//! either the export root or the handle produced by an earlier step, plus
+//! the cleanup steps that undo it. Scenarios are protocol-neutral; an
+//! executor turns each action into the matching v3 procedure or v4 op,
+//! as `run` does over v3. The `dsl` module loads hand-written scenario
+//! files.
+
+use crate::preset::Strategy;
+use serde::{Deserialize, Serialize};
+
+pub mod dsl;
+pub mod run;
+
+/// Default maximum component length (NAME_MAX on Linux and most servers)
+pub const NAME_MAX: usize = 255;
+
+/// Common PATH_MAX, also used as an upper bound for hostile single names
+pub const PATH_MAX: usize = 4096;
+
+/// Typical per-inode hard link limits: ext2/3, ext4, btrfs/ZFS-ish, XFS
+pub const LINK_MAX_TYPICAL: &[u32] = &[32_000, 65_000, 65_535, 2_147_483_647];
+
+/// Most links `hardlink_saturation` is run with; a filesystem that
+/// allows more is not saturated
+pub const LINK_MAX_RUN: u32 = 65_535;
+
+/// Levels of the built-in deep tree and long path
+pub const TREE_DEPTH: usize = 1024;
+pub const PATH_DEPTH: usize = 32;
+
+/// Status codes shared by NFSv3 (nfsstat3) and NFSv4 (nfsstat4)
+pub mod nfs_error {
+    pub const EXIST: u32 = 17;
+    pub const INVAL: u32 = 22;
+    pub const MLINK: u32 = 31;
+    pub const NAMETOOLONG: u32 = 63;
+    pub const NOTEMPTY: u32 = 66;
+}
+
+/// Names and data as UTF-8 strings when possible, `hex:<bytes>` otherwise
+pub(crate) mod name_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(name: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(name) {
+            Ok(text) if !text.starts_with("hex:") => s.serialize_str(text),
+            _ => s.serialize_str(&format!("hex:{}", hex::encode(name))),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(d)?;
+        match text.strip_prefix("hex:") {
+            Some(h) => hex::decode(h).map_err(serde::de::Error::custom),
+            None => Ok(text.into_bytes()),
+        }
+    }
+}
+
+/// Where a step's filehandle comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FhRef {
+    /// The export root
+    Root,
+    /// The handle produced by an earlier step (index into `steps`)
+    Step(usize),
+}
+
+/// Where a step's stateid comes from (v4; ignored by v3 executors)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateRef {
+    /// The all-zeros anonymous stateid
+    Anonymous,
+    /// The open stateid produced by an earlier `Open` step
+    Step(usize),
+}
+
+/// A namespace or file operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    /// Produces the new directory's handle
+    Mkdir {
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    /// Produces the new file's handle
+    Create {
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    /// Produces the looked-up handle
+    Lookup {
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Rename {
+        from_dir: FhRef,
+        #[serde(with = "name_serde")]
+        from_name: Vec<u8>,
+        to_dir: FhRef,
+        #[serde(with = "name_serde")]
+        to_name: Vec<u8>,
+    },
+    Link {
+        file: FhRef,
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Remove {
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Rmdir {
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    /// Produces the file's handle and an open stateid (CREATE/LOOKUP on v3)
+    Open {
+        dir: FhRef,
+        #[serde(with = "name_serde")]
+        name: Vec<u8>,
+    },
+    Read {
+        file: FhRef,
+        stateid: StateRef,
+        offset: u64,
+        count: u32,
+    },
+    Write {
+        file: FhRef,
+        stateid: StateRef,
+        offset: u64,
+        #[serde(with = "name_serde")]
+        data: Vec<u8>,
+    },
+    Close {
+        file: FhRef,
+        stateid: StateRef,
+    },
+}
+
+impl Action {
+    /// Whether the step's handle can be referenced by later steps
+    pub fn produces_fh(&self) -> bool {
+        matches!(
+            self,
+            Action::Mkdir { .. }
+                | Action::Create { .. }
+                | Action::Lookup { .. }
+                | Action::Open { .. }
+        )
+    }
+}
+
+/// Expected result of a step on a conforming server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// Either is acceptable (implementation-defined limits)
+    Either,
+    /// Must fail with this status (see `nfs_error`)
+    Error(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Step {
+    pub action: Action,
+    pub expect: Outcome,
+}
+
+impl Step {
+    pub fn ok(action: Action) -> Self {
+        Self {
+            action,
+            expect: Outcome::Success,
+        }
+    }
+
+    pub fn fails(action: Action) -> Self {
+        Self {
+            action,
+            expect: Outcome::Failure,
+        }
+    }
+
+    pub fn either(action: Action) -> Self {
+        Self {
+            action,
+            expect: Outcome::Either,
+        }
+    }
+
+    pub fn error(action: Action, status: u32) -> Self {
+        Self {
+            action,
+            expect: Outcome::Error(status),
+        }
+    }
+}
+
+/// A named scenario with the steps that undo it
+///
+/// Cleanup runs in order after `steps`, even if some steps failed, and may
+/// reference handles from `steps`. Cleanup failures for objects a failed
+/// step never created are expected and ignored by executors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+    pub cleanup: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            steps: Vec::new(),
+            cleanup: Vec::new(),
+        }
+    }
+
+    /// Append a step, returning a reference to its handle
+    pub fn push(&mut self, step: Step) -> FhRef {
+        self.steps.push(step);
+        FhRef::Step(self.steps.len() - 1)
+    }
+}
+
+/// A unique name of exactly `len` bytes (`len` >= 1)
+///
+/// Names start with the index so siblings differ, then pad with `fill`.
+pub fn component(index: usize, len: usize, fill: u8) -> Vec<u8> {
+    let mut name = format!("{:x}_", index).into_bytes();
+    name.truncate(len);
+    name.resize(len, fill);
+    name
+}
+
+/// A chain of `depth` nested directories under `base`, each named
+/// `component(level, name_len)`; returns the handle of each level
+fn mkdir_chain(s: &mut Scenario, base: FhRef, depth: usize, name_len: usize) -> Vec<FhRef> {
+    let mut levels = Vec::with_capacity(depth);
+    let mut parent = base;
+    for level in 0..depth {
+        let name = component(level, name_len, b'd');
+        // Servers may cap depth or total path length; both are fine
+        parent = s.push(Step::either(Action::Mkdir { dir: parent, name }));
+        levels.push(parent);
+    }
+    levels
+}
+
+/// Remove a chain built by `mkdir_chain`, deepest first
+fn rmdir_chain(s: &mut Scenario, base: FhRef, levels: &[FhRef], name_len: usize) {
+    for level in (0..levels.len()).rev() {
+        let dir = if level == 0 { base } else { levels[level - 1] };
+        s.cleanup.push(Step::either(Action::Rmdir {
+            dir,
+            name: component(level, name_len, b'd'),
+        }));
+    }
+}
+
+/// Directory tree `depth` levels deep, looked up level by level from the
+/// root, with the deepest directory renamed to the top and back
+pub fn deep_tree(depth: usize, name_len: usize) -> Scenario {
+    let mut s = Scenario::new(format!("deep_tree_{}x{}", depth, name_len));
+    let top = s.push(Step::ok(Action::Mkdir {
+        dir: FhRef::Root,
+        name: b"fuzz_deep_tree".to_vec(),
+    }));
+    let levels = mkdir_chain(&mut s, top, depth, name_len);
+
+    // Walk the whole chain by name
+    let mut dir = top;
+    for level in 0..depth {
+        dir = s.push(Step::either(Action::Lookup {
+            dir,
+            name: component(level, name_len, b'd'),
+        }));
+    }
+
+    if depth >= 2 {
+        let deepest = depth - 1;
+        let deepest_parent = levels[deepest - 1];
+        let deepest_name = component(deepest, name_len, b'd');
+        s.push(Step::either(Action::Rename {
+            from_dir: deepest_parent,
+            from_name: deepest_name.clone(),
+            to_dir: top,
+            to_name: b"hoisted".to_vec(),
+        }));
+        // Moving a directory under its own descendant must fail
+        s.push(Step::fails(Action::Rename {
+            from_dir: top,
+            from_name: component(0, name_len, b'd'),
+            to_dir: deepest_parent,
+            to_name: b"loop".to_vec(),
+        }));
+        s.push(Step::either(Action::Rename {
+            from_dir: top,
+            from_name: b"hoisted".to_vec(),
+            to_dir: deepest_parent,
+            to_name: deepest_name,
+        }));
+    }
+
+    rmdir_chain(&mut s, top, &levels, name_len);
+    s.cleanup.push(Step::either(Action::Rmdir {
+        dir: FhRef::Root,
+        name: b"fuzz_deep_tree".to_vec(),
+    }));
+    s
+}
+
+/// Files and directories named at exactly `name_max`, `name_max + 1` and
+/// PATH_MAX bytes, looked up and renamed between each other
+pub fn long_names(name_max: usize) -> Scenario {
+    let mut s = Scenario::new(format!("long_names_{}", name_max));
+    let top = s.push(Step::ok(Action::Mkdir {
+        dir: FhRef::Root,
+        name: b"fuzz_long_names".to_vec(),
+    }));
+
+    let at_max = component(0, name_max, b'f');
+    let over = component(1, name_max + 1, b'f');
+    let huge = component(2, PATH_MAX, b'f');
+    let dir_at_max = component(3, name_max, b'd');
+
+    s.push(Step::ok(Action::Create {
+        dir: top,
+        name: at_max.clone(),
+    }));
+    s.push(Step::fails(Action::Create {
+        dir: top,
+        name: over.clone(),
+    }));
+    s.push(Step::fails(Action::Create {
+        dir: top,
+        name: huge.clone(),
+    }));
+    let subdir = s.push(Step::ok(Action::Mkdir {
+        dir: top,
+        name: dir_at_max.clone(),
+    }));
+
+    for name in [&at_max, &over, &huge] {
+        let expect = if name.len() <= name_max {
+            Outcome::Success
+        } else {
+            Outcome::Failure
+        };
+        s.push(Step {
+            action: Action::Lookup {
+                dir: top,
+                name: name.clone(),
+            },
+            expect,
+        });
+    }
+
+    // Max-length file into the max-length directory, then back under an
+    // over-long name (must fail) and its original name
+    s.push(Step::ok(Action::Rename {
+        from_dir: top,
+        from_name: at_max.clone(),
+        to_dir: subdir,
+        to_name: at_max.clone(),
+    }));
+    s.push(Step::fails(Action::Rename {
+        from_dir: subdir,
+        from_name: at_max.clone(),
+        to_dir: top,
+        to_name: over.clone(),
+    }));
+    s.push(Step::fails(Action::Rename {
+        from_dir: subdir,
+        from_name: at_max.clone(),
+        to_dir: top,
+        to_name: huge.clone(),
+    }));
+    s.push(Step::ok(Action::Rename {
+        from_dir: subdir,
+        from_name: at_max.clone(),
+        to_dir: top,
+        to_name: at_max.clone(),
+    }));
+
+    for name in [&at_max, &over, &huge] {
+        s.cleanup.push(Step::either(Action::Remove {
+            dir: top,
+            name: name.clone(),
+        }));
+    }
+    s.cleanup.push(Step::either(Action::Rmdir {
+        dir: top,
+        name: dir_at_max,
+    }));
+    s.cleanup.push(Step::either(Action::Rmdir {
+        dir: FhRef::Root,
+        name: b"fuzz_long_names".to_vec(),
+    }));
+    s
+}
+
+/// A deep tree of max-length components, so the total path passes
+/// PATH_MAX many times over, with a file renamed from the bottom to the top
+pub fn long_path(depth: usize, name_max: usize) -> Scenario {
+    let mut s = Scenario::new(format!("long_path_{}x{}", depth, name_max));
+    let top = s.push(Step::ok(Action::Mkdir {
+        dir: FhRef::Root,
+        name: b"fuzz_long_path".to_vec(),
+    }));
+    let levels = mkdir_chain(&mut s, top, depth, name_max);
+    let bottom = *levels.last().unwrap_or(&top);
+    let file = component(depth, name_max, b'f');
+
+    s.push(Step::either(Action::Create {
+        dir: bottom,
+        name: file.clone(),
+    }));
+    s.push(Step::either(Action::Lookup {
+        dir: bottom,
+        name: file.clone(),
+    }));
+    s.push(Step::either(Action::Rename {
+        from_dir: bottom,
+        from_name: file.clone(),
+        to_dir: top,
+        to_name: file.clone(),
+    }));
+
+    s.cleanup.push(Step::either(Action::Remove {
+        dir: top,
+        name: file.clone(),
+    }));
+    s.cleanup.push(Step::either(Action::Remove {
+        dir: bottom,
+        name: file,
+    }));
+    rmdir_chain(&mut s, top, &levels, name_max);
+    s.cleanup.push(Step::either(Action::Rmdir {
+        dir: FhRef::Root,
+        name: b"fuzz_long_path".to_vec(),
+    }));
+    s
+}
+
+/// Hard links on one inode up to `link_max` and `overshoot` beyond it,
+/// then unlink/relink and rename at the boundary
+///
+/// `link_max` is the target filesystem's limit (see `LINK_MAX_TYPICAL`);
+/// the original name counts as the first link.
+pub fn hardlink_saturation(link_max: u32, overshoot: u32) -> Scenario {
+    let mut s = Scenario::new(format!("hardlink_saturation_{}", link_max));
+    let top = s.push(Step::ok(Action::Mkdir {
+        dir: FhRef::Root,
+        name: b"fuzz_hardlinks".to_vec(),
+    }));
+    let other = s.push(Step::ok(Action::Mkdir {
+        dir: top,
+        name: b"other".to_vec(),
+    }));
+    let file = s.push(Step::ok(Action::Create {
+        dir: top,
+        name: b"target".to_vec(),
+    }));
+    let link_name = |i: u32| component(i as usize, 12, b'l');
+    let link = |i: u32| Action::Link {
+        file,
+        dir: top,
+        name: link_name(i),
+    };
+
+    // Links 1..link_max bring nlink to exactly link_max
+    for i in 1..link_max {
+        s.push(Step::ok(link(i)));
+    }
+    for i in link_max..link_max.saturating_add(overshoot) {
+        s.push(Step::error(link(i), nfs_error::MLINK));
+    }
+
+    let last = link_max - 1;
+    s.push(Step::error(
+        Action::Link {
+            file,
+            dir: other,
+            name: b"cross_dir".to_vec(),
+        },
+        nfs_error::MLINK,
+    ));
+    // Existing name takes precedence or not; either error is valid
+    s.push(Step::fails(Action::Link {
+        file,
+        dir: top,
+        name: link_name(last),
+    }));
+    // Renaming one link of an inode onto another is a successful no-op
+    s.push(Step::ok(Action::Rename {
+        from_dir: top,
+        from_name: link_name(last),
+        to_dir: top,
+        to_name: b"target".to_vec(),
+    }));
+    // Dropping one link frees exactly one slot
+    s.push(Step::ok(Action::Remove {
+        dir: top,
+        name: link_name(last),
+    }));
+    s.push(Step::ok(Action::Link {
+        file,
+        dir: other,
+        name: b"relinked".to_vec(),
+    }));
+    s.push(Step::error(
+        Action::Link {
+            file,
+            dir: other,
+            name: b"one_too_many".to_vec(),
+        },
+        nfs_error::MLINK,
+    ));
+    s.push(Step::ok(Action::Rename {
+        from_dir: other,
+        from_name: b"relinked".to_vec(),
+        to_dir: top,
+        to_name: link_name(last),
+    }));
+    s.push(Step::error(
+        Action::Rmdir {
+            dir: FhRef::Root,
+            name: b"fuzz_hardlinks".to_vec(),
+        },
+        nfs_error::NOTEMPTY,
+    ));
+
+    for i in 1..link_max.saturating_add(overshoot) {
+        s.cleanup.push(Step::either(Action::Remove {
+            dir: top,
+            name: link_name(i),
+        }));
+    }
+    for (dir, name) in [
+        (other, &b"relinked"[..]),
+        (other, b"cross_dir"),
+        (other, b"one_too_many"),
+        (top, b"target"),
+    ] {
+        s.cleanup.push(Step::either(Action::Remove {
+            dir,
+            name: name.to_vec(),
+        }));
+    }
+    s.cleanup.push(Step::either(Action::Rmdir {
+        dir: top,
+        name: b"other".to_vec(),
+    }));
+    s.cleanup.push(Step::either(Action::Rmdir {
+        dir: FhRef::Root,
+        name: b"fuzz_hardlinks".to_vec(),
+    }));
+    s
+}
+
+/// Campaign strategies run as the scenarios of [`builtin`]
+pub const STRATEGIES: &[Strategy] = &[Strategy::DeepTree, Strategy::LongNames, Strategy::HardLinks];
+
+/// The built-in scenarios of a campaign strategy, on a filesystem with
+/// the `link_max` and `name_max` PATHCONF gives
+pub fn builtin(strategy: Strategy, link_max: u32, name_max: usize) -> Vec<Scenario> {
+    match strategy {
+        Strategy::DeepTree => vec![deep_tree(TREE_DEPTH, 8), long_path(PATH_DEPTH, name_max)],
+        Strategy::LongNames => vec![long_names(name_max)],
+        Strategy::HardLinks if (2..=LINK_MAX_RUN).contains(&link_max) => {
+            vec![hardlink_saturation(link_max, 3)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_component_exact_length() {
+        assert_eq!(component(0x1a, 6, b'x'), b"1a_xxx".to_vec());
+        assert_eq!(component(0x1a, 1, b'x'), b"1".to_vec());
+        assert_eq!(component(7, NAME_MAX + 1, b'x').len(), NAME_MAX + 1);
+    }
+
+    #[test]
+    fn test_deep_tree_cleanup_is_deepest_first() {
+        let s = deep_tree(3000, 8);
+        // top + mkdirs + lookups + three renames
+        assert_eq!(s.steps.len(), 1 + 3000 + 3000 + 3);
+        assert_eq!(s.cleanup.len(), 3001);
+        match &s.cleanup[0].action {
+            Action::Rmdir { dir, name } => {
+                assert_eq!(*dir, FhRef::Step(2999));
+                assert_eq!(name, &component(2999, 8, b'd'));
+            }
+            other => panic!("unexpected first cleanup {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_json_roundtrip_names() {
+        let mut s = Scenario::new("rt");
+        let d = s.push(Step::ok(Action::Mkdir {
+            dir: FhRef::Root,
+            name: b"plain".to_vec(),
+        }));
+        s.push(Step::error(
+            Action::Create {
+                dir: d,
+                name: vec![0xff, 0, b'/'],
+            },
+            nfs_error::INVAL,
+        ));
+        let json = serde_json::to_string(&s).unwrap();
+        assert!(json.contains("\"plain\"") && json.contains("\"hex:ff002f\""));
+        assert_eq!(serde_json::from_str::<Scenario>(&json).unwrap(), s);
+    }
+
+    #[test]
+    fn test_hardlink_saturation_boundary() {
+        let s = hardlink_saturation(10, 3);
+        let links: Vec<Outcome> = s
+            .steps
+            .iter()
+            .filter_map(|st| match &st.action {
+                Action::Link { name, .. } if name.ends_with(b"l") => Some(st.expect),
+                _ => None,
+            })
+            .collect();
+        // 9 successful links, 3 over the limit, then one onto an existing name
+        assert_eq!(links.len(), 13);
+        assert_eq!(links[12], Outcome::Failure);
+        assert!(links[..9].iter().all(|&o| o == Outcome::Success));
+        assert!(links[9..12]
+            .iter()
+            .all(|&o| o == Outcome::Error(nfs_error::MLINK)));
+    }
+
+    #[test]
+    fn test_builtin_scenarios_of_strategies() {
+        for &strategy in STRATEGIES {
+            assert!(!builtin(strategy, 1000, NAME_MAX).is_empty());
+        }
+        assert_eq!(builtin(Strategy::DeepTree, 1000, NAME_MAX).len(), 2);
+        assert!(builtin(Strategy::HardLinks, 2_147_483_647, NAME_MAX).is_empty());
+        assert!(builtin(Strategy::AttrSweep, 1000, NAME_MAX).is_empty());
+    }
+
+    #[test]
+    fn test_long_names_expectations() {
+        let s = long_names(NAME_MAX);
+        let creates: Vec<_> = s
+            .steps
+            .iter()
+            .filter_map(|st| match &st.action {
+                Action::Create { name, .. } => Some((name.len(), st.expect)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            creates,
+            vec![
+                (NAME_MAX, Outcome::Success),
+                (NAME_MAX + 1, Outcome::Failure),
+                (PATH_MAX, Outcome::Failure),
+            ]
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! Running scenarios over v3
+//!
+//! [`run`] sends each step as the matching v3 call, keeps the handle each
+//! produces for the steps that name it, and checks the status against the
+//! step's [`Outcome`]. The cleanup steps run after, however the steps
+//! went. v3 has no opens, so `Open` is an unchecked CREATE, `Close` sends
+//! nothing, and stateids are ignored. A step on a handle its step never
+//! produced is skipped rather than sent with an empty one.
+
+use super::{Action, FhRef, Outcome, Scenario, Step};
+use crate::auth::Identity;
+use crate::boundary::{decode_linkmax3, decode_pathconf3};
+use crate::connection::{ConnectionError, NfsConnection};
+use crate::nfsv3::{self, stable_how, CreateHow, Sattr3};
+use crate::populate::{created, looked_up};
+use crate::rpc::{RpcError, RpcReply};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+#[derive(Error, Debug)]
+pub enum RunError {
+    #[error(transparent)]
+    Connection(#[from] ConnectionError),
+    #[error(transparent)]
+    Rpc(#[from] RpcError),
+    #[error("step {step}: reply does not decode")]
+    Decode { step: usize },
+}
+
+/// What came of one step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StepResult {
+    pub expect: Outcome,
+    /// nfsstat3 of the reply; none if nothing was sent
+    pub status: Option<u32>,
+}
+
+impl StepResult {
+    /// Whether the status is what the step expected; none if skipped
+    pub fn met(&self) -> Option<bool> {
+        let status = self.status?;
+        Some(match self.expect {
+            Outcome::Success => status == nfsv3::stat::OK,
+            Outcome::Failure => status != nfsv3::stat::OK,
+            Outcome::Either => true,
+            Outcome::Error(expected) => status == expected,
+        })
+    }
+}
+
+/// A scenario's steps and cleanup as they went
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Report {
+    pub name: String,
+    pub steps: Vec<StepResult>,
+    pub cleanup: Vec<StepResult>,
+}
+
+impl Report {
+    /// Steps, by index, whose status was not what they expected
+    pub fn unmet(&self) -> impl Iterator<Item = (usize, &StepResult)> {
+        let steps = self.steps.iter().enumerate();
+        steps.filter(|(_, r)| r.met() == Some(false))
+    }
+
+    /// How many steps went out rather than being skipped
+    pub fn sent(&self) -> usize {
+        let all = self.steps.iter().chain(&self.cleanup);
+        all.filter(|r| r.status.is_some()).count()
+    }
+}
+
+/// Sends the steps of one scenario
+struct Runner<'a, S> {
+    conn: &'a mut NfsConnection<S>,
+    identity: &'a Identity,
+    root: &'a [u8],
+    /// Handle each step produced, by index
+    handles: Vec<Option<Vec<u8>>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Runner<'_, S> {
+    fn fh(&self, r: FhRef) -> Option<Vec<u8>> {
+        match r {
+            FhRef::Root => Some(self.root.to_vec()),
+            FhRef::Step(i) => self.handles.get(i).cloned().flatten(),
+        }
+    }
+
+    async fn results(&mut self, call: nfsv3::Call, step: usize) -> Result<Vec<u8>, RunError> {
+        let reply = self.conn.call(&call.message(self.identity)).await?;
+        let results = RpcReply::parse(&reply)?.into_results()?;
+        if results.len() < 4 {
+            return Err(RunError::Decode { step });
+        }
+        Ok(results.to_vec())
+    }
+
+    /// The handle `call` made of `name` in `dir`, looked up if the reply
+    /// did not carry it
+    async fn make(
+        &mut self,
+        call: nfsv3::Call,
+        dir: &[u8],
+        name: &[u8],
+        step: usize,
+    ) -> Result<(u32, Option<Vec<u8>>), RunError> {
+        let results = self.results(call, step).await?;
+        match created(&results).ok_or(RunError::Decode { step })? {
+            (nfsv3::stat::OK, None) => {
+                let results = self.results(nfsv3::lookup(dir, name), step).await?;
+                let (_, fh) = looked_up(&results).ok_or(RunError::Decode { step })?;
+                Ok((nfsv3::stat::OK, fh))
+            }
+            made => Ok(made),
+        }
+    }
+
+    /// The status of step number `index` and the handle it produced;
+    /// none for a step that sends nothing
+    async fn step(
+        &mut self,
+        step: &Step,
+        index: usize,
+    ) -> Result<Option<(u32, Option<Vec<u8>>)>, RunError> {
+        let Some(call) = self.call(&step.action) else {
+            return Ok(None);
+        };
+        let made = match &step.action {
+            Action::Mkdir { dir, name }
+            | Action::Create { dir, name }
+            | Action::Open { dir, name } => {
+                let dir = self.fh(*dir).unwrap_or_default();
+                self.make(call, &dir, name, index).await?
+            }
+            Action::Lookup { .. } => {
+                let results = self.results(call, index).await?;
+                looked_up(&results).ok_or(RunError::Decode { step: index })?
+            }
+            _ => {
+                let results = self.results(call, index).await?;
+                (u32::from_be_bytes(results[..4].try_into().unwrap()), None)
+            }
+        };
+        Ok(Some(made))
+    }
+
+    /// The v3 call of `action`; none for one that sends nothing or names
+    /// a handle never produced
+    fn call(&self, action: &Action) -> Option<nfsv3::Call> {
+        Some(match action {
+            Action::Mkdir { dir, name } => {
+                nfsv3::mkdir(&self.fh(*dir)?, name, &Sattr3::mode(0o755))
+            }
+            Action::Create { dir, name } => {
+                let how = CreateHow::Guarded(Sattr3::mode(0o644));
+                nfsv3::create(&self.fh(*dir)?, name, &how)
+            }
+            Action::Open { dir, name } => {
+                let how = CreateHow::Unchecked(Sattr3::mode(0o644));
+                nfsv3::create(&self.fh(*dir)?, name, &how)
+            }
+            Action::Lookup { dir, name } => nfsv3::lookup(&self.fh(*dir)?, name),
+            Action::Rename {
+                from_dir,
+                from_name,
+                to_dir,
+                to_name,
+            } => nfsv3::rename(&self.fh(*from_dir)?, from_name, &self.fh(*to_dir)?, to_name),
+            Action::Link { file, dir, name } => {
+                nfsv3::link(&self.fh(*file)?, &self.fh(*dir)?, name)
+            }
+            Action::Remove { dir, name } => nfsv3::remove(&self.fh(*dir)?, name),
+            Action::Rmdir { dir, name } => nfsv3::rmdir(&self.fh(*dir)?, name),
+            Action::Read {
+                file,
+                offset,
+                count,
+                ..
+            } => nfsv3::read(&self.fh(*file)?, *offset, *count),
+            Action::Write {
+                file, offset, data, ..
+            } => {
+                let count = data.len() as u32;
+                nfsv3::write(
+                    &self.fh(*file)?,
+                    *offset,
+                    count,
+                    stable_how::FILE_SYNC,
+                    data,
+                )
+            }
+            Action::Close { .. } => return None,
+        })
+    }
+}
+
+/// linkmax and name_max of the filesystem `fh` is on, from PATHCONF;
+/// none if the server would not say
+pub async fn pathconf<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut NfsConnection<S>,
+    identity: &Identity,
+    fh: &[u8],
+) -> Result<Option<(u32, u64)>, RunError> {
+    let reply = conn.call(&nfsv3::pathconf(fh).message(identity)).await?;
+    let results = RpcReply::parse(&reply)?.into_results()?;
+    let name_max = decode_pathconf3(results).and_then(|limits| limits.maxname);
+    Ok(decode_linkmax3(results).zip(name_max))
+}
+
+/// Run `scenario` on `conn` under the export whose root handle is
+/// `root`, then its cleanup; a connection that fails stops the steps but
+/// not the cleanup, and is the error returned after it
+pub async fn run<S: AsyncRead + AsyncWrite + Unpin>(
+    conn: &mut NfsConnection<S>,
+    identity: &Identity,
+    root: &[u8],
+    scenario: &Scenario,
+) -> Result<Report, RunError> {
+    let mut runner = Runner {
+        conn,
+        identity,
+        root,
+        handles: vec![None; scenario.steps.len()],
+    };
+    let mut report = Report {
+        name: scenario.name.clone(),
+        ..Report::default()
+    };
+    let mut failed = None;
+    for (i, step) in scenario.steps.iter().enumerate() {
+        match runner.step(step, i).await {
+            Ok(made) => {
+                let (status, fh) = made.unzip();
+                runner.handles[i] = fh.flatten();
+                report.steps.push(StepResult {
+                    expect: step.expect,
+                    status,
+                });
+            }
+            Err(e) => {
+                failed = Some(e);
+                break;
+            }
+        }
+    }
+    for (i, step) in scenario.cleanup.iter().enumerate() {
+        match runner.step(step, scenario.steps.len() + i).await {
+            Ok(made) => report.cleanup.push(StepResult {
+                expect: step.expect,
+                status: made.map(|(status, _)| status),
+            }),
+            Err(e) => {
+                failed.get_or_insert(e);
+                break;
+            }
+        }
+    }
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(report),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::nfs_error;
+    use super::*;
+    use crate::connection::Timeouts;
+    use crate::hang::read_record;
+    use crate::xdr::{XdrDecoder, XdrEncoder};
+    use tokio::io::AsyncWriteExt;
+
+    /// A v3 server that makes every directory and file asked for, with the
+    /// name as its handle, finds nothing by LOOKUP and refuses to remove
+    /// directories that are not empty
+    async fn server(mut stream: tokio::io::DuplexStream) -> Vec<u32> {
+        let mut procedures = Vec::new();
+        let mut made: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+        while let Ok(call) = read_record(&mut stream).await {
+            let procedure = u32::from_be_bytes(call[20..24].try_into().unwrap());
+            procedures.push(procedure);
+            let mut dec = XdrDecoder::new(&call[24..]);
+            for _ in 0..2 {
+                dec.get_u32().unwrap();
+                dec.get_opaque().unwrap();
+            }
+            let mut enc = XdrEncoder::new();
+            match procedure {
+                nfsv3::proc::MKDIR | nfsv3::proc::CREATE => {
+                    let dir = dec.get_opaque().unwrap().to_vec();
+                    let name = dec.get_opaque().unwrap().to_vec();
+                    enc.put_u32(nfsv3::stat::OK);
+                    enc.put_bool(true);
+                    enc.put_opaque(&name);
+                    made.push((dir, name));
+                }
+                nfsv3::proc::RMDIR => {
+                    let dir = dec.get_opaque().unwrap();
+                    let name = dec.get_opaque().unwrap();
+                    match made.iter().any(|(parent, _)| parent == name) {
+                        true => enc.put_u32(nfs_error::NOTEMPTY),
+                        false => enc.put_u32(nfsv3::stat::OK),
+                    }
+                    made.retain(|m| (&m.0[..], &m.1[..]) != (dir, name));
+                }
+                nfsv3::proc::REMOVE => {
+                    let dir = dec.get_opaque().unwrap();
+                    let name = dec.get_opaque().unwrap();
+                    made.retain(|m| (&m.0[..], &m.1[..]) != (dir, name));
+                    enc.put_u32(nfsv3::stat::OK);
+                }
+                // NFS3ERR_NOENT
+                _ => enc.put_u32(2),
+            }
+            let mut reply = call[..4].to_vec();
+            reply.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+            reply.extend_from_slice(&[0; 8]);
+            reply.extend_from_slice(enc.as_bytes());
+            let mut record = (0x8000_0000 | reply.len() as u32).to_be_bytes().to_vec();
+            record.extend_from_slice(&reply);
+            stream.write_all(&record).await.unwrap();
+        }
+        procedures
+    }
+
+    #[tokio::test]
+    async fn test_steps_are_judged_and_cleanup_always_runs() {
+        let (client, service) = tokio::io::duplex(4096);
+        let serve = tokio::spawn(server(service));
+        let mut scenario = Scenario::new("t");
+        let top = scenario.push(Step::ok(Action::Mkdir {
+            dir: FhRef::Root,
+            name: b"top".to_vec(),
+        }));
+        scenario.push(Step::ok(Action::Create {
+            dir: top,
+            name: b"f".to_vec(),
+        }));
+        let missing = scenario.push(Step::ok(Action::Lookup {
+            dir: top,
+            name: b"gone".to_vec(),
+        }));
+        // Named on a handle the failed LOOKUP never produced
+        scenario.push(Step::ok(Action::Remove {
+            dir: missing,
+            name: b"x".to_vec(),
+        }));
+        scenario.push(Step::error(
+            Action::Rmdir {
+                dir: FhRef::Root,
+                name: b"top".to_vec(),
+            },
+            nfs_error::NOTEMPTY,
+        ));
+        scenario.cleanup.push(Step::either(Action::Remove {
+            dir: top,
+            name: b"f".to_vec(),
+        }));
+        scenario.cleanup.push(Step::ok(Action::Rmdir {
+            dir: FhRef::Root,
+            name: b"top".to_vec(),
+        }));
+
+        let mut conn = NfsConnection::new(client, Timeouts::default());
+        let identity = Identity::new(0, 0);
+        let report = run(&mut conn, &identity, b"root", &scenario).await.unwrap();
+        drop(conn);
+        let statuses: Vec<_> = report.steps.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [Some(0), Some(0), Some(2), None, Some(nfs_error::NOTEMPTY)]
+        );
+        let unmet: Vec<_> = report.unmet().map(|(i, _)| i).collect();
+        assert_eq!(unmet, [2]);
+        assert!(report.cleanup.iter().all(|r| r.met() == Some(true)));
+        assert_eq!(report.sent(), 6);
+        use nfsv3::proc::*;
+        let procedures = serve.await.unwrap();
+        assert_eq!(procedures, [MKDIR, CREATE, LOOKUP, RMDIR, REMOVE, RMDIR]);
+    }
+}
@@ -0,0 +1,272 @@
+//! Built-in argument seeds
+//!
+//! One valid argument body per NFSv3 procedure and per NFSv4 operation,
+//! embedded from `seeds/` so a campaign against a fresh target starts
+//! from well-formed inputs without a packet capture. Filehandles,
+//! stateids, client and session ids in the seeds are fixed placeholders
+//! (see `seeds/README.md`); campaigns substitute the values they obtain
+//! from the server.
+
+use crate::nfsv4::{self, Op};
+use crate::rpc::{next_xid, program, RpcCall};
+use crate::xdr::XdrEncoder;
+use bytes::BytesMut;
+
+/// The placeholder filehandle of the seeds: 32 bytes `00 01 .. 1f`
+pub const FILEHANDLE: [u8; 32] = {
+    let mut fh = [0; 32];
+    let mut i = 0;
+    while i < fh.len() {
+        fh[i] = i as u8;
+        i += 1;
+    }
+    fh
+};
+
+/// `args` with every encoded [`FILEHANDLE`] replaced by `fh`, encoded
+/// the same way; a v3 seed aimed at a real handle, such as MNT's
+pub fn with_filehandle(args: &[u8], fh: &[u8]) -> Vec<u8> {
+    let mut placeholder = XdrEncoder::new();
+    placeholder.put_opaque(&FILEHANDLE);
+    let placeholder = placeholder.as_bytes();
+    let mut real = XdrEncoder::new();
+    real.put_opaque(fh);
+    let real = real.as_bytes();
+    let mut out = Vec::with_capacity(args.len());
+    let mut i = 0;
+    while i < args.len() {
+        if args[i..].starts_with(placeholder) {
+            out.extend_from_slice(real);
+            i += placeholder.len();
+        } else {
+            out.push(args[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+/// Arguments for one procedure (v3) or operation (v4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seed {
+    /// NFS version, 3 or 4
+    pub version: u32,
+    /// v3 procedure number or v4 opcode
+    pub number: u32,
+    pub name: &'static str,
+    pub args: &'static [u8],
+}
+
+impl Seed {
+    /// The seed as a COMPOUND operation (v4 seeds only)
+    pub fn op(&self) -> Option<Op> {
+        (self.version == 4).then(|| Op::raw(self.number, self.args.to_vec()))
+    }
+
+    /// The seed as a record-marked AUTH_NONE call (v3 seeds only)
+    pub fn call(&self) -> Option<BytesMut> {
+        (self.version == 3).then(|| {
+            RpcCall::new(next_xid(), program::NFS, 3, self.number, true)
+                .with_auth_none()
+                .with_args(self.args)
+                .build()
+        })
+    }
+}
+
+macro_rules! seeds {
+    ($version:literal: $($number:expr => $name:literal),* $(,)?) => {
+        &[$(Seed {
+            version: $version,
+            number: $number,
+            name: $name,
+            args: include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/seeds/nfs",
+                stringify!($version),
+                "/",
+                $name,
+                ".xdr"
+            )),
+        }),*]
+    };
+}
+
+/// Seeds for every NFSv3 procedure (RFC 1813)
+pub const NFS3: &[Seed] = seeds!(3:
+    0 => "NULL",
+    1 => "GETATTR",
+    2 => "SETATTR",
+    3 => "LOOKUP",
+    4 => "ACCESS",
+    5 => "READLINK",
+    6 => "READ",
+    7 => "WRITE",
+    8 => "CREATE",
+    9 => "MKDIR",
+    10 => "SYMLINK",
+    11 => "MKNOD",
+    12 => "REMOVE",
+    13 => "RMDIR",
+    14 => "RENAME",
+    15 => "LINK",
+    16 => "READDIR",
+    17 => "READDIRPLUS",
+    18 => "FSSTAT",
+    19 => "FSINFO",
+    20 => "PATHCONF",
+    21 => "COMMIT",
+);
+
+/// Seeds for every NFSv4.0-4.2 operation
+pub const NFS4: &[Seed] = {
+    use nfsv4::op::*;
+    seeds!(4:
+        ACCESS => "ACCESS",
+        CLOSE => "CLOSE",
+        COMMIT => "COMMIT",
+        CREATE => "CREATE",
+        DELEGPURGE => "DELEGPURGE",
+        DELEGRETURN => "DELEGRETURN",
+        GETATTR => "GETATTR",
+        GETFH => "GETFH",
+        LINK => "LINK",
+        LOCK => "LOCK",
+        LOCKT => "LOCKT",
+        LOCKU => "LOCKU",
+        LOOKUP => "LOOKUP",
+        LOOKUPP => "LOOKUPP",
+        NVERIFY => "NVERIFY",
+        OPEN => "OPEN",
+        OPENATTR => "OPENATTR",
+        OPEN_CONFIRM => "OPEN_CONFIRM",
+        OPEN_DOWNGRADE => "OPEN_DOWNGRADE",
+        PUTFH => "PUTFH",
+        PUTPUBFH => "PUTPUBFH",
+        PUTROOTFH => "PUTROOTFH",
+        READ => "READ",
+        READDIR => "READDIR",
+        READLINK => "READLINK",
+        REMOVE => "REMOVE",
+        RENAME => "RENAME",
+        RENEW => "RENEW",
+        RESTOREFH => "RESTOREFH",
+        SAVEFH => "SAVEFH",
+        SECINFO => "SECINFO",
+        SETATTR => "SETATTR",
+        SETCLIENTID => "SETCLIENTID",
+        SETCLIENTID_CONFIRM => "SETCLIENTID_CONFIRM",
+        VERIFY => "VERIFY",
+        WRITE => "WRITE",
+        RELEASE_LOCKOWNER => "RELEASE_LOCKOWNER",
+        BACKCHANNEL_CTL => "BACKCHANNEL_CTL",
+        BIND_CONN_TO_SESSION => "BIND_CONN_TO_SESSION",
+        EXCHANGE_ID => "EXCHANGE_ID",
+        CREATE_SESSION => "CREATE_SESSION",
+        DESTROY_SESSION => "DESTROY_SESSION",
+        FREE_STATEID => "FREE_STATEID",
+        GET_DIR_DELEGATION => "GET_DIR_DELEGATION",
+        GETDEVICEINFO => "GETDEVICEINFO",
+        GETDEVICELIST => "GETDEVICELIST",
+        LAYOUTCOMMIT => "LAYOUTCOMMIT",
+        LAYOUTGET => "LAYOUTGET",
+        LAYOUTRETURN => "LAYOUTRETURN",
+        SECINFO_NO_NAME => "SECINFO_NO_NAME",
+        SEQUENCE => "SEQUENCE",
+        SET_SSV => "SET_SSV",
+        TEST_STATEID => "TEST_STATEID",
+        WANT_DELEGATION => "WANT_DELEGATION",
+        DESTROY_CLIENTID => "DESTROY_CLIENTID",
+        RECLAIM_COMPLETE => "RECLAIM_COMPLETE",
+        ALLOCATE => "ALLOCATE",
+        COPY => "COPY",
+        COPY_NOTIFY => "COPY_NOTIFY",
+        DEALLOCATE => "DEALLOCATE",
+        IO_ADVISE => "IO_ADVISE",
+        LAYOUTERROR => "LAYOUTERROR",
+        LAYOUTSTATS => "LAYOUTSTATS",
+        OFFLOAD_CANCEL => "OFFLOAD_CANCEL",
+        OFFLOAD_STATUS => "OFFLOAD_STATUS",
+        READ_PLUS => "READ_PLUS",
+        SEEK => "SEEK",
+        WRITE_SAME => "WRITE_SAME",
+        CLONE => "CLONE",
+    )
+};
+
+/// All seeds, or those of one NFS version
+pub fn seeds(version: Option<u32>) -> impl Iterator<Item = &'static Seed> {
+    NFS3.iter()
+        .chain(NFS4)
+        .filter(move |s| version.is_none_or(|v| s.version == v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::{attr, bitmap_from_bits, session::Sequence, sparse, Stateid};
+
+    #[test]
+    fn test_every_procedure_and_op_has_a_seed() {
+        let v3: Vec<u32> = NFS3.iter().map(|s| s.number).collect();
+        assert_eq!(v3, (0..=21).collect::<Vec<_>>());
+        let v4: Vec<u32> = NFS4.iter().map(|s| s.number).collect();
+        assert_eq!(
+            v4,
+            (nfsv4::op::ACCESS..=nfsv4::op::CLONE).collect::<Vec<_>>()
+        );
+        assert!(seeds(None).all(|s| s.args.len() % 4 == 0));
+        assert_eq!(seeds(Some(3)).count(), 22);
+    }
+
+    #[test]
+    fn test_seeds_match_encoders() {
+        let find = |name: &str| NFS4.iter().find(|s| s.name == name).unwrap().op().unwrap();
+        let stateid = Stateid::new(1, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]);
+        let mask = bitmap_from_bits(&[
+            attr::TYPE,
+            attr::SIZE,
+            attr::MODE,
+            attr::NUMLINKS,
+            attr::TIME_MODIFY,
+        ]);
+        assert_eq!(find("GETATTR"), nfsv4::getattr(&mask));
+        assert_eq!(find("READ"), nfsv4::read(&stateid, 0, 4096));
+        assert_eq!(
+            find("READDIR"),
+            nfsv4::readdir(0, &[0; 8], 1024, 4096, &[0x12])
+        );
+        assert_eq!(find("ALLOCATE"), sparse::allocate(&stateid, 0, 4096));
+        let seq = Sequence {
+            sessionid: [0x5a; 16],
+            sequenceid: 1,
+            slotid: 0,
+            highest_slotid: 0,
+            cachethis: false,
+        };
+        assert_eq!(find("SEQUENCE"), seq.op());
+    }
+
+    #[test]
+    fn test_v3_seed_call() {
+        let getattr = &NFS3[1];
+        assert!(getattr.op().is_none());
+        let call = getattr.call().unwrap();
+        // Record mark, header, AUTH_NONE cred and verf, then the fh3
+        assert_eq!(call.len(), 4 + 24 + 16 + 4 + 32);
+        assert_eq!(&call[24..28], &[0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn test_filehandle_placeholders_are_filled() {
+        let fh = [0xab; 20];
+        // RENAME names the placeholder twice, once per directory
+        let rename = NFS3.iter().find(|s| s.name == "RENAME").unwrap();
+        let filled = with_filehandle(rename.args, &fh);
+        assert_eq!(filled.len(), rename.args.len() - 2 * 12);
+        assert_eq!(&filled[..24], [&[0, 0, 0, 20][..], &fh].concat());
+        assert!(!filled.windows(32).any(|w| w == FILEHANDLE));
+        // NULL has no handle to fill
+        assert_eq!(with_filehandle(NFS3[0].args, &fh), NFS3[0].args);
+    }
+}
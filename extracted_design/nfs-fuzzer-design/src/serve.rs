@@ -0,0 +1,270 @@
+//! Malicious server for fuzzing NFS clients
+//!
+//! The mirror image of the campaign loop: the client under test, usually
+//! a kernel mounting from this host, talks to `serve` as if it were its
+//! server. Each call is passed on to a real upstream server, which
+//! answers it properly, and the reply goes through the mutation engine
+//! before the client sees it, so the client's decoding runs on replies
+//! that are well formed up to the damage. The RPC header in front of the
+//! results is left alone unless the header is in scope as well; the XID
+//! always is, or the client would drop the reply unread. Every damaged
+//! reply can be logged with its original and the mutation, so the one
+//! that took a client down can be served again.
+//!
+//! Replies to calls a selected [`Template`] answers are replaced with the
+//! template's forgery instead, every time, so a known client bug can be
+//! reproduced on demand; the call still goes upstream first.
+
+use crate::hang::read_record;
+use crate::lineage::Step;
+use crate::mutations::Engine;
+use crate::rpc::RpcReply;
+use crate::templates::Template;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::info;
+
+/// Which replies are damaged, and how much of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServeConfig {
+    /// Probability a reply is mutated
+    pub rate: f64,
+    /// Mutate the RPC header after the XID as well as the results
+    pub header: bool,
+    /// Forged replies served in place of the upstream's
+    pub templates: Vec<Template>,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        Self {
+            rate: 0.1,
+            header: false,
+            templates: Vec::new(),
+        }
+    }
+}
+
+/// Bytes at the start of `reply` that are never mutated: the XID and,
+/// unless `header`, everything before the results of a successful reply
+pub fn protected_len(reply: &[u8], header: bool) -> usize {
+    let xid = reply.len().min(4);
+    match RpcReply::parse(reply) {
+        Ok(r) if !header && r.is_success() => reply.len() - r.results.len(),
+        _ => xid,
+    }
+}
+
+/// Damages replies on their way to the client
+pub struct ReplyMutator {
+    engine: Arc<Engine>,
+    config: ServeConfig,
+    rng: StdRng,
+}
+
+impl ReplyMutator {
+    pub fn new(engine: Arc<Engine>, config: ServeConfig, seed: u64) -> Self {
+        Self {
+            engine,
+            config,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// `reply` to `call` as the client gets it, and the mutation or
+    /// template if one applied
+    pub fn apply(&mut self, call: &[u8], reply: &[u8]) -> (Vec<u8>, Option<Step>) {
+        for template in &self.config.templates {
+            if let Some(forged) = template.forge(call, reply) {
+                return (
+                    forged,
+                    Some(Step::new("template").with("name", template.name())),
+                );
+            }
+        }
+        if !self.rng.gen_bool(self.config.rate) {
+            return (reply.to_vec(), None);
+        }
+        let keep = protected_len(reply, self.config.header);
+        let mut rest = reply[keep..].to_vec();
+        let step = self.engine.mutate(&mut self.rng, &mut rest);
+        ([&reply[..keep], &rest[..]].concat(), step)
+    }
+}
+
+/// One damaged reply, as logged
+#[derive(Debug, Clone, Serialize)]
+pub struct Served {
+    /// Replies on the connection before this one
+    pub index: u64,
+    pub xid: u32,
+    pub step: Step,
+    /// Hex of the reply as the upstream sent it and as it was served
+    pub original: String,
+    pub served: String,
+}
+
+/// `body` as a single-fragment record
+fn record(body: &[u8]) -> Vec<u8> {
+    let mark = 0x8000_0000 | body.len() as u32;
+    [&mark.to_be_bytes()[..], body].concat()
+}
+
+/// Serve one client connection until either side hangs up: every call
+/// goes upstream and every reply back through `mutator`. Damaged replies
+/// go to `log` as JSON lines; returns how many there were.
+pub async fn handle<C, U>(
+    mut client: C,
+    mut upstream: U,
+    mutator: &mut ReplyMutator,
+    mut log: Option<&mut (dyn Write + Send)>,
+) -> io::Result<u64>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+    U: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut damaged = 0;
+    for index in 0.. {
+        let call = match read_record(&mut client).await {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            r => r?,
+        };
+        upstream.write_all(&record(&call)).await?;
+        let reply = match read_record(&mut upstream).await {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            r => r?,
+        };
+        let (served, step) = mutator.apply(&call, &reply);
+        client.write_all(&record(&served)).await?;
+        let Some(step) = step else {
+            continue;
+        };
+        damaged += 1;
+        if let Some(log) = log.as_mut() {
+            let entry = Served {
+                index,
+                xid: reply
+                    .get(..4)
+                    .map_or(0, |b| u32::from_be_bytes(b.try_into().unwrap())),
+                step,
+                original: hex::encode(&reply),
+                served: hex::encode(&served),
+            };
+            serde_json::to_writer(&mut *log, &entry)?;
+            writeln!(log)?;
+            log.flush()?;
+        }
+    }
+    Ok(damaged)
+}
+
+/// Connection `n`'s log under `dir`
+fn log_path(dir: &Path, n: u64) -> PathBuf {
+    dir.join(format!("conn_{:06}.jsonl", n))
+}
+
+/// Accept clients on `listener` and serve each from `upstream`;
+/// connection `n` mutates with seed `seed + n` and, with `log_dir` set,
+/// logs to `<log_dir>/conn_<n>.jsonl`
+pub async fn serve(
+    listener: TcpListener,
+    upstream: SocketAddr,
+    engine: Engine,
+    config: ServeConfig,
+    seed: u64,
+    log_dir: Option<PathBuf>,
+) -> io::Result<()> {
+    if let Some(dir) = &log_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    let engine = Arc::new(engine);
+    for n in 0.. {
+        let (client, peer) = listener.accept().await?;
+        let mut mutator = ReplyMutator::new(engine.clone(), config.clone(), seed.wrapping_add(n));
+        let log_dir = log_dir.clone();
+        tokio::spawn(async move {
+            let served = async {
+                let upstream = TcpStream::connect(upstream).await?;
+                let mut log = match &log_dir {
+                    Some(dir) => Some(BufWriter::new(File::create(log_path(dir, n))?)),
+                    None => None,
+                };
+                let log = log.as_mut().map(|w| w as &mut (dyn Write + Send));
+                handle(client, upstream, &mut mutator, log).await
+            };
+            match served.await {
+                Ok(damaged) => info!("{} closed, {} replies damaged", peer, damaged),
+                Err(e) => info!("{} closed: {}", peer, e),
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{program, success_reply, RpcCall};
+
+    #[test]
+    fn test_protected_len() {
+        let ok = success_reply(7, &[0; 8]);
+        assert_eq!(protected_len(&ok, false), 24);
+        assert_eq!(protected_len(&ok, true), 4);
+        assert_eq!(protected_len(&[1, 2], false), 2);
+        let config = ServeConfig {
+            rate: 1.0,
+            ..Default::default()
+        };
+        let mut m = ReplyMutator::new(Arc::new(Engine::default()), config, 1);
+        for _ in 0..20 {
+            let (served, step) = m.apply(&[], &ok);
+            assert!(step.is_some());
+            assert_eq!(served[..24], ok[..24]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replies_are_damaged_on_the_way() {
+        let (mut client, client_side) = tokio::io::duplex(1 << 16);
+        let (upstream_side, mut upstream) = tokio::io::duplex(1 << 16);
+        let config = ServeConfig {
+            rate: 1.0,
+            ..Default::default()
+        };
+        let server = tokio::spawn(async move {
+            let mut m = ReplyMutator::new(Arc::new(Engine::default()), config, 2);
+            let mut log = Vec::new();
+            let n = handle(client_side, upstream_side, &mut m, Some(&mut log))
+                .await
+                .unwrap();
+            (n, String::from_utf8(log).unwrap())
+        });
+        tokio::spawn(async move {
+            while let Ok(call) = read_record(&mut upstream).await {
+                let xid = u32::from_be_bytes(call[..4].try_into().unwrap());
+                let body = record(&success_reply(xid, &[0xab; 32]));
+                upstream.write_all(&body).await.unwrap();
+            }
+        });
+        let call = RpcCall::new(9, program::NFS, 3, 1, true)
+            .with_auth_none()
+            .build();
+        client.write_all(&call).await.unwrap();
+        let served = read_record(&mut client).await.unwrap();
+        assert_eq!(served[..24], success_reply(9, &[])[..]);
+        drop(client);
+        let (n, log) = server.await.unwrap();
+        assert_eq!(n, 1);
+        assert!(log.starts_with(r#"{"index":0,"xid":9,"step":"#));
+        assert!(log.contains(&hex::encode(&served)));
+    }
+}
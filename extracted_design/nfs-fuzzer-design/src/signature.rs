@@ -0,0 +1,358 @@
+//! Behavior signatures
+//!
+//! Counting new behaviors, tagging corpus entries with what they
+//! triggered, deduplicating findings and diffing two campaigns all ask
+//! whether two cases made the server behave the same way. A
+//! [`Signature`] is what they compare: the procedure called, the statuses
+//! its reply decoded to, how the connection fared and which oracles
+//! fired. Its [`Signature::id`] is FNV-1a over a canonical encoding rather
+//! than std's hasher, whose output is unspecified, so ids from different
+//! runs, builds and machines can be compared directly. [`VERSION`] leads
+//! every id and is bumped whenever the encoding or what goes into it
+//! changes, so ids of different versions never match by accident.
+
+use crate::generate::Input;
+use crate::nfsv4;
+use crate::repro;
+use crate::rpc::{program, Accepted, Rejected, ReplyStatus, RpcReply};
+use crate::xdr::XdrDecoder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Version of the signature encoding
+pub const VERSION: u32 = 1;
+
+/// COMPOUND results recorded, at most
+const MAX_RESULTS: usize = 16;
+
+/// What became of the connection a case was sent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Connection {
+    Replied,
+    /// The server closed or reset it
+    Closed,
+    /// No reply within the request budget
+    Timeout,
+}
+
+impl Connection {
+    pub fn name(self) -> &'static str {
+        match self {
+            Connection::Replied => "replied",
+            Connection::Closed => "closed",
+            Connection::Timeout => "timeout",
+        }
+    }
+}
+
+/// How the server handled one case
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Signature {
+    pub program: u32,
+    pub version: u32,
+    pub procedure: u32,
+    /// Statuses of the reply, outermost first: see [`status_path`]
+    pub statuses: Vec<String>,
+    pub connection: Connection,
+    /// Names of the oracles that flagged the reply
+    pub oracles: BTreeSet<String>,
+}
+
+impl Signature {
+    pub const FILE: &'static str = "signature.json";
+
+    /// Signature of `input` answered with `reply` (without its record mark)
+    pub fn of_reply(input: &Input, reply: &[u8]) -> Self {
+        Self {
+            statuses: status_path(input, reply),
+            ..Self::of_outcome(input, Connection::Replied)
+        }
+    }
+
+    /// Signature of `input` left unanswered
+    pub fn of_outcome(input: &Input, connection: Connection) -> Self {
+        Self {
+            program: input.program,
+            version: input.version,
+            procedure: input.procedure,
+            statuses: Vec::new(),
+            connection,
+            oracles: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_oracle(mut self, name: impl Into<String>) -> Self {
+        self.oracles.insert(name.into());
+        self
+    }
+
+    /// The canonical encoding hashed by [`Signature::hash`]: every field
+    /// length-prefixed, so no two signatures encode the same
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut put = |s: &[u8]| {
+            out.extend_from_slice(&(s.len() as u32).to_be_bytes());
+            out.extend_from_slice(s);
+        };
+        put(&VERSION.to_be_bytes());
+        put(&self.program.to_be_bytes());
+        put(&self.version.to_be_bytes());
+        put(&self.procedure.to_be_bytes());
+        put(&(self.statuses.len() as u32).to_be_bytes());
+        for s in &self.statuses {
+            put(s.as_bytes());
+        }
+        put(self.connection.name().as_bytes());
+        put(&(self.oracles.len() as u32).to_be_bytes());
+        for o in &self.oracles {
+            put(o.as_bytes());
+        }
+        out
+    }
+
+    /// 64-bit FNV-1a of the canonical encoding
+    pub fn hash(&self) -> u64 {
+        self.encode().iter().fold(0xcbf2_9ce4_8422_2325, |h, &b| {
+            (h ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    }
+
+    /// `v<VERSION>:<hash>`, the form signatures are stored and compared in
+    pub fn id(&self) -> String {
+        format!("v{}:{:016x}", VERSION, self.hash())
+    }
+
+    /// Where the signature of the finding at `finding` goes
+    pub fn path_for(finding: &Path) -> PathBuf {
+        repro::finding_file(finding, Self::FILE)
+    }
+
+    /// Save with its id, so findings can be grouped without recomputing it
+    pub fn save(&self, finding: &Path) -> io::Result<()> {
+        let mut value = serde_json::to_value(self)?;
+        value["id"] = self.id().into();
+        std::fs::write(Self::path_for(finding), serde_json::to_vec_pretty(&value)?)
+    }
+}
+
+/// `100003 v4 proc 1 replied NFS4ERR_BADXDR`
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} v{} proc {} {}",
+            self.program,
+            self.version,
+            self.procedure,
+            self.connection.name()
+        )?;
+        for s in &self.statuses {
+            write!(f, " {}", s)?;
+        }
+        for o in &self.oracles {
+            write!(f, " !{}", o)?;
+        }
+        Ok(())
+    }
+}
+
+/// Status a reply is counted under in the campaign statistics
+///
+/// NFS results are named by their leading status word, `NFS3_OK` or
+/// `NFS4ERR_10036`; other programs' successful replies count as
+/// `SUCCESS`.
+pub fn status_name(input: &Input, reply: &[u8]) -> String {
+    let reply = match RpcReply::parse(reply) {
+        Ok(reply) => reply,
+        Err(_) => return "unparsable".to_string(),
+    };
+    let stat = match reply.status {
+        ReplyStatus::Denied(Rejected::RpcMismatch { .. }) => return "RPC_MISMATCH".to_string(),
+        ReplyStatus::Denied(Rejected::AuthError(_)) => return "AUTH_ERROR".to_string(),
+        ReplyStatus::Accepted { stat, .. } => stat,
+    };
+    match stat {
+        Accepted::Success => {}
+        Accepted::ProgUnavail => return "PROG_UNAVAIL".to_string(),
+        Accepted::ProgMismatch { .. } => return "PROG_MISMATCH".to_string(),
+        Accepted::ProcUnavail => return "PROC_UNAVAIL".to_string(),
+        Accepted::GarbageArgs => return "GARBAGE_ARGS".to_string(),
+        Accepted::SystemErr => return "SYSTEM_ERR".to_string(),
+    }
+    let status = reply
+        .results
+        .get(..4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+    match (input.program, status) {
+        (program::NFS, Some(0)) => format!("NFS{}_OK", input.version),
+        (program::NFS, Some(n)) => format!("NFS{}ERR_{}", input.version, n),
+        _ => "SUCCESS".to_string(),
+    }
+}
+
+/// The [`status_name`] of `reply`, then what it says below that: the
+/// auth_stat of an AUTH_ERROR, and `<op>:<status>` for each COMPOUND
+/// result, as far as the results can be stepped over
+pub fn status_path(input: &Input, reply: &[u8]) -> Vec<String> {
+    let mut path = vec![status_name(input, reply)];
+    let Ok(parsed) = RpcReply::parse(reply) else {
+        return path;
+    };
+    match parsed.status {
+        ReplyStatus::Denied(Rejected::AuthError(stat)) => path.push(format!("auth_stat={}", stat)),
+        ReplyStatus::Accepted {
+            stat: Accepted::Success,
+            ..
+        } if (input.program, input.version, input.procedure)
+            == (program::NFS, 4, nfsv4::PROC_COMPOUND) =>
+        {
+            compound_results(&mut XdrDecoder::new(parsed.results), &mut path);
+        }
+        _ => {}
+    }
+    path
+}
+
+/// Append the results of a COMPOUND4res to `path`
+fn compound_results(d: &mut XdrDecoder, path: &mut Vec<String>) -> Option<()> {
+    d.get_u32().ok()?;
+    d.get_opaque().ok()?;
+    let results = d.get_u32().ok()? as usize;
+    for _ in 0..results.min(MAX_RESULTS) {
+        let (opcode, status) = (d.get_u32().ok()?, d.get_u32().ok()?);
+        path.push(format!("{}:{}", opcode, status));
+        if status != 0 {
+            break;
+        }
+        skip_result(opcode, d)?;
+    }
+    Some(())
+}
+
+/// Step over one successful result body; `None` for ops whose results
+/// are not decoded here, which ends the path
+fn skip_result(opcode: u32, d: &mut XdrDecoder) -> Option<()> {
+    use nfsv4::op;
+    match opcode {
+        op::PUTFH
+        | op::PUTPUBFH
+        | op::PUTROOTFH
+        | op::SAVEFH
+        | op::RESTOREFH
+        | op::LOOKUP
+        | op::LOOKUPP => {}
+        op::SEQUENCE => {
+            d.get_raw(16 + 5 * 4).ok()?;
+        }
+        op::GETFH => {
+            d.get_opaque().ok()?;
+        }
+        op::GETATTR => {
+            let words = d.get_u32().ok()?;
+            for _ in 0..words {
+                d.get_u32().ok()?;
+            }
+            d.get_opaque().ok()?;
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineage::Lineage;
+    use crate::rpc::success_reply;
+
+    fn input(version: u32, procedure: u32) -> Input {
+        Input {
+            name: "case".to_string(),
+            program: program::NFS,
+            version,
+            procedure,
+            args: Vec::new(),
+            lineage: Lineage::new("case"),
+            auth: None,
+        }
+    }
+
+    fn reply(words: &[u32]) -> Vec<u8> {
+        let results: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        success_reply(7, &results)
+    }
+
+    #[test]
+    fn test_status_path() {
+        assert_eq!(status_path(&input(3, 1), &reply(&[70])), ["NFS3ERR_70"]);
+        assert_eq!(status_path(&input(3, 1), &[1, 2]), ["unparsable"]);
+        let denied: Vec<u8> = [7u32, 1, 1, 1, 5]
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect();
+        assert_eq!(
+            status_path(&input(3, 1), &denied),
+            ["AUTH_ERROR", "auth_stat=5"]
+        );
+
+        // PUTROOTFH ok, GETFH ok, then LOOKUP fails with NFS4ERR_NOENT
+        use nfsv4::op;
+        let compound = reply(&[
+            2,
+            0,
+            3,
+            op::PUTROOTFH,
+            0,
+            op::GETFH,
+            0,
+            4,
+            0xab,
+            op::LOOKUP,
+            2,
+        ]);
+        assert_eq!(
+            status_path(&input(4, nfsv4::PROC_COMPOUND), &compound),
+            ["NFS4ERR_2", "24:0", "10:0", "15:2"]
+        );
+        // Truncated: the path stops where decoding does
+        let short = &compound[..compound.len() - 8];
+        assert_eq!(
+            status_path(&input(4, nfsv4::PROC_COMPOUND), short),
+            ["NFS4ERR_2", "24:0", "10:0"]
+        );
+    }
+
+    #[test]
+    fn test_ids_are_stable_and_distinct() {
+        let case = input(3, 1);
+        let ok = Signature::of_reply(&case, &reply(&[0]));
+        // Pinned: a change here means ids no longer match older runs,
+        // and VERSION must be bumped
+        assert_eq!(ok.id(), "v1:8e52b764371a18bf");
+        assert_eq!(ok.id(), Signature::of_reply(&case, &reply(&[0])).id());
+        let json = serde_json::to_string(&ok).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Signature>(&json).unwrap().id(),
+            ok.id()
+        );
+
+        let others = [
+            Signature::of_reply(&case, &reply(&[70])),
+            Signature::of_reply(&input(3, 2), &reply(&[0])),
+            Signature::of_outcome(&case, Connection::Timeout),
+            Signature::of_outcome(&case, Connection::Closed),
+            ok.clone().with_oracle("info_leak"),
+        ];
+        let mut ids: BTreeSet<String> = others.iter().map(Signature::id).collect();
+        ids.insert(ok.id());
+        assert_eq!(ids.len(), others.len() + 1);
+        assert_eq!(
+            ok.clone().with_oracle("info_leak").to_string(),
+            "100003 v3 proc 1 replied NFS3_OK !info_leak"
+        );
+    }
+}
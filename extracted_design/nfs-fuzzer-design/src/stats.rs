@@ -0,0 +1,730 @@
+//! Campaign statistics and run-to-run comparison
+//!
+//! Each campaign leaves a `stats.json` in its result directory. Comparing
+//! two of them says whether a strategy change really moved the reply
+//! status mix, latencies or finding rate, or whether the difference is
+//! within run-to-run noise. Per-strategy counters, attributed through
+//! each input's mutation lineage, rank strategies in the campaign report
+//! to guide weight tuning.
+
+use crate::lineage::Lineage;
+use crate::signature::Signature;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StatsError {
+    #[error("stats I/O: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("bad stats file: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Counters one campaign accumulates
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CampaignStats {
+    pub requests: u64,
+    /// Replies per status name (`NFS3ERR_INVAL`, `AUTH_ERROR`, `timeout`, ...)
+    pub statuses: BTreeMap<String, u64>,
+    /// Reply latencies, in microseconds
+    pub latency_us: Latency,
+    pub findings: u64,
+    /// Per-strategy counters, keyed by strategy name
+    #[serde(default)]
+    pub strategies: BTreeMap<String, StrategyStats>,
+    /// Conditions the campaign ran under that bear on its findings, such
+    /// as the middlebox interference the canary saw
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// Cases on which the fuzzer itself panicked, see [`crate::isolate`]
+    #[serde(default)]
+    pub internal_errors: u64,
+    /// Kernel edges covered, with coverage feedback (see [`crate::kcov`])
+    #[serde(default)]
+    pub edges: u64,
+    /// Cases kept for reaching new edges
+    #[serde(default)]
+    pub queued: u64,
+    /// Cases per behavior, keyed by [`Signature::id`]
+    #[serde(default)]
+    pub behaviors: BTreeMap<String, u64>,
+}
+
+/// Reply latency histogram with log-linear buckets
+///
+/// Values below 16us get a bucket each; above that every power of two is
+/// split into eight buckets, so a bucket is within 12.5% of the values it
+/// holds and the histogram never has more than 496 of them however long
+/// the campaign runs. Stored as `[lower bound, count]` pairs; files that
+/// kept every sample as a plain list still load.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "LatencyFile", into = "Vec<(u64, u64)>")]
+pub struct Latency {
+    buckets: BTreeMap<u64, u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LatencyFile {
+    Samples(Vec<u64>),
+    Buckets(Vec<(u64, u64)>),
+}
+
+impl From<LatencyFile> for Latency {
+    fn from(file: LatencyFile) -> Self {
+        let mut latency = Latency::default();
+        match file {
+            LatencyFile::Samples(v) => v.into_iter().for_each(|us| latency.record(us)),
+            LatencyFile::Buckets(v) => {
+                for (lower, n) in v {
+                    *latency.buckets.entry(Latency::bucket(lower)).or_default() += n;
+                }
+            }
+        }
+        latency
+    }
+}
+
+impl From<Latency> for Vec<(u64, u64)> {
+    fn from(latency: Latency) -> Self {
+        latency.buckets.into_iter().collect()
+    }
+}
+
+impl Latency {
+    /// Lower bound of the bucket `us` falls in
+    fn bucket(us: u64) -> u64 {
+        if us < 16 {
+            return us;
+        }
+        let shift = 63 - us.leading_zeros() - 3;
+        us >> shift << shift
+    }
+
+    pub fn record(&mut self, us: u64) {
+        *self.buckets.entry(Self::bucket(us)).or_default() += 1;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.buckets.values().sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// Lower bound of the bucket holding the median, 0 when empty
+    pub fn median(&self) -> u64 {
+        let half = self.len() / 2;
+        let mut seen = 0;
+        for (&lower, &n) in &self.buckets {
+            seen += n;
+            if seen > half {
+                return lower;
+            }
+        }
+        0
+    }
+}
+
+/// What one strategy contributed to a campaign
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StrategyStats {
+    pub inputs: u64,
+    /// Inputs that produced a reply behaviour not seen before
+    pub new_behaviors: u64,
+    /// Findings (crashes, hangs, oracle hits) the strategy contributed to
+    pub crashes: u64,
+}
+
+impl StrategyStats {
+    fn rate(&self, n: u64) -> f64 {
+        if self.inputs == 0 {
+            0.0
+        } else {
+            n as f64 / self.inputs as f64
+        }
+    }
+
+    pub fn crash_rate(&self) -> f64 {
+        self.rate(self.crashes)
+    }
+
+    pub fn behavior_rate(&self) -> f64 {
+        self.rate(self.new_behaviors)
+    }
+}
+
+/// Strategy name inputs without mutations are counted under
+pub const UNMUTATED: &str = "unmutated";
+
+impl CampaignStats {
+    pub const FILE: &'static str = "stats.json";
+
+    pub fn record(&mut self, status: &str, latency: Duration) {
+        self.requests += 1;
+        *self.statuses.entry(status.to_string()).or_default() += 1;
+        self.latency_us.record(latency.as_micros() as u64);
+    }
+
+    /// Count an input for every strategy in its lineage
+    pub fn record_input(&mut self, lineage: &Lineage) {
+        self.attribute(lineage, |s| s.inputs += 1);
+    }
+
+    pub fn record_behavior(&mut self, lineage: &Lineage) {
+        self.attribute(lineage, |s| s.new_behaviors += 1);
+    }
+
+    /// Count a case under its signature, crediting its strategies with a
+    /// new behavior the first time the signature is seen; returns whether
+    /// it was new
+    pub fn record_signature(&mut self, signature: &Signature, lineage: &Lineage) -> bool {
+        let n = self.behaviors.entry(signature.id()).or_default();
+        *n += 1;
+        let new = *n == 1;
+        if new {
+            self.record_behavior(lineage);
+        }
+        new
+    }
+
+    /// Count a finding, crediting every strategy that shaped the input
+    pub fn record_crash(&mut self, lineage: &Lineage) {
+        self.findings += 1;
+        self.attribute(lineage, |s| s.crashes += 1);
+    }
+
+    fn attribute(&mut self, lineage: &Lineage, f: impl Fn(&mut StrategyStats)) {
+        let mut names = lineage.strategies();
+        if names.is_empty() {
+            names.push(UNMUTATED);
+        }
+        for name in names {
+            f(self.strategies.entry(name.to_string()).or_default());
+        }
+    }
+
+    /// Strategies best first: by crash rate, then new-behaviour rate
+    pub fn ranking(&self) -> Vec<(&str, StrategyStats)> {
+        let mut ranked: Vec<(&str, StrategyStats)> = self
+            .strategies
+            .iter()
+            .map(|(name, s)| (name.as_str(), *s))
+            .collect();
+        ranked.sort_by(|(an, a), (bn, b)| {
+            b.crash_rate()
+                .total_cmp(&a.crash_rate())
+                .then(b.behavior_rate().total_cmp(&a.behavior_rate()))
+                .then(an.cmp(bn))
+        });
+        ranked
+    }
+
+    pub fn load(dir: &Path) -> Result<Self, StatsError> {
+        let text = std::fs::read_to_string(dir.join(Self::FILE))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<(), StatsError> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join(Self::FILE), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+}
+
+/// One hypothesis test between the two runs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Test {
+    pub name: String,
+    /// Human-readable summary of the two runs
+    pub detail: String,
+    pub statistic: f64,
+    pub p_value: f64,
+}
+
+/// All tests of one comparison
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub alpha: f64,
+    pub tests: Vec<Test>,
+}
+
+impl Comparison {
+    pub fn significant(&self) -> impl Iterator<Item = &Test> {
+        self.tests.iter().filter(|t| t.p_value < self.alpha)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for t in &self.tests {
+            write!(
+                f,
+                "{}: {} (statistic {:.3}, p = {:.4})",
+                t.name, t.detail, t.statistic, t.p_value
+            )?;
+            if t.p_value < self.alpha {
+                write!(f, " significant")?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} of {} differences significant at alpha = {}",
+            self.significant().count(),
+            self.tests.len(),
+            self.alpha
+        )
+    }
+}
+
+/// Human-readable campaign report
+pub struct Report<'a>(pub &'a CampaignStats);
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = self.0;
+        writeln!(f, "{} requests, {} findings", s.requests, s.findings)?;
+        if s.internal_errors > 0 {
+            writeln!(f, "{} internal errors", s.internal_errors)?;
+        }
+        if s.edges > 0 {
+            writeln!(f, "{} edges covered, {} cases queued", s.edges, s.queued)?;
+        }
+        if !s.behaviors.is_empty() {
+            writeln!(f, "{} distinct behaviors", s.behaviors.len())?;
+        }
+        if !s.tags.is_empty() {
+            let tags: Vec<&str> = s.tags.iter().map(String::as_str).collect();
+            writeln!(f, "tags: {}", tags.join(", "))?;
+        }
+        writeln!(f, "median latency {}us", s.latency_us.median())?;
+        let total = s.statuses.values().sum::<u64>() as f64;
+        for (name, n) in &s.statuses {
+            writeln!(f, "  {:<24} {:>10} {:>8}", name, n, share(*n as f64, total))?;
+        }
+        writeln!(f, "strategy ranking:")?;
+        writeln!(
+            f,
+            "  {:>4} {:<20} {:>10} {:>10} {:>8} {:>10} {:>10}",
+            "rank", "strategy", "inputs", "behaviors", "crashes", "crash/1k", "new/1k"
+        )?;
+        for (i, (name, st)) in s.ranking().iter().enumerate() {
+            writeln!(
+                f,
+                "  {:>4} {:<20} {:>10} {:>10} {:>8} {:>10.2} {:>10.2}",
+                i + 1,
+                name,
+                st.inputs,
+                st.new_behaviors,
+                st.crashes,
+                1000.0 * st.crash_rate(),
+                1000.0 * st.behavior_rate()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare a baseline run `a` with a candidate run `b`
+///
+/// Status mix: chi-square test of homogeneity, then one 2x2 test per
+/// status with a Bonferroni-adjusted p. Behaviors, when both runs
+/// recorded signatures: chi-square over the cases per signature, which
+/// being versioned hashes compare across runs. Latency: Mann-Whitney U
+/// over the histogram buckets, since latencies are far from normal.
+/// Findings: 2x2 test of findings per request.
+pub fn compare(a: &CampaignStats, b: &CampaignStats, alpha: f64) -> Comparison {
+    let mut tests = Vec::new();
+
+    let names: BTreeSet<&String> = a.statuses.keys().chain(b.statuses.keys()).collect();
+    let count = |s: &CampaignStats, n: &String| s.statuses.get(n).copied().unwrap_or(0) as f64;
+    let table: Vec<[f64; 2]> = names.iter().map(|n| [count(a, n), count(b, n)]).collect();
+    let (stat, df) = chi_square(&table);
+    tests.push(Test {
+        name: "status distribution".to_string(),
+        detail: format!("{} statuses", names.len()),
+        statistic: stat,
+        p_value: chi_square_p(stat, df),
+    });
+    let total_a: f64 = table.iter().map(|r| r[0]).sum();
+    let total_b: f64 = table.iter().map(|r| r[1]).sum();
+    for (name, row) in names.iter().zip(&table) {
+        let two_by_two = [[row[0], row[1]], [total_a - row[0], total_b - row[1]]];
+        let (stat, df) = chi_square(&two_by_two);
+        tests.push(Test {
+            name: format!("status {}", name),
+            detail: format!("{} -> {}", share(row[0], total_a), share(row[1], total_b)),
+            statistic: stat,
+            p_value: (chi_square_p(stat, df) * names.len() as f64).min(1.0),
+        });
+    }
+
+    if !a.behaviors.is_empty() && !b.behaviors.is_empty() {
+        let ids: BTreeSet<&String> = a.behaviors.keys().chain(b.behaviors.keys()).collect();
+        let count =
+            |s: &CampaignStats, id: &String| s.behaviors.get(id).copied().unwrap_or(0) as f64;
+        let table: Vec<[f64; 2]> = ids.iter().map(|id| [count(a, id), count(b, id)]).collect();
+        let (stat, df) = chi_square(&table);
+        let only = |s: &CampaignStats, t: &CampaignStats| {
+            s.behaviors
+                .keys()
+                .filter(|id| !t.behaviors.contains_key(*id))
+                .count()
+        };
+        tests.push(Test {
+            name: "behavior distribution".to_string(),
+            detail: format!(
+                "{} signatures, {} only in the baseline, {} only in the candidate",
+                ids.len(),
+                only(a, b),
+                only(b, a)
+            ),
+            statistic: stat,
+            p_value: chi_square_p(stat, df),
+        });
+    }
+
+    let (z, p) = mann_whitney(&a.latency_us, &b.latency_us);
+    tests.push(Test {
+        name: "latency".to_string(),
+        detail: format!(
+            "median {}us -> {}us",
+            a.latency_us.median(),
+            b.latency_us.median()
+        ),
+        statistic: z,
+        p_value: p,
+    });
+
+    let (fa, fb) = (a.findings as f64, b.findings as f64);
+    let table = [
+        [fa, fb],
+        [
+            (a.requests as f64 - fa).max(0.0),
+            (b.requests as f64 - fb).max(0.0),
+        ],
+    ];
+    let (stat, df) = chi_square(&table);
+    tests.push(Test {
+        name: "findings".to_string(),
+        detail: format!(
+            "{}/{} -> {}/{} requests",
+            a.findings, a.requests, b.findings, b.requests
+        ),
+        statistic: stat,
+        p_value: chi_square_p(stat, df),
+    });
+
+    Comparison { alpha, tests }
+}
+
+fn share(n: f64, total: f64) -> String {
+    if total == 0.0 {
+        "0%".to_string()
+    } else {
+        format!("{:.2}%", 100.0 * n / total)
+    }
+}
+
+/// Pearson's statistic and degrees of freedom for a k x 2 table, ignoring
+/// empty rows and columns
+fn chi_square(table: &[[f64; 2]]) -> (f64, usize) {
+    let rows: Vec<&[f64; 2]> = table.iter().filter(|r| r[0] + r[1] > 0.0).collect();
+    let cols = [
+        rows.iter().map(|r| r[0]).sum::<f64>(),
+        rows.iter().map(|r| r[1]).sum::<f64>(),
+    ];
+    let total = cols[0] + cols[1];
+    if rows.len() < 2 || cols.contains(&0.0) {
+        return (0.0, 0);
+    }
+    let stat = rows
+        .iter()
+        .flat_map(|r| {
+            let row = r[0] + r[1];
+            (0..2).map(move |c| {
+                let expected = row * cols[c] / total;
+                (r[c] - expected).powi(2) / expected
+            })
+        })
+        .sum();
+    (stat, rows.len() - 1)
+}
+
+fn chi_square_p(stat: f64, df: usize) -> f64 {
+    if df == 0 {
+        return 1.0;
+    }
+    gamma_q(df as f64 / 2.0, stat / 2.0)
+}
+
+/// Normal-approximation Mann-Whitney U with tie correction; returns z
+/// and the two-sided p. Values sharing a bucket count as ties.
+fn mann_whitney(a: &Latency, b: &Latency) -> (f64, f64) {
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    if a.is_empty() || b.is_empty() {
+        return (0.0, 1.0);
+    }
+    let lowers: BTreeSet<&u64> = a.buckets.keys().chain(b.buckets.keys()).collect();
+    let n = n1 + n2;
+    let (mut rank_a, mut ties, mut below) = (0.0, 0.0, 0.0);
+    for lower in lowers {
+        let in_a = a.buckets.get(lower).copied().unwrap_or(0) as f64;
+        let t = in_a + b.buckets.get(lower).copied().unwrap_or(0) as f64;
+        // Ranks below+1..=below+t averaged
+        rank_a += in_a * (2.0 * below + t + 1.0) / 2.0;
+        ties += t * t * t - t;
+        below += t;
+    }
+    let u = rank_a - n1 * (n1 + 1.0) / 2.0;
+    let var = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if var <= 0.0 {
+        return (0.0, 1.0);
+    }
+    let z = (u - n1 * n2 / 2.0) / var.sqrt();
+    (z, gamma_q(0.5, z * z / 2.0))
+}
+
+fn ln_gamma(x: f64) -> f64 {
+    // Lanczos approximation, g = 7
+    const C: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let a = C
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(C[0], |a, (i, c)| a + c / (x + i as f64));
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+}
+
+/// Regularized upper incomplete gamma Q(a, x)
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const EPS: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // Series for P(a, x)
+        let (mut ap, mut del) = (a, 1.0 / a);
+        let mut sum = del;
+        for _ in 0..500 {
+            ap += 1.0;
+            del *= x / ap;
+            sum += del;
+            if del.abs() < sum.abs() * EPS {
+                break;
+            }
+        }
+        1.0 - sum * front
+    } else {
+        // Continued fraction for Q(a, x), modified Lentz
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..500 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let del = d * c;
+            h *= del;
+            if (del - 1.0).abs() < EPS {
+                break;
+            }
+        }
+        front * h
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distributions() {
+        // chi-square with 1 df: 3.841 is the 5% critical value
+        assert!((chi_square_p(3.841, 1) - 0.05).abs() < 1e-3);
+        assert!((chi_square_p(9.488, 4) - 0.05).abs() < 1e-3);
+        // Two-sided normal: |z| = 1.96 at 5%
+        assert!((gamma_q(0.5, 1.96 * 1.96 / 2.0) - 0.05).abs() < 1e-3);
+        assert_eq!(chi_square_p(0.0, 0), 1.0);
+    }
+
+    fn run(ok: u64, inval: u64, latency: u64, findings: u64) -> CampaignStats {
+        let mut s = CampaignStats::default();
+        for i in 0..ok {
+            s.record("NFS3_OK", Duration::from_micros(latency + i % 7));
+        }
+        for i in 0..inval {
+            s.record("NFS3ERR_INVAL", Duration::from_micros(latency + i % 5));
+        }
+        s.findings = findings;
+        s
+    }
+
+    #[test]
+    fn test_compare_flags_real_changes_only() {
+        let base = run(900, 100, 100, 2);
+        let same = compare(&base, &run(900, 100, 100, 3), 0.05);
+        assert_eq!(same.significant().count(), 0, "{}", same);
+
+        let changed = compare(&base, &run(600, 400, 300, 40), 0.05);
+        let names: Vec<&str> = changed.significant().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "status distribution",
+                "status NFS3ERR_INVAL",
+                "status NFS3_OK",
+                "latency",
+                "findings"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_latency_histogram_is_bounded() {
+        let mut latency = Latency::default();
+        for us in (0..2_000_000).chain([u64::MAX]) {
+            latency.record(us);
+        }
+        assert_eq!(latency.len(), 2_000_001);
+        assert!(latency.buckets.len() <= 496);
+        for (us, lower) in [(0, 0), (15, 15), (16, 16), (100, 96), (1000, 960)] {
+            assert_eq!(Latency::bucket(us), lower);
+        }
+        let median = latency.median();
+        assert!((917_504..=1_000_000).contains(&median), "{}", median);
+    }
+
+    #[test]
+    fn test_stats_file_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("nfs-fuzzer-stats-{}", std::process::id()));
+        let stats = run(3, 1, 10, 1);
+        stats.save(&dir).unwrap();
+        assert_eq!(CampaignStats::load(&dir).unwrap(), stats);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(CampaignStats::load(&dir).is_err());
+        // Files written before per-strategy counters still load
+        let old: CampaignStats =
+            serde_json::from_str(r#"{"requests":1,"statuses":{},"latency_us":[],"findings":0}"#)
+                .unwrap();
+        assert!(old.strategies.is_empty());
+        let samples: CampaignStats = serde_json::from_str(
+            r#"{"requests":3,"statuses":{},"latency_us":[5,90,91],"findings":0}"#,
+        )
+        .unwrap();
+        assert_eq!(samples.latency_us.len(), 3);
+        assert_eq!(samples.latency_us.median(), 88);
+        assert!(old.tags.is_empty());
+        assert_eq!(old.internal_errors, 0);
+    }
+
+    #[test]
+    fn test_strategy_ranking() {
+        use crate::lineage::Step;
+        let mut s = CampaignStats::default();
+        let seed = Lineage::new("v3:GETATTR");
+        let flip = seed.then(Step::new("bitflip").with("offset", 3));
+        let both = flip.then(Step::new("truncate").with("len", 8));
+        for _ in 0..100 {
+            s.record_input(&seed);
+            s.record_input(&flip);
+            s.record_input(&both);
+        }
+        s.record_crash(&both);
+        s.record_crash(&both);
+        s.record_crash(&flip);
+        s.record_behavior(&seed);
+
+        let ranking = s.ranking();
+        let names: Vec<&str> = ranking.iter().map(|(n, _)| *n).collect();
+        // truncate: 2 crashes in 100 inputs beats bitflip's 3 in 200
+        assert_eq!(names, ["truncate", "bitflip", UNMUTATED]);
+        assert_eq!(ranking[1].1.inputs, 200);
+        assert_eq!(ranking[1].1.crashes, 3);
+        assert_eq!(ranking[2].1.new_behaviors, 1);
+        assert_eq!(s.findings, 3);
+        s.tags.insert("middlebox:dropped:segmented".to_string());
+        let report = Report(&s).to_string();
+        assert!(report.contains("3 findings"));
+        assert!(report.contains("tags: middlebox:dropped:segmented"));
+        assert!(report
+            .lines()
+            .any(|l| l.trim_start().starts_with("1 truncate")));
+    }
+
+    #[test]
+    fn test_signatures_count_behaviors() {
+        use crate::generate::Input;
+        use crate::signature::Connection;
+        let seed = Lineage::new("v3:GETATTR");
+        let input = Input {
+            name: "v3:GETATTR".to_string(),
+            program: crate::rpc::program::NFS,
+            version: 3,
+            procedure: 1,
+            args: Vec::new(),
+            lineage: seed.clone(),
+            auth: None,
+        };
+        let (closed, timeout) = (
+            Signature::of_outcome(&input, Connection::Closed),
+            Signature::of_outcome(&input, Connection::Timeout),
+        );
+        let mut a = CampaignStats::default();
+        assert!(a.record_signature(&closed, &seed));
+        assert!(!a.record_signature(&closed, &seed));
+        assert_eq!(a.strategies[UNMUTATED].new_behaviors, 1);
+        assert_eq!(a.behaviors[&closed.id()], 2);
+
+        let mut b = CampaignStats::default();
+        for _ in 0..50 {
+            a.record_signature(&closed, &seed);
+            b.record_signature(&timeout, &seed);
+        }
+        let c = compare(&a, &b, 0.05);
+        let test = c
+            .tests
+            .iter()
+            .find(|t| t.name == "behavior distribution")
+            .unwrap();
+        assert_eq!(
+            test.detail,
+            "2 signatures, 1 only in the baseline, 1 only in the candidate"
+        );
+        assert!(test.p_value < 0.05);
+        assert!(Report(&a).to_string().contains("1 distinct behaviors"));
+    }
+}
@@ -0,0 +1,277 @@
+//! Per-case tracing spans and OpenTelemetry export
+//!
+//! Every test case runs inside a `case` span carrying the campaign id,
+//! the seed it was derived from, its mutation lineage and, once sent, the
+//! XID, so interleaved log lines from parallel workers can be told apart.
+//! [`OtlpFileLayer`] additionally writes each closed span as one line of
+//! OTLP/JSON (the OpenTelemetry file exporter format), which a collector's
+//! file receiver can forward to any tracing backend; the campaign id is the
+//! trace id, so all workers of one campaign land in the same trace.
+
+use crate::lineage::Lineage;
+use rand::Rng;
+use serde_json::{json, Value};
+use std::fmt;
+use std::future::Future;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info_span, Instrument, Span, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Identifies one campaign across workers; doubles as the OTLP trace id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CampaignId(pub u128);
+
+impl CampaignId {
+    pub fn random() -> Self {
+        CampaignId(rand::thread_rng().gen_range(1..=u128::MAX))
+    }
+}
+
+impl fmt::Display for CampaignId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// What identifies one test case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaseContext {
+    pub campaign: CampaignId,
+    pub case: String,
+    pub lineage: Lineage,
+}
+
+/// The span a case runs in; `xid` is filled by [`record_xid`]
+pub fn case_span(ctx: &CaseContext) -> Span {
+    info_span!(
+        "case",
+        campaign = %ctx.campaign,
+        case = %ctx.case,
+        seed = %ctx.lineage.seed,
+        lineage = %ctx.lineage.chain(),
+        xid = tracing::field::Empty,
+    )
+}
+
+/// Record the XID of the request a case sent
+pub fn record_xid(span: &Span, xid: u32) {
+    span.record("xid", format!("{:#010x}", xid).as_str());
+}
+
+/// Run `fut` inside the case's span
+pub fn run_case<F: Future>(ctx: &CaseContext, fut: F) -> impl Future<Output = F::Output> {
+    fut.instrument(case_span(ctx))
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Span state kept in the registry until close
+struct SpanData {
+    span_id: u64,
+    parent: Option<u64>,
+    start: u128,
+    attributes: Vec<Value>,
+}
+
+struct Attrs<'a>(&'a mut Vec<Value>);
+
+impl Attrs<'_> {
+    fn push(&mut self, field: &Field, value: Value) {
+        let key = field.name();
+        self.0.retain(|a| a["key"] != key);
+        self.0.push(json!({"key": key, "value": value}));
+    }
+}
+
+impl Visit for Attrs<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, json!({"stringValue": value}));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        // OTLP/JSON carries 64-bit integers as strings
+        self.push(field, json!({"intValue": value.to_string()}));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.push(field, json!({"intValue": value.to_string()}));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.push(field, json!({"boolValue": value}));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.push(field, json!({"stringValue": format!("{:?}", value)}));
+    }
+}
+
+/// Writes closed spans as OTLP/JSON lines
+pub struct OtlpFileLayer {
+    trace_id: CampaignId,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl OtlpFileLayer {
+    pub fn new(campaign: CampaignId, out: impl Write + Send + 'static) -> Self {
+        Self {
+            trace_id: campaign,
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    fn export(&self, name: &str, data: &SpanData) {
+        let line = json!({
+            "resourceSpans": [{
+                "resource": {"attributes": [
+                    {"key": "service.name", "value": {"stringValue": "nfs-fuzzer"}},
+                ]},
+                "scopeSpans": [{
+                    "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                    "spans": [{
+                        "traceId": self.trace_id.to_string(),
+                        "spanId": format!("{:016x}", data.span_id),
+                        "parentSpanId": data.parent.map(|p| format!("{:016x}", p)).unwrap_or_default(),
+                        "name": name,
+                        "kind": 1,
+                        "startTimeUnixNano": data.start.to_string(),
+                        "endTimeUnixNano": now_nanos().to_string(),
+                        "attributes": data.attributes,
+                    }],
+                }],
+            }],
+        });
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // Telemetry must never take the campaign down; a lost line is fine
+        let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+    }
+}
+
+impl<S> Layer<S> for OtlpFileLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let parent = span.parent().and_then(|p| {
+            let ext = p.extensions();
+            ext.get::<SpanData>().map(|d| d.span_id)
+        });
+        let mut data = SpanData {
+            span_id: rand::thread_rng().gen_range(1..=u64::MAX),
+            parent,
+            start: now_nanos(),
+            attributes: Vec::new(),
+        };
+        attrs.record(&mut Attrs(&mut data.attributes));
+        span.extensions_mut().insert(data);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else { return };
+        let mut ext = span.extensions_mut();
+        if let Some(data) = ext.get_mut::<SpanData>() {
+            values.record(&mut Attrs(&mut data.attributes));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else { return };
+        let ext = span.extensions();
+        if let Some(data) = ext.get::<SpanData>() {
+            self.export(span.name(), data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lineage::Step;
+    use std::sync::Arc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn spans(out: &Shared) -> Vec<Value> {
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        text.lines()
+            .map(|l| {
+                let v: Value = serde_json::from_str(l).unwrap();
+                v["resourceSpans"][0]["scopeSpans"][0]["spans"][0].clone()
+            })
+            .collect()
+    }
+
+    fn attr<'a>(span: &'a Value, key: &str) -> &'a Value {
+        let attrs = span["attributes"].as_array().unwrap();
+        &attrs.iter().find(|a| a["key"] == key).unwrap()["value"]
+    }
+
+    #[test]
+    fn test_case_span_exported_with_context() {
+        let out = Shared::default();
+        let campaign = CampaignId(0xabc);
+        let subscriber =
+            tracing_subscriber::registry().with(OtlpFileLayer::new(campaign, out.clone()));
+        let ctx = CaseContext {
+            campaign,
+            case: "case-7".to_string(),
+            lineage: Lineage::new("v4:OPEN")
+                .then(Step::new("bitflip").with("bit", 3))
+                .then(Step::new("truncate").with("len", 12)),
+        };
+        tracing::subscriber::with_default(subscriber, || {
+            let span = case_span(&ctx);
+            let _enter = span.enter();
+            record_xid(&span, 0x1234);
+            tracing::info_span!("send", bytes = 64u64).in_scope(|| {});
+        });
+
+        let spans = spans(&out);
+        assert_eq!(spans.len(), 2);
+        let (send, case) = (&spans[0], &spans[1]);
+        assert_eq!(case["name"], "case");
+        assert_eq!(case["traceId"], "00000000000000000000000000000abc");
+        assert_eq!(case["parentSpanId"], "");
+        assert_eq!(send["parentSpanId"], case["spanId"]);
+        assert_eq!(attr(case, "seed")["stringValue"], "v4:OPEN");
+        assert_eq!(
+            attr(case, "lineage")["stringValue"],
+            "bitflip(bit=3)>truncate(len=12)"
+        );
+        assert_eq!(attr(case, "xid")["stringValue"], "0x00001234");
+        assert_eq!(attr(send, "bytes")["intValue"], "64");
+    }
+
+    #[test]
+    fn test_campaign_id_is_trace_id_shaped() {
+        let id = CampaignId::random();
+        assert_ne!(id.0, 0);
+        assert_eq!(id.to_string().len(), 32);
+        assert_ne!(id, CampaignId::random());
+    }
+}
@@ -0,0 +1,335 @@
+//! Reply templates for known NFS client bugs
+//!
+//! Where `serve` damages replies at random, a template forges the reply
+//! to one kind of call in a shape that has taken clients down before:
+//! directory entries with names longer than any client buffer, names and
+//! attribute blocks claiming more bytes than the reply holds, handles past
+//! the protocol maximum, and listings that never reach the end. Each is
+//! selected by name, so a client vendor can check a fix against the one
+//! reply that needed it without waiting for the fuzzer to find it again.
+//!
+//! The forged reply keeps the upstream's RPC header, verifier included,
+//! when the upstream accepted the call, and answers with AUTH_NONE
+//! otherwise. v4 templates answer COMPOUNDs that end in GETATTR after
+//! nothing but SEQUENCE and the PUT*FH operations, echoing the SEQUENCE
+//! back so the session stays usable.
+
+use crate::nfsv3;
+use crate::nfsv4::{self, op, put_bitmap, status, SessionId};
+use crate::rpc::{accept_stat, auth_flavor, msg_type, program, reply_stat, RpcReply};
+use crate::xdr::{XdrDecoder, XdrEncoder};
+use clap::ValueEnum;
+
+/// Name length of [`Template::ReaddirLongName`]: past NFS_MAXNAMLEN and
+/// PATH_MAX both
+pub const LONG_NAME: usize = 4097;
+
+/// Handle length of [`Template::ReaddirplusLongFh`]
+pub const LONG_FH: usize = 2 * nfsv3::FHSIZE;
+
+/// Length claimed by the templates that run past the end of the reply
+pub const OVERRUN: u32 = 0x7fff_fff0;
+
+/// fileid of forged directory entries
+const FILEID: u64 = 0x1000;
+
+/// A forged reply, by the bug it reproduces
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    /// v3 READDIR entry with a name of 4097 bytes
+    ReaddirLongName,
+    /// v3 READDIR entry whose name length runs past the end of the reply
+    ReaddirNameOverrun,
+    /// v3 READDIRPLUS entry with a handle longer than NFS3_FHSIZE
+    ReaddirplusLongFh,
+    /// v3 READDIR that hands back the cookie it was asked from, never at EOF
+    ReaddirCookieLoop,
+    /// v4 GETATTR whose attribute values run past the end of the reply
+    Fattr4Overrun,
+    /// v4 GETATTR values shorter than the attributes in the mask
+    Fattr4Short,
+    /// v4 GETATTR mask with more words than the reply holds
+    Fattr4LongBitmap,
+}
+
+impl Template {
+    pub const ALL: [Template; 7] = [
+        Template::ReaddirLongName,
+        Template::ReaddirNameOverrun,
+        Template::ReaddirplusLongFh,
+        Template::ReaddirCookieLoop,
+        Template::Fattr4Overrun,
+        Template::Fattr4Short,
+        Template::Fattr4LongBitmap,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Template::ReaddirLongName => "readdir-long-name",
+            Template::ReaddirNameOverrun => "readdir-name-overrun",
+            Template::ReaddirplusLongFh => "readdirplus-long-fh",
+            Template::ReaddirCookieLoop => "readdir-cookie-loop",
+            Template::Fattr4Overrun => "fattr4-overrun",
+            Template::Fattr4Short => "fattr4-short",
+            Template::Fattr4LongBitmap => "fattr4-long-bitmap",
+        }
+    }
+
+    /// NFS version and procedure of the calls the template answers
+    pub fn target(self) -> (u32, u32) {
+        match self {
+            Template::ReaddirLongName
+            | Template::ReaddirNameOverrun
+            | Template::ReaddirCookieLoop => (nfsv3::VERSION, nfsv3::proc::READDIR),
+            Template::ReaddirplusLongFh => (nfsv3::VERSION, nfsv3::proc::READDIRPLUS),
+            Template::Fattr4Overrun | Template::Fattr4Short | Template::Fattr4LongBitmap => {
+                (4, nfsv4::PROC_COMPOUND)
+            }
+        }
+    }
+
+    /// The forged reply to `call`, both without record marks, given the
+    /// upstream's `reply` to it; `None` if the template does not answer
+    /// the call
+    pub fn forge(self, call: &[u8], reply: &[u8]) -> Option<Vec<u8>> {
+        let (xid, version, procedure, args) = parse_call(call)?;
+        if (version, procedure) != self.target() {
+            return None;
+        }
+        let results = if version == nfsv3::VERSION {
+            self.readdir(args)?
+        } else {
+            self.compound(args)?
+        };
+        Some([header(xid, reply), results].concat())
+    }
+
+    /// READDIR3res or READDIRPLUS3res with one forged entry
+    fn readdir(self, args: &[u8]) -> Option<Vec<u8>> {
+        let mut dec = XdrDecoder::new(args);
+        dec.get_opaque().ok()?;
+        let cookie = dec.get_u64().ok()?;
+        let verf = dec.get_opaque_fixed(nfsv3::VERFSIZE).ok()?;
+        let mut enc = XdrEncoder::new();
+        // NFS3_OK, no directory attributes
+        enc.put_u32(0);
+        enc.put_bool(false);
+        enc.put_opaque_fixed(verf);
+        enc.put_bool(true);
+        enc.put_u64(FILEID);
+        match self {
+            Template::ReaddirLongName => enc.put_opaque(&vec![b'a'; LONG_NAME]),
+            Template::ReaddirNameOverrun => {
+                enc.put_u32(OVERRUN);
+                enc.put_raw(b"name");
+                return Some(enc.as_bytes().to_vec());
+            }
+            _ => enc.put_opaque(b"entry"),
+        }
+        // The loop's only entry resumes where the call started, so the
+        // client's next call asks for the same page again
+        match self {
+            Template::ReaddirCookieLoop => enc.put_u64(cookie),
+            _ => enc.put_u64(cookie.wrapping_add(1)),
+        }
+        if self == Template::ReaddirplusLongFh {
+            // No name_attributes, then the handle
+            enc.put_bool(false);
+            enc.put_bool(true);
+            enc.put_opaque(&[0xfe; LONG_FH]);
+        }
+        enc.put_bool(false);
+        enc.put_bool(self != Template::ReaddirCookieLoop);
+        Some(enc.as_bytes().to_vec())
+    }
+
+    /// COMPOUND4res answering every operation, the final GETATTR forged
+    fn compound(self, args: &[u8]) -> Option<Vec<u8>> {
+        let mut dec = XdrDecoder::new(args);
+        let tag = dec.get_opaque().ok()?;
+        dec.get_u32().ok()?;
+        let count = dec.get_u32().ok()?;
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(status::NFS4_OK);
+        enc.put_opaque(tag);
+        enc.put_u32(count);
+        for n in 1..=count {
+            let opcode = dec.get_u32().ok()?;
+            enc.put_u32(opcode);
+            enc.put_u32(status::NFS4_OK);
+            match opcode {
+                op::SEQUENCE => {
+                    let sessionid = dec
+                        .get_opaque_fixed(std::mem::size_of::<SessionId>())
+                        .ok()?;
+                    let (sequenceid, slotid, highest) = (
+                        dec.get_u32().ok()?,
+                        dec.get_u32().ok()?,
+                        dec.get_u32().ok()?,
+                    );
+                    dec.get_bool().ok()?;
+                    enc.put_opaque_fixed(sessionid);
+                    // target_highest_slotid as asked, no status flags
+                    for word in [sequenceid, slotid, highest, highest, 0] {
+                        enc.put_u32(word);
+                    }
+                }
+                op::PUTFH => {
+                    dec.get_opaque().ok()?;
+                }
+                op::PUTROOTFH | op::PUTPUBFH => {}
+                op::GETATTR if n == count => {
+                    let words = dec.get_u32().ok()? as usize;
+                    let mask = dec.get_raw(words.checked_mul(4)?).ok()?;
+                    let mask: Vec<u32> = mask
+                        .chunks(4)
+                        .map(|w| u32::from_be_bytes(w.try_into().unwrap()))
+                        .collect();
+                    self.fattr4(&mut enc, &mask);
+                }
+                _ => return None,
+            }
+        }
+        Some(enc.as_bytes().to_vec())
+    }
+
+    /// The forged fattr4 for a GETATTR of `mask`
+    fn fattr4(self, enc: &mut XdrEncoder, mask: &[u32]) {
+        match self {
+            Template::Fattr4Overrun => {
+                put_bitmap(enc, mask);
+                enc.put_u32(OVERRUN);
+                enc.put_raw(&[0; 8]);
+            }
+            Template::Fattr4Short => {
+                put_bitmap(enc, mask);
+                enc.put_opaque(&[0; 4]);
+            }
+            _ => {
+                enc.put_u32(OVERRUN);
+                enc.put_raw(&[0xff; 8]);
+            }
+        }
+    }
+}
+
+/// XID, NFS version, procedure and arguments of an NFS call; `None` for
+/// anything else, and for RPCSEC_GSS calls whose arguments may be sealed
+fn parse_call(msg: &[u8]) -> Option<(u32, u32, u32, &[u8])> {
+    let mut dec = XdrDecoder::new(msg);
+    let xid = dec.get_u32().ok()?;
+    if dec.get_u32().ok()? != msg_type::CALL || dec.get_u32().ok()? != 2 {
+        return None;
+    }
+    let (prog, version, procedure) = (
+        dec.get_u32().ok()?,
+        dec.get_u32().ok()?,
+        dec.get_u32().ok()?,
+    );
+    if prog != program::NFS || dec.get_u32().ok()? == auth_flavor::RPCSEC_GSS {
+        return None;
+    }
+    dec.get_opaque().ok()?;
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    Some((xid, version, procedure, dec.rest()))
+}
+
+/// The upstream's header in front of the results when it accepted call
+/// `xid`, else an accepted header with an AUTH_NONE verifier
+fn header(xid: u32, reply: &[u8]) -> Vec<u8> {
+    match RpcReply::parse(reply) {
+        Ok(r) if r.xid == xid && r.is_success() => reply[..reply.len() - r.results.len()].to_vec(),
+        _ => {
+            let mut enc = XdrEncoder::new();
+            for word in [
+                xid,
+                msg_type::REPLY,
+                reply_stat::MSG_ACCEPTED,
+                auth_flavor::AUTH_NONE,
+                0,
+                accept_stat::SUCCESS,
+            ] {
+                enc.put_u32(word);
+            }
+            enc.as_bytes().to_vec()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Identity;
+    use crate::nfsv4::{attr, bitmap_from_bits, CompoundBuilder};
+
+    /// `call` without its record mark
+    fn body(call: &[u8]) -> &[u8] {
+        &call[4..]
+    }
+
+    /// Results of a forged reply
+    fn results(reply: &[u8]) -> &[u8] {
+        RpcReply::parse(reply).unwrap().into_results().unwrap()
+    }
+
+    #[test]
+    fn test_readdir_templates() {
+        let id = Identity::new(0, 0);
+        let call = nfsv3::readdir(b"dir", 7, &[1; 8], 4096).message(&id);
+        let reply = Template::ReaddirLongName.forge(body(&call), &[]).unwrap();
+        let mut dec = XdrDecoder::new(results(&reply));
+        assert_eq!(dec.get_u32().unwrap(), 0);
+        assert!(!dec.get_bool().unwrap());
+        assert_eq!(dec.get_opaque_fixed(8).unwrap(), &[1; 8]);
+        assert!(dec.get_bool().unwrap());
+        assert_eq!(dec.get_u64().unwrap(), FILEID);
+        assert_eq!(dec.get_opaque().unwrap().len(), LONG_NAME);
+
+        let reply = Template::ReaddirCookieLoop.forge(body(&call), &[]).unwrap();
+        let tail = &results(&reply)[results(&reply).len() - 16..];
+        assert_eq!(tail, [&7u64.to_be_bytes()[..], &[0; 8]].concat());
+
+        let reply = Template::ReaddirNameOverrun
+            .forge(body(&call), &[])
+            .unwrap();
+        assert!(reply.ends_with(&[&OVERRUN.to_be_bytes()[..], b"name"].concat()));
+
+        // Only the procedure each template targets
+        assert_eq!(Template::ReaddirplusLongFh.forge(body(&call), &[]), None);
+        let getattr = nfsv3::getattr(b"fh").message(&id);
+        assert_eq!(Template::ReaddirLongName.forge(body(&getattr), &[]), None);
+    }
+
+    #[test]
+    fn test_fattr4_templates() {
+        let id = Identity::new(0, 0);
+        let mask = bitmap_from_bits(&[attr::TYPE, attr::SIZE]);
+        let call = CompoundBuilder::new(0)
+            .with_tag(b"t")
+            .putrootfh()
+            .getattr(&mask)
+            .message(&id);
+        let reply = Template::Fattr4Overrun.forge(body(&call), &[]).unwrap();
+        let mut dec = XdrDecoder::new(results(&reply));
+        assert_eq!(dec.get_u32().unwrap(), status::NFS4_OK);
+        assert_eq!(dec.get_opaque().unwrap(), b"t");
+        assert_eq!(dec.get_u32().unwrap(), 2);
+        for opcode in [op::PUTROOTFH, op::GETATTR] {
+            assert_eq!(dec.get_u32().unwrap(), opcode);
+            assert_eq!(dec.get_u32().unwrap(), status::NFS4_OK);
+        }
+        assert_eq!(dec.get_u32().unwrap(), 1);
+        assert_eq!(dec.get_u32().unwrap(), mask[0]);
+        assert_eq!(dec.get_u32().unwrap(), OVERRUN);
+        assert_eq!(dec.remaining(), 8);
+
+        // GETATTR has to come last, after nothing the template can't answer
+        let call = CompoundBuilder::new(0)
+            .putrootfh()
+            .getattr(&mask)
+            .getfh()
+            .message(&id);
+        assert_eq!(Template::Fattr4Short.forge(body(&call), &[]), None);
+    }
+}
@@ -0,0 +1,552 @@
+//! Symlink swap races (TOCTOU)
+//!
+//! A server that resolves a name and then acts on what it found leaves
+//! a window in which the name can change type underneath it. Here one
+//! connection keeps swapping a path component between a directory and a
+//! symlink, two RENAMEs in one COMPOUND each way, while a second
+//! connection sends LOOKUPs and OPENs through that component. The
+//! symlink leads out of the directory, so a server that follows it, or
+//! that checked the component was a directory just before it became a
+//! link, lands somewhere it should not: a reply that succeeds with an
+//! fsid or fileid other than the one the walk should reach is an
+//! escape. NFS4ERR_SYMLINK, NFS4ERR_NOTDIR and NFS4ERR_NOENT are what a
+//! correct server answers while the component is a link or missing.
+//!
+//! Everything is v4.0, so no session is needed; the open-owner's OPENs
+//! are left unconfirmed.
+
+use crate::auth::Identity;
+use crate::connection::NfsConnection;
+use crate::nfsv4::open::{delegation_type, CreateHow, Open};
+use crate::nfsv4::reexport::{self, level_mask, Level};
+use crate::nfsv4::{
+    attr, getattr, lookup, lookupp, minor_version, op, putfh, remove, rename, savefh, status,
+    CompoundBuilder, Fattr, Op,
+};
+use crate::rpc::RpcReply;
+use crate::xdr::XdrDecoder;
+use bytes::BytesMut;
+use std::cell::Cell;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The component that keeps changing type
+pub const PATH: &[u8] = b"toctou-path";
+/// Where the directory waits while the symlink is at [`PATH`]
+pub const DIR_STASH: &[u8] = b"toctou-dir";
+/// Where the symlink waits while the directory is at [`PATH`]
+pub const LINK_STASH: &[u8] = b"toctou-link";
+/// The file inside the directory; named so that a link to `/etc`
+/// followed by mistake reaches a file too
+pub const INNER: &[u8] = b"passwd";
+
+/// Where the symlink can lead: the root, a system directory, and up
+pub const TARGETS: &[&[u8]] = &[b"/", b"/etc", b"..", b"../.."];
+
+/// Spellings of the component the probes look it up by; the last two
+/// are not valid component names and should be refused outright
+pub const COMPONENTS: &[&[u8]] = &[PATH, b"toctou-path/", b"toctou-path/passwd"];
+
+/// What is at [`PATH`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    Dir,
+    Link,
+}
+
+/// How a probe goes through the component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Probe {
+    /// LOOKUP the component, LOOKUP [`INNER`] in it
+    Lookup,
+    /// LOOKUP the component, OPEN [`INNER`] in it
+    Open,
+    /// LOOKUP the component, LOOKUPP back out of it
+    Parent,
+}
+
+impl Probe {
+    pub const ALL: [Probe; 3] = [Probe::Lookup, Probe::Open, Probe::Parent];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Probe::Lookup => "lookup",
+            Probe::Open => "open",
+            Probe::Parent => "parent",
+        }
+    }
+}
+
+/// Every probe through every spelling of the component
+pub fn probes() -> Vec<(Probe, &'static [u8])> {
+    Probe::ALL
+        .iter()
+        .flat_map(|&p| COMPONENTS.iter().map(move |&c| (p, c)))
+        .collect()
+}
+
+/// Where a probe's GETATTR should land
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Known {
+    /// The directory the component lives in
+    pub parent: Level,
+    /// [`INNER`]
+    pub inner: Level,
+}
+
+impl Known {
+    fn expected(&self, probe: Probe) -> Level {
+        match probe {
+            Probe::Parent => self.parent,
+            Probe::Lookup | Probe::Open => self.inner,
+        }
+    }
+}
+
+/// The directory the race runs in, filled in at run time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixture {
+    /// v4 handle of a writable directory
+    pub dir: Vec<u8>,
+    pub clientid: u64,
+    pub owner: Vec<u8>,
+    /// What the symlink points to, one of [`TARGETS`] or any other
+    pub target: Vec<u8>,
+}
+
+/// CREATE of directory `name` in the current directory
+fn mkdir(name: &[u8]) -> Op {
+    Op::new(op::CREATE, |enc| {
+        // NF4DIR, the name, an empty fattr4
+        enc.put_u32(2);
+        enc.put_opaque(name);
+        enc.put_u32(0);
+        enc.put_u32(0);
+    })
+}
+
+impl Fixture {
+    fn message(&self, ops: impl IntoIterator<Item = Op>, identity: &Identity) -> BytesMut {
+        CompoundBuilder::new(minor_version::V4_0)
+            .with_tag(b"toctou")
+            .putfh(&self.dir)
+            .ops(ops)
+            .message(identity)
+    }
+
+    /// The directory at [`PATH`] with [`INNER`] in it, and the symlink
+    /// at [`LINK_STASH`]
+    pub fn setup(&self, identity: &Identity) -> BytesMut {
+        let mut create = Open::existing(self.clientid, &self.owner, INNER);
+        create.create = Some(CreateHow::Unchecked(Fattr::default()));
+        let ops = [
+            mkdir(PATH),
+            create.op(),
+            putfh(&self.dir),
+            reexport::symlink(LINK_STASH, &self.target),
+        ];
+        self.message(ops, identity)
+    }
+
+    /// GETATTRs of the directory and of [`INNER`], which
+    /// [`decode_known`] reads; sent after [`Fixture::setup`]
+    pub fn survey(&self, identity: &Identity) -> BytesMut {
+        let ops = [
+            getattr(&level_mask()),
+            lookup(PATH),
+            lookup(INNER),
+            getattr(&level_mask()),
+        ];
+        self.message(ops, identity)
+    }
+
+    /// Put `to` at [`PATH`], moving the other out of the way first
+    pub fn swap(&self, to: Form, identity: &Identity) -> BytesMut {
+        let (away, back) = match to {
+            Form::Link => (DIR_STASH, LINK_STASH),
+            Form::Dir => (LINK_STASH, DIR_STASH),
+        };
+        let ops = [savefh(), rename(PATH, away), rename(back, PATH)];
+        self.message(ops, identity)
+    }
+
+    pub fn probe(&self, probe: Probe, component: &[u8], identity: &Identity) -> BytesMut {
+        let through = match probe {
+            Probe::Lookup => lookup(INNER),
+            Probe::Open => Open::existing(self.clientid, &self.owner, INNER).op(),
+            Probe::Parent => lookupp(),
+        };
+        let ops = [lookup(component), through, getattr(&level_mask())];
+        self.message(ops, identity)
+    }
+
+    /// Everything setup made, wherever the swaps left it; each compound
+    /// stops at the first name that is not there
+    pub fn cleanup(&self, identity: &Identity) -> Vec<BytesMut> {
+        let mut out = Vec::new();
+        for dir in [PATH, DIR_STASH] {
+            out.push(self.message([lookup(dir), remove(INNER)], identity));
+        }
+        for name in [PATH, DIR_STASH, LINK_STASH] {
+            out.push(self.message([remove(name)], identity));
+        }
+        out
+    }
+}
+
+/// The parent and [`INNER`] levels from the reply to
+/// [`Fixture::survey`]
+pub fn decode_known(results: &[u8]) -> Option<Known> {
+    let walk = reexport::decode_walk(results)?;
+    match (walk.failed, &walk.levels[..]) {
+        (None, &[parent, inner]) => Some(Known { parent, inner }),
+        _ => None,
+    }
+}
+
+/// What one probe saw
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Reached what it should have
+    Inside,
+    /// Refused, with the status of the op that failed
+    Refused(u32),
+    /// Succeeded somewhere else
+    Escaped(Level),
+    /// The reply does not decode
+    Garbled,
+}
+
+/// Skip an OPEN4resok
+fn skip_open(dec: &mut XdrDecoder<'_>) -> Option<()> {
+    // Stateid, change_info4, rflags
+    dec.get_raw(16 + 20 + 4).ok()?;
+    let words = dec.get_u32().ok()?;
+    dec.get_raw(4 * words as usize).ok()?;
+    let ace = |dec: &mut XdrDecoder<'_>| -> Option<()> {
+        dec.get_raw(12).ok()?;
+        dec.get_opaque().ok().map(|_| ())
+    };
+    match dec.get_u32().ok()? {
+        delegation_type::NONE => {}
+        delegation_type::READ => {
+            // Stateid, recall
+            dec.get_raw(16 + 4).ok()?;
+            ace(dec)?;
+        }
+        delegation_type::WRITE => {
+            // Stateid, recall, space limit
+            dec.get_raw(16 + 4 + 4 + 8).ok()?;
+            ace(dec)?;
+        }
+        delegation_type::NONE_EXT => {
+            // Contention and resource carry whether the server will signal
+            if matches!(dec.get_u32().ok()?, 1 | 2) {
+                dec.get_u32().ok()?;
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+/// The level a probe's COMPOUND4res reached, or the status it stopped at
+fn reached(results: &[u8]) -> Option<Result<Level, u32>> {
+    let mut dec = XdrDecoder::new(results);
+    dec.get_u32().ok()?;
+    dec.get_opaque().ok()?;
+    let count = dec.get_u32().ok()?;
+    for _ in 0..count {
+        let opcode = dec.get_u32().ok()?;
+        let stat = dec.get_u32().ok()?;
+        if stat != status::NFS4_OK {
+            return Some(Err(stat));
+        }
+        match opcode {
+            op::OPEN => skip_open(&mut dec)?,
+            op::GETATTR => {
+                let words = dec.get_u32().ok()?;
+                let mut mask = Vec::new();
+                for _ in 0..words {
+                    mask.push(dec.get_u32().ok()?);
+                }
+                let vals = dec.get_opaque().ok()?;
+                let has = |bit: u32| mask.first().is_some_and(|w| w & (1 << bit) != 0);
+                let mut vals = XdrDecoder::new(vals);
+                let mut level = Level {
+                    fsid: (0, 0),
+                    fileid: 0,
+                };
+                if has(attr::FSID) {
+                    level.fsid = (vals.get_u64().ok()?, vals.get_u64().ok()?);
+                }
+                if has(attr::FILEID) {
+                    level.fileid = vals.get_u64().ok()?;
+                }
+                return Some(Ok(level));
+            }
+            // PUTFH, LOOKUP and LOOKUPP return nothing more
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Judge a probe's COMPOUND4res against where it should have landed
+pub fn classify(probe: Probe, known: &Known, results: &[u8]) -> Verdict {
+    match reached(results) {
+        Some(Ok(level)) if level == known.expected(probe) => Verdict::Inside,
+        Some(Ok(level)) => Verdict::Escaped(level),
+        Some(Err(stat)) => Verdict::Refused(stat),
+        None => Verdict::Garbled,
+    }
+}
+
+/// What the probes saw while the component was being swapped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub inside: usize,
+    pub refused: usize,
+    pub garbled: usize,
+    /// Calls that got no reply
+    pub lost: usize,
+    pub escapes: Vec<(Probe, Vec<u8>, Level)>,
+}
+
+/// Swap the component `rounds` times there and back on `swapper`, ending
+/// as a directory, while `prober` sends [`probes`] through it in turn
+/// until the swapping stops
+pub async fn run<S>(
+    swapper: &mut NfsConnection<S>,
+    prober: &mut NfsConnection<S>,
+    fixture: &Fixture,
+    known: &Known,
+    rounds: usize,
+    identity: &Identity,
+) -> Tally
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let done = Cell::new(false);
+    let swapping = async {
+        for i in 0..2 * rounds {
+            let to = if i.is_multiple_of(2) {
+                Form::Link
+            } else {
+                Form::Dir
+            };
+            if swapper.call(&fixture.swap(to, identity)).await.is_err() {
+                break;
+            }
+        }
+        done.set(true);
+    };
+    let probing = async {
+        let mut tally = Tally::default();
+        for (probe, component) in probes().into_iter().cycle() {
+            if done.get() {
+                break;
+            }
+            let msg = fixture.probe(probe, component, identity);
+            let reply = match prober.call(&msg).await {
+                Ok(reply) => reply,
+                Err(_) => {
+                    tally.lost += 1;
+                    continue;
+                }
+            };
+            let results = RpcReply::parse(&reply)
+                .map(|r| r.results)
+                .unwrap_or_default();
+            match classify(probe, known, results) {
+                Verdict::Inside => tally.inside += 1,
+                Verdict::Refused(_) => tally.refused += 1,
+                Verdict::Garbled => tally.garbled += 1,
+                Verdict::Escaped(level) => tally.escapes.push((probe, component.to_vec(), level)),
+            }
+        }
+        tally
+    };
+    tokio::join!(swapping, probing).1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Timeouts;
+    use crate::hang::read_record;
+    use crate::xdr::XdrEncoder;
+    use tokio::io::AsyncWriteExt;
+
+    fn fixture() -> Fixture {
+        Fixture {
+            dir: vec![3; 16],
+            clientid: 9,
+            owner: b"toctou".to_vec(),
+            target: b"/etc".to_vec(),
+        }
+    }
+
+    fn level(fsid: u64, fileid: u64) -> Level {
+        Level {
+            fsid: (fsid, 0),
+            fileid,
+        }
+    }
+
+    /// COMPOUND4res of `ops`, each OK, the GETATTRs reporting `levels`
+    /// in turn; an OPEN gets a read delegation
+    fn results(ops: &[u32], levels: &[Level]) -> Vec<u8> {
+        let mut levels = levels.iter();
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(status::NFS4_OK);
+        enc.put_opaque(b"");
+        enc.put_u32(ops.len() as u32);
+        for &opcode in ops {
+            enc.put_u32(opcode);
+            enc.put_u32(status::NFS4_OK);
+            if opcode == op::OPEN {
+                enc.put_raw(&[0; 16 + 20 + 4]);
+                enc.put_u32(0);
+                enc.put_u32(delegation_type::READ);
+                enc.put_raw(&[0; 16 + 4 + 12]);
+                enc.put_opaque(b"OWNER@");
+            }
+            if opcode == op::GETATTR {
+                let at = levels.next().unwrap();
+                enc.put_u32(1);
+                enc.put_u32(1 << attr::FSID | 1 << attr::FILEID);
+                let mut vals = XdrEncoder::new();
+                vals.put_u64(at.fsid.0);
+                vals.put_u64(at.fsid.1);
+                vals.put_u64(at.fileid);
+                enc.put_opaque(vals.as_bytes());
+            }
+        }
+        enc.as_bytes().to_vec()
+    }
+
+    /// A probe's ops, landing at `at`
+    fn probed(through: u32, at: Level) -> Vec<u8> {
+        results(&[op::PUTFH, op::LOOKUP, through, op::GETATTR], &[at])
+    }
+
+    #[test]
+    fn test_messages_and_verdicts() {
+        let f = fixture();
+        let id = Identity::new(0, 0);
+        assert_eq!(probes().len(), 3 * COMPONENTS.len());
+        let to_link = f.swap(Form::Link, &id);
+        let to_dir = f.swap(Form::Dir, &id);
+        // The directory goes out first, then the link comes in
+        let at = |m: &[u8], s: &[u8]| m.windows(s.len()).position(|w| w == s).unwrap();
+        assert!(at(&to_link, DIR_STASH) < at(&to_link, LINK_STASH));
+        assert!(at(&to_dir, LINK_STASH) < at(&to_dir, DIR_STASH));
+        let setup = f.setup(&id);
+        assert!(at(&setup, LINK_STASH) > at(&setup, b"/etc"));
+        assert_eq!(f.cleanup(&id).len(), 5);
+
+        let known = Known {
+            parent: level(1, 2),
+            inner: level(1, 3),
+        };
+        let ops = [op::PUTFH, op::GETATTR, op::LOOKUP, op::LOOKUP, op::GETATTR];
+        let survey = results(&ops, &[known.parent, known.inner]);
+        assert_eq!(decode_known(&survey), Some(known));
+        assert_eq!(decode_known(&results(&ops[..2], &[known.parent])), None);
+
+        assert_eq!(
+            classify(Probe::Open, &known, &probed(op::OPEN, known.inner)),
+            Verdict::Inside
+        );
+        // Followed the link to the real /etc/passwd
+        assert_eq!(
+            classify(Probe::Open, &known, &probed(op::OPEN, level(7, 40))),
+            Verdict::Escaped(level(7, 40))
+        );
+        let parent = probed(op::LOOKUPP, known.parent);
+        assert_eq!(classify(Probe::Parent, &known, &parent), Verdict::Inside);
+        assert_eq!(
+            classify(Probe::Lookup, &known, &parent),
+            Verdict::Escaped(known.parent)
+        );
+        let mut refused = XdrEncoder::new();
+        refused.put_u32(status::NFS4ERR_SYMLINK);
+        refused.put_opaque(b"");
+        refused.put_u32(2);
+        refused.put_u32(op::PUTFH);
+        refused.put_u32(status::NFS4_OK);
+        refused.put_u32(op::LOOKUP);
+        refused.put_u32(status::NFS4ERR_SYMLINK);
+        assert_eq!(
+            classify(Probe::Lookup, &known, refused.as_bytes()),
+            Verdict::Refused(status::NFS4ERR_SYMLINK)
+        );
+        assert_eq!(
+            classify(Probe::Open, &known, &probed(op::OPEN, known.inner)[..40]),
+            Verdict::Garbled
+        );
+    }
+
+    #[tokio::test]
+    async fn test_run_counts_escapes() {
+        let (ca, mut sa) = tokio::io::duplex(1 << 16);
+        let (cb, mut sb) = tokio::io::duplex(1 << 16);
+        let timeouts = Timeouts::default();
+        let mut swapper = NfsConnection::new(ca, timeouts);
+        let prober = NfsConnection::new(cb, timeouts);
+        let known = Known {
+            parent: level(1, 2),
+            inner: level(1, 3),
+        };
+        let reply = |call: &[u8], results: &[u8]| {
+            // xid, REPLY, MSG_ACCEPTED, AUTH_NONE verifier, SUCCESS
+            let mut msg = call[..4].to_vec();
+            msg.extend_from_slice(&[0, 0, 0, 1]);
+            msg.extend_from_slice(&[0; 16]);
+            msg.extend_from_slice(results);
+            let mut record = (0x8000_0000 | msg.len() as u32).to_be_bytes().to_vec();
+            record.extend_from_slice(&msg);
+            record
+        };
+        // The swapper's server answers the four swaps; the prober's says
+        // every LOOKUPP probe escaped to fsid 9 and every other got in
+        let swaps = async {
+            for _ in 0..4 {
+                let call = read_record(&mut sa).await.unwrap();
+                let results = results(&[op::PUTFH, op::SAVEFH], &[]);
+                sa.write_all(&reply(&call, &results)).await.unwrap();
+            }
+        };
+        let served = async {
+            while let Ok(call) = read_record(&mut sb).await {
+                // LOOKUPP has no arguments, so it sits right before the
+                // final GETATTR's opcode and two-word bitmap
+                let n = call.len();
+                let parent = call[n - 16..n - 12] == op::LOOKUPP.to_be_bytes();
+                let at = if parent { level(9, 1) } else { known.inner };
+                let through = if parent { op::LOOKUPP } else { op::LOOKUP };
+                if sb
+                    .write_all(&reply(&call, &probed(through, at)))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        };
+        let id = Identity::new(0, 0);
+        let probing = async {
+            let mut prober = prober;
+            let tally = run(&mut swapper, &mut prober, &fixture(), &known, 2, &id).await;
+            drop(prober);
+            tally
+        };
+        let (_, _, tally) = tokio::join!(swaps, served, probing);
+        assert!(tally.inside + tally.escapes.len() >= 1);
+        assert_eq!((tally.garbled, tally.lost), (0, 0));
+        assert!(tally
+            .escapes
+            .iter()
+            .all(|(p, _, l)| *p == Probe::Parent && l.fsid == (9, 0)));
+    }
+}
@@ -0,0 +1,375 @@
+//! Recorded traffic to scenario conversion
+//!
+//! A transcript is a list of RPC calls, each with the reply it got if one
+//! was captured. It comes either from a JSON-lines file of hex-encoded
+//! call/reply pairs or from the raw record-marked TCP streams of a capture
+//! (e.g. Wireshark's "Follow TCP Stream" raw export, one file per
+//! direction). NFSv3 namespace calls are turned into scenario steps so
+//! real client workloads can seed sequence-level mutation.
+
+use crate::nfsv3::proc;
+use crate::rpc::{msg_type, program, reply_stat};
+use crate::scenario::{Action, FhRef, Outcome, Scenario, Step};
+use crate::xdr::XdrDecoder;
+use serde::Deserialize;
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// NFSv3 procedures the converter understands (RFC 1813 §3.3)
+const CONVERTED: &[u32] = &[
+    proc::LOOKUP,
+    proc::CREATE,
+    proc::MKDIR,
+    proc::REMOVE,
+    proc::RMDIR,
+    proc::RENAME,
+    proc::LINK,
+];
+
+#[derive(Debug, Error)]
+pub enum TranscriptError {
+    #[error("line {line}: {source}")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("line {line}: bad hex: {source}")]
+    Hex {
+        line: usize,
+        source: hex::FromHexError,
+    },
+    #[error("record stream truncated at offset {0}")]
+    Truncated(usize),
+}
+
+/// One call and its reply, both without record marks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptEntry {
+    pub call: Vec<u8>,
+    pub reply: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct JsonEntry {
+    call: String,
+    reply: Option<String>,
+}
+
+/// Parse `{"call": "<hex>", "reply": "<hex>"}` lines; blank lines are skipped
+pub fn parse_jsonl(text: &str) -> Result<Vec<TranscriptEntry>, TranscriptError> {
+    let mut entries = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_no = i + 1;
+        let raw: JsonEntry =
+            serde_json::from_str(line).map_err(|source| TranscriptError::Json {
+                line: line_no,
+                source,
+            })?;
+        let decode = |s: &str| {
+            hex::decode(s).map_err(|source| TranscriptError::Hex {
+                line: line_no,
+                source,
+            })
+        };
+        entries.push(TranscriptEntry {
+            call: decode(&raw.call)?,
+            reply: raw.reply.as_deref().map(decode).transpose()?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Split a record-marked TCP stream into records, joining fragments
+pub fn split_records(stream: &[u8]) -> Result<Vec<Vec<u8>>, TranscriptError> {
+    let mut records = Vec::new();
+    let mut current = Vec::new();
+    let mut pos = 0;
+    while pos < stream.len() {
+        let mark = stream
+            .get(pos..pos + 4)
+            .ok_or(TranscriptError::Truncated(pos))?;
+        let mark = u32::from_be_bytes(mark.try_into().unwrap());
+        let len = (mark & 0x7fff_ffff) as usize;
+        let frag = stream
+            .get(pos + 4..pos + 4 + len)
+            .ok_or(TranscriptError::Truncated(pos))?;
+        current.extend_from_slice(frag);
+        pos += 4 + len;
+        if mark & 0x8000_0000 != 0 {
+            records.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        return Err(TranscriptError::Truncated(pos));
+    }
+    Ok(records)
+}
+
+/// Pair calls with replies by XID
+pub fn pair_by_xid(calls: Vec<Vec<u8>>, replies: Vec<Vec<u8>>) -> Vec<TranscriptEntry> {
+    let mut by_xid: HashMap<[u8; 4], Vec<u8>> = HashMap::new();
+    for reply in replies {
+        if let Some(xid) = reply.get(..4) {
+            by_xid.entry(xid.try_into().unwrap()).or_insert(reply);
+        }
+    }
+    calls
+        .into_iter()
+        .map(|call| {
+            let reply = call
+                .get(..4)
+                .and_then(|xid| by_xid.remove(<&[u8; 4]>::try_from(xid).unwrap()));
+            TranscriptEntry { call, reply }
+        })
+        .collect()
+}
+
+struct Call<'a> {
+    xid: u32,
+    procedure: u32,
+    args: XdrDecoder<'a>,
+}
+
+fn parse_call(buf: &[u8]) -> Option<Call<'_>> {
+    let mut r = XdrDecoder::new(buf);
+    let xid = r.get_u32().ok()?;
+    if r.get_u32().ok()? != msg_type::CALL {
+        return None;
+    }
+    let _rpcvers = r.get_u32().ok()?;
+    let (prog, vers, procedure) = (r.get_u32().ok()?, r.get_u32().ok()?, r.get_u32().ok()?);
+    if prog != program::NFS || vers != 3 {
+        return None;
+    }
+    for _ in 0..2 {
+        r.get_u32().ok()?;
+        r.get_opaque().ok()?;
+    }
+    Some(Call {
+        xid,
+        procedure,
+        args: r,
+    })
+}
+
+/// nfsstat3 and the rest of the result, if the call was accepted
+fn parse_reply(buf: &[u8]) -> Option<(u32, XdrDecoder<'_>)> {
+    let mut r = XdrDecoder::new(buf);
+    r.get_u32().ok()?;
+    if r.get_u32().ok()? != msg_type::REPLY || r.get_u32().ok()? != reply_stat::MSG_ACCEPTED {
+        return None;
+    }
+    r.get_u32().ok()?;
+    r.get_opaque().ok()?;
+    if r.get_u32().ok()? != 0 {
+        return None;
+    }
+    Some((r.get_u32().ok()?, r))
+}
+
+/// A converted transcript and the calls that could not be expressed
+#[derive(Debug, Clone)]
+pub struct Conversion {
+    pub scenario: Scenario,
+    pub skipped: Vec<String>,
+}
+
+/// Known handles; the first unknown one becomes the export root
+fn resolve(handles: &mut HashMap<Vec<u8>, FhRef>, fh: &[u8]) -> Option<FhRef> {
+    if let Some(r) = handles.get(fh) {
+        return Some(*r);
+    }
+    if handles.values().any(|r| *r == FhRef::Root) {
+        return None;
+    }
+    handles.insert(fh.to_vec(), FhRef::Root);
+    Some(FhRef::Root)
+}
+
+/// diropargs3; `Err` if truncated, `Ok(None)` if the handle is unknown
+fn dirop(
+    r: &mut XdrDecoder,
+    handles: &mut HashMap<Vec<u8>, FhRef>,
+) -> Result<Option<(FhRef, Vec<u8>)>, ()> {
+    let fh = r.get_opaque().map_err(drop)?;
+    let name = r.get_opaque().map_err(drop)?.to_vec();
+    Ok(resolve(handles, fh).map(|dir| (dir, name)))
+}
+
+fn parse_action(
+    procedure: u32,
+    r: &mut XdrDecoder,
+    handles: &mut HashMap<Vec<u8>, FhRef>,
+) -> Result<Option<Action>, ()> {
+    let action = match procedure {
+        proc::LOOKUP => dirop(r, handles)?.map(|(dir, name)| Action::Lookup { dir, name }),
+        proc::CREATE => dirop(r, handles)?.map(|(dir, name)| Action::Create { dir, name }),
+        proc::MKDIR => dirop(r, handles)?.map(|(dir, name)| Action::Mkdir { dir, name }),
+        proc::REMOVE => dirop(r, handles)?.map(|(dir, name)| Action::Remove { dir, name }),
+        proc::RMDIR => dirop(r, handles)?.map(|(dir, name)| Action::Rmdir { dir, name }),
+        proc::RENAME => {
+            let from = dirop(r, handles)?;
+            let to = dirop(r, handles)?;
+            from.zip(to).map(
+                |((from_dir, from_name), (to_dir, to_name))| Action::Rename {
+                    from_dir,
+                    from_name,
+                    to_dir,
+                    to_name,
+                },
+            )
+        }
+        proc::LINK => {
+            let file = r.get_opaque().map_err(drop)?.to_vec();
+            let link = dirop(r, handles)?;
+            match (handles.get(&file), link) {
+                (Some(&file), Some((dir, name))) => Some(Action::Link { file, dir, name }),
+                _ => None,
+            }
+        }
+        _ => unreachable!("filtered by caller"),
+    };
+    Ok(action)
+}
+
+/// Convert NFSv3 namespace calls into scenario steps
+///
+/// The first handle not produced by an earlier step is taken as the
+/// export root; calls on any other unknown handle are skipped. Outcomes
+/// come from the captured replies (`Either` when there was none), and no
+/// cleanup is generated.
+pub fn to_scenario(name: &str, entries: &[TranscriptEntry]) -> Conversion {
+    let mut scenario = Scenario::new(name);
+    let mut skipped = Vec::new();
+    let mut handles: HashMap<Vec<u8>, FhRef> = HashMap::new();
+
+    for entry in entries {
+        let Some(mut call) = parse_call(&entry.call) else {
+            continue;
+        };
+        if !CONVERTED.contains(&call.procedure) {
+            continue;
+        }
+        let action = match parse_action(call.procedure, &mut call.args, &mut handles) {
+            Ok(Some(action)) => action,
+            Ok(None) => {
+                skipped.push(format!("xid {:#x}: unknown filehandle", call.xid));
+                continue;
+            }
+            Err(()) => {
+                skipped.push(format!("xid {:#x}: truncated arguments", call.xid));
+                continue;
+            }
+        };
+
+        let reply = entry.reply.as_deref().and_then(parse_reply);
+        let expect = match &reply {
+            None => Outcome::Either,
+            Some((0, _)) => Outcome::Success,
+            Some((status, _)) => Outcome::Error(*status),
+        };
+        let produces = action.produces_fh();
+        let step = scenario.push(Step { action, expect });
+
+        // Map the handle the reply produced to this step
+        if let (true, Some((0, mut r))) = (produces, reply) {
+            let fh = if call.procedure == proc::LOOKUP {
+                r.get_opaque().ok()
+            } else {
+                match r.get_u32().ok() {
+                    Some(1) => r.get_opaque().ok(),
+                    _ => None,
+                }
+            };
+            if let Some(fh) = fh {
+                handles.entry(fh.to_vec()).or_insert(step);
+            }
+        }
+    }
+    Conversion { scenario, skipped }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{success_reply, RpcCall};
+    use crate::xdr::XdrEncoder;
+
+    fn call(xid: u32, procedure: u32, args: &[u8]) -> Vec<u8> {
+        RpcCall::new(xid, program::NFS, 3, procedure, false)
+            .with_auth_none()
+            .with_args(args)
+            .build()
+            .to_vec()
+    }
+
+    fn reply(xid: u32, body: impl FnOnce(&mut XdrEncoder)) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        body(&mut enc);
+        success_reply(xid, enc.as_bytes())
+    }
+
+    fn dirop(fh: &[u8], name: &str) -> Vec<u8> {
+        let mut enc = XdrEncoder::new();
+        enc.put_opaque(fh);
+        enc.put_string(name);
+        enc.into_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_split_records_joins_fragments() {
+        let stream = [0, 0, 0, 2, 1, 2, 0x80, 0, 0, 1, 3, 0x80, 0, 0, 1, 4];
+        assert_eq!(
+            split_records(&stream).unwrap(),
+            vec![vec![1, 2, 3], vec![4]]
+        );
+        assert!(split_records(&stream[..6]).is_err());
+    }
+
+    #[test]
+    fn test_mkdir_then_remove_resolves_handles() {
+        let calls = vec![
+            call(1, proc::MKDIR, &dirop(b"root", "d")),
+            call(2, proc::REMOVE, &dirop(b"dirfh", "f")),
+            call(3, proc::RMDIR, &dirop(b"other", "x")),
+        ];
+        let replies = vec![
+            reply(1, |e| {
+                e.put_u32(0);
+                e.put_bool(true);
+                e.put_opaque(b"dirfh");
+            }),
+            reply(2, |e| e.put_u32(2)),
+        ];
+        let conv = to_scenario("t", &pair_by_xid(calls, replies));
+        let steps = &conv.scenario.steps;
+        assert_eq!(steps.len(), 2);
+        assert_eq!(steps[0].expect, Outcome::Success);
+        assert_eq!(
+            steps[1],
+            Step {
+                action: Action::Remove {
+                    dir: FhRef::Step(0),
+                    name: b"f".to_vec()
+                },
+                expect: Outcome::Error(2),
+            }
+        );
+        assert_eq!(conv.skipped.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_jsonl() {
+        let text =
+            "{\"call\": \"0001\", \"reply\": null}\n\n{\"call\": \"ff\", \"reply\": \"00\"}\n";
+        let entries = parse_jsonl(text).unwrap();
+        assert_eq!(entries[1].reply, Some(vec![0]));
+        assert!(matches!(
+            parse_jsonl("{\"call\": \"zz\"}"),
+            Err(TranscriptError::Hex { line: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,389 @@
+//! Decoders for vendor-private attributes and operations
+//!
+//! Servers put private attributes and operations in the number space the
+//! spec leaves unassigned. Without a decoder their values are opaque, and
+//! so is everything after them: fattr4 values are concatenated without
+//! lengths, so one unknown attribute hides every higher-numbered one.
+//! Decoders registered in a [`Registry`] name those values and may flag
+//! them; the analyzer reports both alongside its own checks. Library
+//! users implement [`VendorDecoder`]; [`SpecDecoder`] builds one from a
+//! JSON description for everyone else.
+
+use crate::xdr::XdrDecoder;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VendorError {
+    #[error("vendor decoder spec: {0}")]
+    Spec(#[from] serde_json::Error),
+}
+
+/// A decoded value, the bytes it took, and anything wrong with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    pub value: Value,
+    pub len: usize,
+    pub findings: Vec<String>,
+}
+
+/// Decodes some vendor attributes and operation results; every method
+/// returns `None` for numbers it does not handle
+pub trait VendorDecoder: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// The fattr4 value of attribute `bit` at the start of `buf`
+    fn attr(&self, _bit: u32, _buf: &[u8]) -> Option<Decoded> {
+        None
+    }
+
+    /// The result of op `opcode` at the start of `buf`, after its status
+    fn op(&self, _opcode: u32, _buf: &[u8]) -> Option<Decoded> {
+        None
+    }
+}
+
+/// Registered decoders, asked in registration order
+#[derive(Default)]
+pub struct Registry {
+    decoders: Vec<Box<dyn VendorDecoder>>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.decoders.iter().map(|d| d.name()))
+            .finish()
+    }
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, decoder: impl VendorDecoder + 'static) {
+        self.decoders.push(Box::new(decoder));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    /// The first decoder that handles attribute `bit`, and its result
+    pub fn attr(&self, bit: u32, buf: &[u8]) -> Option<(&str, Decoded)> {
+        self.first(buf, |d| d.attr(bit, buf))
+    }
+
+    /// The first decoder that handles op `opcode`, and its result
+    pub fn op(&self, opcode: u32, buf: &[u8]) -> Option<(&str, Decoded)> {
+        self.first(buf, |d| d.op(opcode, buf))
+    }
+
+    // A decoder claiming more bytes than there are is ignored
+    fn first(
+        &self,
+        buf: &[u8],
+        f: impl Fn(&dyn VendorDecoder) -> Option<Decoded>,
+    ) -> Option<(&str, Decoded)> {
+        self.decoders.iter().find_map(|d| {
+            f(d.as_ref())
+                .filter(|v| v.len <= buf.len())
+                .map(|v| (d.name(), v))
+        })
+    }
+}
+
+/// Wire type of one field in a [`SpecDecoder`] description
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    U32,
+    U64,
+    Bool,
+    Opaque,
+    String,
+    /// Fixed-length opaque of this many bytes
+    Fixed(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct AttrSpec {
+    pub bit: u32,
+    pub name: String,
+    pub fields: Vec<Field>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct OpSpec {
+    pub opcode: u32,
+    pub name: String,
+    pub result: Vec<Field>,
+}
+
+/// A decoder described as data:
+///
+/// ```json
+/// {"name": "acme", "attrs": [{"bit": 1000, "name": "volume_uuid",
+///   "fields": [{"name": "uuid", "type": {"fixed": 16}}]}],
+///  "ops": [{"opcode": 20000, "name": "acme_stat",
+///   "result": [{"name": "load", "type": "u32"}]}]}
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SpecDecoder {
+    pub name: String,
+    #[serde(default)]
+    pub attrs: Vec<AttrSpec>,
+    #[serde(default)]
+    pub ops: Vec<OpSpec>,
+}
+
+impl SpecDecoder {
+    pub fn from_json(text: &str) -> Result<Self, VendorError> {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+/// Bytes as text when they are UTF-8, `hex:` otherwise
+fn bytes_value(b: &[u8]) -> Value {
+    match std::str::from_utf8(b) {
+        Ok(s) => json!(s),
+        Err(_) => json!(format!("hex:{}", hex::encode(b))),
+    }
+}
+
+fn decode_fields(fields: &[Field], buf: &[u8]) -> Option<Decoded> {
+    let mut dec = XdrDecoder::new(buf);
+    let mut out = serde_json::Map::new();
+    let mut findings = Vec::new();
+    for field in fields {
+        let value = match field.ty {
+            FieldType::U32 => json!(dec.get_u32().ok()?),
+            FieldType::U64 => json!(dec.get_u64().ok()?),
+            FieldType::Bool => {
+                let v = dec.get_u32().ok()?;
+                if v > 1 {
+                    findings.push(format!("{} is bool {}", field.name, v));
+                }
+                json!(v != 0)
+            }
+            FieldType::Fixed(n) => json!(hex::encode(dec.get_opaque_fixed(n).ok()?)),
+            FieldType::Opaque => json!(hex::encode(dec.get_opaque().ok()?)),
+            FieldType::String => {
+                let data = dec.get_opaque().ok()?;
+                if std::str::from_utf8(data).is_err() {
+                    findings.push(format!("{} is not UTF-8", field.name));
+                }
+                bytes_value(data)
+            }
+        };
+        out.insert(field.name.clone(), value);
+    }
+    Some(Decoded {
+        value: Value::Object(out),
+        len: dec.position(),
+        findings,
+    })
+}
+
+impl VendorDecoder for SpecDecoder {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn attr(&self, bit: u32, buf: &[u8]) -> Option<Decoded> {
+        let spec = self.attrs.iter().find(|a| a.bit == bit)?;
+        let mut d = decode_fields(&spec.fields, buf)?;
+        d.value = json!({ spec.name.as_str(): d.value });
+        Some(d)
+    }
+
+    fn op(&self, opcode: u32, buf: &[u8]) -> Option<Decoded> {
+        let spec = self.ops.iter().find(|o| o.opcode == opcode)?;
+        let mut d = decode_fields(&spec.result, buf)?;
+        d.value = json!({ spec.name.as_str(): d.value });
+        Some(d)
+    }
+}
+
+/// How a standard attribute is encoded, for walking past it
+#[derive(Debug, Clone, Copy)]
+enum Encoding {
+    Fixed(usize),
+    Opaque,
+    /// bitmap4 or another counted array of words
+    Words,
+}
+
+/// RFC 8881 §5.8 and RFC 7862 §12.2; attributes with structured values
+/// (ACLs, fs_locations, layouts) are not walked
+fn standard_encoding(bit: u32) -> Option<Encoding> {
+    use Encoding::*;
+    Some(match bit {
+        0 | 62 | 64 | 75 => Words,
+        1
+        | 2
+        | 5
+        | 6
+        | 7
+        | 9
+        | 10
+        | 11
+        | 13..=18
+        | 25
+        | 26
+        | 28
+        | 29
+        | 33
+        | 34
+        | 35
+        | 46
+        | 65
+        | 66
+        | 76
+        | 77
+        | 79
+        | 82 => Fixed(4),
+        3 | 4 | 20..=23 | 27 | 30 | 31 | 38..=45 | 55 | 73 | 78 => Fixed(8),
+        47 | 49..=53 | 56 | 57 => Fixed(12),
+        8 | 60 => Fixed(16),
+        19 | 32 | 36 | 37 => Opaque,
+        _ => return None,
+    })
+}
+
+/// One attribute value a vendor decoder produced
+#[derive(Debug, Clone, PartialEq)]
+pub struct VendorValue {
+    pub decoder: String,
+    pub bit: u32,
+    pub value: Value,
+    pub findings: Vec<String>,
+}
+
+/// What [`walk_fattr4`] got through
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Fattr4Walk {
+    pub vendor: Vec<VendorValue>,
+    /// The first attribute nothing could decode; it and every later one
+    /// stay opaque
+    pub opaque_from: Option<u32>,
+    /// Bytes left after the last attribute
+    pub trailing: usize,
+}
+
+/// Walk an fattr4 value list (`mask` as bitmap words), handing vendor
+/// attributes to `registry`; registered decoders take precedence over
+/// the standard encodings so vendors can reuse numbers
+pub fn walk_fattr4(registry: &Registry, mask: &[u32], attrs: &[u8]) -> Fattr4Walk {
+    let mut walk = Fattr4Walk::default();
+    let mut pos = 0;
+    let bits = mask.iter().enumerate().flat_map(|(w, &word)| {
+        (0..32)
+            .filter(move |b| word & (1 << b) != 0)
+            .map(move |b| w as u32 * 32 + b)
+    });
+    for bit in bits {
+        let rest = &attrs[pos.min(attrs.len())..];
+        if let Some((name, d)) = registry.attr(bit, rest) {
+            pos += d.len;
+            walk.vendor.push(VendorValue {
+                decoder: name.to_string(),
+                bit,
+                value: d.value,
+                findings: d.findings,
+            });
+            continue;
+        }
+        let mut dec = XdrDecoder::new(rest);
+        let skipped = match standard_encoding(bit) {
+            Some(Encoding::Fixed(n)) => dec.get_raw(n).is_ok(),
+            Some(Encoding::Opaque) => dec.get_opaque().is_ok(),
+            Some(Encoding::Words) => dec
+                .get_u32()
+                .and_then(|n| dec.get_raw((n as usize).saturating_mul(4)))
+                .is_ok(),
+            None => false,
+        };
+        if !skipped {
+            walk.opaque_from = Some(bit);
+            return walk;
+        }
+        pos += dec.position();
+    }
+    walk.trailing = attrs.len() - pos;
+    walk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfsv4::{attr, bitmap_from_bits};
+    use crate::xdr::XdrEncoder;
+
+    const SPEC: &str = r#"{
+        "name": "acme",
+        "attrs": [{"bit": 1000, "name": "volume", "fields": [
+            {"name": "uuid", "type": {"fixed": 4}},
+            {"name": "label", "type": "string"},
+            {"name": "encrypted", "type": "bool"}
+        ]}],
+        "ops": [{"opcode": 20000, "name": "acme_stat", "result": [
+            {"name": "load", "type": "u64"}
+        ]}]
+    }"#;
+
+    fn registry() -> Registry {
+        let mut r = Registry::new();
+        r.register(SpecDecoder::from_json(SPEC).unwrap());
+        r
+    }
+
+    #[test]
+    fn test_walk_fattr4_through_vendor_attr() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(1); // type
+        enc.put_opaque(b"root@acme");
+        enc.put_raw(&[0xde, 0xad, 0xbe, 0xef]);
+        enc.put_string("vol0");
+        enc.put_u32(7);
+        let mask = bitmap_from_bits(&[attr::TYPE, attr::OWNER, 1000]);
+        let walk = walk_fattr4(&registry(), &mask, enc.as_bytes());
+        assert_eq!(walk.opaque_from, None);
+        assert_eq!(walk.trailing, 0);
+        let v = &walk.vendor[0];
+        assert_eq!((v.decoder.as_str(), v.bit), ("acme", 1000));
+        assert_eq!(
+            v.value,
+            json!({"volume": {"uuid": "deadbeef", "label": "vol0", "encrypted": true}})
+        );
+        assert_eq!(v.findings, ["encrypted is bool 7"]);
+
+        // Nobody knows bit 1001, so nothing after it is decoded
+        let mask = bitmap_from_bits(&[attr::TYPE, 1001]);
+        let walk = walk_fattr4(&Registry::new(), &mask, &[0, 0, 0, 1, 9, 9]);
+        assert_eq!(walk.opaque_from, Some(1001));
+    }
+
+    #[test]
+    fn test_spec_op_and_bounds() {
+        let r = registry();
+        let (name, d) = r.op(20000, &[0, 0, 0, 1, 0, 0, 0, 2, 0xff]).unwrap();
+        assert_eq!(name, "acme");
+        assert_eq!(d.value, json!({"acme_stat": {"load": (1u64 << 32) | 2}}));
+        assert_eq!(d.len, 8);
+        assert!(r.op(20000, &[0, 0, 0, 1]).is_none());
+        assert!(r.op(20001, &[0; 8]).is_none());
+        assert_eq!(format!("{:?}", r), "[\"acme\"]");
+        assert!(SpecDecoder::from_json(r#"{"attrs": []}"#).is_err());
+    }
+}
@@ -0,0 +1,454 @@
+//! Stepping through a transcript
+//!
+//! A crash at the end of a few thousand calls is rarely explained by the
+//! last one alone, and reading the transcript as hex or as a pcap in
+//! Wireshark loses what the fuzzer changed. The viewer shows one
+//! call/reply pair at a time, the decoded call beside its decoded reply,
+//! and below them the words in which the call's arguments differ from
+//! the seed it was likely mutated from: the first call to the same
+//! procedure earlier in the transcript, or else the built-in seed of its
+//! v3 procedure. Keys move through the pairs: `n`, space, `j` or the
+//! right arrow to the next, `p`, `k` or the left arrow back, `g` and `G`
+//! to the first and last, a number then Enter to that pair, `q` to quit.
+//! On a terminal each key acts at once; with input from a pipe the keys
+//! are read a line at a time, an empty line stepping forward.
+
+use crate::fields::Message;
+use crate::rpc::{auth_flavor, msg_type, program, ReplyStatus, RpcReply};
+use crate::seeds;
+use crate::transcript::TranscriptEntry;
+use crate::xdr::XdrDecoder;
+use std::io::{self, IsTerminal, Read, Write};
+use std::process::Command;
+
+/// Columns of each side of a pair
+const WIDTH: usize = 60;
+
+/// Differing words shown at most
+const MAX_DIFFS: usize = 32;
+
+/// What a key asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Next,
+    Prev,
+    First,
+    Last,
+    /// Pair number, from 1
+    Goto(usize),
+    Quit,
+}
+
+/// Turns input bytes into [`Key`]s
+#[derive(Debug, Default)]
+pub struct Keys {
+    /// Input comes a line at a time rather than a key at a time
+    lines: bool,
+    digits: String,
+    /// Bytes of an escape sequence read so far
+    escape: Vec<u8>,
+    /// Whether the line so far held anything
+    typed: bool,
+}
+
+impl Keys {
+    pub fn new(lines: bool) -> Self {
+        Self {
+            lines,
+            ..Self::default()
+        }
+    }
+
+    /// The key `byte` completes, if any
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        if !self.escape.is_empty() || byte == 0x1b {
+            self.escape.push(byte);
+            return match self.escape[..] {
+                [0x1b] | [0x1b, b'['] => None,
+                [0x1b, b'[', b'C'] => self.escaped(Some(Key::Next)),
+                [0x1b, b'[', b'D'] => self.escaped(Some(Key::Prev)),
+                _ => self.escaped(None),
+            };
+        }
+        let typed = std::mem::replace(&mut self.typed, byte != b'\n');
+        match byte {
+            b'0'..=b'9' => {
+                self.digits.push(byte as char);
+                None
+            }
+            b'\n' | b'\r' if !self.digits.is_empty() => {
+                let n = std::mem::take(&mut self.digits).parse().ok()?;
+                Some(Key::Goto(n))
+            }
+            b'\n' if self.lines && typed => None,
+            b'\n' | b'\r' | b' ' | b'n' | b'j' => Some(Key::Next),
+            b'p' | b'k' => Some(Key::Prev),
+            b'g' => Some(Key::First),
+            b'G' => Some(Key::Last),
+            b'q' => Some(Key::Quit),
+            _ => None,
+        }
+    }
+
+    fn escaped(&mut self, key: Option<Key>) -> Option<Key> {
+        self.escape.clear();
+        key
+    }
+}
+
+/// A decoded RPC call
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Call {
+    xid: u32,
+    program: u32,
+    version: u32,
+    procedure: u32,
+    flavor: u32,
+    args: Vec<u8>,
+}
+
+fn parse_call(call: &[u8]) -> Option<Call> {
+    let mut d = XdrDecoder::new(call);
+    let xid = d.get_u32().ok()?;
+    if d.get_u32().ok()? != msg_type::CALL {
+        return None;
+    }
+    d.get_u32().ok()?;
+    let (program, version, procedure) = (d.get_u32().ok()?, d.get_u32().ok()?, d.get_u32().ok()?);
+    let flavor = d.get_u32().ok()?;
+    d.get_opaque().ok()?;
+    d.get_u32().ok()?;
+    d.get_opaque().ok()?;
+    Some(Call {
+        xid,
+        program,
+        version,
+        procedure,
+        flavor,
+        args: d.rest().to_vec(),
+    })
+}
+
+/// Name of a procedure, as the built-in seeds call it
+fn procedure_name(call: &Call) -> String {
+    match (call.program, call.version, call.procedure) {
+        (_, _, 0) => "NULL".to_string(),
+        (program::NFS, 4, 1) => "COMPOUND".to_string(),
+        (program::NFS, 3, p) => seeds::seeds(Some(3))
+            .find(|s| s.number == p)
+            .map_or_else(|| format!("PROC{}", p), |s| s.name.to_string()),
+        (_, _, p) => format!("PROC{}", p),
+    }
+}
+
+/// One field per line: offset, kind, value in hex
+fn field_lines(bytes: &[u8]) -> Vec<String> {
+    let msg = Message::infer(bytes);
+    msg.fields
+        .iter()
+        .map(|field| {
+            format!(
+                "{:>5} {:<12} {}",
+                field.offset,
+                format!("{:?}", field.kind),
+                hex::encode(&msg.bytes[field.range()])
+            )
+        })
+        .collect()
+}
+
+fn call_lines(call: &[u8]) -> Vec<String> {
+    let Some(c) = parse_call(call) else {
+        return vec![format!("not an RPC call ({} bytes)", call.len())];
+    };
+    let flavor = match c.flavor {
+        auth_flavor::AUTH_NONE => "AUTH_NONE".to_string(),
+        auth_flavor::AUTH_SYS => "AUTH_SYS".to_string(),
+        n => format!("flavor {}", n),
+    };
+    let mut lines = vec![
+        format!("CALL xid {:#010x}", c.xid),
+        format!(
+            "{} v{} {} ({})",
+            c.program,
+            c.version,
+            procedure_name(&c),
+            flavor
+        ),
+        format!("{} argument bytes", c.args.len()),
+    ];
+    lines.extend(field_lines(&c.args));
+    lines
+}
+
+fn reply_lines(reply: Option<&[u8]>) -> Vec<String> {
+    let Some(reply) = reply else {
+        return vec!["no reply".to_string()];
+    };
+    let parsed = match RpcReply::parse(reply) {
+        Ok(parsed) => parsed,
+        Err(e) => return vec![format!("unparsable reply: {}", e)],
+    };
+    let mut lines = vec![
+        format!("REPLY xid {:#010x}", parsed.xid),
+        match parsed.status {
+            ReplyStatus::Accepted { stat, .. } => format!("accepted, {:?}", stat),
+            ReplyStatus::Denied(why) => format!("denied, {:?}", why),
+        },
+        format!("{} result bytes", parsed.results.len()),
+    ];
+    lines.extend(field_lines(parsed.results));
+    lines
+}
+
+/// Words of `case` that differ from `seed`, as lines
+fn diff_lines(seed: &[u8], case: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let words = seed.len().max(case.len()).div_ceil(4);
+    let word = |b: &[u8], i: usize| {
+        (i * 4 < b.len()).then(|| hex::encode(&b[i * 4..(i * 4 + 4).min(b.len())]))
+    };
+    let mut differing = 0;
+    for i in 0..words {
+        let (s, c) = (word(seed, i), word(case, i));
+        if s == c {
+            continue;
+        }
+        differing += 1;
+        if differing <= MAX_DIFFS {
+            let show = |w: Option<String>| w.unwrap_or_else(|| "--".to_string());
+            lines.push(format!("{:>5} {:>10} -> {}", i * 4, show(s), show(c)));
+        }
+    }
+    if differing > MAX_DIFFS {
+        lines.push(format!("... {} more", differing - MAX_DIFFS));
+    }
+    match case.len() as isize - seed.len() as isize {
+        0 if differing == 0 => lines.push("same as the seed".to_string()),
+        0 => {}
+        d => lines.push(format!("{:+} bytes", d)),
+    }
+    lines
+}
+
+/// `line` cut or padded to `width` columns
+fn fit(line: &str, width: usize) -> String {
+    let count = line.chars().count();
+    if count > width {
+        let cut: String = line.chars().take(width - 1).collect();
+        format!("{}~", cut)
+    } else {
+        format!("{}{}", line, " ".repeat(width - count))
+    }
+}
+
+/// A transcript and where the viewer is in it
+#[derive(Debug, Clone)]
+pub struct Viewer {
+    entries: Vec<TranscriptEntry>,
+    at: usize,
+}
+
+impl Viewer {
+    pub fn new(entries: Vec<TranscriptEntry>) -> Self {
+        Self { entries, at: 0 }
+    }
+
+    /// Index of the pair shown
+    pub fn at(&self) -> usize {
+        self.at
+    }
+
+    /// Act on `key`; false once it asks to quit
+    pub fn handle(&mut self, key: Key) -> bool {
+        let last = self.entries.len().saturating_sub(1);
+        self.at = match key {
+            Key::Next => (self.at + 1).min(last),
+            Key::Prev => self.at.saturating_sub(1),
+            Key::First => 0,
+            Key::Last => last,
+            Key::Goto(n) => n.saturating_sub(1).min(last),
+            Key::Quit => return false,
+        };
+        true
+    }
+
+    /// The seed of the call at `index` and what it is, if there is one
+    fn seed(&self, index: usize) -> Option<(Vec<u8>, String)> {
+        let call = parse_call(&self.entries[index].call)?;
+        let same = |c: &Call| (c.program, c.version, c.procedure);
+        let earlier = self.entries[..index]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, e)| Some((i, parse_call(&e.call)?)))
+            .find(|(_, c)| same(c) == same(&call));
+        if let Some((i, c)) = earlier {
+            let what = format!("#{}, the first {}", i + 1, procedure_name(&c));
+            return Some((c.args, what));
+        }
+        if (call.program, call.version) != (program::NFS, 3) {
+            return None;
+        }
+        let seed = seeds::seeds(Some(3)).find(|s| s.number == call.procedure)?;
+        Some((seed.args.to_vec(), format!("seed {}", seed.name)))
+    }
+
+    /// The screen for the pair shown
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let Some(entry) = self.entries.get(self.at) else {
+            return "empty transcript\n".to_string();
+        };
+        out.push_str(&format!("#{} of {}\n", self.at + 1, self.entries.len()));
+        let (left, right) = (call_lines(&entry.call), reply_lines(entry.reply.as_deref()));
+        for i in 0..left.len().max(right.len()) {
+            let side = |lines: &[String]| lines.get(i).cloned().unwrap_or_default();
+            let line = format!("{} | {}", fit(&side(&left), WIDTH), side(&right));
+            out.push_str(fit(&line, 2 * WIDTH + 3).trim_end());
+            out.push('\n');
+        }
+        if let (Some((seed, what)), Some(call)) = (self.seed(self.at), parse_call(&entry.call)) {
+            out.push_str(&format!("\nagainst {}:\n", what));
+            for line in diff_lines(&seed, &call.args) {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        out.push_str("\n[n]ext [p]rev [g]first [G]last <number>Enter [q]uit\n");
+        out
+    }
+}
+
+/// Puts the terminal into key-at-a-time mode, and back when dropped
+struct RawMode;
+
+impl RawMode {
+    fn enter() -> Option<Self> {
+        let status = Command::new("stty")
+            .args(["-icanon", "-echo", "min", "1"])
+            .stdin(std::process::Stdio::inherit())
+            .status()
+            .ok()?;
+        status.success().then_some(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = Command::new("stty")
+            .args(["icanon", "echo"])
+            .stdin(std::process::Stdio::inherit())
+            .status();
+    }
+}
+
+/// Step through `entries` with keys from standard input
+pub fn run(entries: Vec<TranscriptEntry>) -> io::Result<()> {
+    let terminal = io::stdin().is_terminal() && io::stdout().is_terminal();
+    let raw = terminal.then(RawMode::enter).flatten();
+    let mut viewer = Viewer::new(entries);
+    let mut keys = Keys::new(raw.is_none());
+    let mut stdout = io::stdout().lock();
+    let clear = if terminal { "\x1b[2J\x1b[H" } else { "" };
+    write!(stdout, "{}{}", clear, viewer.render())?;
+    stdout.flush()?;
+    for byte in io::stdin().lock().bytes() {
+        let Some(key) = keys.feed(byte?) else {
+            continue;
+        };
+        if !viewer.handle(key) {
+            break;
+        }
+        write!(stdout, "{}{}", clear, viewer.render())?;
+        stdout.flush()?;
+    }
+    drop(raw);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::RpcCall;
+
+    fn call(procedure: u32, args: &[u8]) -> Vec<u8> {
+        RpcCall::new(0x10 + procedure, program::NFS, 3, procedure, false)
+            .with_auth_none()
+            .with_args(args)
+            .build()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_keys() {
+        let feed = |keys: &mut Keys, input: &[u8]| -> Vec<Key> {
+            input.iter().filter_map(|&b| keys.feed(b)).collect()
+        };
+        let mut raw = Keys::new(false);
+        assert_eq!(
+            feed(&mut raw, b"nj pk\x1b[C\x1b[D12\ngGq"),
+            [
+                Key::Next,
+                Key::Next,
+                Key::Next,
+                Key::Prev,
+                Key::Prev,
+                Key::Next,
+                Key::Prev,
+                Key::Goto(12),
+                Key::First,
+                Key::Last,
+                Key::Quit
+            ]
+        );
+        // A line of its own steps once; an empty one steps too
+        let mut lines = Keys::new(true);
+        assert_eq!(
+            feed(&mut lines, b"n\n\n7\np\n"),
+            [Key::Next, Key::Next, Key::Goto(7), Key::Prev]
+        );
+    }
+
+    #[test]
+    fn test_viewer_steps_and_diffs_against_the_seed() {
+        let seed = seeds::seeds(Some(3)).find(|s| s.name == "GETATTR").unwrap();
+        let mut mutated = seed.args.to_vec();
+        mutated[4] ^= 0xff;
+        mutated.extend_from_slice(&[0, 0, 0, 1]);
+        let reply = [0x11u32, 1, 0, 0, 0, 0, 70]
+            .iter()
+            .flat_map(|w| w.to_be_bytes())
+            .collect();
+        let entries = vec![
+            TranscriptEntry {
+                call: call(1, seed.args),
+                reply: Some(reply),
+            },
+            TranscriptEntry {
+                call: call(1, &mutated),
+                reply: None,
+            },
+        ];
+        let mut viewer = Viewer::new(entries);
+        let first = viewer.render();
+        assert!(first.starts_with("#1 of 2\nCALL xid 0x00000011"));
+        assert!(first.contains("100003 v3 GETATTR (AUTH_NONE)"));
+        assert!(first.contains("| REPLY xid 0x00000011"));
+        assert!(first.contains("| accepted, Success"));
+        assert!(first.contains("against seed GETATTR:\nsame as the seed"));
+
+        assert!(viewer.handle(Key::Next) && viewer.handle(Key::Next));
+        assert_eq!(viewer.at(), 1);
+        let second = viewer.render();
+        assert!(second.contains("| no reply"));
+        let diff = second
+            .split("against #1, the first GETATTR:\n")
+            .nth(1)
+            .unwrap();
+        assert!(diff.starts_with("    4 "), "{}", diff);
+        assert!(diff.contains("+4 bytes"));
+        assert!(viewer.handle(Key::Goto(1)));
+        assert_eq!(viewer.at(), 0);
+        assert!(!viewer.handle(Key::Quit));
+    }
+}
@@ -0,0 +1,171 @@
+//! Public filehandle probing (WebNFS, RFC 2054/2055; PUTPUBFH)
+//!
+//! WebNFS let clients skip MOUNT: a LOOKUP relative to the public
+//! filehandle (zero-length in v3) could name a whole path in one
+//! component, '/'-separated or in the server's native syntax. v4 keeps a
+//! remnant in PUTPUBFH. Servers that still honour any of this expose
+//! namespace code before any export has been mounted.
+
+use crate::auth::Identity;
+use crate::nfsv3;
+use crate::nfsv4::{self, lookup, lookupp, op, FuzzCase, Op};
+use bytes::BytesMut;
+
+/// RFC 2055 §6.1: a first byte of 0x80 marks a native-syntax path
+pub const NATIVE_PATH_PREFIX: u8 = 0x80;
+
+/// Handles servers might treat as public: the v3 zero-length handle, the
+/// v2-style 32 zero bytes, and a maximum-size zero handle
+pub fn public_handles() -> Vec<(&'static str, Vec<u8>)> {
+    vec![
+        ("v3_empty", Vec::new()),
+        ("v2_zero32", vec![0; 32]),
+        ("zero64", vec![0; 64]),
+    ]
+}
+
+/// Multi-component names to look up relative to the public handle,
+/// including `exports` found during recon
+pub fn public_paths(exports: &[String]) -> Vec<(String, Vec<u8>)> {
+    let mut paths: Vec<(String, Vec<u8>)> = [
+        ("dot", &b"."[..]),
+        ("multi", b"a/b/c"),
+        ("absolute_etc", b"/etc/passwd"),
+        ("dotdot_escape", b"../../../etc/passwd"),
+        ("trailing_slash", b"./"),
+        ("double_slash", b"//"),
+        ("url_escaped", b"%2e%2e/%2e%2e/etc"),
+    ]
+    .iter()
+    .map(|(label, p)| (label.to_string(), p.to_vec()))
+    .collect();
+
+    let mut native = vec![NATIVE_PATH_PREFIX];
+    native.extend_from_slice(b"/etc/passwd");
+    paths.push(("native_etc".to_string(), native));
+    paths.push(("native_bare".to_string(), vec![NATIVE_PATH_PREFIX]));
+
+    for (i, export) in exports.iter().enumerate() {
+        paths.push((format!("export{}", i), export.as_bytes().to_vec()));
+        let relative = export.trim_start_matches('/');
+        paths.push((
+            format!("export{}_relative", i),
+            relative.as_bytes().to_vec(),
+        ));
+    }
+    paths
+}
+
+/// v3 GETATTR and READDIR on each public handle, and LOOKUP of each path
+/// relative to it
+pub fn v3_public_calls(identity: &Identity, exports: &[String]) -> Vec<(String, BytesMut)> {
+    let mut calls = Vec::new();
+    for (label, fh) in public_handles() {
+        calls.push((
+            format!("webnfs_getattr_{}", label),
+            nfsv3::getattr(&fh).message(identity),
+        ));
+        calls.push((
+            format!("webnfs_readdir_{}", label),
+            nfsv3::readdir(&fh, 0, &[0; nfsv3::VERFSIZE], 4096).message(identity),
+        ));
+        for (path_label, path) in public_paths(exports) {
+            calls.push((
+                format!("webnfs_lookup_{}_{}", label, path_label),
+                nfsv3::lookup(&fh, &path).message(identity),
+            ));
+        }
+    }
+    calls
+}
+
+/// PUTPUBFH (no arguments)
+pub fn putpubfh() -> Op {
+    Op::raw(op::PUTPUBFH, Vec::new())
+}
+
+/// v4 cases starting from PUTPUBFH; most end in GETFH so the public
+/// handle can be compared with the root handle
+pub fn putpubfh_cases(exports: &[String]) -> Vec<FuzzCase> {
+    let mut cases = vec![
+        FuzzCase::new("putpubfh", vec![putpubfh(), nfsv4::getfh()]),
+        FuzzCase::new(
+            "putpubfh_vs_root",
+            vec![
+                putpubfh(),
+                nfsv4::getfh(),
+                nfsv4::putrootfh(),
+                nfsv4::getfh(),
+            ],
+        ),
+        FuzzCase::new(
+            "putpubfh_lookupp",
+            vec![putpubfh(), lookupp(), nfsv4::getfh()],
+        ),
+        FuzzCase::new(
+            "putpubfh_secinfo_no_name",
+            vec![
+                putpubfh(),
+                Op::new(op::SECINFO_NO_NAME, |enc| enc.put_u32(0)),
+            ],
+        ),
+        FuzzCase::new(
+            "putpubfh_twice",
+            vec![putpubfh(), putpubfh(), nfsv4::getfh()],
+        ),
+    ];
+    for (label, path) in public_paths(exports) {
+        cases.push(FuzzCase::new(
+            format!("putpubfh_lookup_{}", label),
+            vec![putpubfh(), lookup(&path), nfsv4::getfh()],
+        ));
+        // The v4 way: one LOOKUP per component
+        let components: Vec<Op> = path
+            .split(|&b| b == b'/')
+            .filter(|c| !c.is_empty())
+            .map(lookup)
+            .collect();
+        if components.len() > 1 {
+            let mut ops = vec![putpubfh()];
+            ops.extend(components);
+            ops.push(nfsv4::getfh());
+            cases.push(FuzzCase::new(format!("putpubfh_components_{}", label), ops));
+        }
+    }
+    cases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v3_lookup_on_empty_public_handle() {
+        let calls = v3_public_calls(&Identity::new(65534, 65534), &["/export".to_string()]);
+        let (name, msg) = calls
+            .iter()
+            .find(|(n, _)| n == "webnfs_lookup_v3_empty_multi")
+            .unwrap();
+        assert!(name.starts_with("webnfs_lookup"));
+        // Procedure number, then zero-length fh followed by the path
+        assert_eq!(&msg[24..28], &[0, 0, 0, 3]);
+        let args = &msg[msg.len() - 16..];
+        assert_eq!(&args[..8], &[0, 0, 0, 0, 0, 0, 0, 5]);
+        assert_eq!(&args[8..], b"a/b/c\0\0\0");
+        let per_handle = 2 + public_paths(&["/export".to_string()]).len();
+        assert_eq!(calls.len(), 3 * per_handle);
+    }
+
+    #[test]
+    fn test_putpubfh_cases() {
+        let cases = putpubfh_cases(&["/srv/nfs".to_string()]);
+        assert!(cases.iter().all(|c| c.ops[0].opcode == op::PUTPUBFH));
+        let split = cases
+            .iter()
+            .find(|c| c.name == "putpubfh_components_export0")
+            .unwrap();
+        // PUTPUBFH, LOOKUP srv, LOOKUP nfs, GETFH
+        assert_eq!(split.ops.len(), 4);
+        assert!(cases.iter().any(|c| c.name == "putpubfh_lookup_native_etc"));
+    }
+}
@@ -1,9 +1,12 @@
-//! XDR (External Data Representation) encoding primitives
+//! XDR (External Data Representation) encoding and decoding primitives
 //! 
 //! RFC 4506 defines XDR, used by Sun RPC and NFS.
 //! All integers are big-endian, all data is padded to 4-byte boundaries.
+//! [`XdrDecoder`] works on a buffer holding the whole message;
+//! [`XdrStream`] decodes as the bytes arrive.
 
 use bytes::{BufMut, BytesMut};
+use thiserror::Error;
 
 /// Calculate padding needed to align to 4-byte boundary
 #[inline]
@@ -49,6 +52,21 @@ impl XdrEncoder {
         self.buf.put_i64(value);
     }
 
+    /// Encode a single-precision float (RFC 4506 §4.6)
+    pub fn put_f32(&mut self, value: f32) {
+        self.put_u32(value.to_bits());
+    }
+
+    /// Encode a double-precision float (RFC 4506 §4.7)
+    pub fn put_f64(&mut self, value: f64) {
+        self.put_u64(value.to_bits());
+    }
+
+    /// Encode a quadruple-precision float from its IEEE binary128 bits (RFC 4506 §4.8)
+    pub fn put_quad(&mut self, bits: u128) {
+        self.buf.put_u128(bits);
+    }
+
     /// Encode a boolean as XDR (0 or 1, 4 bytes)
     pub fn put_bool(&mut self, value: bool) {
         self.put_u32(if value { 1 } else { 0 });
@@ -119,6 +137,397 @@ impl Default for XdrEncoder {
     }
 }
 
+/// Why a value could not be decoded; offsets are from the start of the buffer
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum XdrError {
+    #[error("need {need} bytes at offset {offset}, {have} left")]
+    Truncated {
+        offset: usize,
+        need: usize,
+        have: usize,
+    },
+    #[error("bool at offset {offset} is {value}")]
+    InvalidBool { offset: usize, value: u32 },
+    #[error("length {len} at offset {offset} exceeds limit {max}")]
+    TooLong {
+        offset: usize,
+        len: usize,
+        max: usize,
+    },
+    #[error("string at offset {offset} is not UTF-8")]
+    InvalidUtf8 { offset: usize },
+}
+
+impl XdrError {
+    /// Where in the buffer decoding failed
+    pub fn offset(&self) -> usize {
+        match *self {
+            XdrError::Truncated { offset, .. }
+            | XdrError::InvalidBool { offset, .. }
+            | XdrError::TooLong { offset, .. }
+            | XdrError::InvalidUtf8 { offset } => offset,
+        }
+    }
+}
+
+/// XDR decoder - reads wire-format data from a borrowed buffer
+///
+/// Non-zero padding is accepted, but the first offset where it occurs is
+/// kept: servers that leave it dirty may be leaking memory.
+#[derive(Debug, Clone)]
+pub struct XdrDecoder<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    dirty_pad: Option<usize>,
+}
+
+impl<'a> XdrDecoder<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self {
+            buf,
+            pos: 0,
+            dirty_pad: None,
+        }
+    }
+
+    /// Take `n` raw bytes without padding
+    pub fn get_raw(&mut self, n: usize) -> Result<&'a [u8], XdrError> {
+        let truncated = XdrError::Truncated {
+            offset: self.pos,
+            need: n,
+            have: self.remaining(),
+        };
+        let end = self.pos.checked_add(n).ok_or(truncated.clone())?;
+        let data = self.buf.get(self.pos..end).ok_or(truncated)?;
+        self.pos = end;
+        Ok(data)
+    }
+
+    fn get_array<const N: usize>(&mut self) -> Result<[u8; N], XdrError> {
+        Ok(self.get_raw(N)?.try_into().unwrap())
+    }
+
+    /// Decode a 32-bit unsigned integer
+    pub fn get_u32(&mut self) -> Result<u32, XdrError> {
+        self.get_array().map(u32::from_be_bytes)
+    }
+
+    /// Decode a 32-bit signed integer
+    pub fn get_i32(&mut self) -> Result<i32, XdrError> {
+        self.get_array().map(i32::from_be_bytes)
+    }
+
+    /// Decode a 64-bit unsigned integer (hyper)
+    pub fn get_u64(&mut self) -> Result<u64, XdrError> {
+        self.get_array().map(u64::from_be_bytes)
+    }
+
+    /// Decode a 64-bit signed integer
+    pub fn get_i64(&mut self) -> Result<i64, XdrError> {
+        self.get_array().map(i64::from_be_bytes)
+    }
+
+    /// Decode a single-precision float (RFC 4506 §4.6)
+    pub fn get_f32(&mut self) -> Result<f32, XdrError> {
+        self.get_u32().map(f32::from_bits)
+    }
+
+    /// Decode a double-precision float (RFC 4506 §4.7)
+    pub fn get_f64(&mut self) -> Result<f64, XdrError> {
+        self.get_u64().map(f64::from_bits)
+    }
+
+    /// Decode a quadruple-precision float as its IEEE binary128 bits (RFC 4506 §4.8)
+    pub fn get_quad(&mut self) -> Result<u128, XdrError> {
+        self.get_array().map(u128::from_be_bytes)
+    }
+
+    /// Decode a boolean; anything but 0 or 1 is an error
+    pub fn get_bool(&mut self) -> Result<bool, XdrError> {
+        let offset = self.pos;
+        match self.get_u32()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(XdrError::InvalidBool { offset, value }),
+        }
+    }
+
+    /// Decode fixed-length opaque data and skip its padding
+    pub fn get_opaque_fixed(&mut self, len: usize) -> Result<&'a [u8], XdrError> {
+        let data = self.get_raw(len)?;
+        let at = self.pos;
+        if self.get_raw(xdr_pad_len(len))?.iter().any(|&b| b != 0) {
+            self.dirty_pad.get_or_insert(at);
+        }
+        Ok(data)
+    }
+
+    /// Decode variable-length opaque data
+    pub fn get_opaque(&mut self) -> Result<&'a [u8], XdrError> {
+        self.get_opaque_max(usize::MAX)
+    }
+
+    /// Decode variable-length opaque data of at most `max` bytes, the
+    /// bound from an `opaque<max>` declaration
+    pub fn get_opaque_max(&mut self, max: usize) -> Result<&'a [u8], XdrError> {
+        let offset = self.pos;
+        let len = self.get_u32()? as usize;
+        if len > max {
+            return Err(XdrError::TooLong { offset, len, max });
+        }
+        self.get_opaque_fixed(len)
+    }
+
+    /// Decode a string; it must be UTF-8
+    pub fn get_string(&mut self) -> Result<&'a str, XdrError> {
+        let offset = self.pos;
+        let data = self.get_opaque()?;
+        std::str::from_utf8(data).map_err(|_| XdrError::InvalidUtf8 { offset })
+    }
+
+    /// Offset of the next byte to decode
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Bytes not yet decoded
+    pub fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// The undecoded bytes, without consuming them
+    pub fn rest(&self) -> &'a [u8] {
+        &self.buf[self.pos..]
+    }
+
+    /// Check if everything has been decoded
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Offset of the first non-zero padding byte seen so far
+    pub fn dirty_pad(&self) -> Option<usize> {
+        self.dirty_pad
+    }
+}
+
+/// Incremental XDR decoder over data that arrives in pieces
+///
+/// Bytes go in with [`feed`](Self::feed) as they are received. Each
+/// `try_` read either decodes a whole value or, until enough has
+/// arrived, returns `Ok(None)` without consuming anything, so a decode
+/// can stop at any point and pick up where it left off after the next
+/// feed; [`needed`](Self::needed) says how far the last read was short.
+/// Lengths are checked against their limits as soon as they arrive, and
+/// data nobody wants is dropped as it comes in with [`skip`](Self::skip)
+/// rather than buffered. Offsets count from the start of the stream.
+#[derive(Debug, Clone, Default)]
+pub struct XdrStream {
+    /// Received bytes not yet consumed
+    buf: Vec<u8>,
+    pos: usize,
+    /// Bytes still to be dropped as they arrive
+    skipping: usize,
+    needed: usize,
+    dirty_pad: Option<usize>,
+}
+
+impl XdrStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take in the next bytes of the stream
+    pub fn feed(&mut self, mut data: &[u8]) {
+        let dropped = self.skipping.min(data.len());
+        self.skipping -= dropped;
+        data = &data[dropped..];
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Whether `n` bytes can be consumed now; if not, how many more are
+    /// needed is kept for [`needed`](Self::needed)
+    fn ready(&mut self, n: usize) -> bool {
+        if self.skipping == 0 && self.buf.len() >= n {
+            return true;
+        }
+        self.needed = self.skipping + n.saturating_sub(self.buf.len());
+        false
+    }
+
+    fn peek_u32(&self, at: usize) -> u32 {
+        u32::from_be_bytes(self.buf[at..at + 4].try_into().unwrap())
+    }
+
+    fn consume(&mut self, n: usize) -> Vec<u8> {
+        self.pos += n;
+        self.buf.drain(..n).collect()
+    }
+
+    /// Take `n` raw bytes without padding
+    pub fn try_raw(&mut self, n: usize) -> Option<Vec<u8>> {
+        self.ready(n).then(|| self.consume(n))
+    }
+
+    /// Decode a 32-bit unsigned integer
+    pub fn try_u32(&mut self) -> Result<Option<u32>, XdrError> {
+        if !self.ready(4) {
+            return Ok(None);
+        }
+        let v = self.peek_u32(0);
+        self.consume(4);
+        Ok(Some(v))
+    }
+
+    /// Decode a 64-bit unsigned integer (hyper)
+    pub fn try_u64(&mut self) -> Result<Option<u64>, XdrError> {
+        if !self.ready(8) {
+            return Ok(None);
+        }
+        let v = u64::from_be_bytes(self.consume(8).try_into().unwrap());
+        Ok(Some(v))
+    }
+
+    /// Decode a boolean; anything but 0 or 1 is an error
+    pub fn try_bool(&mut self) -> Result<Option<bool>, XdrError> {
+        if !self.ready(4) {
+            return Ok(None);
+        }
+        let offset = self.pos;
+        match self.peek_u32(0) {
+            value @ (0 | 1) => {
+                self.consume(4);
+                Ok(Some(value == 1))
+            }
+            value => Err(XdrError::InvalidBool { offset, value }),
+        }
+    }
+
+    /// The length of the `opaque<max>` about to be decoded, checked
+    /// against `max` as soon as it has arrived
+    fn opaque_len(&mut self, max: usize) -> Result<Option<usize>, XdrError> {
+        if !self.ready(4) {
+            return Ok(None);
+        }
+        let len = self.peek_u32(0) as usize;
+        if len > max {
+            return Err(XdrError::TooLong {
+                offset: self.pos,
+                len,
+                max,
+            });
+        }
+        Ok(Some(len))
+    }
+
+    /// Decode variable-length opaque data of at most `max` bytes
+    pub fn try_opaque_max(&mut self, max: usize) -> Result<Option<Vec<u8>>, XdrError> {
+        let Some(len) = self.opaque_len(max)? else {
+            return Ok(None);
+        };
+        if !self.ready(4 + len + xdr_pad_len(len)) {
+            return Ok(None);
+        }
+        self.consume(4);
+        let data = self.consume(len);
+        let at = self.pos;
+        if self.consume(xdr_pad_len(len)).iter().any(|&b| b != 0) {
+            self.dirty_pad.get_or_insert(at);
+        }
+        Ok(Some(data))
+    }
+
+    /// Pass over variable-length opaque data of at most `max` bytes
+    /// without keeping it, however much of it has arrived; its length
+    pub fn try_skip_opaque(&mut self, max: usize) -> Result<Option<usize>, XdrError> {
+        let Some(len) = self.opaque_len(max)? else {
+            return Ok(None);
+        };
+        self.consume(4);
+        self.skip(len + xdr_pad_len(len));
+        Ok(Some(len))
+    }
+
+    /// Drop the next `n` bytes, those not received yet as they arrive
+    pub fn skip(&mut self, n: usize) {
+        let now = if self.skipping == 0 {
+            n.min(self.buf.len())
+        } else {
+            0
+        };
+        self.buf.drain(..now);
+        self.skipping += n - now;
+        self.pos += n;
+    }
+
+    /// How many more bytes the last read that came up short needed,
+    /// counting any still to be skipped
+    pub fn needed(&self) -> usize {
+        self.needed
+    }
+
+    /// Bytes still to be dropped by [`skip`](Self::skip)
+    pub fn skipping(&self) -> usize {
+        self.skipping
+    }
+
+    /// Received bytes not yet decoded
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Offset of the next value to decode
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Offset of the first non-zero padding byte seen so far
+    pub fn dirty_pad(&self) -> Option<usize> {
+        self.dirty_pad
+    }
+}
+
+/// Hostile IEEE 754 bit patterns for float, double and quadruple fields
+///
+/// Raw bits rather than float values so NaN payloads and signaling bits
+/// survive untouched.
+pub mod hostile_float {
+    pub const F32: &[u32] = &[
+        0x7f80_0000, // +Inf
+        0xff80_0000, // -Inf
+        0x7fc0_0000, // quiet NaN
+        0x7f80_0001, // signaling NaN
+        0xffff_ffff, // negative NaN, full payload
+        0x8000_0000, // -0
+        0x0000_0001, // smallest subnormal
+        0x007f_ffff, // largest subnormal
+        0x7f7f_ffff, // f32::MAX
+    ];
+
+    pub const F64: &[u64] = &[
+        0x7ff0_0000_0000_0000, // +Inf
+        0xfff0_0000_0000_0000, // -Inf
+        0x7ff8_0000_0000_0000, // quiet NaN
+        0x7ff0_0000_0000_0001, // signaling NaN
+        0xffff_ffff_ffff_ffff, // negative NaN, full payload
+        0x8000_0000_0000_0000, // -0
+        0x0000_0000_0000_0001, // smallest subnormal
+        0x000f_ffff_ffff_ffff, // largest subnormal
+        0x7fef_ffff_ffff_ffff, // f64::MAX
+    ];
+
+    pub const QUAD: &[u128] = &[
+        0x7fff_0000_0000_0000_0000_0000_0000_0000, // +Inf
+        0xffff_0000_0000_0000_0000_0000_0000_0000, // -Inf
+        0x7fff_8000_0000_0000_0000_0000_0000_0000, // quiet NaN
+        0x7fff_0000_0000_0000_0000_0000_0000_0001, // signaling NaN
+        u128::MAX,                                 // negative NaN, full payload
+        1 << 127,                                  // -0
+        1,                                         // smallest subnormal
+        0x7ffe_ffff_ffff_ffff_ffff_ffff_ffff_ffff, // largest finite
+    ];
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,6 +557,165 @@ mod tests {
         assert_eq!(enc.as_bytes(), &[0, 0, 0, 3, b'f', b'o', b'o', 0]);
     }
 
+    #[test]
+    fn test_floats() {
+        let mut enc = XdrEncoder::new();
+        enc.put_f32(1.0);
+        enc.put_f64(f64::from_bits(hostile_float::F64[3]));
+        enc.put_quad(hostile_float::QUAD[0]);
+        assert_eq!(&enc.as_bytes()[..4], &[0x3f, 0x80, 0, 0]);
+        // Signaling NaN payload is preserved
+        assert_eq!(&enc.as_bytes()[4..12], &[0x7f, 0xf0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(enc.len(), 4 + 8 + 16);
+        assert_eq!(&enc.as_bytes()[12..14], &[0x7f, 0xff]);
+
+        let mut dec = XdrDecoder::new(enc.as_bytes());
+        assert_eq!(dec.get_f32().unwrap(), 1.0);
+        assert_eq!(dec.get_f64().unwrap().to_bits(), hostile_float::F64[3]);
+        assert_eq!(dec.get_quad().unwrap(), hostile_float::QUAD[0]);
+        assert!(dec.is_empty());
+        assert!(matches!(dec.get_f32(), Err(XdrError::Truncated { .. })));
+    }
+
+    #[test]
+    fn test_hostile_floats_roundtrip() {
+        let mut enc = XdrEncoder::new();
+        for &bits in hostile_float::F32 {
+            enc.put_f32(f32::from_bits(bits));
+        }
+        for &bits in hostile_float::F64 {
+            enc.put_f64(f64::from_bits(bits));
+        }
+        for &bits in hostile_float::QUAD {
+            enc.put_quad(bits);
+        }
+        let mut dec = XdrDecoder::new(enc.as_bytes());
+        for &bits in hostile_float::F32 {
+            assert_eq!(dec.get_f32().unwrap().to_bits(), bits);
+        }
+        for &bits in hostile_float::F64 {
+            assert_eq!(dec.get_f64().unwrap().to_bits(), bits);
+        }
+        for &bits in hostile_float::QUAD {
+            assert_eq!(dec.get_quad().unwrap(), bits);
+        }
+        assert!(dec.is_empty());
+    }
+
+    #[test]
+    fn test_decode_roundtrip() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(7);
+        enc.put_i64(-2);
+        enc.put_bool(true);
+        enc.put_string("foo");
+        enc.put_opaque_fixed(&[9; 6]);
+        let mut dec = XdrDecoder::new(enc.as_bytes());
+        assert_eq!(dec.get_u32(), Ok(7));
+        assert_eq!(dec.get_i64(), Ok(-2));
+        assert_eq!(dec.get_bool(), Ok(true));
+        assert_eq!(dec.get_string(), Ok("foo"));
+        assert_eq!(dec.get_opaque_fixed(6), Ok(&[9; 6][..]));
+        assert!(dec.is_empty());
+        assert_eq!(dec.dirty_pad(), None);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let mut dec = XdrDecoder::new(&[0, 0, 0, 2, 0, 0, 0, 9, 0xff, 0, 0]);
+        assert_eq!(
+            dec.get_bool(),
+            Err(XdrError::InvalidBool {
+                offset: 0,
+                value: 2
+            })
+        );
+        assert_eq!(
+            dec.get_opaque(),
+            Err(XdrError::Truncated {
+                offset: 8,
+                need: 9,
+                have: 3
+            })
+        );
+        assert_eq!(
+            XdrDecoder::new(&[0, 0, 0, 5]).get_opaque_max(4),
+            Err(XdrError::TooLong {
+                offset: 0,
+                len: 5,
+                max: 4
+            })
+        );
+        let mut dec = XdrDecoder::new(&[0, 0, 0, 1, 0xff, 0, 0, 0]);
+        assert_eq!(dec.get_string(), Err(XdrError::InvalidUtf8 { offset: 0 }));
+        let mut dec = XdrDecoder::new(&[0, 0, 0, 1, b'a', 0, 1, 0]);
+        assert_eq!(dec.get_string(), Ok("a"));
+        assert_eq!(dec.dirty_pad(), Some(5));
+        assert_eq!(
+            XdrError::Truncated {
+                offset: 4,
+                need: 8,
+                have: 2
+            }
+            .to_string(),
+            "need 8 bytes at offset 4, 2 left"
+        );
+    }
+
+    #[test]
+    fn test_stream_resumes_across_feeds() {
+        let mut enc = XdrEncoder::new();
+        enc.put_u32(7);
+        enc.put_opaque(b"abcde");
+        enc.put_u64(9);
+        let bytes = enc.as_bytes();
+        let mut s = XdrStream::new();
+        s.feed(&bytes[..2]);
+        assert_eq!(s.try_u32(), Ok(None));
+        assert_eq!(s.needed(), 2);
+        s.feed(&bytes[2..10]);
+        assert_eq!(s.try_u32(), Ok(Some(7)));
+        // Length and part of the data: nothing is consumed yet
+        assert_eq!(s.try_opaque_max(8), Ok(None));
+        assert_eq!((s.needed(), s.position()), (6, 4));
+        s.feed(&bytes[10..]);
+        assert_eq!(s.try_opaque_max(8), Ok(Some(b"abcde".to_vec())));
+        assert_eq!(s.try_u64(), Ok(Some(9)));
+        assert_eq!((s.buffered(), s.position()), (0, bytes.len()));
+    }
+
+    #[test]
+    fn test_stream_limits_and_skips() {
+        // A length over its limit fails before any of the data arrives
+        let mut s = XdrStream::new();
+        s.feed(&[0, 0x10, 0, 0]);
+        assert_eq!(
+            s.try_opaque_max(1 << 16),
+            Err(XdrError::TooLong {
+                offset: 0,
+                len: 1 << 20,
+                max: 1 << 16
+            })
+        );
+        assert_eq!(s.try_skip_opaque(usize::MAX), Ok(Some(1 << 20)));
+        assert_eq!(s.skipping(), 1 << 20);
+        s.feed(&vec![0xaa; (1 << 20) - 1]);
+        assert_eq!(s.try_bool(), Ok(None));
+        assert_eq!((s.buffered(), s.needed()), (0, 5));
+        s.feed(&[0xaa, 0, 0, 0, 2]);
+        assert_eq!(
+            s.try_bool(),
+            Err(XdrError::InvalidBool {
+                offset: 4 + (1 << 20),
+                value: 2
+            })
+        );
+        let mut s = XdrStream::new();
+        s.feed(&[0, 0, 0, 1, b'a', 0, 1, 0]);
+        assert_eq!(s.try_opaque_max(4), Ok(Some(b"a".to_vec())));
+        assert_eq!(s.dirty_pad(), Some(5));
+    }
+
     #[test]
     fn test_opaque_padding() {
         let mut enc = XdrEncoder::new();